@@ -0,0 +1,352 @@
+//! The `hoare!` macro: decorated programs written inline, turned into
+//! `Decorated` constructor chains. It reads `imp!`'s syntax for the
+//! commands a decorated program can have, with `{{ P }}` assertions in
+//! the places the book puts them, and fills in the ones the rules
+//! determine.
+//!
+//! What holds before a command is only known once the commands before it
+//! are built, so the generated code builds a program front to back in a
+//! block of `let`s, each command reading the precondition it needs from
+//! `__pre`.
+
+use proc_macro2::{Delimiter, Group, Span, TokenStream, TokenTree};
+use quote::quote;
+use syn::Result;
+
+use crate::imp::Cursor;
+
+pub fn expand(input: TokenStream) -> Result<TokenStream> {
+    let mut cursor = Cursor::new(input, Span::call_site());
+    let pre = cursor
+        .decoration()?
+        .ok_or_else(|| cursor.error("a precondition `{{ ... }}`"))?;
+    let com = cursor.dcom(None)?;
+    cursor.finish()?;
+    Ok(quote!({
+        let __pre: ::rust_coq::imp::Formula = #pre;
+        let __com = #com;
+        ::rust_coq::imp::Decorated::new(__pre, __com)
+    }))
+}
+
+/// The inner group of `{{ ... }}`, if `tree` is one.
+fn decoration_group(tree: Option<&TokenTree>) -> Option<Group> {
+    let Some(TokenTree::Group(outer)) = tree else {
+        return None;
+    };
+    let mut inner = outer.stream().into_iter();
+    match (outer.delimiter(), inner.next(), inner.next()) {
+        (Delimiter::Brace, Some(TokenTree::Group(g)), None)
+            if g.delimiter() == Delimiter::Brace =>
+        {
+            Some(g)
+        }
+        _ => None,
+    }
+}
+
+const UNDECORATED: [&str; 8] = [
+    "break", "continue", "havoc", "print", "for", "par", "throw", "try",
+];
+
+impl Cursor {
+    /// A `{{ P }}` assertion, if one is next.
+    fn decoration(&mut self) -> Result<Option<TokenStream>> {
+        let Some(g) = decoration_group(self.peek()) else {
+            return Ok(None);
+        };
+        self.next += 1;
+        let mut inner = Cursor::of_group(&g);
+        let p = inner.formula()?;
+        inner.finish()?;
+        Ok(Some(p))
+    }
+
+    fn required_decoration(&mut self, what: &str) -> Result<TokenStream> {
+        let err = self.error(what);
+        self.decoration()?.ok_or(err)
+    }
+
+    /// The decorated commands up to the end, separated by `;`. Each starts
+    /// from `__pre`; `post` is the postcondition for the last one if it
+    /// has none of its own.
+    fn dcom(&mut self, post: Option<&TokenStream>) -> Result<TokenStream> {
+        if self.eat_punct("->>") {
+            let p = self.required_decoration("`{{ ... }}` after `->>`")?;
+            let rest = self.dcom(post)?;
+            return Ok(quote!({
+                let __pre: ::rust_coq::imp::Formula = #p;
+                ::rust_coq::imp::DCom::pre(::core::clone::Clone::clone(&__pre), #rest)
+            }));
+        }
+        let (d, defaulted) = self.dstmt(post)?;
+        if self.peek_punct(";") && self.tokens.len() > self.next + 1 {
+            // Only the last command can do without a postcondition.
+            if defaulted {
+                return Err(self.error("a postcondition `{{ ... }}`"));
+            }
+            self.next += 1;
+            let rest = self.dcom(post)?;
+            return Ok(quote!({
+                let __d = #d;
+                let __pre = ::core::clone::Clone::clone(::rust_coq::imp::DCom::post(&__d));
+                ::rust_coq::imp::DCom::seq(__d, #rest)
+            }));
+        }
+        self.eat_punct(";");
+        Ok(d)
+    }
+
+    /// One decorated command and its `->> {{ Q }}`s, if any, and whether
+    /// it took `post` for lack of a postcondition of its own.
+    fn dstmt(&mut self, post: Option<&TokenStream>) -> Result<(TokenStream, bool)> {
+        let (mut d, defaulted) = self.dcore(post)?;
+        while self.eat_punct("->>") {
+            let q = self.required_decoration("`{{ ... }}` after `->>`")?;
+            d = quote!(::rust_coq::imp::DCom::post_(#d, #q));
+        }
+        Ok((d, defaulted))
+    }
+
+    /// The postcondition written after a command, or else `post`, and
+    /// whether it was `post`.
+    fn own_post(&mut self, post: Option<&TokenStream>) -> Result<(TokenStream, bool)> {
+        match (self.decoration()?, post) {
+            (Some(q), _) => Ok((q, false)),
+            (None, Some(q)) => Ok((q.clone(), true)),
+            (None, None) => Err(self.error("a postcondition `{{ ... }}`")),
+        }
+    }
+
+    fn dcore(&mut self, post: Option<&TokenStream>) -> Result<(TokenStream, bool)> {
+        if self.eat_keyword("skip") {
+            let (q, defaulted) = self.own_post(post)?;
+            return Ok((quote!(::rust_coq::imp::DCom::DSkip(#q)), defaulted));
+        }
+        if self.eat_keyword("if") {
+            return self.dif(post);
+        }
+        if self.eat_keyword("while") {
+            return Ok((self.dwhile()?, false));
+        }
+        if UNDECORATED.iter().any(|kw| self.peek_keyword(kw)) {
+            return Err(self.error(
+                "a command of a decorated program: `skip`, an assignment, `if` or `while`",
+            ));
+        }
+        let x = self.ident().map_err(|_| self.error("a command"))?;
+        self.expect_punct(":=")?;
+        let a = self.aexp()?;
+        let (q, defaulted) = self.own_post(post)?;
+        Ok((quote!(::rust_coq::imp::DCom::asgn(#x, #a, #q)), defaulted))
+    }
+
+    /// A branch or loop body: `{ {{ P }} c }`, where `P` defaults to
+    /// `pre`. Returns the precondition and the command.
+    fn dblock(
+        &mut self,
+        pre: Option<TokenStream>,
+        post: Option<&TokenStream>,
+    ) -> Result<(TokenStream, TokenStream)> {
+        let g = self.group(Delimiter::Brace, "`{`")?;
+        let mut inner = Cursor::of_group(&g);
+        let p = match (inner.decoration()?, pre) {
+            (Some(p), _) | (None, Some(p)) => p,
+            (None, None) => {
+                return Err(inner.error("an invariant or a precondition `{{ ... }}` for the body"))
+            }
+        };
+        let d = inner.dcom(post)?;
+        inner.finish()?;
+        Ok((p, d))
+    }
+
+    /// `if b { {{ P1 }} c1 } else { {{ P2 }} c2 } {{ Q }}`. `P1` and `P2`
+    /// default to what holds before with `b` and with `~b`, and `Q` to
+    /// `post`; a missing `else` is `skip`. After `else if`, the inner `if`
+    /// takes the `Q` and the outer one shares it.
+    fn dif(&mut self, post: Option<&TokenStream>) -> Result<(TokenStream, bool)> {
+        let b = self.bexp()?;
+        let test = quote!(::rust_coq::imp::Formula::from(&__b));
+        let p1 = quote!(::rust_coq::imp::Formula::and(
+            ::core::clone::Clone::clone(&__pre),
+            #test
+        ));
+        let p2 = quote!(::rust_coq::imp::Formula::and(
+            ::core::clone::Clone::clone(&__pre),
+            ::rust_coq::imp::Formula::not(#test)
+        ));
+        let q = quote!(::core::clone::Clone::clone(&__post));
+        let (p1, d1) = self.dblock(Some(p1), Some(&q))?;
+        let else_if = self.peek_keyword("else")
+            && matches!(self.tokens.get(self.next + 1), Some(TokenTree::Ident(x)) if x == "if");
+        let (p2, d2, post, defaulted) = if !self.eat_keyword("else") {
+            let (post, defaulted) = self.own_post(post)?;
+            (
+                p2,
+                quote!(::rust_coq::imp::DCom::DSkip(#q)),
+                post,
+                defaulted,
+            )
+        } else if self.eat_keyword("if") {
+            let (d2, defaulted) = self.dif(post)?;
+            let post = quote!(::core::clone::Clone::clone(::rust_coq::imp::DCom::post(
+                &__d2
+            )));
+            (p2, d2, post, defaulted)
+        } else {
+            let (p2, d2) = self.dblock(Some(p2), Some(&q))?;
+            let (post, defaulted) = self.own_post(post)?;
+            (p2, d2, post, defaulted)
+        };
+        let d2 = quote!(
+            let __d2 = {
+                let __pre = ::core::clone::Clone::clone(&__p2);
+                #d2
+            };
+        );
+        let post = quote!(let __post: ::rust_coq::imp::Formula = #post;);
+        // After `else if` the second branch supplies `__post`, so comes
+        // first; otherwise it may need `__post`.
+        let (first, second) = if else_if { (d2, post) } else { (post, d2) };
+        let d = quote!({
+            let __b = #b;
+            let __p1: ::rust_coq::imp::Formula = #p1;
+            let __p2: ::rust_coq::imp::Formula = #p2;
+            #first
+            #second
+            let __d1 = {
+                let __pre = ::core::clone::Clone::clone(&__p1);
+                #d1
+            };
+            ::rust_coq::imp::DCom::if_(__b, __p1, __d1, __p2, __d2, __post)
+        });
+        Ok((d, defaulted))
+    }
+
+    /// `while b invariant {{ I }} { {{ P }} c } {{ Q }}`. With an invariant,
+    /// `P` defaults to `I && b`, the body's postcondition to `I`, and a
+    /// body ending elsewhere gets `->> {{ I }}`; without one, the
+    /// invariant is whatever the body ends with. `Q` defaults to the
+    /// invariant with `~b`.
+    fn dwhile(&mut self) -> Result<TokenStream> {
+        let b = self.bexp()?;
+        let test = quote!(::rust_coq::imp::Formula::from(&__b));
+        let (before, p, d, after) = if self.eat_keyword("invariant") {
+            let inv = self.required_decoration("an invariant `{{ ... }}`")?;
+            let p = quote!(::rust_coq::imp::Formula::and(
+                ::core::clone::Clone::clone(&__inv),
+                #test
+            ));
+            let q = quote!(::core::clone::Clone::clone(&__inv));
+            let (p, d) = self.dblock(Some(p), Some(&q))?;
+            let before = quote!(let __inv: ::rust_coq::imp::Formula = #inv;);
+            let after = quote!(
+                let __d = if ::rust_coq::imp::DCom::post(&__d) == &__inv {
+                    __d
+                } else {
+                    ::rust_coq::imp::DCom::post_(__d, ::core::clone::Clone::clone(&__inv))
+                };
+            );
+            (before, p, d, after)
+        } else {
+            let (p, d) = self.dblock(None, None)?;
+            let after = quote!(
+                let __inv = ::core::clone::Clone::clone(::rust_coq::imp::DCom::post(&__d));
+            );
+            (quote!(), p, d, after)
+        };
+        let post = match self.decoration()? {
+            Some(q) => q,
+            None => quote!(::rust_coq::imp::Formula::and(
+                ::core::clone::Clone::clone(&__inv),
+                ::rust_coq::imp::Formula::not(#test)
+            )),
+        };
+        Ok(quote!({
+            let __b = #b;
+            #before
+            let __p: ::rust_coq::imp::Formula = #p;
+            let __d = {
+                let __pre = ::core::clone::Clone::clone(&__p);
+                #d
+            };
+            #after
+            let __post: ::rust_coq::imp::Formula = #post;
+            ::rust_coq::imp::DCom::while_(__b, __p, __d, __post)
+        }))
+    }
+
+    /// An assertion: `->` (to the right) below `||` below `&&` below `~`,
+    /// over comparisons, `true`, `false` and `even(a)`.
+    fn formula(&mut self) -> Result<TokenStream> {
+        let p = self.disjunction()?;
+        if self.peek_punct("->") && !self.peek_punct("->>") {
+            self.next += 2;
+            let q = self.formula()?;
+            return Ok(quote!(::rust_coq::imp::Formula::implies(#p, #q)));
+        }
+        Ok(p)
+    }
+
+    fn disjunction(&mut self) -> Result<TokenStream> {
+        let mut p = self.conjunction()?;
+        while self.eat_punct("||") {
+            let q = self.conjunction()?;
+            p = quote!(::rust_coq::imp::Formula::or(#p, #q));
+        }
+        Ok(p)
+    }
+
+    fn conjunction(&mut self) -> Result<TokenStream> {
+        let mut p = self.unary_formula()?;
+        while self.eat_punct("&&") {
+            let q = self.unary_formula()?;
+            p = quote!(::rust_coq::imp::Formula::and(#p, #q));
+        }
+        Ok(p)
+    }
+
+    fn unary_formula(&mut self) -> Result<TokenStream> {
+        if self.eat_punct("~") {
+            let p = self.unary_formula()?;
+            return Ok(quote!(::rust_coq::imp::Formula::not(#p)));
+        }
+        if self.eat_keyword("true") {
+            return Ok(quote!(::rust_coq::imp::Formula::FTrue));
+        }
+        if self.eat_keyword("false") {
+            return Ok(quote!(::rust_coq::imp::Formula::FFalse));
+        }
+        if let (true, Some(TokenTree::Group(g))) =
+            (self.peek_keyword("even"), self.tokens.get(self.next + 1))
+        {
+            if g.delimiter() == Delimiter::Parenthesis {
+                let a = Cursor::of_group(g).whole_aexp()?;
+                self.next += 2;
+                return Ok(quote!(::rust_coq::imp::Formula::even(#a)));
+            }
+        }
+        if let Some(TokenTree::Group(g)) = self.peek() {
+            if g.delimiter() == Delimiter::Parenthesis {
+                let mut inner = Cursor::of_group(g);
+                if let Ok(p) = inner.formula() {
+                    if inner.at_end() {
+                        self.next += 1;
+                        return Ok(p);
+                    }
+                }
+            }
+        }
+        let a1 = self.aexp()?;
+        if self.eat_punct("<=") {
+            let a2 = self.aexp()?;
+            Ok(quote!(::rust_coq::imp::Formula::le(#a1, #a2)))
+        } else if self.eat_punct("=") {
+            let a2 = self.aexp()?;
+            Ok(quote!(::rust_coq::imp::Formula::eq(#a1, #a2)))
+        } else {
+            Err(self.error("`=` or `<=`"))
+        }
+    }
+}
@@ -0,0 +1,386 @@
+//! The `imp!` macro: Imp programs written inline in Rust, turned into
+//! `Com` constructor chains at compile time.
+//!
+//! Rust's tokenizer has already grouped the input into trees, so the
+//! parser here is simpler than the runtime one: a parenthesized or braced
+//! group arrives as a single token, and choosing between a boolean and an
+//! arithmetic reading of `( ... )` is a matter of trying to parse the whole
+//! group as a boolean expression.
+
+use proc_macro2::{Delimiter, Group, Literal, Span, TokenStream, TokenTree};
+use quote::quote;
+use syn::{Error, Lit, Result};
+
+const KEYWORDS: [&str; 16] = [
+    "skip", "break", "continue", "havoc", "print", "if", "else", "while", "for", "par", "with",
+    "throw", "try", "catch", "true", "false",
+];
+
+pub fn expand(input: TokenStream) -> Result<TokenStream> {
+    let mut cursor = Cursor::new(input, Span::call_site());
+    let c = cursor.com()?;
+    cursor.finish()?;
+    Ok(c)
+}
+
+pub(crate) struct Cursor {
+    pub(crate) tokens: Vec<TokenTree>,
+    pub(crate) next: usize,
+    /// Where to report running out of tokens: the closing delimiter of the
+    /// group being parsed, or the macro call.
+    end: Span,
+}
+
+impl Cursor {
+    pub(crate) fn new(tokens: TokenStream, end: Span) -> Self {
+        Cursor {
+            tokens: tokens.into_iter().collect(),
+            next: 0,
+            end,
+        }
+    }
+
+    pub(crate) fn of_group(group: &Group) -> Self {
+        Cursor::new(group.stream(), group.span_close())
+    }
+
+    pub(crate) fn peek(&self) -> Option<&TokenTree> {
+        self.tokens.get(self.next)
+    }
+
+    pub(crate) fn at_end(&self) -> bool {
+        self.next == self.tokens.len()
+    }
+
+    pub(crate) fn error(&self, expected: &str) -> Error {
+        match self.peek() {
+            Some(token) => Error::new(token.span(), format!("expected {}", expected)),
+            None => Error::new(self.end, format!("expected {}, found the end", expected)),
+        }
+    }
+
+    pub(crate) fn finish(&self) -> Result<()> {
+        if self.at_end() {
+            Ok(())
+        } else {
+            Err(self.error("`;` or the end of the block"))
+        }
+    }
+
+    /// Whether the next tokens are the punctuation characters of `op`.
+    pub(crate) fn peek_punct(&self, op: &str) -> bool {
+        op.chars().enumerate().all(|(i, ch)| {
+            matches!(self.tokens.get(self.next + i), Some(TokenTree::Punct(p)) if p.as_char() == ch)
+        })
+    }
+
+    pub(crate) fn eat_punct(&mut self, op: &str) -> bool {
+        let found = self.peek_punct(op);
+        if found {
+            self.next += op.len();
+        }
+        found
+    }
+
+    pub(crate) fn expect_punct(&mut self, op: &str) -> Result<()> {
+        if self.eat_punct(op) {
+            Ok(())
+        } else {
+            Err(self.error(&format!("`{}`", op)))
+        }
+    }
+
+    pub(crate) fn peek_keyword(&self, kw: &str) -> bool {
+        matches!(self.peek(), Some(TokenTree::Ident(x)) if x == kw)
+    }
+
+    pub(crate) fn eat_keyword(&mut self, kw: &str) -> bool {
+        let found = self.peek_keyword(kw);
+        if found {
+            self.next += 1;
+        }
+        found
+    }
+
+    /// A variable name, as a string literal for the generated code.
+    pub(crate) fn ident(&mut self) -> Result<Literal> {
+        match self.peek() {
+            Some(TokenTree::Ident(x)) if !KEYWORDS.contains(&x.to_string().as_str()) => {
+                let name = Literal::string(&x.to_string());
+                self.next += 1;
+                Ok(name)
+            }
+            _ => Err(self.error("a variable")),
+        }
+    }
+
+    pub(crate) fn group(&mut self, delimiter: Delimiter, expected: &str) -> Result<Group> {
+        match self.peek() {
+            Some(TokenTree::Group(g)) if g.delimiter() == delimiter => {
+                let g = g.clone();
+                self.next += 1;
+                Ok(g)
+            }
+            _ => Err(self.error(expected)),
+        }
+    }
+
+    /// A `{ ... }` block, parsed as a command.
+    fn block(&mut self) -> Result<TokenStream> {
+        let g = self.group(Delimiter::Brace, "`{`")?;
+        let mut inner = Cursor::of_group(&g);
+        let c = inner.com()?;
+        inner.finish()?;
+        Ok(c)
+    }
+
+    /// Statements separated by `;`, which associates to the right. A
+    /// trailing `;` is allowed.
+    fn com(&mut self) -> Result<TokenStream> {
+        let c = self.stmt()?;
+        if self.eat_punct(";") && !self.at_end() {
+            let rest = self.com()?;
+            Ok(quote!(::rust_coq::imp::Com::seq(#c, #rest)))
+        } else {
+            Ok(c)
+        }
+    }
+
+    fn stmt(&mut self) -> Result<TokenStream> {
+        if self.eat_keyword("skip") {
+            Ok(quote!(::rust_coq::imp::Com::CSkip))
+        } else if self.eat_keyword("break") {
+            Ok(quote!(::rust_coq::imp::Com::CBreak))
+        } else if self.eat_keyword("continue") {
+            Ok(quote!(::rust_coq::imp::Com::CContinue))
+        } else if self.eat_keyword("havoc") {
+            let x = self.ident()?;
+            Ok(quote!(::rust_coq::imp::Com::havoc(#x)))
+        } else if self.eat_keyword("print") {
+            let a = self.aexp()?;
+            Ok(quote!(::rust_coq::imp::Com::print(#a)))
+        } else if self.eat_keyword("if") {
+            let b = self.bexp()?;
+            let c1 = self.block()?;
+            let c2 = if !self.eat_keyword("else") {
+                quote!(::rust_coq::imp::Com::CSkip)
+            } else if self.peek_keyword("if") {
+                self.stmt()?
+            } else {
+                self.block()?
+            };
+            Ok(quote!(::rust_coq::imp::Com::if_(#b, #c1, #c2)))
+        } else if self.eat_keyword("while") {
+            let b = self.bexp()?;
+            let body = self.block()?;
+            Ok(quote!(::rust_coq::imp::Com::while_(#b, #body)))
+        } else if self.eat_keyword("par") {
+            let c1 = self.block()?;
+            if !self.eat_keyword("with") {
+                return Err(self.error("`with`"));
+            }
+            let c2 = self.block()?;
+            Ok(quote!(::rust_coq::imp::Com::par(#c1, #c2)))
+        } else if self.eat_keyword("throw") {
+            let a = self.aexp()?;
+            Ok(quote!(::rust_coq::imp::Com::throw(#a)))
+        } else if self.eat_keyword("try") {
+            let body = self.block()?;
+            if !self.eat_keyword("catch") {
+                return Err(self.error("`catch`"));
+            }
+            let x = self.ident()?;
+            let handler = self.block()?;
+            Ok(quote!(::rust_coq::imp::Com::try_(#body, #x, #handler)))
+        } else if self.eat_keyword("for") {
+            let init = self.stmt()?;
+            self.expect_punct(";")?;
+            let b = self.bexp()?;
+            self.expect_punct(";")?;
+            let update = self.stmt()?;
+            let body = self.block()?;
+            Ok(quote!(::rust_coq::imp::Com::for_(#init, #b, #update, #body)))
+        } else if let Some(TokenTree::Group(g)) = self.peek() {
+            if g.delimiter() == Delimiter::Bracket {
+                return Err(self.error("a command"));
+            }
+            let g = g.clone();
+            self.next += 1;
+            let mut inner = Cursor::of_group(&g);
+            let c = inner.com()?;
+            inner.finish()?;
+            Ok(c)
+        } else {
+            self.assignment()
+        }
+    }
+
+    /// `X := a`, `X := f(a1, ..., an)` or `A[i] := a`.
+    fn assignment(&mut self) -> Result<TokenStream> {
+        let x = self.ident().map_err(|_| self.error("a command"))?;
+        if let Some(TokenTree::Group(g)) = self.peek() {
+            if g.delimiter() == Delimiter::Bracket {
+                let g = g.clone();
+                self.next += 1;
+                let i = Cursor::of_group(&g).whole_aexp()?;
+                self.expect_punct(":=")?;
+                let a = self.aexp()?;
+                return Ok(quote!(::rust_coq::imp::Com::arr_asgn(#x, #i, #a)));
+            }
+        }
+        self.expect_punct(":=")?;
+        if let (Some(TokenTree::Ident(_)), Some(TokenTree::Group(g))) =
+            (self.peek(), self.tokens.get(self.next + 1))
+        {
+            if g.delimiter() == Delimiter::Parenthesis {
+                let f = self.ident()?;
+                let g = self.group(Delimiter::Parenthesis, "`(`")?;
+                let args = Cursor::of_group(&g).args()?;
+                return Ok(quote!(
+                    ::rust_coq::imp::Com::call(#x, #f, ::std::vec![#(#args),*])
+                ));
+            }
+        }
+        let a = self.aexp()?;
+        Ok(quote!(::rust_coq::imp::Com::asgn(#x, #a)))
+    }
+
+    /// Comma-separated arithmetic expressions filling the whole group.
+    fn args(&mut self) -> Result<Vec<TokenStream>> {
+        let mut args = Vec::new();
+        while !self.at_end() {
+            args.push(self.aexp()?);
+            if !self.at_end() {
+                self.expect_punct(",")?;
+            }
+        }
+        Ok(args)
+    }
+
+    pub(crate) fn bexp(&mut self) -> Result<TokenStream> {
+        let mut b = self.unary_bexp()?;
+        while self.eat_punct("&&") {
+            let b2 = self.unary_bexp()?;
+            b = quote!(::rust_coq::imp::Bexp::and(#b, #b2));
+        }
+        Ok(b)
+    }
+
+    fn unary_bexp(&mut self) -> Result<TokenStream> {
+        if self.eat_punct("~") {
+            let b = self.unary_bexp()?;
+            return Ok(quote!(::rust_coq::imp::Bexp::not(#b)));
+        }
+        if self.eat_keyword("true") {
+            return Ok(quote!(::rust_coq::imp::Bexp::BTrue));
+        }
+        if self.eat_keyword("false") {
+            return Ok(quote!(::rust_coq::imp::Bexp::BFalse));
+        }
+        if let Some(TokenTree::Group(g)) = self.peek() {
+            if g.delimiter() == Delimiter::Parenthesis {
+                let mut inner = Cursor::of_group(g);
+                if let Ok(b) = inner.bexp() {
+                    if inner.at_end() {
+                        self.next += 1;
+                        return Ok(b);
+                    }
+                }
+            }
+        }
+        let a1 = self.aexp()?;
+        if self.eat_punct("<=") {
+            let a2 = self.aexp()?;
+            Ok(quote!(::rust_coq::imp::Bexp::le(#a1, #a2)))
+        } else if self.eat_punct("=") {
+            let a2 = self.aexp()?;
+            Ok(quote!(::rust_coq::imp::Bexp::eq(#a1, #a2)))
+        } else {
+            Err(self.error("`=` or `<=`"))
+        }
+    }
+
+    pub(crate) fn whole_aexp(&mut self) -> Result<TokenStream> {
+        let a = self.aexp()?;
+        if self.at_end() {
+            Ok(a)
+        } else {
+            Err(self.error("an arithmetic operator"))
+        }
+    }
+
+    pub(crate) fn aexp(&mut self) -> Result<TokenStream> {
+        let mut a = self.term()?;
+        loop {
+            if self.eat_punct("+") {
+                let a2 = self.term()?;
+                a = quote!(::rust_coq::imp::Aexp::plus(#a, #a2));
+            } else if !self.peek_punct("->") && self.eat_punct("-") {
+                let a2 = self.term()?;
+                a = quote!(::rust_coq::imp::Aexp::minus(#a, #a2));
+            } else {
+                return Ok(a);
+            }
+        }
+    }
+
+    fn term(&mut self) -> Result<TokenStream> {
+        let mut a = self.factor()?;
+        while self.eat_punct("*") {
+            let a2 = self.factor()?;
+            a = quote!(::rust_coq::imp::Aexp::mult(#a, #a2));
+        }
+        Ok(a)
+    }
+
+    fn factor(&mut self) -> Result<TokenStream> {
+        match self.peek() {
+            Some(TokenTree::Literal(_)) => self.number(false),
+            Some(TokenTree::Punct(p)) if p.as_char() == '-' => {
+                self.next += 1;
+                match self.peek() {
+                    Some(TokenTree::Literal(_)) => self.number(true),
+                    _ => Err(self.error("a number")),
+                }
+            }
+            Some(TokenTree::Group(g)) if g.delimiter() == Delimiter::Parenthesis => {
+                let g = g.clone();
+                self.next += 1;
+                Cursor::of_group(&g).whole_aexp()
+            }
+            _ => {
+                let x = self
+                    .ident()
+                    .map_err(|_| self.error("an arithmetic expression"))?;
+                match self.peek() {
+                    Some(TokenTree::Group(g)) if g.delimiter() == Delimiter::Bracket => {
+                        let g = g.clone();
+                        self.next += 1;
+                        let i = Cursor::of_group(&g).whole_aexp()?;
+                        Ok(quote!(::rust_coq::imp::Aexp::index(#x, #i)))
+                    }
+                    _ => Ok(quote!(::rust_coq::imp::Aexp::var(#x))),
+                }
+            }
+        }
+    }
+
+    fn number(&mut self, negative: bool) -> Result<TokenStream> {
+        let Some(TokenTree::Literal(lit)) = self.peek() else {
+            unreachable!("number called on a non-literal token");
+        };
+        let span = lit.span();
+        let magnitude = match Lit::new(lit.clone()) {
+            Lit::Int(n) if n.suffix().is_empty() => n.base10_parse::<u64>()?,
+            _ => return Err(Error::new(span, "expected an integer literal")),
+        };
+        let n = if negative {
+            -i128::from(magnitude)
+        } else {
+            i128::from(magnitude)
+        };
+        let n = i64::try_from(n).map_err(|_| Error::new(span, "number literal is too large"))?;
+        self.next += 1;
+        Ok(quote!(::rust_coq::imp::Aexp::num(#n)))
+    }
+}
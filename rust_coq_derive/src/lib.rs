@@ -0,0 +1,248 @@
+use proc_macro::TokenStream;
+use proc_macro2::Span;
+use quote::{format_ident, quote};
+use syn::{
+    parse_macro_input, spanned::Spanned, Data, DeriveInput, Fields, GenericArgument, Ident,
+    PathArguments, Type,
+};
+
+mod hoare;
+mod imp;
+
+/// Derive a recursor for an inductive enum, in the spirit of the `_rect`
+/// principles Coq generates for every `Inductive` definition.
+///
+/// For an enum like
+///
+/// ```ignore
+/// #[derive(Recursor)]
+/// enum Peano {
+///     O,
+///     S(Rc<Peano>),
+/// }
+/// ```
+///
+/// this generates
+///
+/// ```ignore
+/// impl Peano {
+///     pub fn rect<R, FO, FS>(&self, case_o: &FO, case_s: &FS) -> R
+///     where
+///         FO: Fn() -> R,
+///         FS: Fn(&Rc<Peano>, R) -> R,
+///     { ... }
+/// }
+/// ```
+///
+/// One case closure is taken per variant, in declaration order. Each case
+/// receives a reference to every field of its variant; a field that holds
+/// the enum itself (directly or behind `Box`, `Rc` or `Arc`) is additionally
+/// followed by the result of recursing into it, just like the induction
+/// hypothesis in `nat_rect : P 0 -> (forall n, P n -> P (S n)) -> forall n, P n`.
+#[proc_macro_derive(Recursor)]
+pub fn derive_recursor(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    match expand(input) {
+        Ok(tokens) => tokens.into(),
+        Err(err) => err.to_compile_error().into(),
+    }
+}
+
+/// Write an Imp command in Rust source and get its `Com` at compile time:
+///
+/// ```ignore
+/// let fact = imp! {
+///     Z := X;
+///     Y := 1;
+///     while ~(Z = 0) {
+///         Y := Y * Z;
+///         Z := Z - 1
+///     }
+/// };
+/// ```
+///
+/// The syntax is the parser's with braces for bodies: `if b { c1 } else {
+/// c2 }` (where `else if` also works), `while b { c }`, `for init; b;
+/// update { c }` and `try { c1 } catch X { c2 }`. A `;` after the last
+/// command of a block is allowed.
+/// Mistakes are compile errors pointing at the offending token.
+#[proc_macro]
+pub fn imp(input: TokenStream) -> TokenStream {
+    match imp::expand(input.into()) {
+        Ok(tokens) => tokens.into(),
+        Err(err) => err.to_compile_error().into(),
+    }
+}
+
+/// Write a decorated program, Hoare2's proof outline, and get its
+/// `Decorated`:
+///
+/// ```ignore
+/// let dec = hoare! {
+///     {{ X = M && Y = N }}
+///     ->> {{ Y - X = N - M }}
+///     while ~(X = 0) invariant {{ Y - X = N - M }} {
+///         ->> {{ Y - 1 - (X - 1) = N - M }}
+///         Y := Y - 1 {{ Y - (X - 1) = N - M }};
+///         X := X - 1
+///     }
+///     ->> {{ Y = N - M }}
+/// };
+/// ```
+///
+/// The commands are `imp!`'s `skip`, assignments, `;`, `if` and `while`,
+/// each followed by the assertion `{{ P }}` after it, and an assertion at
+/// the start of a block is the one before its first command. `->> {{ P }}`
+/// weakens the assertion before it or strengthens the one after it, and
+/// `while b invariant {{ I }}` names the loop's invariant. Assertions use
+/// `imp!`'s boolean syntax, with `||`, `->` and `even(a)` too.
+///
+/// Decorations the rules pin down may be left out: the first assertion of
+/// an `if` branch is what held before with the test or its negation, and
+/// of a loop body the invariant with the test; the last command of a
+/// branch ends in what holds after the `if`, and of a loop body in the
+/// invariant; a loop ends in the invariant with the test negated. Only
+/// the outer precondition is needed to get started, and the last
+/// command's assertion is the program's postcondition.
+#[proc_macro]
+pub fn hoare(input: TokenStream) -> TokenStream {
+    match hoare::expand(input.into()) {
+        Ok(tokens) => tokens.into(),
+        Err(err) => err.to_compile_error().into(),
+    }
+}
+
+fn expand(input: DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
+    let name = &input.ident;
+    let data = match &input.data {
+        Data::Enum(data) => data,
+        _ => {
+            return Err(syn::Error::new(
+                input.span(),
+                "`Recursor` can only be derived for enums",
+            ))
+        }
+    };
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    let result = Ident::new("__R", Span::call_site());
+
+    // The recursive calls simply forward every case closure.
+    let forwarded: Vec<_> = data
+        .variants
+        .iter()
+        .map(|v| format_ident!("case_{}", snake_case(&v.ident.to_string())))
+        .collect();
+
+    let mut case_types = Vec::new();
+    let mut case_params = Vec::new();
+    let mut case_bounds = Vec::new();
+    let mut arms = Vec::new();
+
+    for (variant, case) in data.variants.iter().zip(&forwarded) {
+        let variant_name = &variant.ident;
+        let case_ty = format_ident!("__F{}", variant_name);
+
+        // Argument types of the case closure and the expressions passed to it.
+        let mut arg_types = Vec::new();
+        let mut args = Vec::new();
+        let mut bindings = Vec::new();
+        for (i, field) in variant.fields.iter().enumerate() {
+            let binding = format_ident!("__field_{}", i);
+            let field_ty = &field.ty;
+            arg_types.push(quote!(&#field_ty));
+            args.push(quote!(#binding));
+            match recursion_kind(field_ty, name) {
+                Recursion::None => {}
+                Recursion::Direct => {
+                    arg_types.push(quote!(#result));
+                    args.push(quote!(#binding.rect(#(#forwarded),*)));
+                }
+                Recursion::Indirect => {
+                    arg_types.push(quote!(#result));
+                    args.push(quote!(
+                        ::core::ops::Deref::deref(#binding).rect(#(#forwarded),*)
+                    ));
+                }
+            }
+            bindings.push((field.ident.clone(), binding));
+        }
+
+        let pattern = match &variant.fields {
+            Fields::Unit => quote!(#name::#variant_name),
+            Fields::Unnamed(_) => {
+                let names = bindings.iter().map(|(_, b)| b);
+                quote!(#name::#variant_name(#(#names),*))
+            }
+            Fields::Named(_) => {
+                let names = bindings.iter().map(|(field, b)| quote!(#field: #b));
+                quote!(#name::#variant_name { #(#names),* })
+            }
+        };
+
+        case_bounds.push(quote!(#case_ty: Fn(#(#arg_types),*) -> #result));
+        case_params.push(quote!(#case: &#case_ty));
+        arms.push(quote!(#pattern => #case(#(#args),*)));
+        case_types.push(case_ty);
+    }
+
+    Ok(quote! {
+        impl #impl_generics #name #ty_generics #where_clause {
+            /// Structural recursor generated by `#[derive(Recursor)]`.
+            #[allow(clippy::too_many_arguments)]
+            pub fn rect<#result, #(#case_types),*>(&self, #(#case_params),*) -> #result
+            where
+                #(#case_bounds),*
+            {
+                match self {
+                    #(#arms),*
+                }
+            }
+        }
+    })
+}
+
+enum Recursion {
+    None,
+    Direct,
+    Indirect,
+}
+
+/// Decide whether a field of type `ty` refers back to the enum `name`, either
+/// as the bare type or wrapped in a single-argument pointer such as `Box`.
+fn recursion_kind(ty: &Type, name: &Ident) -> Recursion {
+    let Type::Path(path) = ty else {
+        return Recursion::None;
+    };
+    let Some(last) = path.path.segments.last() else {
+        return Recursion::None;
+    };
+    if last.ident == *name {
+        return Recursion::Direct;
+    }
+    let is_pointer = ["Box", "Rc", "Arc"]
+        .iter()
+        .any(|pointer| last.ident == pointer);
+    if let (true, PathArguments::AngleBracketed(args)) = (is_pointer, &last.arguments) {
+        if let Some(GenericArgument::Type(Type::Path(inner))) = args.args.first() {
+            if inner.path.segments.last().map(|s| &s.ident) == Some(name) {
+                return Recursion::Indirect;
+            }
+        }
+    }
+    Recursion::None
+}
+
+fn snake_case(name: &str) -> String {
+    let mut out = String::new();
+    for (i, c) in name.chars().enumerate() {
+        if c.is_uppercase() {
+            if i != 0 {
+                out.push('_');
+            }
+            out.extend(c.to_lowercase());
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
@@ -0,0 +1,379 @@
+//! An interactive Imp interpreter. Each command runs on the state left by
+//! the previous ones, and the state is printed after it:
+//!
+//! ```text
+//! imp> X := 5; Y := 1
+//! X=5, Y=1
+//! imp> while ~(X = 0) do Y := Y * X; X := X - 1 end
+//! X=0, Y=120
+//! ```
+//!
+//! Whatever a command prints comes first, one number per line. A command
+//! may span several lines; the prompt changes to `...` until it is
+//! complete. Procedure definitions are remembered for later calls. A
+//! command that runs out of fuel keeps the state it got to, and
+//! `:continue` runs the rest of it. Type `:help` for the rest.
+
+use std::collections::BTreeSet;
+use std::fs;
+use std::io::{self, BufRead, Write};
+
+use rust_coq::imp::{
+    parse_program, step_output, Com, Config, EvalError, ParseError, Proc, Program, RunResult,
+};
+use rust_coq::state::{empty_state, State, StateExt};
+
+/// Loop iterations and calls a single command may take.
+const FUEL: u64 = 1_000_000;
+
+/// Small steps `:step` shows before giving up.
+const MAX_STEPS: usize = 1_000;
+
+const HELP: &str = "\
+Enter an Imp command to run it, or a procedure definition to remember it.
+  :load FILE   run the procedures and command in FILE
+  :step COM    run COM one small step at a time, printing each step
+  :continue    carry on with a command that ran out of fuel
+  :reset       forget all variables and procedures
+  :help        show this message
+  :quit        leave (so does end of input)";
+
+struct Session {
+    st: State,
+    /// The variables shown after each command: every one used so far.
+    vars: BTreeSet<String>,
+    procs: Vec<Proc>,
+    /// What is left of the last command, if it ran out of fuel.
+    paused: Option<Config>,
+    /// The fuel each command gets, `FUEL` but for the tests.
+    fuel: u64,
+}
+
+/// What a line of input amounts to.
+enum Reply {
+    Output(String),
+    /// The command isn't finished; wait for more lines.
+    Incomplete,
+    Quit,
+}
+
+impl Session {
+    fn new() -> Self {
+        Session {
+            st: empty_state(),
+            vars: BTreeSet::new(),
+            procs: Vec::new(),
+            paused: None,
+            fuel: FUEL,
+        }
+    }
+
+    fn show(&self) -> String {
+        let vars: Vec<&String> = self.vars.iter().collect();
+        if vars.is_empty() {
+            "(no variables)".to_string()
+        } else {
+            self.st.show(&vars)
+        }
+    }
+
+    fn handle(&mut self, input: &str) -> Reply {
+        let input = input.trim();
+        let (cmd, arg) = match input.split_once(char::is_whitespace) {
+            Some((cmd, arg)) => (cmd, arg.trim()),
+            None => (input, ""),
+        };
+        let output = match cmd {
+            "" => return Reply::Output(String::new()),
+            ":quit" | ":q" => return Reply::Quit,
+            ":help" | ":h" => HELP.to_string(),
+            ":reset" => {
+                *self = Session {
+                    fuel: self.fuel,
+                    ..Session::new()
+                };
+                "state and procedures cleared".to_string()
+            }
+            ":load" => match fs::read_to_string(arg) {
+                Ok(src) => match parse_program(&src) {
+                    Ok(prog) => self.run(prog),
+                    Err(e) => format!("{}: {}", arg, e.report(&src)),
+                },
+                Err(e) => format!("cannot read {}: {}", arg, e),
+            },
+            ":continue" | ":c" => match self.paused.take() {
+                Some(rest) => {
+                    let prog = Program::new(self.procs.clone(), Com::CSkip);
+                    let (result, output) = prog.resume_output(self.st.clone(), &rest, self.fuel);
+                    self.report(result, output)
+                }
+                None => "nothing to continue".to_string(),
+            },
+            ":step" => match parse_program(arg) {
+                Ok(prog) if prog.procs.is_empty() => self.step(prog.main),
+                Ok(_) => "`:step` takes a command, not procedures".to_string(),
+                Err(ParseError::UnexpectedEof { .. }) => return Reply::Incomplete,
+                Err(e) => e.report(arg),
+            },
+            _ if cmd.starts_with(':') => format!("unknown command {}; try :help", cmd),
+            _ => match parse_program(input) {
+                Ok(prog) => self.run(prog),
+                Err(ParseError::UnexpectedEof { .. }) => return Reply::Incomplete,
+                Err(e) => e.report(input),
+            },
+        };
+        Reply::Output(output)
+    }
+
+    /// Add `prog`'s procedures to those already known, replacing any with
+    /// the same names, and run its main command.
+    fn run(&mut self, prog: Program) -> String {
+        let defined: Vec<String> = prog.procs.iter().map(|p| p.name.clone()).collect();
+        let mut procs = self.procs.clone();
+        procs.retain(|p| !defined.contains(&p.name));
+        procs.extend(prog.procs);
+        let prog = Program::new(procs, prog.main);
+        if let Err(e) = prog.check() {
+            return e.to_string();
+        }
+        self.procs = prog.procs.clone();
+        if prog.main == Com::CSkip && !defined.is_empty() {
+            return format!("defined {}", defined.join(", "));
+        }
+        let (result, output) = prog.run_output(self.st.clone(), self.fuel);
+        if result.is_ok() {
+            self.vars.extend(prog.main.vars());
+        }
+        self.report(result, output)
+    }
+
+    /// What a run printed and how it ended, keeping the state it got to.
+    fn report(&mut self, result: Result<RunResult, EvalError>, output: Vec<i64>) -> String {
+        let mut lines: Vec<String> = output.iter().map(i64::to_string).collect();
+        self.paused = None;
+        match result {
+            Ok(result) => {
+                if let RunResult::OutOfGas(_, rest) = &result {
+                    self.paused = Some(rest.clone());
+                }
+                self.st = result.state().clone();
+                lines.push(self.show());
+                if self.paused.is_some() {
+                    lines.push("out of fuel; :continue runs the rest".to_string());
+                }
+                if let RunResult::Thrown(_, code) = result {
+                    lines.push(format!("uncaught exception {}", code));
+                }
+            }
+            Err(e) => lines.push(format!("{}; the state is unchanged", e)),
+        }
+        lines.join("\n")
+    }
+
+    /// Run `c` with the small-step semantics, one line per configuration.
+    fn step(&mut self, c: Com) -> String {
+        self.vars.extend(c.vars());
+        let vars: Vec<&String> = self.vars.iter().collect();
+        let mut lines = Vec::new();
+        let (mut st, mut c) = (self.st.clone(), Config::from(c));
+        for _ in 0..MAX_STEPS {
+            lines.push(format!("{}  [{}]", c, st.show(&vars)));
+            match step_output(&st, &c) {
+                Ok(Some((st1, c1, printed))) => {
+                    (st, c) = (st1, c1);
+                    lines.extend(printed.map(|n| format!("printed {}", n)));
+                }
+                Ok(None) => {
+                    self.st = st;
+                    return lines.join("\n");
+                }
+                Err(e) => {
+                    lines.push(format!("{}; the state is unchanged", e));
+                    return lines.join("\n");
+                }
+            }
+        }
+        lines.push(format!(
+            "stopped after {} steps; the state is unchanged",
+            MAX_STEPS
+        ));
+        lines.join("\n")
+    }
+}
+
+fn main() -> io::Result<()> {
+    let mut session = Session::new();
+    let mut pending = String::new();
+    let stdin = io::stdin();
+    let mut lines = stdin.lock().lines();
+    loop {
+        print!("{}", if pending.is_empty() { "imp> " } else { "...  " });
+        io::stdout().flush()?;
+        let Some(line) = lines.next() else {
+            println!();
+            return Ok(());
+        };
+        pending.push_str(&line?);
+        pending.push('\n');
+        match session.handle(&pending) {
+            Reply::Incomplete => continue,
+            Reply::Quit => return Ok(()),
+            Reply::Output(out) => {
+                if !out.is_empty() {
+                    println!("{}", out);
+                }
+            }
+        }
+        pending.clear();
+    }
+}
+
+#[cfg(test)]
+mod test_repl {
+    use super::*;
+
+    fn reply(session: &mut Session, input: &str) -> String {
+        match session.handle(input) {
+            Reply::Output(out) => out,
+            Reply::Incomplete => "<incomplete>".to_string(),
+            Reply::Quit => "<quit>".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_state_carries_over() {
+        let mut s = Session::new();
+        assert_eq!(reply(&mut s, "X := 5; Y := 1"), "X=5, Y=1");
+        assert_eq!(
+            reply(&mut s, "while ~(X = 0) do Y := Y * X; X := X - 1 end"),
+            "X=0, Y=120"
+        );
+        assert_eq!(reply(&mut s, ":reset"), "state and procedures cleared");
+        assert_eq!(reply(&mut s, "Z := X"), "X=0, Z=0");
+    }
+
+    #[test]
+    fn test_multiline_and_errors() {
+        let mut s = Session::new();
+        assert_eq!(reply(&mut s, "while true do\n"), "<incomplete>");
+        assert_eq!(
+            reply(&mut s, "while true do\n skip end"),
+            "(no variables)\nout of fuel; :continue runs the rest"
+        );
+        assert_eq!(
+            reply(&mut s, "X := := 1"),
+            "expected an arithmetic expression at line 1, col 6, found `:=`\n\
+             1 | X := := 1\n  |      ^^"
+        );
+        assert_eq!(
+            reply(&mut s, "X := 1;\nY := 2;\nZ 3"),
+            "expected `:=` at line 3, col 3, found `3`\n3 | Z 3\n  |   ^"
+        );
+        assert_eq!(
+            reply(&mut s, ":frobnicate"),
+            "unknown command :frobnicate; try :help"
+        );
+        assert_eq!(reply(&mut s, ":quit"), "<quit>");
+    }
+
+    #[test]
+    fn test_procedures_are_remembered() {
+        let mut s = Session::new();
+        assert_eq!(
+            reply(&mut s, "proc double(N) returns R do R := N + N end"),
+            "defined double"
+        );
+        assert_eq!(reply(&mut s, "X := double(21)"), "X=42");
+        assert_eq!(
+            reply(&mut s, "Y := double(X); print Y; print Y + 1"),
+            "84\n85\nX=42, Y=84"
+        );
+        assert_eq!(
+            reply(&mut s, "X := triple(1)"),
+            "the main command calls undefined procedure `triple`"
+        );
+    }
+
+    #[test]
+    fn test_uncaught_exception() {
+        let mut s = Session::new();
+        reply(
+            &mut s,
+            "proc check(N) returns R do if N <= 0 then throw N end; R := N end",
+        );
+        assert_eq!(
+            reply(&mut s, "X := 3; Y := check(X - 5)"),
+            "X=3, Y=0\nuncaught exception -2"
+        );
+        assert_eq!(
+            reply(&mut s, "try Y := check(X - 5) catch E do Y := E * 10 end"),
+            "E=-2, X=3, Y=-20"
+        );
+    }
+
+    #[test]
+    fn test_continue_after_out_of_fuel() {
+        let mut s = Session {
+            fuel: 10,
+            ..Session::new()
+        };
+        assert_eq!(reply(&mut s, ":continue"), "nothing to continue");
+        assert_eq!(
+            reply(&mut s, "while X <= 14 do X := X + 1 end; print X"),
+            "X=10\nout of fuel; :continue runs the rest"
+        );
+        assert_eq!(reply(&mut s, ":continue"), "15\nX=15");
+        assert_eq!(reply(&mut s, ":continue"), "nothing to continue");
+    }
+
+    #[test]
+    fn test_step() {
+        let mut s = Session::new();
+        reply(&mut s, "X := 1");
+        assert_eq!(
+            reply(&mut s, ":step X := X + 1; Y := X"),
+            "X := X + 1; Y := X  [X=1, Y=0]\n\
+             X := 1 + 1; Y := X  [X=1, Y=0]\n\
+             X := 2; Y := X  [X=1, Y=0]\n\
+             skip; Y := X  [X=2, Y=0]\n\
+             Y := X  [X=2, Y=0]\n\
+             Y := 2  [X=2, Y=0]\n\
+             skip  [X=2, Y=2]"
+        );
+        assert_eq!(reply(&mut s, "skip"), "X=2, Y=2");
+        assert_eq!(
+            reply(&mut s, ":step X := 5; Y := f(X)"),
+            "X := 5; Y := f(X)  [X=2, Y=2]\n\
+             skip; Y := f(X)  [X=5, Y=2]\n\
+             Y := f(X)  [X=5, Y=2]\n\
+             not supported here: procedure calls; the state is unchanged"
+        );
+    }
+
+    #[test]
+    fn test_load() {
+        let path = std::env::temp_dir().join(format!("imp_repl_test_{}.imp", std::process::id()));
+        fs::write(
+            &path,
+            "proc sq(N) returns R do R := N * N end\nY := sq(X + 1)",
+        )
+        .unwrap();
+        let mut s = Session::new();
+        reply(&mut s, "X := 3");
+        assert_eq!(
+            reply(&mut s, &format!(":load {}", path.display())),
+            "X=3, Y=16"
+        );
+        fs::write(&path, "X := 1;\nwhile X <= 3 do\n  X := X +\nend").unwrap();
+        assert_eq!(
+            reply(&mut s, &format!(":load {}", path.display())),
+            format!(
+                "{}: expected an arithmetic expression at line 4, col 1, found `end`\n\
+                 4 | end\n  | ^^^",
+                path.display()
+            )
+        );
+        fs::remove_file(&path).unwrap();
+        assert!(reply(&mut s, &format!(":load {}", path.display())).starts_with("cannot read"));
+    }
+}
@@ -0,0 +1,607 @@
+//! An interactive evaluator for the lambda calculi. In STLC mode, the
+//! default, each term is typechecked, then evaluated call by value to a
+//! value, printed with its type:
+//!
+//! ```text
+//! stlc> double = \n:Nat. mult 2 n
+//! defined double : Nat -> Nat
+//! stlc> double (succ 1)
+//! 4 : Nat
+//! stlc> :trace double (succ 1)
+//! (\n:Nat. mult 2 n) (succ 1)
+//! --> (\n:Nat. mult 2 n) 2
+//! --> mult 2 2
+//! --> 4
+//! ```
+//!
+//! `name = term` defines `name`, which the terms after it can use for
+//! `term`. In untyped mode, after `:mode untyped`, terms are those of the
+//! untyped lambda calculus, and they are reduced in normal order to their
+//! normal forms, under binders too. As in the Imp interpreter, input may
+//! span several lines; the prompt changes to `...` until it is complete.
+//! Type `:help` for the rest.
+
+use std::fmt;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::ops::Range;
+
+use rust_coq::lambda::{self, Term};
+use rust_coq::stlc::{self, empty_context, Store, Tm, Ty};
+
+/// Steps a term may take to reach a value or normal form.
+const FUEL: u64 = 100_000;
+
+/// Steps `:trace` shows before giving up.
+const MAX_STEPS: usize = 1_000;
+
+const HELP: &str = "\
+Enter a term to evaluate it, or `name = term` to define name.
+  :type TERM    show the type of TERM
+  :step TERM    show what TERM steps to
+  :trace TERM   evaluate TERM one step at a time, printing each step
+  :load FILE    add the definitions in FILE
+  :mode [MODE]  show the mode, or switch to MODE: stlc, typed and called
+                by value, or untyped, in normal order to a normal form
+  :reset        forget all definitions
+  :help         show this message
+  :quit         leave (so does end of input)";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    Stlc,
+    Untyped,
+}
+
+impl fmt::Display for Mode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Mode::Stlc => write!(f, "stlc"),
+            Mode::Untyped => write!(f, "untyped"),
+        }
+    }
+}
+
+#[derive(Clone)]
+struct Session {
+    mode: Mode,
+    /// The definitions of each mode, oldest first. Each has those before
+    /// it substituted in, so an STLC one is closed.
+    stlc_defs: Vec<(String, Tm)>,
+    untyped_defs: Vec<(String, Term)>,
+    /// The fuel each term gets, `FUEL` but for the tests.
+    fuel: u64,
+}
+
+/// What a line of input amounts to.
+enum Reply {
+    Output(String),
+    /// The input isn't finished; wait for more lines.
+    Incomplete,
+    Quit,
+}
+
+/// A term read in the session's mode, with the definitions substituted
+/// in; an STLC one with its type.
+enum Parsed {
+    Stlc(Tm, Ty),
+    Untyped(Term),
+}
+
+/// Why a term couldn't be read, as reported to the user.
+enum Failure {
+    /// The input ended in the middle of it.
+    Incomplete(String),
+    /// It doesn't parse, or doesn't typecheck.
+    Error(String),
+}
+
+impl From<Failure> for Reply {
+    fn from(failure: Failure) -> Self {
+        match failure {
+            Failure::Incomplete(_) => Reply::Incomplete,
+            Failure::Error(e) => Reply::Output(e),
+        }
+    }
+}
+
+impl Session {
+    fn new() -> Self {
+        Session {
+            mode: Mode::Stlc,
+            stlc_defs: Vec::new(),
+            untyped_defs: Vec::new(),
+            fuel: FUEL,
+        }
+    }
+
+    fn handle(&mut self, input: &str) -> Reply {
+        let input = input.trim();
+        let (cmd, arg) = match input.split_once(char::is_whitespace) {
+            Some((cmd, arg)) => (cmd, arg.trim()),
+            None => (input, ""),
+        };
+        let output = match cmd {
+            "" => return Reply::Output(String::new()),
+            ":quit" | ":q" => return Reply::Quit,
+            ":help" | ":h" => HELP.to_string(),
+            ":reset" => {
+                *self = Session {
+                    mode: self.mode,
+                    fuel: self.fuel,
+                    ..Session::new()
+                };
+                "definitions cleared".to_string()
+            }
+            ":mode" => match arg {
+                "" => format!("{} mode", self.mode),
+                "stlc" | "untyped" => {
+                    self.mode = if arg == "stlc" {
+                        Mode::Stlc
+                    } else {
+                        Mode::Untyped
+                    };
+                    format!("{} mode", self.mode)
+                }
+                _ => format!("unknown mode {}; the modes are stlc and untyped", arg),
+            },
+            ":load" => match fs::read_to_string(arg) {
+                Ok(src) => self.load(arg, &src),
+                Err(e) => format!("cannot read {}: {}", arg, e),
+            },
+            ":type" => match self.read(arg) {
+                Ok(Parsed::Stlc(_, ty)) => ty.to_string(),
+                Ok(Parsed::Untyped(_)) => "untyped terms have no types".to_string(),
+                Err(failure) => return failure.into(),
+            },
+            ":step" => match self.read(arg) {
+                Ok(t) => self.step(t),
+                Err(failure) => return failure.into(),
+            },
+            ":trace" => match self.read(arg) {
+                Ok(t) => self.trace(t),
+                Err(failure) => return failure.into(),
+            },
+            _ if cmd.starts_with(':') => format!("unknown command {}; try :help", cmd),
+            _ => match self.definition(input) {
+                Some((x, body)) => match self.define(x, &body) {
+                    Ok(out) => out,
+                    Err(failure) => return failure.into(),
+                },
+                None => match self.read(input) {
+                    Ok(t) => self.eval(t),
+                    Err(failure) => return failure.into(),
+                },
+            },
+        };
+        Reply::Output(output)
+    }
+
+    /// Parse `input` as a term of the mode, put the definitions in it, and
+    /// typecheck it if it is an STLC term.
+    fn read(&self, input: &str) -> Result<Parsed, Failure> {
+        match self.mode {
+            Mode::Stlc => {
+                let t = stlc::parse_tm(input).map_err(|e| match e {
+                    stlc::ParseError::UnexpectedEof { .. } => Failure::Incomplete(e.report(input)),
+                    e => Failure::Error(e.report(input)),
+                })?;
+                let t = self
+                    .stlc_defs
+                    .iter()
+                    .fold(t, |t, (x, d)| stlc::subst(&t, x, d));
+                match stlc::type_of(&empty_context(), &t) {
+                    Ok(ty) => Ok(Parsed::Stlc(t, ty)),
+                    Err(e) => Err(Failure::Error(e.to_string())),
+                }
+            }
+            Mode::Untyped => {
+                let t = lambda::parse_term(input).map_err(|e| match e {
+                    lambda::ParseError::UnexpectedEof { .. } => {
+                        Failure::Incomplete(e.report(input))
+                    }
+                    e => Failure::Error(e.report(input)),
+                })?;
+                let t = self
+                    .untyped_defs
+                    .iter()
+                    .fold(t, |t, (x, d)| lambda::subst(&t, x, d));
+                Ok(Parsed::Untyped(t))
+            }
+        }
+    }
+
+    /// If `input` is a definition `x = t`, `x` and `input` with all but `t`
+    /// blanked out, so that errors in `t` are reported where they are.
+    fn definition(&self, input: &str) -> Option<(String, String)> {
+        let (x, t) = input.split_once('=')?;
+        let is_var = match self.mode {
+            Mode::Stlc => matches!(stlc::parse_tm(x), Ok(Tm::Var(_))),
+            Mode::Untyped => matches!(lambda::parse_term(x), Ok(Term::Var(_))),
+        };
+        if !is_var || t.starts_with('>') {
+            return None;
+        }
+        Some((x.trim().to_string(), blank(&input[..=x.len()]) + t))
+    }
+
+    /// Define `x` as the term in `body`, replacing any definition of it.
+    fn define(&mut self, x: String, body: &str) -> Result<String, Failure> {
+        let out = match self.read(body)? {
+            Parsed::Stlc(t, ty) => {
+                self.stlc_defs.retain(|(y, _)| *y != x);
+                self.stlc_defs.push((x.clone(), t));
+                format!("defined {} : {}", x, ty)
+            }
+            Parsed::Untyped(t) => {
+                self.untyped_defs.retain(|(y, _)| *y != x);
+                self.untyped_defs.push((x.clone(), t));
+                format!("defined {}", x)
+            }
+        };
+        Ok(out)
+    }
+
+    /// Add the definitions in `src`, the contents of the file `path`, or
+    /// none of them if one of them is wrong.
+    fn load(&mut self, path: &str, src: &str) -> String {
+        let mut session = self.clone();
+        let mut lines = Vec::new();
+        for entry in entries(src) {
+            // Everything before the entry blanked out, so that errors in it
+            // are reported at their lines in the file.
+            let input = blank(&src[..entry.start]) + &src[entry.clone()];
+            let Some((x, body)) = session.definition(&input) else {
+                let line = src[..entry.start].matches('\n').count() + 1;
+                return format!("{}: line {} is not a definition `name = term`", path, line);
+            };
+            match session.define(x.clone(), &body) {
+                Ok(out) => lines.push(out),
+                Err(Failure::Incomplete(e) | Failure::Error(e)) => {
+                    return format!("{}: in the definition of {}: {}", path, x, e);
+                }
+            }
+        }
+        *self = session;
+        if lines.is_empty() {
+            format!("{}: no definitions", path)
+        } else {
+            lines.join("\n")
+        }
+    }
+
+    /// Evaluate `t` to a value, or reduce it to its normal form.
+    fn eval(&self, t: Parsed) -> String {
+        match t {
+            Parsed::Stlc(t, ty) => match stlc::eval_in(&t, &Store::new(), self.fuel) {
+                Some((v, _)) => format!("{} : {}", v, ty),
+                None => format!("no value after {} steps", self.fuel),
+            },
+            Parsed::Untyped(t) => match lambda::normalize(&t, self.fuel) {
+                Some(n) => n.to_string(),
+                None => format!("no normal form after {} steps", self.fuel),
+            },
+        }
+    }
+
+    /// The term `t` takes one step to.
+    fn step(&self, t: Parsed) -> String {
+        match t {
+            Parsed::Stlc(t, _) => match stlc::step(&t) {
+                Some(t1) => t1.to_string(),
+                None if stlc::is_value(&t) => format!("{} is a value", t),
+                None => format!("{} takes no step", t),
+            },
+            Parsed::Untyped(t) => match lambda::step_normal_order(&t) {
+                Some(t1) => t1.to_string(),
+                None => format!("{} is in normal form", t),
+            },
+        }
+    }
+
+    /// `t` and every term it steps to on the way to a value or normal form.
+    fn trace(&self, t: Parsed) -> String {
+        match t {
+            Parsed::Stlc(t, _) => {
+                let mut store = Store::new();
+                trace(t, |t| {
+                    let (t1, store1) = stlc::step_in(t, &store)?;
+                    store = store1;
+                    Some(t1)
+                })
+            }
+            Parsed::Untyped(t) => trace(t, lambda::step_normal_order),
+        }
+    }
+}
+
+/// `t` and the terms `step` takes it to, one per line, each after the first
+/// marked with `-->`, for at most `MAX_STEPS` steps.
+fn trace<T: fmt::Display>(mut t: T, mut step: impl FnMut(&T) -> Option<T>) -> String {
+    let mut lines = vec![t.to_string()];
+    for _ in 0..MAX_STEPS {
+        match step(&t) {
+            Some(t1) => {
+                lines.push(format!("--> {}", t1));
+                t = t1;
+            }
+            None => return lines.join("\n"),
+        }
+    }
+    lines.push(format!("stopped after {} steps", MAX_STEPS));
+    lines.join("\n")
+}
+
+/// `s` with all but its line breaks turned into spaces.
+fn blank(s: &str) -> String {
+    s.chars().map(|c| if c == '\n' { c } else { ' ' }).collect()
+}
+
+/// The byte ranges of the entries of a file: each starts at the beginning
+/// of a line, and goes on over the indented and blank lines after it.
+/// Lines starting with `#` are comments.
+fn entries(src: &str) -> Vec<Range<usize>> {
+    let mut entries: Vec<Range<usize>> = Vec::new();
+    let mut open = false;
+    let mut pos = 0;
+    for line in src.split_inclusive('\n') {
+        let end = pos + line.len();
+        if line.starts_with('#') {
+            open = false;
+        } else if line.starts_with(char::is_whitespace) {
+            if let Some(entry) = entries.last_mut().filter(|_| open) {
+                entry.end = end;
+            }
+        } else {
+            entries.push(pos..end);
+            open = true;
+        }
+        pos = end;
+    }
+    entries
+}
+
+fn main() -> io::Result<()> {
+    let mut session = Session::new();
+    let mut pending = String::new();
+    let stdin = io::stdin();
+    let mut lines = stdin.lock().lines();
+    loop {
+        if pending.is_empty() {
+            print!("{}> ", session.mode);
+        } else {
+            print!("...  ");
+        }
+        io::stdout().flush()?;
+        let Some(line) = lines.next() else {
+            println!();
+            return Ok(());
+        };
+        pending.push_str(&line?);
+        pending.push('\n');
+        match session.handle(&pending) {
+            Reply::Incomplete => continue,
+            Reply::Quit => return Ok(()),
+            Reply::Output(out) => {
+                if !out.is_empty() {
+                    println!("{}", out);
+                }
+            }
+        }
+        pending.clear();
+    }
+}
+
+#[cfg(test)]
+mod test_repl {
+    use super::*;
+
+    fn reply(session: &mut Session, input: &str) -> String {
+        match session.handle(input) {
+            Reply::Output(out) => out,
+            Reply::Incomplete => "<incomplete>".to_string(),
+            Reply::Quit => "<quit>".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_evaluate() {
+        let mut s = Session::new();
+        assert_eq!(reply(&mut s, "(\\x:Nat. succ x) 1"), "2 : Nat");
+        assert_eq!(
+            reply(&mut s, "\\x:Bool. if x then false else true"),
+            "\\x:Bool. if x then false else true : Bool -> Bool"
+        );
+        assert_eq!(
+            reply(&mut s, "let r = ref 0 in r := succ (!r); !r"),
+            "1 : Nat"
+        );
+        assert_eq!(reply(&mut s, "try pred error with 0"), "0 : Nat");
+        // Ill-typed terms aren't evaluated.
+        assert_eq!(
+            reply(&mut s, "succ true"),
+            "`true` has type Bool, expected Nat"
+        );
+        assert_eq!(reply(&mut s, "f 1"), "unbound variable f");
+    }
+
+    #[test]
+    fn test_definitions() {
+        let mut s = Session::new();
+        assert_eq!(
+            reply(&mut s, "double = \\n:Nat. mult 2 n"),
+            "defined double : Nat -> Nat"
+        );
+        assert_eq!(
+            reply(&mut s, "quad = \\n:Nat. double (double n)"),
+            "defined quad : Nat -> Nat"
+        );
+        assert_eq!(reply(&mut s, "quad 3"), "12 : Nat");
+        // A definition replaces an earlier one, but not where it was used.
+        assert_eq!(
+            reply(&mut s, "double = \\n:Nat. n"),
+            "defined double : Nat -> Nat"
+        );
+        assert_eq!(reply(&mut s, "quad 3"), "12 : Nat");
+        assert_eq!(reply(&mut s, "double 3"), "3 : Nat");
+        // A bound variable shadows a definition.
+        assert_eq!(reply(&mut s, "(\\double:Bool. double) true"), "true : Bool");
+        assert_eq!(
+            reply(&mut s, "bad = double true"),
+            "`true` has type Bool, expected Nat"
+        );
+        // `=` elsewhere doesn't make a definition.
+        assert_eq!(reply(&mut s, "{x=1}.x"), "1 : Nat");
+        assert_eq!(reply(&mut s, ":reset"), "definitions cleared");
+        assert_eq!(reply(&mut s, "quad 3"), "unbound variable quad");
+    }
+
+    #[test]
+    fn test_type_step_trace() {
+        let mut s = Session::new();
+        reply(&mut s, "double = \\n:Nat. mult 2 n");
+        assert_eq!(reply(&mut s, ":type double"), "Nat -> Nat");
+        assert_eq!(reply(&mut s, ":type \\r:{x:Nat}. r.x"), "{x:Nat} -> Nat");
+        assert_eq!(
+            reply(&mut s, ":step double (succ 1)"),
+            "(\\n:Nat. mult 2 n) 2"
+        );
+        assert_eq!(reply(&mut s, ":step 3"), "3 is a value");
+        assert_eq!(reply(&mut s, ":step error"), "error takes no step");
+        assert_eq!(
+            reply(&mut s, ":trace double (succ 1)"),
+            "(\\n:Nat. mult 2 n) (succ 1)\n\
+             --> (\\n:Nat. mult 2 n) 2\n\
+             --> mult 2 2\n\
+             --> 4"
+        );
+        // The store is kept from one step to the next.
+        assert_eq!(
+            reply(&mut s, ":trace (\\r:Ref Nat. !r) (ref 5)"),
+            "(\\r:Ref Nat. !r) (ref 5)\n\
+             --> (\\r:Ref Nat. !r) (loc 0)\n\
+             --> !(loc 0)\n\
+             --> 5"
+        );
+        let out = reply(&mut s, ":trace fix (\\f:Nat -> Nat. \\n:Nat. f (succ n)) 0");
+        assert!(out.ends_with("stopped after 1000 steps"), "{}", out);
+        assert_eq!(
+            reply(&mut s, ":step if 1 then 2 else 3"),
+            "`1` has type Nat, expected Bool"
+        );
+        s.fuel = 10;
+        assert_eq!(
+            reply(&mut s, "fix (\\f:Nat -> Nat. \\n:Nat. f n) 0"),
+            "no value after 10 steps"
+        );
+    }
+
+    #[test]
+    fn test_untyped_mode() {
+        let mut s = Session::new();
+        assert_eq!(reply(&mut s, ":mode"), "stlc mode");
+        reply(&mut s, "one = 1");
+        assert_eq!(reply(&mut s, ":mode untyped"), "untyped mode");
+        assert_eq!(reply(&mut s, "two = \\f x. f (f x)"), "defined two");
+        assert_eq!(
+            reply(&mut s, "plus = \\m n f x. m f (n f x)"),
+            "defined plus"
+        );
+        assert_eq!(reply(&mut s, "plus two two"), "\\f. \\x. f (f (f (f x)))");
+        assert_eq!(
+            reply(&mut s, ":trace (\\x. \\y. x) y"),
+            "(\\x. \\y. x) y\n--> \\y0. y"
+        );
+        assert_eq!(reply(&mut s, ":step \\x. x"), "\\x. x is in normal form");
+        assert_eq!(reply(&mut s, ":type two"), "untyped terms have no types");
+        s.fuel = 10;
+        assert_eq!(
+            reply(&mut s, "(\\x. x x) (\\x. x x)"),
+            "no normal form after 10 steps"
+        );
+        // Each mode has its own definitions.
+        assert_eq!(reply(&mut s, "one"), "one");
+        assert_eq!(reply(&mut s, ":mode stlc"), "stlc mode");
+        assert_eq!(reply(&mut s, "one"), "1 : Nat");
+        assert_eq!(
+            reply(&mut s, ":mode typed"),
+            "unknown mode typed; the modes are stlc and untyped"
+        );
+    }
+
+    #[test]
+    fn test_multiline_and_errors() {
+        let mut s = Session::new();
+        assert_eq!(reply(&mut s, "\\x:Nat.\n"), "<incomplete>");
+        assert_eq!(
+            reply(&mut s, "\\x:Nat.\n succ x"),
+            "\\x:Nat. succ x : Nat -> Nat"
+        );
+        assert_eq!(reply(&mut s, "id =\n"), "<incomplete>");
+        assert_eq!(
+            reply(&mut s, "id =\n  \\x:Nat. x"),
+            "defined id : Nat -> Nat"
+        );
+        assert_eq!(
+            reply(&mut s, "(\\x:Nat. x) )"),
+            "expected end of input at line 1, col 13, found `)`\n\
+             1 | (\\x:Nat. x) )\n  |             ^"
+        );
+        // Errors in a definition are where they are in it.
+        assert_eq!(
+            reply(&mut s, "f = \\x:Nat x"),
+            "expected `.` or `->` at line 1, col 12, found `x`\n\
+             1 |     \\x:Nat x\n  |            ^"
+        );
+        assert_eq!(
+            reply(&mut s, ":frobnicate"),
+            "unknown command :frobnicate; try :help"
+        );
+        assert_eq!(reply(&mut s, ":quit"), "<quit>");
+    }
+
+    #[test]
+    fn test_load() {
+        let path =
+            std::env::temp_dir().join(format!("lambda_repl_test_{}.stlc", std::process::id()));
+        fs::write(
+            &path,
+            "# Arithmetic.\n\
+             double = \\n:Nat. mult 2 n\n\
+             quad =\n  \\n:Nat.\n\n    double (double n)\n",
+        )
+        .unwrap();
+        let mut s = Session::new();
+        assert_eq!(
+            reply(&mut s, &format!(":load {}", path.display())),
+            "defined double : Nat -> Nat\ndefined quad : Nat -> Nat"
+        );
+        assert_eq!(reply(&mut s, "quad 2"), "8 : Nat");
+        // Nothing is defined from a file with a mistake in it.
+        fs::write(
+            &path,
+            "half = \\n:Nat. n\nsix = double 3\nbad = \\n:Nat.\n  succ n n)",
+        )
+        .unwrap();
+        assert_eq!(
+            reply(&mut s, &format!(":load {}", path.display())),
+            format!(
+                "{}: in the definition of bad: \
+                 expected end of input at line 4, col 11, found `)`\n\
+                 4 |   succ n n)\n  |           ^",
+                path.display()
+            )
+        );
+        assert_eq!(reply(&mut s, "six"), "unbound variable six");
+        fs::write(&path, "one = 1\nsucc one\n").unwrap();
+        assert_eq!(
+            reply(&mut s, &format!(":load {}", path.display())),
+            format!(
+                "{}: line 2 is not a definition `name = term`",
+                path.display()
+            )
+        );
+        fs::remove_file(&path).unwrap();
+        assert!(reply(&mut s, &format!(":load {}", path.display())).starts_with("cannot read"));
+    }
+}
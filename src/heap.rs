@@ -0,0 +1,176 @@
+use std::fmt;
+
+use crate::map::{pm_disjoint_union, pm_empty, pm_remove, pm_update, PartialMap};
+
+/// Heap locations.
+pub type Loc = usize;
+
+/// A heap is a partial map from locations to values together with the next
+/// location `alloc` will hand out. Every location ever allocated is below
+/// that counter, which is what makes the domain of the otherwise opaque
+/// partial map enumerable.
+#[derive(Clone)]
+pub struct Heap<V> {
+    cells: PartialMap<Loc, V>,
+    next: Loc,
+}
+
+impl<V: 'static + Clone> Heap<V> {
+    pub fn new() -> Self {
+        Heap {
+            cells: pm_empty(),
+            next: 0,
+        }
+    }
+
+    /// The one-cell heap `l |-> v`.
+    pub fn singleton(l: Loc, v: V) -> Self {
+        Heap {
+            cells: pm_update(pm_empty(), l, v),
+            next: l + 1,
+        }
+    }
+
+    /// Store `v` at a fresh location.
+    pub fn alloc(self, v: V) -> (Self, Loc) {
+        let l = self.next;
+        let heap = Heap {
+            cells: pm_update(self.cells, l, v),
+            next: l + 1,
+        };
+        (heap, l)
+    }
+
+    pub fn lookup(&self, l: Loc) -> Option<V> {
+        (self.cells)(&l)
+    }
+
+    pub fn contains(&self, l: Loc) -> bool {
+        self.lookup(l).is_some()
+    }
+
+    /// Overwrite an allocated location; `None` if `l` is dangling.
+    pub fn assign(self, l: Loc, v: V) -> Option<Self> {
+        if !self.contains(l) {
+            return None;
+        }
+        Some(Heap {
+            cells: pm_update(self.cells, l, v),
+            next: self.next,
+        })
+    }
+
+    /// Deallocate `l`; `None` if it wasn't allocated.
+    pub fn free(self, l: Loc) -> Option<Self> {
+        if !self.contains(l) {
+            return None;
+        }
+        Some(Heap {
+            cells: pm_remove(self.cells, l),
+            next: self.next,
+        })
+    }
+
+    /// The allocated locations in increasing order.
+    pub fn domain(&self) -> Vec<Loc> {
+        (0..self.next).filter(|l| self.contains(*l)).collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.domain().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Whether no location is allocated in both heaps.
+    pub fn disjoint(&self, other: &Heap<V>) -> bool {
+        (0..self.next.max(other.next)).all(|l| !(self.contains(l) && other.contains(l)))
+    }
+
+    /// The disjoint union `h1 ⊎ h2`, or `None` if the heaps overlap.
+    pub fn disjoint_union(self, other: Heap<V>) -> Option<Self> {
+        let next = self.next.max(other.next);
+        let keys: Vec<Loc> = (0..next).collect();
+        pm_disjoint_union(self.cells, other.cells, &keys).map(|cells| Heap { cells, next })
+    }
+}
+
+impl<V: 'static + Clone> Default for Heap<V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<V: 'static + Clone + fmt::Debug> fmt::Debug for Heap<V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map()
+            .entries(
+                self.domain()
+                    .into_iter()
+                    .map(|l| (l, self.lookup(l).unwrap())),
+            )
+            .finish()
+    }
+}
+
+/// Heaps are equal when they hold the same values at the same locations.
+impl<V: 'static + Clone + PartialEq> PartialEq for Heap<V> {
+    fn eq(&self, other: &Self) -> bool {
+        (0..self.next.max(other.next)).all(|l| self.lookup(l) == other.lookup(l))
+    }
+}
+
+#[cfg(test)]
+mod test_heap {
+    use super::*;
+
+    #[test]
+    fn test_alloc_and_lookup() {
+        let (heap, l1) = Heap::new().alloc(1);
+        let (heap, l2) = heap.alloc(2);
+        assert_ne!(l1, l2);
+        assert_eq!(heap.lookup(l1), Some(1));
+        assert_eq!(heap.lookup(l2), Some(2));
+        assert_eq!(heap.domain(), vec![l1, l2]);
+    }
+
+    #[test]
+    fn test_assign() {
+        let (heap, l) = Heap::new().alloc(1);
+        let heap = heap.assign(l, 10).unwrap();
+        assert_eq!(heap.lookup(l), Some(10));
+        assert!(heap.assign(l + 1, 0).is_none());
+    }
+
+    #[test]
+    fn test_free() {
+        let (heap, l) = Heap::new().alloc("x");
+        let heap = heap.free(l).unwrap();
+        assert_eq!(heap.lookup(l), None);
+        assert!(heap.is_empty());
+        assert!(heap.free(l).is_none());
+    }
+
+    #[test]
+    fn test_disjoint() {
+        let h1 = Heap::singleton(0, 'a');
+        let h2 = Heap::singleton(3, 'b');
+        assert!(h1.disjoint(&h2));
+        assert!(!h1.disjoint(&Heap::singleton(0, 'c')));
+        let union = h1.clone().disjoint_union(h2).unwrap();
+        assert_eq!(union.domain(), vec![0, 3]);
+        assert!(union.disjoint_union(h1).is_none());
+    }
+
+    #[test]
+    fn test_alloc_after_union_is_fresh() {
+        let union = Heap::singleton(0, 0)
+            .disjoint_union(Heap::singleton(4, 4))
+            .unwrap();
+        let (heap, l) = union.alloc(5);
+        assert_eq!(l, 5);
+        assert_eq!(heap.len(), 3);
+    }
+}
@@ -0,0 +1,1449 @@
+//! Imp, the small imperative language of the Software Foundations Imp
+//! chapter, evaluated over the crate's `State`s.
+
+use std::collections::BTreeSet;
+use std::fmt;
+use std::mem;
+
+use crate::map::{pm_empty, tm_update};
+use crate::state::{empty_state, State, StateExt};
+
+mod analysis;
+mod arrays;
+mod bmc;
+mod bytecode;
+mod cevalfun;
+mod cost;
+mod dce;
+mod debugger;
+mod decorated;
+mod derivation;
+mod desugar;
+mod difftest;
+mod discharge;
+mod hoare;
+mod invariants;
+mod liveness;
+mod nondet;
+mod par;
+mod parser;
+mod pe;
+mod pretty;
+mod procs;
+mod proof;
+mod random;
+mod register;
+mod sep;
+mod smallstep;
+mod smt;
+mod stack;
+mod staged;
+mod total;
+mod transform;
+mod wp;
+
+pub use analysis::{analyze_intervals, AbsState, Interval, IntervalAnalysis};
+use arrays::write_array;
+pub use arrays::{
+    aeval_arrays, array_from, array_to_vec, beval_arrays, ceval_arrays, empty_arrays, Array,
+    Arrays, OutOfBounds,
+};
+pub use bmc::{check_invariant, reachable_states, TraceStep, Violation};
+pub use bytecode::{
+    decode, encode, s_execute_bytecode, BytecodeError, DecodeError, MAGIC, VERSION,
+};
+pub use cevalfun::{ceval_step, ceval_step_adaptive};
+pub use cost::{ceval_cost, normalize_cost, CostModel};
+pub use dce::{eliminate_dead_code, eliminate_dead_code_for};
+pub use debugger::{Breakpoint, Debugger, Stop};
+pub use decorated::{verification_conditions, DCom, Decorated};
+pub use derivation::{ceval_derivation, Derivation, Rule};
+pub use desugar::DesugarFor;
+use desugar::{continue_runs, continue_runs_running};
+pub use difftest::{
+    check_aexp_backend, check_bigstep_smallstep_agree, check_bigstep_smallstep_random,
+    check_bigstep_smallstep_random_seeded, check_cequiv, check_cequiv_seeded,
+    check_compiler_correct, check_compiler_correct_seeded, AexpCounterexample, Counterexample,
+    Difference, Disagreement,
+};
+pub use discharge::{discharge, Strategy, Verdict};
+pub use hoare::{Assertion, Formula, HoareTriple, Implication, TripleCheck, TripleViolation};
+pub use invariants::{check_loop_invariant, infer_invariants, InvariantFailure, Obligation};
+pub use liveness::{liveness, liveness_for, LivenessInfo};
+pub use nondet::{ceval_nondet, Reachable};
+pub use par::{interleavings, run_random_schedule};
+pub use parser::{
+    parse_aexp, parse_bexp, parse_com, parse_program, tokenize, ParseError, Span, Token,
+};
+#[cfg(feature = "spans")]
+pub use parser::{parse_com_spanned, SpanTree};
+pub use pe::{check_pe_correct, pe_aexp, pe_bexp, pe_com, pe_update, PeState};
+pub use procs::{Proc, Procs, Program, ProgramError};
+pub use proof::{HoareProof, ProofError};
+pub use random::{
+    gen_random_com, minimize_com, minimize_state, random_aexp, random_bexp, random_state,
+    random_value, shrink_com, shrink_value,
+};
+pub use register::{r_compile, r_execute, Label, RCond, RFinal, RInstr, ROp, Reg};
+pub use sep::{hceval, FrameViolation, HCom, HeapAssertion, HeapError, HeapTriple, HeapViolation};
+use smallstep::Running;
+pub use smallstep::{
+    astep, bstep, multistep, normalize, normalize_output, step, step_output, Config,
+};
+pub use smt::{smt_aexp, smt_bexp, smt_formula, smt_implication, smt_satisfiable, smt_valid};
+pub use stack::{s_compile, s_execute, SInstr, StackUnderflow};
+pub use staged::compile_to_fn;
+pub use total::{Correctness, TotalCheck, TotalTriple, VariantViolation};
+pub use transform::{check_sound, Optimize0Plus, OptimizeMult1, Transform, Unsound};
+pub use wp::{wp, wp_with_invariants};
+
+/// Arithmetic expressions.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Aexp {
+    ANum(i64),
+    AId(String),
+    APlus(Box<Aexp>, Box<Aexp>),
+    AMinus(Box<Aexp>, Box<Aexp>),
+    AMult(Box<Aexp>, Box<Aexp>),
+    /// `AIndex(a, i)` reads element `i` of the array `a`, written `a[i]`.
+    AIndex(String, Box<Aexp>),
+}
+
+/// Boolean expressions.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Bexp {
+    BTrue,
+    BFalse,
+    BEq(Box<Aexp>, Box<Aexp>),
+    BLe(Box<Aexp>, Box<Aexp>),
+    BNot(Box<Bexp>),
+    BAnd(Box<Bexp>, Box<Bexp>),
+}
+
+// Shorthands for building expressions without spelling out every `Box`.
+impl Aexp {
+    pub fn num(n: i64) -> Aexp {
+        Aexp::ANum(n)
+    }
+
+    pub fn var(x: &str) -> Aexp {
+        Aexp::AId(x.to_string())
+    }
+
+    pub fn plus(a1: Aexp, a2: Aexp) -> Aexp {
+        Aexp::APlus(Box::new(a1), Box::new(a2))
+    }
+
+    pub fn minus(a1: Aexp, a2: Aexp) -> Aexp {
+        Aexp::AMinus(Box::new(a1), Box::new(a2))
+    }
+
+    pub fn mult(a1: Aexp, a2: Aexp) -> Aexp {
+        Aexp::AMult(Box::new(a1), Box::new(a2))
+    }
+
+    pub fn index(a: &str, i: Aexp) -> Aexp {
+        Aexp::AIndex(a.to_string(), Box::new(i))
+    }
+
+    /// The variables the expression reads. Arrays don't count.
+    pub fn vars(&self) -> BTreeSet<String> {
+        let mut vars = BTreeSet::new();
+        self.collect_vars(&mut vars);
+        vars
+    }
+
+    fn collect_vars(&self, vars: &mut BTreeSet<String>) {
+        match self {
+            Aexp::ANum(_) => {}
+            Aexp::AId(x) => {
+                vars.insert(x.clone());
+            }
+            Aexp::APlus(a1, a2) | Aexp::AMinus(a1, a2) | Aexp::AMult(a1, a2) => {
+                a1.collect_vars(vars);
+                a2.collect_vars(vars);
+            }
+            Aexp::AIndex(_, i) => i.collect_vars(vars),
+        }
+    }
+
+    /// The expression with every read of `x` replaced by `a`.
+    pub fn subst(&self, x: &str, a: &Aexp) -> Aexp {
+        match self {
+            Aexp::ANum(_) => self.clone(),
+            Aexp::AId(y) if y == x => a.clone(),
+            Aexp::AId(_) => self.clone(),
+            Aexp::APlus(a1, a2) => Aexp::plus(a1.subst(x, a), a2.subst(x, a)),
+            Aexp::AMinus(a1, a2) => Aexp::minus(a1.subst(x, a), a2.subst(x, a)),
+            Aexp::AMult(a1, a2) => Aexp::mult(a1.subst(x, a), a2.subst(x, a)),
+            Aexp::AIndex(y, i) => Aexp::index(y, i.subst(x, a)),
+        }
+    }
+}
+
+impl Bexp {
+    pub fn eq(a1: Aexp, a2: Aexp) -> Bexp {
+        Bexp::BEq(Box::new(a1), Box::new(a2))
+    }
+
+    pub fn le(a1: Aexp, a2: Aexp) -> Bexp {
+        Bexp::BLe(Box::new(a1), Box::new(a2))
+    }
+
+    #[allow(clippy::should_implement_trait)]
+    pub fn not(b: Bexp) -> Bexp {
+        Bexp::BNot(Box::new(b))
+    }
+
+    pub fn and(b1: Bexp, b2: Bexp) -> Bexp {
+        Bexp::BAnd(Box::new(b1), Box::new(b2))
+    }
+
+    /// The variables the expression reads.
+    pub fn vars(&self) -> BTreeSet<String> {
+        let mut vars = BTreeSet::new();
+        self.collect_vars(&mut vars);
+        vars
+    }
+
+    fn collect_vars(&self, vars: &mut BTreeSet<String>) {
+        match self {
+            Bexp::BTrue | Bexp::BFalse => {}
+            Bexp::BEq(a1, a2) | Bexp::BLe(a1, a2) => {
+                a1.collect_vars(vars);
+                a2.collect_vars(vars);
+            }
+            Bexp::BNot(b) => b.collect_vars(vars),
+            Bexp::BAnd(b1, b2) => {
+                b1.collect_vars(vars);
+                b2.collect_vars(vars);
+            }
+        }
+    }
+}
+
+/// Evaluate an arithmetic expression. Imp numbers are `i64`s here rather
+/// than Coq's `nat`s, so `-` really subtracts; all three operations wrap
+/// on overflow instead of panicking.
+///
+/// A `State` holds no arrays, so every array element reads as `0`; see
+/// `aeval_arrays` for the general case.
+pub fn aeval(st: &State, a: &Aexp) -> i64 {
+    aeval_arrays(st, &empty_arrays(), OutOfBounds::Zero, a)
+        .expect("reading an array with OutOfBounds::Zero cannot fail")
+}
+
+/// Evaluate a boolean expression. `BAnd` short-circuits, which is
+/// unobservable since expressions have no side effects.
+pub fn beval(st: &State, b: &Bexp) -> bool {
+    beval_arrays(st, &empty_arrays(), OutOfBounds::Zero, b)
+        .expect("reading an array with OutOfBounds::Zero cannot fail")
+}
+
+#[cfg(test)]
+mod test_imp_exp {
+    use super::*;
+    use crate::state;
+    use crate::state::empty_state;
+
+    #[test]
+    fn test_aexp1() {
+        // 2 + (3 * 4) = 14
+        let a = Aexp::plus(Aexp::num(2), Aexp::mult(Aexp::num(3), Aexp::num(4)));
+        assert_eq!(aeval(&empty_state(), &a), 14);
+    }
+
+    #[test]
+    fn test_aexp_vars() {
+        // X + Y * Z with X = 3, Y = 4, Z = 5
+        let st = state! {"X" => 3, "Y" => 4, "Z" => 5};
+        let a = Aexp::plus(Aexp::var("X"), Aexp::mult(Aexp::var("Y"), Aexp::var("Z")));
+        assert_eq!(aeval(&st, &a), 23);
+    }
+
+    #[test]
+    fn test_aexp_minus_is_not_truncated() {
+        let a = Aexp::minus(Aexp::num(2), Aexp::var("X"));
+        assert_eq!(aeval(&state! {"X" => 5}, &a), -3);
+    }
+
+    #[test]
+    fn test_aexp_wraps_on_overflow() {
+        let a = Aexp::plus(Aexp::num(i64::MAX), Aexp::num(1));
+        assert_eq!(aeval(&empty_state(), &a), i64::MIN);
+    }
+
+    #[test]
+    fn test_bexp1() {
+        // true && ~(X <= 4) with X = 5
+        let b = Bexp::and(
+            Bexp::BTrue,
+            Bexp::not(Bexp::le(Aexp::var("X"), Aexp::num(4))),
+        );
+        assert!(beval(&state! {"X" => 5}, &b));
+        assert!(!beval(&state! {"X" => 4}, &b));
+    }
+
+    #[test]
+    fn test_bexp_eq() {
+        let b = Bexp::eq(Aexp::var("X"), Aexp::plus(Aexp::num(1), Aexp::num(1)));
+        assert!(beval(&state! {"X" => 2}, &b));
+        assert!(!beval(&empty_state(), &b));
+        assert!(!beval(&empty_state(), &Bexp::BFalse));
+    }
+}
+
+/// Commands.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Com {
+    CSkip,
+    CAsgn(String, Aexp),
+    CSeq(Box<Com>, Box<Com>),
+    CIf(Bexp, Box<Com>, Box<Com>),
+    CWhile(Bexp, Box<Com>),
+    /// Leave the innermost enclosing loop.
+    CBreak,
+    /// Skip the rest of the innermost enclosing loop's body.
+    CContinue,
+    /// `CFor(init, b, update, body)` is C's `for`: `init`, then `body` and
+    /// `update` for as long as `b` holds. A `continue` in the body still
+    /// runs `update`. `desugar` turns it into a `while` loop.
+    CFor(Box<Com>, Bexp, Box<Com>, Box<Com>),
+    /// `havoc X` sets `X` to an arbitrary number. `ceval_nondet` explores
+    /// the choices; the deterministic evaluators always pick `0`.
+    CHavoc(String),
+    /// `CCall(x, f, args)` is `x := f(args)`: a call of the procedure `f`
+    /// of the enclosing `Program`.
+    CCall(String, String, Vec<Aexp>),
+    /// `CArrAsgn(a, i, e)` is `a[i] := e`.
+    CArrAsgn(String, Aexp, Aexp),
+    /// `print a` appends the value of `a` to the program's output, which
+    /// `ceval_output`, `normalize_output` and `Program::run_output` return
+    /// alongside the final state. Other evaluators drop it.
+    CPrint(Aexp),
+    /// `CPar(c1, c2)`, written `par c1 with c2 end`, runs `c1` and `c2` at
+    /// the same time. Small steps interleave theirs in every possible
+    /// order (see `interleavings`); the other evaluators run all of `c1`
+    /// first and then `c2`, which is one of those orders. A `break` or
+    /// `continue` in a branch only ends that branch; an exception ends the
+    /// whole `par` at once.
+    CPar(Box<Com>, Box<Com>),
+    /// `throw a` raises an exception with the value of `a` as its code. It
+    /// ends every command around it up to the nearest `try`, loops
+    /// included, and a program that doesn't catch it ends there.
+    CThrow(Aexp),
+    /// `CTry(body, x, handler)`, written `try body catch x do handler end`,
+    /// runs `body`, and if that throws, sets `x` to the code and runs
+    /// `handler`. A `break` or `continue` passes through to the loop
+    /// around, like any other command's.
+    CTry(Box<Com>, String, Box<Com>),
+}
+
+impl Com {
+    pub fn asgn(x: &str, a: Aexp) -> Com {
+        Com::CAsgn(x.to_string(), a)
+    }
+
+    pub fn seq(c1: Com, c2: Com) -> Com {
+        Com::CSeq(Box::new(c1), Box::new(c2))
+    }
+
+    pub fn if_(b: Bexp, c1: Com, c2: Com) -> Com {
+        Com::CIf(b, Box::new(c1), Box::new(c2))
+    }
+
+    pub fn while_(b: Bexp, c: Com) -> Com {
+        Com::CWhile(b, Box::new(c))
+    }
+
+    pub fn for_(init: Com, b: Bexp, update: Com, body: Com) -> Com {
+        Com::CFor(Box::new(init), b, Box::new(update), Box::new(body))
+    }
+
+    pub fn havoc(x: &str) -> Com {
+        Com::CHavoc(x.to_string())
+    }
+
+    pub fn call(x: &str, f: &str, args: Vec<Aexp>) -> Com {
+        Com::CCall(x.to_string(), f.to_string(), args)
+    }
+
+    pub fn arr_asgn(a: &str, i: Aexp, e: Aexp) -> Com {
+        Com::CArrAsgn(a.to_string(), i, e)
+    }
+
+    pub fn print(a: Aexp) -> Com {
+        Com::CPrint(a)
+    }
+
+    pub fn par(c1: Com, c2: Com) -> Com {
+        Com::CPar(Box::new(c1), Box::new(c2))
+    }
+
+    pub fn throw(a: Aexp) -> Com {
+        Com::CThrow(a)
+    }
+
+    pub fn try_(body: Com, x: &str, handler: Com) -> Com {
+        Com::CTry(Box::new(body), x.to_string(), Box::new(handler))
+    }
+
+    /// The variables the command reads or assigns. Arrays don't count.
+    pub fn vars(&self) -> BTreeSet<String> {
+        let mut vars = BTreeSet::new();
+        self.collect_vars(&mut vars);
+        vars
+    }
+
+    fn collect_vars(&self, vars: &mut BTreeSet<String>) {
+        match self {
+            Com::CSkip | Com::CBreak | Com::CContinue => {}
+            Com::CAsgn(x, a) => {
+                vars.insert(x.clone());
+                a.collect_vars(vars);
+            }
+            Com::CHavoc(x) => {
+                vars.insert(x.clone());
+            }
+            Com::CCall(x, _, args) => {
+                vars.insert(x.clone());
+                for a in args {
+                    a.collect_vars(vars);
+                }
+            }
+            Com::CArrAsgn(_, i, e) => {
+                i.collect_vars(vars);
+                e.collect_vars(vars);
+            }
+            Com::CPrint(a) | Com::CThrow(a) => a.collect_vars(vars),
+            Com::CTry(body, x, handler) => {
+                body.collect_vars(vars);
+                vars.insert(x.clone());
+                handler.collect_vars(vars);
+            }
+            Com::CSeq(c1, c2) | Com::CPar(c1, c2) => {
+                c1.collect_vars(vars);
+                c2.collect_vars(vars);
+            }
+            Com::CIf(b, c1, c2) => {
+                b.collect_vars(vars);
+                c1.collect_vars(vars);
+                c2.collect_vars(vars);
+            }
+            Com::CWhile(b, c) => {
+                b.collect_vars(vars);
+                c.collect_vars(vars);
+            }
+            Com::CFor(init, b, update, body) => {
+                init.collect_vars(vars);
+                b.collect_vars(vars);
+                update.collect_vars(vars);
+                body.collect_vars(vars);
+            }
+        }
+    }
+
+    /// The variables the command can assign: by `:=`, `havoc`, a call, or
+    /// catching an exception.
+    pub fn modified(&self) -> BTreeSet<String> {
+        let mut vars = BTreeSet::new();
+        self.collect_modified(&mut vars);
+        vars
+    }
+
+    fn collect_modified(&self, vars: &mut BTreeSet<String>) {
+        match self {
+            Com::CSkip
+            | Com::CBreak
+            | Com::CContinue
+            | Com::CArrAsgn(..)
+            | Com::CPrint(_)
+            | Com::CThrow(_) => {}
+            Com::CAsgn(x, _) | Com::CHavoc(x) | Com::CCall(x, ..) => {
+                vars.insert(x.clone());
+            }
+            Com::CTry(body, x, handler) => {
+                body.collect_modified(vars);
+                vars.insert(x.clone());
+                handler.collect_modified(vars);
+            }
+            Com::CSeq(c1, c2) | Com::CPar(c1, c2) | Com::CIf(_, c1, c2) => {
+                c1.collect_modified(vars);
+                c2.collect_modified(vars);
+            }
+            Com::CWhile(_, c) => c.collect_modified(vars),
+            Com::CFor(init, _, update, body) => {
+                init.collect_modified(vars);
+                update.collect_modified(vars);
+                body.collect_modified(vars);
+            }
+        }
+    }
+}
+
+/// How a command finished: normally, at a `break` or `continue` that is
+/// on its way out to the enclosing loop, or by throwing an exception with
+/// the given code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Signal {
+    Normal,
+    Break,
+    Continue,
+    Throw(i64),
+}
+
+/// Why evaluation stopped without a result. The errors from procedure
+/// calls carry a `stack` naming the procedures that were active, outermost
+/// first.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EvalError {
+    /// The fuel ran out, so the program may diverge.
+    OutOfFuel,
+    UndefinedProc {
+        name: String,
+        stack: Vec<String>,
+    },
+    ArityMismatch {
+        name: String,
+        expected: usize,
+        found: usize,
+        stack: Vec<String>,
+    },
+    /// More than `MAX_CALL_DEPTH` calls were active at once.
+    StackOverflow {
+        stack: Vec<String>,
+    },
+    /// An array access under `OutOfBounds::Fault` to an element the array
+    /// doesn't have.
+    OutOfBounds {
+        array: String,
+        index: i64,
+    },
+}
+
+impl fmt::Display for EvalError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let path = |stack: &[String]| {
+            let mut path = vec!["main"];
+            path.extend(stack.iter().map(String::as_str));
+            path.join(" > ")
+        };
+        match self {
+            EvalError::OutOfFuel => write!(f, "out of fuel"),
+            EvalError::UndefinedProc { name, stack } => write!(
+                f,
+                "call to undefined procedure `{}` in {}",
+                name,
+                path(stack)
+            ),
+            EvalError::ArityMismatch {
+                name,
+                expected,
+                found,
+                stack,
+            } => write!(
+                f,
+                "`{}` takes {} arguments but was called with {} in {}",
+                name,
+                expected,
+                found,
+                path(stack)
+            ),
+            EvalError::StackOverflow { stack } => write!(
+                f,
+                "more than {} nested calls, from {}",
+                MAX_CALL_DEPTH,
+                path(&stack[..1])
+            ),
+            EvalError::OutOfBounds { array, index } => {
+                write!(f, "index {} is out of bounds for array `{}`", index, array)
+            }
+        }
+    }
+}
+
+impl std::error::Error for EvalError {}
+
+/// A construct that an evaluator, compiler or logic doesn't handle, named
+/// as `"procedure calls"`, `"arrays"`, `"par"` or `"havoc"`. Calls only make sense inside a
+/// `Program`, and arrays live outside the `State` most of them work on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Unsupported(pub &'static str);
+
+impl fmt::Display for Unsupported {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "not supported here: {}", self.0)
+    }
+}
+
+impl std::error::Error for Unsupported {}
+
+/// The deepest procedure calls may nest before evaluation gives up with
+/// `EvalError::StackOverflow`. Each Imp call costs a handful of Rust stack
+/// frames, so this stays well clear of the default thread stack size.
+pub const MAX_CALL_DEPTH: usize = 256;
+
+/// Big-step evaluation, `st =[ c ]=> st'`. Imp is deterministic, so the
+/// relation of the book is a function here, except that it is partial: on
+/// a diverging program `ceval` diverges too. Use `ceval_gas` when that
+/// matters. A `break` or `continue` outside any loop ends the program.
+///
+/// Calls only make sense inside a `Program`, which has its own `run`, so
+/// reaching one is an error, here and in the other `ceval_*` functions.
+pub fn ceval(st: State, c: &Com) -> Result<State, Unsupported> {
+    Ok(ceval_signal(st, c)?.0)
+}
+
+/// How a run on a budget of gas ended.
+#[derive(Clone)]
+pub enum RunResult {
+    Finished(State),
+    /// The gas ran out in the given state with the given configuration
+    /// still to run. Running that from that state, with `resume`, finishes
+    /// the job, so a long run can be spread over several budgets;
+    /// `Debugger::with_state` steps through the rest.
+    ///
+    /// Arrays aren't part of a `State`, so they start out empty again.
+    OutOfGas(State, Config),
+    /// The program ended with an exception nothing caught, with the given
+    /// code, in the given state.
+    Thrown(State, i64),
+}
+
+impl RunResult {
+    /// The final state, or `None` if the gas ran out.
+    pub fn finished(self) -> Option<State> {
+        match self {
+            RunResult::Finished(st) | RunResult::Thrown(st, _) => Some(st),
+            RunResult::OutOfGas(..) => None,
+        }
+    }
+
+    /// The state reached: the final one, or where the gas ran out.
+    pub fn state(&self) -> &State {
+        match self {
+            RunResult::Finished(st) | RunResult::OutOfGas(st, _) | RunResult::Thrown(st, _) => st,
+        }
+    }
+
+    /// Carry on with another `gas` loop iterations, if there is anything
+    /// left to run.
+    pub fn resume(self, gas: u64) -> Result<RunResult, Unsupported> {
+        match self {
+            RunResult::OutOfGas(st, c) => {
+                without_procs(Machine::new(Some(gas), pm_empty()).exec_gas(st, &c.0))
+            }
+            done => Ok(done),
+        }
+    }
+}
+
+/// `ceval` on a budget of `gas` iterations of loops in total, the only
+/// constructs that can diverge. If the budget runs out, the result holds
+/// the state so far and the rest of the program.
+pub fn ceval_gas(st: State, c: &Com, gas: u64) -> Result<RunResult, Unsupported> {
+    without_procs(Machine::new(Some(gas), pm_empty()).exec_gas(st, c))
+}
+
+/// `ceval_gas` for when only a finished run matters: `None` if the `fuel`
+/// runs out.
+pub fn ceval_fuel(st: State, c: &Com, fuel: u64) -> Result<Option<State>, Unsupported> {
+    Ok(ceval_gas(st, c, fuel)?.finished())
+}
+
+/// `ceval` that also reports how the program finished, `st =[ c ]=> st',
+/// s` in the book's break exercise: `Signal::Break` or `Signal::Continue`
+/// if it ended at one outside any loop, and `Signal::Throw` if at an
+/// exception nothing caught.
+pub fn ceval_signal(st: State, c: &Com) -> Result<(State, Signal), Unsupported> {
+    without_procs(Machine::new(None, pm_empty()).exec(st, c))
+}
+
+/// `ceval_gas` that also returns what the program printed, in order. If
+/// the gas runs out, the output is what was printed until then, so two
+/// diverging programs can still be told apart by what they print.
+pub fn ceval_output(st: State, c: &Com, gas: u64) -> Result<(RunResult, Vec<i64>), Unsupported> {
+    let mut machine = Machine::new(Some(gas), pm_empty());
+    let result = without_procs(machine.exec_gas(st, c))?;
+    Ok((result, machine.output))
+}
+
+/// The result of a run of a machine without procedures, in which a call
+/// is the only thing that can go wrong: arrays read `0` where they have
+/// no element, and with no procedures there is no stack to overflow.
+fn without_procs<T>(result: Result<T, EvalError>) -> Result<T, Unsupported> {
+    result.map_err(|e| match e {
+        EvalError::UndefinedProc { .. } => Unsupported("procedure calls"),
+        e => unreachable!("a run without procedures failed: {}", e),
+    })
+}
+
+/// Run `c` with `ceval_fuel` and describe the outcome for a person: the
+/// final values of the variables `c` mentions, e.g. `X=5, Y=120, Z=0`, or
+/// that it ran out of fuel, or that it reached a call.
+pub fn run_and_show(st: State, c: &Com, fuel: u64) -> String {
+    let vars: Vec<String> = c.vars().into_iter().collect();
+    match ceval_fuel(st, c, fuel) {
+        Ok(Some(st)) => st.show(&vars),
+        Ok(None) => format!("no result after {} loop iterations", fuel),
+        Err(e) => e.to_string(),
+    }
+}
+
+/// What the big-step evaluator runs: a command, or what is left of one
+/// when a run is resumed.
+trait Runnable {
+    fn run(&self, machine: &mut Machine, st: State) -> Result<(State, Signal), EvalError>;
+
+    /// The same, as what is left to run when the fuel runs out.
+    fn running(&self) -> Running;
+}
+
+impl Runnable for Com {
+    fn run(&self, machine: &mut Machine, st: State) -> Result<(State, Signal), EvalError> {
+        machine.exec(st, self)
+    }
+
+    fn running(&self) -> Running {
+        Running::Com(self.clone())
+    }
+}
+
+impl Runnable for Running {
+    fn run(&self, machine: &mut Machine, st: State) -> Result<(State, Signal), EvalError> {
+        machine.exec_running(st, self)
+    }
+
+    fn running(&self) -> Running {
+        self.clone()
+    }
+}
+
+/// The big-step evaluator's bookkeeping: the fuel left, if it is limited,
+/// the procedures calls can go to, the calls in progress, and the arrays,
+/// which unlike variables are shared by every procedure, what has been
+/// printed, and, if there is a cost model, what the run has cost so far.
+struct Machine {
+    fuel: Option<u64>,
+    /// Once the fuel has run out, the state and what is left to run of the
+    /// commands `OutOfFuel` has unwound through so far. `None` if it ran
+    /// out inside a procedure call, which leaves nothing to resume.
+    stuck: Option<(State, Running)>,
+    procs: Procs,
+    stack: Vec<String>,
+    arrays: Arrays,
+    bounds: OutOfBounds,
+    output: Vec<i64>,
+    model: Option<CostModel>,
+    cost: u64,
+}
+
+impl Machine {
+    fn new(fuel: Option<u64>, procs: Procs) -> Self {
+        Machine {
+            fuel,
+            stuck: None,
+            procs,
+            stack: Vec::new(),
+            arrays: empty_arrays(),
+            bounds: OutOfBounds::Zero,
+            output: Vec::new(),
+            model: None,
+            cost: 0,
+        }
+    }
+
+    fn aeval(&mut self, st: &State, a: &Aexp) -> Result<i64, EvalError> {
+        let n = aeval_arrays(st, &self.arrays, self.bounds, a)?;
+        self.charge(|model| model.aexp(a));
+        Ok(n)
+    }
+
+    fn beval(&mut self, st: &State, b: &Bexp) -> Result<bool, EvalError> {
+        let holds = |b: &Bexp| beval_arrays(st, &self.arrays, self.bounds, b);
+        let v = holds(b)?;
+        if let Some(model) = &self.model {
+            self.cost += model.bexp(b, &holds)?;
+        }
+        Ok(v)
+    }
+
+    /// Add the price `model` sets, if there is a cost model.
+    fn charge(&mut self, price: impl FnOnce(&CostModel) -> u64) {
+        if let Some(model) = &self.model {
+            self.cost += price(model);
+        }
+    }
+
+    /// Use up one unit of fuel, for a loop iteration or a call.
+    fn tick(&mut self) -> Result<(), EvalError> {
+        if let Some(n) = &mut self.fuel {
+            *n = n.checked_sub(1).ok_or(EvalError::OutOfFuel)?;
+        }
+        Ok(())
+    }
+
+    /// Run `c` to the end, or to where the fuel runs out outside any call.
+    fn exec_gas(&mut self, st: State, c: &impl Runnable) -> Result<RunResult, EvalError> {
+        match c.run(self, st) {
+            Ok((st, Signal::Throw(code))) => Ok(RunResult::Thrown(st, code)),
+            Ok((st, _)) => Ok(RunResult::Finished(st)),
+            Err(EvalError::OutOfFuel) => match self.stuck.take() {
+                Some((st, rest)) => Ok(RunResult::OutOfGas(st, Config(rest))),
+                None => Err(EvalError::OutOfFuel),
+            },
+            Err(e) => Err(e),
+        }
+    }
+
+    /// If `result` is the fuel running out in a subcommand, turn what is
+    /// left of the subcommand into what is left of the command around it.
+    fn unwind<T>(
+        &mut self,
+        result: Result<T, EvalError>,
+        rest: impl FnOnce(Running) -> Running,
+    ) -> Result<T, EvalError> {
+        if let (Err(EvalError::OutOfFuel), Some((_, left))) = (&result, &mut self.stuck) {
+            *left = rest(mem::replace(left, Running::Com(Com::CSkip)));
+        }
+        result
+    }
+
+    /// Run what is left of a command: `exec`, and for the parts under way,
+    /// what their commands do from there on.
+    fn exec_running(&mut self, st: State, r: &Running) -> Result<(State, Signal), EvalError> {
+        match r {
+            Running::Com(c) => self.exec(st, c),
+            Running::Seq(r1, c2) => {
+                let done = self.exec_running(st, r1);
+                let done = self.unwind(done, |rest| Running::seq(rest, c2.clone()))?;
+                self.charge(|model| model.seq);
+                match done {
+                    (st, Signal::Normal) => self.exec(st, c2),
+                    interrupted => Ok(interrupted),
+                }
+            }
+            Running::If(b, r1, c2) => {
+                let v = self.beval(&st, b)?;
+                self.charge(|model| model.if_);
+                if v {
+                    self.exec_running(st, r1)
+                } else {
+                    self.exec(st, c2)
+                }
+            }
+            Running::Loop(rest, b, body) => {
+                let done = self.exec_running(st, rest);
+                let done =
+                    self.unwind(done, |rest| Running::loop_(rest, b.clone(), body.clone()))?;
+                self.charge(|model| model.iter);
+                match done {
+                    (st, Signal::Break) => Ok((st, Signal::Normal)),
+                    (st, Signal::Throw(code)) => Ok((st, Signal::Throw(code))),
+                    (st, _) => self.run_loop(st, b, body, None),
+                }
+            }
+            Running::Par(r1, r2) => self.run_par(st, &**r1, &**r2),
+            Running::Try(body, x, handler) => self.run_try(st, &**body, x, handler),
+        }
+    }
+
+    fn exec(&mut self, st: State, c: &Com) -> Result<(State, Signal), EvalError> {
+        match c {
+            Com::CSkip => Ok((st, Signal::Normal)),
+            Com::CBreak => Ok((st, Signal::Break)),
+            Com::CContinue => Ok((st, Signal::Continue)),
+            Com::CAsgn(x, a) => {
+                let n = self.aeval(&st, a)?;
+                self.charge(|model| model.asgn);
+                Ok((tm_update(st, x.clone(), n), Signal::Normal))
+            }
+            Com::CHavoc(x) => {
+                self.charge(|model| model.havoc);
+                Ok((tm_update(st, x.clone(), 0), Signal::Normal))
+            }
+            Com::CSeq(c1, c2) => {
+                let done = self.exec(st, c1);
+                let done = self.unwind(done, |rest| Running::seq(rest, (**c2).clone()))?;
+                self.charge(|model| model.seq);
+                match done {
+                    (st, Signal::Normal) => self.exec(st, c2),
+                    interrupted => Ok(interrupted),
+                }
+            }
+            Com::CIf(b, c1, c2) => {
+                let v = self.beval(&st, b)?;
+                self.charge(|model| model.if_);
+                if v {
+                    self.exec(st, c1)
+                } else {
+                    self.exec(st, c2)
+                }
+            }
+            // Iterate rather than recurse so long-running loops don't grow
+            // the Rust stack.
+            Com::CWhile(b, body) => self.run_loop(st, b, body, None),
+            Com::CFor(init, b, update, body) => {
+                // Priced as its lowering, `init; while b do body; update
+                // end`.
+                self.charge(|model| model.for_);
+                let done = self.exec(st, init);
+                let done = self.unwind(done, |rest| {
+                    let c = Com::CFor(
+                        Box::new(Com::CSkip),
+                        b.clone(),
+                        update.clone(),
+                        body.clone(),
+                    );
+                    Running::seq(rest, c)
+                })?;
+                self.charge(|model| model.seq);
+                match done {
+                    (st, Signal::Normal) => self.run_loop(st, b, body, Some(update)),
+                    interrupted => Ok(interrupted),
+                }
+            }
+            Com::CCall(x, f, args) => match self.call(&st, f, args)? {
+                Ok(n) => Ok((tm_update(st, x.clone(), n), Signal::Normal)),
+                Err(code) => Ok((st, Signal::Throw(code))),
+            },
+            Com::CArrAsgn(a, i, e) => {
+                let i = self.aeval(&st, i)?;
+                let n = self.aeval(&st, e)?;
+                self.arrays = write_array(&self.arrays, self.bounds, a, i, n)?;
+                self.charge(|model| model.arr_asgn);
+                Ok((st, Signal::Normal))
+            }
+            Com::CPrint(a) => {
+                let n = self.aeval(&st, a)?;
+                self.output.push(n);
+                self.charge(|model| model.print);
+                Ok((st, Signal::Normal))
+            }
+            Com::CPar(c1, c2) => self.run_par(st, &**c1, &**c2),
+            Com::CThrow(a) => {
+                let code = self.aeval(&st, a)?;
+                Ok((st, Signal::Throw(code)))
+            }
+            Com::CTry(body, x, handler) => self.run_try(st, &**body, x, handler),
+        }
+    }
+
+    // `run_par` and `run_try` are kept out of `exec` so that its frame,
+    // which recursive procedures stack up, stays small.
+
+    /// One of the interleavings of `c1` and `c2`: all of `c1`, then all of
+    /// `c2`. Priced like small steps, which finish with `par skip with skip
+    /// end` to `skip`.
+    fn run_par(
+        &mut self,
+        st: State,
+        c1: &impl Runnable,
+        c2: &impl Runnable,
+    ) -> Result<(State, Signal), EvalError> {
+        let done = c1.run(self, st);
+        let (st, signal) = self.unwind(done, |rest| Running::par(rest, c2.running()))?;
+        if let Signal::Throw(_) = signal {
+            self.charge(|model| model.seq);
+            return Ok((st, signal));
+        }
+        let done = c2.run(self, st);
+        let (st, signal) =
+            self.unwind(done, |rest| Running::par(Running::Com(Com::CSkip), rest))?;
+        self.charge(|model| model.seq);
+        match signal {
+            Signal::Throw(_) => Ok((st, signal)),
+            _ => Ok((st, Signal::Normal)),
+        }
+    }
+
+    fn run_try(
+        &mut self,
+        st: State,
+        body: &impl Runnable,
+        x: &str,
+        handler: &Com,
+    ) -> Result<(State, Signal), EvalError> {
+        let done = body.run(self, st);
+        let done = self.unwind(done, |rest| Running::try_(rest, x, handler.clone()))?;
+        self.charge(|model| model.try_);
+        match done {
+            (st, Signal::Throw(code)) => self.exec(tm_update(st, x.to_string(), code), handler),
+            finished => Ok(finished),
+        }
+    }
+
+    /// Run `body` (followed by `update`, for a `for` loop) while `b` holds.
+    /// The update belongs to the loop, so a `break` in it ends the loop too.
+    ///
+    /// For the cost model, each test is a `while` unrolling to an `if`, and
+    /// a `for` loop's body is `body; update`, where a `continue` in `body`
+    /// has become `update; continue`.
+    fn run_loop(
+        &mut self,
+        st: State,
+        b: &Bexp,
+        body: &Com,
+        update: Option<&Com>,
+    ) -> Result<(State, Signal), EvalError> {
+        let mut st = st;
+        loop {
+            self.charge(|model| model.while_);
+            let v = self.beval(&st, b)?;
+            self.charge(|model| model.if_);
+            if !v {
+                break;
+            }
+            if let Err(e) = self.tick() {
+                let rest = match update {
+                    Some(update) => Com::CFor(
+                        Box::new(Com::CSkip),
+                        b.clone(),
+                        Box::new(update.clone()),
+                        Box::new(body.clone()),
+                    ),
+                    None => Com::while_(b.clone(), body.clone()),
+                };
+                self.stuck = Some((st, Running::Com(rest)));
+                return Err(e);
+            }
+            // A `for` loop partway through an iteration has no form of its
+            // own, so what is left of one is its lowering's.
+            let lowered = |update: &Com| Com::seq(continue_runs(body, update), update.clone());
+            let done = self.exec(st, body);
+            let (st1, mut signal) = self.unwind(done, |rest| match update {
+                Some(update) => Running::loop_(
+                    Running::seq(continue_runs_running(&rest, update), update.clone()),
+                    b.clone(),
+                    lowered(update),
+                ),
+                None => Running::loop_(rest, b.clone(), body.clone()),
+            })?;
+            st = st1;
+            if let Some(update) = update {
+                if signal == Signal::Continue {
+                    self.charge(|model| model.seq);
+                }
+                self.charge(|model| model.seq);
+                if !matches!(signal, Signal::Break | Signal::Throw(_)) {
+                    let done = self.exec(st, update);
+                    let (st1, signal1) = self.unwind(done, |rest| {
+                        Running::loop_(rest, b.clone(), lowered(update))
+                    })?;
+                    st = st1;
+                    signal = signal1;
+                }
+            }
+            self.charge(|model| model.iter);
+            match signal {
+                Signal::Break => break,
+                Signal::Throw(_) => return Ok((st, signal)),
+                _ => {}
+            }
+        }
+        Ok((st, Signal::Normal))
+    }
+
+    /// Call `f` with the values of `args` in `st`, and return the final
+    /// value of its return variable, or the code of an exception its body
+    /// didn't catch, which goes on to the caller. The body runs in a fresh
+    /// state holding just the parameters, so it can neither see nor change
+    /// the caller's variables.
+    fn call(
+        &mut self,
+        st: &State,
+        f: &String,
+        args: &[Aexp],
+    ) -> Result<Result<i64, i64>, EvalError> {
+        let Some(proc) = (self.procs)(f) else {
+            return Err(EvalError::UndefinedProc {
+                name: f.clone(),
+                stack: self.stack.clone(),
+            });
+        };
+        if args.len() != proc.params.len() {
+            return Err(EvalError::ArityMismatch {
+                name: f.clone(),
+                expected: proc.params.len(),
+                found: args.len(),
+                stack: self.stack.clone(),
+            });
+        }
+        if self.stack.len() >= MAX_CALL_DEPTH {
+            return Err(EvalError::StackOverflow {
+                stack: self.stack.clone(),
+            });
+        }
+        self.tick()?;
+        self.charge(|model| model.call);
+        let mut frame = empty_state();
+        for (x, a) in proc.params.iter().zip(args) {
+            frame = tm_update(frame, x.clone(), self.aeval(st, a)?);
+        }
+        self.stack.push(f.clone());
+        let (frame, signal) = self
+            .exec(frame, &proc.body)
+            .inspect_err(|_| self.stuck = None)?;
+        self.stack.pop();
+        match signal {
+            Signal::Throw(code) => Ok(Err(code)),
+            _ => Ok(Ok(frame(&proc.ret))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test_imp_com {
+    use super::*;
+    use crate::imp::parse_com;
+    use crate::state;
+    use crate::state::lookup;
+
+    /// `Z := X; Y := 1; while ~(Z = 0) do Y := Y * Z; Z := Z - 1 end`
+    fn factorial_in_imp() -> Com {
+        Com::seq(
+            Com::asgn("Z", Aexp::var("X")),
+            Com::seq(
+                Com::asgn("Y", Aexp::num(1)),
+                Com::while_(
+                    Bexp::not(Bexp::eq(Aexp::var("Z"), Aexp::num(0))),
+                    Com::seq(
+                        Com::asgn("Y", Aexp::mult(Aexp::var("Y"), Aexp::var("Z"))),
+                        Com::asgn("Z", Aexp::minus(Aexp::var("Z"), Aexp::num(1))),
+                    ),
+                ),
+            ),
+        )
+    }
+
+    /// `while ~(X = 0) do Z := Z - 1; X := X - 1 end`
+    fn subtract_slowly() -> Com {
+        Com::while_(
+            Bexp::not(Bexp::eq(Aexp::var("X"), Aexp::num(0))),
+            Com::seq(
+                Com::asgn("Z", Aexp::minus(Aexp::var("Z"), Aexp::num(1))),
+                Com::asgn("X", Aexp::minus(Aexp::var("X"), Aexp::num(1))),
+            ),
+        )
+    }
+
+    fn loop_forever() -> Com {
+        Com::while_(Bexp::BTrue, Com::CSkip)
+    }
+
+    #[test]
+    fn test_factorial() {
+        let st = ceval(state! {"X" => 5}, &factorial_in_imp()).unwrap();
+        assert_eq!(lookup(&st, "Y"), 120);
+        assert_eq!(lookup(&st, "Z"), 0);
+        assert_eq!(lookup(&st, "X"), 5);
+    }
+
+    #[test]
+    fn test_subtract_slowly() {
+        let st = ceval(state! {"X" => 3, "Z" => 5}, &subtract_slowly()).unwrap();
+        assert_eq!(lookup(&st, "Z"), 2);
+        assert_eq!(lookup(&st, "X"), 0);
+    }
+
+    #[test]
+    fn test_if() {
+        // if X <= 1 then Y := 3 else Z := 4 end
+        let c = Com::if_(
+            Bexp::le(Aexp::var("X"), Aexp::num(1)),
+            Com::asgn("Y", Aexp::num(3)),
+            Com::asgn("Z", Aexp::num(4)),
+        );
+        let st = ceval(state! {"X" => 2}, &c).unwrap();
+        assert_eq!((lookup(&st, "Y"), lookup(&st, "Z")), (0, 4));
+        let st = ceval(state! {"X" => 1}, &c).unwrap();
+        assert_eq!((lookup(&st, "Y"), lookup(&st, "Z")), (3, 0));
+    }
+
+    #[test]
+    fn test_fuel_enough() {
+        let st = ceval_fuel(state! {"X" => 5}, &factorial_in_imp(), 5)
+            .unwrap()
+            .unwrap();
+        assert_eq!(lookup(&st, "Y"), 120);
+        assert!(ceval_fuel(state! {"X" => 5}, &factorial_in_imp(), 4)
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn test_break_exits_loop() {
+        let c = parse_com(
+            "X := 0; while true do X := X + 1; if X = 3 then break else skip end; Y := Y + 1 end",
+        )
+        .unwrap();
+        let (st, signal) = ceval_signal(state! {}, &c).unwrap();
+        assert_eq!((lookup(&st, "X"), lookup(&st, "Y")), (3, 2));
+        assert_eq!(signal, Signal::Normal);
+    }
+
+    #[test]
+    fn test_continue_skips_rest_of_body() {
+        // Sum the odd numbers up to 10; J := I mod 2 by repeated subtraction.
+        let c = parse_com(
+            "I := 0; while I <= 9 do I := I + 1; \
+             J := I; while 2 <= J do J := J - 2 end; \
+             if J = 0 then continue else skip end; \
+             S := S + I end",
+        )
+        .unwrap();
+        let st = ceval(state! {}, &c).unwrap();
+        assert_eq!(lookup(&st, "S"), 25);
+        assert_eq!(lookup(&st, "I"), 10);
+    }
+
+    #[test]
+    fn test_break_only_leaves_inner_loop() {
+        let c = parse_com(
+            "while I <= 2 do I := I + 1; while true do J := J + 1; break end; K := K + 1 end",
+        )
+        .unwrap();
+        let st = ceval(state! {}, &c).unwrap();
+        assert_eq!(
+            (lookup(&st, "I"), lookup(&st, "J"), lookup(&st, "K")),
+            (3, 3, 3)
+        );
+    }
+
+    #[test]
+    fn test_break_outside_loop() {
+        let c = parse_com("X := 1; break; X := 2").unwrap();
+        let (st, signal) = ceval_signal(state! {}, &c).unwrap();
+        assert_eq!((lookup(&st, "X"), signal), (1, Signal::Break));
+    }
+
+    #[test]
+    fn test_run_and_show() {
+        assert_eq!(
+            run_and_show(state! {"X" => 5}, &factorial_in_imp(), 100),
+            "X=5, Y=120, Z=0"
+        );
+        assert_eq!(
+            run_and_show(state! {}, &loop_forever(), 100),
+            "no result after 100 loop iterations"
+        );
+    }
+
+    #[test]
+    fn test_fuel_divergence() {
+        assert!(ceval_fuel(state! {}, &loop_forever(), 10_000)
+            .unwrap()
+            .is_none());
+        // A negative X makes subtract_slowly count down forever.
+        assert!(ceval_fuel(state! {"X" => -1}, &subtract_slowly(), 1_000)
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn test_output() {
+        let c = parse_com("Y := 1; while 1 <= X do Y := Y * X; print Y; X := X - 1 end").unwrap();
+        let (st, output) = ceval_output(state! {"X" => 4}, &c, 100).unwrap();
+        assert_eq!(lookup(&st.finished().unwrap(), "Y"), 24);
+        assert_eq!(output, [4, 12, 24, 24]);
+        // What was printed before the fuel ran out is kept.
+        let c = parse_com("while true do X := X + 1; print X end").unwrap();
+        let (st, output) = ceval_output(state! {}, &c, 3).unwrap();
+        assert!(st.finished().is_none());
+        assert_eq!(output, [1, 2, 3]);
+    }
+
+    #[test]
+    fn test_calls_are_unsupported() {
+        let call = Some(Unsupported("procedure calls"));
+        let c = parse_com("X := 1; Y := f(X)").unwrap();
+        assert_eq!(ceval(state! {}, &c).err(), call);
+        assert_eq!(ceval_signal(state! {}, &c).err(), call);
+        assert_eq!(ceval_gas(state! {}, &c, 10).err(), call);
+        assert_eq!(ceval_fuel(state! {}, &c, 10).err(), call);
+        assert_eq!(ceval_output(state! {}, &c, 10).err(), call);
+        let model = CostModel::default();
+        assert_eq!(ceval_cost(state! {}, &c, 10, &model).err(), call);
+        assert_eq!(
+            run_and_show(state! {}, &c, 10),
+            "not supported here: procedure calls"
+        );
+        // Only once the run gets to it.
+        let c = parse_com("while X <= 2 do X := X + 1 end; Y := f(X)").unwrap();
+        let paused = ceval_gas(state! {}, &c, 1).unwrap();
+        assert!(matches!(paused, RunResult::OutOfGas(..)));
+        assert_eq!(paused.resume(10).err(), call);
+    }
+
+    #[test]
+    fn test_out_of_gas_resumes() {
+        let programs = [
+            factorial_in_imp(),
+            parse_com("X := 0; while true do X := X + 1; if 9 <= X then break else skip end end")
+                .unwrap(),
+            parse_com(
+                "for I := 0; I <= 6; I := I + 1 do if I = 2 then continue else skip end; \
+                 for J := 0; J <= I; J := J + 1 do S := S + J end; \
+                 par X := X + I with Y := Y + 1 end end",
+            )
+            .unwrap(),
+        ];
+        for c in &programs {
+            let expected = ceval(state! {"X" => 5}, c).unwrap();
+            // Any budget, resumed as often as it takes, ends where one run
+            // does.
+            for gas in 1..5 {
+                let mut result = ceval_gas(state! {"X" => 5}, c, gas).unwrap();
+                let mut resumed = 0;
+                while let RunResult::OutOfGas(..) = result {
+                    result = result.resume(gas).unwrap();
+                    resumed += 1;
+                }
+                assert!(resumed > 0);
+                let st = result.finished().unwrap();
+                for x in c.vars() {
+                    assert_eq!(st(&x), expected(&x), "{} in {} with gas {}", x, c, gas);
+                }
+            }
+        }
+        let RunResult::OutOfGas(st, rest) = ceval_gas(state! {}, &loop_forever(), 3).unwrap()
+        else {
+            panic!("loop_forever finished");
+        };
+        assert_eq!(rest, Config::from(loop_forever()));
+        assert_eq!(lookup(&st, "X"), 0);
+    }
+
+    const EXCEPTIONS: [&str; 6] = [
+        "X := 0; try while true do X := X + 1; if 3 <= X then throw X * 10 else skip end end; \
+         Y := 1 catch E do Y := E end",
+        "while true do try X := X + 1; break catch E do X := 100 end end; Y := X",
+        "try try throw 1 catch E do throw E + 1 end catch F do G := F end",
+        "for I := 0; I <= 5; I := I + 1 do if I = 2 then continue else S := S + I end; \
+         if 4 <= I then throw I end end; S := 0",
+        "X := 1; try par X := 2; throw X; X := 3 with X := 4 end catch E do Y := E end",
+        "try for I := 0; I <= 3; I := I + 1 do try if I = 2 then throw 5 end catch E do \
+         break end end; throw 9 catch F do skip end",
+    ];
+
+    #[test]
+    fn test_exceptions() {
+        let run = |p: &str| ceval_signal(state! {}, &parse_com(p).unwrap()).unwrap();
+        // A `throw` leaves the loop, and the rest of the `try` body.
+        let (st, signal) = run(EXCEPTIONS[0]);
+        assert_eq!(signal, Signal::Normal);
+        assert_eq!((lookup(&st, "X"), lookup(&st, "Y")), (3, 30));
+        // A `break` in a `try` still ends its loop.
+        let (st, _) = run(EXCEPTIONS[1]);
+        assert_eq!((lookup(&st, "X"), lookup(&st, "Y")), (1, 1));
+        // A handler can throw on to an outer one.
+        let (st, _) = run(EXCEPTIONS[2]);
+        assert_eq!(lookup(&st, "G"), 2);
+        let (st, signal) = run(EXCEPTIONS[3]);
+        assert_eq!(signal, Signal::Throw(4));
+        assert_eq!(lookup(&st, "S"), 8);
+        // A `throw` in one branch ends the whole `par`.
+        let (st, _) = run(EXCEPTIONS[4]);
+        assert_eq!((lookup(&st, "X"), lookup(&st, "Y")), (2, 2));
+        // The inner handler's `break` ends the loop, whose exit throws.
+        let (st, _) = run(EXCEPTIONS[5]);
+        assert_eq!(
+            (lookup(&st, "I"), lookup(&st, "E"), lookup(&st, "F")),
+            (2, 5, 9)
+        );
+    }
+
+    #[test]
+    fn test_uncaught_exception() {
+        let c = parse_com("X := 1; while true do throw X + 6; X := 2 end").unwrap();
+        let RunResult::Thrown(st, code) = ceval_gas(state! {}, &c, 10).unwrap() else {
+            panic!("the exception wasn't reported");
+        };
+        assert_eq!((lookup(&st, "X"), code), (1, 7));
+        let c = parse_com("try throw 1 catch E do skip end; X := 1").unwrap();
+        assert!(matches!(
+            ceval_gas(state! {}, &c, 10).unwrap(),
+            RunResult::Finished(_)
+        ));
+    }
+
+    #[test]
+    fn test_evaluators_agree_on_exceptions() {
+        for p in EXCEPTIONS {
+            let c = parse_com(p).unwrap();
+            assert_eq!(parse_com(&c.to_pretty_string()).unwrap(), c);
+            let (expected, signal) = ceval_signal(state! {}, &c).unwrap();
+            let (derived, tree) = ceval_derivation(state! {}, &c, 100).unwrap().unwrap();
+            assert_eq!(tree.signal, signal, "{}", p);
+            let mut gas = ceval_gas(state! {}, &c, 1).unwrap();
+            while let RunResult::OutOfGas(..) = gas {
+                gas = gas.resume(1).unwrap();
+            }
+            let compiled = r_execute(state! {}, &r_compile(&c).unwrap(), 10_000).unwrap();
+            let code = match signal {
+                Signal::Throw(code) => Some(code),
+                _ => None,
+            };
+            assert_eq!(compiled.thrown, code, "{}", p);
+            let found = [
+                normalize(state! {}, &c, 10_000).unwrap().unwrap(),
+                ceval_step(state! {}, &c, 100).unwrap().unwrap(),
+                derived,
+                gas.finished().unwrap(),
+                compiled.state,
+                compile_to_fn(&c, 100).unwrap()(state! {}).unwrap(),
+                ceval_cost(state! {}, &c, 100, &CostModel::default())
+                    .unwrap()
+                    .unwrap()
+                    .0,
+            ];
+            for (i, st) in found.iter().enumerate() {
+                for x in c.vars() {
+                    assert_eq!(st(&x), expected(&x), "{} in {} by evaluator {}", x, p, i);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_analyses_follow_exceptions() {
+        let programs: Vec<Com> = EXCEPTIONS.iter().map(|p| parse_com(p).unwrap()).collect();
+        let states = [state! {}, state! {"X" => 2}, state! {"X" => -1, "S" => 4}];
+        check_sound(&DesugarFor, &programs, &states, 100)
+            .unwrap()
+            .unwrap();
+        check_pe_correct(
+            &PeState::from([("X".to_string(), 1)]),
+            &programs,
+            &states,
+            100,
+        )
+        .unwrap()
+        .unwrap();
+        for c in &programs {
+            let expected = ceval(state! {}, c).unwrap();
+            let slim = eliminate_dead_code(c);
+            let ranges = analyze_intervals(c, &AbsState::uniform(Interval::constant(0)));
+            for x in c.vars() {
+                assert_eq!(
+                    ceval(state! {}, &slim).unwrap()(&x),
+                    expected(&x),
+                    "{} in {}",
+                    x,
+                    c
+                );
+                let end = ranges.at_end().unwrap();
+                assert!(end.get(&x).contains(expected(&x)), "{} in {}", x, c);
+            }
+        }
+        // The handler reads Y, so the write before the `throw` stays, and
+        // only the one after it goes.
+        let c = parse_com("try Y := 1; throw 0; Y := 2 catch E do X := Y end").unwrap();
+        assert_eq!(
+            eliminate_dead_code_for(&c, &BTreeSet::from(["X".to_string()])),
+            parse_com("try Y := 1; throw 0 catch E do X := Y end").unwrap()
+        );
+        let info = liveness_for(&c, &BTreeSet::from(["X".to_string()]));
+        assert!(info.live_out(2).unwrap().contains("Y"));
+    }
+}
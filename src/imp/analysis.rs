@@ -0,0 +1,717 @@
+//! Interval analysis, an abstract interpretation of Imp: each variable is
+//! over-approximated by a range of values it can hold, at each line of the
+//! program's block layout (`{:#}`, numbered from `1`, as in the debugger).
+//!
+//! Loops are analysed by iterating from their entry state until the state
+//! at the test stops growing. Bounds that keep moving are widened to
+//! infinity so that this ends, and then a few more iterations without
+//! widening take back what they can. Arithmetic that could overflow, and
+//! so wrap, gives an unknown result. The analysis is sound for every
+//! evaluator: whatever the program does, its variables stay within the
+//! ranges found, so an assertion that the ranges satisfy can't fail.
+//!
+//! Inside a `par` branch, nothing is known about the variables the other
+//! branch writes, since it may write them between any two steps.
+//!
+//! A `throw` carries the state it was thrown in, and an interval for the
+//! value thrown, to the handler that catches it.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+use super::pretty::{block_lines, seq_lines};
+use super::{Aexp, Bexp, Com};
+
+/// The values from `lo` to `hi`, both included. `i64::MIN` and `i64::MAX`
+/// stand for no bound.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Interval {
+    pub lo: i64,
+    pub hi: i64,
+}
+
+impl Interval {
+    /// Every value.
+    pub const TOP: Interval = Interval {
+        lo: i64::MIN,
+        hi: i64::MAX,
+    };
+
+    /// Panics if `lo > hi`; an empty interval is an unreachable state
+    /// instead.
+    pub fn new(lo: i64, hi: i64) -> Interval {
+        assert!(lo <= hi, "empty interval [{}, {}]", lo, hi);
+        Interval { lo, hi }
+    }
+
+    pub fn constant(n: i64) -> Interval {
+        Interval { lo: n, hi: n }
+    }
+
+    pub fn contains(&self, n: i64) -> bool {
+        self.lo <= n && n <= self.hi
+    }
+
+    fn includes(&self, other: &Interval) -> bool {
+        self.lo <= other.lo && other.hi <= self.hi
+    }
+
+    fn join(&self, other: &Interval) -> Interval {
+        Interval {
+            lo: self.lo.min(other.lo),
+            hi: self.hi.max(other.hi),
+        }
+    }
+
+    fn meet(&self, other: &Interval) -> Option<Interval> {
+        let (lo, hi) = (self.lo.max(other.lo), self.hi.min(other.hi));
+        (lo <= hi).then_some(Interval { lo, hi })
+    }
+
+    /// `self` joined with `next`, with any bound that moved pushed to
+    /// infinity.
+    fn widen(&self, next: &Interval) -> Interval {
+        Interval {
+            lo: if next.lo < self.lo { i64::MIN } else { self.lo },
+            hi: if next.hi > self.hi { i64::MAX } else { self.hi },
+        }
+    }
+
+    /// The interval from the smallest to the largest of `corners`, or
+    /// `TOP` if that leaves `i64`, since the result would then wrap.
+    fn hull(corners: impl IntoIterator<Item = i128>) -> Interval {
+        let (lo, hi) = corners
+            .into_iter()
+            .fold((i128::MAX, i128::MIN), |(lo, hi), n| (lo.min(n), hi.max(n)));
+        match (i64::try_from(lo), i64::try_from(hi)) {
+            (Ok(lo), Ok(hi)) => Interval { lo, hi },
+            _ => Interval::TOP,
+        }
+    }
+}
+
+impl fmt::Display for Interval {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.lo {
+            i64::MIN => write!(f, "[-inf, ")?,
+            lo => write!(f, "[{}, ", lo)?,
+        }
+        match self.hi {
+            i64::MAX => write!(f, "+inf]"),
+            hi => write!(f, "{}]", hi),
+        }
+    }
+}
+
+/// An interval for every variable: those listed, and `default` for the
+/// rest.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AbsState {
+    vars: BTreeMap<String, Interval>,
+    default: Interval,
+}
+
+impl AbsState {
+    /// Nothing known about any variable.
+    pub fn top() -> AbsState {
+        AbsState::uniform(Interval::TOP)
+    }
+
+    /// Every variable in `i`. `uniform(Interval::constant(0))` describes
+    /// the empty state.
+    pub fn uniform(i: Interval) -> AbsState {
+        AbsState {
+            vars: BTreeMap::new(),
+            default: i,
+        }
+    }
+
+    /// The same, with `x` in `i`.
+    pub fn with(mut self, x: &str, i: Interval) -> AbsState {
+        self.vars.insert(x.to_string(), i);
+        self
+    }
+
+    pub fn get(&self, x: &str) -> Interval {
+        self.vars.get(x).copied().unwrap_or(self.default)
+    }
+
+    /// The values `a` can have.
+    pub fn aexp(&self, a: &Aexp) -> Interval {
+        match a {
+            Aexp::ANum(n) => Interval::constant(*n),
+            Aexp::AId(x) => self.get(x),
+            Aexp::APlus(a1, a2) => {
+                let (i1, i2) = (self.aexp(a1), self.aexp(a2));
+                Interval::hull([i1.lo as i128 + i2.lo as i128, i1.hi as i128 + i2.hi as i128])
+            }
+            Aexp::AMinus(a1, a2) => {
+                let (i1, i2) = (self.aexp(a1), self.aexp(a2));
+                Interval::hull([i1.lo as i128 - i2.hi as i128, i1.hi as i128 - i2.lo as i128])
+            }
+            Aexp::AMult(a1, a2) => {
+                let (i1, i2) = (self.aexp(a1), self.aexp(a2));
+                Interval::hull([
+                    i1.lo as i128 * i2.lo as i128,
+                    i1.lo as i128 * i2.hi as i128,
+                    i1.hi as i128 * i2.lo as i128,
+                    i1.hi as i128 * i2.hi as i128,
+                ])
+            }
+            // Arrays aren't tracked.
+            Aexp::AIndex(..) => Interval::TOP,
+        }
+    }
+
+    /// The states of `self` where `b` is `truth`, narrowed as far as
+    /// comparisons with a variable allow, or `None` if there are none.
+    pub fn assume(&self, b: &Bexp, truth: bool) -> Option<AbsState> {
+        match (b, truth) {
+            (Bexp::BTrue, true) | (Bexp::BFalse, false) => Some(self.clone()),
+            (Bexp::BTrue, false) | (Bexp::BFalse, true) => None,
+            (Bexp::BNot(b1), _) => self.assume(b1, !truth),
+            (Bexp::BAnd(b1, b2), true) => self.assume(b1, true)?.assume(b2, true),
+            (Bexp::BAnd(b1, b2), false) => join(
+                self.assume(b1, false),
+                self.assume(b1, true).and_then(|st| st.assume(b2, false)),
+            ),
+            (Bexp::BLe(a1, a2), true) => {
+                let (i1, i2) = (self.aexp(a1), self.aexp(a2));
+                let mut st = self.clone();
+                (st.refine(a1, Interval { lo: i64::MIN, ..i2 })
+                    && st.refine(a2, Interval { hi: i64::MAX, ..i1 }))
+                .then_some(st)
+            }
+            (Bexp::BLe(a1, a2), false) => {
+                // a2 < a1, so a2 + 1 <= a1 and a2 <= a1 - 1.
+                let (i1, i2) = (self.aexp(a1), self.aexp(a2));
+                if i1.hi <= i2.lo {
+                    return None;
+                }
+                let mut st = self.clone();
+                (st.refine(a1, Interval::new(i2.lo + 1, i64::MAX))
+                    && st.refine(a2, Interval::new(i64::MIN, i1.hi - 1)))
+                .then_some(st)
+            }
+            (Bexp::BEq(a1, a2), true) => {
+                let both = self.aexp(a1).meet(&self.aexp(a2))?;
+                let mut st = self.clone();
+                (st.refine(a1, both) && st.refine(a2, both)).then_some(st)
+            }
+            (Bexp::BEq(a1, a2), false) => {
+                let (i1, i2) = (self.aexp(a1), self.aexp(a2));
+                let mut st = self.clone();
+                let ok = match (i1.lo == i1.hi, i2.lo == i2.hi) {
+                    (true, true) => i1 != i2,
+                    (false, true) => {
+                        st.exclude(a1, i2.lo);
+                        true
+                    }
+                    (true, false) => {
+                        st.exclude(a2, i1.lo);
+                        true
+                    }
+                    (false, false) => true,
+                };
+                ok.then_some(st)
+            }
+        }
+    }
+
+    /// Whether `b` may be false in some state of `self`.
+    pub fn may_violate(&self, b: &Bexp) -> bool {
+        self.assume(b, false).is_some()
+    }
+
+    /// Narrow the variable `a` to `i`, if it is one, and say whether `a`
+    /// can still be in `i`.
+    fn refine(&mut self, a: &Aexp, i: Interval) -> bool {
+        match self.aexp(a).meet(&i) {
+            Some(narrowed) => {
+                if let Aexp::AId(x) = a {
+                    self.vars.insert(x.clone(), narrowed);
+                }
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Take `n` out of the variable `a`, if it is one and `n` is one of its
+    /// bounds. `a` must be able to be something else.
+    fn exclude(&mut self, a: &Aexp, n: i64) {
+        let Aexp::AId(x) = a else {
+            return;
+        };
+        let i = self.get(x);
+        let narrowed = if i.lo == n {
+            Interval { lo: n + 1, ..i }
+        } else if i.hi == n {
+            Interval { hi: n - 1, ..i }
+        } else {
+            i
+        };
+        self.vars.insert(x.clone(), narrowed);
+    }
+
+    fn set(&mut self, x: &str, i: Interval) {
+        self.vars.insert(x.to_string(), i);
+    }
+
+    fn zip(&self, other: &AbsState, f: impl Fn(&Interval, &Interval) -> Interval) -> AbsState {
+        let mut vars = BTreeMap::new();
+        for x in self.vars.keys().chain(other.vars.keys()) {
+            vars.insert(x.clone(), f(&self.get(x), &other.get(x)));
+        }
+        AbsState {
+            vars,
+            default: f(&self.default, &other.default),
+        }
+    }
+
+    /// Whether every state of `other` is one of `self`.
+    fn includes(&self, other: &AbsState) -> bool {
+        self.default.includes(&other.default)
+            && self
+                .vars
+                .keys()
+                .chain(other.vars.keys())
+                .all(|x| self.get(x).includes(&other.get(x)))
+    }
+}
+
+impl fmt::Display for AbsState {
+    /// The variables that have their own interval, as `X=[0, 3], Y=[1, 1]`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, (x, v)) in self.vars.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{}={}", x, v)?;
+        }
+        Ok(())
+    }
+}
+
+/// `None` is the unreachable state, below every other.
+fn join(st1: Option<AbsState>, st2: Option<AbsState>) -> Option<AbsState> {
+    match (st1, st2) {
+        (Some(st1), Some(st2)) => Some(st1.zip(&st2, Interval::join)),
+        (st, None) | (None, st) => st,
+    }
+}
+
+/// The result of `analyze_intervals`.
+#[derive(Debug, Clone)]
+pub struct IntervalAnalysis {
+    /// For each line, the state before the command starting on it, if it
+    /// is reachable.
+    lines: Vec<Option<AbsState>>,
+    end: Option<AbsState>,
+}
+
+impl IntervalAnalysis {
+    /// The states when the command on `line` is about to run, or for a
+    /// loop, whenever its condition is tested. `None` if the line is never
+    /// reached or no command starts on it.
+    pub fn at(&self, line: usize) -> Option<&AbsState> {
+        self.lines.get(line.checked_sub(1)?)?.as_ref()
+    }
+
+    /// The states the program can end in, or `None` if it can't end.
+    pub fn at_end(&self) -> Option<&AbsState> {
+        self.end.as_ref()
+    }
+
+    /// Whether the analysis can't rule out `assertion` being false when
+    /// the command on `line` is about to run. A line that is never reached
+    /// can't violate anything.
+    pub fn can_violate(&self, line: usize, assertion: &Bexp) -> bool {
+        self.at(line).is_some_and(|st| st.may_violate(assertion))
+    }
+
+    /// Whether `assertion` might be false when the program ends.
+    pub fn can_violate_at_end(&self, assertion: &Bexp) -> bool {
+        self.end
+            .as_ref()
+            .is_some_and(|st| st.may_violate(assertion))
+    }
+}
+
+/// Analyse `c` started from a state in `init`.
+pub fn analyze_intervals(c: &Com, init: &AbsState) -> IntervalAnalysis {
+    let mut analyzer = Analyzer {
+        lines: vec![None; block_lines(c)],
+        record: true,
+        interference: BTreeSet::new(),
+    };
+    let flow = analyzer.exec(Some(init.clone()), c, 1);
+    IntervalAnalysis {
+        lines: analyzer.lines,
+        // Outside a loop, `break` and `continue` end the program, as does
+        // an uncaught `throw`.
+        end: join(
+            join(join(flow.normal, flow.brk), flow.cont),
+            flow.throw.map(|(st, _)| st),
+        ),
+    }
+}
+
+/// How many times a loop is iterated without widening once its state has
+/// stopped growing.
+const NARROWING: usize = 3;
+
+/// The states a command can finish in, by how it finishes.
+#[derive(Default)]
+struct Flow {
+    normal: Option<AbsState>,
+    brk: Option<AbsState>,
+    cont: Option<AbsState>,
+    /// The states a `throw` leaves in, with the values it may throw.
+    throw: Thrown,
+}
+
+type Thrown = Option<(AbsState, Interval)>;
+
+fn join_thrown(t1: Thrown, t2: Thrown) -> Thrown {
+    match (t1, t2) {
+        (Some((st1, i1)), Some((st2, i2))) => Some((st1.zip(&st2, Interval::join), i1.join(&i2))),
+        (t, None) | (None, t) => t,
+    }
+}
+
+impl Flow {
+    fn normal(st: Option<AbsState>) -> Flow {
+        Flow {
+            normal: st,
+            ..Flow::default()
+        }
+    }
+
+    fn join(self, other: Flow) -> Flow {
+        Flow {
+            normal: join(self.normal, other.normal),
+            brk: join(self.brk, other.brk),
+            cont: join(self.cont, other.cont),
+            throw: join_thrown(self.throw, other.throw),
+        }
+    }
+}
+
+struct Analyzer {
+    lines: Vec<Option<AbsState>>,
+    /// Whether to record states in `lines`. A loop's iterations before it
+    /// settles don't, and neither do the parts of a `for` header.
+    record: bool,
+    /// The variables that a `par` branch running alongside may write at any
+    /// moment, so nothing is known about them.
+    interference: BTreeSet<String>,
+}
+
+impl Analyzer {
+    /// Run `c`, whose first line is `line`, from `st`.
+    fn exec(&mut self, st: Option<AbsState>, c: &Com, line: usize) -> Flow {
+        let Some(mut st) = st else {
+            return Flow::default();
+        };
+        for x in &self.interference {
+            st.set(x, Interval::TOP);
+        }
+        if !matches!(c, Com::CSeq(..) | Com::CWhile(..) | Com::CFor(..)) {
+            self.record(line, &st);
+        }
+        match c {
+            Com::CSkip | Com::CPrint(_) | Com::CArrAsgn(..) => Flow::normal(Some(st)),
+            Com::CBreak => Flow {
+                brk: Some(st),
+                ..Flow::default()
+            },
+            Com::CContinue => Flow {
+                cont: Some(st),
+                ..Flow::default()
+            },
+            Com::CAsgn(x, a) => {
+                let i = st.aexp(a);
+                st.set(x, i);
+                Flow::normal(Some(st))
+            }
+            Com::CThrow(a) => {
+                let i = st.aexp(a);
+                Flow {
+                    throw: Some((st, i)),
+                    ..Flow::default()
+                }
+            }
+            // `havoc` can pick anything.
+            Com::CHavoc(x) => {
+                st.set(x, Interval::TOP);
+                Flow::normal(Some(st))
+            }
+            // A call can return anything, or throw anything before `x` is
+            // set.
+            Com::CCall(x, ..) => {
+                let throw = Some((st.clone(), Interval::TOP));
+                st.set(x, Interval::TOP);
+                Flow {
+                    throw,
+                    ..Flow::normal(Some(st))
+                }
+            }
+            Com::CSeq(c1, c2) => {
+                let (line1, line2) = seq_lines(c1, line);
+                let first = self.exec(Some(st), c1, line1);
+                let second = self.exec(first.normal, c2, line2);
+                Flow {
+                    normal: second.normal,
+                    brk: join(first.brk, second.brk),
+                    cont: join(first.cont, second.cont),
+                    throw: join_thrown(first.throw, second.throw),
+                }
+            }
+            Com::CIf(b, c1, c2) => {
+                let then = self.exec(st.assume(b, true), c1, line + 1);
+                let else_ = self.exec(st.assume(b, false), c2, line + 2 + block_lines(c1));
+                then.join(else_)
+            }
+            Com::CWhile(b, body) => self.run_loop(st, b, body, None, line),
+            Com::CFor(init, b, update, body) => {
+                let init = self.quietly(|analyzer| analyzer.exec(Some(st), init, line));
+                let Some(st) = init.normal else {
+                    return init;
+                };
+                let after = self.run_loop(st, b, body, Some(update), line);
+                Flow {
+                    normal: after.normal,
+                    throw: join_thrown(init.throw, after.throw),
+                    ..init
+                }
+            }
+            Com::CPar(c1, c2) => {
+                let assigned = |c: &Com| {
+                    let mut vars = BTreeSet::new();
+                    c.collect_assigned(&mut vars);
+                    vars
+                };
+                let (writes1, writes2) = (assigned(c1), assigned(c2));
+                let (end1, throw1) = self.branch(&st, c1, line + 1, &writes2);
+                let (end2, throw2) = self.branch(&st, c2, line + 2 + block_lines(c1), &writes1);
+                // A `throw` in either branch ends the whole `par`.
+                let throw = join_thrown(throw1, throw2);
+                let (Some(end1), Some(end2)) = (end1, end2) else {
+                    return Flow {
+                        throw,
+                        ..Flow::default()
+                    };
+                };
+                // A variable ends as the last branch to write it left it.
+                for x in writes1.union(&writes2) {
+                    let i = match (writes1.contains(x), writes2.contains(x)) {
+                        (true, false) => end1.get(x),
+                        (false, true) => end2.get(x),
+                        _ => end1.get(x).join(&end2.get(x)),
+                    };
+                    st.set(x, i);
+                }
+                Flow {
+                    throw,
+                    ..Flow::normal(Some(st))
+                }
+            }
+            Com::CTry(body, x, handler) => {
+                let mut flow = self.exec(Some(st), body, line + 1);
+                let caught = flow.throw.take().map(|(mut st, i)| {
+                    st.set(x, i);
+                    st
+                });
+                let handled = self.exec(caught, handler, line + 2 + block_lines(body));
+                flow.join(handled)
+            }
+        }
+    }
+
+    /// Run a loop testing `b` on line `line` from `entry`, and return the
+    /// states it can end in, normally or by a `throw`.
+    fn run_loop(
+        &mut self,
+        entry: AbsState,
+        b: &Bexp,
+        body: &Com,
+        update: Option<&Com>,
+        line: usize,
+    ) -> Flow {
+        let mut head = entry.clone();
+        let mut narrowing = 0;
+        while narrowing < NARROWING {
+            let (next, ..) =
+                self.quietly(|analyzer| analyzer.iterate(&entry, &head, b, body, update, line));
+            if narrowing == 0 && !head.includes(&next) {
+                head = head.zip(&next, Interval::widen);
+            } else if next == head {
+                break;
+            } else {
+                // `head` is a fixpoint from here on, and so is `next`.
+                head = next;
+                narrowing += 1;
+            }
+        }
+        self.record(line, &head);
+        let (_, brk, throw) = self.iterate(&entry, &head, b, body, update, line);
+        Flow {
+            normal: join(head.assume(b, false), brk),
+            throw,
+            ..Flow::default()
+        }
+    }
+
+    /// One iteration of a loop from `head`: the states at the next test,
+    /// those in which a `break` leaves the loop, and what a `throw` takes
+    /// out of it.
+    fn iterate(
+        &mut self,
+        entry: &AbsState,
+        head: &AbsState,
+        b: &Bexp,
+        body: &Com,
+        update: Option<&Com>,
+        line: usize,
+    ) -> (AbsState, Option<AbsState>, Thrown) {
+        let done = self.exec(head.assume(b, true), body, line + 1);
+        let (again, brk, throw) = match update {
+            // The update belongs to the loop: a `break` in it ends the loop
+            // and a `continue` goes on to the test.
+            Some(update) => {
+                let after = join(done.normal, done.cont);
+                let update = self.quietly(|analyzer| analyzer.exec(after, update, line));
+                (
+                    join(update.normal, update.cont),
+                    join(done.brk, update.brk),
+                    join_thrown(done.throw, update.throw),
+                )
+            }
+            None => (join(done.normal, done.cont), done.brk, done.throw),
+        };
+        (
+            join(Some(entry.clone()), again).expect("the entry state is reachable"),
+            brk,
+            throw,
+        )
+    }
+
+    /// The states a `par` branch `c` can end in, from `st`, while the other
+    /// branch writes `interference`, and what it may throw. A `break` or
+    /// `continue` ends just the branch.
+    fn branch(
+        &mut self,
+        st: &AbsState,
+        c: &Com,
+        line: usize,
+        interference: &BTreeSet<String>,
+    ) -> (Option<AbsState>, Thrown) {
+        let outer = self.interference.clone();
+        self.interference.extend(interference.iter().cloned());
+        let flow = self.exec(Some(st.clone()), c, line);
+        self.interference = outer;
+        (join(join(flow.normal, flow.brk), flow.cont), flow.throw)
+    }
+
+    /// Run `f` without recording any states.
+    fn quietly<T>(&mut self, f: impl FnOnce(&mut Analyzer) -> T) -> T {
+        let record = std::mem::replace(&mut self.record, false);
+        let result = f(self);
+        self.record = record;
+        result
+    }
+
+    fn record(&mut self, line: usize, st: &AbsState) {
+        if self.record {
+            let seen = &mut self.lines[line - 1];
+            *seen = join(seen.take(), Some(st.clone()));
+        }
+    }
+}
+
+#[cfg(test)]
+mod test_imp_analysis {
+    use super::*;
+    use crate::imp::{ceval_fuel, parse_aexp, parse_bexp, parse_com};
+    use crate::state;
+
+    fn zero() -> AbsState {
+        AbsState::uniform(Interval::constant(0))
+    }
+
+    #[test]
+    fn test_interval_arithmetic() {
+        let st = AbsState::top()
+            .with("X", Interval::new(-2, 3))
+            .with("Y", Interval::new(1, 4));
+        let i = |a: &str| st.aexp(&parse_aexp(a).unwrap());
+        assert_eq!(i("X + Y"), Interval::new(-1, 7));
+        assert_eq!(i("X - Y"), Interval::new(-6, 2));
+        assert_eq!(i("X * Y"), Interval::new(-8, 12));
+        assert_eq!(i("X * X"), Interval::new(-6, 9));
+        assert_eq!(i("Z + 1"), Interval::TOP);
+        assert_eq!(i("9223372036854775807 + Y"), Interval::TOP);
+        assert_eq!(Interval::new(0, i64::MAX).to_string(), "[0, +inf]");
+        let narrowed = st
+            .assume(&parse_bexp("~(X <= 0) && ~(Y = 4)").unwrap(), true)
+            .unwrap();
+        assert_eq!(narrowed.to_string(), "X=[1, 3], Y=[1, 3]");
+        assert_eq!(st.assume(&parse_bexp("Y <= X - 3").unwrap(), true), None);
+    }
+
+    #[test]
+    fn test_loop_bounds() {
+        let c = parse_com("I := 0; S := 0; while I <= 9 do S := S + 2; I := I + 1 end").unwrap();
+        let analysis = analyze_intervals(&c, &zero());
+        // Line 3 is the loop, 4 the first line of its body.
+        assert_eq!(analysis.at(3).unwrap().get("I"), Interval::new(0, 10));
+        assert_eq!(analysis.at(4).unwrap().get("I"), Interval::new(0, 9));
+        assert_eq!(analysis.at_end().unwrap().get("I"), Interval::constant(10));
+        assert!(!analysis.can_violate_at_end(&parse_bexp("I = 10").unwrap()));
+        // S + 2 might overflow as far as the analysis knows, so S is lost.
+        assert!(analysis.can_violate_at_end(&parse_bexp("0 <= S").unwrap()));
+        assert!(analysis.can_violate(4, &parse_bexp("I <= 8").unwrap()));
+        assert!(!analysis.can_violate(4, &parse_bexp("I <= 9").unwrap()));
+    }
+
+    #[test]
+    fn test_unreachable_code() {
+        let c = parse_com(
+            "X := 3; if X <= 2 then Y := 1 else Y := 2 end; while true do break end; Z := Y",
+        )
+        .unwrap();
+        let analysis = analyze_intervals(&c, &AbsState::top());
+        assert_eq!(analysis.at(3), None);
+        assert!(analysis.at(5).is_some());
+        assert!(!analysis.can_violate_at_end(&parse_bexp("Z = 2").unwrap()));
+        let c = parse_com("while true do X := X + 1 end").unwrap();
+        assert_eq!(analyze_intervals(&c, &zero()).at_end(), None);
+    }
+
+    #[test]
+    fn test_sound_on_samples() {
+        let programs = [
+            "Z := X; Y := 1; while ~(Z = 0) do Y := Y * Z; Z := Z - 1 end",
+            "while X <= 6 do X := X + 1; if X = 4 then continue else Y := Y + X end end",
+            "for I := 0; I <= 5; I := I + 1 do if I = 3 then break else S := S + I end end",
+            "if X <= 0 then Y := 0 - X else Y := X end; while 1 <= Y do Y := Y - 2 end",
+            "havoc Y; if Y <= 10 && 0 <= Y then X := Y * Y else X := 0 end",
+        ];
+        let vars = ["X", "Y", "Z", "S", "I"];
+        let init = AbsState::uniform(Interval::constant(0)).with("X", Interval::new(-5, 5));
+        for p in programs {
+            let c = parse_com(p).unwrap();
+            let analysis = analyze_intervals(&c, &init);
+            assert_eq!(analysis.lines.len(), c.to_pretty_string().lines().count());
+            for x in -5..=5 {
+                if let Some(end) = ceval_fuel(state! {"X" => x}, &c, 1_000).unwrap() {
+                    let abs = analysis.at_end().expect("the program ended");
+                    for x in vars {
+                        assert!(abs.get(x).contains(end(x)), "{} after {}", x, p);
+                    }
+                }
+            }
+        }
+    }
+}
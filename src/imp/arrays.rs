@@ -0,0 +1,240 @@
+//! Arrays: `A[i]` reads an element and `A[i] := e` writes one. Each array
+//! is a `PartialMap<i64, i64>` from indices to elements, and the arrays of
+//! a run live beside its `State` in an `Arrays` map from names, in which
+//! every array starts out with no elements.
+//!
+//! What an access to a missing element does is up to the caller: under
+//! `OutOfBounds::Zero` arrays are unbounded and full of zeros, like Imp's
+//! variables, and under `OutOfBounds::Fault` an array has exactly the
+//! elements it was given, so a sorting program can be checked to stay
+//! within them.
+
+use super::{Aexp, Bexp, Com, EvalError, Machine};
+use crate::map::{pm_empty, pm_update, tm_empty, tm_update, PartialMap, TotalMap};
+use crate::state::State;
+
+pub type Array = PartialMap<i64, i64>;
+
+pub type Arrays = TotalMap<String, Array>;
+
+/// What reading or writing an element an array doesn't have does.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum OutOfBounds {
+    /// Reads give `0` and writes add the element.
+    Zero,
+    /// Both fail with `EvalError::OutOfBounds`.
+    Fault,
+}
+
+/// No arrays with any elements.
+pub fn empty_arrays() -> Arrays {
+    tm_empty(pm_empty())
+}
+
+/// The array with `values` at indices `0`, `1`, ... and nothing elsewhere.
+pub fn array_from(values: &[i64]) -> Array {
+    values
+        .iter()
+        .enumerate()
+        .fold(pm_empty(), |a, (i, n)| pm_update(a, i as i64, *n))
+}
+
+/// The first `len` elements of `a`, or `None` if one of them is missing.
+pub fn array_to_vec(a: &Array, len: usize) -> Option<Vec<i64>> {
+    (0..len as i64).map(|i| a(&i)).collect()
+}
+
+/// `aeval` over `st` and `arrays`.
+pub fn aeval_arrays(
+    st: &State,
+    arrays: &Arrays,
+    bounds: OutOfBounds,
+    a: &Aexp,
+) -> Result<i64, EvalError> {
+    let eval = |a| aeval_arrays(st, arrays, bounds, a);
+    Ok(match a {
+        Aexp::ANum(n) => *n,
+        Aexp::AId(x) => st(x),
+        Aexp::APlus(a1, a2) => eval(a1)?.wrapping_add(eval(a2)?),
+        Aexp::AMinus(a1, a2) => eval(a1)?.wrapping_sub(eval(a2)?),
+        Aexp::AMult(a1, a2) => eval(a1)?.wrapping_mul(eval(a2)?),
+        Aexp::AIndex(x, i) => {
+            let index = eval(i)?;
+            match (arrays(x)(&index), bounds) {
+                (Some(n), _) => n,
+                (None, OutOfBounds::Zero) => 0,
+                (None, OutOfBounds::Fault) => {
+                    return Err(EvalError::OutOfBounds {
+                        array: x.clone(),
+                        index,
+                    })
+                }
+            }
+        }
+    })
+}
+
+/// `beval` over `st` and `arrays`.
+pub fn beval_arrays(
+    st: &State,
+    arrays: &Arrays,
+    bounds: OutOfBounds,
+    b: &Bexp,
+) -> Result<bool, EvalError> {
+    let aeval = |a| aeval_arrays(st, arrays, bounds, a);
+    let beval = |b| beval_arrays(st, arrays, bounds, b);
+    Ok(match b {
+        Bexp::BTrue => true,
+        Bexp::BFalse => false,
+        Bexp::BEq(a1, a2) => aeval(a1)? == aeval(a2)?,
+        Bexp::BLe(a1, a2) => aeval(a1)? <= aeval(a2)?,
+        Bexp::BNot(b1) => !beval(b1)?,
+        Bexp::BAnd(b1, b2) => beval(b1)? && beval(b2)?,
+    })
+}
+
+/// `arrays` with element `i` of `x` set to `n`.
+pub(super) fn write_array(
+    arrays: &Arrays,
+    bounds: OutOfBounds,
+    x: &str,
+    i: i64,
+    n: i64,
+) -> Result<Arrays, EvalError> {
+    let x = x.to_string();
+    let a = arrays(&x);
+    if bounds == OutOfBounds::Fault && a(&i).is_none() {
+        return Err(EvalError::OutOfBounds { array: x, index: i });
+    }
+    Ok(tm_update(arrays.clone(), x, pm_update(a, i, n)))
+}
+
+/// `ceval_fuel` for a command using arrays: run `c` from `st` and `arrays`
+/// and return both at the end. Fails with `EvalError::OutOfFuel` after
+/// `fuel` loop iterations, and with `UndefinedProc` on a call, as plain
+/// commands have no procedures.
+pub fn ceval_arrays(
+    st: State,
+    arrays: Arrays,
+    c: &Com,
+    bounds: OutOfBounds,
+    fuel: u64,
+) -> Result<(State, Arrays), EvalError> {
+    let mut machine = Machine {
+        arrays,
+        bounds,
+        ..Machine::new(Some(fuel), pm_empty())
+    };
+    let (st, _) = machine.exec(st, c)?;
+    Ok((st, machine.arrays))
+}
+
+#[cfg(test)]
+mod test_imp_arrays {
+    use super::*;
+    use crate::imp::{aeval, parse_aexp, parse_com, Program};
+    use crate::state;
+    use crate::state::{empty_state, lookup};
+
+    const INSERTION_SORT: &str = "
+        I := 1;
+        while I <= N - 1 do
+            J := I;
+            while 1 <= J && A[J] <= A[J - 1] do
+                T := A[J]; A[J] := A[J - 1]; A[J - 1] := T;
+                J := J - 1
+            end;
+            I := I + 1
+        end";
+
+    fn with_array(x: &str, values: &[i64]) -> Arrays {
+        tm_update(empty_arrays(), x.to_string(), array_from(values))
+    }
+
+    #[test]
+    fn test_insertion_sort() {
+        let c = parse_com(INSERTION_SORT).unwrap();
+        let input = [5, 2, 9, 1, 3, 2];
+        let st = state! {"N" => input.len() as i64};
+        let (_, arrays) =
+            ceval_arrays(st, with_array("A", &input), &c, OutOfBounds::Fault, 1_000).unwrap();
+        assert_eq!(
+            array_to_vec(&arrays(&"A".to_string()), input.len()),
+            Some(vec![1, 2, 2, 3, 5, 9])
+        );
+        // Nothing was written past the end.
+        assert_eq!(arrays(&"A".to_string())(&(input.len() as i64)), None);
+    }
+
+    #[test]
+    fn test_out_of_bounds() {
+        let read = parse_com("X := A[3]").unwrap();
+        let arrays = with_array("A", &[1, 2]);
+        let err = ceval_arrays(empty_state(), arrays.clone(), &read, OutOfBounds::Fault, 10)
+            .map(|_| ())
+            .unwrap_err();
+        assert_eq!(err.to_string(), "index 3 is out of bounds for array `A`");
+        let (st, _) =
+            ceval_arrays(empty_state(), arrays.clone(), &read, OutOfBounds::Zero, 10).unwrap();
+        assert_eq!(lookup(&st, "X"), 0);
+
+        let write = parse_com("A[-1] := 7; X := A[-1]").unwrap();
+        assert!(ceval_arrays(
+            empty_state(),
+            arrays.clone(),
+            &write,
+            OutOfBounds::Fault,
+            10
+        )
+        .is_err());
+        let (st, arrays) =
+            ceval_arrays(empty_state(), arrays, &write, OutOfBounds::Zero, 10).unwrap();
+        assert_eq!(lookup(&st, "X"), 7);
+        assert_eq!(arrays(&"A".to_string())(&-1), Some(7));
+    }
+
+    #[test]
+    fn test_scalar_evaluators() {
+        // Plain `aeval` sees only empty arrays, while `ceval` keeps the
+        // arrays for the duration of the run.
+        assert_eq!(
+            aeval(&state! {"I" => 2}, &parse_aexp("A[I] + 1").unwrap()),
+            1
+        );
+        let st = crate::imp::ceval(
+            empty_state(),
+            &parse_com("A[0] := 4; X := A[0] * 2").unwrap(),
+        )
+        .unwrap();
+        assert_eq!(lookup(&st, "X"), 8);
+    }
+
+    #[test]
+    fn test_arrays_are_shared_by_procedures() {
+        let prog: Program =
+            "proc push(V) local N returns R do N := A[0] + 1; A[N] := V; A[0] := N end
+             R := push(3); R := push(4); X := A[0]; Y := A[2]"
+                .parse()
+                .unwrap();
+        let st = prog.run(empty_state()).unwrap();
+        assert_eq!((lookup(&st, "X"), lookup(&st, "Y")), (2, 4));
+    }
+
+    #[test]
+    fn test_print_round_trip() {
+        let c = parse_com("A[I + 1] := A[I] * 2; X := B[A[0]]").unwrap();
+        assert_eq!(
+            c,
+            Com::seq(
+                Com::arr_asgn(
+                    "A",
+                    Aexp::plus(Aexp::var("I"), Aexp::num(1)),
+                    Aexp::mult(Aexp::index("A", Aexp::var("I")), Aexp::num(2))
+                ),
+                Com::asgn("X", Aexp::index("B", Aexp::index("A", Aexp::num(0))))
+            )
+        );
+        assert_eq!(c.to_string(), "A[I + 1] := A[I] * 2; X := B[A[0]]");
+        assert_eq!(c.vars().into_iter().collect::<Vec<_>>(), ["I", "X"]);
+    }
+}
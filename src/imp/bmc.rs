@@ -0,0 +1,318 @@
+//! Bounded model checking: a breadth-first search of the graph whose nodes
+//! are small-step configurations, a state and what is left to run, and
+//! whose edges are steps. `havoc X` steps to one successor for each value
+//! of a given domain, and `par` to one for each branch that can step;
+//! every other step is `step`'s. Configurations are compared on the
+//! program's variables, so each is explored once.
+
+use std::collections::HashSet;
+use std::fmt;
+
+use super::smallstep::{done, step, thrown, Config, Running};
+use super::{beval, Bexp, Com, Reachable, Unsupported};
+use crate::map::tm_update;
+use crate::state::State;
+
+/// One configuration of a trace.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceStep {
+    /// The state, on the program's variables.
+    pub state: Vec<(String, i64)>,
+    pub com: Config,
+}
+
+/// A run that breaks the invariant `check_invariant` was given.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Violation {
+    /// From the initial configuration to the first one whose state breaks
+    /// the invariant. No trace is shorter.
+    pub trace: Vec<TraceStep>,
+}
+
+impl Violation {
+    /// The state where the invariant fails, as `X=0, Y=10`.
+    pub fn state(&self) -> String {
+        show(&self.trace[self.trace.len() - 1].state)
+    }
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "the invariant fails after {} steps:",
+            self.trace.len() - 1
+        )?;
+        for step in &self.trace {
+            write!(f, "\n  {}  |  {}", show(&step.state), step.com)?;
+        }
+        Ok(())
+    }
+}
+
+fn show(state: &[(String, i64)]) -> String {
+    let vars: Vec<_> = state.iter().map(|(x, n)| format!("{}={}", x, n)).collect();
+    vars.join(", ")
+}
+
+/// Every state `c` passes through from `init` in at most `bound` steps,
+/// with each `havoc` picking from `domain`. `complete` is `false` if some
+/// run was cut off by the bound. Reaching a procedure call, an array
+/// write or a `par` is an error, as in `successors`.
+pub fn reachable_states(
+    c: &Com,
+    init: State,
+    domain: &[i64],
+    bound: usize,
+) -> Result<Reachable, Unsupported> {
+    let mut search = Search::new(c, init);
+    search.run(domain, bound, |_| false)?;
+    let mut seen = HashSet::new();
+    let states = search
+        .nodes
+        .iter()
+        .filter(|node| seen.insert(search.key(&node.st)))
+        .map(|node| node.st.clone())
+        .collect();
+    Ok(Reachable {
+        states,
+        complete: search.complete,
+    })
+}
+
+/// Check that `inv` holds in every state `c` passes through from `init` in
+/// at most `bound` steps, with each `havoc` picking from `domain`, and
+/// return a shortest run to a state where it doesn't if there is one.
+/// Fails where `reachable_states` does.
+pub fn check_invariant(
+    c: &Com,
+    init: State,
+    domain: &[i64],
+    inv: &Bexp,
+    bound: usize,
+) -> Result<Result<(), Violation>, Unsupported> {
+    let mut search = Search::new(c, init);
+    Ok(match search.run(domain, bound, |st| !beval(st, inv))? {
+        None => Ok(()),
+        Some(bad) => {
+            let mut trace = Vec::new();
+            let mut at = Some(bad);
+            while let Some(i) = at {
+                let node = &search.nodes[i];
+                trace.push(TraceStep {
+                    state: search
+                        .vars
+                        .iter()
+                        .map(|x| (x.clone(), (node.st)(x)))
+                        .collect(),
+                    com: Config(node.com.clone()),
+                });
+                at = node.parent;
+            }
+            trace.reverse();
+            Err(Violation { trace })
+        }
+    })
+}
+
+pub(super) struct Node {
+    pub(super) st: State,
+    pub(super) com: Running,
+    parent: Option<usize>,
+}
+
+pub(super) struct Search {
+    /// The program's variables; nothing else can change.
+    vars: Vec<String>,
+    /// Every configuration found, in the order found.
+    pub(super) nodes: Vec<Node>,
+    pub(super) complete: bool,
+}
+
+impl Search {
+    pub(super) fn new(c: &Com, init: State) -> Search {
+        Search {
+            vars: c.vars().into_iter().collect(),
+            nodes: vec![Node {
+                st: init,
+                com: Running::Com(c.clone()),
+                parent: None,
+            }],
+            complete: true,
+        }
+    }
+
+    pub(super) fn key(&self, st: &State) -> Vec<i64> {
+        self.vars.iter().map(|x| st(x)).collect()
+    }
+
+    /// Explore up to `bound` steps from the initial configuration, and
+    /// return the first node found whose state is `bad`.
+    pub(super) fn run(
+        &mut self,
+        domain: &[i64],
+        bound: usize,
+        bad: impl Fn(&State) -> bool,
+    ) -> Result<Option<usize>, Unsupported> {
+        let mut seen = HashSet::new();
+        seen.insert((self.key(&self.nodes[0].st), self.nodes[0].com.clone()));
+        if bad(&self.nodes[0].st) {
+            return Ok(Some(0));
+        }
+        let mut frontier = vec![0];
+        for _ in 0..bound {
+            let mut next = Vec::new();
+            for i in frontier {
+                for (st, com) in successors(&self.nodes[i].st, &self.nodes[i].com, domain)? {
+                    if !seen.insert((self.key(&st), com.clone())) {
+                        continue;
+                    }
+                    let found = bad(&st);
+                    self.nodes.push(Node {
+                        st,
+                        com,
+                        parent: Some(i),
+                    });
+                    if found {
+                        return Ok(Some(self.nodes.len() - 1));
+                    }
+                    next.push(self.nodes.len() - 1);
+                }
+            }
+            frontier = next;
+        }
+        for i in frontier {
+            if !successors(&self.nodes[i].st, &self.nodes[i].com, domain)?.is_empty() {
+                self.complete = false;
+                break;
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// The configurations one step from `st` and `c`: `step`'s, except that
+/// a `havoc` picks each value of `domain` and a `par` steps either branch.
+pub(super) fn successors(
+    st: &State,
+    r: &Running,
+    domain: &[i64],
+) -> Result<Vec<(State, Running)>, Unsupported> {
+    let within = |r: &Running, wrap: &dyn Fn(Running) -> Running| {
+        Ok(successors(st, r, domain)?
+            .into_iter()
+            .map(|(st, r)| (st, wrap(r)))
+            .collect::<Vec<_>>())
+    };
+    match r {
+        Running::Com(Com::CHavoc(x)) => Ok(domain
+            .iter()
+            .map(|n| {
+                (
+                    tm_update(st.clone(), x.clone(), *n),
+                    Running::Com(Com::CSkip),
+                )
+            })
+            .collect()),
+        Running::Com(c @ (Com::CSeq(..) | Com::CPar(..) | Com::CTry(..))) => {
+            successors(st, &Running::open(c), domain)
+        }
+        Running::Seq(r1, c2) if !done(r1) => within(r1, &|r1| Running::seq(r1, c2.clone())),
+        Running::Loop(rest, b, body) if !done(rest) => {
+            within(rest, &|rest| Running::loop_(rest, b.clone(), body.clone()))
+        }
+        // Once a branch has thrown, `step` ends the `par`.
+        Running::Par(r1, r2)
+            if (!done(r1) || !done(r2)) && thrown(r1).is_none() && thrown(r2).is_none() =>
+        {
+            let mut next = within(r1, &|r1| Running::par(r1, (**r2).clone()))?;
+            next.extend(within(r2, &|r2| Running::par((**r1).clone(), r2))?);
+            Ok(next)
+        }
+        Running::Try(body, x, handler) if !done(body) => {
+            within(body, &|body| Running::try_(body, x, handler.clone()))
+        }
+        _ => Ok(step(st, &Config(r.clone()))?
+            .map(|(st, c)| (st, c.0))
+            .into_iter()
+            .collect()),
+    }
+}
+
+#[cfg(test)]
+mod test_imp_bmc {
+    use super::*;
+    use crate::imp::{parse_bexp, parse_com};
+    use crate::state;
+    use crate::state::empty_state;
+
+    #[test]
+    fn test_reachable_states() {
+        let c = parse_com("X := 1; X := X + 1; X := X * 3").unwrap();
+        let reached = reachable_states(&c, empty_state(), &[], 100).unwrap();
+        assert!(reached.complete);
+        assert_eq!(
+            reached.values(&["X"]),
+            [[0], [1], [2], [6]].map(Vec::from).into()
+        );
+        let reached = reachable_states(&c, empty_state(), &[], 3).unwrap();
+        assert!(!reached.complete);
+        assert_eq!(reached.values(&["X"]), [[0], [1]].map(Vec::from).into());
+        // Each havoc branches over the domain.
+        let c = parse_com("havoc X; havoc Y; Y := X + Y").unwrap();
+        let reached = reachable_states(&c, empty_state(), &[0, 1, 2], 100).unwrap();
+        assert_eq!(reached.values(&["Y"]), (0..=4).map(|n| vec![n]).collect());
+    }
+
+    #[test]
+    fn test_invariant_holds() {
+        let c = parse_com("X := 0; while X <= 4 do X := X + 1 end").unwrap();
+        let inv = parse_bexp("0 <= X && X <= 5").unwrap();
+        assert_eq!(
+            check_invariant(&c, empty_state(), &[], &inv, 1_000).unwrap(),
+            Ok(())
+        );
+        // A loop that never ends is fine: its states repeat.
+        let c =
+            parse_com("while true do havoc X; if 3 <= X then X := 0 else skip end end").unwrap();
+        let inv = parse_bexp("X <= 3").unwrap();
+        assert_eq!(
+            check_invariant(&c, empty_state(), &[0, 1, 3], &inv, 1_000).unwrap(),
+            Ok(())
+        );
+        assert!(
+            reachable_states(&c, empty_state(), &[0, 1, 3], 1_000)
+                .unwrap()
+                .complete
+        );
+    }
+
+    #[test]
+    fn test_violation_trace() {
+        let c = parse_com("havoc X; if X <= 1 then Y := 10 - X else Y := X end").unwrap();
+        let inv = parse_bexp("Y <= 9").unwrap();
+        let violation = check_invariant(&c, state! {}, &[0, 1, 2], &inv, 100)
+            .unwrap()
+            .unwrap_err();
+        // The shortest run havocs X to 0 and takes the first branch.
+        let first = &violation.trace[0];
+        assert_eq!(first.com, c.clone().into());
+        assert_eq!(violation.state(), "X=0, Y=10");
+        for pair in violation.trace.windows(2) {
+            let st = pair[0]
+                .state
+                .iter()
+                .fold(empty_state(), |st, (x, n)| tm_update(st, x.clone(), *n));
+            assert!(successors(&st, &pair[0].com.0, &[0, 1, 2])
+                .unwrap()
+                .iter()
+                .any(
+                    |(st, r)| *r == pair[1].com.0 && pair[1].state.iter().all(|(x, n)| st(x) == *n)
+                ));
+        }
+        assert!(violation.to_string().starts_with(&format!(
+            "the invariant fails after {} steps:\n  X=0, Y=0  |  havoc X; ",
+            violation.trace.len() - 1
+        )));
+    }
+}
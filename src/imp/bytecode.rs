@@ -0,0 +1,403 @@
+//! A byte encoding of stack machine programs, so that compiled code can be
+//! saved and run later, or handed to a fuzzer as raw input.
+//!
+//! An encoded program is the magic bytes `IMPS`, a version byte, and then
+//! the instructions, each an opcode byte followed by its operand, if any:
+//!
+//! ```text
+//! 0  SPush   the number, zigzag LEB128 (small magnitudes take one byte)
+//! 1  SLoad   the length of the name, LEB128, then the name in UTF-8
+//! 2  SPlus
+//! 3  SMinus
+//! 4  SMult
+//! ```
+//!
+//! With the `serde` feature, `SInstr` is also `Serialize` and
+//! `Deserialize`, as an ordinary enum: `{"SPush": 5}`, `"SPlus"`.
+
+use std::fmt;
+
+use super::stack::{s_execute, SInstr, StackUnderflow};
+use crate::state::State;
+
+/// The first bytes of every encoded program.
+pub const MAGIC: &[u8; 4] = b"IMPS";
+
+/// The version of the encoding `encode` writes and `decode` reads.
+pub const VERSION: u8 = 1;
+
+const PUSH: u8 = 0;
+const LOAD: u8 = 1;
+const PLUS: u8 = 2;
+const MINUS: u8 = 3;
+const MULT: u8 = 4;
+
+/// Why a byte string isn't an encoded program. Positions are byte offsets
+/// into it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DecodeError {
+    /// It doesn't start with `MAGIC`.
+    BadMagic,
+    /// It was written by a version of the encoding this one can't read.
+    UnsupportedVersion(u8),
+    UnknownOpcode {
+        opcode: u8,
+        pos: usize,
+    },
+    /// It ends partway through the instruction at `pos`.
+    Truncated {
+        pos: usize,
+    },
+    /// The number at `pos` doesn't fit in an `i64`, or a length in a
+    /// `usize`.
+    NumberTooLarge {
+        pos: usize,
+    },
+    /// The variable name at `pos` isn't UTF-8.
+    BadName {
+        pos: usize,
+    },
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::BadMagic => write!(f, "not stack machine bytecode"),
+            DecodeError::UnsupportedVersion(v) => write!(
+                f,
+                "bytecode version {} is not supported (expected {})",
+                v, VERSION
+            ),
+            DecodeError::UnknownOpcode { opcode, pos } => {
+                write!(f, "unknown opcode {} at byte {}", opcode, pos)
+            }
+            DecodeError::Truncated { pos } => {
+                write!(f, "bytecode ends inside the instruction at byte {}", pos)
+            }
+            DecodeError::NumberTooLarge { pos } => write!(f, "number too large at byte {}", pos),
+            DecodeError::BadName { pos } => write!(f, "invalid variable name at byte {}", pos),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+/// Why `s_execute_bytecode` failed: at loading or at running.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BytecodeError {
+    Decode(DecodeError),
+    Underflow(StackUnderflow),
+}
+
+impl fmt::Display for BytecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BytecodeError::Decode(e) => e.fmt(f),
+            BytecodeError::Underflow(e) => e.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for BytecodeError {}
+
+impl From<DecodeError> for BytecodeError {
+    fn from(e: DecodeError) -> Self {
+        BytecodeError::Decode(e)
+    }
+}
+
+impl From<StackUnderflow> for BytecodeError {
+    fn from(e: StackUnderflow) -> Self {
+        BytecodeError::Underflow(e)
+    }
+}
+
+/// The bytes of `prog`, in the current version of the encoding.
+pub fn encode(prog: &[SInstr]) -> Vec<u8> {
+    let mut bytes = MAGIC.to_vec();
+    bytes.push(VERSION);
+    for instr in prog {
+        match instr {
+            SInstr::SPush(n) => {
+                bytes.push(PUSH);
+                // Zigzag, so that small negative numbers are short too.
+                write_uleb(&mut bytes, ((n << 1) ^ (n >> 63)) as u64);
+            }
+            SInstr::SLoad(x) => {
+                bytes.push(LOAD);
+                write_uleb(&mut bytes, x.len() as u64);
+                bytes.extend_from_slice(x.as_bytes());
+            }
+            SInstr::SPlus => bytes.push(PLUS),
+            SInstr::SMinus => bytes.push(MINUS),
+            SInstr::SMult => bytes.push(MULT),
+        }
+    }
+    bytes
+}
+
+/// The program `bytes` encodes. Any byte string is either one or gets an
+/// error; none makes this panic.
+pub fn decode(bytes: &[u8]) -> Result<Vec<SInstr>, DecodeError> {
+    let Some(rest) = bytes.strip_prefix(MAGIC) else {
+        return Err(DecodeError::BadMagic);
+    };
+    match rest.first() {
+        Some(&VERSION) => {}
+        Some(&v) => return Err(DecodeError::UnsupportedVersion(v)),
+        None => return Err(DecodeError::BadMagic),
+    }
+    let mut reader = Reader {
+        bytes,
+        pos: MAGIC.len() + 1,
+    };
+    let mut prog = Vec::new();
+    while reader.pos < bytes.len() {
+        let start = reader.pos;
+        let opcode = bytes[start];
+        reader.pos += 1;
+        prog.push(match opcode {
+            PUSH => {
+                let z = reader.uleb(start)?;
+                SInstr::SPush((z >> 1) as i64 ^ -((z & 1) as i64))
+            }
+            LOAD => {
+                let len = usize::try_from(reader.uleb(start)?)
+                    .map_err(|_| DecodeError::NumberTooLarge { pos: start })?;
+                let name_pos = reader.pos;
+                let end = name_pos
+                    .checked_add(len)
+                    .filter(|&end| end <= bytes.len())
+                    .ok_or(DecodeError::Truncated { pos: start })?;
+                reader.pos = end;
+                let name = std::str::from_utf8(&bytes[name_pos..end])
+                    .map_err(|_| DecodeError::BadName { pos: name_pos })?;
+                SInstr::SLoad(name.to_string())
+            }
+            PLUS => SInstr::SPlus,
+            MINUS => SInstr::SMinus,
+            MULT => SInstr::SMult,
+            _ => return Err(DecodeError::UnknownOpcode { opcode, pos: start }),
+        });
+    }
+    Ok(prog)
+}
+
+/// Load the program `bytes` encodes and run it with `s_execute`.
+pub fn s_execute_bytecode(
+    st: &State,
+    stack: Vec<i64>,
+    bytes: &[u8],
+) -> Result<Vec<i64>, BytecodeError> {
+    Ok(s_execute(st, stack, &decode(bytes)?)?)
+}
+
+fn write_uleb(bytes: &mut Vec<u8>, n: u64) {
+    let mut n = n;
+    while n >= 0x80 {
+        bytes.push(n as u8 | 0x80);
+        n >>= 7;
+    }
+    bytes.push(n as u8);
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl Reader<'_> {
+    /// An unsigned LEB128 number, in the instruction at `start`.
+    fn uleb(&mut self, start: usize) -> Result<u64, DecodeError> {
+        let mut n = 0u64;
+        for shift in (0..64).step_by(7) {
+            let Some(&byte) = self.bytes.get(self.pos) else {
+                return Err(DecodeError::Truncated { pos: start });
+            };
+            self.pos += 1;
+            let bits = u64::from(byte & 0x7f);
+            if bits << shift >> shift != bits {
+                return Err(DecodeError::NumberTooLarge { pos: start });
+            }
+            n |= bits << shift;
+            if byte & 0x80 == 0 {
+                return Ok(n);
+            }
+        }
+        Err(DecodeError::NumberTooLarge { pos: start })
+    }
+}
+
+#[cfg(feature = "serde")]
+mod serde_impls {
+    use std::fmt;
+
+    use serde::de::{EnumAccess, Error, VariantAccess, Visitor};
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    use super::SInstr;
+
+    const VARIANTS: &[&str] = &["SPush", "SLoad", "SPlus", "SMinus", "SMult"];
+
+    impl Serialize for SInstr {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            match self {
+                SInstr::SPush(n) => serializer.serialize_newtype_variant("SInstr", 0, "SPush", n),
+                SInstr::SLoad(x) => serializer.serialize_newtype_variant("SInstr", 1, "SLoad", x),
+                SInstr::SPlus => serializer.serialize_unit_variant("SInstr", 2, "SPlus"),
+                SInstr::SMinus => serializer.serialize_unit_variant("SInstr", 3, "SMinus"),
+                SInstr::SMult => serializer.serialize_unit_variant("SInstr", 4, "SMult"),
+            }
+        }
+    }
+
+    impl<'de> Deserialize<'de> for SInstr {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            struct SInstrVisitor;
+
+            impl<'de> Visitor<'de> for SInstrVisitor {
+                type Value = SInstr;
+
+                fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                    write!(f, "a stack machine instruction")
+                }
+
+                fn visit_enum<A: EnumAccess<'de>>(self, data: A) -> Result<SInstr, A::Error> {
+                    let (name, variant) = data.variant::<String>()?;
+                    match name.as_str() {
+                        "SPush" => variant.newtype_variant().map(SInstr::SPush),
+                        "SLoad" => variant.newtype_variant().map(SInstr::SLoad),
+                        "SPlus" => variant.unit_variant().map(|()| SInstr::SPlus),
+                        "SMinus" => variant.unit_variant().map(|()| SInstr::SMinus),
+                        "SMult" => variant.unit_variant().map(|()| SInstr::SMult),
+                        other => Err(A::Error::unknown_variant(other, VARIANTS)),
+                    }
+                }
+            }
+
+            deserializer.deserialize_enum("SInstr", VARIANTS, SInstrVisitor)
+        }
+    }
+
+    #[cfg(test)]
+    mod test_serde_sinstr {
+        use super::SInstr;
+
+        #[test]
+        fn test_json_round_trip() {
+            let prog = vec![
+                SInstr::SPush(-5),
+                SInstr::SLoad("X".to_string()),
+                SInstr::SMinus,
+            ];
+            let json = serde_json::to_string(&prog).unwrap();
+            assert_eq!(json, r#"[{"SPush":-5},{"SLoad":"X"},"SMinus"]"#);
+            let back: Vec<SInstr> = serde_json::from_str(&json).unwrap();
+            assert_eq!(back, prog);
+            assert!(serde_json::from_str::<SInstr>(r#""SDiv""#).is_err());
+        }
+    }
+}
+
+#[cfg(test)]
+mod test_imp_bytecode {
+    use super::*;
+    use crate::imp::{aeval, parse_aexp, s_compile};
+    use crate::rng::Rng;
+    use crate::state;
+
+    #[test]
+    fn test_round_trip() {
+        let prog = vec![
+            SInstr::SPush(0),
+            SInstr::SPush(-1),
+            SInstr::SPush(i64::MIN),
+            SInstr::SPush(i64::MAX),
+            SInstr::SLoad("X".to_string()),
+            SInstr::SLoad(String::new()),
+            SInstr::SLoad("é".repeat(100)),
+            SInstr::SPlus,
+            SInstr::SMinus,
+            SInstr::SMult,
+        ];
+        assert_eq!(decode(&encode(&prog)), Ok(prog));
+        assert_eq!(decode(&encode(&[])), Ok(vec![]));
+    }
+
+    #[test]
+    fn test_layout() {
+        let prog = [
+            SInstr::SPush(-2),
+            SInstr::SLoad("X".to_string()),
+            SInstr::SPlus,
+        ];
+        assert_eq!(encode(&prog), b"IMPS\x01\x00\x03\x01\x01X\x02");
+        // Zigzag and LEB128: 64 is 128, which takes a second byte.
+        assert_eq!(&encode(&[SInstr::SPush(64)])[5..], [0, 0x80, 0x01]);
+    }
+
+    #[test]
+    fn test_compiled_programs_run_from_bytes() {
+        let st = state! {"X" => 7, "Y" => -2};
+        for s in [
+            "X - 2 * Y",
+            "(X + Y) * (X - Y) - 3 * X * X",
+            "0 - 9223372036854775807",
+        ] {
+            let a = parse_aexp(s).unwrap();
+            let bytes = encode(&s_compile(&a).unwrap());
+            assert_eq!(
+                s_execute_bytecode(&st, vec![], &bytes),
+                Ok(vec![aeval(&st, &a)])
+            );
+        }
+        assert_eq!(
+            s_execute_bytecode(&st, vec![], b"IMPS\x01\x02"),
+            Err(BytecodeError::Underflow(StackUnderflow { pc: 0 }))
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(decode(b""), Err(DecodeError::BadMagic));
+        assert_eq!(decode(b"IMPS"), Err(DecodeError::BadMagic));
+        assert_eq!(decode(b"IMPS\x02"), Err(DecodeError::UnsupportedVersion(2)));
+        assert_eq!(
+            decode(b"IMPS\x01\x02\x09"),
+            Err(DecodeError::UnknownOpcode { opcode: 9, pos: 6 })
+        );
+        assert_eq!(
+            decode(b"IMPS\x01\x00\x80"),
+            Err(DecodeError::Truncated { pos: 5 })
+        );
+        assert_eq!(
+            decode(b"IMPS\x01\x01\x05XY"),
+            Err(DecodeError::Truncated { pos: 5 })
+        );
+        assert_eq!(
+            decode(b"IMPS\x01\x00\xff\xff\xff\xff\xff\xff\xff\xff\xff\x7f"),
+            Err(DecodeError::NumberTooLarge { pos: 5 })
+        );
+        assert_eq!(
+            decode(b"IMPS\x01\x02\x01\x01\xff"),
+            Err(DecodeError::BadName { pos: 8 })
+        );
+        assert_eq!(
+            DecodeError::Truncated { pos: 5 }.to_string(),
+            "bytecode ends inside the instruction at byte 5"
+        );
+    }
+
+    #[test]
+    fn test_random_bytes_never_panic() {
+        let mut rng = Rng::new(17);
+        for _ in 0..2_000 {
+            let len = rng.below(24) as usize;
+            let mut bytes = encode(&[]);
+            bytes.extend((0..len).map(|_| rng.below(256) as u8));
+            if let Ok(prog) = decode(&bytes) {
+                assert_eq!(decode(&encode(&prog)), Ok(prog));
+            }
+        }
+    }
+}
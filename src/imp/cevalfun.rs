@@ -0,0 +1,175 @@
+//! The step-indexed evaluator of the ImpCEvalFun chapter. Where
+//! `ceval_fuel` counts loop iterations in total, `ceval_step` bounds the
+//! depth of the evaluation, exactly as the book's `ceval_step st c i`
+//! does: every command costs one unit, and each of its subcommands, as
+//! well as each further iteration of a loop, gets what is left.
+
+use super::desugar::lower_for;
+use super::{aeval, beval, Bexp, Com, Signal, Unsupported};
+use crate::map::tm_update;
+use crate::state::State;
+
+/// Evaluate `c` from `st` with step index `fuel`, or `None` if the index
+/// runs out first. Like `ceval`, a `break` or `continue` outside any loop
+/// ends the program, and so does an exception nothing catches, and
+/// `havoc` picks `0`. Procedure calls need the procedures `Program::run`
+/// keeps, and array writes the arrays `ceval_arrays` keeps, so reaching
+/// either is an error.
+pub fn ceval_step(st: State, c: &Com, fuel: u64) -> Result<Option<State>, Unsupported> {
+    match exec(st, c, fuel) {
+        Ok((st, _)) => Ok(Some(st)),
+        Err(Stop::OutOfFuel) => Ok(None),
+        Err(Stop::Unsupported(e)) => Err(e),
+    }
+}
+
+/// `ceval_step` with step index `initial`, then twice that, and so on
+/// until the run finishes or the index would exceed `cap`. Returns the
+/// final state and the index that sufficed.
+pub fn ceval_step_adaptive(
+    st: State,
+    c: &Com,
+    initial: u64,
+    cap: u64,
+) -> Result<Option<(State, u64)>, Unsupported> {
+    let mut fuel = initial.max(1);
+    while fuel <= cap {
+        if let Some(st) = ceval_step(st.clone(), c, fuel)? {
+            return Ok(Some((st, fuel)));
+        }
+        let Some(more) = fuel.checked_mul(2) else {
+            break;
+        };
+        fuel = more;
+    }
+    Ok(None)
+}
+
+/// Why `exec` stopped short.
+enum Stop {
+    OutOfFuel,
+    Unsupported(Unsupported),
+}
+
+fn exec(st: State, c: &Com, fuel: u64) -> Result<(State, Signal), Stop> {
+    let fuel = fuel.checked_sub(1).ok_or(Stop::OutOfFuel)?;
+    match c {
+        Com::CSkip => Ok((st, Signal::Normal)),
+        Com::CBreak => Ok((st, Signal::Break)),
+        Com::CContinue => Ok((st, Signal::Continue)),
+        Com::CAsgn(x, a) => {
+            let n = aeval(&st, a);
+            Ok((tm_update(st, x.clone(), n), Signal::Normal))
+        }
+        Com::CHavoc(x) => Ok((tm_update(st, x.clone(), 0), Signal::Normal)),
+        Com::CPrint(_) => Ok((st, Signal::Normal)),
+        Com::CSeq(c1, c2) => match exec(st, c1, fuel)? {
+            (st, Signal::Normal) => exec(st, c2, fuel),
+            interrupted => Ok(interrupted),
+        },
+        Com::CIf(b, c1, c2) => {
+            let branch = if beval(&st, b) { c1 } else { c2 };
+            exec(st, branch, fuel)
+        }
+        Com::CWhile(b, body) => run_loop(st, b, body, fuel),
+        Com::CFor(..) => exec(st, &lower_for(c), fuel),
+        // Like `ceval`, the left branch and then the right.
+        Com::CPar(c1, c2) => match exec(st, c1, fuel)? {
+            (st, Signal::Throw(code)) => Ok((st, Signal::Throw(code))),
+            (st, _) => match exec(st, c2, fuel)? {
+                (st, Signal::Throw(code)) => Ok((st, Signal::Throw(code))),
+                (st, _) => Ok((st, Signal::Normal)),
+            },
+        },
+        Com::CThrow(a) => Ok((st.clone(), Signal::Throw(aeval(&st, a)))),
+        Com::CTry(body, x, handler) => match exec(st, body, fuel)? {
+            (st, Signal::Throw(code)) => exec(tm_update(st, x.clone(), code), handler, fuel),
+            finished => Ok(finished),
+        },
+        Com::CCall(..) => Err(Stop::Unsupported(Unsupported("procedure calls"))),
+        Com::CArrAsgn(..) => Err(Stop::Unsupported(Unsupported("arrays"))),
+    }
+}
+
+/// The book's `while` case, `ceval_step st' (while b do c end) i'` after
+/// each iteration, unrolled into a loop so that long runs don't recurse.
+fn run_loop(st: State, b: &Bexp, body: &Com, fuel: u64) -> Result<(State, Signal), Stop> {
+    let (mut st, mut fuel) = (st, fuel);
+    while beval(&st, b) {
+        let (st1, signal) = exec(st, body, fuel)?;
+        st = st1;
+        match signal {
+            Signal::Break => break,
+            Signal::Throw(_) => return Ok((st, signal)),
+            _ => {}
+        }
+        fuel = fuel.checked_sub(1).ok_or(Stop::OutOfFuel)?;
+    }
+    Ok((st, Signal::Normal))
+}
+
+#[cfg(test)]
+mod test_imp_cevalfun {
+    use super::*;
+    use crate::imp::{ceval, parse_com};
+    use crate::state;
+    use crate::state::{empty_state, lookup};
+
+    const PROGRAMS: [&str; 5] = [
+        "Z := X; Y := 1; while ~(Z = 0) do Y := Y * Z; Z := Z - 1 end",
+        "Y := 0; while Y <= X - 1 do Y := Y + 1; Z := Z + Y end",
+        "if X <= 2 then Y := 1 else Y := 2; Z := 3 end",
+        "while true do X := X + 1; if 5 <= X then break else continue end end",
+        "for I := 0; I <= X; I := I + 1 do if I = 2 then continue else Y := Y + I end end",
+    ];
+
+    #[test]
+    fn test_pup_to_n() {
+        // Two iterations nested under two sequencing steps: the loop's body
+        // needs index 2 on the second iteration, which is index 6 at the
+        // top.
+        let c = parse_com("X := 2; Y := 0; while ~(X = 0) do Y := Y + X; X := X - 1 end").unwrap();
+        assert!(ceval_step(empty_state(), &c, 5).unwrap().is_none());
+        let st = ceval_step(empty_state(), &c, 6).unwrap().unwrap();
+        assert_eq!((lookup(&st, "X"), lookup(&st, "Y")), (0, 3));
+    }
+
+    #[test]
+    fn test_agrees_with_ceval() {
+        for p in PROGRAMS {
+            let c = parse_com(p).unwrap();
+            for x in 0..6 {
+                let st = state! {"X" => x};
+                let expected = ceval(st.clone(), &c).unwrap();
+                let (found, _) = ceval_step_adaptive(st, &c, 1, 1 << 20).unwrap().unwrap();
+                for v in c.vars() {
+                    assert_eq!(lookup(&found, &v), lookup(&expected, &v), "{} on {}", v, p);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_more_fuel_is_harmless() {
+        // ceval_step_more: once an index suffices, every larger one gives
+        // the same result.
+        let c = parse_com(PROGRAMS[0]).unwrap();
+        let st = state! {"X" => 4};
+        let (first, enough) = ceval_step_adaptive(st.clone(), &c, 1, 1 << 20)
+            .unwrap()
+            .unwrap();
+        assert!(ceval_step(st.clone(), &c, enough / 2).unwrap().is_none());
+        for fuel in [enough, enough + 1, enough * 10] {
+            let st = ceval_step(st.clone(), &c, fuel).unwrap().unwrap();
+            assert_eq!(lookup(&st, "Y"), lookup(&first, "Y"));
+        }
+    }
+
+    #[test]
+    fn test_adaptive_gives_up_at_cap() {
+        let c = parse_com("while true do skip end").unwrap();
+        assert!(ceval_step_adaptive(empty_state(), &c, 1, 1 << 12)
+            .unwrap()
+            .is_none());
+    }
+}
@@ -0,0 +1,314 @@
+//! Cost semantics: what a run costs under a `CostModel`, which prices each
+//! rule of the small-step semantics. The cost of a run is the sum of the
+//! prices of the steps it takes, so under the default model, where every
+//! rule costs `1`, it is the number of small steps.
+//!
+//! `normalize_cost` counts by stepping. `ceval_cost` gets the same total
+//! from the big-step evaluator, charging each construct for the small
+//! steps it would take: a `while` test, for instance, costs `while_` for
+//! the unrolling and `if_` for the `if` it unrolls to.
+
+use super::smallstep::{done, step, thrown, Config, Running};
+use super::{without_procs, Aexp, Bexp, Com, EvalError, Machine, Unsupported};
+use crate::map::pm_empty;
+use crate::state::State;
+
+/// The price of each kind of small step.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CostModel {
+    /// Reading a variable.
+    pub var: u64,
+    /// Applying an operator, including `~`, `&&`, the comparisons and
+    /// indexing.
+    pub op: u64,
+    /// Finishing an assignment, `x := n` to `skip`.
+    pub asgn: u64,
+    pub havoc: u64,
+    pub print: u64,
+    /// Leaving the first command of a sequence, `skip; c` to `c` or
+    /// `break; c` to `break`, and ending a `par` once both branches have or
+    /// one has thrown.
+    pub seq: u64,
+    /// Taking a branch once the condition is known.
+    pub if_: u64,
+    /// Unrolling a `while` into an `if`, once per test of its condition.
+    pub while_: u64,
+    /// Lowering a `for` loop to a `while` loop.
+    pub for_: u64,
+    /// Finishing an iteration of a loop body.
+    pub iter: u64,
+    /// Leaving a `try`, by finishing its body or catching an exception.
+    pub try_: u64,
+    /// Calling a procedure and writing to an array. Small steps support
+    /// neither, so these only show up in `ceval_cost`.
+    pub call: u64,
+    pub arr_asgn: u64,
+}
+
+impl CostModel {
+    /// Every step costs `n`.
+    pub const fn uniform(n: u64) -> Self {
+        CostModel {
+            var: n,
+            op: n,
+            asgn: n,
+            havoc: n,
+            print: n,
+            seq: n,
+            if_: n,
+            while_: n,
+            for_: n,
+            iter: n,
+            try_: n,
+            call: n,
+            arr_asgn: n,
+        }
+    }
+
+    /// What evaluating `a` costs.
+    pub fn aexp(&self, a: &Aexp) -> u64 {
+        match a {
+            Aexp::ANum(_) => 0,
+            Aexp::AId(_) => self.var,
+            Aexp::APlus(a1, a2) | Aexp::AMinus(a1, a2) | Aexp::AMult(a1, a2) => {
+                self.op + self.aexp(a1) + self.aexp(a2)
+            }
+            Aexp::AIndex(_, i) => self.op + self.aexp(i),
+        }
+    }
+
+    /// What evaluating `b` costs, where `holds` decides the left operand
+    /// of each `&&`: its right operand is only evaluated if that is true.
+    pub(super) fn bexp<E>(
+        &self,
+        b: &Bexp,
+        holds: &impl Fn(&Bexp) -> Result<bool, E>,
+    ) -> Result<u64, E> {
+        Ok(match b {
+            Bexp::BTrue | Bexp::BFalse => 0,
+            Bexp::BEq(a1, a2) | Bexp::BLe(a1, a2) => self.op + self.aexp(a1) + self.aexp(a2),
+            Bexp::BNot(b1) => self.op + self.bexp(b1, holds)?,
+            Bexp::BAnd(b1, b2) => {
+                let right = if holds(b1)? { self.bexp(b2, holds)? } else { 0 };
+                self.op + self.bexp(b1, holds)? + right
+            }
+        })
+    }
+
+    /// The price of the step `step` takes from `r`, which must be able to
+    /// step.
+    fn step(&self, r: &Running) -> u64 {
+        match r {
+            Running::Com(c) => self.step_com(c),
+            Running::Seq(r1, _) if done(r1) => self.seq,
+            Running::Seq(r1, _) => self.step(r1),
+            Running::If(Bexp::BTrue | Bexp::BFalse, ..) => self.if_,
+            Running::If(b, ..) => self.bstep(b),
+            Running::Loop(rest, ..) if done(rest) => self.iter,
+            Running::Loop(rest, ..) => self.step(rest),
+            Running::Par(r1, r2) if thrown(r1).is_some() || thrown(r2).is_some() => self.seq,
+            Running::Par(r1, r2) => match (done(r1), done(r2)) {
+                (true, true) => self.seq,
+                (false, _) => self.step(r1),
+                (true, false) => self.step(r2),
+            },
+            Running::Try(body, ..) if done(body) => self.try_,
+            Running::Try(body, ..) => self.step(body),
+        }
+    }
+
+    fn step_com(&self, c: &Com) -> u64 {
+        match c {
+            Com::CAsgn(_, Aexp::ANum(_)) => self.asgn,
+            Com::CAsgn(_, a) => self.astep(a),
+            Com::CHavoc(_) => self.havoc,
+            Com::CPrint(Aexp::ANum(_)) => self.print,
+            Com::CPrint(a) => self.astep(a),
+            Com::CIf(Bexp::BTrue | Bexp::BFalse, ..) => self.if_,
+            Com::CIf(b, ..) => self.bstep(b),
+            Com::CWhile(..) => self.while_,
+            Com::CFor(..) => self.for_,
+            Com::CThrow(a) => self.astep(a),
+            Com::CSeq(..) | Com::CPar(..) | Com::CTry(..) => self.step(&Running::open(c)),
+            Com::CSkip | Com::CBreak | Com::CContinue | Com::CCall(..) | Com::CArrAsgn(..) => {
+                unreachable!("CostModel::step called on a command that doesn't step")
+            }
+        }
+    }
+
+    fn astep(&self, a: &Aexp) -> u64 {
+        match a {
+            Aexp::ANum(_) => unreachable!("a number doesn't step"),
+            Aexp::AId(_) => self.var,
+            Aexp::APlus(a1, a2) | Aexp::AMinus(a1, a2) | Aexp::AMult(a1, a2) => {
+                match (&**a1, &**a2) {
+                    (Aexp::ANum(_), Aexp::ANum(_)) => self.op,
+                    (Aexp::ANum(_), a2) => self.astep(a2),
+                    (a1, _) => self.astep(a1),
+                }
+            }
+            Aexp::AIndex(_, i) => match &**i {
+                Aexp::ANum(_) => self.op,
+                i => self.astep(i),
+            },
+        }
+    }
+
+    fn bstep(&self, b: &Bexp) -> u64 {
+        match b {
+            Bexp::BTrue | Bexp::BFalse => unreachable!("a boolean doesn't step"),
+            Bexp::BEq(a1, a2) | Bexp::BLe(a1, a2) => match (&**a1, &**a2) {
+                (Aexp::ANum(_), Aexp::ANum(_)) => self.op,
+                (Aexp::ANum(_), a2) => self.astep(a2),
+                (a1, _) => self.astep(a1),
+            },
+            Bexp::BNot(b1) | Bexp::BAnd(b1, _) => match &**b1 {
+                Bexp::BTrue | Bexp::BFalse => self.op,
+                b1 => self.bstep(b1),
+            },
+        }
+    }
+}
+
+impl Default for CostModel {
+    fn default() -> Self {
+        CostModel::uniform(1)
+    }
+}
+
+/// `ceval_fuel` that also returns what the run cost under `model`, and
+/// like it fails on procedure calls.
+pub fn ceval_cost(
+    st: State,
+    c: &Com,
+    fuel: u64,
+    model: &CostModel,
+) -> Result<Option<(State, u64)>, Unsupported> {
+    let mut machine = Machine {
+        model: Some(*model),
+        ..Machine::new(Some(fuel), pm_empty())
+    };
+    match machine.exec(st, c) {
+        Err(EvalError::OutOfFuel) => Ok(None),
+        result => Ok(Some((without_procs(result)?.0, machine.cost))),
+    }
+}
+
+/// `normalize` that also returns what the steps cost under `model`.
+pub fn normalize_cost(
+    st: State,
+    c: &Com,
+    max_steps: usize,
+    model: &CostModel,
+) -> Result<Option<(State, u64)>, Unsupported> {
+    let (mut st, mut c) = (st, Config::from(c.clone()));
+    let mut cost = 0;
+    for _ in 0..max_steps {
+        match step(&st, &c)? {
+            Some((st1, c1)) => {
+                cost += model.step(&c.0);
+                st = st1;
+                c = c1;
+            }
+            None => return Ok(Some((st, cost))),
+        }
+    }
+    Ok(done(&c.0).then_some((st, cost)))
+}
+
+#[cfg(test)]
+mod test_imp_cost {
+    use super::*;
+    use crate::imp::{eliminate_dead_code, multistep, parse_com, Optimize0Plus, Transform};
+    use crate::state;
+    use crate::state::{empty_state, lookup};
+
+    const PROGRAMS: [&str; 8] = [
+        "Z := X; Y := 1; while ~(Z = 0) do Y := Y * Z; Z := Z - 1 end",
+        "if X <= 2 && ~(X = 1) then Y := X + 1 else (Y := 1; Y := Y * 2); Z := 3 end",
+        "while true do X := X + 1; if 5 <= X then break else skip end; Y := Y + X end",
+        "while X <= 6 do X := X + 1; if X = 4 then continue else Y := Y + X end end",
+        "X := 1; break; X := 2",
+        "for I := 0; I <= X; I := I + 1 do if I = 2 then continue else Y := Y + I end end",
+        "for (I := 9; J := 0); 0 <= I; (I := I - 1; if I = 3 then break else skip end) do \
+         J := J + I; print J; havoc K end",
+        "while Y <= 3 do Y := Y + 1; for J := 0; true; J := J + 1 do if 2 <= J then break else continue end end end",
+    ];
+
+    #[test]
+    fn test_default_counts_steps() {
+        let c = parse_com(PROGRAMS[0]).unwrap();
+        let st = state! {"X" => 3};
+        let (_, _, steps) = multistep(st.clone(), c.clone().into(), 10_000).unwrap();
+        let (_, cost) = normalize_cost(st, &c, 10_000, &CostModel::default())
+            .unwrap()
+            .unwrap();
+        assert_eq!(cost, steps as u64);
+        // X := 2 + 3 takes two steps: the addition and the assignment.
+        let c = parse_com("X := 2 + 3").unwrap();
+        let (_, cost) = ceval_cost(empty_state(), &c, 10, &CostModel::default())
+            .unwrap()
+            .unwrap();
+        assert_eq!(cost, 2);
+    }
+
+    #[test]
+    fn test_big_step_agrees_with_small_step() {
+        // Distinct powers of two, so that each construct's count can be
+        // read off the total and a mistake in any one of them shows.
+        let mut model = CostModel::uniform(0);
+        for (i, price) in [
+            &mut model.var,
+            &mut model.op,
+            &mut model.asgn,
+            &mut model.havoc,
+            &mut model.print,
+            &mut model.seq,
+            &mut model.if_,
+            &mut model.while_,
+            &mut model.for_,
+            &mut model.iter,
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            *price = 1 << (6 * i);
+        }
+        for p in PROGRAMS {
+            let c = parse_com(p).unwrap();
+            for x in 0..5 {
+                let st = state! {"X" => x, "Z" => 10};
+                let (big, big_cost) = ceval_cost(st.clone(), &c, 1_000, &model).unwrap().unwrap();
+                let (small, small_cost) = normalize_cost(st, &c, 100_000, &model).unwrap().unwrap();
+                assert_eq!(big_cost, small_cost, "{} from X = {}", p, x);
+                for v in c.vars() {
+                    assert_eq!(lookup(&big, &v), lookup(&small, &v), "{} on {}", v, p);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_compare_variants() {
+        let model = CostModel::default();
+        let cost = |c: &Com| {
+            ceval_cost(state! {"X" => 4}, c, 100, &model)
+                .unwrap()
+                .unwrap()
+                .1
+        };
+        let c = parse_com("T := 0 + X; T := 1; Y := 0 + T * X").unwrap();
+        let optimized = Optimize0Plus.transform_com(&c);
+        let trimmed = eliminate_dead_code(&optimized);
+        assert!(cost(&optimized) < cost(&c));
+        assert!(cost(&trimmed) < cost(&optimized));
+        assert!(ceval_cost(
+            empty_state(),
+            &parse_com("while true do skip end").unwrap(),
+            10,
+            &model
+        )
+        .unwrap()
+        .is_none());
+    }
+}
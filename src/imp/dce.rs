@@ -0,0 +1,296 @@
+//! Dead-code elimination. Walking a command backwards from its end, track
+//! the variables that are still going to be read (the live ones); an
+//! assignment or `havoc` of a variable that isn't live has no effect
+//! anyone can see, and is dropped. Conditions that fold to constants
+//! (without knowing any variable, see `pe_bexp`) have their dead branch
+//! pruned, and a `while` whose condition is `false` disappears.
+//!
+//! Expressions are taken to be pure, so a dead `X := A[i]` goes even if
+//! the read would fault under `OutOfBounds::Fault`. Procedure calls and
+//! array writes are always kept, since they can fault, diverge or write
+//! arrays, and so is `print`. So is all of a `par`. A `throw` jumps to its
+//! handler, so what is live there is live at the `throw`.
+
+use std::collections::BTreeSet;
+
+use super::pe::{pe_bexp, seq, PeState};
+use super::{Bexp, Com};
+
+pub(super) type Live = BTreeSet<String>;
+
+/// `c` without the code that can't affect its final state: every variable
+/// it mentions is observed once it ends.
+pub fn eliminate_dead_code(c: &Com) -> Com {
+    eliminate_dead_code_for(c, &c.vars())
+}
+
+/// `c` without the code that can't affect the values of `outputs` when it
+/// ends. Other variables may end up different.
+pub fn eliminate_dead_code_for(c: &Com, outputs: &BTreeSet<String>) -> Com {
+    let exit = Exits {
+        brk: outputs,
+        cont: outputs,
+        throw: outputs,
+    };
+    dce(c, outputs, exit).0
+}
+
+/// What is live where a `break`, a `continue` and a `throw` jump to.
+/// Outside any loop and `try` they all end the program.
+#[derive(Clone, Copy)]
+pub(super) struct Exits<'a> {
+    pub(super) brk: &'a Live,
+    pub(super) cont: &'a Live,
+    pub(super) throw: &'a Live,
+}
+
+/// `c` with its dead code removed, given what is live after it, and what
+/// is live before it.
+fn dce(c: &Com, out: &Live, exits: Exits) -> (Com, Live) {
+    match c {
+        Com::CSkip => (Com::CSkip, out.clone()),
+        Com::CBreak => (Com::CBreak, exits.brk.clone()),
+        Com::CContinue => (Com::CContinue, exits.cont.clone()),
+        Com::CAsgn(x, a) if out.contains(x) => {
+            let mut live = out.clone();
+            live.remove(x);
+            a.collect_vars(&mut live);
+            (c.clone(), live)
+        }
+        Com::CHavoc(x) if out.contains(x) => {
+            let mut live = out.clone();
+            live.remove(x);
+            (c.clone(), live)
+        }
+        Com::CAsgn(..) | Com::CHavoc(_) => (Com::CSkip, out.clone()),
+        Com::CCall(x, _, args) => {
+            // The procedure may throw instead of returning.
+            let mut live = out.clone();
+            live.remove(x);
+            live.extend(exits.throw.iter().cloned());
+            for a in args {
+                a.collect_vars(&mut live);
+            }
+            (c.clone(), live)
+        }
+        Com::CArrAsgn(_, i, a) => {
+            let mut live = out.clone();
+            i.collect_vars(&mut live);
+            a.collect_vars(&mut live);
+            (c.clone(), live)
+        }
+        Com::CPrint(a) => {
+            let mut live = out.clone();
+            a.collect_vars(&mut live);
+            (c.clone(), live)
+        }
+        Com::CThrow(a) => {
+            let mut live = exits.throw.clone();
+            a.collect_vars(&mut live);
+            (c.clone(), live)
+        }
+        Com::CSeq(c1, c2) => {
+            let (c2, live) = dce(c2, out, exits);
+            let (c1, live) = dce(c1, &live, exits);
+            (seq(c1, c2), live)
+        }
+        Com::CIf(b, c1, c2) => match pe_bexp(&PeState::new(), b) {
+            Bexp::BTrue => dce(c1, out, exits),
+            Bexp::BFalse => dce(c2, out, exits),
+            b => {
+                let (c1, mut live) = dce(c1, out, exits);
+                let (c2, live2) = dce(c2, out, exits);
+                live.extend(live2);
+                if c1 == Com::CSkip && c2 == Com::CSkip {
+                    (Com::CSkip, out.clone())
+                } else {
+                    b.collect_vars(&mut live);
+                    (Com::if_(b, c1, c2), live)
+                }
+            }
+        },
+        Com::CWhile(b, body) => match pe_bexp(&PeState::new(), b) {
+            Bexp::BFalse => (Com::CSkip, out.clone()),
+            b => {
+                let (body, head) = dce_loop(&b, body, None, out, exits.throw);
+                (Com::while_(b, body), head)
+            }
+        },
+        Com::CFor(init, b, update, body) => match pe_bexp(&PeState::new(), b) {
+            Bexp::BFalse => dce(init, out, exits),
+            b => {
+                let (body, head) = dce_loop(&b, body, Some(update), out, exits.throw);
+                let update = dce(update, &head, loop_exits(out, &head, exits.throw)).0;
+                let (init, live) = dce(init, &head, exits);
+                (Com::for_(init, b, update, body), live)
+            }
+        },
+        // With the branches interleaved, no write in one is sure to come
+        // before the other reads it, so both are kept whole and everything
+        // they mention is live, as is what a `throw` from either needs.
+        Com::CPar(..) => {
+            let mut live = out.clone();
+            live.extend(c.vars());
+            live.extend(exits.throw.iter().cloned());
+            (c.clone(), live)
+        }
+        // The handler starts with `x` set to what was thrown, so what it
+        // needs except `x` is live at each `throw` in the body.
+        Com::CTry(body, x, handler) => {
+            let (handler, mut caught) = dce(handler, out, exits);
+            caught.remove(x);
+            let (body, live) = dce(
+                body,
+                out,
+                Exits {
+                    throw: &caught,
+                    ..exits
+                },
+            );
+            if body == Com::CSkip {
+                (Com::CSkip, live)
+            } else {
+                (Com::try_(body, x, handler), live)
+            }
+        }
+    }
+}
+
+/// The exits of a loop's body, with `out` live after the loop, `head` at
+/// its test, and `throw` where the loop's own `throw`s land.
+pub(super) fn loop_exits<'a>(out: &'a Live, head: &'a Live, throw: &'a Live) -> Exits<'a> {
+    Exits {
+        brk: out,
+        cont: head,
+        throw,
+    }
+}
+
+/// The body of a loop testing `b`, with `update` run after each iteration
+/// for a `for` loop, and what is live at the test. That is the least set
+/// containing what the test reads, what is live after the loop, and what
+/// the body needs to leave it live at the test again; it is found by
+/// iterating from the first two, which ends because each round can only
+/// add variables the loop mentions.
+fn dce_loop(b: &Bexp, body: &Com, update: Option<&Com>, out: &Live, throw: &Live) -> (Com, Live) {
+    let mut head = out.clone();
+    b.collect_vars(&mut head);
+    loop {
+        // A `for` loop's `continue` runs the update before the test.
+        let after_body = match update {
+            Some(update) => dce(update, &head, loop_exits(out, &head, throw)).1,
+            None => head.clone(),
+        };
+        let (body, live) = dce(body, &after_body, loop_exits(out, &after_body, throw));
+        let mut next = head.clone();
+        next.extend(live);
+        if next == head {
+            return (body, head);
+        }
+        head = next;
+    }
+}
+
+#[cfg(test)]
+mod test_imp_dce {
+    use super::*;
+    use crate::imp::{ceval_fuel, parse_com, random_state};
+    use crate::rng::Rng;
+
+    fn outputs(vars: &[&str]) -> BTreeSet<String> {
+        vars.iter().map(|x| x.to_string()).collect()
+    }
+
+    fn dce_str(p: &str, out: &[&str]) -> Com {
+        eliminate_dead_code_for(&parse_com(p).unwrap(), &outputs(out))
+    }
+
+    #[test]
+    fn test_overwritten_assignment() {
+        assert_eq!(
+            eliminate_dead_code(&parse_com("X := 1; Y := 2; X := Y + 3").unwrap()),
+            parse_com("Y := 2; X := Y + 3").unwrap()
+        );
+        assert_eq!(
+            dce_str("T := X * 2; Y := T + 1; Z := T", &["Y"]),
+            parse_com("T := X * 2; Y := T + 1").unwrap()
+        );
+    }
+
+    #[test]
+    fn test_constant_branches() {
+        assert_eq!(
+            dce_str("if 1 <= 2 && ~false then Y := 1 else Y := 2 end", &["Y"]),
+            parse_com("Y := 1").unwrap()
+        );
+        assert_eq!(
+            dce_str("while 3 = 4 do X := X + 1 end; Y := X", &["Y"]),
+            parse_com("Y := X").unwrap()
+        );
+        // Both branches dead: the test goes with them.
+        assert_eq!(
+            dce_str("if X <= 0 then T := 1 else T := 2 end; Y := 0", &["Y"]),
+            parse_com("Y := 0").unwrap()
+        );
+    }
+
+    #[test]
+    fn test_loops_keep_what_later_iterations_read() {
+        // T is read by the next iteration's test, S only after the loop, and
+        // U never.
+        let c = "T := 3; while 1 <= T do S := S + T; U := T; T := T - 1 end";
+        assert_eq!(
+            dce_str(c, &["S"]),
+            parse_com("T := 3; while 1 <= T do S := S + T; T := T - 1 end").unwrap()
+        );
+        assert_eq!(
+            dce_str(c, &["U"]),
+            parse_com("T := 3; while 1 <= T do U := T; T := T - 1 end").unwrap()
+        );
+        // What a `continue` skips to is live at the `continue`, through the
+        // for loop's update.
+        assert_eq!(
+            dce_str(
+                "for I := 0; I <= 4; I := I + K do K := 1; if I = 2 then continue else S := S + I end end",
+                &["S"]
+            ),
+            parse_com(
+                "for I := 0; I <= 4; I := I + K do K := 1; if I = 2 then continue else S := S + I end end"
+            )
+            .unwrap()
+        );
+    }
+
+    #[test]
+    fn test_preserves_outputs() {
+        let programs = [
+            "X := 1; Y := 2; X := Y + 3; Z := X * W",
+            "T := X * 2; Y := T + 1; Z := T; havoc T",
+            "if W <= 0 then T := 1; Y := 5 else T := 2 end; Y := Y + T",
+            "T := 3; while 1 <= T do S := S + T; U := T; T := T - 1 end",
+            "Y := 1; while Y <= W do Y := Y * 2; U := Y; if X = 3 then break else X := 0 end end",
+            "for I := 0; I <= W; I := I + 1 do T := I; if I = X then continue else S := S + T end; U := S end",
+            "X := 7; if W = 0 then break else skip end; X := 8; Y := X",
+        ];
+        let mut rng = Rng::new(5);
+        let vars = ["W", "X", "Y", "Z", "S", "T", "U", "I"];
+        let states: Vec<_> = (0..50).map(|_| random_state(&mut rng, &vars)).collect();
+        for p in programs {
+            let c = parse_com(p).unwrap();
+            for out in [&vars[..], &["Y"], &["S", "U"], &["X", "Z"]] {
+                let reduced = dce_str(p, out);
+                for st in &states {
+                    let expected = ceval_fuel(st.clone(), &c, 1_000).unwrap();
+                    let found = ceval_fuel(st.clone(), &reduced, 1_000).unwrap();
+                    assert_eq!(found.is_some(), expected.is_some(), "{}", p);
+                    if let (Some(found), Some(expected)) = (found, expected) {
+                        for x in out {
+                            let x = x.to_string();
+                            assert_eq!(found(&x), expected(&x), "{} after {}", x, p);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
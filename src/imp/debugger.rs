@@ -0,0 +1,361 @@
+//! A stepping debugger over the small-step semantics. It runs a program one
+//! step at a time, can stop at a line of the program's listing or when a
+//! variable changes, and can step backwards: the state lives in a
+//! `VersionedMap`, so undoing a step is a rollback to the version before
+//! it.
+//!
+//! Lines are those of the block layout, `{:#}`, numbered from `1`. The
+//! small-step semantics rewrites the program as it runs, so the debugger
+//! finds its place by comparing the command about to run with the
+//! commands of the listing; a breakpoint on a line also stops at any
+//! identical command elsewhere.
+
+use super::smallstep::{done, done_com, step_output, Config, Running};
+use super::{Com, Unsupported};
+use crate::map::VersionedMap;
+use crate::state::{empty_state, State, StateExt};
+
+/// Where `run_to_breakpoint` should stop.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Breakpoint {
+    /// When the command on this line of the listing is about to run.
+    Line(usize),
+    /// Right after a step that changes this variable.
+    Watch(String),
+}
+
+/// Why `run_to_breakpoint` stopped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stop {
+    Breakpoint,
+    /// The program ran to the end.
+    Finished,
+    /// The step budget ran out first.
+    OutOfSteps,
+}
+
+/// A step taken, with what is needed to take it back.
+struct Past {
+    com: Config,
+    version: usize,
+    printed: bool,
+}
+
+pub struct Debugger {
+    /// The program as given, and for each line of its listing, the command
+    /// that starts there, if any.
+    program: Com,
+    lines: Vec<Option<Com>>,
+    /// The variables the program mentions, which are all a step can change.
+    vars: Vec<String>,
+    com: Config,
+    st: VersionedMap<str, i64>,
+    past: Vec<Past>,
+    output: Vec<i64>,
+}
+
+impl Debugger {
+    /// A debugger about to run `c` from the empty state.
+    ///
+    pub fn new(c: Com) -> Self {
+        Debugger::with_state(c, empty_state())
+    }
+
+    /// A debugger about to run `c` from `st`, which may be what is left of
+    /// a run out of gas.
+    pub fn with_state(c: impl Into<Config>, st: State) -> Self {
+        let com = c.into();
+        let program = com.0.to_com();
+        let mut lines = Vec::new();
+        collect_lines(&program, &mut lines);
+        Debugger {
+            vars: program.vars().into_iter().collect(),
+            lines,
+            com,
+            program,
+            st: VersionedMap::from_map(st),
+            past: Vec::new(),
+            output: Vec::new(),
+        }
+    }
+
+    /// Take one step. Returns `false`, and does nothing, if the program
+    /// has finished, and an error if the next step is one `step` can't
+    /// take.
+    pub fn step(&mut self) -> Result<bool, Unsupported> {
+        let Some((st, com, printed)) = step_output(&self.st.current(), &self.com)? else {
+            return Ok(false);
+        };
+        let version = self.st.snapshot();
+        for x in &self.vars {
+            let n = st(x);
+            if self.st.apply(x) != n {
+                self.st.update(x.clone(), n);
+            }
+        }
+        self.output.extend(printed);
+        self.past.push(Past {
+            com: std::mem::replace(&mut self.com, com),
+            version,
+            printed: printed.is_some(),
+        });
+        Ok(true)
+    }
+
+    /// Take the latest step back. Returns `false` at the start.
+    pub fn step_back(&mut self) -> bool {
+        let Some(past) = self.past.pop() else {
+            return false;
+        };
+        self.st.rollback(past.version);
+        self.com = past.com;
+        if past.printed {
+            self.output.pop();
+        }
+        true
+    }
+
+    /// Step until `bp` is hit, the program finishes, or `max_steps` steps
+    /// have been taken. At least one step is taken, so running again
+    /// moves on to the next hit.
+    pub fn run_to_breakpoint(
+        &mut self,
+        bp: &Breakpoint,
+        max_steps: usize,
+    ) -> Result<Stop, Unsupported> {
+        for _ in 0..max_steps {
+            let before = self.st.version();
+            if !self.step()? {
+                return Ok(Stop::Finished);
+            }
+            let hit = match bp {
+                Breakpoint::Line(n) => {
+                    let at = |c: &Config| {
+                        focus(&c.0).is_some_and(|f| self.lines.get(n - 1) == Some(&Some(f.clone())))
+                    };
+                    at(&self.com) && !at(&self.past.last().expect("a step was taken").com)
+                }
+                Breakpoint::Watch(x) => self.st.history()[before..].iter().any(|(y, _)| y == x),
+            };
+            if hit {
+                return Ok(Stop::Breakpoint);
+            }
+        }
+        Ok(if self.is_finished() {
+            Stop::Finished
+        } else {
+            Stop::OutOfSteps
+        })
+    }
+
+    /// The current value of `x`.
+    pub fn inspect(&self, x: &str) -> i64 {
+        self.st.apply(x)
+    }
+
+    pub fn state(&self) -> State {
+        self.st.current()
+    }
+
+    /// Every variable update so far, oldest first.
+    pub fn history(&self) -> &[(String, i64)] {
+        self.st.history()
+    }
+
+    /// What has been printed so far.
+    pub fn output(&self) -> &[i64] {
+        &self.output
+    }
+
+    /// The steps taken so far.
+    pub fn steps(&self) -> usize {
+        self.past.len()
+    }
+
+    /// What is left to run.
+    pub fn current(&self) -> &Config {
+        &self.com
+    }
+
+    pub fn is_finished(&self) -> bool {
+        done(&self.com.0)
+    }
+
+    /// The line of the listing with the command about to run, if it is on
+    /// one.
+    pub fn line(&self) -> Option<usize> {
+        let focus = focus(&self.com.0)?;
+        self.lines
+            .iter()
+            .position(|c| c.as_ref() == Some(focus))
+            .map(|i| i + 1)
+    }
+
+    /// The program with line numbers, and `>` marking the current line.
+    pub fn listing(&self) -> String {
+        let current = self.line();
+        self.program
+            .to_pretty_string()
+            .lines()
+            .enumerate()
+            .map(|(i, line)| {
+                let mark = if current == Some(i + 1) { '>' } else { ' ' };
+                format!("{}{:3}  {}", mark, i + 1, line)
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    /// The current state, shown on the program's variables.
+    pub fn show_state(&self) -> String {
+        self.st.current().show(&self.vars)
+    }
+}
+
+/// The command of `r` that runs next: the first one of a sequence or of
+/// the rest of a loop iteration or the body of a `try`, or the branch of a
+/// `par` that `step` steps. `None` for a loop unrolled to an `if`, which
+/// is no command of the program.
+pub(super) fn focus(r: &Running) -> Option<&Com> {
+    match r {
+        Running::Com(c) => Some(focus_com(c)),
+        Running::Seq(r1, _) | Running::Loop(r1, ..) => focus(r1),
+        Running::Par(r1, _) if !done(r1) => focus(r1),
+        Running::Par(_, r2) => focus(r2),
+        Running::Try(body, ..) => focus(body),
+        Running::If(..) => None,
+    }
+}
+
+fn focus_com(c: &Com) -> &Com {
+    match c {
+        Com::CSeq(c1, _) => focus_com(c1),
+        Com::CPar(c1, _) if !done_com(c1) => focus_com(c1),
+        Com::CPar(_, c2) if !done_com(c2) => focus_com(c2),
+        Com::CTry(body, ..) if !done_com(body) => focus_com(body),
+        _ => c,
+    }
+}
+
+/// For each line `c` takes up in the block layout, the command starting on
+/// it, following `write_com`.
+fn collect_lines(c: &Com, lines: &mut Vec<Option<Com>>) {
+    match c {
+        Com::CSeq(c1, c2) => {
+            if let Com::CSeq(..) = **c1 {
+                lines.push(None);
+                collect_lines(c1, lines);
+                lines.push(None);
+            } else {
+                collect_lines(c1, lines);
+            }
+            collect_lines(c2, lines);
+        }
+        Com::CIf(_, c1, c2) | Com::CPar(c1, c2) | Com::CTry(c1, _, c2) => {
+            lines.push(Some(c.clone()));
+            collect_lines(c1, lines);
+            lines.push(None);
+            collect_lines(c2, lines);
+            lines.push(None);
+        }
+        Com::CWhile(_, body) | Com::CFor(_, _, _, body) => {
+            lines.push(Some(c.clone()));
+            collect_lines(body, lines);
+            lines.push(None);
+        }
+        _ => lines.push(Some(c.clone())),
+    }
+}
+
+#[cfg(test)]
+mod test_imp_debugger {
+    use super::*;
+    use crate::imp::{ceval, parse_com};
+    use crate::state;
+    use crate::state::lookup;
+
+    const FACTORIAL: &str = "Z := X; Y := 1; while ~(Z = 0) do Y := Y * Z; Z := Z - 1 end";
+
+    #[test]
+    fn test_lines_match_the_listing() {
+        for p in [
+            FACTORIAL,
+            "(X := 1; Y := 2); if X <= Y then Z := 1 else (Z := 2; Z := 3) end; skip",
+            "for I := 0; I <= 3; I := I + 1 do if I = 2 then continue else print I end end",
+        ] {
+            let c = parse_com(p).unwrap();
+            let d = Debugger::new(c.clone());
+            assert_eq!(d.lines.len(), c.to_pretty_string().lines().count(), "{}", p);
+        }
+    }
+
+    #[test]
+    fn test_step_and_step_back() {
+        let c = parse_com(FACTORIAL).unwrap();
+        let mut d = Debugger::with_state(c.clone(), state! {"X" => 3});
+        assert_eq!(d.line(), Some(1));
+        while d.step().unwrap() {}
+        assert!(d.is_finished());
+        assert_eq!(d.inspect("Y"), 6);
+        assert_eq!(
+            d.inspect("Y"),
+            lookup(&ceval(state! {"X" => 3}, &c).unwrap(), "Y")
+        );
+        let steps = d.steps();
+        // Step all the way back: the state and the program are as they were.
+        while d.step_back() {}
+        assert_eq!(d.steps(), 0);
+        assert_eq!(d.current(), &c.into());
+        assert_eq!((d.inspect("Y"), d.inspect("X")), (0, 3));
+        assert!(d.history().is_empty());
+        for _ in 0..steps {
+            assert!(d.step().unwrap());
+        }
+        assert_eq!(d.inspect("Y"), 6);
+        assert!(!d.step().unwrap());
+    }
+
+    #[test]
+    fn test_line_breakpoint() {
+        let c = parse_com(FACTORIAL).unwrap();
+        let mut d = Debugger::with_state(c, state! {"X" => 3});
+        // Line 4 is `Y := Y * Z`, once per iteration.
+        let bp = Breakpoint::Line(4);
+        let mut seen = vec![];
+        while d.run_to_breakpoint(&bp, 1_000) == Ok(Stop::Breakpoint) {
+            assert_eq!(d.line(), Some(4));
+            seen.push((d.inspect("Y"), d.inspect("Z")));
+        }
+        assert_eq!(seen, [(1, 3), (3, 2), (6, 1)]);
+        assert!(d.is_finished());
+        assert_eq!(d.listing().lines().nth(3), Some("   4    Y := Y * Z;"));
+    }
+
+    #[test]
+    fn test_watch_and_history() {
+        let c = parse_com("X := 1; Y := 2; print X + Y; X := X + 10").unwrap();
+        let mut d = Debugger::new(c);
+        let bp = Breakpoint::Watch("X".to_string());
+        assert_eq!(d.run_to_breakpoint(&bp, 100), Ok(Stop::Breakpoint));
+        assert_eq!(d.inspect("X"), 1);
+        assert_eq!(d.run_to_breakpoint(&bp, 100), Ok(Stop::Breakpoint));
+        assert_eq!(d.inspect("X"), 11);
+        assert_eq!(d.output(), [3]);
+        assert_eq!(
+            d.history(),
+            [
+                ("X".to_string(), 1),
+                ("Y".to_string(), 2),
+                ("X".to_string(), 11)
+            ]
+        );
+        assert_eq!(d.run_to_breakpoint(&bp, 100), Ok(Stop::Finished));
+        // Stepping back over the print takes its output back too.
+        while d.output() == [3] {
+            assert!(d.step_back());
+        }
+        assert_eq!(d.show_state(), "X=1, Y=2");
+        let mut d = Debugger::new(parse_com("while true do skip end").unwrap());
+        assert_eq!(d.run_to_breakpoint(&bp, 100), Ok(Stop::OutOfSteps));
+    }
+}
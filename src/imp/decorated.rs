@@ -0,0 +1,589 @@
+//! Decorated programs, from Hoare2: a program with an assertion after each
+//! command, and an outer precondition, laid out like a proof outline. The
+//! assertions make the proof of the triple mechanical, except for the
+//! implications between assertions the rules leave behind, which
+//! `verification_conditions` collects. Like a triple's, the decorations
+//! can mention ghosts, which the conditions are then about every value of.
+
+use std::collections::BTreeSet;
+use std::fmt;
+
+use super::{Aexp, Bexp, Com, Formula, HoareTriple, Implication};
+
+/// A decorated command, `dcom` in the book. Every constructor but `DSeq`
+/// and `DPre` ends with the assertion true after it, which `post` reads
+/// off.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DCom {
+    DSkip(Formula),
+    DSeq(Box<DCom>, Box<DCom>),
+    DAsgn(String, Aexp, Formula),
+    /// The test, the assertions at the start of each branch, the branches,
+    /// and what holds after the `if`.
+    DIf(Bexp, Formula, Box<DCom>, Formula, Box<DCom>, Formula),
+    /// The test, the assertion at the start of the body, the body, and
+    /// what holds after the loop. The invariant is the body's
+    /// postcondition.
+    DWhile(Bexp, Formula, Box<DCom>, Formula),
+    /// `->> {{P}} d`: `P` is implied by what holds before.
+    DPre(Formula, Box<DCom>),
+    /// `d ->> {{Q}}`: `Q` is implied by what holds after `d`.
+    DPost(Box<DCom>, Formula),
+}
+
+/// A decorated program: a precondition and a decorated command.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Decorated {
+    pub pre: Formula,
+    pub com: DCom,
+    /// The logical variables, as in `HoareTriple`.
+    pub ghosts: BTreeSet<String>,
+}
+
+impl DCom {
+    pub fn seq(d1: DCom, d2: DCom) -> DCom {
+        DCom::DSeq(Box::new(d1), Box::new(d2))
+    }
+
+    pub fn asgn(x: &str, a: Aexp, post: Formula) -> DCom {
+        DCom::DAsgn(x.to_string(), a, post)
+    }
+
+    pub fn if_(b: Bexp, p1: Formula, d1: DCom, p2: Formula, d2: DCom, post: Formula) -> DCom {
+        DCom::DIf(b, p1, Box::new(d1), p2, Box::new(d2), post)
+    }
+
+    pub fn while_(b: Bexp, body_pre: Formula, body: DCom, post: Formula) -> DCom {
+        DCom::DWhile(b, body_pre, Box::new(body), post)
+    }
+
+    pub fn pre(p: Formula, d: DCom) -> DCom {
+        DCom::DPre(p, Box::new(d))
+    }
+
+    pub fn post_(d: DCom, q: Formula) -> DCom {
+        DCom::DPost(Box::new(d), q)
+    }
+
+    /// The assertion true after the command.
+    pub fn post(&self) -> &Formula {
+        match self {
+            DCom::DSkip(q)
+            | DCom::DAsgn(_, _, q)
+            | DCom::DIf(.., q)
+            | DCom::DWhile(.., q)
+            | DCom::DPost(_, q) => q,
+            DCom::DSeq(_, d) | DCom::DPre(_, d) => d.post(),
+        }
+    }
+
+    /// The command without its decorations, `extract` in the book.
+    pub fn erase(&self) -> Com {
+        match self {
+            DCom::DSkip(_) => Com::CSkip,
+            DCom::DSeq(d1, d2) => Com::seq(d1.erase(), d2.erase()),
+            DCom::DAsgn(x, a, _) => Com::asgn(x, a.clone()),
+            DCom::DIf(b, _, d1, _, d2, _) => Com::if_(b.clone(), d1.erase(), d2.erase()),
+            DCom::DWhile(b, _, d, _) => Com::while_(b.clone(), d.erase()),
+            DCom::DPre(_, d) | DCom::DPost(d, _) => d.erase(),
+        }
+    }
+
+    /// The command with `f` applied to each of its decorations.
+    fn map_formulas(&self, f: &impl Fn(&Formula) -> Formula) -> DCom {
+        match self {
+            DCom::DSkip(q) => DCom::DSkip(f(q)),
+            DCom::DSeq(d1, d2) => DCom::seq(d1.map_formulas(f), d2.map_formulas(f)),
+            DCom::DAsgn(x, a, q) => DCom::asgn(x, a.clone(), f(q)),
+            DCom::DIf(b, p1, d1, p2, d2, q) => DCom::if_(
+                b.clone(),
+                f(p1),
+                d1.map_formulas(f),
+                f(p2),
+                d2.map_formulas(f),
+                f(q),
+            ),
+            DCom::DWhile(b, p, d, q) => DCom::while_(b.clone(), f(p), d.map_formulas(f), f(q)),
+            DCom::DPre(p, d) => DCom::pre(f(p), d.map_formulas(f)),
+            DCom::DPost(d, q) => DCom::post_(d.map_formulas(f), f(q)),
+        }
+    }
+
+    /// The conditions under which the decorations are locally consistent
+    /// when `pre` holds before the command, `verification_conditions` in
+    /// the book, one implication each.
+    fn collect_vcs(&self, pre: &Formula, vcs: &mut Vec<Implication>) {
+        match self {
+            DCom::DSkip(q) => vcs.push(Implication::new(pre.clone(), q.clone())),
+            DCom::DSeq(d1, d2) => {
+                d1.collect_vcs(pre, vcs);
+                d2.collect_vcs(d1.post(), vcs);
+            }
+            DCom::DAsgn(x, a, q) => vcs.push(Implication::new(pre.clone(), q.subst(x, a))),
+            DCom::DIf(b, p1, d1, p2, d2, q) => {
+                let b = Formula::from(b);
+                vcs.push(Implication::new(
+                    Formula::and(pre.clone(), b.clone()),
+                    p1.clone(),
+                ));
+                vcs.push(Implication::new(
+                    Formula::and(pre.clone(), Formula::not(b)),
+                    p2.clone(),
+                ));
+                vcs.push(Implication::new(d1.post().clone(), q.clone()));
+                vcs.push(Implication::new(d2.post().clone(), q.clone()));
+                d1.collect_vcs(p1, vcs);
+                d2.collect_vcs(p2, vcs);
+            }
+            DCom::DWhile(b, body_pre, d, q) => {
+                let b = Formula::from(b);
+                let inv = d.post();
+                vcs.push(Implication::new(pre.clone(), inv.clone()));
+                vcs.push(Implication::new(
+                    Formula::and(inv.clone(), b.clone()),
+                    body_pre.clone(),
+                ));
+                vcs.push(Implication::new(
+                    Formula::and(inv.clone(), Formula::not(b)),
+                    q.clone(),
+                ));
+                d.collect_vcs(body_pre, vcs);
+            }
+            DCom::DPre(p, d) => {
+                vcs.push(Implication::new(pre.clone(), p.clone()));
+                d.collect_vcs(p, vcs);
+            }
+            DCom::DPost(d, q) => {
+                d.collect_vcs(pre, vcs);
+                vcs.push(Implication::new(d.post().clone(), q.clone()));
+            }
+        }
+    }
+
+    fn lines(&self, depth: usize, out: &mut Vec<String>) {
+        let line =
+            |s: String, out: &mut Vec<String>| out.push(format!("{}{}", "  ".repeat(depth), s));
+        match self {
+            DCom::DSkip(q) => {
+                line("skip".to_string(), out);
+                line(format!("{{{{ {} }}}}", q), out);
+            }
+            DCom::DSeq(d1, d2) => {
+                d1.lines(depth, out);
+                let last = out.len() - 1;
+                out[last].push(';');
+                d2.lines(depth, out);
+            }
+            DCom::DAsgn(x, a, q) => {
+                line(format!("{} := {}", x, a), out);
+                line(format!("{{{{ {} }}}}", q), out);
+            }
+            DCom::DIf(b, p1, d1, p2, d2, q) => {
+                line(format!("if {} then", b), out);
+                line(format!("  {{{{ {} }}}}", p1), out);
+                d1.lines(depth + 1, out);
+                line("else".to_string(), out);
+                line(format!("  {{{{ {} }}}}", p2), out);
+                d2.lines(depth + 1, out);
+                line("end".to_string(), out);
+                line(format!("{{{{ {} }}}}", q), out);
+            }
+            DCom::DWhile(b, body_pre, d, q) => {
+                line(format!("while {} do", b), out);
+                line(format!("  {{{{ {} }}}}", body_pre), out);
+                d.lines(depth + 1, out);
+                line("end".to_string(), out);
+                line(format!("{{{{ {} }}}}", q), out);
+            }
+            DCom::DPre(p, d) => {
+                line(format!("->> {{{{ {} }}}}", p), out);
+                d.lines(depth, out);
+            }
+            DCom::DPost(d, q) => {
+                d.lines(depth, out);
+                line(format!("->> {{{{ {} }}}}", q), out);
+            }
+        }
+    }
+}
+
+impl Decorated {
+    pub fn new(pre: Formula, com: DCom) -> Decorated {
+        Decorated {
+            pre,
+            com,
+            ghosts: BTreeSet::new(),
+        }
+    }
+
+    /// The program with `ghosts` among its logical variables.
+    ///
+    /// Panics if the program assigns one of them.
+    pub fn with_ghosts(mut self, ghosts: &[&str]) -> Decorated {
+        let modified = self.com.erase().modified();
+        for x in ghosts {
+            assert!(
+                !modified.contains(*x),
+                "the program assigns the ghost {}",
+                x
+            );
+            self.ghosts.insert(x.to_string());
+        }
+        self
+    }
+
+    /// The instance with each ghost in `values` fixed to its number in
+    /// every decoration, and no longer a ghost. Its verification
+    /// conditions are those of `self` instantiated the same way.
+    ///
+    /// Panics if one of them isn't a ghost.
+    pub fn instantiate(&self, values: &[(&str, i64)]) -> Decorated {
+        let mut ghosts = self.ghosts.clone();
+        for (x, _) in values {
+            assert!(ghosts.remove(*x), "{} is not a ghost of the program", x);
+        }
+        Decorated {
+            pre: self.pre.instantiate(values),
+            com: self.com.map_formulas(&|p| p.instantiate(values)),
+            ghosts,
+        }
+    }
+
+    pub fn post(&self) -> &Formula {
+        self.com.post()
+    }
+
+    /// The triple the decorations prove, once the verification conditions
+    /// are.
+    pub fn triple(&self) -> HoareTriple {
+        let mut triple = HoareTriple::new(self.pre.clone(), self.com.erase(), self.post().clone());
+        triple.ghosts = self.ghosts.clone();
+        triple
+    }
+}
+
+/// Laid out as in the book, an assertion on each line of its own after
+/// the command it decorates.
+impl fmt::Display for Decorated {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut out = vec![format!("{{{{ {} }}}}", self.pre)];
+        self.com.lines(0, &mut out);
+        write!(f, "{}", out.join("\n"))
+    }
+}
+
+/// The implications that, if all valid, make `dec`'s decorations a proof
+/// of `dec.triple()`, in the order they arise reading the program from
+/// the top: each assertion must follow, by the rule of the command before
+/// it, from the one before that.
+pub fn verification_conditions(dec: &Decorated) -> Vec<Implication> {
+    let mut vcs = Vec::new();
+    dec.com.collect_vcs(&dec.pre, &mut vcs);
+    vcs
+}
+
+#[cfg(test)]
+mod test_imp_decorated {
+    use super::*;
+    use crate::imp::{parse_aexp, parse_bexp, parse_com, random_state};
+    use crate::rng::Rng;
+    use crate::state;
+    use crate::state::State;
+
+    fn f(src: &str) -> Formula {
+        Formula::from(parse_bexp(src).unwrap())
+    }
+
+    fn and(p: &str, q: &str) -> Formula {
+        Formula::and(f(p), f(q))
+    }
+
+    fn a(src: &str) -> Aexp {
+        parse_aexp(src).unwrap()
+    }
+
+    fn states(vars: &[&str]) -> Vec<State> {
+        let mut rng = Rng::new(23);
+        (0..500).map(|_| random_state(&mut rng, vars)).collect()
+    }
+
+    /// Whether every condition holds in every one of `states`.
+    fn all_hold(vcs: &[Implication], states: &[State]) -> bool {
+        vcs.iter().all(|vc| states.iter().all(|st| vc.holds(st)))
+    }
+
+    /// `slow_subtraction_dec` from Hoare2, with `M` and `N` for the
+    /// book's `m` and `n`.
+    fn slow_subtraction() -> Decorated {
+        let inv = f("Y - X = N - M");
+        Decorated::new(
+            and("X = M", "Y = N"),
+            DCom::pre(
+                inv.clone(),
+                DCom::post_(
+                    DCom::while_(
+                        parse_bexp("~(X = 0)").unwrap(),
+                        Formula::and(inv.clone(), f("~(X = 0)")),
+                        DCom::pre(
+                            f("Y - 1 - (X - 1) = N - M"),
+                            DCom::seq(
+                                DCom::asgn("Y", a("Y - 1"), f("Y - (X - 1) = N - M")),
+                                DCom::asgn("X", a("X - 1"), inv.clone()),
+                            ),
+                        ),
+                        Formula::and(inv, f("~~(X = 0)")),
+                    ),
+                    f("Y = N - M"),
+                ),
+            ),
+        )
+    }
+
+    #[test]
+    fn test_slow_subtraction() {
+        let dec = slow_subtraction();
+        assert_eq!(
+            dec.to_string(),
+            "{{ X = M && Y = N }}\n\
+             ->> {{ Y - X = N - M }}\n\
+             while ~(X = 0) do\n  \
+               {{ Y - X = N - M && ~(X = 0) }}\n  \
+               ->> {{ Y - 1 - (X - 1) = N - M }}\n  \
+               Y := Y - 1\n  \
+               {{ Y - (X - 1) = N - M }};\n  \
+               X := X - 1\n  \
+               {{ Y - X = N - M }}\n\
+             end\n\
+             {{ Y - X = N - M && ~~(X = 0) }}\n\
+             ->> {{ Y = N - M }}"
+        );
+        let vcs = verification_conditions(&dec);
+        assert_eq!(vcs.len(), 8);
+        assert_eq!(vcs[0].to_string(), "X = M && Y = N ->> Y - X = N - M");
+        assert!(all_hold(&vcs, &states(&["X", "Y", "M", "N"])));
+        assert_eq!(
+            dec.triple().com,
+            parse_com("while ~(X = 0) do Y := Y - 1; X := X - 1 end").unwrap()
+        );
+        assert!(dec
+            .triple()
+            .check_on(&states(&["X", "Y", "M", "N"]), 20)
+            .unwrap()
+            .is_ok());
+
+        // Getting a decoration wrong breaks one of the conditions.
+        let mut wrong = dec.clone();
+        let DCom::DPre(_, d) = &mut wrong.com else {
+            unreachable!()
+        };
+        let DCom::DPost(d, _) = &mut **d else {
+            unreachable!()
+        };
+        let DCom::DWhile(_, _, body, _) = &mut **d else {
+            unreachable!()
+        };
+        **body = DCom::pre(
+            f("Y - 1 - (X - 1) = N - M"),
+            DCom::seq(
+                DCom::asgn("Y", a("Y - 1"), f("Y - (X - 1) = N - M")),
+                DCom::asgn("X", a("X + 1"), f("Y - X = N - M")),
+            ),
+        );
+        assert!(!all_hold(
+            &verification_conditions(&wrong),
+            &states(&["X", "Y", "M", "N"])
+        ));
+    }
+
+    /// `find_parity` from Hoare2, with `M` for `m`: the invariant says
+    /// `X` stays between `0` and `M` and differs from `M` by an even
+    /// amount.
+    #[test]
+    fn test_find_parity() {
+        let inv = Formula::and(and("0 <= X", "X <= M"), Formula::even(a("M - X")));
+        let post = Formula::and(
+            Formula::implies(Formula::even(a("M")), f("X = 0")),
+            Formula::implies(Formula::not(Formula::even(a("M"))), f("X = 1")),
+        );
+        let dec = Decorated::new(
+            and("X = M", "0 <= X"),
+            DCom::pre(
+                inv.clone(),
+                DCom::post_(
+                    DCom::while_(
+                        parse_bexp("2 <= X").unwrap(),
+                        Formula::and(inv.clone(), f("2 <= X")),
+                        DCom::pre(
+                            inv.subst("X", &a("X - 2")),
+                            DCom::asgn("X", a("X - 2"), inv.clone()),
+                        ),
+                        Formula::and(inv.clone(), f("~(2 <= X)")),
+                    ),
+                    post,
+                ),
+            ),
+        );
+        assert!(dec
+            .to_string()
+            .contains("->> {{ (0 <= X - 2 && X - 2 <= M) && even(M - (X - 2)) }}"));
+        let vcs = verification_conditions(&dec);
+        assert!(all_hold(&vcs, &states(&["X", "M"])));
+        // The states that reach the loop have to be tried too, and small
+        // ones are where the parity matters.
+        let small: Vec<State> = (0..20).map(|n| state! {"X" => n, "M" => n}).collect();
+        assert!(all_hold(&vcs, &small));
+        assert_eq!(
+            dec.triple().check_on(&small, 100).unwrap().unwrap().tested,
+            20
+        );
+    }
+
+    #[test]
+    fn test_if_conditions() {
+        // {{true}} if X <= Y then Z := Y - X else Z := X - Y end {{0 <= Z}}
+        // is wrong once the subtraction overflows, though no small state
+        // shows it.
+        let dec = Decorated::new(
+            Formula::FTrue,
+            DCom::if_(
+                parse_bexp("X <= Y").unwrap(),
+                f("X <= Y"),
+                DCom::asgn("Z", a("Y - X"), f("0 <= Z")),
+                f("Y <= X"),
+                DCom::asgn("Z", a("X - Y"), f("0 <= Z")),
+                f("0 <= Z"),
+            ),
+        );
+        let vcs = verification_conditions(&dec);
+        assert_eq!(
+            vcs.iter().map(|vc| vc.to_string()).collect::<Vec<_>>(),
+            [
+                "true && X <= Y ->> X <= Y",
+                "true && ~(X <= Y) ->> Y <= X",
+                "0 <= Z ->> 0 <= Z",
+                "0 <= Z ->> 0 <= Z",
+                "X <= Y ->> 0 <= Y - X",
+                "Y <= X ->> 0 <= X - Y",
+            ]
+        );
+        let small: Vec<State> = (-3..3)
+            .flat_map(|x| (-3..3).map(move |y| state! {"X" => x, "Y" => y}))
+            .collect();
+        assert!(all_hold(&vcs, &small));
+        assert!(!vcs[4].holds(&state! {"X" => -1, "Y" => i64::MAX}));
+    }
+
+    #[test]
+    fn test_hoare_macro() {
+        use crate::hoare;
+        // The invariant fills in the loop's other decorations.
+        let dec = hoare! {
+            {{ X = M && Y = N }}
+            ->> {{ Y - X = N - M }}
+            while ~(X = 0) invariant {{ Y - X = N - M }} {
+                ->> {{ Y - 1 - (X - 1) = N - M }}
+                Y := Y - 1 {{ Y - (X - 1) = N - M }};
+                X := X - 1
+            }
+            ->> {{ Y = N - M }}
+        };
+        assert_eq!(dec, slow_subtraction());
+
+        // Branch preconditions default to the test and its negation, and
+        // the branches end where the `if` does.
+        let dec = hoare! {
+            {{ true }}
+            if X <= Y {
+                {{ X <= Y }}
+                Z := Y - X
+            } else {
+                {{ Y <= X }}
+                Z := X - Y
+            } {{ 0 <= Z }}
+        };
+        let vcs = verification_conditions(&dec);
+        assert_eq!(vcs[0].to_string(), "true && X <= Y ->> X <= Y");
+        assert_eq!(vcs[4].to_string(), "X <= Y ->> 0 <= Y - X");
+        let dec = hoare! {
+            {{ true }}
+            if X <= Y { Z := Y } else if Y <= 0 { Z := 0 } else { Z := X } {{ 0 <= Z || X <= Z }}
+        };
+        let DCom::DIf(_, _, _, p2, d2, q) = &dec.com else {
+            unreachable!()
+        };
+        assert_eq!(p2.to_string(), "true && ~(X <= Y)");
+        assert_eq!(d2.post(), q);
+        assert_eq!(q.to_string(), "0 <= Z || X <= Z");
+        assert_eq!(
+            dec.triple().com,
+            parse_com("if X <= Y then Z := Y else if Y <= 0 then Z := 0 else Z := X end end")
+                .unwrap()
+        );
+
+        // A body that ends elsewhere is weakened to the invariant, and
+        // without an invariant the body's decorations are needed.
+        let dec = hoare! {
+            {{ X = M && 0 <= X }}
+            while 2 <= X invariant {{ 0 <= X && X <= M && even(M - X) }} {
+                X := X - 2 {{ 0 <= X && even(M - X) && X <= M }}
+            } {{ (even(M) -> X = 0) && (~even(M) -> X = 1) }}
+        };
+        let DCom::DWhile(_, p, body, _) = &dec.com else {
+            unreachable!()
+        };
+        assert_eq!(
+            p.to_string(),
+            "((0 <= X && X <= M) && even(M - X)) && 2 <= X"
+        );
+        assert!(matches!(**body, DCom::DPost(..)));
+        let small: Vec<State> = (0..20).map(|n| state! {"X" => n, "M" => n}).collect();
+        assert!(all_hold(&verification_conditions(&dec), &small));
+        let explicit = hoare! {
+            {{ 0 <= X }}
+            Y := 0 {{ 0 <= X && Y = 0 }};
+            while 1 <= X {
+                {{ Y + X <= 100 && 1 <= X }}
+                X := X - 1 {{ Y + X + 1 <= 100 }};
+                Y := Y + 1 {{ Y + X <= 100 }}
+            }
+        };
+        assert_eq!(explicit.post().to_string(), "Y + X <= 100 && ~(1 <= X)");
+    }
+
+    #[test]
+    fn test_ghosts() {
+        // Swapping by arithmetic, with ghosts `m` and `n` for the starting
+        // values.
+        let dec = Decorated::new(
+            and("X = m", "Y = n"),
+            DCom::seq(
+                DCom::asgn("X", a("X + Y"), and("X = m + n", "Y = n")),
+                DCom::seq(
+                    DCom::asgn("Y", a("X - Y"), and("X = m + n", "Y = m")),
+                    DCom::asgn("X", a("X - Y"), and("X = n", "Y = m")),
+                ),
+            ),
+        )
+        .with_ghosts(&["m", "n"]);
+        let vcs = verification_conditions(&dec);
+        assert!(all_hold(&vcs, &states(&["X", "Y", "m", "n"])));
+        let triple = dec.triple();
+        assert_eq!(triple.ghosts, dec.ghosts);
+        let values: Vec<i64> = (-3..=3).collect();
+        let check = triple
+            .check_ghosts(&[state! {"X" => 1, "Y" => -2}], &values, 100)
+            .unwrap()
+            .unwrap();
+        assert_eq!(check.tested, 1);
+
+        // Instantiating commutes with collecting the conditions.
+        let instance = dec.instantiate(&[("m", 1), ("n", 2)]);
+        assert_eq!(instance.ghosts.len(), 0);
+        assert_eq!(instance.pre.to_string(), "X = 1 && Y = 2");
+        let instantiated: Vec<Implication> = vcs
+            .iter()
+            .map(|vc| vc.instantiate(&[("m", 1), ("n", 2)]))
+            .collect();
+        assert_eq!(verification_conditions(&instance), instantiated);
+    }
+}
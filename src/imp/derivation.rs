@@ -0,0 +1,424 @@
+//! Big-step derivation trees. `ceval_derivation` evaluates like `ceval`,
+//! but also returns the proof of `st =[ c ]=> st'` that the book's
+//! inductive `ceval` relation would have: one node per rule applied, with
+//! the premises it needed above it. A `while` loop is a chain of
+//! `E_WhileTrue` nodes, one per iteration, ending in `E_WhileFalse`.
+//!
+//! The rules for `break` and `continue` follow the book's break exercise,
+//! `st =[ c ]=> st' / s`, with `continue` treated like `break`. A `throw`
+//! is one more signal: it passes every rule that stops at a `break`, loops
+//! included, until an `E_TryCatch` catches it.
+
+use std::fmt;
+
+use super::desugar::lower_for;
+use super::{aeval, beval, Com, Signal, Unsupported};
+use crate::map::tm_update;
+use crate::state::State;
+
+/// The rules of the big-step relation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Rule {
+    ESkip,
+    EAsgn,
+    EHavoc,
+    EPrint,
+    EBreak,
+    EContinue,
+    EThrow,
+    /// `c1` finished normally, then `c2` ran.
+    ESeq,
+    /// `c1` stopped at a `break`, `continue` or `throw`, so `c2` didn't run.
+    ESeqInterrupt,
+    EIfTrue,
+    EIfFalse,
+    EWhileFalse,
+    /// The body finished normally or at a `continue`, and the loop went on.
+    EWhileTrue,
+    /// The body stopped at a `break`, or threw.
+    EWhileTrueBreak,
+    /// A `for` loop, by its lowering to a `while` loop.
+    EFor,
+    /// Both branches of a `par`, the left one first; only the left one if
+    /// it threw.
+    EPar,
+    /// The body of a `try` didn't throw.
+    ETry,
+    /// The body of a `try` threw, and the handler ran.
+    ETryCatch,
+}
+
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Rule::ESkip => "E_Skip",
+            Rule::EAsgn => "E_Asgn",
+            Rule::EHavoc => "E_Havoc",
+            Rule::EPrint => "E_Print",
+            Rule::EBreak => "E_Break",
+            Rule::EContinue => "E_Continue",
+            Rule::EThrow => "E_Throw",
+            Rule::ESeq => "E_Seq",
+            Rule::ESeqInterrupt => "E_SeqInterrupt",
+            Rule::EIfTrue => "E_IfTrue",
+            Rule::EIfFalse => "E_IfFalse",
+            Rule::EWhileFalse => "E_WhileFalse",
+            Rule::EWhileTrue => "E_WhileTrue",
+            Rule::EWhileTrueBreak => "E_WhileTrueBreak",
+            Rule::EFor => "E_For",
+            Rule::EPar => "E_Par",
+            Rule::ETry => "E_Try",
+            Rule::ETryCatch => "E_TryCatch",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// A derivation of `before =[ com ]=> after / signal`. The states are
+/// shown on the variables of the command the whole derivation is for.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Derivation {
+    pub rule: Rule,
+    pub com: Com,
+    pub before: Vec<(String, i64)>,
+    pub after: Vec<(String, i64)>,
+    pub signal: Signal,
+    pub premises: Vec<Derivation>,
+}
+
+/// `ceval_fuel`, also returning the derivation of the run. `fuel` bounds
+/// the loop iterations, and with them the height of the tree. The book's
+/// relation has no procedure calls or array writes, so reaching one is an
+/// error.
+pub fn ceval_derivation(
+    st: State,
+    c: &Com,
+    fuel: u64,
+) -> Result<Option<(State, Derivation)>, Unsupported> {
+    let vars: Vec<String> = c.vars().into_iter().collect();
+    let mut builder = Builder {
+        vars,
+        fuel,
+        unsupported: None,
+    };
+    let derived = builder.derive(st, c);
+    match builder.unsupported {
+        Some(e) => Err(e),
+        None => Ok(derived),
+    }
+}
+
+struct Builder {
+    vars: Vec<String>,
+    fuel: u64,
+    /// What stopped the derivation, if it wasn't the fuel running out.
+    unsupported: Option<Unsupported>,
+}
+
+impl Builder {
+    fn snapshot(&self, st: &State) -> Vec<(String, i64)> {
+        self.vars.iter().map(|x| (x.clone(), st(x))).collect()
+    }
+
+    /// The derivation for `c` from `st`, with its conclusion's final state.
+    fn derive(&mut self, st: State, c: &Com) -> Option<(State, Derivation)> {
+        let before = self.snapshot(&st);
+        let (st, rule, signal, premises) = match c {
+            Com::CSkip => (st, Rule::ESkip, Signal::Normal, vec![]),
+            Com::CBreak => (st, Rule::EBreak, Signal::Break, vec![]),
+            Com::CContinue => (st, Rule::EContinue, Signal::Continue, vec![]),
+            Com::CAsgn(x, a) => {
+                let n = aeval(&st, a);
+                (
+                    tm_update(st, x.clone(), n),
+                    Rule::EAsgn,
+                    Signal::Normal,
+                    vec![],
+                )
+            }
+            Com::CHavoc(x) => (
+                tm_update(st, x.clone(), 0),
+                Rule::EHavoc,
+                Signal::Normal,
+                vec![],
+            ),
+            Com::CPrint(_) => (st, Rule::EPrint, Signal::Normal, vec![]),
+            Com::CThrow(a) => {
+                let code = aeval(&st, a);
+                (st, Rule::EThrow, Signal::Throw(code), vec![])
+            }
+            Com::CSeq(c1, c2) => {
+                let (st, d1) = self.derive(st, c1)?;
+                if d1.signal == Signal::Normal {
+                    let (st, d2) = self.derive(st, c2)?;
+                    let signal = d2.signal;
+                    (st, Rule::ESeq, signal, vec![d1, d2])
+                } else {
+                    let signal = d1.signal;
+                    (st, Rule::ESeqInterrupt, signal, vec![d1])
+                }
+            }
+            Com::CIf(b, c1, c2) => {
+                let (rule, branch) = if beval(&st, b) {
+                    (Rule::EIfTrue, c1)
+                } else {
+                    (Rule::EIfFalse, c2)
+                };
+                let (st, d) = self.derive(st, branch)?;
+                let signal = d.signal;
+                (st, rule, signal, vec![d])
+            }
+            Com::CWhile(b, body) => {
+                if !beval(&st, b) {
+                    (st, Rule::EWhileFalse, Signal::Normal, vec![])
+                } else {
+                    self.fuel = self.fuel.checked_sub(1)?;
+                    let (st, d_body) = self.derive(st, body)?;
+                    if let Signal::Break | Signal::Throw(_) = d_body.signal {
+                        let signal = match d_body.signal {
+                            Signal::Break => Signal::Normal,
+                            signal => signal,
+                        };
+                        (st, Rule::EWhileTrueBreak, signal, vec![d_body])
+                    } else {
+                        let (st, d_loop) = self.derive(st, c)?;
+                        let signal = d_loop.signal;
+                        (st, Rule::EWhileTrue, signal, vec![d_body, d_loop])
+                    }
+                }
+            }
+            Com::CFor(..) => {
+                let (st, d) = self.derive(st, &lower_for(c))?;
+                let signal = d.signal;
+                (st, Rule::EFor, signal, vec![d])
+            }
+            Com::CPar(c1, c2) => {
+                let (st, d1) = self.derive(st, c1)?;
+                if let Signal::Throw(_) = d1.signal {
+                    let signal = d1.signal;
+                    (st, Rule::EPar, signal, vec![d1])
+                } else {
+                    let (st, d2) = self.derive(st, c2)?;
+                    let signal = match d2.signal {
+                        Signal::Throw(_) => d2.signal,
+                        _ => Signal::Normal,
+                    };
+                    (st, Rule::EPar, signal, vec![d1, d2])
+                }
+            }
+            Com::CTry(body, x, handler) => {
+                let (st, d_body) = self.derive(st, body)?;
+                if let Signal::Throw(code) = d_body.signal {
+                    let st = tm_update(st, x.clone(), code);
+                    let (st, d_handler) = self.derive(st, handler)?;
+                    let signal = d_handler.signal;
+                    (st, Rule::ETryCatch, signal, vec![d_body, d_handler])
+                } else {
+                    let signal = d_body.signal;
+                    (st, Rule::ETry, signal, vec![d_body])
+                }
+            }
+            Com::CCall(..) => {
+                self.unsupported = Some(Unsupported("procedure calls"));
+                return None;
+            }
+            Com::CArrAsgn(..) => {
+                self.unsupported = Some(Unsupported("arrays"));
+                return None;
+            }
+        };
+        let after = self.snapshot(&st);
+        let d = Derivation {
+            rule,
+            com: c.clone(),
+            before,
+            after,
+            signal,
+            premises,
+        };
+        Some((st, d))
+    }
+}
+
+impl Derivation {
+    /// The number of rule applications.
+    pub fn size(&self) -> usize {
+        1 + self.premises.iter().map(Derivation::size).sum::<usize>()
+    }
+
+    /// The conclusion, `before =[ com ]=> after`, with `/ break`, `/
+    /// continue` or `/ throw n` after it if the command stopped at one.
+    pub fn judgment(&self) -> String {
+        let show = |st: &[(String, i64)]| {
+            let vars: Vec<String> = st.iter().map(|(x, n)| format!("{}={}", x, n)).collect();
+            if vars.is_empty() {
+                "{}".to_string()
+            } else {
+                vars.join(", ")
+            }
+        };
+        let signal = match self.signal {
+            Signal::Normal => String::new(),
+            Signal::Break => " / break".to_string(),
+            Signal::Continue => " / continue".to_string(),
+            Signal::Throw(code) => format!(" / throw {}", code),
+        };
+        format!(
+            "{} =[ {} ]=> {}{}",
+            show(&self.before),
+            self.com,
+            show(&self.after),
+            signal
+        )
+    }
+
+    /// The tree in Graphviz's DOT language, premises above conclusions as in
+    /// the book.
+    pub fn to_dot(&self) -> String {
+        let mut out = String::from(
+            "digraph derivation {\n  rankdir=BT;\n  node [shape=box, fontname=\"monospace\"];\n",
+        );
+        let mut next = 0;
+        self.write_dot(&mut out, &mut next);
+        out.push_str("}\n");
+        out
+    }
+
+    /// Add this node and the ones above it to `out`, numbering them from
+    /// `next`, and return this node's number.
+    fn write_dot(&self, out: &mut String, next: &mut usize) -> usize {
+        let id = *next;
+        *next += 1;
+        let label = format!("{}\n{}", self.rule, self.judgment());
+        out.push_str(&format!("  n{} [label={}];\n", id, dot_string(&label)));
+        for premise in &self.premises {
+            let child = premise.write_dot(out, next);
+            out.push_str(&format!("  n{} -> n{};\n", child, id));
+        }
+        id
+    }
+
+    fn fmt_indented(&self, f: &mut fmt::Formatter<'_>, depth: usize) -> fmt::Result {
+        writeln!(
+            f,
+            "{:indent$}{}: {}",
+            "",
+            self.rule,
+            self.judgment(),
+            indent = 2 * depth
+        )?;
+        for premise in &self.premises {
+            premise.fmt_indented(f, depth + 1)?;
+        }
+        Ok(())
+    }
+}
+
+/// `s` as a quoted DOT string.
+fn dot_string(s: &str) -> String {
+    let mut quoted = String::from("\"");
+    for ch in s.chars() {
+        match ch {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            ch => quoted.push(ch),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// One line per rule application, the conclusion first and each premise
+/// indented below the rule that needed it.
+impl fmt::Display for Derivation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.fmt_indented(f, 0)
+    }
+}
+
+#[cfg(test)]
+mod test_imp_derivation {
+    use super::*;
+    use crate::imp::{ceval, parse_com};
+    use crate::state;
+    use crate::state::lookup;
+
+    #[test]
+    fn test_display() {
+        // The book's ceval_example1.
+        let c = parse_com("X := 2; if X <= 1 then Y := 3 else Z := 4 end").unwrap();
+        let (st, d) = ceval_derivation(state! {}, &c, 10).unwrap().unwrap();
+        assert_eq!(lookup(&st, "Z"), 4);
+        assert_eq!(
+            d.to_string(),
+            "E_Seq: X=0, Y=0, Z=0 =[ X := 2; if X <= 1 then Y := 3 else Z := 4 end ]=> X=2, Y=0, Z=4\n\
+             \x20 E_Asgn: X=0, Y=0, Z=0 =[ X := 2 ]=> X=2, Y=0, Z=0\n\
+             \x20 E_IfFalse: X=2, Y=0, Z=0 =[ if X <= 1 then Y := 3 else Z := 4 end ]=> X=2, Y=0, Z=4\n\
+             \x20   E_Asgn: X=2, Y=0, Z=0 =[ Z := 4 ]=> X=2, Y=0, Z=4\n"
+        );
+    }
+
+    #[test]
+    fn test_loop_shape() {
+        let c =
+            parse_com("while X <= 2 do X := X + 1; if X = 2 then break else skip end end").unwrap();
+        let (_, d) = ceval_derivation(state! {}, &c, 10).unwrap().unwrap();
+        let mut rules = vec![];
+        let mut node = &d;
+        while let [body, rest @ ..] = node.premises.as_slice() {
+            rules.push(node.rule);
+            assert_eq!(body.rule, Rule::ESeq);
+            match rest {
+                [next] => node = next,
+                _ => break,
+            }
+        }
+        assert_eq!(rules, [Rule::EWhileTrue, Rule::EWhileTrueBreak]);
+        let body = &node.premises[0];
+        assert_eq!(body.signal, Signal::Break);
+        assert!(body.judgment().ends_with("X=2 / break"));
+        assert!(
+            ceval_derivation(state! {}, &parse_com("while true do skip end").unwrap(), 50)
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn test_agrees_with_ceval() {
+        let programs = [
+            "Z := X; Y := 1; while ~(Z = 0) do Y := Y * Z; Z := Z - 1 end",
+            "while true do X := X + 1; if 5 <= X then break else continue end end",
+            "for I := 0; I <= X; I := I + 1 do if I = 2 then continue else Y := Y + I end end",
+            "X := 1; break; X := 2",
+        ];
+        for p in programs {
+            let c = parse_com(p).unwrap();
+            for x in 0..4 {
+                let expected = ceval(state! {"X" => x}, &c).unwrap();
+                let (st, d) = ceval_derivation(state! {"X" => x}, &c, 100)
+                    .unwrap()
+                    .unwrap();
+                for (v, n) in &d.after {
+                    assert_eq!(lookup(&st, v), *n);
+                    assert_eq!(lookup(&expected, v), *n, "{} on {}", v, p);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_dot() {
+        let c = parse_com("X := 1; print X").unwrap();
+        let (_, d) = ceval_derivation(state! {}, &c, 10).unwrap().unwrap();
+        assert_eq!(d.size(), 3);
+        assert_eq!(
+            d.to_dot(),
+            "digraph derivation {\n  rankdir=BT;\n  node [shape=box, fontname=\"monospace\"];\n  \
+             n0 [label=\"E_Seq\\nX=0 =[ X := 1; print X ]=> X=1\"];\n  \
+             n1 [label=\"E_Asgn\\nX=0 =[ X := 1 ]=> X=1\"];\n  n1 -> n0;\n  \
+             n2 [label=\"E_Print\\nX=1 =[ print X ]=> X=1\"];\n  n2 -> n0;\n}\n"
+        );
+    }
+}
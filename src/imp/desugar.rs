@@ -0,0 +1,161 @@
+//! Lowering `for` loops to `while` loops.
+
+use super::smallstep::Running;
+use super::{Com, Transform};
+
+/// Rewrites every `CFor` into `init; while b do body; update end`, where a
+/// `continue` belonging to the loop becomes `update; continue` so that the
+/// update still runs.
+pub struct DesugarFor;
+
+impl Transform for DesugarFor {
+    fn rewrite_com(&self, c: Com) -> Com {
+        match c {
+            Com::CFor(..) => lower_for(&c),
+            c => c,
+        }
+    }
+}
+
+impl Com {
+    /// The same program with every `for` loop lowered to a `while` loop.
+    pub fn desugar(&self) -> Com {
+        DesugarFor.transform_com(self)
+    }
+}
+
+/// Lower the outermost `for` of `c`, leaving any inside it alone. Panics if
+/// `c` isn't a `CFor`.
+pub(super) fn lower_for(c: &Com) -> Com {
+    let Com::CFor(init, b, update, body) = c else {
+        panic!("lower_for called on a command that is not a for loop");
+    };
+    let body = continue_runs(body, update);
+    Com::seq(
+        (**init).clone(),
+        Com::while_(b.clone(), Com::seq(body, (**update).clone())),
+    )
+}
+
+/// `body` with each of its own `continue`s preceded by `update`, skipping
+/// nested loops, whose `continue`s are theirs.
+pub(super) fn continue_runs(body: &Com, update: &Com) -> Com {
+    match body {
+        Com::CContinue => Com::seq(update.clone(), Com::CContinue),
+        Com::CSeq(c1, c2) => Com::seq(continue_runs(c1, update), continue_runs(c2, update)),
+        Com::CIf(b, c1, c2) => Com::if_(
+            b.clone(),
+            continue_runs(c1, update),
+            continue_runs(c2, update),
+        ),
+        Com::CTry(c1, x, c2) => Com::try_(
+            continue_runs(c1, update),
+            x,
+            continue_runs(c2, update),
+        ),
+        Com::CSkip
+        | Com::CAsgn(..)
+        | Com::CHavoc(_)
+        | Com::CCall(..)
+        | Com::CArrAsgn(..)
+        | Com::CPrint(_)
+        | Com::CBreak
+        | Com::CThrow(_)
+        | Com::CWhile(..)
+        | Com::CFor(..)
+        // A branch's `continue` only ends the branch.
+        | Com::CPar(..) => body.clone(),
+    }
+}
+
+/// `continue_runs` on what is left of a body partway through. A loop
+/// under way there, or unrolled to an `if`, is a nested one.
+pub(super) fn continue_runs_running(rest: &Running, update: &Com) -> Running {
+    match rest {
+        Running::Com(c) => Running::Com(continue_runs(c, update)),
+        Running::Seq(r1, c2) => {
+            Running::seq(continue_runs_running(r1, update), continue_runs(c2, update))
+        }
+        Running::Try(body, x, handler) => Running::try_(
+            continue_runs_running(body, update),
+            x,
+            continue_runs(handler, update),
+        ),
+        Running::If(..) | Running::Loop(..) | Running::Par(..) => rest.clone(),
+    }
+}
+
+#[cfg(test)]
+mod test_imp_desugar {
+    use crate::imp::{ceval, parse_com, Com};
+    use crate::state;
+    use crate::state::lookup;
+
+    #[test]
+    fn test_desugar_shape() {
+        let c = parse_com("for I := 0; I <= 2; I := I + 1 do S := S + I end").unwrap();
+        assert_eq!(
+            c.desugar(),
+            parse_com("I := 0; while I <= 2 do S := S + I; I := I + 1 end").unwrap()
+        );
+    }
+
+    #[test]
+    fn test_continue_runs_update() {
+        let c = parse_com(
+            "for I := 0; I <= 5; I := I + 1 do if I = 3 then continue else S := S + I end end",
+        )
+        .unwrap();
+        assert_eq!(
+            c.desugar().to_string(),
+            "I := 0; while I <= 5 do if I = 3 then I := I + 1; continue else S := S + I end; \
+             I := I + 1 end"
+        );
+        let st = ceval(state! {}, &c).unwrap();
+        assert_eq!((lookup(&st, "S"), lookup(&st, "I")), (12, 6));
+    }
+
+    #[test]
+    fn test_nested_loops_keep_their_continue() {
+        let c = parse_com(
+            "for I := 0; I <= 2; I := I + 1 do \
+             for J := 0; J <= 2; J := J + 1 do if J = I then continue else N := N + 1 end end; \
+             while false do continue end end",
+        )
+        .unwrap();
+        let lowered = c.desugar();
+        assert!(!format!("{:?}", lowered).contains("CFor"));
+        assert_eq!(lookup(&ceval(state! {}, &lowered).unwrap(), "N"), 6);
+    }
+
+    #[test]
+    fn test_desugared_agrees_with_direct() {
+        let programs = [
+            "for I := 0; I <= X; I := I + 1 do S := S + I * I end",
+            "for I := X; 0 <= I; I := I - 1 do if I = 1 then break else continue end end",
+            "for (I := 0; S := 1); I <= 10; (I := I + 1; S := S * 2) do \
+             if X <= I then break else skip end end",
+            "for skip; true; if 10 <= I then break else I := I + 1 end do S := S + 1 end",
+            "while true do for I := 0; true; I := I + 1 do if X <= I then break else continue end end; \
+             break end",
+        ];
+        for p in programs {
+            let c = parse_com(p).unwrap();
+            let lowered = c.desugar();
+            assert!(matches!(lowered, Com::CSeq(..) | Com::CWhile(..)), "{}", p);
+            for x in -1..6 {
+                let direct = ceval(state! {"X" => x}, &c).unwrap();
+                let desugared = ceval(state! {"X" => x}, &lowered).unwrap();
+                for v in c.vars() {
+                    assert_eq!(
+                        lookup(&direct, &v),
+                        lookup(&desugared, &v),
+                        "{} on {}",
+                        v,
+                        p
+                    );
+                }
+            }
+        }
+    }
+}
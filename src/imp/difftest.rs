@@ -0,0 +1,701 @@
+//! Differential testing: run randomly generated inputs through a backend
+//! and through the reference evaluators and report the first disagreement.
+//! Every harness takes a seed, and the convenience entry points read it
+//! from `RUST_COQ_SEED` (see `crate::rng`), so a failure printed in CI can
+//! be replayed locally.
+
+use std::fmt;
+
+use super::random::{gen_random_com, minimize_com, minimize_state, random_aexp, random_state};
+use super::smallstep::{done, thrown, Config};
+use super::{
+    aeval, ceval_output, s_compile, s_execute, step_output, Aexp, Com, RunResult, Unsupported,
+};
+use crate::rng::{seed_from_env, Rng, SEED_VAR};
+use crate::state::State;
+
+/// The seed used when `RUST_COQ_SEED` is not set.
+pub const DEFAULT_SEED: u64 = 0x1337;
+
+/// The variables random expressions and states range over.
+pub const VARS: [&str; 3] = ["X", "Y", "Z"];
+
+/// The largest expression, in operators, the harnesses generate.
+pub const MAX_SIZE: usize = 8;
+
+/// An expression and state on which a backend disagreed with `aeval`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AexpCounterexample {
+    pub seed: u64,
+    /// Which of the generated cases failed, counting from `0`.
+    pub case: usize,
+    pub aexp: Aexp,
+    /// The values of `VARS` in the starting state.
+    pub state: Vec<(String, i64)>,
+    pub expected: i64,
+    /// What the backend produced, `None` if it failed outright.
+    pub found: Option<i64>,
+}
+
+impl fmt::Display for AexpCounterexample {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state: Vec<String> = self
+            .state
+            .iter()
+            .map(|(x, n)| format!("{}={}", x, n))
+            .collect();
+        let found = self
+            .found
+            .map_or("no result".to_string(), |n| n.to_string());
+        write!(
+            f,
+            "case {}: `{}` with {} should be {}, but the backend gave {} \
+             (rerun with {}={})",
+            self.case,
+            self.aexp,
+            state.join(", "),
+            self.expected,
+            found,
+            SEED_VAR,
+            self.seed
+        )
+    }
+}
+
+/// Check `backend` against `aeval` on `n_cases` random expressions, each
+/// evaluated in a fresh random state.
+pub fn check_aexp_backend(
+    n_cases: usize,
+    seed: u64,
+    backend: impl Fn(&State, &Aexp) -> Option<i64>,
+) -> Result<(), AexpCounterexample> {
+    let mut rng = Rng::new(seed);
+    for case in 0..n_cases {
+        let size = rng.below(MAX_SIZE as u64 + 1) as usize;
+        let a = random_aexp(&mut rng, size, &VARS);
+        let st = random_state(&mut rng, &VARS);
+        let expected = aeval(&st, &a);
+        let found = backend(&st, &a);
+        if found != Some(expected) {
+            return Err(AexpCounterexample {
+                seed,
+                case,
+                aexp: a,
+                state: VARS.iter().map(|x| (x.to_string(), st(x))).collect(),
+                expected,
+                found,
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Running the compiled code on an empty stack must leave exactly the
+/// expression's value.
+fn run_compiled(st: &State, a: &Aexp) -> Option<i64> {
+    match s_execute(st, vec![], &s_compile(a).ok()?).ok()?.as_slice() {
+        [n] => Some(*n),
+        _ => None,
+    }
+}
+
+/// Check `s_compile` and `s_execute` against `aeval` on `n_cases` random
+/// expressions, seeded from `RUST_COQ_SEED`.
+pub fn check_compiler_correct(n_cases: usize) -> Result<(), AexpCounterexample> {
+    check_compiler_correct_seeded(n_cases, seed_from_env(DEFAULT_SEED))
+}
+
+pub fn check_compiler_correct_seeded(n_cases: usize, seed: u64) -> Result<(), AexpCounterexample> {
+    check_aexp_backend(n_cases, seed, run_compiled)
+}
+
+/// A starting state from which two programs end differently, refuting
+/// their equivalence.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Counterexample {
+    pub seed: u64,
+    /// Which of the generated states it was, counting from `0`.
+    pub case: usize,
+    /// The values of the variable universe in the starting state, shrunk
+    /// from the generated ones with `minimize_state` for as long as the
+    /// programs still differ. The difference is this state's.
+    pub state: Vec<(String, i64)>,
+    pub difference: Difference,
+}
+
+/// How the two runs of a `Counterexample` differ.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Difference {
+    /// The programs leave `var` with different values: its final values
+    /// after each, `None` if one ran out of fuel.
+    Var {
+        var: String,
+        first: Option<i64>,
+        second: Option<i64>,
+    },
+    /// The programs print different things.
+    Output { first: Vec<i64>, second: Vec<i64> },
+    /// The programs end with different uncaught exceptions, `None` for
+    /// none.
+    Thrown {
+        first: Option<i64>,
+        second: Option<i64>,
+    },
+}
+
+impl Difference {
+    /// Describe the difference, calling the runs `first` and `second`.
+    fn write(&self, f: &mut fmt::Formatter<'_>, first: &str, second: &str) -> fmt::Result {
+        let show = |v: &Option<i64>| v.map_or("no result".to_string(), |n| n.to_string());
+        match self {
+            Difference::Var {
+                var,
+                first: v1,
+                second: v2,
+            } => write!(
+                f,
+                "{} ends with {} = {} and {} with {}",
+                first,
+                var,
+                show(v1),
+                second,
+                show(v2)
+            ),
+            Difference::Output {
+                first: out1,
+                second: out2,
+            } => write!(f, "{} prints {:?} and {} {:?}", first, out1, second, out2),
+            Difference::Thrown {
+                first: n1,
+                second: n2,
+            } => {
+                let show = |v: &Option<i64>| v.map_or("nothing".to_string(), |n| n.to_string());
+                write!(
+                    f,
+                    "{} throws {} and {} {}",
+                    first,
+                    show(n1),
+                    second,
+                    show(n2)
+                )
+            }
+        }
+    }
+}
+
+impl fmt::Display for Counterexample {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state: Vec<String> = self
+            .state
+            .iter()
+            .map(|(x, n)| format!("{}={}", x, n))
+            .collect();
+        write!(f, "case {}: from {} ", self.case, state.join(", "))?;
+        self.difference
+            .write(f, "the first program", "the second")?;
+        write!(f, " (rerun with {}={})", SEED_VAR, self.seed)
+    }
+}
+
+/// Test the equivalence `cequiv c1 c2` of the Equiv chapter: run both
+/// programs from `n_states` random states over `vars`, with at most `fuel`
+/// loop iterations, and compare what they print and the final values of
+/// `vars`. Two runs that both run out of fuel agree if what one printed so
+/// far is a prefix of what the other did, so diverging programs are
+/// equivalent unless their output tells them apart. Seeded from
+/// `RUST_COQ_SEED`.
+///
+/// Passing proves nothing, but a counterexample is a real one (short of
+/// fuel running out on one side only). A run that reaches a procedure
+/// call fails the whole check.
+pub fn check_cequiv(
+    c1: &Com,
+    c2: &Com,
+    vars: &[&str],
+    n_states: usize,
+    fuel: u64,
+) -> Result<Result<(), Counterexample>, Unsupported> {
+    check_cequiv_seeded(c1, c2, vars, n_states, fuel, seed_from_env(DEFAULT_SEED))
+}
+
+pub fn check_cequiv_seeded(
+    c1: &Com,
+    c2: &Com,
+    vars: &[&str],
+    n_states: usize,
+    fuel: u64,
+    seed: u64,
+) -> Result<Result<(), Counterexample>, Unsupported> {
+    let mut rng = Rng::new(seed);
+    for case in 0..n_states {
+        let st = random_state(&mut rng, vars);
+        if cequiv_differ(&st, c1, c2, vars, fuel)?.is_none() {
+            continue;
+        }
+        let st = minimize_state(&st, vars, |st| {
+            cequiv_differ(st, c1, c2, vars, fuel).is_ok_and(|d| d.is_some())
+        });
+        let difference = cequiv_differ(&st, c1, c2, vars, fuel)?
+            .expect("the shrunk state still tells the programs apart");
+        return Ok(Err(Counterexample {
+            seed,
+            case,
+            state: vars.iter().map(|y| (y.to_string(), st(y))).collect(),
+            difference,
+        }));
+    }
+    Ok(Ok(()))
+}
+
+/// Run `c1` and `c2` from `st` and say how they differ, if they do.
+fn cequiv_differ(
+    st: &State,
+    c1: &Com,
+    c2: &Com,
+    vars: &[&str],
+    fuel: u64,
+) -> Result<Option<Difference>, Unsupported> {
+    let (first, out1) = ceval_output(st.clone(), c1, fuel)?;
+    let (second, out2) = ceval_output(st.clone(), c2, fuel)?;
+    let (first, second) = (first.finished(), second.finished());
+    let outputs_agree = match (&first, &second) {
+        (None, None) => out1.starts_with(&out2) || out2.starts_with(&out1),
+        _ => out1 == out2,
+    };
+    if !outputs_agree {
+        return Ok(Some(Difference::Output {
+            first: out1,
+            second: out2,
+        }));
+    }
+    for x in vars {
+        let x = x.to_string();
+        let (v1, v2) = (
+            first.as_ref().map(|st| st(&x)),
+            second.as_ref().map(|st| st(&x)),
+        );
+        if v1 != v2 {
+            return Ok(Some(Difference::Var {
+                var: x,
+                first: v1,
+                second: v2,
+            }));
+        }
+    }
+    Ok(None)
+}
+
+/// A program on which the big-step and small-step semantics disagree.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Disagreement {
+    /// The seed the programs were generated from, if they were.
+    pub seed: Option<u64>,
+    /// Which program it was, counting from `0`.
+    pub case: usize,
+    pub program: Com,
+    /// The smallest program found by shrinking `program` that still shows
+    /// a disagreement from the same state. The difference is this one's.
+    pub minimized: Com,
+    /// The values of the variables `program` mentions in the starting
+    /// state, shrunk with `minimize_state` once `minimized` is found, for
+    /// as long as it still shows a disagreement.
+    pub state: Vec<(String, i64)>,
+    /// How the runs differ, big-step first.
+    pub difference: Difference,
+}
+
+impl fmt::Display for Disagreement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state: Vec<String> = self
+            .state
+            .iter()
+            .map(|(x, n)| format!("{}={}", x, n))
+            .collect();
+        write!(
+            f,
+            "program {}: from {}, `{}` shrinks to `{}`, on which ",
+            self.case,
+            state.join(", "),
+            self.program,
+            self.minimized
+        )?;
+        self.difference.write(f, "big-step", "small-step")?;
+        if let Some(seed) = self.seed {
+            write!(f, " (rerun with {}={})", SEED_VAR, seed)?;
+        }
+        Ok(())
+    }
+}
+
+/// How a run of a program ended: its final state, or `None` if it ran out
+/// of fuel, the exception it ended with, and what it printed.
+struct Outcome {
+    state: Option<State>,
+    thrown: Option<i64>,
+    output: Vec<i64>,
+}
+
+impl Outcome {
+    /// The outcome of a run that reached a procedure call, which neither
+    /// semantics has without a `Program`: no end, and nothing printed.
+    fn unsupported() -> Outcome {
+        Outcome {
+            state: None,
+            thrown: None,
+            output: Vec::new(),
+        }
+    }
+}
+
+fn run_bigstep(st: State, c: &Com, fuel: u64) -> Outcome {
+    let Ok((result, output)) = ceval_output(st, c, fuel) else {
+        return Outcome::unsupported();
+    };
+    let thrown = match result {
+        RunResult::Thrown(_, n) => Some(n),
+        _ => None,
+    };
+    Outcome {
+        state: result.finished(),
+        thrown,
+        output,
+    }
+}
+
+fn run_smallstep(st: State, c: &Com, fuel: u64) -> Outcome {
+    let (mut st, mut c) = (st, Config::from(c.clone()));
+    let mut output = Vec::new();
+    for _ in 0..fuel {
+        match step_output(&st, &c) {
+            Ok(Some((st1, c1, printed))) => {
+                (st, c) = (st1, c1);
+                output.extend(printed);
+            }
+            Ok(None) => break,
+            Err(_) => return Outcome::unsupported(),
+        }
+    }
+    Outcome {
+        thrown: thrown(&c.0),
+        state: done(&c.0).then_some(st),
+        output,
+    }
+}
+
+/// Run `c` from `st` both ways and say how the runs differ, if they do.
+/// Every loop iteration takes at least one step, so a small-step run that
+/// finishes within `fuel` steps has a big-step run that finishes within
+/// `fuel` iterations; only a small-step run that doesn't finish proves
+/// nothing.
+fn differ(
+    st: &State,
+    c: &Com,
+    fuel: u64,
+    bigstep: &impl Fn(State, &Com, u64) -> Outcome,
+) -> Option<Difference> {
+    let small = run_smallstep(st.clone(), c, fuel);
+    let small_state = small.state?;
+    let big = bigstep(st.clone(), c, fuel);
+    if big.output != small.output {
+        return Some(Difference::Output {
+            first: big.output,
+            second: small.output,
+        });
+    }
+    if big.thrown != small.thrown {
+        return Some(Difference::Thrown {
+            first: big.thrown,
+            second: small.thrown,
+        });
+    }
+    for x in c.vars() {
+        let first = big.state.as_ref().map(|st| st(&x));
+        let second = Some(small_state(&x));
+        if first != second {
+            return Some(Difference::Var {
+                var: x,
+                first,
+                second,
+            });
+        }
+    }
+    None
+}
+
+/// Check that `ceval` and the small-step `step` agree on every one of
+/// `programs` run from every one of `states`: on what they print, the
+/// exception nothing catches, and the final values of the variables each
+/// program mentions. The small-step run gets `fuel` steps and the big-step
+/// one `fuel` loop iterations; where the small-step run doesn't finish, or
+/// reaches a command `step` doesn't support, there is nothing to compare.
+/// The first disagreement is shrunk with `minimize_com`, and then its
+/// state with `minimize_state`, before it is reported.
+pub fn check_bigstep_smallstep_agree(
+    programs: &[Com],
+    states: &[State],
+    fuel: u64,
+) -> Result<(), Box<Disagreement>> {
+    check_agree_with(programs, states, fuel, None, run_bigstep)
+}
+
+/// `check_bigstep_smallstep_agree` on `n_programs` programs from
+/// `gen_random_com` over `VARS`, each run from `n_states` random states.
+/// Seeded from `RUST_COQ_SEED`.
+pub fn check_bigstep_smallstep_random(
+    n_programs: usize,
+    n_states: usize,
+    fuel: u64,
+) -> Result<(), Box<Disagreement>> {
+    check_bigstep_smallstep_random_seeded(n_programs, n_states, fuel, seed_from_env(DEFAULT_SEED))
+}
+
+/// The size, in commands, of the programs the harnesses generate.
+pub const PROGRAM_SIZE: usize = 12;
+
+pub fn check_bigstep_smallstep_random_seeded(
+    n_programs: usize,
+    n_states: usize,
+    fuel: u64,
+    seed: u64,
+) -> Result<(), Box<Disagreement>> {
+    let mut rng = Rng::new(seed);
+    let programs: Vec<Com> = (0..n_programs)
+        .map(|_| gen_random_com(PROGRAM_SIZE, &VARS, &mut rng))
+        .collect();
+    let states: Vec<State> = (0..n_states)
+        .map(|_| random_state(&mut rng, &VARS))
+        .collect();
+    check_agree_with(&programs, &states, fuel, Some(seed), run_bigstep)
+}
+
+fn check_agree_with(
+    programs: &[Com],
+    states: &[State],
+    fuel: u64,
+    seed: Option<u64>,
+    bigstep: impl Fn(State, &Com, u64) -> Outcome,
+) -> Result<(), Box<Disagreement>> {
+    for (case, c) in programs.iter().enumerate() {
+        for st in states {
+            if differ(st, c, fuel, &bigstep).is_none() {
+                continue;
+            }
+            let minimized = minimize_com(c, |c| differ(st, c, fuel, &bigstep).is_some());
+            let vars: Vec<String> = c.vars().into_iter().collect();
+            let st = minimize_state(st, &vars, |st| {
+                differ(st, &minimized, fuel, &bigstep).is_some()
+            });
+            let difference = differ(&st, &minimized, fuel, &bigstep).unwrap();
+            return Err(Box::new(Disagreement {
+                seed,
+                case,
+                program: c.clone(),
+                minimized,
+                state: c.vars().into_iter().map(|x| (x.clone(), st(&x))).collect(),
+                difference,
+            }));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test_imp_difftest {
+    use super::*;
+    use crate::imp::{parse_com, SInstr};
+    use crate::state;
+
+    #[test]
+    fn test_compiler_correct() {
+        if let Err(e) = check_compiler_correct(2_000) {
+            panic!("{}", e);
+        }
+    }
+
+    /// A compiler that gets the operand order of `-` wrong.
+    fn run_swapped(st: &State, a: &Aexp) -> Option<i64> {
+        let prog: Vec<SInstr> = s_compile(a)
+            .unwrap()
+            .into_iter()
+            .map(|i| match i {
+                SInstr::SMinus => SInstr::SPlus,
+                i => i,
+            })
+            .collect();
+        s_execute(st, vec![], &prog).ok()?.pop()
+    }
+
+    #[test]
+    fn test_broken_backend_is_caught() {
+        let err = check_aexp_backend(2_000, 1, run_swapped).unwrap_err();
+        assert_eq!(err.seed, 1);
+        assert_ne!(err.found, Some(err.expected));
+        assert!(err.to_string().contains("rerun with RUST_COQ_SEED=1"));
+        // The same seed finds the same counterexample.
+        assert_eq!(check_aexp_backend(2_000, 1, run_swapped), Err(err));
+    }
+
+    fn cequiv(c1: &str, c2: &str) -> Result<(), Counterexample> {
+        let (c1, c2) = (parse_com(c1).unwrap(), parse_com(c2).unwrap());
+        check_cequiv_seeded(&c1, &c2, &VARS, 200, 100, 7).unwrap()
+    }
+
+    #[test]
+    fn test_equiv_chapter_equivalences() {
+        // skip_left, swap_if_branches, a self-assignment, and two loops
+        // that both diverge.
+        assert_eq!(cequiv("skip; X := X + 1", "X := X + 1"), Ok(()));
+        assert_eq!(
+            cequiv(
+                "if X <= Y then Z := X else Z := Y end",
+                "if ~(X <= Y) then Z := Y else Z := X end"
+            ),
+            Ok(())
+        );
+        assert_eq!(cequiv("X := X", "skip"), Ok(()));
+        assert_eq!(
+            cequiv(
+                "while true do skip end",
+                "while ~(X = X + 1) do X := X + 1 end"
+            ),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn test_inequivalence_is_refuted() {
+        let err = cequiv("X := X + 1; Y := X", "Y := X; X := X + 1").unwrap_err();
+        let x = err.state[0].1;
+        assert_eq!(
+            err.difference,
+            Difference::Var {
+                var: "Y".to_string(),
+                first: Some(x + 1),
+                second: Some(x)
+            }
+        );
+        assert!(err.to_string().contains("rerun with RUST_COQ_SEED=7"));
+
+        // One side terminating and the other not is a difference too.
+        let err = cequiv("while 0 <= X do X := X + 1 end", "skip").unwrap_err();
+        assert!(matches!(
+            err.difference,
+            Difference::Var { var, first: None, .. } if var == "X"
+        ));
+    }
+
+    #[test]
+    fn test_counterexample_is_minimized() {
+        // Differ only when X is above 3, and then on Y.
+        let err = cequiv(
+            "if 4 <= X then Y := X * Z else skip end",
+            "if 4 <= X then Y := X * Z + 1 else skip end",
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "case 0: from X=4, Y=0, Z=0 the first program ends with Y = 0 and the second \
+             with 1 (rerun with RUST_COQ_SEED=7)"
+        );
+    }
+
+    #[test]
+    fn test_output_is_compared() {
+        // Same final state, different output.
+        let err = cequiv("print 1; print 2", "print 2; print 1").unwrap_err();
+        assert_eq!(
+            err.difference,
+            Difference::Output {
+                first: vec![1, 2],
+                second: vec![2, 1]
+            }
+        );
+        assert!(err
+            .to_string()
+            .contains("the first program prints [1, 2] and the second [2, 1]"));
+        assert_eq!(cequiv("X := 2; print X", "print 2; X := 2"), Ok(()));
+
+        // Both diverge, but what they print tells them apart...
+        let err = cequiv("while true do print 1 end", "while true do print 2 end").unwrap_err();
+        assert!(matches!(err.difference, Difference::Output { .. }));
+        // ...unless one prints a prefix of the other.
+        assert_eq!(
+            cequiv(
+                "while true do print 1 end",
+                "while true do print 1; print 1 end"
+            ),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn test_bigstep_smallstep_agree() {
+        if let Err(e) = check_bigstep_smallstep_random(100, 3, 1_000) {
+            panic!("{}", e);
+        }
+        let programs: Vec<Com> = [
+            "X := 1; while true do X := X + 1; if 5 <= X then break else skip end end",
+            "try for I := 0; I <= 3; I := I + 1 do print I; if I = 2 then throw I * 10 \
+             else continue end end catch E do X := E end",
+            "X := 4; throw X - 1; X := 0",
+            // Never finishes, so there is nothing to compare.
+            "while true do print 1 end",
+        ]
+        .iter()
+        .map(|src| parse_com(src).unwrap())
+        .collect();
+        let states = [state! {"X" => 3}, state! {"X" => -1, "E" => 7}];
+        assert_eq!(
+            check_bigstep_smallstep_agree(&programs, &states, 1_000),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn test_disagreement_is_minimized() {
+        // A big-step semantics that prints everything twice.
+        let stutter = |st, c: &Com, fuel| {
+            let mut outcome = run_bigstep(st, c, fuel);
+            outcome.output = outcome.output.iter().flat_map(|n| [*n, *n]).collect();
+            outcome
+        };
+        let c = parse_com("X := 2; if X <= 2 then Y := X * 3; print Y + X else skip end").unwrap();
+        let err = check_agree_with(
+            std::slice::from_ref(&c),
+            &[state! {}],
+            100,
+            Some(5),
+            stutter,
+        )
+        .unwrap_err();
+        assert_eq!(err.program, c);
+        assert_eq!(err.minimized, parse_com("print 0").unwrap());
+        assert_eq!(
+            err.difference,
+            Difference::Output {
+                first: vec![0, 0],
+                second: vec![0]
+            }
+        );
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "program 0: from X=0, Y=0, `{}` shrinks to `print 0`, on which big-step \
+                 prints [0, 0] and small-step [0] (rerun with RUST_COQ_SEED=5)",
+                c
+            )
+        );
+    }
+
+    #[test]
+    fn test_calls_are_unsupported() {
+        let (c1, c2) = (
+            parse_com("X := f(1)").unwrap(),
+            parse_com("X := 1").unwrap(),
+        );
+        assert_eq!(
+            check_cequiv_seeded(&c1, &c2, &VARS, 10, 100, 7).err(),
+            Some(Unsupported("procedure calls"))
+        );
+    }
+}
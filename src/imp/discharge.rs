@@ -0,0 +1,296 @@
+//! Discharging verification conditions, which turns decorated programs
+//! into a small verifier: `verification_conditions` says what has to be
+//! true, and `discharge` tries each condition on states, or, with the
+//! `smt-solver` feature, hands it to an SMT solver that can prove it.
+
+use std::fmt;
+
+use super::random::random_state;
+use super::Implication;
+use crate::map::tm_update;
+use crate::rng::Rng;
+use crate::state::{empty_state, State};
+
+/// How `discharge` decides a condition.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Strategy {
+    /// Try every state giving each variable the condition mentions a value
+    /// from `lo` to `hi`, inclusive. With `n` variables that is
+    /// `(hi - lo + 1)^n` states, so keep the range small.
+    Exhaustive { lo: i64, hi: i64 },
+    /// Try `cases` states from `random_state`, which favours small values
+    /// but also tries the extremes where arithmetic wraps.
+    Random { cases: usize, seed: u64 },
+    /// Run `command` with `args`, feeding it the condition from
+    /// `smt_implication` on its standard input, as `z3 -in` reads it.
+    #[cfg(feature = "smt-solver")]
+    Smt { command: String, args: Vec<String> },
+}
+
+/// What `discharge` found out about a condition.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Verdict {
+    /// The solver proved the condition valid.
+    Proved,
+    /// No state tried breaks the condition, which proves nothing unless
+    /// every state was tried.
+    Passed { tried: usize },
+    /// A state satisfying the hypothesis but not the conclusion, on the
+    /// variables the condition mentions.
+    Refuted(Vec<(String, i64)>),
+    /// The solver couldn't decide, or couldn't be run, and said this.
+    Unknown(String),
+}
+
+impl Verdict {
+    /// Whether nothing was found against the condition.
+    pub fn holds(&self) -> bool {
+        matches!(self, Verdict::Proved | Verdict::Passed { .. })
+    }
+}
+
+impl fmt::Display for Verdict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Verdict::Proved => write!(f, "proved"),
+            Verdict::Passed { tried } => write!(f, "passed on {} states", tried),
+            Verdict::Refuted(st) => {
+                let st: Vec<String> = st.iter().map(|(x, n)| format!("{}={}", x, n)).collect();
+                write!(f, "refuted by {}", st.join(", "))
+            }
+            Verdict::Unknown(reason) => write!(f, "unknown: {}", reason),
+        }
+    }
+}
+
+/// Decide each of `vcs` with `strategy`, one verdict per condition, in
+/// order.
+pub fn discharge(vcs: &[Implication], strategy: &Strategy) -> Vec<Verdict> {
+    match strategy {
+        Strategy::Exhaustive { lo, hi } => vcs.iter().map(|vc| exhaustive(vc, *lo, *hi)).collect(),
+        Strategy::Random { cases, seed } => {
+            let mut rng = Rng::new(*seed);
+            vcs.iter().map(|vc| random(vc, *cases, &mut rng)).collect()
+        }
+        #[cfg(feature = "smt-solver")]
+        Strategy::Smt { command, args } => vcs.iter().map(|vc| solve(vc, command, args)).collect(),
+    }
+}
+
+fn vars(vc: &Implication) -> Vec<String> {
+    let mut vars = vc.hyp.vars();
+    vars.extend(vc.concl.vars());
+    vars.into_iter().collect()
+}
+
+fn refuted(vc: &Implication, vars: &[String], st: &State) -> Option<Verdict> {
+    (!vc.holds(st)).then(|| Verdict::Refuted(vars.iter().map(|x| (x.clone(), st(x))).collect()))
+}
+
+fn exhaustive(vc: &Implication, lo: i64, hi: i64) -> Verdict {
+    let vars = vars(vc);
+    let mut values = vec![lo; vars.len()];
+    let mut tried = 0;
+    if lo > hi {
+        return Verdict::Passed { tried };
+    }
+    loop {
+        let st = vars
+            .iter()
+            .zip(&values)
+            .fold(empty_state(), |st, (x, n)| tm_update(st, x.clone(), *n));
+        if let Some(verdict) = refuted(vc, &vars, &st) {
+            return verdict;
+        }
+        tried += 1;
+        // Count up in base `hi - lo + 1`, the first variable fastest.
+        let Some(i) = values.iter().position(|n| *n < hi) else {
+            return Verdict::Passed { tried };
+        };
+        values[i] += 1;
+        values[..i].fill(lo);
+    }
+}
+
+fn random(vc: &Implication, cases: usize, rng: &mut Rng) -> Verdict {
+    let vars = vars(vc);
+    let names: Vec<&str> = vars.iter().map(String::as_str).collect();
+    for _ in 0..cases {
+        let st = random_state(rng, &names);
+        if let Some(verdict) = refuted(vc, &vars, &st) {
+            return verdict;
+        }
+    }
+    Verdict::Passed { tried: cases }
+}
+
+#[cfg(feature = "smt-solver")]
+fn solve(vc: &Implication, command: &str, args: &[String]) -> Verdict {
+    use std::io::Write;
+    use std::process::{Command, Stdio};
+
+    let child = Command::new(command)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn();
+    let mut child = match child {
+        Ok(child) => child,
+        Err(e) => return Verdict::Unknown(format!("can't run {}: {}", command, e)),
+    };
+    let script = super::smt_implication(vc);
+    if let Some(mut stdin) = child.stdin.take() {
+        if let Err(e) = stdin.write_all(script.as_bytes()) {
+            return Verdict::Unknown(format!("can't write to {}: {}", command, e));
+        }
+    }
+    let output = match child.wait_with_output() {
+        Ok(output) => output,
+        Err(e) => return Verdict::Unknown(format!("{} failed: {}", command, e)),
+    };
+    let answer = String::from_utf8_lossy(&output.stdout);
+    match answer.lines().next().map(str::trim) {
+        Some("unsat") => Verdict::Proved,
+        Some("sat") => Verdict::Refuted(parse_model(&answer, &vars(vc))),
+        _ => Verdict::Unknown(answer.trim().to_string()),
+    }
+}
+
+/// The values of `vars` in a model the solver printed, with `0` for any
+/// it left out, which it does when the value doesn't matter.
+#[cfg(feature = "smt-solver")]
+fn parse_model(answer: &str, vars: &[String]) -> Vec<(String, i64)> {
+    let value = |x: &str| {
+        let quoted = format!("|{}|", x);
+        let mut words = answer
+            .split(|c: char| c.is_whitespace() || c == '(' || c == ')')
+            .filter(|w| !w.is_empty());
+        // `(define-fun X () (_ BitVec 64) #x...)`: the value comes four
+        // words after the name.
+        while words.any(|w| w == x || w == quoted) {
+            let (Some("_"), Some(_), Some(_), Some(v)) =
+                (words.next(), words.next(), words.next(), words.next())
+            else {
+                continue;
+            };
+            let n = match (v.strip_prefix("#x"), v.strip_prefix("#b")) {
+                (Some(hex), _) => u64::from_str_radix(hex, 16),
+                (_, Some(bin)) => u64::from_str_radix(bin, 2),
+                _ => continue,
+            };
+            return n.ok().map(|n| n as i64);
+        }
+        None
+    };
+    vars.iter()
+        .map(|x| (x.clone(), value(x).unwrap_or(0)))
+        .collect()
+}
+
+#[cfg(test)]
+mod test_imp_discharge {
+    use super::*;
+    use crate::imp::{parse_bexp, verification_conditions, Aexp, DCom, Decorated, Formula};
+
+    fn f(src: &str) -> Formula {
+        Formula::from(parse_bexp(src).unwrap())
+    }
+
+    #[test]
+    fn test_exhaustive() {
+        let valid = Implication::new(f("X <= Y && Y <= Z"), f("X <= Z"));
+        assert_eq!(
+            discharge(&[valid], &Strategy::Exhaustive { lo: -2, hi: 2 }),
+            [Verdict::Passed { tried: 125 }]
+        );
+        let invalid = [Implication::new(f("X <= Y"), f("X * 2 <= Y + 1"))];
+        let verdicts = discharge(&invalid, &Strategy::Exhaustive { lo: 0, hi: 3 });
+        // The first counterexample in counting order.
+        assert_eq!(
+            verdicts,
+            [Verdict::Refuted(vec![
+                ("X".to_string(), 2),
+                ("Y".to_string(), 2)
+            ])]
+        );
+        assert_eq!(verdicts[0].to_string(), "refuted by X=2, Y=2");
+        assert!(!verdicts[0].holds());
+        let none = discharge(&invalid, &Strategy::Exhaustive { lo: 1, hi: 0 });
+        assert_eq!(none, [Verdict::Passed { tried: 0 }]);
+    }
+
+    #[test]
+    fn test_random_finds_overflow() {
+        // True of small numbers, but not once X + 1 wraps.
+        let vc = [Implication::new(Formula::FTrue, f("X <= X + 1"))];
+        let small = discharge(&vc, &Strategy::Exhaustive { lo: -5, hi: 5 });
+        assert!(small[0].holds());
+        let verdicts = discharge(
+            &vc,
+            &Strategy::Random {
+                cases: 1_000,
+                seed: 1,
+            },
+        );
+        assert_eq!(
+            verdicts,
+            [Verdict::Refuted(vec![("X".to_string(), i64::MAX)])]
+        );
+    }
+
+    #[test]
+    fn test_decorated_pipeline() {
+        // {{true}} X := 3 {{X = 3}} ->> {{2 <= X}}; Y := X * 2 {{X <= Y}}
+        let good = Decorated::new(
+            Formula::FTrue,
+            DCom::seq(
+                DCom::post_(DCom::asgn("X", Aexp::num(3), f("X = 3")), f("2 <= X")),
+                DCom::asgn("Y", Aexp::mult(Aexp::var("X"), Aexp::num(2)), f("X <= Y")),
+            ),
+        );
+        let vcs = verification_conditions(&good);
+        let strategy = Strategy::Exhaustive { lo: -3, hi: 3 };
+        let verdicts = discharge(&vcs, &strategy);
+        // 2 <= X ->> X <= X * 2 wraps too, but not between -3 and 3.
+        assert!(verdicts.iter().all(Verdict::holds), "{:?}", verdicts);
+        let verdicts = discharge(
+            &vcs,
+            &Strategy::Random {
+                cases: 1_000,
+                seed: 2,
+            },
+        );
+        assert!(!verdicts[2].holds());
+        assert!(verdicts[..2].iter().all(Verdict::holds));
+    }
+
+    #[cfg(feature = "smt-solver")]
+    #[test]
+    fn test_missing_solver() {
+        let vc = Implication::new(Formula::FTrue, Formula::FTrue);
+        let strategy = Strategy::Smt {
+            command: "no-such-solver-here".to_string(),
+            args: vec!["-in".to_string()],
+        };
+        let verdicts = discharge(&[vc], &strategy);
+        assert!(
+            matches!(&verdicts[0], Verdict::Unknown(reason) if reason.starts_with("can't run"))
+        );
+    }
+
+    #[cfg(feature = "smt-solver")]
+    #[test]
+    fn test_parse_model() {
+        let answer = "sat\n(\n  (define-fun Y () (_ BitVec 64)\n    #x0000000000000002)\n  \
+                      (define-fun X () (_ BitVec 64)\n    #xffffffffffffffff)\n)\n";
+        assert_eq!(
+            parse_model(answer, &["X".to_string(), "Y".to_string(), "Z".to_string()]),
+            [
+                ("X".to_string(), -1),
+                ("Y".to_string(), 2),
+                ("Z".to_string(), 0)
+            ]
+        );
+    }
+}
@@ -0,0 +1,706 @@
+//! Assertions and Hoare triples, from the Hoare chapter. An assertion is a
+//! property of states: either a `Formula`, which can be reasoned about
+//! syntactically, or any Rust predicate, which can only be evaluated. A
+//! triple `{{P}} c {{Q}}` can't be proved here, but `check_on` tests it by
+//! running `c` from states satisfying `P`.
+//!
+//! A triple may also have ghosts: logical variables like the `m` in
+//! `{{X = m}} X := X + 1 {{X = m + 1}}`, which are quantified over the
+//! whole triple. A ghost reads like any other variable, but the program
+//! can't assign it, so it keeps the value it starts with from the
+//! precondition to the postcondition.
+
+use std::collections::BTreeSet;
+use std::fmt;
+use std::rc::Rc;
+
+use super::random::minimize_state;
+use super::{aeval, ceval_fuel, Aexp, Bexp, Com, Unsupported};
+use crate::map::tm_update;
+use crate::state::State;
+
+/// The formulas of the assertion language: boolean expressions with `||`
+/// and `->` as well, since assertions need them where programs don't, and
+/// evenness, which `find_parity`'s invariant needs and `+`, `-` and `*`
+/// can't say.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Formula {
+    FTrue,
+    FFalse,
+    FEq(Box<Aexp>, Box<Aexp>),
+    FLe(Box<Aexp>, Box<Aexp>),
+    /// `even(a)`, `ap ev a` in the book. Wrapping doesn't change whether a
+    /// number is even.
+    FEven(Box<Aexp>),
+    FNot(Box<Formula>),
+    FAnd(Box<Formula>, Box<Formula>),
+    FOr(Box<Formula>, Box<Formula>),
+    FImplies(Box<Formula>, Box<Formula>),
+}
+
+impl Formula {
+    pub fn eq(a1: Aexp, a2: Aexp) -> Formula {
+        Formula::FEq(Box::new(a1), Box::new(a2))
+    }
+
+    pub fn le(a1: Aexp, a2: Aexp) -> Formula {
+        Formula::FLe(Box::new(a1), Box::new(a2))
+    }
+
+    pub fn even(a: Aexp) -> Formula {
+        Formula::FEven(Box::new(a))
+    }
+
+    #[allow(clippy::should_implement_trait)]
+    pub fn not(p: Formula) -> Formula {
+        Formula::FNot(Box::new(p))
+    }
+
+    pub fn and(p: Formula, q: Formula) -> Formula {
+        Formula::FAnd(Box::new(p), Box::new(q))
+    }
+
+    pub fn or(p: Formula, q: Formula) -> Formula {
+        Formula::FOr(Box::new(p), Box::new(q))
+    }
+
+    pub fn implies(p: Formula, q: Formula) -> Formula {
+        Formula::FImplies(Box::new(p), Box::new(q))
+    }
+
+    /// Whether the formula is true in `st`.
+    pub fn holds(&self, st: &State) -> bool {
+        match self {
+            Formula::FTrue => true,
+            Formula::FFalse => false,
+            Formula::FEq(a1, a2) => aeval(st, a1) == aeval(st, a2),
+            Formula::FLe(a1, a2) => aeval(st, a1) <= aeval(st, a2),
+            Formula::FEven(a) => aeval(st, a) % 2 == 0,
+            Formula::FNot(p) => !p.holds(st),
+            Formula::FAnd(p, q) => p.holds(st) && q.holds(st),
+            Formula::FOr(p, q) => p.holds(st) || q.holds(st),
+            Formula::FImplies(p, q) => !p.holds(st) || q.holds(st),
+        }
+    }
+
+    /// The variables the formula mentions.
+    pub fn vars(&self) -> BTreeSet<String> {
+        let mut vars = BTreeSet::new();
+        self.collect_vars(&mut vars);
+        vars
+    }
+
+    fn collect_vars(&self, vars: &mut BTreeSet<String>) {
+        match self {
+            Formula::FTrue | Formula::FFalse => {}
+            Formula::FEq(a1, a2) | Formula::FLe(a1, a2) => {
+                vars.extend(a1.vars());
+                vars.extend(a2.vars());
+            }
+            Formula::FEven(a) => vars.extend(a.vars()),
+            Formula::FNot(p) => p.collect_vars(vars),
+            Formula::FAnd(p, q) | Formula::FOr(p, q) | Formula::FImplies(p, q) => {
+                p.collect_vars(vars);
+                q.collect_vars(vars);
+            }
+        }
+    }
+
+    /// `P [X |-> a]`: the formula with every `x` replaced by `a`, which
+    /// holds before `x := a` exactly when the formula holds after it.
+    pub fn subst(&self, x: &str, a: &Aexp) -> Formula {
+        match self {
+            Formula::FTrue | Formula::FFalse => self.clone(),
+            Formula::FEq(a1, a2) => Formula::eq(a1.subst(x, a), a2.subst(x, a)),
+            Formula::FLe(a1, a2) => Formula::le(a1.subst(x, a), a2.subst(x, a)),
+            Formula::FEven(a1) => Formula::even(a1.subst(x, a)),
+            Formula::FNot(p) => Formula::not(p.subst(x, a)),
+            Formula::FAnd(p, q) => Formula::and(p.subst(x, a), q.subst(x, a)),
+            Formula::FOr(p, q) => Formula::or(p.subst(x, a), q.subst(x, a)),
+            Formula::FImplies(p, q) => Formula::implies(p.subst(x, a), q.subst(x, a)),
+        }
+    }
+
+    /// The formula with each of the variables in `values`, usually ghosts,
+    /// replaced by its number.
+    pub fn instantiate(&self, values: &[(&str, i64)]) -> Formula {
+        values
+            .iter()
+            .fold(self.clone(), |p, (x, n)| p.subst(x, &Aexp::num(*n)))
+    }
+}
+
+impl From<&Bexp> for Formula {
+    fn from(b: &Bexp) -> Formula {
+        match b {
+            Bexp::BTrue => Formula::FTrue,
+            Bexp::BFalse => Formula::FFalse,
+            Bexp::BEq(a1, a2) => Formula::eq((**a1).clone(), (**a2).clone()),
+            Bexp::BLe(a1, a2) => Formula::le((**a1).clone(), (**a2).clone()),
+            Bexp::BNot(b) => Formula::not(Formula::from(&**b)),
+            Bexp::BAnd(b1, b2) => Formula::and(Formula::from(&**b1), Formula::from(&**b2)),
+        }
+    }
+}
+
+impl From<Bexp> for Formula {
+    fn from(b: Bexp) -> Formula {
+        Formula::from(&b)
+    }
+}
+
+/// Connectives are printed as in Imp, with `||` and `->` added, and any
+/// operand that is itself a connective in parentheses.
+impl fmt::Display for Formula {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Formula::FTrue => write!(f, "true"),
+            Formula::FFalse => write!(f, "false"),
+            Formula::FEq(a1, a2) => write!(f, "{} = {}", a1, a2),
+            Formula::FLe(a1, a2) => write!(f, "{} <= {}", a1, a2),
+            Formula::FEven(a) => write!(f, "even({})", a),
+            Formula::FNot(p) => {
+                write!(f, "~")?;
+                write_operand(f, p, true)
+            }
+            Formula::FAnd(p, q) => write_binary(f, p, "&&", q),
+            Formula::FOr(p, q) => write_binary(f, p, "||", q),
+            Formula::FImplies(p, q) => write_binary(f, p, "->", q),
+        }
+    }
+}
+
+fn write_binary(f: &mut fmt::Formatter<'_>, p: &Formula, op: &str, q: &Formula) -> fmt::Result {
+    write_operand(f, p, false)?;
+    write!(f, " {} ", op)?;
+    write_operand(f, q, false)
+}
+
+/// Write `p` in parentheses if it is a connective, or, under a `~`, if it
+/// is anything that isn't atomic.
+fn write_operand(f: &mut fmt::Formatter<'_>, p: &Formula, negated: bool) -> fmt::Result {
+    let atomic = match p {
+        Formula::FTrue | Formula::FFalse | Formula::FEven(_) | Formula::FNot(_) => true,
+        Formula::FEq(..) | Formula::FLe(..) => !negated,
+        _ => false,
+    };
+    if atomic {
+        write!(f, "{}", p)
+    } else {
+        write!(f, "({})", p)
+    }
+}
+
+/// A property of states, `Assertion := state -> Prop` in the book.
+#[derive(Clone)]
+pub enum Assertion {
+    Formula(Formula),
+    /// Any predicate, with a name to show it by.
+    Pred(String, Rc<dyn Fn(&State) -> bool>),
+}
+
+impl Assertion {
+    pub fn pred(name: &str, p: impl Fn(&State) -> bool + 'static) -> Assertion {
+        Assertion::Pred(name.to_string(), Rc::new(p))
+    }
+
+    /// Whether the assertion is true in `st`.
+    pub fn holds(&self, st: &State) -> bool {
+        match self {
+            Assertion::Formula(p) => p.holds(st),
+            Assertion::Pred(_, p) => p(st),
+        }
+    }
+
+    /// The assertion's formula, if it is one.
+    pub fn formula(&self) -> Option<&Formula> {
+        match self {
+            Assertion::Formula(p) => Some(p),
+            Assertion::Pred(..) => None,
+        }
+    }
+
+    /// The variables the assertion is known to mention: none for a
+    /// predicate.
+    pub fn vars(&self) -> BTreeSet<String> {
+        self.formula().map_or_else(BTreeSet::new, Formula::vars)
+    }
+
+    /// The assertion with each of the variables in `values` fixed to its
+    /// number: substituted into a formula, and set in the state a
+    /// predicate is given.
+    pub fn instantiate(&self, values: &[(&str, i64)]) -> Assertion {
+        match self {
+            Assertion::Formula(p) => Assertion::Formula(p.instantiate(values)),
+            Assertion::Pred(name, p) => {
+                let shown: Vec<String> =
+                    values.iter().map(|(x, n)| format!("{}={}", x, n)).collect();
+                let values: Vec<(String, i64)> =
+                    values.iter().map(|(x, n)| (x.to_string(), *n)).collect();
+                let p = p.clone();
+                Assertion::pred(&format!("{} [{}]", name, shown.join(", ")), move |st| {
+                    let st = values
+                        .iter()
+                        .fold(st.clone(), |st, (x, n)| tm_update(st, x.clone(), *n));
+                    p(&st)
+                })
+            }
+        }
+    }
+}
+
+impl From<Formula> for Assertion {
+    fn from(p: Formula) -> Assertion {
+        Assertion::Formula(p)
+    }
+}
+
+impl From<Bexp> for Assertion {
+    fn from(b: Bexp) -> Assertion {
+        Assertion::Formula(Formula::from(&b))
+    }
+}
+
+impl fmt::Display for Assertion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Assertion::Formula(p) => write!(f, "{}", p),
+            Assertion::Pred(name, _) => write!(f, "{}", name),
+        }
+    }
+}
+
+impl fmt::Debug for Assertion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Assertion::Formula(p) => f.debug_tuple("Formula").field(p).finish(),
+            Assertion::Pred(name, _) => f.debug_tuple("Pred").field(name).finish(),
+        }
+    }
+}
+
+/// `hyp ->> concl`: every state satisfying `hyp` satisfies `concl`. The
+/// side conditions of a proof, which hold or not without any program.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Implication {
+    pub hyp: Formula,
+    pub concl: Formula,
+}
+
+impl Implication {
+    pub fn new(hyp: Formula, concl: Formula) -> Implication {
+        Implication { hyp, concl }
+    }
+
+    /// Whether `st` is no counterexample: it satisfies `concl` or not
+    /// `hyp`.
+    pub fn holds(&self, st: &State) -> bool {
+        !self.hyp.holds(st) || self.concl.holds(st)
+    }
+
+    /// The implication for particular values of some of its variables.
+    /// Valid implications stay valid, so this is for looking at one
+    /// instance of those about ghosts.
+    pub fn instantiate(&self, values: &[(&str, i64)]) -> Implication {
+        Implication::new(self.hyp.instantiate(values), self.concl.instantiate(values))
+    }
+}
+
+impl fmt::Display for Implication {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ->> {}", self.hyp, self.concl)
+    }
+}
+
+/// The triple `{{pre}} com {{post}}`: if `com` starts in a state
+/// satisfying `pre` and finishes, it finishes in one satisfying `post`,
+/// for every value of the `ghosts`.
+#[derive(Debug, Clone)]
+pub struct HoareTriple {
+    pub pre: Assertion,
+    pub com: Com,
+    pub post: Assertion,
+    /// The logical variables, which `com` doesn't assign.
+    pub ghosts: BTreeSet<String>,
+}
+
+/// How much of a `check_on` actually tested the triple.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TripleCheck {
+    /// Runs that satisfied the precondition and finished, each ending in a
+    /// state satisfying the postcondition.
+    pub tested: usize,
+    /// States that didn't satisfy the precondition, so weren't run.
+    pub skipped: usize,
+    /// Runs that ran out of fuel, about which the triple says nothing.
+    pub diverged: usize,
+}
+
+/// A run refuting a triple.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TripleViolation {
+    /// Which of the given states it started from, counting from `0`.
+    pub case: usize,
+    /// The starting and final states on the variables the program and
+    /// formulas mention. The starting state is the given one shrunk with
+    /// `minimize_state` for as long as the run from it still refutes the
+    /// triple.
+    pub initial: Vec<(String, i64)>,
+    pub last: Vec<(String, i64)>,
+}
+
+impl fmt::Display for TripleViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let show = |st: &[(String, i64)]| {
+            let st: Vec<String> = st.iter().map(|(x, n)| format!("{}={}", x, n)).collect();
+            st.join(", ")
+        };
+        write!(
+            f,
+            "case {}: from {} the program ends in {}, breaking the postcondition",
+            self.case,
+            show(&self.initial),
+            show(&self.last)
+        )
+    }
+}
+
+impl HoareTriple {
+    pub fn new(pre: impl Into<Assertion>, com: Com, post: impl Into<Assertion>) -> HoareTriple {
+        HoareTriple {
+            pre: pre.into(),
+            com,
+            post: post.into(),
+            ghosts: BTreeSet::new(),
+        }
+    }
+
+    /// The triple with `ghosts` among its logical variables.
+    ///
+    /// Panics if the program assigns one of them.
+    pub fn with_ghosts(mut self, ghosts: &[&str]) -> HoareTriple {
+        let modified = self.com.modified();
+        for x in ghosts {
+            assert!(
+                !modified.contains(*x),
+                "`{}` assigns the ghost {}",
+                self.com,
+                x
+            );
+            self.ghosts.insert(x.to_string());
+        }
+        self
+    }
+
+    /// The instance of the triple with each ghost in `values` fixed to its
+    /// number, and no longer a ghost.
+    ///
+    /// Panics if one of them isn't a ghost.
+    pub fn instantiate(&self, values: &[(&str, i64)]) -> HoareTriple {
+        let mut ghosts = self.ghosts.clone();
+        for (x, _) in values {
+            assert!(ghosts.remove(*x), "{} is not a ghost of the triple", x);
+        }
+        HoareTriple {
+            pre: self.pre.instantiate(values),
+            com: self.com.clone(),
+            post: self.post.instantiate(values),
+            ghosts,
+        }
+    }
+
+    /// Test the triple on each of `states` satisfying the precondition,
+    /// running the program with `ceval_fuel` and at most `fuel` loop
+    /// iterations. A run ended by an uncaught exception counts as finished.
+    /// Passing tells how many runs were tested, since a triple tested on no
+    /// runs at all passes too. Ghosts take their values from the states.
+    /// A run that reaches a procedure call fails the whole check.
+    pub fn check_on(
+        &self,
+        states: &[State],
+        fuel: u64,
+    ) -> Result<Result<TripleCheck, TripleViolation>, Unsupported> {
+        let mut check = TripleCheck {
+            tested: 0,
+            skipped: 0,
+            diverged: 0,
+        };
+        for (case, st) in states.iter().enumerate() {
+            if let Err(v) = self.check_run(case, st, fuel, &mut check)? {
+                return Ok(Err(v));
+            }
+        }
+        Ok(Ok(check))
+    }
+
+    /// Like `check_on`, but run from each of `states` once for every way
+    /// of giving the ghosts values from `values`, since random states
+    /// seldom meet a precondition like `X = m`. A violation's `case` is
+    /// still the index of the state, and its `initial` has the ghosts'
+    /// values.
+    pub fn check_ghosts(
+        &self,
+        states: &[State],
+        values: &[i64],
+        fuel: u64,
+    ) -> Result<Result<TripleCheck, TripleViolation>, Unsupported> {
+        let mut check = TripleCheck {
+            tested: 0,
+            skipped: 0,
+            diverged: 0,
+        };
+        for (case, st) in states.iter().enumerate() {
+            let mut instances = vec![st.clone()];
+            for x in &self.ghosts {
+                instances = instances
+                    .iter()
+                    .flat_map(|st| values.iter().map(|n| tm_update(st.clone(), x.clone(), *n)))
+                    .collect();
+            }
+            for st in &instances {
+                if let Err(v) = self.check_run(case, st, fuel, &mut check)? {
+                    return Ok(Err(v));
+                }
+            }
+        }
+        Ok(Ok(check))
+    }
+
+    fn check_run(
+        &self,
+        case: usize,
+        st: &State,
+        fuel: u64,
+        check: &mut TripleCheck,
+    ) -> Result<Result<(), TripleViolation>, Unsupported> {
+        if !self.pre.holds(st) {
+            check.skipped += 1;
+            return Ok(Ok(()));
+        }
+        let Some(last) = ceval_fuel(st.clone(), &self.com, fuel)? else {
+            check.diverged += 1;
+            return Ok(Ok(()));
+        };
+        if !self.post.holds(&last) {
+            let refutes = |st: &State| self.refuted_from(st, fuel).is_some();
+            let vars: Vec<String> = self.values(st).into_iter().map(|(x, _)| x).collect();
+            let st = minimize_state(st, &vars, refutes);
+            let last = self
+                .refuted_from(&st, fuel)
+                .expect("the run still refutes the triple");
+            return Ok(Err(TripleViolation {
+                case,
+                initial: self.values(&st),
+                last: self.values(&last),
+            }));
+        }
+        check.tested += 1;
+        Ok(Ok(()))
+    }
+
+    /// The final state of the run from `st`, if it refutes the triple.
+    fn refuted_from(&self, st: &State, fuel: u64) -> Option<State> {
+        if !self.pre.holds(st) {
+            return None;
+        }
+        ceval_fuel(st.clone(), &self.com, fuel)
+            .ok()?
+            .filter(|last| !self.post.holds(last))
+    }
+
+    fn values(&self, st: &State) -> Vec<(String, i64)> {
+        let mut vars = self.com.vars();
+        vars.extend(self.pre.vars());
+        vars.extend(self.post.vars());
+        vars.extend(self.ghosts.iter().cloned());
+        vars.into_iter().map(|x| (x.clone(), st(&x))).collect()
+    }
+}
+
+impl fmt::Display for HoareTriple {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{{{{{}}}}} {} {{{{{}}}}}", self.pre, self.com, self.post)
+    }
+}
+
+#[cfg(test)]
+mod test_imp_hoare {
+    use super::*;
+    use crate::imp::{parse_bexp, parse_com, random_state};
+    use crate::rng::Rng;
+    use crate::state;
+    use crate::state::lookup;
+
+    fn states(n: usize) -> Vec<State> {
+        let mut rng = Rng::new(3);
+        (0..n)
+            .map(|_| random_state(&mut rng, &["X", "Y", "Z"]))
+            .collect()
+    }
+
+    #[test]
+    fn test_formulas() {
+        let p = Formula::implies(
+            Formula::from(parse_bexp("X <= 3").unwrap()),
+            Formula::or(
+                Formula::eq(Aexp::var("Y"), Aexp::num(0)),
+                Formula::not(Formula::le(Aexp::var("X"), Aexp::var("Y"))),
+            ),
+        );
+        assert_eq!(p.to_string(), "X <= 3 -> (Y = 0 || ~(X <= Y))");
+        assert!(p.holds(&state! {"X" => 5}));
+        assert!(p.holds(&state! {"X" => 3, "Y" => 2}));
+        assert!(!p.holds(&state! {"X" => 1, "Y" => 2}));
+        assert_eq!(p.vars(), BTreeSet::from(["X".to_string(), "Y".to_string()]));
+        let b = parse_bexp("~(X = 1) && true").unwrap();
+        assert_eq!(Formula::from(&b).to_string(), b.to_string());
+    }
+
+    #[test]
+    fn test_valid_triples() {
+        // {{X <= 5}} X := X + 1 {{X <= 6}}, from the Hoare chapter.
+        let triple = HoareTriple::new(
+            parse_bexp("X <= 5").unwrap(),
+            parse_com("X := X + 1").unwrap(),
+            parse_bexp("X <= 6").unwrap(),
+        );
+        assert_eq!(triple.to_string(), "{{X <= 5}} X := X + 1 {{X <= 6}}");
+        let check = triple.check_on(&states(200), 100).unwrap().unwrap();
+        assert!(check.tested > 50);
+        assert_eq!(check.tested + check.skipped, 200);
+
+        // A predicate the formulas can't say: Z ends up the larger of X
+        // and Y.
+        let max = HoareTriple::new(
+            Formula::FTrue,
+            parse_com("if X <= Y then Z := Y else Z := X end").unwrap(),
+            Assertion::pred("Z = max(X, Y)", |st| {
+                lookup(st, "Z") == lookup(st, "X").max(lookup(st, "Y"))
+            }),
+        );
+        assert_eq!(
+            max.check_on(&states(100), 100).unwrap().unwrap().tested,
+            100
+        );
+
+        // Nothing is said about runs that don't finish.
+        let forever = HoareTriple::new(
+            Formula::FTrue,
+            parse_com("while true do skip end").unwrap(),
+            Formula::FFalse,
+        );
+        let check = forever.check_on(&states(5), 100).unwrap().unwrap();
+        assert_eq!((check.tested, check.diverged), (0, 5));
+    }
+
+    #[test]
+    fn test_violation() {
+        let triple = HoareTriple::new(
+            parse_bexp("0 <= X").unwrap(),
+            parse_com("Y := X - 1").unwrap(),
+            parse_bexp("1 <= Y").unwrap(),
+        );
+        let violation = triple
+            .check_on(&[state! {"X" => 7}, state! {"X" => 1}], 100)
+            .unwrap()
+            .unwrap_err();
+        assert_eq!(violation.case, 1);
+        assert_eq!(
+            violation.to_string(),
+            "case 1: from X=0, Y=0 the program ends in X=0, Y=-1, breaking the postcondition"
+        );
+
+        // A random counterexample is shrunk to a readable one.
+        let max = HoareTriple::new(
+            Formula::FTrue,
+            parse_com("if X <= Y then Z := Y else Z := X end").unwrap(),
+            parse_bexp("Z = Y").unwrap(),
+        );
+        let violation = max.check_on(&states(100), 100).unwrap().unwrap_err();
+        let initial: Vec<String> = violation
+            .initial
+            .iter()
+            .map(|(x, n)| format!("{}={}", x, n))
+            .collect();
+        assert_eq!(initial, ["X=0", "Y=-1", "Z=0"]);
+    }
+
+    #[test]
+    fn test_ghosts() {
+        // {{X = m}} X := X + 1 {{X = m + 1}}, with `m` a logical variable.
+        let triple = HoareTriple::new(
+            parse_bexp("X = m").unwrap(),
+            parse_com("X := X + 1").unwrap(),
+            parse_bexp("X = m + 1").unwrap(),
+        )
+        .with_ghosts(&["m"]);
+        // Random values for `m` too seldom meet the precondition, but
+        // giving it each of a few does.
+        let values: Vec<i64> = (-20..=20).collect();
+        let check = triple
+            .check_ghosts(&states(100), &values, 100)
+            .unwrap()
+            .unwrap();
+        assert!(check.tested >= 90);
+        assert_eq!(check.tested + check.skipped, 100 * values.len());
+
+        let instance = triple.instantiate(&[("m", 3)]);
+        assert_eq!(instance.to_string(), "{{X = 3}} X := X + 1 {{X = 3 + 1}}");
+        assert!(instance.ghosts.is_empty());
+        assert_eq!(
+            instance
+                .check_on(&[state! {"X" => 3}], 100)
+                .unwrap()
+                .unwrap()
+                .tested,
+            1
+        );
+
+        let wrong = HoareTriple::new(
+            parse_bexp("X = m").unwrap(),
+            parse_com("X := X + 1").unwrap(),
+            parse_bexp("X = m + 2").unwrap(),
+        )
+        .with_ghosts(&["m"]);
+        let violation = wrong
+            .check_ghosts(&[state! {"X" => 4}], &[3, 4], 100)
+            .unwrap()
+            .unwrap_err();
+        assert_eq!(
+            violation.to_string(),
+            "case 0: from X=4, m=4 the program ends in X=5, m=4, breaking the postcondition"
+        );
+
+        // A predicate sees the instance's value in the state.
+        let pred = Assertion::pred("X = m", |st| lookup(st, "X") == lookup(st, "m"));
+        let pred = pred.instantiate(&[("m", 2)]);
+        assert_eq!(pred.to_string(), "X = m [m=2]");
+        assert!(pred.holds(&state! {"X" => 2, "m" => 7}));
+    }
+
+    #[test]
+    #[should_panic(expected = "assigns the ghost m")]
+    fn test_assigned_ghost() {
+        let c = parse_com("try throw 1 catch m do skip end").unwrap();
+        assert_eq!(c.modified(), BTreeSet::from(["m".to_string()]));
+        HoareTriple::new(Formula::FTrue, c, Formula::FTrue).with_ghosts(&["m"]);
+    }
+
+    #[test]
+    fn test_calls_are_unsupported() {
+        let t = HoareTriple::new(
+            Formula::FTrue,
+            parse_com("X := f(1)").unwrap(),
+            Formula::FTrue,
+        );
+        assert_eq!(
+            t.check_on(&states(5), 100).err(),
+            Some(Unsupported("procedure calls"))
+        );
+        let t = t.with_ghosts(&["m"]);
+        assert_eq!(
+            t.check_ghosts(&states(5), &[0, 1], 100).err(),
+            Some(Unsupported("procedure calls"))
+        );
+    }
+}
@@ -0,0 +1,530 @@
+//! Loop invariants, tested rather than proved. For `{{P}} while b do c end
+//! {{Q}}` an invariant `I` has three obligations, the premises of the
+//! while rule with consequence around it: `P` implies `I` (initiation),
+//! `{{I && b}} c {{I}}` (preservation), and `I && ~b` implies `Q`
+//! (sufficiency). `check_loop_invariant` tries each on sample states,
+//! and `infer_invariants` guesses invariants and keeps those that pass.
+
+use std::collections::BTreeSet;
+use std::fmt;
+
+use super::debugger::focus;
+use super::desugar::lower_for;
+use super::random::random_state;
+use super::smallstep::{step, Config};
+use super::wp::collect_loops;
+use super::{beval, ceval_fuel, Aexp, Assertion, Bexp, Com, Formula, Unsupported};
+use crate::map::tm_update;
+use crate::rng::Rng;
+use crate::state::{empty_state, State};
+
+/// Which obligation of an invariant failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Obligation {
+    /// The precondition doesn't imply the invariant.
+    Initiation,
+    /// An iteration of the body doesn't keep the invariant.
+    Preservation,
+    /// Leaving the loop with the invariant doesn't give the postcondition.
+    Sufficiency,
+}
+
+impl fmt::Display for Obligation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Obligation::Initiation => write!(f, "initiation"),
+            Obligation::Preservation => write!(f, "preservation"),
+            Obligation::Sufficiency => write!(f, "sufficiency"),
+        }
+    }
+}
+
+/// A sample state on which an invariant fails an obligation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvariantFailure {
+    pub obligation: Obligation,
+    /// The state, on the variables the loop and assertions mention.
+    pub witness: Vec<(String, i64)>,
+    /// For preservation, the state the body ends in.
+    pub after: Option<Vec<(String, i64)>>,
+}
+
+impl fmt::Display for InvariantFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let show = |st: &[(String, i64)]| {
+            let st: Vec<String> = st.iter().map(|(x, n)| format!("{}={}", x, n)).collect();
+            st.join(", ")
+        };
+        write!(f, "{} fails ", self.obligation)?;
+        match (&self.obligation, &self.after) {
+            (Obligation::Initiation, _) => write!(
+                f,
+                "at {}: the precondition holds but the invariant doesn't",
+                show(&self.witness)
+            ),
+            (Obligation::Preservation, Some(after)) => write!(
+                f,
+                "from {}: the body ends in {}, where the invariant doesn't hold",
+                show(&self.witness),
+                show(after)
+            ),
+            _ => write!(
+                f,
+                "at {}: the loop ends with the invariant but not the postcondition",
+                show(&self.witness)
+            ),
+        }
+    }
+}
+
+/// Test `inv` as an invariant of `while guard do body end` between `pre`
+/// and `post`, on each of `samples`: initiation on those satisfying `pre`,
+/// preservation on those satisfying `inv` and `guard`, running the body
+/// with at most `fuel` loop iterations, and sufficiency on those
+/// satisfying `inv` but not `guard`. A body that runs out of fuel proves
+/// nothing; one that ends in a `break` or `continue`, or an uncaught
+/// `throw`, is held to the invariant too, which is more than `break`
+/// needs. The obligations are tried in that order on each sample before
+/// the next. A body that reaches a procedure call fails the whole check.
+pub fn check_loop_invariant(
+    pre: &Assertion,
+    inv: &Assertion,
+    guard: &Bexp,
+    body: &Com,
+    post: &Assertion,
+    samples: &[State],
+    fuel: u64,
+) -> Result<Result<(), InvariantFailure>, Unsupported> {
+    let mut vars: BTreeSet<String> = guard.vars();
+    vars.extend(body.vars());
+    for a in [pre, inv, post] {
+        vars.extend(a.vars());
+    }
+    let values = |st: &State| vars.iter().map(|x| (x.clone(), st(x))).collect();
+    let failure = |obligation, st: &State, after: Option<&State>| InvariantFailure {
+        obligation,
+        witness: values(st),
+        after: after.map(values),
+    };
+    for st in samples {
+        if pre.holds(st) && !inv.holds(st) {
+            return Ok(Err(failure(Obligation::Initiation, st, None)));
+        }
+        if !inv.holds(st) {
+            continue;
+        }
+        if beval(st, guard) {
+            if let Some(after) = ceval_fuel(st.clone(), body, fuel)? {
+                if !inv.holds(&after) {
+                    return Ok(Err(failure(Obligation::Preservation, st, Some(&after))));
+                }
+            }
+        } else if !post.holds(st) {
+            return Ok(Err(failure(Obligation::Sufficiency, st, None)));
+        }
+    }
+    Ok(Ok(()))
+}
+
+/// How many steps each run of `infer_invariants` takes at most, and how
+/// many iterations the bodies it checks may take.
+const STEPS: usize = 2_000;
+const FUEL: u64 = 50;
+
+/// Guess an invariant for each loop of `com`, in the order
+/// `wp_with_invariants` wants them: each loop before those inside it and
+/// those after it. This is a heuristic, in the style of Daikon and
+/// Houdini, and nothing it returns is proved:
+///
+/// - `com` is run, in small steps, from small and random states
+///   satisfying `pre`, recording the states each loop's test is reached
+///   in.
+/// - The candidates are the equations `X = n`, and `a*X + b*Y + c*Z = n`
+///   with coefficients from -2 to 2 and `n` a constant; the bounds
+///   `0 <= X` and `X <= Y`; and the conjuncts of `pre` and `post`. They
+///   range over all the variables of `com`, `pre` and `post`, since an
+///   invariant often relates what a loop changes to what it doesn't.
+///   Those true in every recorded state stay.
+/// - The conjunction of what is left is tested for preservation with
+///   `check_loop_invariant`, on the recorded states and random ones, and
+///   candidates false after a failing iteration are dropped until it
+///   passes.
+///
+/// So an invariant is only found if it is a conjunction of candidates,
+/// and one can be missed, or a wrong one kept, if the samples are
+/// unlucky. A loop never reached gets `true`. Check the result, for
+/// instance by discharging the side conditions of `wp_with_invariants`.
+/// The sample runs are taken in small steps, so reaching a procedure call
+/// or an array write is an error, as in `step`.
+pub fn infer_invariants(
+    com: &Com,
+    pre: &Formula,
+    post: &Formula,
+) -> Result<Vec<Formula>, Unsupported> {
+    let mut loops = Vec::new();
+    collect_loops(com, &mut loops);
+    // Each loop as the `while` that small steps run, which for a `for`
+    // loop is the one it desugars to.
+    let heads: Vec<Com> = loops
+        .iter()
+        .map(|c| match c {
+            Com::CWhile(..) => (*c).clone(),
+            Com::CFor(..) => match lower_for(c) {
+                Com::CSeq(_, w) => *w,
+                _ => unreachable!("a for loop desugars to init; while"),
+            },
+            _ => unreachable!("collect_loops only finds while and for loops"),
+        })
+        .collect();
+    let mut all_vars: BTreeSet<String> = com.vars();
+    all_vars.extend(pre.vars());
+    all_vars.extend(post.vars());
+    let vars: Vec<&str> = all_vars.iter().map(String::as_str).collect();
+    let mut rng = Rng::new(0);
+    let mut seen = vec![Vec::new(); heads.len()];
+    for st in initial_states(&vars, pre, &mut rng) {
+        record_heads(st, com, &heads, &mut seen)?;
+    }
+    let mut extra = Vec::new();
+    for f in [pre, post] {
+        conjuncts(f, &mut extra);
+    }
+    heads
+        .iter()
+        .zip(&seen)
+        .map(|(head, seen)| {
+            let Com::CWhile(guard, body) = head else {
+                unreachable!("heads are while loops")
+            };
+            let mut samples = seen.clone();
+            samples.extend((0..200).map(|_| random_state(&mut rng, &vars)));
+            let candidates = candidates(&all_vars, seen, &extra);
+            houdini(candidates, guard, body, &samples)
+        })
+        .collect()
+}
+
+/// Up to 50 states satisfying `pre`: small ones first, then random ones.
+fn initial_states(vars: &[&str], pre: &Formula, rng: &mut Rng) -> Vec<State> {
+    let small = [0, 1, 2, 3, -1];
+    // Every combination of small values on up to four variables, and more
+    // of them set to 0.
+    let n = vars.len().min(4);
+    let mut states: Vec<State> = (0..small.len().pow(n as u32))
+        .map(|mut i| {
+            vars.iter().enumerate().fold(empty_state(), |st, (k, x)| {
+                let v = if k < n { small[i % small.len()] } else { 0 };
+                i /= small.len();
+                tm_update(st, x.to_string(), v)
+            })
+        })
+        .filter(|st| pre.holds(st))
+        .take(40)
+        .collect();
+    for _ in 0..1_000 {
+        if states.len() >= 50 {
+            break;
+        }
+        let st = random_state(rng, vars);
+        if pre.holds(&st) {
+            states.push(st);
+        }
+    }
+    states
+}
+
+/// Run `c` from `st`, adding to `seen[k]` each state in which a loop
+/// structurally equal to `heads[k]` is about to test its condition.
+fn record_heads(
+    st: State,
+    c: &Com,
+    heads: &[Com],
+    seen: &mut [Vec<State>],
+) -> Result<(), Unsupported> {
+    let (mut st, mut c) = (st, Config::from(c.clone()));
+    for _ in 0..STEPS {
+        if let Some(k) = focus(&c.0).and_then(|f| heads.iter().position(|h| h == f)) {
+            if seen[k].len() < 200 {
+                seen[k].push(st.clone());
+            }
+        }
+        match step(&st, &c)? {
+            Some((st1, c1)) => (st, c) = (st1, c1),
+            None => return Ok(()),
+        }
+    }
+    Ok(())
+}
+
+fn conjuncts(f: &Formula, out: &mut Vec<Formula>) {
+    match f {
+        Formula::FAnd(p, q) => {
+            conjuncts(p, out);
+            conjuncts(q, out);
+        }
+        Formula::FTrue => {}
+        _ => out.push(f.clone()),
+    }
+}
+
+/// The candidates on `vars` true in every state of `seen`.
+fn candidates(vars: &BTreeSet<String>, seen: &[State], extra: &[Formula]) -> Vec<Formula> {
+    let Some(first) = seen.first() else {
+        return Vec::new();
+    };
+    let all = |f: &Formula| seen.iter().all(|st| f.holds(st));
+    let constant = |x: &String| seen.iter().all(|st| st(x) == first(x));
+    let mut found: Vec<Formula> = Vec::new();
+    let mut add = |f: Formula| {
+        if !found.contains(&f) && all(&f) {
+            found.push(f);
+        }
+    };
+    let vars: Vec<&String> = vars.iter().collect();
+    for (i, x) in vars.iter().enumerate() {
+        if constant(x) {
+            add(Formula::eq(Aexp::var(x), Aexp::num(first(x))));
+            continue;
+        }
+        add(Formula::le(Aexp::num(0), Aexp::var(x)));
+        for y in vars[i + 1..].iter().filter(|y| !constant(y)) {
+            add(Formula::le(Aexp::var(x), Aexp::var(y)));
+            add(Formula::le(Aexp::var(y), Aexp::var(x)));
+        }
+    }
+    // `cx*X + cy*Y + cz*Z = n` for coefficients `cx` of 1 or 2 and `cy`
+    // and `cz` of -2 to 2, not all even, over the variables that change;
+    // `cz` is 0 for two variables.
+    let changing: Vec<&String> = vars.into_iter().filter(|x| !constant(x)).collect();
+    let coefficients = [1, -1, 2, -2];
+    for (i, x) in changing.iter().enumerate() {
+        for (j, y) in changing.iter().enumerate().skip(i + 1) {
+            for z in changing[j + 1..].iter().map(Some).chain([None]) {
+                let zs: &[i64] = if z.is_some() { &coefficients } else { &[0] };
+                for (cx, cy, cz) in [1, 2]
+                    .into_iter()
+                    .flat_map(|cx| coefficients.map(|cy| (cx, cy)))
+                    .flat_map(|(cx, cy)| zs.iter().map(move |cz| (cx, cy, *cz)))
+                {
+                    if [cx, cy, cz].iter().all(|c| c % 2 == 0) {
+                        continue;
+                    }
+                    let terms: Vec<(&String, i64)> = [(*x, cx), (*y, cy)]
+                        .into_iter()
+                        .chain(z.map(|z| (*z, cz)))
+                        .collect();
+                    let value = |st: &State| {
+                        terms
+                            .iter()
+                            .fold(0i64, |n, (x, c)| n.wrapping_add(c.wrapping_mul(st(x))))
+                    };
+                    add(linear(&terms, value(first)));
+                }
+            }
+        }
+    }
+    for f in extra {
+        add(f.clone());
+    }
+    found
+}
+
+/// `sum of c*X over terms = n`, written without negative coefficients or
+/// a coefficient of 1.
+fn linear(terms: &[(&String, i64)], n: i64) -> Formula {
+    let side = |positive: bool, n: i64| {
+        let terms = terms
+            .iter()
+            .filter(|(_, c)| (*c > 0) == positive)
+            .map(|(x, c)| match c.abs() {
+                1 => Aexp::var(x),
+                c => Aexp::mult(Aexp::num(c), Aexp::var(x)),
+            });
+        match (terms.reduce(Aexp::plus), n) {
+            (None, n) => Aexp::num(n),
+            (Some(a), 0) => a,
+            (Some(a), n) => Aexp::plus(a, Aexp::num(n)),
+        }
+    };
+    if n >= 0 {
+        Formula::eq(side(true, 0), side(false, n))
+    } else {
+        Formula::eq(side(true, n.wrapping_neg()), side(false, 0))
+    }
+}
+
+/// Drop candidates until their conjunction is preserved by `body` under
+/// `guard` on `samples`.
+fn houdini(
+    mut candidates: Vec<Formula>,
+    guard: &Bexp,
+    body: &Com,
+    samples: &[State],
+) -> Result<Formula, Unsupported> {
+    loop {
+        let inv = candidates
+            .iter()
+            .cloned()
+            .reduce(Formula::and)
+            .unwrap_or(Formula::FTrue);
+        // With `false` before and `true` after, only preservation can fail.
+        let result = check_loop_invariant(
+            &Formula::FFalse.into(),
+            &inv.clone().into(),
+            guard,
+            body,
+            &Formula::FTrue.into(),
+            samples,
+            FUEL,
+        )?;
+        let Err(InvariantFailure {
+            after: Some(after), ..
+        }) = result
+        else {
+            return Ok(inv);
+        };
+        let after = after
+            .into_iter()
+            .fold(empty_state(), |st, (x, n)| tm_update(st, x, n));
+        candidates.retain(|f| f.holds(&after));
+    }
+}
+
+#[cfg(test)]
+mod test_imp_invariants {
+    use super::*;
+    use crate::imp::{discharge, parse_bexp, parse_com, wp_with_invariants, Strategy, Verdict};
+    use crate::rng::Rng;
+    use crate::state;
+
+    fn a(src: &str) -> Assertion {
+        parse_bexp(src).unwrap().into()
+    }
+
+    /// Random states, which seldom satisfy the precondition, and some
+    /// that do.
+    fn samples() -> Vec<State> {
+        let mut rng = Rng::new(29);
+        let mut samples: Vec<State> = (0..500)
+            .map(|_| random_state(&mut rng, &["X", "Y", "N"]))
+            .collect();
+        samples.extend((0..5).map(|n| state! { "X" => n, "Y" => 0, "N" => n }));
+        samples
+    }
+
+    /// `{{X = N && Y = 0}} while ~(X = 0) do X := X - 1; Y := Y + 1 end
+    /// {{Y = N}}`, against `inv`.
+    fn check(inv: Assertion) -> Result<(), InvariantFailure> {
+        check_loop_invariant(
+            &a("X = N && Y = 0"),
+            &inv,
+            &parse_bexp("~(X = 0)").unwrap(),
+            &parse_com("X := X - 1; Y := Y + 1").unwrap(),
+            &a("Y = N"),
+            &samples(),
+            10,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_good_invariant() {
+        assert_eq!(check(a("X + Y = N")), Ok(()));
+    }
+
+    #[test]
+    fn test_each_obligation_can_fail() {
+        // Too strong to start with.
+        let err = check(a("X + Y = N && X = 0")).unwrap_err();
+        assert_eq!(err.obligation, Obligation::Initiation);
+        assert!(err.to_string().starts_with("initiation fails at N="));
+
+        // True at the start, but not kept.
+        let err = check(a("Y = 0")).unwrap_err();
+        assert_eq!(err.obligation, Obligation::Preservation);
+        let after = err.after.clone().unwrap();
+        assert_eq!(
+            after.iter().find(|(x, _)| x == "Y"),
+            Some(&("Y".to_string(), 1))
+        );
+        assert!(err.to_string().contains("the body ends in"));
+
+        // Kept, but too weak to give the postcondition.
+        let err = check(Formula::FTrue.into()).unwrap_err();
+        assert_eq!(err.obligation, Obligation::Sufficiency);
+        let x = err.witness.iter().find(|(x, _)| x == "X").unwrap().1;
+        assert_eq!(x, 0);
+    }
+
+    fn f(src: &str) -> Formula {
+        parse_bexp(src).unwrap().into()
+    }
+
+    fn infer(c: &str, pre: &str, post: &str) -> Vec<String> {
+        let invs = infer_invariants(&parse_com(c).unwrap(), &f(pre), &f(post)).unwrap();
+        invs.iter().map(Formula::to_string).collect()
+    }
+
+    #[test]
+    fn test_infer_counting_loop() {
+        let c = parse_com("while ~(X = 0) do X := X - 1; Y := Y + 1 end").unwrap();
+        let invs = infer_invariants(&c, &f("X = N && Y = 0"), &f("Y = N")).unwrap();
+        assert_eq!(invs.len(), 1);
+        assert_eq!(invs[0].to_string(), "(X <= N && 0 <= Y) && N = X + Y");
+        // What it found is good enough to verify the loop with.
+        let (pre, mut side) = wp_with_invariants(&c, &f("Y = N"), &invs).unwrap();
+        side.push(crate::imp::Implication::new(f("X = N && Y = 0"), pre));
+        let verdicts = discharge(&side, &Strategy::Exhaustive { lo: -3, hi: 3 });
+        assert!(verdicts.iter().all(Verdict::holds), "{:?}", verdicts);
+    }
+
+    #[test]
+    fn test_infer_coefficients_and_loops() {
+        assert_eq!(
+            infer(
+                "Y := 0; Z := X; while ~(Z = 0) do Z := Z - 1; Y := Y + 2 end",
+                "0 <= X",
+                "Y = X + X"
+            ),
+            ["(((0 <= X && Z <= X) && 0 <= Y) && 0 <= Z) && 2 * X = Y + 2 * Z"]
+        );
+        // One per loop, `for` loops included, and `true` for a loop never
+        // reached.
+        assert_eq!(
+            infer(
+                "for I := 0; I <= 4; I := I + 1 do S := S + I end; \
+                 while 0 <= I do I := I - 1 end; \
+                 if I = 0 then while true do skip end else skip end",
+                "S = 0",
+                "true"
+            ),
+            ["0 <= I", "S = 10", "true"]
+        );
+    }
+
+    #[test]
+    fn test_calls_are_unsupported() {
+        let body = parse_com("X := f(X)").unwrap();
+        let guard = parse_bexp("~(X = 0)").unwrap();
+        let checked = check_loop_invariant(
+            &a("true"),
+            &a("true"),
+            &guard,
+            &body,
+            &a("true"),
+            &samples(),
+            10,
+        );
+        assert_eq!(checked.err(), Some(Unsupported("procedure calls")));
+        let c = parse_com("while ~(X = 0) do X := f(X) end").unwrap();
+        assert_eq!(
+            infer_invariants(&c, &f("true"), &f("true")).err(),
+            Some(Unsupported("procedure calls"))
+        );
+        let c = parse_com("while ~(X = 0) do a[X] := 1; X := X - 1 end").unwrap();
+        assert_eq!(
+            infer_invariants(&c, &f("true"), &f("true")).err(),
+            Some(Unsupported("arrays"))
+        );
+    }
+}
@@ -0,0 +1,285 @@
+//! Live variables. A variable is live at a point of a program if some way
+//! on from there reads it before writing it; walking each command
+//! backwards from what is live after it gives what is live before it.
+//! Unlike `eliminate_dead_code`, which works the same way but drops what it
+//! finds dead as it goes, this leaves the program alone and reports the
+//! sets for every line of its block layout (`{:#}`, numbered from `1`).
+//!
+//! A read counts even when it only feeds a dead assignment, so in
+//! `Y := X; Y := 1` the variable `X` is live at the start.
+
+use std::collections::BTreeSet;
+
+use super::dce::{loop_exits, Exits, Live};
+use super::pretty::{block_lines, seq_lines};
+use super::{Bexp, Com};
+
+/// The result of `liveness`.
+#[derive(Debug, Clone)]
+pub struct LivenessInfo {
+    /// For each line with a command starting on it, what is live before and
+    /// after that command.
+    lines: Vec<Option<(Live, Live)>>,
+    entry: Live,
+}
+
+impl LivenessInfo {
+    /// What is live before the command on `line` runs; for a loop, at its
+    /// test. `None` if no command starts on `line`.
+    pub fn live_in(&self, line: usize) -> Option<&BTreeSet<String>> {
+        Some(&self.lines.get(line.checked_sub(1)?)?.as_ref()?.0)
+    }
+
+    /// What is live once the command on `line` has run, wherever it goes
+    /// next: after a `break`, what is live after its loop.
+    pub fn live_out(&self, line: usize) -> Option<&BTreeSet<String>> {
+        Some(&self.lines.get(line.checked_sub(1)?)?.as_ref()?.1)
+    }
+
+    /// What is live when the program starts: the variables whose initial
+    /// values it may read.
+    pub fn entry(&self) -> &BTreeSet<String> {
+        &self.entry
+    }
+}
+
+/// Liveness in `c` when every variable it mentions is observed once it
+/// ends.
+pub fn liveness(c: &Com) -> LivenessInfo {
+    liveness_for(c, &c.vars())
+}
+
+/// Liveness in `c` when only `outputs` are observed once it ends.
+pub fn liveness_for(c: &Com, outputs: &BTreeSet<String>) -> LivenessInfo {
+    let mut lines = vec![None; block_lines(c)];
+    let exits = Exits {
+        brk: outputs,
+        cont: outputs,
+        throw: outputs,
+    };
+    let entry = live(c, outputs, exits, Some(1), &mut lines);
+    LivenessInfo { lines, entry }
+}
+
+/// What is live before `c`, given what is live after it, recording both
+/// for each command if `line`, the line `c` starts on, is given. Loops are
+/// walked several times before they settle, and each walk overwrites what
+/// the last recorded, so what is left is from the final one.
+fn live(
+    c: &Com,
+    out: &Live,
+    exits: Exits,
+    line: Option<usize>,
+    lines: &mut [Option<(Live, Live)>],
+) -> Live {
+    let (before, after) = match c {
+        Com::CSkip => (out.clone(), out.clone()),
+        Com::CBreak => (exits.brk.clone(), exits.brk.clone()),
+        Com::CContinue => (exits.cont.clone(), exits.cont.clone()),
+        Com::CAsgn(x, a) => {
+            let mut live = out.clone();
+            live.remove(x);
+            a.collect_vars(&mut live);
+            (live, out.clone())
+        }
+        Com::CHavoc(x) => {
+            let mut live = out.clone();
+            live.remove(x);
+            (live, out.clone())
+        }
+        Com::CCall(x, _, args) => {
+            let mut live = out.clone();
+            live.remove(x);
+            live.extend(exits.throw.iter().cloned());
+            for a in args {
+                a.collect_vars(&mut live);
+            }
+            (live, out.clone())
+        }
+        Com::CArrAsgn(_, i, a) => {
+            let mut live = out.clone();
+            i.collect_vars(&mut live);
+            a.collect_vars(&mut live);
+            (live, out.clone())
+        }
+        Com::CPrint(a) => {
+            let mut live = out.clone();
+            a.collect_vars(&mut live);
+            (live, out.clone())
+        }
+        Com::CThrow(a) => {
+            let mut live = exits.throw.clone();
+            a.collect_vars(&mut live);
+            (live, exits.throw.clone())
+        }
+        Com::CSeq(c1, c2) => {
+            let (line1, line2) = split(line, |line| seq_lines(c1, line));
+            let live2 = live(c2, out, exits, line2, lines);
+            return live(c1, &live2, exits, line1, lines);
+        }
+        Com::CIf(b, c1, c2) => {
+            let mut live1 = live(c1, out, exits, line.map(|l| l + 1), lines);
+            let line2 = line.map(|l| l + 2 + block_lines(c1));
+            live1.extend(live(c2, out, exits, line2, lines));
+            b.collect_vars(&mut live1);
+            (live1, out.clone())
+        }
+        Com::CWhile(b, body) => {
+            let head = live_loop(b, body, None, out, exits.throw, line, lines);
+            (head, out.clone())
+        }
+        Com::CFor(init, b, update, body) => {
+            let head = live_loop(b, body, Some(update), out, exits.throw, line, lines);
+            (live(init, &head, exits, None, lines), out.clone())
+        }
+        // Either branch may read what the other writes at any point, so
+        // what the other branch mentions is live throughout a branch, and
+        // everything either mentions at its end.
+        Com::CPar(c1, c2) => {
+            let mut all = out.clone();
+            all.extend(c.vars());
+            all.extend(exits.throw.iter().cloned());
+            let ends = Exits {
+                brk: &all,
+                cont: &all,
+                throw: &all,
+            };
+            let line2 = line.map(|l| l + 2 + block_lines(c1));
+            for (branch, other, line) in [(c1, c2, line.map(|l| l + 1)), (c2, c1, line2)] {
+                live(branch, &all, ends, line, lines);
+                if let Some(line) = line {
+                    let other = other.vars();
+                    for (before, after) in lines[line - 1..][..block_lines(branch)]
+                        .iter_mut()
+                        .flatten()
+                    {
+                        before.extend(other.iter().cloned());
+                        after.extend(other.iter().cloned());
+                    }
+                }
+            }
+            (all.clone(), out.clone())
+        }
+        Com::CTry(body, x, handler) => {
+            let line2 = line.map(|l| l + 2 + block_lines(body));
+            let mut caught = live(handler, out, exits, line2, lines);
+            caught.remove(x);
+            let exits = Exits {
+                throw: &caught,
+                ..exits
+            };
+            let before = live(body, out, exits, line.map(|l| l + 1), lines);
+            (before, out.clone())
+        }
+    };
+    if let Some(line) = line {
+        lines[line - 1] = Some((before.clone(), after));
+    }
+    before
+}
+
+/// What is live at the test of a loop testing `b`, with `update` run after
+/// each iteration for a `for` loop: the least set containing what the test
+/// reads, what is live after the loop, and what the body needs to leave
+/// live at the test again.
+fn live_loop(
+    b: &Bexp,
+    body: &Com,
+    update: Option<&Com>,
+    out: &Live,
+    throw: &Live,
+    line: Option<usize>,
+    lines: &mut [Option<(Live, Live)>],
+) -> Live {
+    let mut head = out.clone();
+    b.collect_vars(&mut head);
+    loop {
+        let after_body = match update {
+            Some(update) => live(update, &head, loop_exits(out, &head, throw), None, lines),
+            None => head.clone(),
+        };
+        let body_line = line.map(|l| l + 1);
+        let mut next = live(
+            body,
+            &after_body,
+            loop_exits(out, &after_body, throw),
+            body_line,
+            lines,
+        );
+        next.extend(head.iter().cloned());
+        if next == head {
+            return head;
+        }
+        head = next;
+    }
+}
+
+fn split(
+    line: Option<usize>,
+    f: impl FnOnce(usize) -> (usize, usize),
+) -> (Option<usize>, Option<usize>) {
+    match line.map(f) {
+        Some((line1, line2)) => (Some(line1), Some(line2)),
+        None => (None, None),
+    }
+}
+
+#[cfg(test)]
+mod test_imp_liveness {
+    use super::*;
+    use crate::imp::{eliminate_dead_code, parse_com};
+
+    fn set(vars: &[&str]) -> BTreeSet<String> {
+        vars.iter().map(|x| x.to_string()).collect()
+    }
+
+    #[test]
+    fn test_straight_line() {
+        let c = parse_com("T := X * 2; Y := T + 1; Z := X; T := 0").unwrap();
+        let info = liveness_for(&c, &set(&["Y", "Z"]));
+        assert_eq!(info.entry(), &set(&["X"]));
+        assert_eq!(info.live_in(2), Some(&set(&["T", "X"])));
+        assert_eq!(info.live_out(2), Some(&set(&["X", "Y"])));
+        // T isn't read after line 4, so it is dead there.
+        assert_eq!(info.live_out(4), Some(&set(&["Y", "Z"])));
+        assert_eq!(info.live_in(4), Some(&set(&["Y", "Z"])));
+        assert_eq!(info.live_in(5), None);
+        let info = liveness(&parse_com("Y := X; Y := 1").unwrap());
+        assert_eq!(info.entry(), &set(&["X"]));
+    }
+
+    #[test]
+    fn test_loops() {
+        let c = parse_com(
+            "S := 0; I := 0; while I <= N do if I = 2 then I := I + 1; continue else skip end; \
+             S := S + I; I := I + 1 end",
+        )
+        .unwrap();
+        let info = liveness_for(&c, &set(&["S"]));
+        assert_eq!(info.entry(), &set(&["N"]));
+        // Line 3 is the loop test: I and N for the test, S for afterwards.
+        assert_eq!(info.live_in(3), Some(&set(&["I", "N", "S"])));
+        assert_eq!(info.live_out(3), Some(&set(&["S"])));
+        // The `continue` goes back to the test.
+        assert_eq!(info.live_out(6), Some(&set(&["I", "N", "S"])));
+        let listing = c.to_pretty_string();
+        assert_eq!(listing.lines().nth(5).map(str::trim), Some("continue"));
+        assert_eq!(info.lines.len(), listing.lines().count());
+    }
+
+    #[test]
+    fn test_dead_stores() {
+        // An assignment whose variable is dead after it is what the
+        // dead-code pass drops.
+        let c = parse_com("X := 1; Y := 2; X := Y + 3").unwrap();
+        let info = liveness(&c);
+        assert!(!info.live_out(1).unwrap().contains("X"));
+        assert!(info.live_out(2).unwrap().contains("Y"));
+        assert_eq!(
+            eliminate_dead_code(&c),
+            parse_com("Y := 2; X := Y + 3").unwrap()
+        );
+        let c = parse_com("for I := 0; I <= 2; I := I + 1 do S := S + I end").unwrap();
+        assert_eq!(liveness(&c).entry(), &set(&["S"]));
+    }
+}
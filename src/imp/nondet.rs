@@ -0,0 +1,315 @@
+//! Nondeterministic evaluation: `havoc X` may set `X` to any number, so a
+//! program has a set of possible final states rather than one. Exploring
+//! every integer is impossible, so the choices range over a given finite
+//! domain, and loops over a fuel budget per execution path.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use super::{aeval, beval, Bexp, Com, Signal, Unsupported};
+use crate::map::tm_update;
+use crate::state::State;
+
+/// The states a program can reach: its final states for `ceval_nondet`,
+/// and every state along the way for `reachable_states`.
+#[derive(Clone)]
+pub struct Reachable {
+    /// One state per distinct outcome, in no particular order.
+    pub states: Vec<State>,
+    /// `false` if some execution ran out of fuel (or steps), in which case
+    /// `states` may be missing outcomes.
+    pub complete: bool,
+}
+
+impl Reachable {
+    /// The reachable combinations of values of `vars`, which is the easiest
+    /// way to compare two programs' outcomes.
+    pub fn values(&self, vars: &[&str]) -> BTreeSet<Vec<i64>> {
+        self.states
+            .iter()
+            .map(|st| vars.iter().map(|x| st(x)).collect())
+            .collect()
+    }
+}
+
+/// Every final state `c` can reach from `st` when each `havoc` picks a
+/// value from `domain`, with at most `fuel` loop iterations along any one
+/// execution. A `break` or `continue` outside any loop ends the program,
+/// as in `ceval`. The branches of a `par` interleave step by step, which
+/// `interleavings` explores, so a `par` is an error here.
+pub fn ceval_nondet(
+    st: State,
+    c: &Com,
+    domain: &[i64],
+    fuel: u64,
+) -> Result<Reachable, Unsupported> {
+    let mut explorer = Explorer {
+        domain,
+        vars: c.vars().into_iter().collect(),
+        complete: true,
+    };
+    let outcomes = explorer.exec(vec![Path { st, fuel }], c)?;
+    Ok(Reachable {
+        states: outcomes.into_iter().map(|(path, _)| path.st).collect(),
+        complete: explorer.complete,
+    })
+}
+
+/// One execution in progress.
+#[derive(Clone)]
+struct Path {
+    st: State,
+    fuel: u64,
+}
+
+struct Explorer<'a> {
+    domain: &'a [i64],
+    /// The program's variables; nothing else can differ between paths.
+    vars: Vec<String>,
+    complete: bool,
+}
+
+impl Explorer<'_> {
+    /// Merge paths that reached the same state with the same signal,
+    /// keeping the one with the most fuel left, since it can do everything
+    /// the others can. Without this, loops around a `havoc` blow up.
+    fn dedup(&self, outcomes: Vec<(Path, Signal)>) -> Vec<(Path, Signal)> {
+        let mut merged: BTreeMap<(Vec<i64>, Signal), Path> = BTreeMap::new();
+        for (path, signal) in outcomes {
+            let key: Vec<i64> = self.vars.iter().map(|x| (path.st)(x)).collect();
+            match merged.get(&(key.clone(), signal)) {
+                Some(other) if other.fuel >= path.fuel => {}
+                _ => {
+                    merged.insert((key, signal), path);
+                }
+            }
+        }
+        merged
+            .into_iter()
+            .map(|((_, signal), path)| (path, signal))
+            .collect()
+    }
+
+    fn exec(&mut self, paths: Vec<Path>, c: &Com) -> Result<Vec<(Path, Signal)>, Unsupported> {
+        let outcomes = match c {
+            Com::CSkip => paths.into_iter().map(|p| (p, Signal::Normal)).collect(),
+            Com::CBreak => paths.into_iter().map(|p| (p, Signal::Break)).collect(),
+            Com::CContinue => paths.into_iter().map(|p| (p, Signal::Continue)).collect(),
+            Com::CAsgn(x, a) => paths
+                .into_iter()
+                .map(|p| {
+                    let n = aeval(&p.st, a);
+                    let st = tm_update(p.st, x.clone(), n);
+                    (Path { st, ..p }, Signal::Normal)
+                })
+                .collect(),
+            // Output isn't part of an outcome, so `print` only has to be
+            // evaluated, and that can't fail.
+            Com::CPrint(_) => paths.into_iter().map(|p| (p, Signal::Normal)).collect(),
+            Com::CHavoc(x) => paths
+                .iter()
+                .flat_map(|p| {
+                    self.domain.iter().map(move |n| {
+                        let st = tm_update(p.st.clone(), x.clone(), *n);
+                        (Path { st, fuel: p.fuel }, Signal::Normal)
+                    })
+                })
+                .collect(),
+            Com::CSeq(c1, c2) => {
+                let (normal, mut interrupted) = split_normal(self.exec(paths, c1)?);
+                interrupted.extend(self.exec(normal, c2)?);
+                interrupted
+            }
+            Com::CIf(b, c1, c2) => {
+                let (yes, no): (Vec<Path>, Vec<Path>) =
+                    paths.into_iter().partition(|p| beval(&p.st, b));
+                let mut outcomes = self.exec(yes, c1)?;
+                outcomes.extend(self.exec(no, c2)?);
+                outcomes
+            }
+            Com::CWhile(b, body) => self.run_loop(paths, b, body, None)?,
+            Com::CFor(init, b, update, body) => {
+                let (normal, mut interrupted) = split_normal(self.exec(paths, init)?);
+                interrupted.extend(self.run_loop(normal, b, body, Some(update))?);
+                interrupted
+            }
+            Com::CCall(..) => return Err(Unsupported("procedure calls")),
+            Com::CArrAsgn(..) => return Err(Unsupported("arrays")),
+            Com::CPar(..) => return Err(Unsupported("par")),
+            Com::CThrow(a) => paths
+                .into_iter()
+                .map(|p| {
+                    let code = aeval(&p.st, a);
+                    (p, Signal::Throw(code))
+                })
+                .collect(),
+            Com::CTry(body, x, handler) => {
+                let mut outcomes = Vec::new();
+                let mut caught = Vec::new();
+                for (p, signal) in self.exec(paths, body)? {
+                    match signal {
+                        Signal::Throw(code) => caught.push(Path {
+                            st: tm_update(p.st, x.clone(), code),
+                            ..p
+                        }),
+                        _ => outcomes.push((p, signal)),
+                    }
+                }
+                outcomes.extend(self.exec(caught, handler)?);
+                outcomes
+            }
+        };
+        Ok(self.dedup(outcomes))
+    }
+
+    fn run_loop(
+        &mut self,
+        paths: Vec<Path>,
+        b: &Bexp,
+        body: &Com,
+        update: Option<&Com>,
+    ) -> Result<Vec<(Path, Signal)>, Unsupported> {
+        let mut done = Vec::new();
+        let mut active = paths;
+        while !active.is_empty() {
+            let (go, stop): (Vec<Path>, Vec<Path>) =
+                active.into_iter().partition(|p| beval(&p.st, b));
+            done.extend(stop.into_iter().map(|p| (p, Signal::Normal)));
+            let mut fueled = Vec::new();
+            for p in go {
+                if p.fuel == 0 {
+                    self.complete = false;
+                } else {
+                    fueled.push(Path {
+                        fuel: p.fuel - 1,
+                        ..p
+                    });
+                }
+            }
+            active = Vec::new();
+            for (p, signal) in self.exec(fueled, body)? {
+                match signal {
+                    Signal::Break => done.push((p, Signal::Normal)),
+                    Signal::Throw(_) => done.push((p, signal)),
+                    _ => active.push(p),
+                }
+            }
+            if let Some(update) = update {
+                let updated = self.exec(active, update)?;
+                active = Vec::new();
+                for (p, signal) in updated {
+                    match signal {
+                        Signal::Break => done.push((p, Signal::Normal)),
+                        Signal::Throw(_) => done.push((p, signal)),
+                        _ => active.push(p),
+                    }
+                }
+            }
+        }
+        Ok(done)
+    }
+}
+
+/// Separate the paths that finished normally from those carrying a
+/// `break` or `continue` outward.
+fn split_normal(outcomes: Vec<(Path, Signal)>) -> (Vec<Path>, Vec<(Path, Signal)>) {
+    let (normal, interrupted): (Vec<_>, Vec<_>) = outcomes
+        .into_iter()
+        .partition(|(_, signal)| *signal == Signal::Normal);
+    (normal.into_iter().map(|(p, _)| p).collect(), interrupted)
+}
+
+#[cfg(test)]
+mod test_imp_nondet {
+    use super::*;
+    use crate::imp::{ceval, parse_com};
+    use crate::state;
+
+    const DOMAIN: [i64; 3] = [0, 1, 2];
+
+    fn values(program: &str, vars: &[&str]) -> BTreeSet<Vec<i64>> {
+        let c = parse_com(program).unwrap();
+        let reachable = ceval_nondet(state! {}, &c, &DOMAIN, 100).unwrap();
+        assert!(reachable.complete);
+        reachable.values(vars)
+    }
+
+    #[test]
+    fn test_havoc_reaches_whole_domain() {
+        assert_eq!(
+            values("havoc X", &["X"]),
+            BTreeSet::from([vec![0], vec![1], vec![2]])
+        );
+    }
+
+    #[test]
+    fn test_havoc_order_does_not_matter() {
+        // pXY and pYX from the Equiv chapter are equivalent.
+        assert_eq!(
+            values("havoc X; havoc Y", &["X", "Y"]),
+            values("havoc Y; havoc X", &["X", "Y"])
+        );
+    }
+
+    #[test]
+    fn test_havoc_twice_is_not_copy() {
+        // ptwice and pcopy from the Equiv chapter are not: only the former
+        // can end with X and Y different.
+        let twice = values("havoc X; havoc Y", &["X", "Y"]);
+        let copy = values("havoc X; Y := X", &["X", "Y"]);
+        assert_eq!(twice.len(), 9);
+        assert_eq!(copy.len(), 3);
+        assert!(copy.is_subset(&twice));
+        assert!(twice.contains(&vec![0, 1]));
+    }
+
+    #[test]
+    fn test_loops_merge_paths() {
+        let reachable = values(
+            "X := 0; while X <= 9 do havoc Y; S := S + Y; X := X + 1 end",
+            &["S"],
+        );
+        // Ten draws from {0, 1, 2} sum to anything in 0..=20.
+        assert_eq!(reachable, (0..=20).map(|s| vec![s]).collect());
+        assert_eq!(
+            values(
+                "for I := 0; I <= 3; I := I + 1 do havoc X; if X = 1 then break else skip end end",
+                &["I"]
+            ),
+            (0..=4).map(|i| vec![i]).collect()
+        );
+    }
+
+    #[test]
+    fn test_out_of_fuel_is_reported() {
+        let c = parse_com("havoc X; while X = 1 do skip end").unwrap();
+        let reachable = ceval_nondet(state! {}, &c, &DOMAIN, 10).unwrap();
+        assert!(!reachable.complete);
+        assert_eq!(reachable.values(&["X"]), BTreeSet::from([vec![0], vec![2]]));
+    }
+
+    #[test]
+    fn test_deterministic_choice_is_reachable() {
+        let c = parse_com("havoc X; Y := X + 1").unwrap();
+        let st = ceval(state! {}, &c).unwrap();
+        let chosen = vec![st("X"), st("Y")];
+        assert!(values("havoc X; Y := X + 1", &["X", "Y"]).contains(&chosen));
+    }
+
+    #[test]
+    fn test_calls_are_unsupported() {
+        let c = parse_com("havoc X; Y := f(X)").unwrap();
+        assert_eq!(
+            ceval_nondet(state! {}, &c, &DOMAIN, 10).err(),
+            Some(Unsupported("procedure calls"))
+        );
+    }
+
+    #[test]
+    fn test_par_is_unsupported() {
+        let c = parse_com("par X := 1 with X := 2 end").unwrap();
+        assert_eq!(
+            ceval_nondet(state! {}, &c, &DOMAIN, 10).err(),
+            Some(Unsupported("par"))
+        );
+    }
+}
@@ -0,0 +1,156 @@
+//! Running `par` programs. A `par` has no one outcome: its branches' steps
+//! interleave in any order, so a race between them can end in several
+//! states. `interleavings` finds all of them, within a bound on the length
+//! of a run, and `run_random_schedule` follows one interleaving picked at
+//! random, as a real scheduler might.
+
+use std::collections::HashSet;
+
+use super::bmc::{successors, Search};
+use super::smallstep::{done, Running};
+use super::{Com, Reachable, Unsupported};
+use crate::rng::Rng;
+use crate::state::State;
+
+/// Every final state `c` can reach from `st` under some interleaving of its
+/// `par` branches, with each `havoc` picking from `domain` and no run
+/// longer than `bound` steps. `complete` is `false` if some run was cut
+/// off by the bound.
+pub fn interleavings(
+    c: &Com,
+    st: State,
+    domain: &[i64],
+    bound: usize,
+) -> Result<Reachable, Unsupported> {
+    let mut search = Search::new(c, st);
+    search.run(domain, bound, |_| false)?;
+    let mut seen = HashSet::new();
+    let states = search
+        .nodes
+        .iter()
+        .filter(|node| done(&node.com) && seen.insert(search.key(&node.st)))
+        .map(|node| node.st.clone())
+        .collect();
+    Ok(Reachable {
+        states,
+        complete: search.complete,
+    })
+}
+
+/// Run `c` from `st`, picking which `par` branch steps next, and which
+/// value of `domain` each `havoc` sets its variable to, uniformly at
+/// random. `None` if it hasn't finished within `max_steps` steps.
+pub fn run_random_schedule(
+    st: State,
+    c: &Com,
+    domain: &[i64],
+    rng: &mut Rng,
+    max_steps: usize,
+) -> Result<Option<State>, Unsupported> {
+    let (mut st, mut c) = (st, Running::Com(c.clone()));
+    for _ in 0..max_steps {
+        if done(&c) {
+            return Ok(Some(st));
+        }
+        let mut next = successors(&st, &c, domain)?;
+        let i = rng.below(next.len() as u64) as usize;
+        (st, c) = next.swap_remove(i);
+    }
+    Ok(done(&c).then_some(st))
+}
+
+#[cfg(test)]
+mod test_imp_par {
+    use std::collections::BTreeSet;
+
+    use super::*;
+    use crate::imp::{
+        analyze_intervals, ceval, check_invariant, liveness, parse_bexp, parse_com, AbsState,
+        Interval,
+    };
+    use crate::state::empty_state;
+
+    const RACE: &str = "par X := X + 1 with X := X + 1 end";
+
+    #[test]
+    fn test_race_has_one_outcome_per_interleaving() {
+        let c = parse_com(RACE).unwrap();
+        // Reading X is a step of its own, so both branches can read 0
+        // before either writes.
+        let reached = interleavings(&c, empty_state(), &[], 100).unwrap();
+        assert!(reached.complete);
+        assert_eq!(reached.values(&["X"]), BTreeSet::from([vec![1], vec![2]]));
+        // The other evaluators run the branches one after the other.
+        assert_eq!(ceval(empty_state(), &c).unwrap()("X"), 2);
+        let reached = interleavings(&c, empty_state(), &[], 3).unwrap();
+        assert!(!reached.complete);
+    }
+
+    #[test]
+    fn test_random_schedules_find_the_race() {
+        let c = parse_com(RACE).unwrap();
+        let outcomes: BTreeSet<i64> = (0..50)
+            .map(|seed| {
+                let st =
+                    run_random_schedule(empty_state(), &c, &[], &mut Rng::new(seed), 100).unwrap();
+                st.unwrap()("X")
+            })
+            .collect();
+        assert_eq!(outcomes, BTreeSet::from([1, 2]));
+        let c = parse_com("par while true do skip end with skip end").unwrap();
+        assert!(
+            run_random_schedule(empty_state(), &c, &[], &mut Rng::new(0), 100)
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn test_branch_ends_only_itself() {
+        let c = parse_com("par X := 1; break; X := 2 with Y := 1 end; Z := 1").unwrap();
+        let reached = interleavings(&c, empty_state(), &[], 100).unwrap();
+        assert_eq!(
+            reached.values(&["X", "Y", "Z"]),
+            BTreeSet::from([vec![1, 1, 1]])
+        );
+        assert_eq!(ceval(empty_state(), &c).unwrap()("Z"), 1);
+    }
+
+    #[test]
+    fn test_throw_ends_both_branches() {
+        let c = parse_com(
+            "try par X := 1; throw 5; X := 2 with Y := 1; Y := 2 end catch E do Z := E end",
+        )
+        .unwrap();
+        // The other branch may have run none, some or all of its steps.
+        let reached = interleavings(&c, empty_state(), &[], 100).unwrap();
+        assert_eq!(
+            reached.values(&["X", "Y", "Z"]),
+            BTreeSet::from([vec![1, 0, 5], vec![1, 1, 5], vec![1, 2, 5]])
+        );
+    }
+
+    #[test]
+    fn test_lost_update_violates_invariant() {
+        let c = parse_com(&format!("{}; D := 1", RACE)).unwrap();
+        let inv = parse_bexp("~(D = 1 && ~(X = 2))").unwrap();
+        let violation = check_invariant(&c, empty_state(), &[], &inv, 100)
+            .unwrap()
+            .unwrap_err();
+        assert_eq!(violation.state(), "D=1, X=1");
+    }
+
+    #[test]
+    fn test_analyses_allow_for_interference() {
+        let c = parse_com("X := 0; par X := X + 1 with Y := X end").unwrap();
+        // Y may see X before or after the increment.
+        let ranges = analyze_intervals(&c, &AbsState::top());
+        assert_eq!(ranges.at_end().unwrap().get("X"), Interval::constant(1));
+        assert!(ranges.at_end().unwrap().get("Y").contains(0));
+        assert!(ranges.at_end().unwrap().get("Y").contains(1));
+        // X is read by the other branch, so it is live throughout.
+        let info = liveness(&parse_com("par X := 1 with Y := X end").unwrap());
+        assert!(info.live_out(2).unwrap().contains("X"));
+        assert_eq!(parse_com(&c.to_pretty_string()).unwrap(), c);
+    }
+}
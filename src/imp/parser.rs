@@ -0,0 +1,712 @@
+//! A lexer and recursive-descent parser for the concrete syntax of Imp, as
+//! in the ImpParser chapter:
+//!
+//! ```text
+//! program ::= proc* com?
+//! proc ::= "proc" ident "(" idents? ")" ("local" idents)? "returns" ident
+//!          "do" com "end"
+//! idents ::= ident ("," ident)*
+//! com  ::= simple (";" com)?
+//! simple ::= "skip" | "break" | "continue" | "havoc" ident | "print" aexp
+//!          | ident ":=" aexp | ident ":=" ident "(" (aexp ("," aexp)*)? ")"
+//!          | ident "[" aexp "]" ":=" aexp
+//!          | "(" com ")"
+//!          | "if" bexp "then" com ("else" com)? "end"
+//!          | "while" bexp "do" com "end"
+//!          | "for" simple ";" bexp ";" simple "do" com "end"
+//!          | "par" com "with" com "end"
+//!          | "throw" aexp | "try" com "catch" ident "do" com "end"
+//! bexp ::= unary ("&&" unary)*
+//! unary ::= "~" unary | "true" | "false" | "(" bexp ")" | aexp ("=" | "<=") aexp
+//! aexp ::= term (("+" | "-") term)*
+//! term ::= factor ("*" factor)*
+//! factor ::= "-"? number | ident | ident "[" aexp "]" | "(" aexp ")"
+//! ```
+//!
+//! `;` associates to the right and the arithmetic operators to the left. A
+//! missing `else` branch means `skip`. Imp itself has no negation, but a
+//! `-` directly before a literal makes a negative constant, so that every
+//! `ANum` can be written down.
+//!
+//! Every token carries its `Span` in the input, so an error can be shown
+//! at its line and column with `ParseError::report`. With the `spans`
+//! feature, `parse_com_spanned` also returns where each command came from.
+
+use std::str::FromStr;
+
+use super::{Aexp, Bexp, Com, Proc, Program};
+use crate::syntax::{self, Cursor};
+pub use crate::syntax::{ParseError, Span, Token};
+
+const KEYWORDS: [&str; 22] = [
+    "skip", "if", "then", "else", "end", "while", "do", "true", "false", "break", "continue",
+    "for", "havoc", "proc", "local", "returns", "print", "par", "with", "throw", "try", "catch",
+];
+
+// Longest first, so `:=` and `<=` win over any prefix.
+const SYMBOLS: [&str; 14] = [
+    ":=", "<=", "&&", ";", "+", "-", "*", "=", "~", "(", ")", ",", "[", "]",
+];
+
+/// Split `input` into tokens, each paired with its byte offset.
+pub fn tokenize(input: &str) -> Result<Vec<(Token, usize)>, ParseError> {
+    Ok(lex(input)?
+        .into_iter()
+        .map(|(token, span)| (token, span.start))
+        .collect())
+}
+
+/// `tokenize`, with the whole span of each token.
+fn lex(input: &str) -> Result<Vec<(Token, Span)>, ParseError> {
+    syntax::lex(input, &KEYWORDS, &SYMBOLS)
+}
+
+/// Where each command of a parse came from: the span of the command, and
+/// one tree for each command directly inside it, in the order `Com` holds
+/// them. A missing `else` branch gets an empty span where it would be.
+#[cfg(feature = "spans")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SpanTree {
+    pub span: Span,
+    pub children: Vec<SpanTree>,
+}
+
+struct Parser {
+    tokens: Vec<(Token, Span)>,
+    next: usize,
+    /// The trees of the commands parsed so far and not yet inside another.
+    #[cfg(feature = "spans")]
+    spans: Vec<SpanTree>,
+}
+
+impl Cursor for Parser {
+    fn tokens(&self) -> &[(Token, Span)] {
+        &self.tokens
+    }
+
+    fn position(&self) -> usize {
+        self.next
+    }
+
+    fn advance(&mut self) {
+        self.next += 1;
+    }
+}
+
+impl Parser {
+    fn new(input: &str) -> Result<Self, ParseError> {
+        Ok(Parser {
+            tokens: lex(input)?,
+            next: 0,
+            #[cfg(feature = "spans")]
+            spans: Vec::new(),
+        })
+    }
+
+    /// Record that `c` was parsed from the tokens from `start` on, taking
+    /// the trees of the commands inside it, and return it.
+    fn node(&mut self, start: usize, c: Com) -> Com {
+        #[cfg(feature = "spans")]
+        {
+            let span = match (self.tokens.get(start), start < self.next) {
+                (Some((_, first)), true) => Span {
+                    start: first.start,
+                    end: self.tokens[self.next - 1].1.end,
+                },
+                // Nothing was read: an empty span before the next token.
+                (Some((_, next)), false) => Span {
+                    start: next.start,
+                    end: next.start,
+                },
+                (None, _) => {
+                    let end = self.tokens.last().map_or(0, |(_, span)| span.end);
+                    Span { start: end, end }
+                }
+            };
+            let inside = match c {
+                Com::CWhile(..) => 1,
+                Com::CSeq(..) | Com::CIf(..) | Com::CPar(..) | Com::CTry(..) => 2,
+                Com::CFor(..) => 3,
+                _ => 0,
+            };
+            let children = self.spans.split_off(self.spans.len() - inside);
+            self.spans.push(SpanTree { span, children });
+        }
+        #[cfg(not(feature = "spans"))]
+        let _ = start;
+        c
+    }
+
+    /// Zero or more of `item`, separated by commas and ended by `)`, which
+    /// is consumed.
+    fn list<T>(
+        &mut self,
+        mut item: impl FnMut(&mut Self) -> Result<T, ParseError>,
+    ) -> Result<Vec<T>, ParseError> {
+        let mut items = Vec::new();
+        if self.eat(")") {
+            return Ok(items);
+        }
+        loop {
+            items.push(item(self)?);
+            if self.eat(")") {
+                return Ok(items);
+            }
+            self.expect(",")?;
+        }
+    }
+
+    fn program(&mut self) -> Result<Program, ParseError> {
+        let mut procs = Vec::new();
+        while self.eat("proc") {
+            let name = self.ident()?;
+            self.expect("(")?;
+            let params = self.list(Self::ident)?;
+            let mut locals = Vec::new();
+            if self.eat("local") {
+                locals.push(self.ident()?);
+                while self.eat(",") {
+                    locals.push(self.ident()?);
+                }
+            }
+            self.expect("returns")?;
+            let ret = self.ident()?;
+            self.expect("do")?;
+            let body = self.com()?;
+            self.expect("end")?;
+            procs.push(Proc {
+                name,
+                params,
+                locals,
+                ret,
+                body,
+            });
+        }
+        // A file of nothing but procedures has `skip` for its main command.
+        let main = if self.next == self.tokens.len() && !procs.is_empty() {
+            self.node(self.next, Com::CSkip)
+        } else {
+            self.com()?
+        };
+        Ok(Program::new(procs, main))
+    }
+
+    fn com(&mut self) -> Result<Com, ParseError> {
+        let start = self.next;
+        let c = self.simple_com()?;
+        if self.eat(";") {
+            let c = Com::seq(c, self.com()?);
+            Ok(self.node(start, c))
+        } else {
+            Ok(c)
+        }
+    }
+
+    fn simple_com(&mut self) -> Result<Com, ParseError> {
+        let start = self.next;
+        let c = self.simple_com_from()?;
+        // A parenthesized command is the one inside, already recorded.
+        if let Some((Token::Symbol("("), _)) = self.tokens.get(start) {
+            return Ok(c);
+        }
+        Ok(self.node(start, c))
+    }
+
+    fn simple_com_from(&mut self) -> Result<Com, ParseError> {
+        if self.eat("skip") {
+            Ok(Com::CSkip)
+        } else if self.eat("break") {
+            Ok(Com::CBreak)
+        } else if self.eat("continue") {
+            Ok(Com::CContinue)
+        } else if self.eat("havoc") {
+            Ok(Com::CHavoc(self.ident()?))
+        } else if self.eat("print") {
+            Ok(Com::CPrint(self.aexp()?))
+        } else if self.eat("if") {
+            let b = self.bexp()?;
+            self.expect("then")?;
+            let c1 = self.com()?;
+            let c2 = if self.eat("else") {
+                self.com()?
+            } else {
+                self.node(self.next, Com::CSkip)
+            };
+            self.expect("end")?;
+            Ok(Com::if_(b, c1, c2))
+        } else if self.eat("(") {
+            let c = self.com()?;
+            self.expect(")")?;
+            Ok(c)
+        } else if self.eat("for") {
+            let init = self.simple_com()?;
+            self.expect(";")?;
+            let b = self.bexp()?;
+            self.expect(";")?;
+            let update = self.simple_com()?;
+            self.expect("do")?;
+            let body = self.com()?;
+            self.expect("end")?;
+            Ok(Com::for_(init, b, update, body))
+        } else if self.eat("par") {
+            let c1 = self.com()?;
+            self.expect("with")?;
+            let c2 = self.com()?;
+            self.expect("end")?;
+            Ok(Com::par(c1, c2))
+        } else if self.eat("throw") {
+            Ok(Com::CThrow(self.aexp()?))
+        } else if self.eat("try") {
+            let body = self.com()?;
+            self.expect("catch")?;
+            let x = self.ident()?;
+            self.expect("do")?;
+            let handler = self.com()?;
+            self.expect("end")?;
+            Ok(Com::try_(body, &x, handler))
+        } else if self.eat("while") {
+            let b = self.bexp()?;
+            self.expect("do")?;
+            let c = self.com()?;
+            self.expect("end")?;
+            Ok(Com::while_(b, c))
+        } else if let Some(Token::Ident(_)) = self.peek() {
+            let x = self.ident()?;
+            if self.eat("[") {
+                let i = self.aexp()?;
+                self.expect("]")?;
+                self.expect(":=")?;
+                return Ok(Com::CArrAsgn(x, i, self.aexp()?));
+            }
+            self.expect(":=")?;
+            // A variable followed by `(` can only be a call.
+            match (self.peek(), self.tokens.get(self.next + 1)) {
+                (Some(Token::Ident(_)), Some((Token::Symbol("("), _))) => {
+                    let f = self.ident()?;
+                    self.next += 1;
+                    Ok(Com::CCall(x, f, self.list(Self::aexp)?))
+                }
+                _ => Ok(Com::CAsgn(x, self.aexp()?)),
+            }
+        } else {
+            Err(self.error("a command"))
+        }
+    }
+
+    fn bexp(&mut self) -> Result<Bexp, ParseError> {
+        let mut b = self.unary_bexp()?;
+        while self.eat("&&") {
+            b = Bexp::and(b, self.unary_bexp()?);
+        }
+        Ok(b)
+    }
+
+    fn unary_bexp(&mut self) -> Result<Bexp, ParseError> {
+        if self.eat("~") {
+            return Ok(Bexp::not(self.unary_bexp()?));
+        }
+        if self.eat("true") {
+            return Ok(Bexp::BTrue);
+        }
+        if self.eat("false") {
+            return Ok(Bexp::BFalse);
+        }
+        // `(` may open either a boolean or an arithmetic expression, as in
+        // `(X = 1) && ...` versus `(X + 1) <= 2`; try the boolean reading
+        // first and fall back to a comparison.
+        let start = self.next;
+        if self.eat("(") {
+            if let Ok(b) = self.bexp() {
+                if self.eat(")") {
+                    return Ok(b);
+                }
+            }
+            self.next = start;
+        }
+        let a1 = self.aexp()?;
+        if self.eat("=") {
+            Ok(Bexp::eq(a1, self.aexp()?))
+        } else if self.eat("<=") {
+            Ok(Bexp::le(a1, self.aexp()?))
+        } else {
+            Err(self.error("`=` or `<=`"))
+        }
+    }
+
+    fn aexp(&mut self) -> Result<Aexp, ParseError> {
+        let mut a = self.term()?;
+        loop {
+            if self.eat("+") {
+                a = Aexp::plus(a, self.term()?);
+            } else if self.eat("-") {
+                a = Aexp::minus(a, self.term()?);
+            } else {
+                return Ok(a);
+            }
+        }
+    }
+
+    fn term(&mut self) -> Result<Aexp, ParseError> {
+        let mut a = self.factor()?;
+        while self.eat("*") {
+            a = Aexp::mult(a, self.factor()?);
+        }
+        Ok(a)
+    }
+
+    fn factor(&mut self) -> Result<Aexp, ParseError> {
+        match self.peek() {
+            Some(Token::Num(_)) => self.number(false),
+            Some(Token::Symbol("-")) => match self.tokens.get(self.next + 1) {
+                Some((Token::Num(_), _)) => {
+                    self.next += 1;
+                    self.number(true)
+                }
+                _ => Err(self.error("an arithmetic expression")),
+            },
+            Some(Token::Ident(_)) => {
+                let x = self.ident()?;
+                if self.eat("[") {
+                    let i = self.aexp()?;
+                    self.expect("]")?;
+                    Ok(Aexp::AIndex(x, Box::new(i)))
+                } else {
+                    Ok(Aexp::AId(x))
+                }
+            }
+            Some(Token::Symbol("(")) => {
+                self.next += 1;
+                let a = self.aexp()?;
+                self.expect(")")?;
+                Ok(a)
+            }
+            _ => Err(self.error("an arithmetic expression")),
+        }
+    }
+
+    fn number(&mut self, negative: bool) -> Result<Aexp, ParseError> {
+        let (token, span) = &self.tokens[self.next];
+        let Token::Num(magnitude) = token else {
+            unreachable!("number called on a non-number token");
+        };
+        let n = if negative {
+            -i128::from(*magnitude)
+        } else {
+            i128::from(*magnitude)
+        };
+        let n = i64::try_from(n).map_err(|_| ParseError::NumberTooLarge { pos: span.start })?;
+        self.next += 1;
+        Ok(Aexp::ANum(n))
+    }
+}
+
+pub fn parse_aexp(input: &str) -> Result<Aexp, ParseError> {
+    let mut p = Parser::new(input)?;
+    let a = p.aexp()?;
+    p.finish(a)
+}
+
+pub fn parse_bexp(input: &str) -> Result<Bexp, ParseError> {
+    let mut p = Parser::new(input)?;
+    let b = p.bexp()?;
+    p.finish(b)
+}
+
+pub fn parse_com(input: &str) -> Result<Com, ParseError> {
+    let mut p = Parser::new(input)?;
+    let c = p.com()?;
+    p.finish(c)
+}
+
+/// `parse_com`, also returning the span of each command.
+#[cfg(feature = "spans")]
+pub fn parse_com_spanned(input: &str) -> Result<(Com, SpanTree), ParseError> {
+    let mut p = Parser::new(input)?;
+    let c = p.com()?;
+    let c = p.finish(c)?;
+    let tree = p.spans.pop().expect("a command was parsed");
+    Ok((c, tree))
+}
+
+pub fn parse_program(input: &str) -> Result<Program, ParseError> {
+    let mut p = Parser::new(input)?;
+    let prog = p.program()?;
+    p.finish(prog)
+}
+
+impl FromStr for Aexp {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse_aexp(s)
+    }
+}
+
+impl FromStr for Bexp {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse_bexp(s)
+    }
+}
+
+impl FromStr for Com {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse_com(s)
+    }
+}
+
+impl FromStr for Program {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse_program(s)
+    }
+}
+
+#[cfg(test)]
+mod test_imp_parser {
+    use super::*;
+    use crate::imp::ceval;
+    use crate::state;
+    use crate::state::lookup;
+
+    #[test]
+    fn test_tokenize() {
+        let tokens: Vec<Token> = tokenize("X:=X1<=3")
+            .unwrap()
+            .into_iter()
+            .map(|(t, _)| t)
+            .collect();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::Ident("X".to_string()),
+                Token::Symbol(":="),
+                Token::Ident("X1".to_string()),
+                Token::Symbol("<="),
+                Token::Num(3),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_factorial() {
+        let c = parse_com("Z := X; Y := 1; while ~(Z = 0) do Y := Y * Z; Z := Z - 1 end").unwrap();
+        let expected = Com::seq(
+            Com::asgn("Z", Aexp::var("X")),
+            Com::seq(
+                Com::asgn("Y", Aexp::num(1)),
+                Com::while_(
+                    Bexp::not(Bexp::eq(Aexp::var("Z"), Aexp::num(0))),
+                    Com::seq(
+                        Com::asgn("Y", Aexp::mult(Aexp::var("Y"), Aexp::var("Z"))),
+                        Com::asgn("Z", Aexp::minus(Aexp::var("Z"), Aexp::num(1))),
+                    ),
+                ),
+            ),
+        );
+        assert_eq!(c, expected);
+        let st = ceval(state! {"X" => 4}, &c).unwrap();
+        assert_eq!(lookup(&st, "Y"), 24);
+    }
+
+    #[test]
+    fn test_precedence_and_associativity() {
+        assert_eq!(
+            parse_aexp("1 + 2 * X - 3").unwrap(),
+            Aexp::minus(
+                Aexp::plus(Aexp::num(1), Aexp::mult(Aexp::num(2), Aexp::var("X"))),
+                Aexp::num(3)
+            )
+        );
+        assert_eq!(
+            parse_aexp("(1 + 2) * 3").unwrap(),
+            Aexp::mult(Aexp::plus(Aexp::num(1), Aexp::num(2)), Aexp::num(3))
+        );
+    }
+
+    #[test]
+    fn test_parenthesized_bexp_and_aexp() {
+        assert_eq!(
+            parse_bexp("(X + 1) <= 2 && ~true").unwrap(),
+            Bexp::and(
+                Bexp::le(Aexp::plus(Aexp::var("X"), Aexp::num(1)), Aexp::num(2)),
+                Bexp::not(Bexp::BTrue)
+            )
+        );
+        assert_eq!(
+            parse_bexp("(X = 1) && ((Y)) = 2").unwrap(),
+            Bexp::and(
+                Bexp::eq(Aexp::var("X"), Aexp::num(1)),
+                Bexp::eq(Aexp::var("Y"), Aexp::num(2))
+            )
+        );
+    }
+
+    #[test]
+    fn test_negative_literals() {
+        assert_eq!(
+            parse_aexp("X - -3").unwrap(),
+            Aexp::minus(Aexp::var("X"), Aexp::num(-3))
+        );
+        assert_eq!(
+            parse_aexp("-9223372036854775808").unwrap(),
+            Aexp::num(i64::MIN)
+        );
+        assert_eq!(
+            parse_aexp("9223372036854775808"),
+            Err(ParseError::NumberTooLarge { pos: 0 })
+        );
+        assert!(parse_aexp("- X").is_err());
+    }
+
+    #[test]
+    fn test_parenthesized_com() {
+        let c = parse_com("(X := 1; Y := 2); Z := 3").unwrap();
+        assert_eq!(
+            c,
+            Com::seq(
+                Com::seq(Com::asgn("X", Aexp::num(1)), Com::asgn("Y", Aexp::num(2))),
+                Com::asgn("Z", Aexp::num(3))
+            )
+        );
+    }
+
+    #[test]
+    fn test_if_without_else() {
+        assert_eq!(
+            "if X = 0 then Y := 1 end".parse::<Com>().unwrap(),
+            Com::if_(
+                Bexp::eq(Aexp::var("X"), Aexp::num(0)),
+                Com::asgn("Y", Aexp::num(1)),
+                Com::CSkip
+            )
+        );
+    }
+
+    #[test]
+    fn test_imp_macro_agrees_with_parser() {
+        use crate::imp;
+        let cases = [
+            (
+                imp! {
+                    Z := X;
+                    Y := 1;
+                    while ~(Z = 0) {
+                        Y := Y * Z;
+                        Z := Z - 1
+                    }
+                },
+                "Z := X; Y := 1; while ~(Z = 0) do Y := Y * Z; Z := Z - 1 end",
+            ),
+            (
+                imp! {
+                    if (X = 1) && (X + 1) <= 2 { skip } else if X <= -3 { Y := 2 } else { break };
+                    (A := 1; B := 2); havoc C; print C * 2;
+                },
+                "if (X = 1) && (X + 1) <= 2 then skip else if X <= -3 then Y := 2 else break end \
+                 end; (A := 1; B := 2); havoc C; print C * 2",
+            ),
+            (
+                imp! {
+                    for I := 0; I <= N - 1; I := I + 1 { S := S + A[I]; continue };
+                    A[I - 1] := 0 - -9223372036854775808;
+                    R := f(); R := g(X, 2 * (X + 1))
+                },
+                "for I := 0; I <= N - 1; I := I + 1 do S := S + A[I]; continue end; \
+                 A[I - 1] := 0 - -9223372036854775808; R := f(); R := g(X, 2 * (X + 1))",
+            ),
+            (
+                imp! { par { X := X + 1 } with { Y := X; X := 0 } },
+                "par X := X + 1 with Y := X; X := 0 end",
+            ),
+            (
+                imp! { try { X := 1; throw X + 1 } catch E { print E } },
+                "try X := 1; throw X + 1 catch E do print E end",
+            ),
+        ];
+        for (c, src) in cases {
+            assert_eq!(c, parse_com(src).unwrap(), "{}", src);
+        }
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(
+            parse_com("X := 1; if X then skip end"),
+            Err(ParseError::UnexpectedToken {
+                found: Token::Keyword("then"),
+                pos: 13,
+                expected: "`=` or `<=`"
+            })
+        );
+        assert_eq!(
+            parse_com("while true do skip"),
+            Err(ParseError::UnexpectedEof { expected: "end" })
+        );
+        assert_eq!(
+            parse_com("X := 1 # 2"),
+            Err(ParseError::UnexpectedChar { ch: '#', pos: 7 })
+        );
+        assert_eq!(
+            parse_aexp("99999999999999999999999"),
+            Err(ParseError::NumberTooLarge { pos: 0 })
+        );
+        assert_eq!(
+            parse_com("skip skip").unwrap_err().to_string(),
+            "unexpected token `skip` at position 5, expected end of input"
+        );
+    }
+
+    #[test]
+    fn test_report() {
+        let report = |input: &str| parse_com(input).unwrap_err().report(input);
+        assert_eq!(
+            report("X := 1;\nif X then\n  Y := 2\nend"),
+            "expected `=` or `<=` at line 2, col 6, found `then`\n2 | if X then\n  |      ^^^^"
+        );
+        assert_eq!(
+            report("while true do\n  skip\n"),
+            "expected `end` at line 2, col 7, found end of input\n2 |   skip\n  |       ^"
+        );
+        assert_eq!(
+            report("X := 99999999999999999999 + 1"),
+            "number literal too large at line 1, col 6\n\
+             1 | X := 99999999999999999999 + 1\n  |      ^^^^^^^^^^^^^^^^^^^^"
+        );
+        // Columns count characters, not bytes.
+        assert_eq!(
+            report("Ä := 1 # 2"),
+            "unexpected character '#' at line 1, col 8\n1 | Ä := 1 # 2\n  |        ^"
+        );
+        let (line, col) = Span { start: 9, end: 10 }.line_col("a\nb\n\nccccccccccc\nddddd");
+        assert_eq!((line, col), (4, 5));
+    }
+
+    #[cfg(feature = "spans")]
+    #[test]
+    fn test_spans() {
+        let input = "X := 1;\nwhile X <= 3 do\n  if X = 2 then skip end;\n  X := X + 1\nend";
+        let (c, tree) = parse_com_spanned(input).unwrap();
+        assert_eq!(c, parse_com(input).unwrap());
+        let text = |tree: &SpanTree| tree.span.text(input).to_string();
+        assert_eq!(text(&tree), input);
+        assert_eq!(text(&tree.children[0]), "X := 1");
+        let body = &tree.children[1].children[0];
+        assert_eq!(text(body), "if X = 2 then skip end;\n  X := X + 1");
+        let if_ = &body.children[0];
+        assert_eq!(text(&if_.children[0]), "skip");
+        // The missing `else` is an empty span just before the `end`.
+        assert_eq!(if_.children[1].span.start, if_.children[1].span.end);
+        assert_eq!(if_.children[1].span.line_col(input), (3, 22));
+        assert_eq!(body.children[1].span.line_col(input), (4, 3));
+        let (_, tree) =
+            parse_com_spanned("(X := 1; Y := 2); for I := 0; true; (I := I + 1) do skip end")
+                .unwrap();
+        assert_eq!(tree.children[0].children.len(), 2);
+        assert_eq!(tree.children[1].children.len(), 3);
+    }
+}
@@ -0,0 +1,479 @@
+//! Partial evaluation, following the PE chapter: specialize a program to
+//! the variables whose values are known before it runs. Known variables
+//! live in a `PeState`; expressions over them fold to constants, an
+//! assignment of a constant just updates the `PeState`, and a branch on a
+//! known condition is decided. What can't be decided is left in the
+//! residual program.
+//!
+//! A loop is kept, but first every variable its body assigns is forgotten,
+//! with an assignment in the residual so the real state catches up. The
+//! body is specialized to what remains, which no iteration can change, so
+//! it needs no fixpoint; the residual re-establishes that state wherever
+//! the body can leave the loop.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use super::desugar::lower_for;
+use super::transform::{Transform, Unsound};
+use super::{ceval_fuel, Aexp, Bexp, Com, Unsupported};
+use crate::map::tm_update;
+use crate::state::{State, StateExt};
+
+/// The statically known variables and their values. Any variable not in
+/// it is dynamic.
+pub type PeState = BTreeMap<String, i64>;
+
+/// `st` with the known values of `pe_st` written over it, the book's
+/// `pe_update`.
+pub fn pe_update(st: State, pe_st: &PeState) -> State {
+    pe_st
+        .iter()
+        .fold(st, |st, (x, n)| tm_update(st, x.clone(), *n))
+}
+
+pub fn pe_aexp(pe_st: &PeState, a: &Aexp) -> Aexp {
+    match a {
+        Aexp::ANum(_) => a.clone(),
+        Aexp::AId(x) => match pe_st.get(x) {
+            Some(n) => Aexp::ANum(*n),
+            None => a.clone(),
+        },
+        Aexp::APlus(a1, a2) | Aexp::AMinus(a1, a2) | Aexp::AMult(a1, a2) => {
+            match (pe_aexp(pe_st, a1), pe_aexp(pe_st, a2)) {
+                (Aexp::ANum(n1), Aexp::ANum(n2)) => Aexp::ANum(match a {
+                    Aexp::APlus(..) => n1.wrapping_add(n2),
+                    Aexp::AMinus(..) => n1.wrapping_sub(n2),
+                    _ => n1.wrapping_mul(n2),
+                }),
+                (a1, a2) => match a {
+                    Aexp::APlus(..) => Aexp::plus(a1, a2),
+                    Aexp::AMinus(..) => Aexp::minus(a1, a2),
+                    _ => Aexp::mult(a1, a2),
+                },
+            }
+        }
+        Aexp::AIndex(x, i) => Aexp::index(x, pe_aexp(pe_st, i)),
+    }
+}
+
+pub fn pe_bexp(pe_st: &PeState, b: &Bexp) -> Bexp {
+    let of_bool = |v| if v { Bexp::BTrue } else { Bexp::BFalse };
+    match b {
+        Bexp::BTrue | Bexp::BFalse => b.clone(),
+        Bexp::BEq(a1, a2) | Bexp::BLe(a1, a2) => match (pe_aexp(pe_st, a1), pe_aexp(pe_st, a2)) {
+            (Aexp::ANum(n1), Aexp::ANum(n2)) => of_bool(match b {
+                Bexp::BEq(..) => n1 == n2,
+                _ => n1 <= n2,
+            }),
+            (a1, a2) => match b {
+                Bexp::BEq(..) => Bexp::eq(a1, a2),
+                _ => Bexp::le(a1, a2),
+            },
+        },
+        Bexp::BNot(b1) => match pe_bexp(pe_st, b1) {
+            Bexp::BTrue => Bexp::BFalse,
+            Bexp::BFalse => Bexp::BTrue,
+            b1 => Bexp::not(b1),
+        },
+        Bexp::BAnd(b1, b2) => match (pe_bexp(pe_st, b1), pe_bexp(pe_st, b2)) {
+            (Bexp::BFalse, _) => Bexp::BFalse,
+            (Bexp::BTrue, b2) => b2,
+            (b1, Bexp::BTrue) => b1,
+            (b1, b2) => Bexp::and(b1, b2),
+        },
+    }
+}
+
+/// Specialize `c` to `pe_st`, returning the residual program and what is
+/// known when it ends. Running the residual from a state that agrees with
+/// `pe_st`, and then writing the returned `PeState` over the result, gives
+/// what running `c` would have.
+pub fn pe_com(pe_st: &PeState, c: &Com) -> (Com, PeState) {
+    let (residual, pe_st) = pe(pe_st.clone(), c, None);
+    if escapes(c) || throws(c) {
+        // A `break` outside any loop, or an uncaught `throw`, stops the
+        // program with everything known written out (see `interrupt`), so
+        // the normal end must do the same for the final `PeState` to be
+        // right on both paths.
+        let end = assign_changed(&pe_st, &PeState::new());
+        (seq(residual, end), PeState::new())
+    } else {
+        (residual, pe_st)
+    }
+}
+
+/// `pe_com` with `target`, the `PeState` the innermost enclosing loop
+/// expects at its test, if there is a loop.
+fn pe(pe_st: PeState, c: &Com, target: Option<&PeState>) -> (Com, PeState) {
+    match c {
+        Com::CSkip => (Com::CSkip, pe_st),
+        Com::CAsgn(x, a) => match pe_aexp(&pe_st, a) {
+            Aexp::ANum(n) => {
+                let mut pe_st = pe_st;
+                pe_st.insert(x.clone(), n);
+                (Com::CSkip, pe_st)
+            }
+            a => (Com::CAsgn(x.clone(), a), forget(pe_st, x)),
+        },
+        Com::CHavoc(x) => (c.clone(), forget(pe_st, x)),
+        Com::CCall(x, f, args) => {
+            let args = args.iter().map(|a| pe_aexp(&pe_st, a)).collect();
+            (Com::CCall(x.clone(), f.clone(), args), forget(pe_st, x))
+        }
+        Com::CArrAsgn(x, i, a) => (
+            Com::CArrAsgn(x.clone(), pe_aexp(&pe_st, i), pe_aexp(&pe_st, a)),
+            pe_st,
+        ),
+        Com::CPrint(a) => (Com::CPrint(pe_aexp(&pe_st, a)), pe_st),
+        Com::CBreak | Com::CContinue => (interrupt(&pe_st, target, c.clone()), pe_st),
+        // Wherever the exception is caught, the handler can't know what is
+        // known here, so everything is written out as for leaving the
+        // program.
+        Com::CThrow(a) => {
+            let a = pe_aexp(&pe_st, a);
+            (interrupt(&pe_st, None, Com::CThrow(a)), pe_st)
+        }
+        Com::CSeq(c1, c2) => {
+            let (c1, pe_st) = pe(pe_st, c1, target);
+            let (c2, pe_st) = pe(pe_st, c2, target);
+            (seq(c1, c2), pe_st)
+        }
+        Com::CIf(b, c1, c2) => match pe_bexp(&pe_st, b) {
+            Bexp::BTrue => pe(pe_st, c1, target),
+            Bexp::BFalse => pe(pe_st, c2, target),
+            b => {
+                let (c1, st1) = pe(pe_st.clone(), c1, target);
+                let (c2, st2) = pe(pe_st, c2, target);
+                // Keep only what both branches agree on, and have each
+                // branch write out the rest.
+                let joined: PeState = st1
+                    .iter()
+                    .filter(|(x, n)| st2.get(*x) == Some(n))
+                    .map(|(x, n)| (x.clone(), *n))
+                    .collect();
+                let c1 = seq(c1, assign_changed(&st1, &joined));
+                let c2 = seq(c2, assign_changed(&st2, &joined));
+                (Com::if_(b, c1, c2), joined)
+            }
+        },
+        Com::CWhile(b, body) => pe_loop(pe_st, b, body),
+        Com::CFor(..) => pe(pe_st, &lower_for(c), target),
+        // Either branch can see the other's writes at any point, so nothing
+        // they assign is known inside or after; only what neither touches
+        // can be substituted.
+        Com::CPar(c1, c2) => {
+            let mut assigned = BTreeSet::new();
+            c.collect_assigned(&mut assigned);
+            let known: PeState = pe_st
+                .iter()
+                .filter(|(x, _)| !assigned.contains(*x))
+                .map(|(x, n)| (x.clone(), *n))
+                .collect();
+            let substitute = Substitute(&known);
+            let residual = Com::par(substitute.transform_com(c1), substitute.transform_com(c2));
+            (seq(assign_changed(&pe_st, &known), residual), known)
+        }
+        // Like a loop: what the body or handler assign is forgotten first,
+        // so the handler knows the same however the body got to it, even
+        // from a procedure call that threw. The two ends are joined as for
+        // an `if`.
+        Com::CTry(body, x, handler) => {
+            let mut changed = BTreeSet::new();
+            c.collect_assigned(&mut changed);
+            let invariant: PeState = pe_st
+                .iter()
+                .filter(|(x, _)| !changed.contains(*x))
+                .map(|(x, n)| (x.clone(), *n))
+                .collect();
+            let (body, st1) = pe(invariant.clone(), body, target);
+            let (handler, st2) = pe(invariant.clone(), handler, target);
+            let joined: PeState = st1
+                .iter()
+                .filter(|(x, n)| st2.get(*x) == Some(n))
+                .map(|(x, n)| (x.clone(), *n))
+                .collect();
+            let body = seq(body, assign_changed(&st1, &joined));
+            let handler = seq(handler, assign_changed(&st2, &joined));
+            let residual = if body == Com::CSkip {
+                Com::CSkip
+            } else {
+                Com::try_(body, x, handler)
+            };
+            (seq(assign_changed(&pe_st, &invariant), residual), joined)
+        }
+    }
+}
+
+/// Replaces the variables a `PeState` knows with their values, and does
+/// nothing else.
+struct Substitute<'a>(&'a PeState);
+
+impl Transform for Substitute<'_> {
+    fn rewrite_aexp(&self, a: Aexp) -> Aexp {
+        match a {
+            Aexp::AId(x) if self.0.contains_key(&x) => Aexp::ANum(self.0[&x]),
+            a => a,
+        }
+    }
+}
+
+/// A loop, given by its test `b` and `body`.
+fn pe_loop(pe_st: PeState, b: &Bexp, body: &Com) -> (Com, PeState) {
+    if pe_bexp(&pe_st, b) == Bexp::BFalse {
+        return (Com::CSkip, pe_st);
+    }
+    let mut changed = BTreeSet::new();
+    body.collect_assigned(&mut changed);
+    let invariant: PeState = pe_st
+        .iter()
+        .filter(|(x, _)| !changed.contains(*x))
+        .map(|(x, n)| (x.clone(), *n))
+        .collect();
+    let pre = assign_changed(&pe_st, &invariant);
+    let in_loop = |c: &Com| {
+        let (c, end) = pe(invariant.clone(), c, Some(&invariant));
+        seq(c, assign_changed(&end, &invariant))
+    };
+    let b = pe_bexp(&invariant, b);
+    let body = in_loop(body);
+    let residual = if b == Bexp::BFalse {
+        Com::CSkip
+    } else {
+        Com::while_(b, body)
+    };
+    (seq(pre, residual), invariant)
+}
+
+/// A `break` or `continue` at a point where `pe_st` is known: first write
+/// out whatever the place it jumps to doesn't expect. Outside any loop that
+/// is everything, see `pe_com`.
+fn interrupt(pe_st: &PeState, target: Option<&PeState>, c: Com) -> Com {
+    let empty = PeState::new();
+    seq(assign_changed(pe_st, target.unwrap_or(&empty)), c)
+}
+
+fn forget(mut pe_st: PeState, x: &str) -> PeState {
+    pe_st.remove(x);
+    pe_st
+}
+
+/// Assignments bringing the variables that `from` knows but `to` doesn't
+/// (or knows differently) to their values in `from`, the book's `assign
+/// pe_st (pe_compare pe_st pe_st')`.
+fn assign_changed(from: &PeState, to: &PeState) -> Com {
+    from.iter()
+        .filter(|(x, n)| to.get(*x) != Some(n))
+        .map(|(x, n)| Com::asgn(x, Aexp::num(*n)))
+        .rev()
+        .fold(Com::CSkip, |rest, c| seq(c, rest))
+}
+
+/// `c1; c2`, dropping `skip`s and keeping `;` associated to the right, as
+/// the parser does.
+pub(super) fn seq(c1: Com, c2: Com) -> Com {
+    match (c1, c2) {
+        (Com::CSkip, c) | (c, Com::CSkip) => c,
+        (Com::CSeq(c1, c1b), c2) => Com::seq(*c1, seq(*c1b, c2)),
+        (c1, c2) => Com::seq(c1, c2),
+    }
+}
+
+/// Whether `c` has a `break` or `continue` outside any of its loops.
+fn escapes(c: &Com) -> bool {
+    match c {
+        Com::CBreak | Com::CContinue => true,
+        Com::CSeq(c1, c2) | Com::CIf(_, c1, c2) => escapes(c1) || escapes(c2),
+        Com::CFor(init, ..) => escapes(init),
+        Com::CTry(body, _, handler) => escapes(body) || escapes(handler),
+        _ => false,
+    }
+}
+
+/// Whether `c` may throw an exception it doesn't catch itself. A procedure
+/// call may throw anything.
+fn throws(c: &Com) -> bool {
+    match c {
+        Com::CThrow(_) | Com::CCall(..) => true,
+        Com::CSeq(c1, c2) | Com::CIf(_, c1, c2) | Com::CPar(c1, c2) => throws(c1) || throws(c2),
+        Com::CWhile(_, body) => throws(body),
+        Com::CFor(init, _, update, body) => throws(init) || throws(update) || throws(body),
+        Com::CTry(_, _, handler) => throws(handler),
+        Com::CSkip
+        | Com::CBreak
+        | Com::CContinue
+        | Com::CAsgn(..)
+        | Com::CHavoc(_)
+        | Com::CArrAsgn(..)
+        | Com::CPrint(_) => false,
+    }
+}
+
+impl Com {
+    pub(super) fn collect_assigned(&self, vars: &mut BTreeSet<String>) {
+        match self {
+            Com::CAsgn(x, _) | Com::CHavoc(x) | Com::CCall(x, ..) => {
+                vars.insert(x.clone());
+            }
+            Com::CSeq(c1, c2) | Com::CIf(_, c1, c2) | Com::CPar(c1, c2) => {
+                c1.collect_assigned(vars);
+                c2.collect_assigned(vars);
+            }
+            Com::CTry(body, x, handler) => {
+                vars.insert(x.clone());
+                body.collect_assigned(vars);
+                handler.collect_assigned(vars);
+            }
+            Com::CWhile(_, body) => body.collect_assigned(vars),
+            Com::CFor(init, _, update, body) => {
+                init.collect_assigned(vars);
+                update.collect_assigned(vars);
+                body.collect_assigned(vars);
+            }
+            Com::CSkip
+            | Com::CBreak
+            | Com::CContinue
+            | Com::CArrAsgn(..)
+            | Com::CPrint(_)
+            | Com::CThrow(_) => {}
+        }
+    }
+}
+
+/// Check `pe_com` on each program: from each sample state, updated with
+/// `pe_st`, running the original must end like running the residual and
+/// then applying the final `PeState`, on every variable either mentions.
+/// Reports failures as `Unsound`, the partial evaluator being the
+/// transform, and fails on procedure calls as `check_sound` does.
+pub fn check_pe_correct(
+    pe_st: &PeState,
+    programs: &[Com],
+    states: &[State],
+    fuel: u64,
+) -> Result<Result<(), Unsound>, Unsupported> {
+    for (p, c) in programs.iter().enumerate() {
+        let (residual, pe_end) = pe_com(pe_st, c);
+        let mut vars = c.vars();
+        vars.extend(residual.vars());
+        vars.extend(pe_st.keys().cloned());
+        let vars: Vec<String> = vars.into_iter().collect();
+        for (i, st) in states.iter().enumerate() {
+            let st = pe_update(st.clone(), pe_st);
+            let expected = ceval_fuel(st.clone(), c, fuel)?;
+            let found = ceval_fuel(st.clone(), &residual, fuel)?.map(|st| pe_update(st, &pe_end));
+            for x in &vars {
+                let expected = expected.as_ref().map(|st| st(x));
+                let found = found.as_ref().map(|st| st(x));
+                if expected != found {
+                    return Ok(Err(Unsound {
+                        program: p,
+                        state: i,
+                        initial: st.show(&vars),
+                        var: x.clone(),
+                        expected,
+                        found,
+                    }));
+                }
+            }
+        }
+    }
+    Ok(Ok(()))
+}
+
+#[cfg(test)]
+mod test_imp_pe {
+    use super::*;
+    use crate::imp::{parse_com, random_state};
+    use crate::rng::Rng;
+
+    fn known(vars: &[(&str, i64)]) -> PeState {
+        vars.iter().map(|(x, n)| (x.to_string(), *n)).collect()
+    }
+
+    #[test]
+    fn test_pe_aexp_folds() {
+        // From the book: (X + 3) * Y with X = 5 and Y dynamic.
+        let a = parse_com("Z := (X + 3) * Y").unwrap();
+        let (c, _) = pe_com(&known(&[("X", 5)]), &a);
+        assert_eq!(c, parse_com("Z := 8 * Y").unwrap());
+    }
+
+    #[test]
+    fn test_static_assignments_vanish() {
+        let c = parse_com("X := 3; Y := X + 1; Z := Y * W").unwrap();
+        let (residual, end) = pe_com(&PeState::new(), &c);
+        assert_eq!(residual, parse_com("Z := 4 * W").unwrap());
+        assert_eq!(end, known(&[("X", 3), ("Y", 4)]));
+    }
+
+    #[test]
+    fn test_if_join() {
+        // The book's example: both branches set X, to different values, so
+        // X is written out in each and is dynamic afterwards; Y agrees.
+        let c = parse_com("if W <= 0 then X := 1; Y := 2 else X := 5; Y := 2 end").unwrap();
+        let (residual, end) = pe_com(&PeState::new(), &c);
+        assert_eq!(
+            residual,
+            parse_com("if W <= 0 then X := 1 else X := 5 end").unwrap()
+        );
+        assert_eq!(end, known(&[("Y", 2)]));
+        // With W known the branch is decided statically.
+        let (residual, end) = pe_com(&known(&[("W", 1)]), &c);
+        assert_eq!(residual, Com::CSkip);
+        assert_eq!(end, known(&[("W", 1), ("X", 5), ("Y", 2)]));
+    }
+
+    #[test]
+    fn test_loop_keeps_invariant_part() {
+        // N is never assigned in the loop, so it stays known inside; Y and
+        // Z are assigned, so they are written out before the loop.
+        let c =
+            parse_com("Y := 0; Z := N; while 1 <= Z do Y := Y + N * 2; Z := Z - 1 end").unwrap();
+        let (residual, end) = pe_com(&known(&[("N", 3)]), &c);
+        assert_eq!(
+            residual,
+            parse_com("Y := 0; Z := 3; while 1 <= Z do Y := Y + 6; Z := Z - 1 end").unwrap()
+        );
+        assert_eq!(end, known(&[("N", 3)]));
+    }
+
+    #[test]
+    fn test_pe_correct() {
+        let programs: Vec<Com> = [
+            "X := 3; Y := X + 1; Z := Y * W",
+            "if W <= 0 then X := 1; Y := 2 else X := 5; Y := 2 end; Z := X + Y",
+            "Y := 0; Z := N; while 1 <= Z do Y := Y + N * 2; Z := Z - 1 end",
+            "Y := 1; while Y <= W do Y := Y * 2; if X = 3 then break else X := 0 end end",
+            "X := 1; while true do X := X + 1; if 4 <= X then break else continue end end",
+            "for I := 0; I <= N; I := I + 1 do if I = W then continue else Y := Y + I end end",
+            "X := 7; if W = 0 then break else skip end; X := 8",
+            "havoc N; Y := N + X",
+        ]
+        .iter()
+        .map(|s| parse_com(s).unwrap())
+        .collect();
+        let mut rng = Rng::new(11);
+        let states: Vec<State> = (0..30)
+            .map(|_| random_state(&mut rng, &["W", "X", "Y", "N"]))
+            .collect();
+        for pe_st in [
+            PeState::new(),
+            known(&[("N", 3)]),
+            known(&[("X", 3), ("W", 0)]),
+            known(&[("W", 2), ("N", 4), ("Y", -1)]),
+        ] {
+            if let Err(e) = check_pe_correct(&pe_st, &programs, &states, 1_000).unwrap() {
+                panic!("{:?}: {}", pe_st, e);
+            }
+        }
+    }
+
+    #[test]
+    fn test_calls_are_unsupported() {
+        let programs = [parse_com("Y := f(X)").unwrap()];
+        let states = [crate::state::empty_state()];
+        assert_eq!(
+            check_pe_correct(&PeState::new(), &programs, &states, 100).err(),
+            Some(Unsupported("procedure calls"))
+        );
+    }
+}
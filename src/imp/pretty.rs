@@ -0,0 +1,414 @@
+//! Printing Imp back to the concrete syntax the parser reads. Parentheses
+//! are inserted only where precedence or associativity requires them, so
+//! `parse_com(&c.to_string()) == Ok(c)` for every command.
+//!
+//! `{}` prints a command on one line; `{:#}` (or `to_pretty_string`) puts
+//! every command on its own line and indents loop and branch bodies.
+
+use std::fmt;
+
+use super::{Aexp, Bexp, Com, Proc, Program};
+
+const INDENT: &str = "  ";
+
+// Binding strength of the arithmetic operators; atoms bind tightest.
+const PREC_SUM: u8 = 1;
+const PREC_PRODUCT: u8 = 2;
+const PREC_ATOM: u8 = 3;
+
+fn aexp_prec(a: &Aexp) -> u8 {
+    match a {
+        Aexp::APlus(..) | Aexp::AMinus(..) => PREC_SUM,
+        Aexp::AMult(..) => PREC_PRODUCT,
+        Aexp::ANum(_) | Aexp::AId(_) | Aexp::AIndex(..) => PREC_ATOM,
+    }
+}
+
+/// Print `a`, parenthesized if it binds more loosely than `min_prec`.
+fn write_aexp(f: &mut fmt::Formatter<'_>, a: &Aexp, min_prec: u8) -> fmt::Result {
+    let prec = aexp_prec(a);
+    if prec < min_prec {
+        write!(f, "(")?;
+    }
+    match a {
+        Aexp::ANum(n) => write!(f, "{}", n)?,
+        Aexp::AId(x) => write!(f, "{}", x)?,
+        Aexp::AIndex(x, i) => write!(f, "{}[{}]", x, i)?,
+        // The operators are left-associative, so a right operand at the
+        // same level needs parentheses.
+        Aexp::APlus(a1, a2) | Aexp::AMinus(a1, a2) | Aexp::AMult(a1, a2) => {
+            let op = match a {
+                Aexp::APlus(..) => "+",
+                Aexp::AMinus(..) => "-",
+                _ => "*",
+            };
+            write_aexp(f, a1, prec)?;
+            write!(f, " {} ", op)?;
+            write_aexp(f, a2, prec + 1)?;
+        }
+    }
+    if prec < min_prec {
+        write!(f, ")")?;
+    }
+    Ok(())
+}
+
+impl fmt::Display for Aexp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write_aexp(f, self, PREC_SUM)
+    }
+}
+
+/// Print an operand of `&&` or `~`. Conjunctions and comparisons under a
+/// `~`, and right-nested conjunctions, get parentheses; `~X = 0` would also
+/// parse, but `~(X = 0)` is how the book writes it.
+fn write_bexp_operand(f: &mut fmt::Formatter<'_>, b: &Bexp, under_not: bool) -> fmt::Result {
+    let parens = match b {
+        Bexp::BAnd(..) => true,
+        Bexp::BEq(..) | Bexp::BLe(..) => under_not,
+        _ => false,
+    };
+    if parens {
+        write!(f, "({})", b)
+    } else {
+        write!(f, "{}", b)
+    }
+}
+
+impl fmt::Display for Bexp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Bexp::BTrue => write!(f, "true"),
+            Bexp::BFalse => write!(f, "false"),
+            Bexp::BEq(a1, a2) => write!(f, "{} = {}", a1, a2),
+            Bexp::BLe(a1, a2) => write!(f, "{} <= {}", a1, a2),
+            Bexp::BNot(b) => {
+                write!(f, "~")?;
+                write_bexp_operand(f, b, true)
+            }
+            Bexp::BAnd(b1, b2) => {
+                write!(f, "{} && ", b1)?;
+                write_bexp_operand(f, b2, false)
+            }
+        }
+    }
+}
+
+/// Where the next piece of a command goes: on one line, or on fresh lines
+/// at some depth.
+#[derive(Clone, Copy)]
+enum Layout {
+    Inline,
+    Block(usize),
+}
+
+impl Layout {
+    fn nested(self) -> Layout {
+        match self {
+            Layout::Inline => Layout::Inline,
+            Layout::Block(depth) => Layout::Block(depth + 1),
+        }
+    }
+
+    /// Start a new line (or, inline, just a space) at this layout's depth.
+    fn newline(self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Layout::Inline => write!(f, " "),
+            Layout::Block(depth) => {
+                writeln!(f)?;
+                write!(f, "{}", INDENT.repeat(depth))
+            }
+        }
+    }
+}
+
+/// Print `c`, assuming the cursor is already where it should start.
+fn write_com(f: &mut fmt::Formatter<'_>, c: &Com, layout: Layout) -> fmt::Result {
+    match c {
+        Com::CSkip => write!(f, "skip"),
+        Com::CBreak => write!(f, "break"),
+        Com::CContinue => write!(f, "continue"),
+        Com::CAsgn(x, a) => write!(f, "{} := {}", x, a),
+        Com::CHavoc(x) => write!(f, "havoc {}", x),
+        Com::CArrAsgn(x, i, a) => write!(f, "{}[{}] := {}", x, i, a),
+        Com::CPrint(a) => write!(f, "print {}", a),
+        Com::CThrow(a) => write!(f, "throw {}", a),
+        Com::CCall(x, p, args) => {
+            write!(f, "{} := {}(", x, p)?;
+            write_args(f, args)?;
+            write!(f, ")")
+        }
+        Com::CSeq(c1, c2) => {
+            // `;` associates to the right, so a sequence on the left needs
+            // parentheses to keep its shape.
+            if let Com::CSeq(..) = **c1 {
+                write!(f, "(")?;
+                match layout {
+                    Layout::Inline => write_com(f, c1, layout)?,
+                    Layout::Block(_) => {
+                        layout.nested().newline(f)?;
+                        write_com(f, c1, layout.nested())?;
+                        layout.newline(f)?;
+                    }
+                }
+                write!(f, ")")?;
+            } else {
+                write_com(f, c1, layout)?;
+            }
+            write!(f, ";")?;
+            layout.newline(f)?;
+            write_com(f, c2, layout)
+        }
+        Com::CIf(b, c1, c2) => {
+            write!(f, "if {} then", b)?;
+            layout.nested().newline(f)?;
+            write_com(f, c1, layout.nested())?;
+            layout.newline(f)?;
+            write!(f, "else")?;
+            layout.nested().newline(f)?;
+            write_com(f, c2, layout.nested())?;
+            layout.newline(f)?;
+            write!(f, "end")
+        }
+        Com::CWhile(b, body) => {
+            write!(f, "while {} do", b)?;
+            layout.nested().newline(f)?;
+            write_com(f, body, layout.nested())?;
+            layout.newline(f)?;
+            write!(f, "end")
+        }
+        Com::CFor(init, b, update, body) => {
+            write!(f, "for ")?;
+            write_simple_com(f, init)?;
+            write!(f, "; {}; ", b)?;
+            write_simple_com(f, update)?;
+            write!(f, " do")?;
+            layout.nested().newline(f)?;
+            write_com(f, body, layout.nested())?;
+            layout.newline(f)?;
+            write!(f, "end")
+        }
+        Com::CPar(c1, c2) => {
+            write!(f, "par")?;
+            layout.nested().newline(f)?;
+            write_com(f, c1, layout.nested())?;
+            layout.newline(f)?;
+            write!(f, "with")?;
+            layout.nested().newline(f)?;
+            write_com(f, c2, layout.nested())?;
+            layout.newline(f)?;
+            write!(f, "end")
+        }
+        Com::CTry(body, x, handler) => {
+            write!(f, "try")?;
+            layout.nested().newline(f)?;
+            write_com(f, body, layout.nested())?;
+            layout.newline(f)?;
+            write!(f, "catch {} do", x)?;
+            layout.nested().newline(f)?;
+            write_com(f, handler, layout.nested())?;
+            layout.newline(f)?;
+            write!(f, "end")
+        }
+    }
+}
+
+/// Print a comma-separated argument or parameter list.
+pub(super) fn write_args<T: fmt::Display>(f: &mut fmt::Formatter<'_>, args: &[T]) -> fmt::Result {
+    for (i, a) in args.iter().enumerate() {
+        if i > 0 {
+            write!(f, ", ")?;
+        }
+        write!(f, "{}", a)?;
+    }
+    Ok(())
+}
+
+/// Print the header part of a `for`, on one line and in parentheses if it
+/// is a sequence.
+fn write_simple_com(f: &mut fmt::Formatter<'_>, c: &Com) -> fmt::Result {
+    match c {
+        Com::CSeq(..) => {
+            write!(f, "(")?;
+            write_com(f, c, Layout::Inline)?;
+            write!(f, ")")
+        }
+        _ => write_com(f, c, Layout::Inline),
+    }
+}
+
+/// How many lines `c` takes up in the block layout.
+pub(super) fn block_lines(c: &Com) -> usize {
+    match c {
+        Com::CSeq(c1, c2) => {
+            let parens = if let Com::CSeq(..) = **c1 { 2 } else { 0 };
+            parens + block_lines(c1) + block_lines(c2)
+        }
+        Com::CIf(_, c1, c2) | Com::CPar(c1, c2) | Com::CTry(c1, _, c2) => {
+            3 + block_lines(c1) + block_lines(c2)
+        }
+        Com::CWhile(_, body) | Com::CFor(_, _, _, body) => 2 + block_lines(body),
+        _ => 1,
+    }
+}
+
+/// The first lines of `c1` and `c2` in the block layout of `c1; c2`, if it
+/// starts on `line`.
+pub(super) fn seq_lines(c1: &Com, line: usize) -> (usize, usize) {
+    match c1 {
+        Com::CSeq(..) => (line + 1, line + 2 + block_lines(c1)),
+        _ => (line, line + block_lines(c1)),
+    }
+}
+
+impl fmt::Display for Com {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let layout = if f.alternate() {
+            Layout::Block(0)
+        } else {
+            Layout::Inline
+        };
+        write_com(f, self, layout)
+    }
+}
+
+impl Com {
+    /// The program laid out one command per line with indented bodies;
+    /// the same as `format!("{:#}", self)`.
+    pub fn to_pretty_string(&self) -> String {
+        format!("{:#}", self)
+    }
+}
+
+impl fmt::Display for Proc {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let layout = if f.alternate() {
+            Layout::Block(0)
+        } else {
+            Layout::Inline
+        };
+        write!(f, "proc {}(", self.name)?;
+        write_args(f, &self.params)?;
+        write!(f, ")")?;
+        if !self.locals.is_empty() {
+            write!(f, " local ")?;
+            write_args(f, &self.locals)?;
+        }
+        write!(f, " returns {} do", self.ret)?;
+        layout.nested().newline(f)?;
+        write_com(f, &self.body, layout.nested())?;
+        layout.newline(f)?;
+        write!(f, "end")
+    }
+}
+
+/// `{:#}` leaves a blank line after each procedure.
+impl fmt::Display for Program {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for p in &self.procs {
+            if f.alternate() {
+                write!(f, "{:#}\n\n", p)?;
+            } else {
+                write!(f, "{} ", p)?;
+            }
+        }
+        if f.alternate() {
+            write!(f, "{:#}", self.main)
+        } else {
+            write!(f, "{}", self.main)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test_imp_pretty {
+    use crate::imp::{parse_aexp, parse_bexp, parse_com, Aexp, Bexp, Com};
+
+    const FACT: &str = "Z := X; Y := 1; while ~(Z = 0) do Y := Y * Z; Z := Z - 1 end";
+
+    #[test]
+    fn test_print_factorial() {
+        let c = parse_com(FACT).unwrap();
+        assert_eq!(c.to_string(), FACT);
+        assert_eq!(
+            c.to_pretty_string(),
+            "Z := X;\nY := 1;\nwhile ~(Z = 0) do\n  Y := Y * Z;\n  Z := Z - 1\nend"
+        );
+    }
+
+    #[test]
+    fn test_aexp_parentheses() {
+        let a = Aexp::minus(Aexp::var("X"), Aexp::minus(Aexp::var("Y"), Aexp::num(1)));
+        assert_eq!(a.to_string(), "X - (Y - 1)");
+        let a = Aexp::mult(Aexp::plus(Aexp::num(1), Aexp::num(2)), Aexp::num(-3));
+        assert_eq!(a.to_string(), "(1 + 2) * -3");
+        let a = Aexp::plus(Aexp::plus(Aexp::num(1), Aexp::num(2)), Aexp::num(3));
+        assert_eq!(a.to_string(), "1 + 2 + 3");
+    }
+
+    #[test]
+    fn test_bexp_parentheses() {
+        let b = Bexp::and(
+            Bexp::BTrue,
+            Bexp::and(
+                Bexp::not(Bexp::BFalse),
+                Bexp::le(Aexp::var("X"), Aexp::num(2)),
+            ),
+        );
+        assert_eq!(b.to_string(), "true && (~false && X <= 2)");
+        assert_eq!(parse_bexp(&b.to_string()).unwrap(), b);
+    }
+
+    #[test]
+    fn test_nested_pretty() {
+        let c = parse_com("if X <= 1 then (skip; skip); Y := 1 else while true do skip end end")
+            .unwrap();
+        let pretty = c.to_pretty_string();
+        assert_eq!(
+            pretty,
+            "if X <= 1 then\n  (\n    skip;\n    skip\n  );\n  Y := 1\nelse\n  while true do\n    skip\n  end\nend"
+        );
+        assert_eq!(parse_com(&pretty).unwrap(), c);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let aexps = [
+            Aexp::num(i64::MIN),
+            Aexp::minus(Aexp::num(0), Aexp::mult(Aexp::var("X"), Aexp::var("Y"))),
+            Aexp::mult(Aexp::var("X"), Aexp::mult(Aexp::var("Y"), Aexp::var("Z"))),
+        ];
+        for a in &aexps {
+            assert_eq!(&parse_aexp(&a.to_string()).unwrap(), a);
+        }
+        let b = Bexp::not(Bexp::not(Bexp::and(
+            Bexp::eq(aexps[0].clone(), aexps[1].clone()),
+            Bexp::BTrue,
+        )));
+        assert_eq!(parse_bexp(&b.to_string()).unwrap(), b);
+        let coms = [
+            Com::seq(
+                Com::seq(Com::CSkip, Com::seq(Com::CSkip, Com::CSkip)),
+                Com::asgn("X", aexps[2].clone()),
+            ),
+            Com::if_(
+                b.clone(),
+                Com::CSkip,
+                Com::while_(b, Com::asgn("Y", aexps[1].clone())),
+            ),
+            parse_com(FACT).unwrap(),
+            parse_com("while true do if X = 1 then break else continue end end").unwrap(),
+            Com::for_(
+                Com::seq(Com::asgn("I", Aexp::num(0)), Com::asgn("S", Aexp::num(0))),
+                Bexp::le(Aexp::var("I"), Aexp::num(3)),
+                Com::asgn("I", Aexp::plus(Aexp::var("I"), Aexp::num(1))),
+                Com::asgn("S", Aexp::plus(Aexp::var("S"), Aexp::var("I"))),
+            ),
+            parse_com("havoc X; havoc Y").unwrap(),
+        ];
+        for c in &coms {
+            assert_eq!(&parse_com(&c.to_string()).unwrap(), c);
+            assert_eq!(&parse_com(&c.to_pretty_string()).unwrap(), c);
+        }
+    }
+}
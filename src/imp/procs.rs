@@ -0,0 +1,393 @@
+//! Procedures: a `Program` is a list of named procedures and a main
+//! command that calls them with `X := f(a1, ..., an)`.
+//!
+//! A procedure runs in a state of its own, built from nothing but its
+//! parameters, so its variables are local to the call and the caller's
+//! are out of reach. Its locals, like every other variable, start at `0`,
+//! and the value it returns is whatever its return variable holds when the
+//! body finishes. Procedures may call each other and themselves.
+
+use std::collections::BTreeSet;
+use std::fmt;
+use std::rc::Rc;
+
+use super::{Com, Config, EvalError, Machine, RunResult};
+use crate::map::{pm_empty, pm_update, PartialMap};
+use crate::state::State;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Proc {
+    pub name: String,
+    pub params: Vec<String>,
+    pub locals: Vec<String>,
+    pub ret: String,
+    pub body: Com,
+}
+
+impl Proc {
+    /// The variables the body may use: the parameters, the locals, and the
+    /// return variable.
+    pub fn scope(&self) -> BTreeSet<String> {
+        let mut scope: BTreeSet<String> = self.params.iter().cloned().collect();
+        scope.extend(self.locals.iter().cloned());
+        scope.insert(self.ret.clone());
+        scope
+    }
+}
+
+/// The procedures a call can go to, by name.
+pub type Procs = PartialMap<String, Rc<Proc>>;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Program {
+    pub procs: Vec<Proc>,
+    pub main: Com,
+}
+
+/// A mistake `Program::check` found. `proc` is the procedure it is in, or
+/// `None` for the main command.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProgramError {
+    DuplicateProc {
+        name: String,
+    },
+    /// A name declared twice among a procedure's parameters and locals.
+    DuplicateVar {
+        proc: String,
+        var: String,
+    },
+    /// A variable a procedure body uses without declaring it.
+    UndeclaredVar {
+        proc: String,
+        var: String,
+    },
+    UndefinedProc {
+        proc: Option<String>,
+        name: String,
+    },
+    ArityMismatch {
+        proc: Option<String>,
+        name: String,
+        expected: usize,
+        found: usize,
+    },
+}
+
+impl fmt::Display for ProgramError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let place = |proc: &Option<String>| match proc {
+            Some(p) => format!("`{}`", p),
+            None => "the main command".to_string(),
+        };
+        match self {
+            ProgramError::DuplicateProc { name } => {
+                write!(f, "procedure `{}` is defined twice", name)
+            }
+            ProgramError::DuplicateVar { proc, var } => {
+                write!(f, "`{}` is declared twice in `{}`", var, proc)
+            }
+            ProgramError::UndeclaredVar { proc, var } => {
+                write!(f, "`{}` uses undeclared variable `{}`", proc, var)
+            }
+            ProgramError::UndefinedProc { proc, name } => {
+                write!(f, "{} calls undefined procedure `{}`", place(proc), name)
+            }
+            ProgramError::ArityMismatch {
+                proc,
+                name,
+                expected,
+                found,
+            } => write!(
+                f,
+                "{} calls `{}` with {} arguments, but it takes {}",
+                place(proc),
+                name,
+                found,
+                expected
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ProgramError {}
+
+impl Program {
+    pub fn new(procs: Vec<Proc>, main: Com) -> Self {
+        Program { procs, main }
+    }
+
+    /// The procedures by name. If a name is defined twice, the later
+    /// definition wins; `check` reports it.
+    pub fn table(&self) -> Procs {
+        self.procs.iter().fold(pm_empty(), |table, p| {
+            pm_update(table, p.name.clone(), Rc::new(p.clone()))
+        })
+    }
+
+    /// Run the main command from `st`. Like `ceval`, this diverges if the
+    /// program does; unbounded recursion stops with
+    /// `EvalError::StackOverflow`.
+    pub fn run(&self, st: State) -> Result<State, EvalError> {
+        self.run_machine(st, None)
+    }
+
+    /// `run` with at most `fuel` loop iterations and calls in total.
+    pub fn run_fuel(&self, st: State, fuel: u64) -> Result<State, EvalError> {
+        self.run_machine(st, Some(fuel))
+    }
+
+    /// `run_fuel` that also returns what the program printed, including
+    /// anything printed before an error. Running out of fuel is only an
+    /// error inside a call; elsewhere the result is the rest of the main
+    /// command, as for `ceval_gas`, which `resume_output` carries on with.
+    pub fn run_output(&self, st: State, fuel: u64) -> (Result<RunResult, EvalError>, Vec<i64>) {
+        let mut machine = Machine::new(Some(fuel), self.table());
+        let result = machine.exec_gas(st, &self.main);
+        (result, machine.output)
+    }
+
+    /// `run_output` for `rest`, what was left of the main command when it
+    /// ran out of fuel, in place of the main command.
+    pub fn resume_output(
+        &self,
+        st: State,
+        rest: &Config,
+        fuel: u64,
+    ) -> (Result<RunResult, EvalError>, Vec<i64>) {
+        let mut machine = Machine::new(Some(fuel), self.table());
+        let result = machine.exec_gas(st, &rest.0);
+        (result, machine.output)
+    }
+
+    fn run_machine(&self, st: State, fuel: Option<u64>) -> Result<State, EvalError> {
+        Machine::new(fuel, self.table())
+            .exec(st, &self.main)
+            .map(|(st, _)| st)
+    }
+
+    /// Find the mistakes that would otherwise only show up while running,
+    /// or not at all: calls to missing procedures or with the wrong number
+    /// of arguments, and procedure bodies using variables they don't
+    /// declare (which would silently read `0`). Reports the first found.
+    pub fn check(&self) -> Result<(), ProgramError> {
+        let table = self.table();
+        let mut names = BTreeSet::new();
+        for p in &self.procs {
+            if !names.insert(&p.name) {
+                return Err(ProgramError::DuplicateProc {
+                    name: p.name.clone(),
+                });
+            }
+            let mut declared = BTreeSet::new();
+            for x in p.params.iter().chain(&p.locals) {
+                if !declared.insert(x) {
+                    return Err(ProgramError::DuplicateVar {
+                        proc: p.name.clone(),
+                        var: x.clone(),
+                    });
+                }
+            }
+            let scope = p.scope();
+            if let Some(x) = p.body.vars().into_iter().find(|x| !scope.contains(x)) {
+                return Err(ProgramError::UndeclaredVar {
+                    proc: p.name.clone(),
+                    var: x,
+                });
+            }
+            check_calls(&table, Some(&p.name), &p.body)?;
+        }
+        check_calls(&table, None, &self.main)
+    }
+}
+
+fn check_calls(table: &Procs, proc: Option<&String>, c: &Com) -> Result<(), ProgramError> {
+    match c {
+        Com::CSkip
+        | Com::CBreak
+        | Com::CContinue
+        | Com::CAsgn(..)
+        | Com::CArrAsgn(..)
+        | Com::CPrint(_)
+        | Com::CThrow(_)
+        | Com::CHavoc(_) => Ok(()),
+        Com::CSeq(c1, c2) | Com::CIf(_, c1, c2) | Com::CPar(c1, c2) | Com::CTry(c1, _, c2) => {
+            check_calls(table, proc, c1)?;
+            check_calls(table, proc, c2)
+        }
+        Com::CWhile(_, body) => check_calls(table, proc, body),
+        Com::CFor(init, _, update, body) => {
+            check_calls(table, proc, init)?;
+            check_calls(table, proc, update)?;
+            check_calls(table, proc, body)
+        }
+        Com::CCall(_, f, args) => match table(f) {
+            None => Err(ProgramError::UndefinedProc {
+                proc: proc.cloned(),
+                name: f.clone(),
+            }),
+            Some(p) if p.params.len() != args.len() => Err(ProgramError::ArityMismatch {
+                proc: proc.cloned(),
+                name: f.clone(),
+                expected: p.params.len(),
+                found: args.len(),
+            }),
+            Some(_) => Ok(()),
+        },
+    }
+}
+
+#[cfg(test)]
+mod test_imp_procs {
+    use super::*;
+    use crate::imp::{ceval, parse_com, parse_program, Unsupported, MAX_CALL_DEPTH};
+    use crate::state;
+    use crate::state::{empty_state, lookup};
+
+    const FACT: &str = "
+        proc fact(N) local M returns R do
+            if N = 0 then R := 1 else M := fact(N - 1); R := N * M end
+        end
+        Y := fact(X)";
+
+    #[test]
+    fn test_recursive_factorial() {
+        let prog = parse_program(FACT).unwrap();
+        assert_eq!(prog.check(), Ok(()));
+        let st = prog.run(state! {"X" => 5}).unwrap();
+        assert_eq!(lookup(&st, "Y"), 120);
+    }
+
+    #[test]
+    fn test_mutual_recursion() {
+        let prog = parse_program(
+            "proc even(N) local M returns R do
+                 if N = 0 then R := 1 else R := odd(N - 1) end
+             end
+             proc odd(N) local M returns R do
+                 if N = 0 then R := 0 else R := even(N - 1) end
+             end
+             A := even(10); B := odd(7); C := even(3)",
+        )
+        .unwrap();
+        let st = prog.run(empty_state()).unwrap();
+        assert_eq!(
+            (lookup(&st, "A"), lookup(&st, "B"), lookup(&st, "C")),
+            (1, 1, 0)
+        );
+    }
+
+    #[test]
+    fn test_scoping() {
+        // The callee's `X` is its own: the caller's `X` and `T` are neither
+        // visible to it nor changed by it.
+        let prog = parse_program(
+            "proc clobber(X) local T returns R do R := X + T; X := 100; T := 100 end
+             T := 5; Y := clobber(X + 1)",
+        )
+        .unwrap();
+        let st = prog.run(state! {"X" => 2}).unwrap();
+        assert_eq!(
+            (lookup(&st, "X"), lookup(&st, "T"), lookup(&st, "Y")),
+            (2, 5, 3)
+        );
+    }
+
+    #[test]
+    fn test_runtime_errors() {
+        let prog = parse_program("proc f(A, B) returns R do R := g(A) end X := f(1, 2)").unwrap();
+        let err = prog.run(empty_state()).map(|_| ()).unwrap_err();
+        assert_eq!(
+            err,
+            EvalError::UndefinedProc {
+                name: "g".to_string(),
+                stack: vec!["f".to_string()],
+            }
+        );
+        assert_eq!(
+            err.to_string(),
+            "call to undefined procedure `g` in main > f"
+        );
+
+        let prog = parse_program("proc f(A, B) returns R do skip end X := f(1)").unwrap();
+        assert!(matches!(
+            prog.run(empty_state()),
+            Err(EvalError::ArityMismatch {
+                expected: 2,
+                found: 1,
+                ..
+            })
+        ));
+
+        let prog = parse_program("proc f(N) returns R do R := f(N + 1) end X := f(0)").unwrap();
+        match prog.run(empty_state()) {
+            Err(EvalError::StackOverflow { stack }) => assert_eq!(stack.len(), MAX_CALL_DEPTH),
+            other => panic!("expected a stack overflow, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn test_fuel_counts_calls() {
+        let prog = parse_program(FACT).unwrap();
+        // fact(3) makes four calls.
+        assert!(prog.run_fuel(state! {"X" => 3}, 4).is_ok());
+        assert_eq!(
+            prog.run_fuel(state! {"X" => 3}, 3).map(|_| ()),
+            Err(EvalError::OutOfFuel)
+        );
+    }
+
+    #[test]
+    fn test_check() {
+        let cases = [
+            (
+                "proc f() returns R do R := X end skip",
+                "`f` uses undeclared variable `X`",
+            ),
+            (
+                "proc f(A) local A returns R do skip end skip",
+                "`A` is declared twice in `f`",
+            ),
+            (
+                "proc f() returns R do skip end proc f() returns R do skip end skip",
+                "procedure `f` is defined twice",
+            ),
+            (
+                "proc f() returns R do R := g() end skip",
+                "`f` calls undefined procedure `g`",
+            ),
+            (
+                "proc f(A) returns R do skip end while true do X := f(1, 2) end",
+                "the main command calls `f` with 2 arguments, but it takes 1",
+            ),
+        ];
+        for (src, msg) in cases {
+            let prog = parse_program(src).unwrap();
+            assert_eq!(prog.check().unwrap_err().to_string(), msg, "{}", src);
+        }
+    }
+
+    #[test]
+    fn test_print_round_trip() {
+        let prog = parse_program(FACT).unwrap();
+        assert_eq!(
+            prog.to_string(),
+            "proc fact(N) local M returns R do if N = 0 then R := 1 else M := fact(N - 1); \
+             R := N * M end end Y := fact(X)"
+        );
+        assert_eq!(parse_program(&prog.to_string()).unwrap(), prog);
+        assert_eq!(parse_program(&format!("{:#}", prog)).unwrap(), prog);
+        let library = parse_program("proc id(X) returns X do skip end").unwrap();
+        assert_eq!(library.main, Com::CSkip);
+        let c = parse_com("X := f(); Y := g(X, 2 * X)").unwrap();
+        assert_eq!(c.to_string(), "X := f(); Y := g(X, 2 * X)");
+    }
+
+    #[test]
+    fn test_ceval_has_no_procedures() {
+        let c = parse_com("X := f(1)").unwrap();
+        assert_eq!(
+            ceval(empty_state(), &c).err(),
+            Some(Unsupported("procedure calls"))
+        );
+    }
+}
@@ -0,0 +1,310 @@
+//! Hoare logic as a proof system, from HoareAsLogic: a `HoareProof` is a
+//! derivation built from the rules, `hoare_proof` in the book, and
+//! checking it means checking each rule is used on premises of the shape
+//! it needs. The rule of consequence also needs implications between
+//! assertions, which aren't derivations; the checker hands those back, to
+//! be discharged separately.
+
+use std::fmt;
+
+use super::{Aexp, Assertion, Bexp, Com, Formula, HoareTriple, Implication};
+
+/// A derivation of a triple, one constructor per rule of Hoare logic.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HoareProof {
+    /// `{{P}} skip {{P}}`.
+    Skip(Formula),
+    /// `{{Q [X |-> a]}} X := a {{Q}}`.
+    Asgn(String, Aexp, Formula),
+    /// From `{{P}} c1 {{Q}}` and `{{Q}} c2 {{R}}`, `{{P}} c1; c2 {{R}}`.
+    Seq(Box<HoareProof>, Box<HoareProof>),
+    /// From `{{P && b}} c1 {{Q}}` and `{{P && ~b}} c2 {{Q}}`,
+    /// `{{P}} if b then c1 else c2 end {{Q}}`.
+    If(Bexp, Box<HoareProof>, Box<HoareProof>),
+    /// From `{{P && b}} c {{P}}`, `{{P}} while b do c end {{P && ~b}}`.
+    While(Bexp, Box<HoareProof>),
+    /// From `P ->> P'`, `{{P'}} c {{Q'}}` and `Q' ->> Q`, `{{P}} c {{Q}}`.
+    Consequence(Formula, Box<HoareProof>, Formula),
+}
+
+/// Why a `HoareProof` doesn't derive what it should.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProofError {
+    /// A rule's premise has the wrong assertion: `expected` by the rule,
+    /// `found` in the premise's derivation.
+    Premise {
+        rule: &'static str,
+        expected: Formula,
+        found: Formula,
+    },
+    /// The proof is of a different triple from the one it was checked
+    /// against.
+    Conclusion { proved: String, wanted: String },
+    /// The triple has a predicate for an assertion, which no rule can
+    /// mention.
+    NotAFormula(String),
+}
+
+impl fmt::Display for ProofError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProofError::Premise {
+                rule,
+                expected,
+                found,
+            } => write!(
+                f,
+                "{} needs a premise with {{{{{}}}}} but has one with {{{{{}}}}}",
+                rule, expected, found
+            ),
+            ProofError::Conclusion { proved, wanted } => {
+                write!(f, "the proof is of {}, not {}", proved, wanted)
+            }
+            ProofError::NotAFormula(p) => write!(f, "{} is not a formula", p),
+        }
+    }
+}
+
+impl HoareProof {
+    pub fn seq(p1: HoareProof, p2: HoareProof) -> HoareProof {
+        HoareProof::Seq(Box::new(p1), Box::new(p2))
+    }
+
+    pub fn if_(b: Bexp, p1: HoareProof, p2: HoareProof) -> HoareProof {
+        HoareProof::If(b, Box::new(p1), Box::new(p2))
+    }
+
+    pub fn while_(b: Bexp, p: HoareProof) -> HoareProof {
+        HoareProof::While(b, Box::new(p))
+    }
+
+    pub fn consequence(pre: Formula, p: HoareProof, post: Formula) -> HoareProof {
+        HoareProof::Consequence(pre, Box::new(p), post)
+    }
+
+    /// The triple the proof derives, as its precondition, command and
+    /// postcondition, and the implications its uses of consequence rely
+    /// on, innermost first. The triple holds if they are all valid.
+    pub fn conclusion(&self) -> Result<(Formula, Com, Formula, Vec<Implication>), ProofError> {
+        let mut side = Vec::new();
+        let (pre, c, post) = self.derive(&mut side)?;
+        Ok((pre, c, post, side))
+    }
+
+    fn derive(&self, side: &mut Vec<Implication>) -> Result<(Formula, Com, Formula), ProofError> {
+        match self {
+            HoareProof::Skip(p) => Ok((p.clone(), Com::CSkip, p.clone())),
+            HoareProof::Asgn(x, a, q) => Ok((q.subst(x, a), Com::asgn(x, a.clone()), q.clone())),
+            HoareProof::Seq(p1, p2) => {
+                let (pre, c1, mid) = p1.derive(side)?;
+                let (mid2, c2, post) = p2.derive(side)?;
+                premise("hoare_seq", &mid, &mid2)?;
+                Ok((pre, Com::seq(c1, c2), post))
+            }
+            HoareProof::If(b, p1, p2) => {
+                let (pre1, c1, post) = p1.derive(side)?;
+                let (pre2, c2, post2) = p2.derive(side)?;
+                let Formula::FAnd(pre, _) = &pre1 else {
+                    return Err(ProofError::Premise {
+                        rule: "hoare_if",
+                        expected: Formula::and(pre1.clone(), Formula::from(b)),
+                        found: pre1,
+                    });
+                };
+                let pre = (**pre).clone();
+                let test = Formula::from(b);
+                premise("hoare_if", &Formula::and(pre.clone(), test.clone()), &pre1)?;
+                premise(
+                    "hoare_if",
+                    &Formula::and(pre.clone(), Formula::not(test)),
+                    &pre2,
+                )?;
+                premise("hoare_if", &post, &post2)?;
+                Ok((pre, Com::if_(b.clone(), c1, c2), post))
+            }
+            HoareProof::While(b, p) => {
+                let (pre, c, inv) = p.derive(side)?;
+                let test = Formula::from(b);
+                premise(
+                    "hoare_while",
+                    &Formula::and(inv.clone(), test.clone()),
+                    &pre,
+                )?;
+                let post = Formula::and(inv.clone(), Formula::not(test));
+                Ok((inv, Com::while_(b.clone(), c), post))
+            }
+            HoareProof::Consequence(pre, p, post) => {
+                let (pre1, c, post1) = p.derive(side)?;
+                side.push(Implication::new(pre.clone(), pre1));
+                side.push(Implication::new(post1, post.clone()));
+                Ok((pre.clone(), c, post.clone()))
+            }
+        }
+    }
+
+    /// Check that the proof derives `triple`, returning the implications
+    /// left to discharge, as `conclusion` does.
+    pub fn check(&self, triple: &HoareTriple) -> Result<Vec<Implication>, ProofError> {
+        let formula = |a: &Assertion| {
+            a.formula()
+                .cloned()
+                .ok_or_else(|| ProofError::NotAFormula(a.to_string()))
+        };
+        let (pre, post) = (formula(&triple.pre)?, formula(&triple.post)?);
+        let (pre1, c, post1, side) = self.conclusion()?;
+        if (&pre1, &c, &post1) != (&pre, &triple.com, &post) {
+            return Err(ProofError::Conclusion {
+                proved: HoareTriple::new(pre1, c, post1).to_string(),
+                wanted: triple.to_string(),
+            });
+        }
+        Ok(side)
+    }
+}
+
+fn premise(rule: &'static str, expected: &Formula, found: &Formula) -> Result<(), ProofError> {
+    if expected == found {
+        Ok(())
+    } else {
+        Err(ProofError::Premise {
+            rule,
+            expected: expected.clone(),
+            found: found.clone(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test_imp_proof {
+    use super::*;
+    use crate::imp::{discharge, parse_aexp, parse_bexp, parse_com, Strategy, Verdict};
+
+    fn f(src: &str) -> Formula {
+        Formula::from(parse_bexp(src).unwrap())
+    }
+
+    fn b(src: &str) -> Bexp {
+        parse_bexp(src).unwrap()
+    }
+
+    fn asgn(x: &str, a: &str, q: &str) -> HoareProof {
+        HoareProof::Asgn(x.to_string(), parse_aexp(a).unwrap(), f(q))
+    }
+
+    fn triple(pre: &str, c: &str, post: &str) -> HoareTriple {
+        HoareTriple::new(f(pre), parse_com(c).unwrap(), f(post))
+    }
+
+    fn valid(side: &[Implication]) -> bool {
+        let verdicts = discharge(side, &Strategy::Exhaustive { lo: -12, hi: 12 });
+        verdicts.iter().all(Verdict::holds)
+    }
+
+    #[test]
+    fn test_assignment_with_consequence() {
+        // hoare_asgn_example1 with consequence.
+        let proof = HoareProof::consequence(f("X = 1"), asgn("X", "X + 1", "X = 2"), f("X = 2"));
+        let side = proof
+            .check(&triple("X = 1", "X := X + 1", "X = 2"))
+            .unwrap();
+        assert_eq!(
+            side.iter().map(|s| s.to_string()).collect::<Vec<_>>(),
+            ["X = 1 ->> X + 1 = 2", "X = 2 ->> X = 2"]
+        );
+        assert!(valid(&side));
+        let (pre, _, _, _) = asgn("X", "X + 1", "X = 2").conclusion().unwrap();
+        assert_eq!(pre, f("X + 1 = 2"));
+    }
+
+    #[test]
+    fn test_if_and_seq() {
+        // {{true}} if X <= Y then Z := Y else Z := X end; W := Z {{X <= W}}
+        let branches = HoareProof::if_(
+            b("X <= Y"),
+            HoareProof::consequence(
+                Formula::and(Formula::FTrue, f("X <= Y")),
+                asgn("Z", "Y", "X <= Z"),
+                f("X <= Z"),
+            ),
+            HoareProof::consequence(
+                Formula::and(Formula::FTrue, Formula::not(f("X <= Y"))),
+                asgn("Z", "X", "X <= Z"),
+                f("X <= Z"),
+            ),
+        );
+        let proof = HoareProof::seq(branches.clone(), asgn("W", "Z", "X <= W"));
+        let c = "if X <= Y then Z := Y else Z := X end; W := Z";
+        let side = proof.check(&triple("true", c, "X <= W")).unwrap();
+        assert_eq!(side.len(), 4);
+        assert!(valid(&side));
+
+        // The seq rule needs the if to end where the assignment starts.
+        let proof = HoareProof::seq(branches, asgn("W", "Z", "Y <= W"));
+        assert_eq!(
+            proof.check(&triple("true", c, "Y <= W")),
+            Err(ProofError::Premise {
+                rule: "hoare_seq",
+                expected: f("X <= Z"),
+                found: f("Y <= Z"),
+            })
+        );
+    }
+
+    #[test]
+    fn test_while() {
+        // {{X <= 10}} while X <= 9 do X := X + 1 end {{X = 10}}, with
+        // invariant X <= 10.
+        let body = HoareProof::consequence(
+            Formula::and(f("X <= 10"), f("X <= 9")),
+            asgn("X", "X + 1", "X <= 10"),
+            f("X <= 10"),
+        );
+        let proof = HoareProof::consequence(
+            f("X <= 10"),
+            HoareProof::while_(b("X <= 9"), body),
+            f("X = 10"),
+        );
+        let side = proof
+            .check(&triple(
+                "X <= 10",
+                "while X <= 9 do X := X + 1 end",
+                "X = 10",
+            ))
+            .unwrap();
+        assert_eq!(side.len(), 4);
+        assert_eq!(side[3].to_string(), "X <= 10 && ~(X <= 9) ->> X = 10");
+        assert!(valid(&side));
+
+        // The body has to preserve the invariant under the loop's test.
+        let wrong = HoareProof::while_(b("X <= 9"), asgn("X", "X + 1", "X <= 10"));
+        assert_eq!(
+            wrong.conclusion().unwrap_err().to_string(),
+            "hoare_while needs a premise with {{X <= 10 && X <= 9}} but has one with \
+             {{X + 1 <= 10}}"
+        );
+    }
+
+    #[test]
+    fn test_wrong_conclusion() {
+        let proof = HoareProof::Skip(f("X = 1"));
+        let err = proof.check(&triple("X = 1", "skip", "X = 2")).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "the proof is of {{X = 1}} skip {{X = 1}}, not {{X = 1}} skip {{X = 2}}"
+        );
+        let pred = HoareTriple::new(
+            Assertion::pred("anything", |_| true),
+            Com::CSkip,
+            Formula::FTrue,
+        );
+        assert_eq!(
+            proof.check(&pred),
+            Err(ProofError::NotAFormula("anything".to_string()))
+        );
+        // A consequence whose implication is false still checks, but leaves
+        // a condition discharging refutes.
+        let proof = HoareProof::consequence(f("true"), HoareProof::Skip(f("X = 1")), f("X = 1"));
+        let side = proof.check(&triple("true", "skip", "X = 1")).unwrap();
+        assert!(!valid(&side));
+    }
+}
@@ -0,0 +1,494 @@
+//! Random Imp expressions, programs and states for the differential test
+//! harnesses, and shrinking to cut a failing program or state down to
+//! size.
+//!
+//! Generated programs stick to what every evaluator supports: no `havoc`,
+//! `par`, procedure calls or arrays. Most loops count a variable of their
+//! own down to `0`, so most programs terminate.
+
+use super::{Aexp, Bexp, Com};
+use crate::map::tm_update;
+use crate::rng::Rng;
+use crate::state::{empty_state, State};
+
+/// A random number, mostly small, with the occasional extreme value so that
+/// wrapping arithmetic gets exercised.
+pub fn random_value(rng: &mut Rng) -> i64 {
+    if rng.chance(1, 20) {
+        *rng.choose(&[i64::MIN, i64::MAX, i64::MIN + 1, i64::MAX - 1])
+    } else {
+        rng.range(-20, 20)
+    }
+}
+
+/// A random arithmetic expression with at most `size` operators over the
+/// variables in `vars`. With no variables, only constants are generated.
+pub fn random_aexp(rng: &mut Rng, size: usize, vars: &[&str]) -> Aexp {
+    if size == 0 || rng.chance(1, 4) {
+        return if vars.is_empty() || rng.chance(1, 2) {
+            Aexp::ANum(random_value(rng))
+        } else {
+            let x = *rng.choose(vars);
+            Aexp::var(x)
+        };
+    }
+    let left = rng.below(size as u64) as usize;
+    let a1 = random_aexp(rng, left, vars);
+    let a2 = random_aexp(rng, size - 1 - left, vars);
+    match rng.below(3) {
+        0 => Aexp::plus(a1, a2),
+        1 => Aexp::minus(a1, a2),
+        _ => Aexp::mult(a1, a2),
+    }
+}
+
+/// A random boolean expression with at most `size` operators, counting
+/// those of the arithmetic expressions it compares.
+pub fn random_bexp(rng: &mut Rng, size: usize, vars: &[&str]) -> Bexp {
+    if size == 0 {
+        return if rng.chance(1, 2) {
+            Bexp::BTrue
+        } else {
+            Bexp::BFalse
+        };
+    }
+    let left = rng.below(size as u64) as usize;
+    match rng.below(4) {
+        0 => Bexp::not(random_bexp(rng, size - 1, vars)),
+        1 => Bexp::and(
+            random_bexp(rng, left, vars),
+            random_bexp(rng, size - 1 - left, vars),
+        ),
+        2 => Bexp::eq(
+            random_aexp(rng, left, vars),
+            random_aexp(rng, size - 1 - left, vars),
+        ),
+        _ => Bexp::le(
+            random_aexp(rng, left, vars),
+            random_aexp(rng, size - 1 - left, vars),
+        ),
+    }
+}
+
+/// The largest expression, in operators, in a generated program.
+const EXP_SIZE: usize = 3;
+
+/// The most iterations a generated counting loop runs.
+const MAX_ITERATIONS: i64 = 3;
+
+/// A random program of about `size` commands over the variables in `vars`.
+/// Loops are mostly `for` loops counting `I0`, `I1`, ... (one name per
+/// nesting depth, which the body leaves alone) up to a small bound; now
+/// and then a `while` loop tests a random condition and may run forever,
+/// so run what this makes with fuel. `break` and `continue` only appear
+/// inside loops, and `throw` mostly inside a `try`.
+pub fn gen_random_com(size: usize, vars: &[&str], rng: &mut Rng) -> Com {
+    let mut generator = Generator {
+        rng,
+        vars,
+        loops: 0,
+        tries: 0,
+    };
+    generator.com(size)
+}
+
+struct Generator<'a> {
+    rng: &'a mut Rng,
+    vars: &'a [&'a str],
+    /// How many loops the command being generated is inside.
+    loops: usize,
+    /// How many `try` bodies it is inside.
+    tries: usize,
+}
+
+impl Generator<'_> {
+    fn com(&mut self, size: usize) -> Com {
+        if size <= 1 {
+            return self.simple();
+        }
+        match self.rng.below(10) {
+            0..=3 => {
+                let left = 1 + self.rng.below(size as u64 - 1) as usize;
+                Com::seq(self.com(left), self.com(size - left))
+            }
+            4 | 5 => {
+                let b = self.bexp();
+                let left = self.rng.below(size as u64) as usize;
+                Com::if_(b, self.com(left), self.com(size - 1 - left))
+            }
+            6 | 7 => {
+                let counter = format!("I{}", self.loops);
+                let bound = self.rng.range(0, MAX_ITERATIONS - 1);
+                self.loops += 1;
+                let body = self.com(size - 1);
+                self.loops -= 1;
+                Com::for_(
+                    Com::asgn(&counter, Aexp::num(0)),
+                    Bexp::le(Aexp::var(&counter), Aexp::num(bound)),
+                    Com::asgn(&counter, Aexp::plus(Aexp::var(&counter), Aexp::num(1))),
+                    body,
+                )
+            }
+            8 if !self.vars.is_empty() => {
+                let left = self.rng.below(size as u64) as usize;
+                self.tries += 1;
+                let body = self.com(left.max(1));
+                self.tries -= 1;
+                let x = *self.rng.choose(self.vars);
+                Com::try_(body, x, self.com(size - 1 - left))
+            }
+            _ => {
+                let b = self.bexp();
+                self.loops += 1;
+                let body = self.com(size - 1);
+                self.loops -= 1;
+                Com::while_(b, body)
+            }
+        }
+    }
+
+    fn simple(&mut self) -> Com {
+        let roll = self.rng.below(20);
+        match roll {
+            0 | 1 if self.loops > 0 => Com::CBreak,
+            2 if self.loops > 0 => Com::CContinue,
+            3 if self.tries > 0 || self.rng.chance(1, 4) => Com::throw(self.aexp()),
+            4 | 5 => Com::print(self.aexp()),
+            _ if self.vars.is_empty() => Com::CSkip,
+            6 => Com::CSkip,
+            _ => {
+                let x = *self.rng.choose(self.vars);
+                Com::asgn(x, self.aexp())
+            }
+        }
+    }
+
+    fn aexp(&mut self) -> Aexp {
+        let size = self.rng.below(EXP_SIZE as u64 + 1) as usize;
+        random_aexp(self.rng, size, self.vars)
+    }
+
+    fn bexp(&mut self) -> Bexp {
+        let size = self.rng.below(EXP_SIZE as u64 + 1) as usize;
+        random_bexp(self.rng, size, self.vars)
+    }
+}
+
+/// Programs a little simpler than `c`, simplest first: `skip`, the parts
+/// of `c` that are commands, and `c` with one of those parts or one of its
+/// expressions shrunk. A counting loop's header is left alone, so what
+/// terminated still does.
+pub fn shrink_com(c: &Com) -> Vec<Com> {
+    let mut smaller = Vec::new();
+    if *c != Com::CSkip {
+        smaller.push(Com::CSkip);
+    }
+    match c {
+        Com::CSkip | Com::CBreak | Com::CContinue | Com::CHavoc(_) => {}
+        Com::CAsgn(x, a) => {
+            smaller.extend(shrink_aexp(a).into_iter().map(|a| Com::CAsgn(x.clone(), a)))
+        }
+        Com::CPrint(a) => smaller.extend(shrink_aexp(a).into_iter().map(Com::CPrint)),
+        Com::CThrow(a) => smaller.extend(shrink_aexp(a).into_iter().map(Com::CThrow)),
+        Com::CCall(x, f, args) => {
+            for (i, a) in args.iter().enumerate() {
+                for a in shrink_aexp(a) {
+                    let mut args = args.clone();
+                    args[i] = a;
+                    smaller.push(Com::CCall(x.clone(), f.clone(), args));
+                }
+            }
+        }
+        Com::CArrAsgn(x, i, a) => {
+            for i in shrink_aexp(i) {
+                smaller.push(Com::CArrAsgn(x.clone(), i, a.clone()));
+            }
+            for a in shrink_aexp(a) {
+                smaller.push(Com::CArrAsgn(x.clone(), i.clone(), a));
+            }
+        }
+        Com::CSeq(c1, c2) | Com::CPar(c1, c2) => {
+            smaller.push((**c1).clone());
+            smaller.push((**c2).clone());
+            let rebuild = |c1, c2| match c {
+                Com::CSeq(..) => Com::seq(c1, c2),
+                _ => Com::par(c1, c2),
+            };
+            for c1 in shrink_com(c1) {
+                smaller.push(rebuild(c1, (**c2).clone()));
+            }
+            for c2 in shrink_com(c2) {
+                smaller.push(rebuild((**c1).clone(), c2));
+            }
+        }
+        Com::CIf(b, c1, c2) => {
+            smaller.push((**c1).clone());
+            smaller.push((**c2).clone());
+            for b in shrink_bexp(b) {
+                smaller.push(Com::if_(b, (**c1).clone(), (**c2).clone()));
+            }
+            for c1 in shrink_com(c1) {
+                smaller.push(Com::if_(b.clone(), c1, (**c2).clone()));
+            }
+            for c2 in shrink_com(c2) {
+                smaller.push(Com::if_(b.clone(), (**c1).clone(), c2));
+            }
+        }
+        Com::CWhile(b, body) => {
+            smaller.push((**body).clone());
+            for body in shrink_com(body) {
+                smaller.push(Com::while_(b.clone(), body));
+            }
+        }
+        Com::CFor(init, b, update, body) => {
+            smaller.push((**body).clone());
+            for body in shrink_com(body) {
+                smaller.push(Com::for_(
+                    (**init).clone(),
+                    b.clone(),
+                    (**update).clone(),
+                    body,
+                ));
+            }
+        }
+        Com::CTry(body, x, handler) => {
+            smaller.push((**body).clone());
+            smaller.push((**handler).clone());
+            for body in shrink_com(body) {
+                smaller.push(Com::try_(body, x, (**handler).clone()));
+            }
+            for handler in shrink_com(handler) {
+                smaller.push(Com::try_((**body).clone(), x, handler));
+            }
+        }
+    }
+    smaller
+}
+
+/// Expressions a little simpler than `a`: `0`, its operands, and `a` with
+/// one of them shrunk.
+fn shrink_aexp(a: &Aexp) -> Vec<Aexp> {
+    let mut smaller = Vec::new();
+    if *a != Aexp::ANum(0) {
+        smaller.push(Aexp::ANum(0));
+    }
+    match a {
+        Aexp::ANum(n) if *n != 0 && *n != 1 => smaller.push(Aexp::ANum(1)),
+        Aexp::ANum(_) | Aexp::AId(_) => {}
+        Aexp::APlus(a1, a2) | Aexp::AMinus(a1, a2) | Aexp::AMult(a1, a2) => {
+            smaller.push((**a1).clone());
+            smaller.push((**a2).clone());
+            let rebuild = |a1, a2| match a {
+                Aexp::APlus(..) => Aexp::plus(a1, a2),
+                Aexp::AMinus(..) => Aexp::minus(a1, a2),
+                _ => Aexp::mult(a1, a2),
+            };
+            for a1 in shrink_aexp(a1) {
+                smaller.push(rebuild(a1, (**a2).clone()));
+            }
+            for a2 in shrink_aexp(a2) {
+                smaller.push(rebuild((**a1).clone(), a2));
+            }
+        }
+        Aexp::AIndex(x, i) => {
+            for i in shrink_aexp(i) {
+                smaller.push(Aexp::AIndex(x.clone(), Box::new(i)));
+            }
+        }
+    }
+    smaller
+}
+
+fn shrink_bexp(b: &Bexp) -> Vec<Bexp> {
+    let mut smaller = Vec::new();
+    match b {
+        Bexp::BTrue => {}
+        Bexp::BFalse => smaller.push(Bexp::BTrue),
+        Bexp::BNot(b1) => {
+            smaller.push((**b1).clone());
+            smaller.extend(shrink_bexp(b1).into_iter().map(Bexp::not));
+        }
+        Bexp::BAnd(b1, b2) => {
+            smaller.push((**b1).clone());
+            smaller.push((**b2).clone());
+        }
+        Bexp::BEq(a1, a2) | Bexp::BLe(a1, a2) => {
+            smaller.extend([Bexp::BTrue, Bexp::BFalse]);
+            let rebuild = |a1, a2| match b {
+                Bexp::BEq(..) => Bexp::eq(a1, a2),
+                _ => Bexp::le(a1, a2),
+            };
+            for a1 in shrink_aexp(a1) {
+                smaller.push(rebuild(a1, (**a2).clone()));
+            }
+            for a2 in shrink_aexp(a2) {
+                smaller.push(rebuild((**a1).clone(), a2));
+            }
+        }
+    }
+    smaller
+}
+
+/// Shrink `c` for as long as some simpler program still `fails`, and
+/// return the last one that did. `c` itself should fail.
+pub fn minimize_com(c: &Com, fails: impl Fn(&Com) -> bool) -> Com {
+    let mut c = c.clone();
+    while let Some(smaller) = shrink_com(&c).into_iter().find(|c| fails(c)) {
+        c = smaller;
+    }
+    c
+}
+
+/// Numbers a little simpler than `n`, simplest first: `0`, `-n` if `n` is
+/// negative, and `n` halved or moved one towards `0`.
+pub fn shrink_value(n: i64) -> Vec<i64> {
+    let mut smaller = Vec::new();
+    let candidates = [
+        Some(0),
+        n.checked_neg().filter(|_| n < 0),
+        Some(n / 2),
+        Some(n - n.signum()),
+    ];
+    for m in candidates.into_iter().flatten() {
+        if m != n && !smaller.contains(&m) {
+            smaller.push(m);
+        }
+    }
+    smaller
+}
+
+/// Shrink the values of `vars` in `st` with `shrink_value`, one variable
+/// at a time, for as long as some simpler state still `fails`, and return
+/// the last one that did. Other variables are left as they are. `st`
+/// itself should fail.
+pub fn minimize_state<S: AsRef<str>>(
+    st: &State,
+    vars: &[S],
+    fails: impl Fn(&State) -> bool,
+) -> State {
+    let mut st = st.clone();
+    'shrinking: loop {
+        for x in vars {
+            let x = x.as_ref().to_string();
+            for n in shrink_value(st(&x)) {
+                let smaller = tm_update(st.clone(), x.clone(), n);
+                if fails(&smaller) {
+                    st = smaller;
+                    continue 'shrinking;
+                }
+            }
+        }
+        return st;
+    }
+}
+
+/// A state giving each of `vars` a random value.
+pub fn random_state(rng: &mut Rng, vars: &[&str]) -> State {
+    vars.iter().fold(empty_state(), |st, x| {
+        tm_update(st, x.to_string(), random_value(rng))
+    })
+}
+
+#[cfg(test)]
+mod test_imp_random {
+    use super::*;
+    use crate::imp::{ceval_output, normalize_output, parse_com, r_compile, r_execute};
+    use crate::state;
+    use crate::state::{lookup, StateExt};
+
+    fn operators(a: &Aexp) -> usize {
+        match a {
+            Aexp::ANum(_) | Aexp::AId(_) => 0,
+            Aexp::APlus(a1, a2) | Aexp::AMinus(a1, a2) | Aexp::AMult(a1, a2) => {
+                1 + operators(a1) + operators(a2)
+            }
+            Aexp::AIndex(_, i) => 1 + operators(i),
+        }
+    }
+
+    #[test]
+    fn test_aexp_size_and_vars() {
+        let mut rng = Rng::new(0);
+        for _ in 0..200 {
+            let a = random_aexp(&mut rng, 6, &["X", "Y"]);
+            assert!(a.vars().iter().all(|x| x == "X" || x == "Y"));
+            assert!(operators(&a) <= 6);
+        }
+        assert!(random_aexp(&mut rng, 5, &[]).vars().is_empty());
+    }
+
+    #[test]
+    fn test_generated_programs() {
+        let mut rng = Rng::new(11);
+        let mut finished = 0;
+        for _ in 0..300 {
+            let c = gen_random_com(12, &["X", "Y"], &mut rng);
+            assert_eq!(parse_com(&c.to_pretty_string()).unwrap(), c);
+            assert!(c
+                .vars()
+                .iter()
+                .all(|x| x == "X" || x == "Y" || x.starts_with('I')));
+            let st = random_state(&mut rng, &["X", "Y"]);
+            let (result, printed) = ceval_output(st.clone(), &c, 1_000).unwrap();
+            let Some(expected) = result.finished() else {
+                continue;
+            };
+            finished += 1;
+            let (found, output) = normalize_output(st.clone(), &c, 100_000).unwrap();
+            let found = found.unwrap();
+            let compiled = r_execute(st, &r_compile(&c).unwrap(), 100_000).unwrap();
+            for x in c.vars() {
+                assert_eq!(found(&x), expected(&x), "{} in {}", x, c);
+                assert_eq!((compiled.state)(&x), expected(&x), "{} in {}", x, c);
+            }
+            assert_eq!(output, printed, "{}", c);
+            assert_eq!(compiled.output, printed, "{}", c);
+        }
+        assert!(finished > 250, "only {} finished", finished);
+    }
+
+    #[test]
+    fn test_minimize() {
+        let c = parse_com(
+            "X := 1; for I0 := 0; I0 <= 2; I0 := I0 + 1 do \
+             if X <= 3 then Y := Y + X * 2; print Y - 7 else skip end end; Z := 4",
+        )
+        .unwrap();
+        // Anything printing a negative number.
+        let fails = |c: &Com| {
+            let (_, printed) = ceval_output(empty_state(), c, 100).unwrap();
+            printed.iter().any(|n| *n < 0)
+        };
+        assert!(fails(&c));
+        let small = minimize_com(&c, fails);
+        assert!(fails(&small));
+        assert_eq!(small, parse_com("print 0 - 1").unwrap());
+        assert!(shrink_com(&c).iter().all(|smaller| *smaller != c));
+        assert!(shrink_com(&Com::CSkip).is_empty());
+    }
+
+    #[test]
+    fn test_minimize_state() {
+        assert_eq!(shrink_value(-7), vec![0, 7, -3, -6]);
+        assert_eq!(shrink_value(1), vec![0]);
+        assert!(shrink_value(0).is_empty());
+        assert_eq!(shrink_value(i64::MIN)[..2], [0, i64::MIN / 2]);
+
+        // Fails while X is at least 5 and below Y; Z doesn't matter.
+        let fails = |st: &State| 5 <= lookup(st, "X") && lookup(st, "X") < lookup(st, "Y");
+        let st = state! {"X" => 17, "Y" => i64::MAX, "Z" => -12};
+        let small = minimize_state(&st, &["X", "Y", "Z"], fails);
+        assert_eq!(small.show(&["X", "Y", "Z"]), "X=5, Y=6, Z=0");
+        // Only the named variables shrink.
+        let small = minimize_state(&st, &["Y"], fails);
+        assert_eq!(small.show(&["X", "Y", "Z"]), "X=17, Y=18, Z=-12");
+    }
+
+    #[test]
+    fn test_state_covers_vars_only() {
+        let mut rng = Rng::new(5);
+        let states: Vec<State> = (0..50).map(|_| random_state(&mut rng, &["X"])).collect();
+        assert!(states.iter().all(|st| lookup(st, "Y") == 0));
+        assert!(states.iter().any(|st| lookup(st, "X") != 0));
+    }
+}
@@ -0,0 +1,540 @@
+//! A register machine with three-address code, and a compiler from whole
+//! commands to it. Unlike the stack machine, which only evaluates
+//! expressions, this one has jumps, so loops and conditionals compile to
+//! plain control flow: a loop is a test that jumps past it, its body, and
+//! a jump back, and `break` and `continue` are jumps to either end. A
+//! `throw` jumps to its handler, or, uncaught, halts with `RThrow`.
+//!
+//! Expressions are evaluated into registers numbered from the one the
+//! result goes in, each operator using the next register up for its right
+//! operand, so a program needs as many registers as its deepest
+//! expression. Tests compile to conditional jumps rather than values.
+
+use std::collections::HashMap;
+use std::fmt;
+
+use super::{Aexp, Bexp, Com, Unsupported};
+use crate::map::tm_update;
+use crate::state::State;
+
+pub type Reg = usize;
+pub type Label = usize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ROp {
+    Add,
+    Sub,
+    Mul,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RCond {
+    Eq,
+    Ne,
+    Le,
+    Gt,
+}
+
+impl RCond {
+    fn holds(self, n1: i64, n2: i64) -> bool {
+        match self {
+            RCond::Eq => n1 == n2,
+            RCond::Ne => n1 != n2,
+            RCond::Le => n1 <= n2,
+            RCond::Gt => n1 > n2,
+        }
+    }
+
+    fn negate(self) -> RCond {
+        match self {
+            RCond::Eq => RCond::Ne,
+            RCond::Ne => RCond::Eq,
+            RCond::Le => RCond::Gt,
+            RCond::Gt => RCond::Le,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum RInstr {
+    /// `RConst(r, n)` is `r := n`.
+    RConst(Reg, i64),
+    /// `RLoad(r, x)` is `r := x`, reading a variable.
+    RLoad(Reg, String),
+    /// `RStore(x, r)` is `x := r`, writing a variable.
+    RStore(String, Reg),
+    /// `ROp(op, r, r1, r2)` is `r := r1 op r2`, wrapping like `aeval`.
+    ROp(ROp, Reg, Reg, Reg),
+    RPrint(Reg),
+    RJump(Label),
+    /// `RJumpIf(cond, r1, r2, l)` jumps to `l` if `r1 cond r2`, and
+    /// otherwise goes on to the next instruction.
+    RJumpIf(RCond, Reg, Reg, Label),
+    /// Marks where jumps to the label land; running it does nothing.
+    RLabel(Label),
+    /// Halts with an uncaught exception, thrown with the value of the
+    /// register.
+    RThrow(Reg),
+}
+
+impl fmt::Display for RInstr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RInstr::RConst(r, n) => write!(f, "  r{} := {}", r, n),
+            RInstr::RLoad(r, x) => write!(f, "  r{} := {}", r, x),
+            RInstr::RStore(x, r) => write!(f, "  {} := r{}", x, r),
+            RInstr::ROp(op, r, r1, r2) => {
+                let op = match op {
+                    ROp::Add => "+",
+                    ROp::Sub => "-",
+                    ROp::Mul => "*",
+                };
+                write!(f, "  r{} := r{} {} r{}", r, r1, op, r2)
+            }
+            RInstr::RPrint(r) => write!(f, "  print r{}", r),
+            RInstr::RJump(l) => write!(f, "  goto L{}", l),
+            RInstr::RJumpIf(cond, r1, r2, l) => {
+                let cond = match cond {
+                    RCond::Eq => "=",
+                    RCond::Ne => "<>",
+                    RCond::Le => "<=",
+                    RCond::Gt => ">",
+                };
+                write!(f, "  if r{} {} r{} goto L{}", r1, cond, r2, l)
+            }
+            RInstr::RLabel(l) => write!(f, "L{}:", l),
+            RInstr::RThrow(r) => write!(f, "  throw r{}", r),
+        }
+    }
+}
+
+/// How a register machine run ended, short of running out of steps.
+#[derive(Clone)]
+pub struct RFinal {
+    pub state: State,
+    /// What `RPrint` printed, in order.
+    pub output: Vec<i64>,
+    /// What an `RThrow` threw, if one halted the run.
+    pub thrown: Option<i64>,
+}
+
+/// Compile `c` to code that leaves the state `ceval` would, and prints
+/// what `ceval_output` would. `havoc` sets its variable to `0`, a `par`
+/// runs its branches one after the other, and a `break` or `continue`
+/// outside any loop ends the program, as in `ceval`. An uncaught `throw`
+/// ends it with `RThrow`. The machine has no procedures and no arrays, so
+/// a call or an array access is an error.
+pub fn r_compile(c: &Com) -> Result<Vec<RInstr>, Unsupported> {
+    let mut compiler = Compiler {
+        code: Vec::new(),
+        labels: 0,
+        unsupported: None,
+    };
+    let end = compiler.label();
+    compiler.com(
+        c,
+        Exits {
+            brk: end,
+            cont: end,
+            catch: None,
+        },
+    );
+    compiler.code.push(RInstr::RLabel(end));
+    match compiler.unsupported {
+        Some(e) => Err(e),
+        None => Ok(compiler.code),
+    }
+}
+
+/// Run `prog` from `st` for at most `max_steps` instructions, or `None` if
+/// it hasn't finished by then. Registers start out `0`.
+///
+/// Panics if a jump goes to a label `prog` doesn't define.
+pub fn r_execute(st: State, prog: &[RInstr], max_steps: usize) -> Option<RFinal> {
+    let mut targets = HashMap::new();
+    let mut n_regs = 0;
+    for (pc, instr) in prog.iter().enumerate() {
+        match instr {
+            RInstr::RLabel(l) => {
+                targets.insert(*l, pc);
+            }
+            RInstr::RConst(r, _)
+            | RInstr::RLoad(r, _)
+            | RInstr::RStore(_, r)
+            | RInstr::RPrint(r)
+            | RInstr::RThrow(r) => n_regs = n_regs.max(r + 1),
+            RInstr::ROp(_, r, r1, r2) => n_regs = n_regs.max(r.max(r1).max(r2) + 1),
+            RInstr::RJumpIf(_, r1, r2, _) => n_regs = n_regs.max(r1.max(r2) + 1),
+            RInstr::RJump(_) => {}
+        }
+    }
+    let target = |l: &Label| match targets.get(l) {
+        Some(&pc) => pc,
+        None => panic!("jump to undefined label L{}", l),
+    };
+    let mut regs = vec![0; n_regs];
+    let mut st = st;
+    let mut output = Vec::new();
+    let mut pc = 0;
+    for _ in 0..max_steps {
+        let Some(instr) = prog.get(pc) else {
+            return Some(RFinal {
+                state: st,
+                output,
+                thrown: None,
+            });
+        };
+        pc += 1;
+        match instr {
+            RInstr::RConst(r, n) => regs[*r] = *n,
+            RInstr::RLoad(r, x) => regs[*r] = st(x),
+            RInstr::RStore(x, r) => st = tm_update(st, x.clone(), regs[*r]),
+            RInstr::ROp(op, r, r1, r2) => {
+                let (n1, n2) = (regs[*r1], regs[*r2]);
+                regs[*r] = match op {
+                    ROp::Add => n1.wrapping_add(n2),
+                    ROp::Sub => n1.wrapping_sub(n2),
+                    ROp::Mul => n1.wrapping_mul(n2),
+                };
+            }
+            RInstr::RPrint(r) => output.push(regs[*r]),
+            RInstr::RJump(l) => pc = target(l),
+            RInstr::RJumpIf(cond, r1, r2, l) => {
+                if cond.holds(regs[*r1], regs[*r2]) {
+                    pc = target(l);
+                }
+            }
+            RInstr::RLabel(_) => {}
+            RInstr::RThrow(r) => {
+                return Some(RFinal {
+                    state: st,
+                    output,
+                    thrown: Some(regs[*r]),
+                })
+            }
+        }
+    }
+    (pc >= prog.len()).then_some(RFinal {
+        state: st,
+        output,
+        thrown: None,
+    })
+}
+
+/// Where `break` and `continue` jump to, and where a `throw` does, with
+/// the value thrown in `r0`, if it is caught.
+#[derive(Clone, Copy)]
+struct Exits {
+    brk: Label,
+    cont: Label,
+    catch: Option<Label>,
+}
+
+struct Compiler {
+    code: Vec<RInstr>,
+    labels: usize,
+    /// The first construct found that the machine doesn't have.
+    unsupported: Option<Unsupported>,
+}
+
+impl Compiler {
+    fn label(&mut self) -> Label {
+        self.labels += 1;
+        self.labels - 1
+    }
+
+    /// Code leaving the value of `a` in `r`, using the registers above it.
+    fn aexp(&mut self, a: &Aexp, r: Reg) {
+        match a {
+            Aexp::ANum(n) => self.code.push(RInstr::RConst(r, *n)),
+            Aexp::AId(x) => self.code.push(RInstr::RLoad(r, x.clone())),
+            Aexp::APlus(a1, a2) | Aexp::AMinus(a1, a2) | Aexp::AMult(a1, a2) => {
+                self.aexp(a1, r);
+                self.aexp(a2, r + 1);
+                let op = match a {
+                    Aexp::APlus(..) => ROp::Add,
+                    Aexp::AMinus(..) => ROp::Sub,
+                    _ => ROp::Mul,
+                };
+                self.code.push(RInstr::ROp(op, r, r, r + 1));
+            }
+            Aexp::AIndex(..) => {
+                self.unsupported.get_or_insert(Unsupported("arrays"));
+            }
+        }
+    }
+
+    /// Code jumping to `target` if `b` evaluates to `when`, and otherwise
+    /// going on.
+    fn branch(&mut self, b: &Bexp, when: bool, target: Label) {
+        match b {
+            Bexp::BTrue | Bexp::BFalse => {
+                if (*b == Bexp::BTrue) == when {
+                    self.code.push(RInstr::RJump(target));
+                }
+            }
+            Bexp::BEq(a1, a2) | Bexp::BLe(a1, a2) => {
+                self.aexp(a1, 0);
+                self.aexp(a2, 1);
+                let cond = if matches!(b, Bexp::BEq(..)) {
+                    RCond::Eq
+                } else {
+                    RCond::Le
+                };
+                let cond = if when { cond } else { cond.negate() };
+                self.code.push(RInstr::RJumpIf(cond, 0, 1, target));
+            }
+            Bexp::BNot(b1) => self.branch(b1, !when, target),
+            Bexp::BAnd(b1, b2) => {
+                if when {
+                    let skip = self.label();
+                    self.branch(b1, false, skip);
+                    self.branch(b2, true, target);
+                    self.code.push(RInstr::RLabel(skip));
+                } else {
+                    self.branch(b1, false, target);
+                    self.branch(b2, false, target);
+                }
+            }
+        }
+    }
+
+    fn com(&mut self, c: &Com, exits: Exits) {
+        match c {
+            Com::CSkip => {}
+            Com::CBreak => self.code.push(RInstr::RJump(exits.brk)),
+            Com::CContinue => self.code.push(RInstr::RJump(exits.cont)),
+            Com::CAsgn(x, a) => {
+                self.aexp(a, 0);
+                self.code.push(RInstr::RStore(x.clone(), 0));
+            }
+            Com::CHavoc(x) => {
+                self.code.push(RInstr::RConst(0, 0));
+                self.code.push(RInstr::RStore(x.clone(), 0));
+            }
+            Com::CPrint(a) => {
+                self.aexp(a, 0);
+                self.code.push(RInstr::RPrint(0));
+            }
+            Com::CThrow(a) => {
+                self.aexp(a, 0);
+                match exits.catch {
+                    Some(handler) => self.code.push(RInstr::RJump(handler)),
+                    None => self.code.push(RInstr::RThrow(0)),
+                }
+            }
+            Com::CSeq(c1, c2) => {
+                self.com(c1, exits);
+                self.com(c2, exits);
+            }
+            Com::CIf(b, c1, c2) => {
+                let (other, end) = (self.label(), self.label());
+                self.branch(b, false, other);
+                self.com(c1, exits);
+                self.code.push(RInstr::RJump(end));
+                self.code.push(RInstr::RLabel(other));
+                self.com(c2, exits);
+                self.code.push(RInstr::RLabel(end));
+            }
+            Com::CWhile(b, body) => self.while_loop(b, body, None, exits.catch),
+            Com::CFor(init, b, update, body) => {
+                self.com(init, exits);
+                self.while_loop(b, body, Some(update), exits.catch);
+            }
+            // A branch's `break` or `continue` only ends the branch.
+            Com::CPar(c1, c2) => {
+                for c in [c1, c2] {
+                    let end = self.label();
+                    self.com(
+                        c,
+                        Exits {
+                            brk: end,
+                            cont: end,
+                            ..exits
+                        },
+                    );
+                    self.code.push(RInstr::RLabel(end));
+                }
+            }
+            Com::CTry(body, x, handler) => {
+                let (caught, end) = (self.label(), self.label());
+                self.com(
+                    body,
+                    Exits {
+                        catch: Some(caught),
+                        ..exits
+                    },
+                );
+                self.code.push(RInstr::RJump(end));
+                self.code.push(RInstr::RLabel(caught));
+                self.code.push(RInstr::RStore(x.clone(), 0));
+                self.com(handler, exits);
+                self.code.push(RInstr::RLabel(end));
+            }
+            Com::CCall(..) => {
+                self.unsupported
+                    .get_or_insert(Unsupported("procedure calls"));
+            }
+            Com::CArrAsgn(..) => {
+                self.unsupported.get_or_insert(Unsupported("arrays"));
+            }
+        }
+    }
+
+    /// A loop testing `b` and running `body`, then `update` if it is a
+    /// `for` loop's, where `continue` goes to the update. A `throw` goes
+    /// to `catch`, as outside the loop.
+    fn while_loop(&mut self, b: &Bexp, body: &Com, update: Option<&Com>, catch: Option<Label>) {
+        let (head, end) = (self.label(), self.label());
+        self.code.push(RInstr::RLabel(head));
+        self.loop_from(head, end, b, body, update, catch);
+    }
+
+    /// The rest of a loop whose test starts at `head`, already placed, and
+    /// which ends at `end`, not yet placed.
+    fn loop_from(
+        &mut self,
+        head: Label,
+        end: Label,
+        b: &Bexp,
+        body: &Com,
+        update: Option<&Com>,
+        catch: Option<Label>,
+    ) {
+        self.branch(b, false, end);
+        match update {
+            Some(update) => {
+                let next = self.label();
+                self.com(
+                    body,
+                    Exits {
+                        brk: end,
+                        cont: next,
+                        catch,
+                    },
+                );
+                self.code.push(RInstr::RLabel(next));
+                self.com(
+                    update,
+                    Exits {
+                        brk: end,
+                        cont: head,
+                        catch,
+                    },
+                );
+            }
+            None => self.com(
+                body,
+                Exits {
+                    brk: end,
+                    cont: head,
+                    catch,
+                },
+            ),
+        }
+        self.code.push(RInstr::RJump(head));
+        self.code.push(RInstr::RLabel(end));
+    }
+}
+
+#[cfg(test)]
+mod test_imp_register {
+    use super::*;
+    use crate::imp::{ceval_output, parse_com, random_aexp, random_state};
+    use crate::rng::Rng;
+    use crate::state;
+    use crate::state::lookup;
+
+    const PROGRAMS: [&str; 8] = [
+        "Z := X; Y := 1; while ~(Z = 0) do Y := Y * Z; Z := Z - 1 end",
+        "Y := 0; while Y <= X - 1 do Y := Y + 1; Z := Z + Y; print Z end",
+        "if X <= 2 && ~(X = 1) then Y := 1 else Y := 2; Z := 3 end",
+        "while true do X := X + 1; if 5 <= X then break else continue end end",
+        "for I := 0; I <= X; I := I + 1 do if I = 2 then continue else Y := Y + I end end",
+        "for I := 0; I <= X; I := I + 1 do \
+         for J := 0; true; J := J + 1 do if I <= J then break else print J end end end",
+        "X := 1; break; X := 2",
+        "par Y := X; break; Y := 0 with havoc X; while X <= 3 do X := X + 2 end end",
+    ];
+
+    fn agrees(c: &Com, st: State) {
+        let (expected, printed) = ceval_output(st.clone(), c, 10_000).unwrap();
+        let expected = expected.finished().unwrap();
+        let found = r_execute(st, &r_compile(c).unwrap(), 100_000).unwrap();
+        for x in c.vars() {
+            assert_eq!(lookup(&found.state, &x), expected(&x), "{} in {}", x, c);
+        }
+        assert_eq!(found.output, printed, "{}", c);
+    }
+
+    #[test]
+    fn test_agrees_with_ceval() {
+        for p in PROGRAMS {
+            let c = parse_com(p).unwrap();
+            for x in 0..6 {
+                agrees(&c, state! {"X" => x});
+            }
+        }
+    }
+
+    #[test]
+    fn test_random_expressions() {
+        let mut rng = Rng::new(3);
+        let vars = ["X", "Y", "Z"];
+        for _ in 0..300 {
+            let (a1, a2) = (
+                random_aexp(&mut rng, 6, &vars),
+                random_aexp(&mut rng, 6, &vars),
+            );
+            let c = Com::if_(
+                Bexp::BAnd(
+                    Box::new(Bexp::BLe(Box::new(a1.clone()), Box::new(a2.clone()))),
+                    Box::new(Bexp::BNot(Box::new(Bexp::BEq(
+                        Box::new(a1.clone()),
+                        Box::new(Aexp::var("Z")),
+                    )))),
+                ),
+                Com::asgn("X", a1),
+                Com::seq(Com::asgn("Y", a2.clone()), Com::print(a2)),
+            );
+            agrees(&c, random_state(&mut rng, &vars));
+        }
+    }
+
+    #[test]
+    fn test_listing() {
+        let c = parse_com("while 1 <= X do X := X - 1 end").unwrap();
+        let listing: Vec<String> = r_compile(&c)
+            .unwrap()
+            .iter()
+            .map(|i| i.to_string())
+            .collect();
+        assert_eq!(
+            listing,
+            [
+                "L1:",
+                "  r0 := 1",
+                "  r1 := X",
+                "  if r0 > r1 goto L2",
+                "  r0 := X",
+                "  r1 := 1",
+                "  r0 := r0 - r1",
+                "  X := r0",
+                "  goto L1",
+                "L2:",
+                "L0:",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_out_of_steps() {
+        let c = parse_com("while true do skip end").unwrap();
+        assert!(r_execute(state! {}, &r_compile(&c).unwrap(), 1_000).is_none());
+        // Exactly enough steps is enough.
+        let prog = r_compile(&parse_com("X := 1").unwrap()).unwrap();
+        assert_eq!(prog.len(), 3);
+        assert!(r_execute(state! {}, &prog, 2).is_none());
+        assert!(r_execute(state! {}, &prog, 3).is_some());
+    }
+}
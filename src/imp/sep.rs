@@ -0,0 +1,654 @@
+//! Separation logic over a heap. Imp's heap commands are Reynolds's: `X :=
+//! [a]` loads from location `a`, `[a] := b` stores to it, `X := alloc(a1,
+//! ..., an)` allocates `n` consecutive cells holding the `ai`, and `free a`
+//! deallocates one. Touching a location that isn't allocated is a fault.
+//!
+//! An assertion describes a state and a heap fragment: `emp` the empty
+//! heap, `a |-> b` the heap of the one cell `a` holding `b`, and `P * Q` a
+//! heap that splits into disjoint parts satisfying `P` and `Q`. A triple
+//! `{{P}} c {{Q}}` says that `c` run on a heap satisfying `P` doesn't fault
+//! and ends in one satisfying `Q`, and the frame rule that it then also
+//! takes `P * R` to `Q * R` for any `R` about variables `c` doesn't set,
+//! since the cells `R` describes are ones `c` never touches.
+
+use std::collections::BTreeSet;
+use std::fmt;
+
+use super::{aeval, beval, Aexp, Bexp, Formula, TripleCheck};
+use crate::heap::{Heap, Loc};
+use crate::map::tm_update;
+use crate::state::State;
+
+/// Imp with a heap; the commands other than the heap ones are Imp's.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HCom {
+    HSkip,
+    HAsgn(String, Aexp),
+    HSeq(Box<HCom>, Box<HCom>),
+    HIf(Bexp, Box<HCom>, Box<HCom>),
+    HWhile(Bexp, Box<HCom>),
+    /// `X := [a]`.
+    HLoad(String, Aexp),
+    /// `[a] := b`.
+    HStore(Aexp, Aexp),
+    /// `X := alloc(a1, ..., an)`, for at least one `ai`.
+    HAlloc(String, Vec<Aexp>),
+    /// `free a`.
+    HFree(Aexp),
+}
+
+impl HCom {
+    pub fn asgn(x: &str, a: Aexp) -> HCom {
+        HCom::HAsgn(x.to_string(), a)
+    }
+
+    pub fn seq(c1: HCom, c2: HCom) -> HCom {
+        HCom::HSeq(Box::new(c1), Box::new(c2))
+    }
+
+    pub fn if_(b: Bexp, c1: HCom, c2: HCom) -> HCom {
+        HCom::HIf(b, Box::new(c1), Box::new(c2))
+    }
+
+    pub fn while_(b: Bexp, c: HCom) -> HCom {
+        HCom::HWhile(b, Box::new(c))
+    }
+
+    pub fn load(x: &str, a: Aexp) -> HCom {
+        HCom::HLoad(x.to_string(), a)
+    }
+
+    pub fn store(a: Aexp, b: Aexp) -> HCom {
+        HCom::HStore(a, b)
+    }
+
+    pub fn alloc(x: &str, values: Vec<Aexp>) -> HCom {
+        assert!(!values.is_empty(), "alloc needs at least one value");
+        HCom::HAlloc(x.to_string(), values)
+    }
+
+    pub fn free(a: Aexp) -> HCom {
+        HCom::HFree(a)
+    }
+
+    /// The variables the command mentions.
+    pub fn vars(&self) -> BTreeSet<String> {
+        let mut vars = BTreeSet::new();
+        self.collect_vars(&mut vars, false);
+        vars
+    }
+
+    /// The variables the command can set, which a frame mustn't mention.
+    pub fn modified(&self) -> BTreeSet<String> {
+        let mut vars = BTreeSet::new();
+        self.collect_vars(&mut vars, true);
+        vars
+    }
+
+    fn collect_vars(&self, vars: &mut BTreeSet<String>, only_set: bool) {
+        let read = |a: &Aexp, vars: &mut BTreeSet<String>| {
+            if !only_set {
+                vars.extend(a.vars());
+            }
+        };
+        match self {
+            HCom::HSkip => {}
+            HCom::HAsgn(x, a) | HCom::HLoad(x, a) => {
+                vars.insert(x.clone());
+                read(a, vars);
+            }
+            HCom::HAlloc(x, values) => {
+                vars.insert(x.clone());
+                values.iter().for_each(|a| read(a, vars));
+            }
+            HCom::HStore(a, b) => {
+                read(a, vars);
+                read(b, vars);
+            }
+            HCom::HFree(a) => read(a, vars),
+            HCom::HSeq(c1, c2) => {
+                c1.collect_vars(vars, only_set);
+                c2.collect_vars(vars, only_set);
+            }
+            HCom::HIf(b, c1, c2) => {
+                if !only_set {
+                    vars.extend(b.vars());
+                }
+                c1.collect_vars(vars, only_set);
+                c2.collect_vars(vars, only_set);
+            }
+            HCom::HWhile(b, c) => {
+                if !only_set {
+                    vars.extend(b.vars());
+                }
+                c.collect_vars(vars, only_set);
+            }
+        }
+    }
+}
+
+impl fmt::Display for HCom {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HCom::HSkip => write!(f, "skip"),
+            HCom::HAsgn(x, a) => write!(f, "{} := {}", x, a),
+            HCom::HSeq(c1, c2) => write!(f, "{}; {}", c1, c2),
+            HCom::HIf(b, c1, c2) => write!(f, "if {} then {} else {} end", b, c1, c2),
+            HCom::HWhile(b, c) => write!(f, "while {} do {} end", b, c),
+            HCom::HLoad(x, a) => write!(f, "{} := [{}]", x, a),
+            HCom::HStore(a, b) => write!(f, "[{}] := {}", a, b),
+            HCom::HAlloc(x, values) => {
+                let values: Vec<String> = values.iter().map(Aexp::to_string).collect();
+                write!(f, "{} := alloc({})", x, values.join(", "))
+            }
+            HCom::HFree(a) => write!(f, "free {}", a),
+        }
+    }
+}
+
+/// Why a heap program didn't finish.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HeapError {
+    /// The fuel ran out, so the program may diverge.
+    OutOfFuel,
+    /// The program touched a location that isn't allocated.
+    Fault(i64),
+}
+
+impl fmt::Display for HeapError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HeapError::OutOfFuel => write!(f, "out of fuel"),
+            HeapError::Fault(l) => write!(f, "memory fault at location {}", l),
+        }
+    }
+}
+
+/// Run `c` from `st` and `heap` with at most `fuel` loop iterations.
+pub fn hceval(
+    st: State,
+    heap: Heap<i64>,
+    c: &HCom,
+    fuel: u64,
+) -> Result<(State, Heap<i64>), HeapError> {
+    let mut fuel = fuel;
+    run(st, heap, c, &mut fuel)
+}
+
+fn run(
+    st: State,
+    heap: Heap<i64>,
+    c: &HCom,
+    fuel: &mut u64,
+) -> Result<(State, Heap<i64>), HeapError> {
+    let loc = |st: &State, a: &Aexp| {
+        let n = aeval(st, a);
+        Loc::try_from(n).map_err(|_| HeapError::Fault(n))
+    };
+    match c {
+        HCom::HSkip => Ok((st, heap)),
+        HCom::HAsgn(x, a) => {
+            let n = aeval(&st, a);
+            Ok((tm_update(st, x.clone(), n), heap))
+        }
+        HCom::HSeq(c1, c2) => {
+            let (st, heap) = run(st, heap, c1, fuel)?;
+            run(st, heap, c2, fuel)
+        }
+        HCom::HIf(b, c1, c2) => {
+            if beval(&st, b) {
+                run(st, heap, c1, fuel)
+            } else {
+                run(st, heap, c2, fuel)
+            }
+        }
+        HCom::HWhile(b, body) => {
+            let (mut st, mut heap) = (st, heap);
+            while beval(&st, b) {
+                if *fuel == 0 {
+                    return Err(HeapError::OutOfFuel);
+                }
+                *fuel -= 1;
+                (st, heap) = run(st, heap, body, fuel)?;
+            }
+            Ok((st, heap))
+        }
+        HCom::HLoad(x, a) => {
+            let l = loc(&st, a)?;
+            let n = heap.lookup(l).ok_or(HeapError::Fault(l as i64))?;
+            Ok((tm_update(st, x.clone(), n), heap))
+        }
+        HCom::HStore(a, b) => {
+            let l = loc(&st, a)?;
+            let n = aeval(&st, b);
+            let heap = heap.assign(l, n).ok_or(HeapError::Fault(l as i64))?;
+            Ok((st, heap))
+        }
+        HCom::HAlloc(x, values) => {
+            let (mut heap, mut first) = (heap, None);
+            for a in values {
+                let l;
+                (heap, l) = heap.alloc(aeval(&st, a));
+                first.get_or_insert(l);
+            }
+            let first = first.expect("alloc needs at least one value");
+            Ok((tm_update(st, x.clone(), first as i64), heap))
+        }
+        HCom::HFree(a) => {
+            let l = loc(&st, a)?;
+            let heap = heap.free(l).ok_or(HeapError::Fault(l as i64))?;
+            Ok((st, heap))
+        }
+    }
+}
+
+/// An assertion about a state and a heap fragment.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HeapAssertion {
+    /// The heap is empty.
+    Emp,
+    /// `a |-> b`: the heap is the one cell `a`, holding `b`.
+    PointsTo(Aexp, Aexp),
+    /// `P * Q`: the heap splits into disjoint parts satisfying `P` and `Q`.
+    Star(Box<HeapAssertion>, Box<HeapAssertion>),
+    /// `pure(F)`: the heap is empty and the state satisfies `F`.
+    Pure(Formula),
+    /// Any heap at all, which `P * true` uses to allow cells beyond `P`'s.
+    Any,
+}
+
+impl HeapAssertion {
+    pub fn points_to(a: Aexp, b: Aexp) -> HeapAssertion {
+        HeapAssertion::PointsTo(a, b)
+    }
+
+    pub fn star(p: HeapAssertion, q: HeapAssertion) -> HeapAssertion {
+        HeapAssertion::Star(Box::new(p), Box::new(q))
+    }
+
+    /// Whether `st` and `heap` satisfy the assertion. A `*` tries every way
+    /// of splitting the heap, so this takes time exponential in its size.
+    pub fn holds(&self, st: &State, heap: &Heap<i64>) -> bool {
+        match self {
+            HeapAssertion::Emp => heap.is_empty(),
+            HeapAssertion::PointsTo(a, b) => {
+                let l = aeval(st, a);
+                heap.len() == 1
+                    && Loc::try_from(l).is_ok_and(|l| heap.lookup(l) == Some(aeval(st, b)))
+            }
+            HeapAssertion::Star(p, q) => splits(heap)
+                .into_iter()
+                .any(|(h1, h2)| p.holds(st, &h1) && q.holds(st, &h2)),
+            HeapAssertion::Pure(f) => heap.is_empty() && f.holds(st),
+            HeapAssertion::Any => true,
+        }
+    }
+
+    /// The variables the assertion mentions.
+    pub fn vars(&self) -> BTreeSet<String> {
+        match self {
+            HeapAssertion::Emp | HeapAssertion::Any => BTreeSet::new(),
+            HeapAssertion::PointsTo(a, b) => {
+                let mut vars = a.vars();
+                vars.extend(b.vars());
+                vars
+            }
+            HeapAssertion::Star(p, q) => {
+                let mut vars = p.vars();
+                vars.extend(q.vars());
+                vars
+            }
+            HeapAssertion::Pure(f) => f.vars(),
+        }
+    }
+}
+
+/// Every way of splitting `heap` into two disjoint parts.
+fn splits(heap: &Heap<i64>) -> Vec<(Heap<i64>, Heap<i64>)> {
+    let cells: Vec<(Loc, i64)> = cells(heap);
+    let part = |mask: usize, side: bool| {
+        cells
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| (mask >> i & 1 == 1) == side)
+            .fold(Heap::new(), |h, (_, (l, n))| {
+                h.disjoint_union(Heap::singleton(*l, *n))
+                    .expect("the cells are distinct")
+            })
+    };
+    (0..1usize << cells.len())
+        .map(|mask| (part(mask, true), part(mask, false)))
+        .collect()
+}
+
+fn cells(heap: &Heap<i64>) -> Vec<(Loc, i64)> {
+    heap.domain()
+        .into_iter()
+        .map(|l| (l, heap.lookup(l).expect("in the domain")))
+        .collect()
+}
+
+impl fmt::Display for HeapAssertion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HeapAssertion::Emp => write!(f, "emp"),
+            // Parenthesized, so that `*` can't be read as multiplication.
+            HeapAssertion::PointsTo(a, b) => {
+                let atom = |a: &Aexp| match a {
+                    Aexp::ANum(_) | Aexp::AId(_) => a.to_string(),
+                    _ => format!("({})", a),
+                };
+                write!(f, "{} |-> {}", atom(a), atom(b))
+            }
+            HeapAssertion::Star(p, q) => write!(f, "{} * {}", p, q),
+            HeapAssertion::Pure(p) => write!(f, "pure({})", p),
+            HeapAssertion::Any => write!(f, "true"),
+        }
+    }
+}
+
+/// A separation logic triple `{{pre}} com {{post}}`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HeapTriple {
+    pub pre: HeapAssertion,
+    pub com: HCom,
+    pub post: HeapAssertion,
+}
+
+/// A run refuting a heap triple, from the `case`th sample, counting from
+/// `0`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HeapViolation {
+    /// The program touched a location that isn't allocated.
+    Fault { case: usize, loc: i64 },
+    /// The program ended in this state, on the variables the triple
+    /// mentions, and heap, which break the postcondition.
+    Post {
+        case: usize,
+        state: Vec<(String, i64)>,
+        heap: Vec<(Loc, i64)>,
+    },
+}
+
+impl fmt::Display for HeapViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HeapViolation::Fault { case, loc } => {
+                write!(f, "case {}: memory fault at location {}", case, loc)
+            }
+            HeapViolation::Post { case, state, heap } => {
+                let state: Vec<String> =
+                    state.iter().map(|(x, n)| format!("{}={}", x, n)).collect();
+                let heap: Vec<String> = heap
+                    .iter()
+                    .map(|(l, n)| format!("{} |-> {}", l, n))
+                    .collect();
+                write!(
+                    f,
+                    "case {}: the program ends in {} with heap {{{}}}, breaking the \
+                     postcondition",
+                    case,
+                    state.join(", "),
+                    heap.join(", ")
+                )
+            }
+        }
+    }
+}
+
+/// Why `check_frame` rejected a frame.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FrameViolation {
+    /// The frame mentions a variable the program can set, so the frame rule
+    /// doesn't apply.
+    Modifies(String),
+    /// The triple itself fails on the samples.
+    Triple(HeapViolation),
+    /// The triple holds on the samples, but not with the frame added.
+    Framed(HeapViolation),
+}
+
+impl fmt::Display for FrameViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FrameViolation::Modifies(x) => {
+                write!(f, "the frame mentions {}, which the program sets", x)
+            }
+            FrameViolation::Triple(v) => write!(f, "the triple fails: {}", v),
+            FrameViolation::Framed(v) => write!(f, "the framed triple fails: {}", v),
+        }
+    }
+}
+
+impl HeapTriple {
+    pub fn new(pre: HeapAssertion, com: HCom, post: HeapAssertion) -> HeapTriple {
+        HeapTriple { pre, com, post }
+    }
+
+    /// Test the triple on each of `samples` satisfying the precondition, as
+    /// `HoareTriple::check_on` does, with at most `fuel` loop iterations.
+    /// A fault refutes the triple.
+    pub fn check_on(
+        &self,
+        samples: &[(State, Heap<i64>)],
+        fuel: u64,
+    ) -> Result<TripleCheck, HeapViolation> {
+        let mut check = TripleCheck {
+            tested: 0,
+            skipped: 0,
+            diverged: 0,
+        };
+        for (case, (st, heap)) in samples.iter().enumerate() {
+            if !self.pre.holds(st, heap) {
+                check.skipped += 1;
+                continue;
+            }
+            match hceval(st.clone(), heap.clone(), &self.com, fuel) {
+                Err(HeapError::OutOfFuel) => check.diverged += 1,
+                Err(HeapError::Fault(loc)) => return Err(HeapViolation::Fault { case, loc }),
+                Ok((st, heap)) if !self.post.holds(&st, &heap) => {
+                    return Err(HeapViolation::Post {
+                        case,
+                        state: self.values(&st),
+                        heap: cells(&heap),
+                    })
+                }
+                Ok(_) => check.tested += 1,
+            }
+        }
+        Ok(check)
+    }
+
+    /// The triple with `frame` starred onto both sides.
+    pub fn framed(&self, frame: &HeapAssertion) -> HeapTriple {
+        HeapTriple::new(
+            HeapAssertion::star(self.pre.clone(), frame.clone()),
+            self.com.clone(),
+            HeapAssertion::star(self.post.clone(), frame.clone()),
+        )
+    }
+
+    /// Test the frame rule for `frame` on `samples`: that the triple holds,
+    /// and then that so does `{{pre * frame}} com {{post * frame}}`. The
+    /// samples need heaps with cells beyond the precondition's for the
+    /// second part to test anything. Returns what testing the framed
+    /// triple found.
+    pub fn check_frame(
+        &self,
+        frame: &HeapAssertion,
+        samples: &[(State, Heap<i64>)],
+        fuel: u64,
+    ) -> Result<TripleCheck, FrameViolation> {
+        let modified = self.com.modified();
+        if let Some(x) = frame.vars().into_iter().find(|x| modified.contains(x)) {
+            return Err(FrameViolation::Modifies(x));
+        }
+        self.check_on(samples, fuel)
+            .map_err(FrameViolation::Triple)?;
+        self.framed(frame)
+            .check_on(samples, fuel)
+            .map_err(FrameViolation::Framed)
+    }
+
+    fn values(&self, st: &State) -> Vec<(String, i64)> {
+        let mut vars = self.com.vars();
+        vars.extend(self.pre.vars());
+        vars.extend(self.post.vars());
+        vars.into_iter().map(|x| (x.clone(), st(&x))).collect()
+    }
+}
+
+impl fmt::Display for HeapTriple {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{{{{{}}}}} {} {{{{{}}}}}", self.pre, self.com, self.post)
+    }
+}
+
+#[cfg(test)]
+mod test_imp_sep {
+    use super::*;
+    use crate::imp::parse_bexp;
+    use crate::rng::Rng;
+    use crate::state;
+    use crate::state::lookup;
+
+    fn var(x: &str) -> Aexp {
+        Aexp::var(x)
+    }
+
+    fn num(n: i64) -> Aexp {
+        Aexp::num(n)
+    }
+
+    fn heap(cells: &[(Loc, i64)]) -> Heap<i64> {
+        cells.iter().fold(Heap::new(), |h, (l, n)| {
+            h.disjoint_union(Heap::singleton(*l, *n)).unwrap()
+        })
+    }
+
+    /// States with `X` and `Y` below `4`, and heaps that often have `X`
+    /// holding `V` and `Y` holding `3`, and sometimes a cell beyond those.
+    fn samples(n: usize) -> Vec<(State, Heap<i64>)> {
+        let mut rng = Rng::new(11);
+        (0..n)
+            .map(|_| {
+                let (x, y, v) = (rng.below(4), rng.below(4), rng.range(0, 3));
+                let mut h = Heap::new();
+                if rng.chance(2, 3) {
+                    h = heap(&[(x as Loc, v)]);
+                }
+                for (l, n) in [(y as Loc, 3), (4 + rng.below(2) as Loc, 7)] {
+                    if rng.chance(1, 2) && !h.contains(l) {
+                        h = h.disjoint_union(Heap::singleton(l, n)).unwrap();
+                    }
+                }
+                let st = state! {"X" => x as i64, "Y" => y as i64, "V" => v};
+                (st, h)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_heap_commands() {
+        // X := alloc(1, 2); Y := [X + 1]; [X] := Y + 10; free X + 1
+        let c = HCom::seq(
+            HCom::alloc("X", vec![num(1), num(2)]),
+            HCom::seq(
+                HCom::load("Y", Aexp::plus(var("X"), num(1))),
+                HCom::seq(
+                    HCom::store(var("X"), Aexp::plus(var("Y"), num(10))),
+                    HCom::free(Aexp::plus(var("X"), num(1))),
+                ),
+            ),
+        );
+        assert_eq!(
+            c.to_string(),
+            "X := alloc(1, 2); Y := [X + 1]; [X] := Y + 10; free X + 1"
+        );
+        let (st, h) = hceval(state! {}, heap(&[(0, 7)]), &c, 10).unwrap();
+        assert_eq!((lookup(&st, "X"), lookup(&st, "Y")), (1, 2));
+        assert_eq!(h, heap(&[(0, 7), (1, 12)]));
+        // Using the freed cell faults.
+        let again = HCom::seq(c.clone(), HCom::load("Z", num(2)));
+        let err = hceval(state! {}, heap(&[(0, 7)]), &again, 10);
+        assert_eq!(err.err(), Some(HeapError::Fault(2)));
+        let neg = hceval(state! {}, Heap::new(), &HCom::free(num(-1)), 10);
+        assert_eq!(
+            neg.err().unwrap().to_string(),
+            "memory fault at location -1"
+        );
+        let spin = HCom::while_(Bexp::BTrue, HCom::HSkip);
+        assert_eq!(
+            hceval(state! {}, Heap::new(), &spin, 5).err(),
+            Some(HeapError::OutOfFuel)
+        );
+    }
+
+    #[test]
+    fn test_assertions() {
+        let st = state! {"X" => 2, "Y" => 5};
+        let xy = HeapAssertion::star(
+            HeapAssertion::points_to(var("X"), num(1)),
+            HeapAssertion::points_to(var("Y"), num(0)),
+        );
+        assert_eq!(xy.to_string(), "X |-> 1 * Y |-> 0");
+        assert!(xy.holds(&st, &heap(&[(2, 1), (5, 0)])));
+        // Separating: one cell can't be both halves.
+        let xx = HeapAssertion::star(
+            HeapAssertion::points_to(var("X"), num(1)),
+            HeapAssertion::points_to(var("X"), num(1)),
+        );
+        assert!(!xx.holds(&st, &heap(&[(2, 1)])));
+        // Points-to is exact, unless starred with `true`.
+        let x = HeapAssertion::points_to(var("X"), num(1));
+        assert!(!x.holds(&st, &heap(&[(2, 1), (5, 0)])));
+        assert!(HeapAssertion::star(x, HeapAssertion::Any).holds(&st, &heap(&[(2, 1), (5, 0)])));
+        assert!(HeapAssertion::Emp.holds(&st, &Heap::new()));
+        let pure = HeapAssertion::Pure(Formula::from(parse_bexp("X <= Y").unwrap()));
+        assert!(pure.holds(&st, &Heap::new()));
+        assert!(!pure.holds(&st, &heap(&[(0, 0)])));
+    }
+
+    #[test]
+    fn test_frame_rule() {
+        // {{X |-> V}} [X] := V + 1 {{X |-> V + 1}}, framed by Y |-> 3.
+        let triple = HeapTriple::new(
+            HeapAssertion::points_to(var("X"), var("V")),
+            HCom::store(var("X"), Aexp::plus(var("V"), num(1))),
+            HeapAssertion::points_to(var("X"), Aexp::plus(var("V"), num(1))),
+        );
+        let frame = HeapAssertion::points_to(var("Y"), num(3));
+        let samples = samples(400);
+        let check = triple.check_frame(&frame, &samples, 10).unwrap();
+        assert!(check.tested > 0, "{:?}", check);
+        assert_eq!(
+            triple.framed(&frame).to_string(),
+            "{{X |-> V * Y |-> 3}} [X] := V + 1 {{X |-> (V + 1) * Y |-> 3}}"
+        );
+
+        // The frame can't mention what the program sets.
+        let load = HeapTriple::new(
+            HeapAssertion::points_to(var("X"), num(1)),
+            HCom::load("Y", var("X")),
+            HeapAssertion::points_to(var("X"), num(1)),
+        );
+        assert_eq!(
+            load.check_frame(&frame, &samples, 10),
+            Err(FrameViolation::Modifies("Y".to_string()))
+        );
+
+        // Storing to a cell the precondition doesn't own faults on the
+        // empty heap, so the triple fails before the frame is tried.
+        let wild = HeapTriple::new(
+            HeapAssertion::Emp,
+            HCom::store(var("X"), num(0)),
+            HeapAssertion::Emp,
+        );
+        let err = wild.check_frame(&frame, &samples, 10).unwrap_err();
+        assert!(matches!(
+            err,
+            FrameViolation::Triple(HeapViolation::Fault { .. })
+        ));
+        assert!(err.to_string().starts_with("the triple fails: case "));
+    }
+}
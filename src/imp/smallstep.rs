@@ -0,0 +1,520 @@
+//! Small-step semantics for Imp, following the Smallstep chapter.
+//! Expressions reduce one operator at a time, left operand first, and a
+//! command steps by reducing its leftmost redex. Values are `ANum`s,
+//! `BTrue`/`BFalse`, and `CSkip`; `CBreak` and `CContinue` don't step
+//! either, but bubble up through sequences until a loop catches them, and
+//! neither does `throw n`, which bubbles up through loops too, until a
+//! `try` does.
+//!
+//! Steps rewrite a `Config`, which is a command but for loops partway
+//! through an iteration: a loop unrolls into one, which runs an iteration
+//! and then becomes the loop again, and is where a `break` or `continue`
+//! in the body stops.
+//!
+//! `step` is deterministic, so it steps the left branch of a `par` until
+//! that finishes; `interleavings` explores the other orders.
+
+use std::fmt;
+
+use super::desugar::lower_for;
+use super::{Aexp, Bexp, Com, Unsupported};
+use crate::map::tm_update;
+use crate::state::State;
+
+/// One step of `a`, or `None` if `a` is already a number.
+pub fn astep(st: &State, a: &Aexp) -> Option<Aexp> {
+    match a {
+        Aexp::ANum(_) => None,
+        Aexp::AId(x) => Some(Aexp::ANum(st(x))),
+        Aexp::APlus(a1, a2) | Aexp::AMinus(a1, a2) | Aexp::AMult(a1, a2) => {
+            let rebuild = |a1, a2| match a {
+                Aexp::APlus(..) => Aexp::plus(a1, a2),
+                Aexp::AMinus(..) => Aexp::minus(a1, a2),
+                _ => Aexp::mult(a1, a2),
+            };
+            match (&**a1, &**a2) {
+                (Aexp::ANum(n1), Aexp::ANum(n2)) => Some(Aexp::ANum(match a {
+                    Aexp::APlus(..) => n1.wrapping_add(*n2),
+                    Aexp::AMinus(..) => n1.wrapping_sub(*n2),
+                    _ => n1.wrapping_mul(*n2),
+                })),
+                (Aexp::ANum(_), _) => Some(rebuild((**a1).clone(), astep(st, a2)?)),
+                _ => Some(rebuild(astep(st, a1)?, (**a2).clone())),
+            }
+        }
+        // As with `aeval`, there are no arrays here, so an element is `0`.
+        Aexp::AIndex(x, i) => match &**i {
+            Aexp::ANum(_) => Some(Aexp::ANum(0)),
+            _ => Some(Aexp::index(x, astep(st, i)?)),
+        },
+    }
+}
+
+/// One step of `b`, or `None` if `b` is already `true` or `false`.
+pub fn bstep(st: &State, b: &Bexp) -> Option<Bexp> {
+    let of_bool = |v| if v { Bexp::BTrue } else { Bexp::BFalse };
+    match b {
+        Bexp::BTrue | Bexp::BFalse => None,
+        Bexp::BEq(a1, a2) | Bexp::BLe(a1, a2) => {
+            let rebuild = |a1, a2| match b {
+                Bexp::BEq(..) => Bexp::eq(a1, a2),
+                _ => Bexp::le(a1, a2),
+            };
+            match (&**a1, &**a2) {
+                (Aexp::ANum(n1), Aexp::ANum(n2)) => Some(of_bool(match b {
+                    Bexp::BEq(..) => n1 == n2,
+                    _ => n1 <= n2,
+                })),
+                (Aexp::ANum(_), _) => Some(rebuild((**a1).clone(), astep(st, a2)?)),
+                _ => Some(rebuild(astep(st, a1)?, (**a2).clone())),
+            }
+        }
+        Bexp::BNot(b1) => match &**b1 {
+            Bexp::BTrue => Some(Bexp::BFalse),
+            Bexp::BFalse => Some(Bexp::BTrue),
+            _ => Some(Bexp::not(bstep(st, b1)?)),
+        },
+        // `false && b2` stops without looking at `b2`; `true && b2` is `b2`.
+        Bexp::BAnd(b1, b2) => match &**b1 {
+            Bexp::BTrue => Some((**b2).clone()),
+            Bexp::BFalse => Some(Bexp::BFalse),
+            _ => Some(Bexp::and(bstep(st, b1)?, (**b2).clone())),
+        },
+    }
+}
+
+/// What is left to run of a command partway through: a `Com`, except that
+/// a loop may be in the middle of an iteration. Such a loop has no syntax
+/// of its own, since a `break` or `continue` in the rest of the iteration
+/// has to land on it, so only `step` and an evaluator that runs out of gas
+/// build one. `Config::from` starts a command, and a configuration prints
+/// as the command it amounts to, the rest of each iteration followed by
+/// its loop.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Config(pub(super) Running);
+
+/// The parts of a `Config`. The constructors below turn anything a `Com`
+/// can say back into a `Running::Com`, so two configurations for the same
+/// command are equal.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(super) enum Running {
+    Com(Com),
+    /// `c1; c2` with `c1` under way.
+    Seq(Box<Running>, Com),
+    /// `if b then c1 else c2`, what a `while` unrolls to, with the first
+    /// iteration in `c1`.
+    If(Bexp, Box<Running>, Com),
+    /// `Loop(rest, b, body)` is the loop `while b do body end` with `rest`
+    /// of an iteration still to run.
+    Loop(Box<Running>, Bexp, Com),
+    Par(Box<Running>, Box<Running>),
+    Try(Box<Running>, String, Com),
+}
+
+impl From<Com> for Config {
+    fn from(c: Com) -> Self {
+        Config(Running::Com(c))
+    }
+}
+
+impl fmt::Display for Config {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&self.0.to_com(), f)
+    }
+}
+
+impl Running {
+    pub(super) fn seq(r1: Running, c2: Com) -> Running {
+        match r1 {
+            Running::Com(c1) => Running::Com(Com::seq(c1, c2)),
+            r1 => Running::Seq(Box::new(r1), c2),
+        }
+    }
+
+    fn if_(b: Bexp, r1: Running, c2: Com) -> Running {
+        match r1 {
+            Running::Com(c1) => Running::Com(Com::if_(b, c1, c2)),
+            r1 => Running::If(b, Box::new(r1), c2),
+        }
+    }
+
+    pub(super) fn loop_(rest: Running, b: Bexp, body: Com) -> Running {
+        Running::Loop(Box::new(rest), b, body)
+    }
+
+    pub(super) fn par(r1: Running, r2: Running) -> Running {
+        match (r1, r2) {
+            (Running::Com(c1), Running::Com(c2)) => Running::Com(Com::par(c1, c2)),
+            (r1, r2) => Running::Par(Box::new(r1), Box::new(r2)),
+        }
+    }
+
+    pub(super) fn try_(body: Running, x: &str, handler: Com) -> Running {
+        match body {
+            Running::Com(body) => Running::Com(Com::try_(body, x, handler)),
+            body => Running::Try(Box::new(body), x.to_string(), handler),
+        }
+    }
+
+    /// `c`, with its first part under way if it is a sequence, a `par` or a
+    /// `try`: where its next step is taken.
+    pub(super) fn open(c: &Com) -> Running {
+        let com = |c: &Com| Box::new(Running::Com(c.clone()));
+        match c {
+            Com::CSeq(c1, c2) => Running::Seq(com(c1), (**c2).clone()),
+            Com::CPar(c1, c2) => Running::Par(com(c1), com(c2)),
+            Com::CTry(body, x, handler) => Running::Try(com(body), x.clone(), (**handler).clone()),
+            _ => Running::Com(c.clone()),
+        }
+    }
+
+    /// The command, if nothing in it is under way.
+    pub(super) fn com(&self) -> Option<&Com> {
+        match self {
+            Running::Com(c) => Some(c),
+            _ => None,
+        }
+    }
+
+    /// The command this amounts to, with each loop under way written as
+    /// the rest of its iteration followed by the loop.
+    pub(super) fn to_com(&self) -> Com {
+        match self {
+            Running::Com(c) => c.clone(),
+            Running::Seq(r1, c2) => Com::seq(r1.to_com(), c2.clone()),
+            Running::If(b, r1, c2) => Com::if_(b.clone(), r1.to_com(), c2.clone()),
+            Running::Loop(rest, b, body) => {
+                Com::seq(rest.to_com(), Com::while_(b.clone(), body.clone()))
+            }
+            Running::Par(r1, r2) => Com::par(r1.to_com(), r2.to_com()),
+            Running::Try(body, x, handler) => Com::try_(body.to_com(), x, handler.clone()),
+        }
+    }
+}
+
+/// One step of the configuration `(c, st)`, or `None` if `c` is `skip`,
+/// `break`, `continue` or `throw n`. Procedure calls and array writes
+/// have no small steps, so reaching one is an error.
+pub fn step(st: &State, c: &Config) -> Result<Option<(State, Config)>, Unsupported> {
+    Ok(step_output(st, c)?.map(|(st, c, _)| (st, c)))
+}
+
+/// `step`, also returning the number printed if the step was a `print`'s.
+pub fn step_output(
+    st: &State,
+    c: &Config,
+) -> Result<Option<(State, Config, Option<i64>)>, Unsupported> {
+    Ok(step_running(st, &c.0)?.map(|(st, r, printed)| (st, Config(r), printed)))
+}
+
+type Stepped = Result<Option<(State, Running, Option<i64>)>, Unsupported>;
+
+/// The step `inner` takes, if any, as a step of what `wrap` builds around
+/// it.
+fn within(inner: Stepped, wrap: impl FnOnce(Running) -> Running) -> Stepped {
+    Ok(inner?.map(|(st, r, printed)| (st, wrap(r), printed)))
+}
+
+fn step_running(st: &State, r: &Running) -> Stepped {
+    let quiet = |st: State, r| Ok(Some((st, r, None)));
+    match r {
+        Running::Com(c) => step_com(st, c),
+        Running::Seq(r1, c2) => match r1.com() {
+            Some(Com::CSkip) => quiet(st.clone(), Running::Com(c2.clone())),
+            _ if done(r1) => quiet(st.clone(), (**r1).clone()),
+            _ => within(step_running(st, r1), |r1| Running::seq(r1, c2.clone())),
+        },
+        Running::If(b, r1, c2) => match b {
+            Bexp::BTrue => quiet(st.clone(), (**r1).clone()),
+            Bexp::BFalse => quiet(st.clone(), Running::Com(c2.clone())),
+            _ => Ok(
+                bstep(st, b).map(|b| (st.clone(), Running::If(b, r1.clone(), c2.clone()), None))
+            ),
+        },
+        Running::Loop(rest, b, body) => match rest.com() {
+            Some(Com::CSkip | Com::CContinue) => quiet(
+                st.clone(),
+                Running::Com(Com::while_(b.clone(), body.clone())),
+            ),
+            Some(Com::CBreak) => quiet(st.clone(), Running::Com(Com::CSkip)),
+            Some(Com::CThrow(Aexp::ANum(_))) => quiet(st.clone(), (**rest).clone()),
+            _ => within(step_running(st, rest), |rest| {
+                Running::loop_(rest, b.clone(), body.clone())
+            }),
+        },
+        // The left branch first; `interleavings` tries every order.
+        Running::Par(r1, _) if thrown(r1).is_some() => quiet(st.clone(), (**r1).clone()),
+        Running::Par(_, r2) if thrown(r2).is_some() => quiet(st.clone(), (**r2).clone()),
+        Running::Par(r1, r2) => match (done(r1), done(r2)) {
+            (true, true) => quiet(st.clone(), Running::Com(Com::CSkip)),
+            (false, _) => within(step_running(st, r1), |r1| Running::par(r1, (**r2).clone())),
+            (true, false) => within(step_running(st, r2), |r2| Running::par((**r1).clone(), r2)),
+        },
+        Running::Try(body, x, handler) => match body.com() {
+            Some(Com::CSkip | Com::CBreak | Com::CContinue) => quiet(st.clone(), (**body).clone()),
+            Some(Com::CThrow(Aexp::ANum(n))) => quiet(
+                tm_update(st.clone(), x.clone(), *n),
+                Running::Com(handler.clone()),
+            ),
+            _ => within(step_running(st, body), |body| {
+                Running::try_(body, x, handler.clone())
+            }),
+        },
+    }
+}
+
+/// One step of a command with nothing in it under way.
+fn step_com(st: &State, c: &Com) -> Stepped {
+    // `None` for an expression that has no step left, which is one that
+    // the arms below deal with first.
+    let quiet = |st: State, c: Option<Com>| Ok(c.map(|c| (st, Running::Com(c), None)));
+    match c {
+        Com::CSkip | Com::CBreak | Com::CContinue => Ok(None),
+        Com::CAsgn(x, a) => match a {
+            Aexp::ANum(n) => quiet(tm_update(st.clone(), x.clone(), *n), Some(Com::CSkip)),
+            _ => quiet(st.clone(), astep(st, a).map(|a| Com::CAsgn(x.clone(), a))),
+        },
+        // Like `ceval`, small steps resolve the choice of `havoc` to `0`.
+        Com::CHavoc(x) => quiet(tm_update(st.clone(), x.clone(), 0), Some(Com::CSkip)),
+        Com::CPrint(a) => match a {
+            Aexp::ANum(n) => Ok(Some((st.clone(), Running::Com(Com::CSkip), Some(*n)))),
+            _ => quiet(st.clone(), astep(st, a).map(Com::CPrint)),
+        },
+        Com::CIf(b, c1, c2) => match b {
+            Bexp::BTrue => quiet(st.clone(), Some((**c1).clone())),
+            Bexp::BFalse => quiet(st.clone(), Some((**c2).clone())),
+            _ => quiet(
+                st.clone(),
+                bstep(st, b).map(|b| Com::CIf(b, c1.clone(), c2.clone())),
+            ),
+        },
+        Com::CWhile(b, body) => {
+            let first = Running::loop_(Running::Com((**body).clone()), b.clone(), (**body).clone());
+            Ok(Some((
+                st.clone(),
+                Running::if_(b.clone(), first, Com::CSkip),
+                None,
+            )))
+        }
+        // A `for` loop steps to its desugaring, the way `while` steps to an
+        // `if`.
+        Com::CFor(..) => quiet(st.clone(), Some(lower_for(c))),
+        Com::CCall(..) => Err(Unsupported("procedure calls")),
+        Com::CArrAsgn(..) => Err(Unsupported("arrays")),
+        Com::CThrow(a) => quiet(st.clone(), astep(st, a).map(Com::CThrow)),
+        Com::CSeq(..) | Com::CPar(..) | Com::CTry(..) => step_running(st, &Running::open(c)),
+    }
+}
+
+/// Whether `r` has finished: it is `skip`, or a `break`, `continue` or
+/// `throw n` on its way out.
+pub(super) fn done(r: &Running) -> bool {
+    r.com().is_some_and(done_com)
+}
+
+pub(super) fn done_com(c: &Com) -> bool {
+    matches!(
+        c,
+        Com::CSkip | Com::CBreak | Com::CContinue | Com::CThrow(Aexp::ANum(_))
+    )
+}
+
+/// The code of the exception `r` is, if it is `throw n`.
+pub(super) fn thrown(r: &Running) -> Option<i64> {
+    match r.com() {
+        Some(Com::CThrow(Aexp::ANum(n))) => Some(*n),
+        _ => None,
+    }
+}
+
+/// Take up to `max_steps` steps from `(c, st)`, returning the configuration
+/// reached and the number of steps taken. Fewer than `max_steps` steps
+/// means the program finished.
+pub fn multistep(
+    st: State,
+    c: Config,
+    max_steps: usize,
+) -> Result<(State, Config, usize), Unsupported> {
+    let (mut st, mut c) = (st, c);
+    for n in 0..max_steps {
+        match step(&st, &c)? {
+            Some((st1, c1)) => {
+                st = st1;
+                c = c1;
+            }
+            None => return Ok((st, c, n)),
+        }
+    }
+    Ok((st, c, max_steps))
+}
+
+/// Run `c` to completion, or give up with `None` after `max_steps` steps.
+/// As with `ceval`, a `break` or `continue` outside any loop ends the
+/// program, and so does an exception nothing catches.
+pub fn normalize(st: State, c: &Com, max_steps: usize) -> Result<Option<State>, Unsupported> {
+    let (st, c, _) = multistep(st, c.clone().into(), max_steps)?;
+    Ok(done(&c.0).then_some(st))
+}
+
+/// `normalize` that also returns what the program printed, in order: all
+/// of it, or what came out in the first `max_steps` steps if the state is
+/// `None`.
+pub fn normalize_output(
+    st: State,
+    c: &Com,
+    max_steps: usize,
+) -> Result<(Option<State>, Vec<i64>), Unsupported> {
+    let (mut st, mut c) = (st, Config::from(c.clone()));
+    let mut output = Vec::new();
+    for _ in 0..max_steps {
+        match step_output(&st, &c)? {
+            Some((st1, c1, printed)) => {
+                st = st1;
+                c = c1;
+                output.extend(printed);
+            }
+            None => return Ok((Some(st), output)),
+        }
+    }
+    Ok((done(&c.0).then_some(st), output))
+}
+
+#[cfg(test)]
+mod test_imp_smallstep {
+    use super::*;
+    use crate::imp::{ceval, ceval_output, parse_aexp, parse_com};
+    use crate::state;
+    use crate::state::{empty_state, lookup};
+
+    #[test]
+    fn test_astep_left_to_right() {
+        let st = state! {"X" => 2};
+        let mut a = parse_aexp("(1 + X) * (X - 3)").unwrap();
+        let mut trace = vec![a.to_string()];
+        while let Some(a1) = astep(&st, &a) {
+            a = a1;
+            trace.push(a.to_string());
+        }
+        assert_eq!(
+            trace,
+            [
+                "(1 + X) * (X - 3)",
+                "(1 + 2) * (X - 3)",
+                "3 * (X - 3)",
+                "3 * (2 - 3)",
+                "3 * -1",
+                "-3",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_bstep_and_short_circuits() {
+        let b = Bexp::and(Bexp::BFalse, Bexp::eq(Aexp::var("X"), Aexp::num(0)));
+        assert_eq!(bstep(&empty_state(), &b), Some(Bexp::BFalse));
+        assert_eq!(bstep(&empty_state(), &Bexp::BFalse), None);
+    }
+
+    #[test]
+    fn test_step_assignment() {
+        let (st, c) = step(&empty_state(), &Com::asgn("X", Aexp::num(3)).into())
+            .unwrap()
+            .unwrap();
+        assert_eq!(c, Com::CSkip.into());
+        assert_eq!(lookup(&st, "X"), 3);
+        assert!(step(&st, &c).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_calls_are_unsupported() {
+        let c = parse_com("X := 1; Y := f(X)").unwrap();
+        assert_eq!(
+            normalize(empty_state(), &c, 100).err(),
+            Some(Unsupported("procedure calls"))
+        );
+    }
+
+    #[test]
+    fn test_agrees_with_ceval() {
+        let programs = [
+            "Z := X; Y := 1; while ~(Z = 0) do Y := Y * Z; Z := Z - 1 end",
+            "while ~(X = 0) do Z := Z - 1; X := X - 1 end",
+            "if X <= 2 && ~(X = 1) then Y := X + 1 else (Y := 1; Y := Y * 2); Z := 3 end",
+            "while true do X := X + 1; if 5 <= X then break else skip end; Y := Y + X end",
+            "while X <= 6 do X := X + 1; if X = 4 then continue else Y := Y + X end end",
+            "while Y <= 3 do Y := Y + 1; while true do Z := Z + 1; break; Z := 0 end end",
+            "X := 1; break; X := 2",
+            "for I := 0; I <= X; I := I + 1 do if I = 2 then continue else Y := Y + I end end",
+            "for (I := 9; J := 0); 0 <= I; I := I - 1 do J := J + I; if J <= 20 then skip else break end end",
+        ];
+        for p in programs {
+            let c = parse_com(p).unwrap();
+            for x in 0..5 {
+                let st = state! {"X" => x, "Z" => 10};
+                let big = ceval(st.clone(), &c).unwrap();
+                let small = normalize(st, &c, 10_000).unwrap().unwrap();
+                for v in c.vars() {
+                    assert_eq!(lookup(&big, &v), lookup(&small, &v), "{} on {}", v, p);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_loop_under_way() {
+        let c: Config = parse_com("while true do break; X := 1 end").unwrap().into();
+        let mut trace = vec![c.to_string()];
+        let (mut st, mut c) = (empty_state(), c);
+        while let Some((st1, c1)) = step(&st, &c).unwrap() {
+            (st, c) = (st1, c1);
+            trace.push(c.to_string());
+        }
+        // Partway through an iteration, the loop follows the rest of it,
+        // and the `break` there ends the loop, not just the iteration.
+        assert_eq!(
+            trace,
+            [
+                "while true do break; X := 1 end",
+                "if true then (break; X := 1); while true do break; X := 1 end else skip end",
+                "(break; X := 1); while true do break; X := 1 end",
+                "break; while true do break; X := 1 end",
+                "skip",
+            ]
+        );
+        assert_eq!(lookup(&st, "X"), 0);
+    }
+
+    #[test]
+    fn test_budget() {
+        let c = parse_com("while true do skip end").unwrap();
+        assert!(normalize(empty_state(), &c, 1_000).unwrap().is_none());
+        let (_, c1, n) = multistep(empty_state(), c.into(), 1_000).unwrap();
+        assert_eq!(n, 1_000);
+        assert_ne!(c1, Com::CSkip.into());
+        let (_, c2, n) =
+            multistep(empty_state(), Com::asgn("X", Aexp::num(1)).into(), 1_000).unwrap();
+        assert_eq!((c2, n), (Com::CSkip.into(), 1));
+    }
+
+    #[test]
+    fn test_output_agrees_with_ceval() {
+        let c = parse_com(
+            "for I := 0; I <= X; I := I + 1 do print I * I; if I = 2 then break else skip end end; \
+             print 0 - I",
+        )
+        .unwrap();
+        for x in 0..4 {
+            let (st, output) = normalize_output(state! {"X" => x}, &c, 1_000).unwrap();
+            let (expected_st, expected) = ceval_output(state! {"X" => x}, &c, 100).unwrap();
+            assert_eq!(output, expected);
+            assert_eq!(
+                lookup(&st.unwrap(), "I"),
+                lookup(&expected_st.finished().unwrap(), "I")
+            );
+        }
+        let (_, c1, printed) = step_output(&empty_state(), &Com::print(Aexp::num(7)).into())
+            .unwrap()
+            .unwrap();
+        assert_eq!((c1, printed), (Com::CSkip.into(), Some(7)));
+    }
+}
@@ -0,0 +1,265 @@
+//! SMT-LIB 2 export. Expressions become terms over 64-bit bitvectors, so
+//! `+`, `-` and `*` wrap exactly as `aeval`'s do and `<=` is signed; an
+//! array is an SMT array from indices to values, with no bounds, so a read
+//! out of bounds can be anything. The scripts only ask the question: feed
+//! them to Z3 or CVC5, e.g. `z3 -in`, and read the answer.
+//!
+//! Assertion formulas and verification conditions are exported the same
+//! way, `even(a)` as `a` having remainder `0` by `2`.
+//!
+//! A variable and an array with the same name are declared once, as the
+//! variable, so such a script is ill-sorted.
+
+use std::collections::BTreeSet;
+
+use super::{Aexp, Bexp, Formula, Implication};
+
+/// `a` as a term of sort `(_ BitVec 64)`.
+pub fn smt_aexp(a: &Aexp) -> String {
+    match a {
+        Aexp::ANum(n) => literal(*n),
+        Aexp::AId(x) => symbol(x),
+        Aexp::APlus(a1, a2) => format!("(bvadd {} {})", smt_aexp(a1), smt_aexp(a2)),
+        Aexp::AMinus(a1, a2) => format!("(bvsub {} {})", smt_aexp(a1), smt_aexp(a2)),
+        Aexp::AMult(a1, a2) => format!("(bvmul {} {})", smt_aexp(a1), smt_aexp(a2)),
+        Aexp::AIndex(x, i) => format!("(select {} {})", symbol(x), smt_aexp(i)),
+    }
+}
+
+/// `b` as a term of sort `Bool`.
+pub fn smt_bexp(b: &Bexp) -> String {
+    match b {
+        Bexp::BTrue => "true".to_string(),
+        Bexp::BFalse => "false".to_string(),
+        Bexp::BEq(a1, a2) => format!("(= {} {})", smt_aexp(a1), smt_aexp(a2)),
+        Bexp::BLe(a1, a2) => format!("(bvsle {} {})", smt_aexp(a1), smt_aexp(a2)),
+        Bexp::BNot(b) => format!("(not {})", smt_bexp(b)),
+        Bexp::BAnd(b1, b2) => format!("(and {} {})", smt_bexp(b1), smt_bexp(b2)),
+    }
+}
+
+/// `p` as a term of sort `Bool`.
+pub fn smt_formula(p: &Formula) -> String {
+    match p {
+        Formula::FTrue => "true".to_string(),
+        Formula::FFalse => "false".to_string(),
+        Formula::FEq(a1, a2) => format!("(= {} {})", smt_aexp(a1), smt_aexp(a2)),
+        Formula::FLe(a1, a2) => format!("(bvsle {} {})", smt_aexp(a1), smt_aexp(a2)),
+        Formula::FEven(a) => format!("(= (bvsrem {} (_ bv2 64)) (_ bv0 64))", smt_aexp(a)),
+        Formula::FNot(p) => format!("(not {})", smt_formula(p)),
+        Formula::FAnd(p, q) => format!("(and {} {})", smt_formula(p), smt_formula(q)),
+        Formula::FOr(p, q) => format!("(or {} {})", smt_formula(p), smt_formula(q)),
+        Formula::FImplies(p, q) => format!("(=> {} {})", smt_formula(p), smt_formula(q)),
+    }
+}
+
+/// A script asking whether some state satisfies all of `conds`, such as the
+/// branch conditions along one path through a program: `sat` if one does,
+/// and the model is such a state.
+pub fn smt_satisfiable(conds: &[Bexp]) -> String {
+    let mut names = Names::default();
+    for b in conds {
+        names.bexp(b);
+    }
+    let asserts: Vec<String> = conds.iter().map(smt_bexp).collect();
+    script(&names, &asserts)
+}
+
+/// A script asking whether `goal` holds in every state satisfying all of
+/// `hyps`: `unsat` if it does, and otherwise the model is a state where it
+/// doesn't.
+pub fn smt_valid(hyps: &[Bexp], goal: &Bexp) -> String {
+    let mut names = Names::default();
+    for b in hyps.iter().chain([goal]) {
+        names.bexp(b);
+    }
+    let mut asserts: Vec<String> = hyps.iter().map(smt_bexp).collect();
+    asserts.push(format!("(not {})", smt_bexp(goal)));
+    script(&names, &asserts)
+}
+
+/// A script asking whether `vc` is valid: `unsat` if it is, and otherwise
+/// the model is a counterexample.
+pub fn smt_implication(vc: &Implication) -> String {
+    let mut names = Names::default();
+    names.formula(&vc.hyp);
+    names.formula(&vc.concl);
+    let asserts = [
+        smt_formula(&vc.hyp),
+        format!("(not {})", smt_formula(&vc.concl)),
+    ];
+    script(&names, &asserts)
+}
+
+/// Declare `names`, assert each of `asserts`, and ask for a model if they
+/// are satisfiable.
+fn script(names: &Names, asserts: &[String]) -> String {
+    let Names { vars, arrays } = names;
+    let logic = if arrays.is_empty() { "QF_BV" } else { "QF_ABV" };
+    let mut out = format!("(set-logic {})\n", logic);
+    for x in vars {
+        out += &format!("(declare-const {} (_ BitVec 64))\n", symbol(x));
+    }
+    for x in arrays.difference(vars) {
+        out += &format!(
+            "(declare-const {} (Array (_ BitVec 64) (_ BitVec 64)))\n",
+            symbol(x)
+        );
+    }
+    for a in asserts {
+        out += &format!("(assert {})\n", a);
+    }
+    out + "(check-sat)\n(get-model)\n"
+}
+
+/// The variables and arrays a script declares.
+#[derive(Default)]
+struct Names {
+    vars: BTreeSet<String>,
+    arrays: BTreeSet<String>,
+}
+
+impl Names {
+    fn aexp(&mut self, a: &Aexp) {
+        match a {
+            Aexp::ANum(_) => {}
+            Aexp::AId(x) => {
+                self.vars.insert(x.clone());
+            }
+            Aexp::APlus(a1, a2) | Aexp::AMinus(a1, a2) | Aexp::AMult(a1, a2) => {
+                self.aexp(a1);
+                self.aexp(a2);
+            }
+            Aexp::AIndex(x, i) => {
+                self.arrays.insert(x.clone());
+                self.aexp(i);
+            }
+        }
+    }
+
+    fn bexp(&mut self, b: &Bexp) {
+        match b {
+            Bexp::BTrue | Bexp::BFalse => {}
+            Bexp::BEq(a1, a2) | Bexp::BLe(a1, a2) => {
+                self.aexp(a1);
+                self.aexp(a2);
+            }
+            Bexp::BNot(b) => self.bexp(b),
+            Bexp::BAnd(b1, b2) => {
+                self.bexp(b1);
+                self.bexp(b2);
+            }
+        }
+    }
+
+    fn formula(&mut self, p: &Formula) {
+        match p {
+            Formula::FTrue | Formula::FFalse => {}
+            Formula::FEq(a1, a2) | Formula::FLe(a1, a2) => {
+                self.aexp(a1);
+                self.aexp(a2);
+            }
+            Formula::FEven(a) => self.aexp(a),
+            Formula::FNot(p) => self.formula(p),
+            Formula::FAnd(p, q) | Formula::FOr(p, q) | Formula::FImplies(p, q) => {
+                self.formula(p);
+                self.formula(q);
+            }
+        }
+    }
+}
+
+/// `n` as a 64-bit literal, negated if negative so it reads as `n`.
+fn literal(n: i64) -> String {
+    match n {
+        i64::MIN => "#x8000000000000000".to_string(),
+        n if n < 0 => format!("(bvneg (_ bv{} 64))", -n),
+        n => format!("(_ bv{} 64)", n),
+    }
+}
+
+/// Names SMT-LIB already gives a meaning to, which a variable can't take
+/// unquoted.
+const RESERVED: [&str; 12] = [
+    "true", "false", "not", "and", "or", "xor", "ite", "select", "store", "let", "forall", "exists",
+];
+
+/// `x` as an SMT-LIB symbol, quoted with `|...|` if it could be taken for
+/// something else.
+fn symbol(x: &str) -> String {
+    if RESERVED.contains(&x) || x.starts_with("bv") {
+        format!("|{}|", x)
+    } else {
+        x.to_string()
+    }
+}
+
+#[cfg(test)]
+mod test_imp_smt {
+    use super::*;
+    use crate::imp::parse_bexp;
+
+    #[test]
+    fn test_terms() {
+        let b = parse_bexp("~(X + 2 * Y <= 0 - 5) && A[I] = Z - 1").unwrap();
+        assert_eq!(
+            smt_bexp(&b),
+            "(and (not (bvsle (bvadd X (bvmul (_ bv2 64) Y)) (bvsub (_ bv0 64) (_ bv5 64)))) \
+             (= (select A I) (bvsub Z (_ bv1 64))))"
+        );
+        assert_eq!(smt_aexp(&Aexp::num(-3)), "(bvneg (_ bv3 64))");
+        assert_eq!(smt_aexp(&Aexp::num(i64::MIN)), "#x8000000000000000");
+        assert_eq!(smt_aexp(&Aexp::var("and")), "|and|");
+        assert_eq!(smt_aexp(&Aexp::var("bvadd")), "|bvadd|");
+        assert_eq!(smt_bexp(&Bexp::BTrue), "true");
+    }
+
+    #[test]
+    fn test_scripts() {
+        let path = [
+            parse_bexp("X <= 3").unwrap(),
+            parse_bexp("~(X = Y)").unwrap(),
+        ];
+        assert_eq!(
+            smt_satisfiable(&path),
+            "(set-logic QF_BV)\n\
+             (declare-const X (_ BitVec 64))\n\
+             (declare-const Y (_ BitVec 64))\n\
+             (assert (bvsle X (_ bv3 64)))\n\
+             (assert (not (= X Y)))\n\
+             (check-sat)\n\
+             (get-model)\n"
+        );
+        let goal = parse_bexp("A[0] = X").unwrap();
+        assert_eq!(
+            smt_valid(&path[..1], &goal),
+            "(set-logic QF_ABV)\n\
+             (declare-const X (_ BitVec 64))\n\
+             (declare-const A (Array (_ BitVec 64) (_ BitVec 64)))\n\
+             (assert (bvsle X (_ bv3 64)))\n\
+             (assert (not (= (select A (_ bv0 64)) X)))\n\
+             (check-sat)\n\
+             (get-model)\n"
+        );
+        let vc = Implication::new(
+            Formula::even(Aexp::var("X")),
+            Formula::or(
+                Formula::FFalse,
+                Formula::implies(Formula::FTrue, Formula::FFalse),
+            ),
+        );
+        assert_eq!(
+            smt_implication(&vc),
+            "(set-logic QF_BV)\n\
+             (declare-const X (_ BitVec 64))\n\
+             (assert (= (bvsrem X (_ bv2 64)) (_ bv0 64)))\n\
+             (assert (not (or false (=> true false))))\n\
+             (check-sat)\n\
+             (get-model)\n"
+        );
+        assert_eq!(
+            smt_valid(&[], &Bexp::BTrue),
+            "(set-logic QF_BV)\n(assert (not true))\n(check-sat)\n(get-model)\n"
+        );
+    }
+}
@@ -0,0 +1,226 @@
+//! Staged evaluation: translate a command into nested Rust closures once,
+//! so that running it no longer inspects the AST. Each node becomes a
+//! closure that calls the closures of its children; a loop body is
+//! dispatched on once at compile time instead of once per iteration.
+
+use super::{Aexp, Bexp, Com, Signal, Unsupported};
+use crate::map::tm_update;
+use crate::state::State;
+
+type AFn = Box<dyn Fn(&State) -> i64>;
+type BFn = Box<dyn Fn(&State) -> bool>;
+/// A compiled command takes the fuel left and returns `None` when it runs
+/// out.
+type CFn = Box<dyn Fn(State, &mut u64) -> Option<(State, Signal)>>;
+
+/// Compile `c` into a function behaving like `|st| ceval_fuel(st, c,
+/// fuel)`: each call gets `fuel` loop iterations and returns `None` if it
+/// needs more.
+///
+/// Procedure calls need the procedures `Program::run` keeps, and array
+/// writes the arrays `ceval_arrays` keeps, so both are an error.
+pub fn compile_to_fn(c: &Com, fuel: u64) -> Result<impl Fn(State) -> Option<State>, Unsupported> {
+    let run = compile_com(c)?;
+    Ok(move |st| {
+        let mut fuel = fuel;
+        run(st, &mut fuel).map(|(st, _)| st)
+    })
+}
+
+fn compile_aexp(a: &Aexp) -> AFn {
+    match a {
+        Aexp::ANum(n) => {
+            let n = *n;
+            Box::new(move |_| n)
+        }
+        Aexp::AId(x) => {
+            let x = x.clone();
+            Box::new(move |st| st(&x))
+        }
+        Aexp::APlus(a1, a2) => {
+            let (f1, f2) = (compile_aexp(a1), compile_aexp(a2));
+            Box::new(move |st| f1(st).wrapping_add(f2(st)))
+        }
+        Aexp::AMinus(a1, a2) => {
+            let (f1, f2) = (compile_aexp(a1), compile_aexp(a2));
+            Box::new(move |st| f1(st).wrapping_sub(f2(st)))
+        }
+        Aexp::AMult(a1, a2) => {
+            let (f1, f2) = (compile_aexp(a1), compile_aexp(a2));
+            Box::new(move |st| f1(st).wrapping_mul(f2(st)))
+        }
+        // As in `aeval`, a `State` has no arrays, so every element is `0`.
+        Aexp::AIndex(..) => Box::new(|_| 0),
+    }
+}
+
+fn compile_bexp(b: &Bexp) -> BFn {
+    match b {
+        Bexp::BTrue => Box::new(|_| true),
+        Bexp::BFalse => Box::new(|_| false),
+        Bexp::BEq(a1, a2) => {
+            let (f1, f2) = (compile_aexp(a1), compile_aexp(a2));
+            Box::new(move |st| f1(st) == f2(st))
+        }
+        Bexp::BLe(a1, a2) => {
+            let (f1, f2) = (compile_aexp(a1), compile_aexp(a2));
+            Box::new(move |st| f1(st) <= f2(st))
+        }
+        Bexp::BNot(b1) => {
+            let f = compile_bexp(b1);
+            Box::new(move |st| !f(st))
+        }
+        Bexp::BAnd(b1, b2) => {
+            let (f1, f2) = (compile_bexp(b1), compile_bexp(b2));
+            Box::new(move |st| f1(st) && f2(st))
+        }
+    }
+}
+
+fn compile_com(c: &Com) -> Result<CFn, Unsupported> {
+    Ok(match c {
+        Com::CSkip => Box::new(|st, _| Some((st, Signal::Normal))),
+        Com::CBreak => Box::new(|st, _| Some((st, Signal::Break))),
+        Com::CContinue => Box::new(|st, _| Some((st, Signal::Continue))),
+        Com::CAsgn(x, a) => {
+            let (x, f) = (x.clone(), compile_aexp(a));
+            Box::new(move |st, _| {
+                let n = f(&st);
+                Some((tm_update(st, x.clone(), n), Signal::Normal))
+            })
+        }
+        // Only the state comes out of a compiled command, so `print` is a
+        // no-op.
+        Com::CPrint(_) => Box::new(|st, _| Some((st, Signal::Normal))),
+        Com::CHavoc(x) => {
+            let x = x.clone();
+            Box::new(move |st, _| Some((tm_update(st, x.clone(), 0), Signal::Normal)))
+        }
+        Com::CSeq(c1, c2) => {
+            let (f1, f2) = (compile_com(c1)?, compile_com(c2)?);
+            Box::new(move |st, fuel| match f1(st, fuel)? {
+                (st, Signal::Normal) => f2(st, fuel),
+                interrupted => Some(interrupted),
+            })
+        }
+        Com::CIf(b, c1, c2) => {
+            let (fb, f1, f2) = (compile_bexp(b), compile_com(c1)?, compile_com(c2)?);
+            Box::new(move |st, fuel| if fb(&st) { f1(st, fuel) } else { f2(st, fuel) })
+        }
+        Com::CWhile(b, body) => compile_loop(compile_bexp(b), compile_com(body)?, None),
+        Com::CFor(init, b, update, body) => {
+            let finit = compile_com(init)?;
+            let run = compile_loop(
+                compile_bexp(b),
+                compile_com(body)?,
+                Some(compile_com(update)?),
+            );
+            Box::new(move |st, fuel| match finit(st, fuel)? {
+                (st, Signal::Normal) => run(st, fuel),
+                interrupted => Some(interrupted),
+            })
+        }
+        // Like `ceval`, the left branch and then the right.
+        Com::CPar(c1, c2) => {
+            let (f1, f2) = (compile_com(c1)?, compile_com(c2)?);
+            Box::new(move |st, fuel| match f1(st, fuel)? {
+                (st, Signal::Throw(code)) => Some((st, Signal::Throw(code))),
+                (st, _) => match f2(st, fuel)? {
+                    (st, Signal::Throw(code)) => Some((st, Signal::Throw(code))),
+                    (st, _) => Some((st, Signal::Normal)),
+                },
+            })
+        }
+        Com::CThrow(a) => {
+            let f = compile_aexp(a);
+            Box::new(move |st, _| {
+                let code = f(&st);
+                Some((st, Signal::Throw(code)))
+            })
+        }
+        Com::CTry(body, x, handler) => {
+            let (fbody, x, fhandler) = (compile_com(body)?, x.clone(), compile_com(handler)?);
+            Box::new(move |st, fuel| match fbody(st, fuel)? {
+                (st, Signal::Throw(code)) => fhandler(tm_update(st, x.clone(), code), fuel),
+                finished => Some(finished),
+            })
+        }
+        Com::CCall(..) => return Err(Unsupported("procedure calls")),
+        Com::CArrAsgn(..) => return Err(Unsupported("arrays")),
+    })
+}
+
+/// A `while` loop, or a `for` loop's loop part when there is an `update`,
+/// with `ceval`'s treatment of `break` and fuel.
+fn compile_loop(b: BFn, body: CFn, update: Option<CFn>) -> CFn {
+    Box::new(move |st, fuel| {
+        let mut st = st;
+        while b(&st) {
+            *fuel = fuel.checked_sub(1)?;
+            let (st1, signal) = body(st, fuel)?;
+            st = st1;
+            match signal {
+                Signal::Break => break,
+                Signal::Throw(_) => return Some((st, signal)),
+                _ => {}
+            }
+            if let Some(update) = &update {
+                let (st1, signal) = update(st, fuel)?;
+                st = st1;
+                match signal {
+                    Signal::Break => break,
+                    Signal::Throw(_) => return Some((st, signal)),
+                    _ => {}
+                }
+            }
+        }
+        Some((st, Signal::Normal))
+    })
+}
+
+#[cfg(test)]
+mod test_imp_staged {
+    use super::*;
+    use crate::imp::{ceval_fuel, parse_com, random_state};
+    use crate::rng::Rng;
+    use crate::state;
+    use crate::state::lookup;
+
+    #[test]
+    fn test_factorial() {
+        let c = parse_com("Z := X; Y := 1; while ~(Z = 0) do Y := Y * Z; Z := Z - 1 end").unwrap();
+        let fact = compile_to_fn(&c, 100).unwrap();
+        // The compiled program can be run any number of times.
+        for (x, y) in [(0, 1), (5, 120), (10, 3_628_800)] {
+            assert_eq!(lookup(&fact(state! {"X" => x}).unwrap(), "Y"), y);
+        }
+        assert!(fact(state! {"X" => -1}).is_none());
+    }
+
+    #[test]
+    fn test_agrees_with_ceval_fuel() {
+        let programs = [
+            "Y := 0; while Y <= X - 1 do Y := Y + 1; Z := Z + Y end",
+            "if X <= Y && ~(X = 0) then Z := X * Y else Z := X - Y end; havoc X",
+            "while true do X := X + 1; if 5 <= X then break else continue end end",
+            "for I := 0; I <= X; I := I + 1 do if I = 2 then continue else Y := Y + I end end",
+            "X := 1; break; X := 2",
+        ];
+        let mut rng = Rng::new(3);
+        for p in programs {
+            let c = parse_com(p).unwrap();
+            let staged = compile_to_fn(&c, 50).unwrap();
+            for _ in 0..100 {
+                let st = random_state(&mut rng, &["X", "Y", "Z"]);
+                let expected = ceval_fuel(st.clone(), &c, 50).unwrap();
+                let found = staged(st);
+                assert_eq!(found.is_some(), expected.is_some(), "{}", p);
+                if let (Some(found), Some(expected)) = (found, expected) {
+                    for v in c.vars() {
+                        assert_eq!(lookup(&found, &v), lookup(&expected, &v), "{} on {}", v, p);
+                    }
+                }
+            }
+        }
+    }
+}
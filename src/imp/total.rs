@@ -0,0 +1,498 @@
+//! Total correctness, from the end of Hoare2: `[[P]] c [[Q]]` says that
+//! `c` started where `P` holds terminates, in a state satisfying `Q`. The
+//! usual argument for a loop is a variant: an expression that is a natural
+//! number whenever the loop's test holds and that each iteration makes
+//! strictly smaller, which can't go on forever. A `TotalTriple` carries one
+//! per loop, and checking it tests the measures on every iteration of the
+//! runs it tries, alongside the postcondition.
+
+use std::fmt;
+
+use super::random::minimize_state;
+use super::wp::collect_loops;
+use super::{aeval, beval, Aexp, Assertion, Bexp, Com, Signal, TripleViolation, Unsupported};
+use crate::map::tm_update;
+use crate::state::State;
+
+/// A total correctness triple, with a variant for each loop of `com` in
+/// the order `wp_with_invariants` takes invariants: each loop before those
+/// inside it and those after it.
+#[derive(Debug, Clone)]
+pub struct TotalTriple {
+    pub pre: Assertion,
+    pub com: Com,
+    pub post: Assertion,
+    pub variants: Vec<Aexp>,
+}
+
+/// How a loop's variant failed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VariantViolation {
+    /// The loop's test held with the measure negative.
+    Negative {
+        case: usize,
+        /// Which loop, counting from `0` in the order of `variants`.
+        loop_index: usize,
+        measure: i64,
+        state: Vec<(String, i64)>,
+    },
+    /// An iteration didn't make the measure smaller.
+    NotDecreasing {
+        case: usize,
+        loop_index: usize,
+        before: i64,
+        after: i64,
+        /// The state at the start of the iteration.
+        state: Vec<(String, i64)>,
+    },
+}
+
+impl fmt::Display for VariantViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let show = |st: &[(String, i64)]| {
+            let st: Vec<String> = st.iter().map(|(x, n)| format!("{}={}", x, n)).collect();
+            st.join(", ")
+        };
+        match self {
+            VariantViolation::Negative {
+                case,
+                loop_index,
+                measure,
+                state,
+            } => write!(
+                f,
+                "case {}: loop {} runs at {} with its variant at {}",
+                case,
+                loop_index,
+                show(state),
+                measure
+            ),
+            VariantViolation::NotDecreasing {
+                case,
+                loop_index,
+                before,
+                after,
+                state,
+            } => write!(
+                f,
+                "case {}: an iteration of loop {} from {} takes its variant from {} to {}",
+                case,
+                loop_index,
+                show(state),
+                before,
+                after
+            ),
+        }
+    }
+}
+
+/// What checking a `TotalTriple` showed, once no run broke the
+/// postcondition.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Correctness {
+    /// The variants did their job too.
+    Total,
+    /// The postcondition held, but this variant failed, so termination
+    /// isn't shown.
+    Partial(VariantViolation),
+}
+
+/// The runs a `TotalTriple` was tested on, as for `TripleCheck`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TotalCheck {
+    pub tested: usize,
+    pub skipped: usize,
+    /// Runs that ran out of fuel with every variant still decreasing.
+    pub diverged: usize,
+    pub correctness: Correctness,
+}
+
+impl TotalTriple {
+    /// Panics unless there is one variant per loop of `com`.
+    pub fn new(
+        pre: impl Into<Assertion>,
+        com: Com,
+        post: impl Into<Assertion>,
+        variants: Vec<Aexp>,
+    ) -> TotalTriple {
+        let mut loops = Vec::new();
+        collect_loops(&com, &mut loops);
+        assert_eq!(
+            loops.len(),
+            variants.len(),
+            "`{}` has {} loops but {} variants were given",
+            com,
+            loops.len(),
+            variants.len()
+        );
+        TotalTriple {
+            pre: pre.into(),
+            com,
+            post: post.into(),
+            variants,
+        }
+    }
+
+    /// Test the triple on each of `states` satisfying the precondition, as
+    /// `HoareTriple::check_on` does, with at most `fuel` loop iterations
+    /// in all. On each iteration, the loop's variant must be at least `0`
+    /// at the start and smaller at the end; an iteration ended by `break`
+    /// or an exception has no end to compare. A run whose variant fails
+    /// goes on to its end for the postcondition, so a triple that is only
+    /// partially correct is told apart from one that is wrong. A run that
+    /// reaches a procedure call or an array write fails the whole check.
+    pub fn check_on(
+        &self,
+        states: &[State],
+        fuel: u64,
+    ) -> Result<Result<TotalCheck, TripleViolation>, Unsupported> {
+        let mut loops = Vec::new();
+        collect_loops(&self.com, &mut loops);
+        let mut check = TotalCheck {
+            tested: 0,
+            skipped: 0,
+            diverged: 0,
+            correctness: Correctness::Total,
+        };
+        for (case, st) in states.iter().enumerate() {
+            if !self.pre.holds(st) {
+                check.skipped += 1;
+                continue;
+            }
+            let (last, violation) = self.run(&loops, case, st, fuel)?;
+            let failed = violation.is_some();
+            if let (Some(v), Correctness::Total) = (violation, &check.correctness) {
+                check.correctness = Correctness::Partial(v);
+            }
+            let Some(last) = last else {
+                check.diverged += usize::from(!failed);
+                continue;
+            };
+            if !self.post.holds(&last) {
+                // Shrunk as `HoareTriple::check_on` does.
+                let refuted_from = |st: &State| {
+                    let last = self.run(&loops, case, st, fuel).ok()?.0?;
+                    (self.pre.holds(st) && !self.post.holds(&last)).then_some(last)
+                };
+                let vars: Vec<String> = self.values(st).into_iter().map(|(x, _)| x).collect();
+                let st = minimize_state(st, &vars, |st| refuted_from(st).is_some());
+                let last = refuted_from(&st).expect("the run still refutes the triple");
+                return Ok(Err(TripleViolation {
+                    case,
+                    initial: self.values(&st),
+                    last: self.values(&last),
+                }));
+            }
+            check.tested += 1;
+        }
+        Ok(Ok(check))
+    }
+
+    /// The final state of the run from `st`, `None` if the fuel ran out,
+    /// and the first variant to fail on the way.
+    fn run(
+        &self,
+        loops: &[&Com],
+        case: usize,
+        st: &State,
+        fuel: u64,
+    ) -> Result<(Option<State>, Option<VariantViolation>), Unsupported> {
+        let mut run = Run {
+            triple: self,
+            loops,
+            case,
+            fuel,
+            violation: None,
+            unsupported: None,
+        };
+        let last = run.exec(st.clone(), &self.com).map(|(st, _)| st);
+        match run.unsupported {
+            Some(e) => Err(e),
+            None => Ok((last, run.violation)),
+        }
+    }
+
+    fn values(&self, st: &State) -> Vec<(String, i64)> {
+        let mut vars = self.com.vars();
+        vars.extend(self.pre.vars());
+        vars.extend(self.post.vars());
+        for v in &self.variants {
+            vars.extend(v.vars());
+        }
+        vars.into_iter().map(|x| (x.clone(), st(&x))).collect()
+    }
+}
+
+impl fmt::Display for TotalTriple {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[[{}]] {} [[{}]]", self.pre, self.com, self.post)
+    }
+}
+
+/// One run, watching the variants until one fails.
+struct Run<'a> {
+    triple: &'a TotalTriple,
+    loops: &'a [&'a Com],
+    case: usize,
+    fuel: u64,
+    violation: Option<VariantViolation>,
+    /// What stopped the run, if it wasn't the fuel running out.
+    unsupported: Option<Unsupported>,
+}
+
+impl Run<'_> {
+    /// `None` if the fuel ran out, or the run reached something
+    /// `unsupported` names.
+    fn exec(&mut self, st: State, c: &Com) -> Option<(State, Signal)> {
+        match c {
+            Com::CSkip => Some((st, Signal::Normal)),
+            Com::CBreak => Some((st, Signal::Break)),
+            Com::CContinue => Some((st, Signal::Continue)),
+            Com::CAsgn(x, a) => {
+                let n = aeval(&st, a);
+                Some((tm_update(st, x.clone(), n), Signal::Normal))
+            }
+            // Like `ceval`, `havoc` picks `0` and `print` is dropped.
+            Com::CHavoc(x) => Some((tm_update(st, x.clone(), 0), Signal::Normal)),
+            Com::CPrint(_) => Some((st, Signal::Normal)),
+            Com::CSeq(c1, c2) => match self.exec(st, c1)? {
+                (st, Signal::Normal) => self.exec(st, c2),
+                interrupted => Some(interrupted),
+            },
+            Com::CIf(b, c1, c2) => {
+                if beval(&st, b) {
+                    self.exec(st, c1)
+                } else {
+                    self.exec(st, c2)
+                }
+            }
+            Com::CWhile(b, body) => self.run_loop(c, st, b, body, None),
+            Com::CFor(init, b, update, body) => match self.exec(st, init)? {
+                (st, Signal::Normal) => self.run_loop(c, st, b, body, Some(update)),
+                interrupted => Some(interrupted),
+            },
+            Com::CPar(c1, c2) => match self.exec(st, c1)? {
+                (st, Signal::Throw(code)) => Some((st, Signal::Throw(code))),
+                (st, _) => match self.exec(st, c2)? {
+                    (st, Signal::Throw(code)) => Some((st, Signal::Throw(code))),
+                    (st, _) => Some((st, Signal::Normal)),
+                },
+            },
+            Com::CThrow(a) => {
+                let code = aeval(&st, a);
+                Some((st, Signal::Throw(code)))
+            }
+            Com::CTry(body, x, handler) => match self.exec(st, body)? {
+                (st, Signal::Throw(code)) => self.exec(tm_update(st, x.clone(), code), handler),
+                done => Some(done),
+            },
+            Com::CCall(..) => {
+                self.unsupported = Some(Unsupported("procedure calls"));
+                None
+            }
+            Com::CArrAsgn(..) => {
+                self.unsupported = Some(Unsupported("arrays"));
+                None
+            }
+        }
+    }
+
+    fn run_loop(
+        &mut self,
+        c: &Com,
+        st: State,
+        b: &Bexp,
+        body: &Com,
+        update: Option<&Com>,
+    ) -> Option<(State, Signal)> {
+        let k = self
+            .loops
+            .iter()
+            .position(|l| std::ptr::eq(*l, c))
+            .expect("every loop is in `loops`");
+        let variant = &self.triple.variants[k];
+        let mut st = st;
+        while beval(&st, b) {
+            if self.fuel == 0 {
+                return None;
+            }
+            self.fuel -= 1;
+            let before = aeval(&st, variant);
+            if before < 0 {
+                self.fail(VariantViolation::Negative {
+                    case: self.case,
+                    loop_index: k,
+                    measure: before,
+                    state: self.triple.values(&st),
+                });
+            }
+            let start = st.clone();
+            match self.exec(st, body)? {
+                (next, Signal::Normal | Signal::Continue) => st = next,
+                (next, Signal::Break) => return Some((next, Signal::Normal)),
+                thrown => return Some(thrown),
+            }
+            if let Some(update) = update {
+                match self.exec(st, update)? {
+                    (next, Signal::Normal) => st = next,
+                    (next, Signal::Break) => return Some((next, Signal::Normal)),
+                    // `continue` in the update ends it, like in the body.
+                    (next, Signal::Continue) => st = next,
+                    thrown => return Some(thrown),
+                }
+            }
+            let after = aeval(&st, variant);
+            if after >= before {
+                self.fail(VariantViolation::NotDecreasing {
+                    case: self.case,
+                    loop_index: k,
+                    before,
+                    after,
+                    state: self.triple.values(&start),
+                });
+            }
+        }
+        Some((st, Signal::Normal))
+    }
+
+    /// Keep the first violation of the run.
+    fn fail(&mut self, v: VariantViolation) {
+        self.violation.get_or_insert(v);
+    }
+}
+
+#[cfg(test)]
+mod test_imp_total {
+    use super::*;
+    use crate::imp::{parse_aexp, parse_bexp, parse_com, random_state};
+    use crate::rng::Rng;
+
+    fn states(n: usize) -> Vec<State> {
+        let mut rng = Rng::new(41);
+        (0..n)
+            .map(|_| random_state(&mut rng, &["X", "Y", "Z"]))
+            .collect()
+    }
+
+    fn triple(pre: &str, c: &str, post: &str, variants: &[&str]) -> TotalTriple {
+        TotalTriple::new(
+            parse_bexp(pre).unwrap(),
+            parse_com(c).unwrap(),
+            parse_bexp(post).unwrap(),
+            variants.iter().map(|v| parse_aexp(v).unwrap()).collect(),
+        )
+    }
+
+    #[test]
+    fn test_total() {
+        // [[0 <= X && X <= 100]] Y := 0; while ~(X = 0) do X := X - 1; Y :=
+        // Y + 1 end [[X = 0]] with variant X.
+        let t = triple(
+            "0 <= X && X <= 100",
+            "Y := 0; while ~(X = 0) do X := X - 1; Y := Y + 1 end",
+            "X = 0",
+            &["X"],
+        );
+        assert_eq!(
+            t.to_string(),
+            "[[0 <= X && X <= 100]] Y := 0; while ~(X = 0) do X := X - 1; Y := Y + 1 end \
+             [[X = 0]]"
+        );
+        let check = t.check_on(&states(300), 200).unwrap().unwrap();
+        assert_eq!(check.correctness, Correctness::Total);
+        assert!(check.tested > 0);
+
+        // Nested loops, each with its own variant; the inner one is
+        // reached afresh on each outer iteration.
+        let nested = triple(
+            "0 <= X && X <= 5",
+            "while 1 <= X do Y := X; while 1 <= Y do Y := Y - 1 end; X := X - 1 end",
+            "X = 0",
+            &["X", "Y"],
+        );
+        let check = nested.check_on(&states(300), 200).unwrap().unwrap();
+        assert_eq!(check.correctness, Correctness::Total);
+        assert!(check.tested > 0);
+    }
+
+    #[test]
+    fn test_partial_only() {
+        // X grows while it is below Z, so Z - X, not X, is the variant.
+        let c = "while X <= Z - 1 do X := X + 1 end";
+        let wrong = triple("0 <= X && X <= Z && Z <= 50", c, "X = Z", &["X"]);
+        let check = wrong.check_on(&states(300), 200).unwrap().unwrap();
+        let Correctness::Partial(v) = check.correctness else {
+            panic!("expected only partial correctness");
+        };
+        assert!(matches!(
+            v,
+            VariantViolation::NotDecreasing { loop_index: 0, .. }
+        ));
+        assert!(v.to_string().contains("of loop 0 from "));
+        // The runs still confirm the postcondition.
+        assert!(check.tested > 0);
+        let right = triple("0 <= X && X <= Z && Z <= 50", c, "X = Z", &["Z - X"]);
+        assert_eq!(
+            right
+                .check_on(&states(300), 200)
+                .unwrap()
+                .unwrap()
+                .correctness,
+            Correctness::Total
+        );
+
+        // Decreasing but not bounded below: the measure goes negative
+        // while the loop still runs.
+        let below = triple(
+            "0 <= X && X <= 50",
+            "Y := 10; while 1 <= X do X := X - 1; Y := Y - 1 end",
+            "X = 0",
+            &["Y"],
+        );
+        let check = below.check_on(&states(300), 200).unwrap().unwrap();
+        assert!(matches!(
+            check.correctness,
+            Correctness::Partial(VariantViolation::Negative { measure: -1, .. })
+        ));
+    }
+
+    #[test]
+    fn test_wrong_and_diverging() {
+        // A wrong postcondition is an error whatever the variants do.
+        let t = triple(
+            "X <= 10",
+            "while X <= 9 do X := X + 1 end",
+            "X = 11",
+            &["10 - X"],
+        );
+        let violation = t.check_on(&states(300), 100).unwrap().unwrap_err();
+        assert_eq!(violation.initial, [("X".to_string(), 0)]);
+        // A run out of fuel with its variants still decreasing says
+        // nothing either way.
+        let long = triple(
+            "100 <= X",
+            "while 1 <= X do X := X - 1 end",
+            "false",
+            &["X"],
+        );
+        let check = long.check_on(&states(300), 10).unwrap().unwrap();
+        assert_eq!(check.correctness, Correctness::Total);
+        assert_eq!(check.tested, 0);
+        assert!(check.diverged > 0);
+        // One whose variant has already failed doesn't terminate as far
+        // as the check can tell.
+        let spin = triple("true", "while true do skip end", "false", &["0"]);
+        let check = spin.check_on(&states(3), 10).unwrap().unwrap();
+        assert!(matches!(check.correctness, Correctness::Partial(_)));
+        assert_eq!(check.diverged, 0);
+    }
+
+    #[test]
+    fn test_calls_are_unsupported() {
+        let t = triple("true", "while 1 <= X do X := dec(X) end", "X <= 0", &["X"]);
+        assert_eq!(
+            t.check_on(&states(10), 100).err(),
+            Some(Unsupported("procedure calls"))
+        );
+    }
+}
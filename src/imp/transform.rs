@@ -0,0 +1,298 @@
+//! Bottom-up rewriting of Imp programs, generalizing `optimize_0plus` from
+//! the Imp chapter. A `Transform` says how to rewrite a single node whose
+//! children have already been rewritten; the provided `transform_*`
+//! methods do the traversal.
+
+use std::fmt;
+
+use super::{ceval_fuel, Aexp, Bexp, Com, Unsupported};
+use crate::state::{State, StateExt};
+
+pub trait Transform {
+    /// Rewrite one arithmetic node. The default leaves it alone.
+    fn rewrite_aexp(&self, a: Aexp) -> Aexp {
+        a
+    }
+
+    /// Rewrite one boolean node. The default leaves it alone.
+    fn rewrite_bexp(&self, b: Bexp) -> Bexp {
+        b
+    }
+
+    /// Rewrite one command node. The default leaves it alone.
+    fn rewrite_com(&self, c: Com) -> Com {
+        c
+    }
+
+    fn transform_aexp(&self, a: &Aexp) -> Aexp {
+        let a = match a {
+            Aexp::ANum(_) | Aexp::AId(_) => a.clone(),
+            Aexp::APlus(a1, a2) => Aexp::plus(self.transform_aexp(a1), self.transform_aexp(a2)),
+            Aexp::AMinus(a1, a2) => Aexp::minus(self.transform_aexp(a1), self.transform_aexp(a2)),
+            Aexp::AMult(a1, a2) => Aexp::mult(self.transform_aexp(a1), self.transform_aexp(a2)),
+            Aexp::AIndex(x, i) => Aexp::AIndex(x.clone(), Box::new(self.transform_aexp(i))),
+        };
+        self.rewrite_aexp(a)
+    }
+
+    fn transform_bexp(&self, b: &Bexp) -> Bexp {
+        let b = match b {
+            Bexp::BTrue | Bexp::BFalse => b.clone(),
+            Bexp::BEq(a1, a2) => Bexp::eq(self.transform_aexp(a1), self.transform_aexp(a2)),
+            Bexp::BLe(a1, a2) => Bexp::le(self.transform_aexp(a1), self.transform_aexp(a2)),
+            Bexp::BNot(b1) => Bexp::not(self.transform_bexp(b1)),
+            Bexp::BAnd(b1, b2) => Bexp::and(self.transform_bexp(b1), self.transform_bexp(b2)),
+        };
+        self.rewrite_bexp(b)
+    }
+
+    fn transform_com(&self, c: &Com) -> Com {
+        let c = match c {
+            Com::CSkip | Com::CBreak | Com::CContinue | Com::CHavoc(_) => c.clone(),
+            Com::CAsgn(x, a) => Com::CAsgn(x.clone(), self.transform_aexp(a)),
+            Com::CArrAsgn(x, i, a) => {
+                Com::CArrAsgn(x.clone(), self.transform_aexp(i), self.transform_aexp(a))
+            }
+            Com::CPrint(a) => Com::CPrint(self.transform_aexp(a)),
+            Com::CThrow(a) => Com::CThrow(self.transform_aexp(a)),
+            Com::CSeq(c1, c2) => Com::seq(self.transform_com(c1), self.transform_com(c2)),
+            Com::CIf(b, c1, c2) => Com::if_(
+                self.transform_bexp(b),
+                self.transform_com(c1),
+                self.transform_com(c2),
+            ),
+            Com::CWhile(b, body) => Com::while_(self.transform_bexp(b), self.transform_com(body)),
+            Com::CFor(init, b, update, body) => Com::for_(
+                self.transform_com(init),
+                self.transform_bexp(b),
+                self.transform_com(update),
+                self.transform_com(body),
+            ),
+            Com::CPar(c1, c2) => Com::par(self.transform_com(c1), self.transform_com(c2)),
+            Com::CTry(body, x, handler) => {
+                Com::try_(self.transform_com(body), x, self.transform_com(handler))
+            }
+            Com::CCall(x, f, args) => Com::CCall(
+                x.clone(),
+                f.clone(),
+                args.iter().map(|a| self.transform_aexp(a)).collect(),
+            ),
+        };
+        self.rewrite_com(c)
+    }
+}
+
+/// Running two transforms in one pass: at every node, the first rewrite and
+/// then the second.
+impl<T1: Transform, T2: Transform> Transform for (T1, T2) {
+    fn rewrite_aexp(&self, a: Aexp) -> Aexp {
+        self.1.rewrite_aexp(self.0.rewrite_aexp(a))
+    }
+
+    fn rewrite_bexp(&self, b: Bexp) -> Bexp {
+        self.1.rewrite_bexp(self.0.rewrite_bexp(b))
+    }
+
+    fn rewrite_com(&self, c: Com) -> Com {
+        self.1.rewrite_com(self.0.rewrite_com(c))
+    }
+}
+
+/// `0 + a` becomes `a`.
+pub struct Optimize0Plus;
+
+impl Transform for Optimize0Plus {
+    fn rewrite_aexp(&self, a: Aexp) -> Aexp {
+        match a {
+            Aexp::APlus(a1, a2) if *a1 == Aexp::ANum(0) => *a2,
+            a => a,
+        }
+    }
+}
+
+/// `1 * a` and `a * 1` become `a`.
+pub struct OptimizeMult1;
+
+impl Transform for OptimizeMult1 {
+    fn rewrite_aexp(&self, a: Aexp) -> Aexp {
+        match a {
+            Aexp::AMult(a1, a2) if *a1 == Aexp::ANum(1) => *a2,
+            Aexp::AMult(a1, a2) if *a2 == Aexp::ANum(1) => *a1,
+            a => a,
+        }
+    }
+}
+
+/// A program and a starting state on which a transform changed the result,
+/// given as indices into the samples.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Unsound {
+    pub program: usize,
+    pub state: usize,
+    /// The starting state, shown on the variables either program mentions.
+    pub initial: String,
+    pub var: String,
+    /// The final values of `var`, or `None` for a run that ran out of fuel.
+    pub expected: Option<i64>,
+    pub found: Option<i64>,
+}
+
+impl fmt::Display for Unsound {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let show = |v: Option<i64>| v.map_or("no result".to_string(), |n| n.to_string());
+        write!(
+            f,
+            "program #{} from state #{} ({}) ends with {} = {} after the transform instead of {}",
+            self.program,
+            self.state,
+            self.initial,
+            self.var,
+            show(self.found),
+            show(self.expected)
+        )
+    }
+}
+
+/// Check that `t` preserves the meaning of each program: running the
+/// original and the transformed program from each sample state (with at
+/// most `fuel` loop iterations) must end with the same values for every
+/// variable either program mentions. If neither run finishes, they agree.
+/// A run that reaches a procedure call fails the whole check.
+pub fn check_sound(
+    t: &impl Transform,
+    programs: &[Com],
+    states: &[State],
+    fuel: u64,
+) -> Result<Result<(), Unsound>, Unsupported> {
+    for (p, c) in programs.iter().enumerate() {
+        let transformed = t.transform_com(c);
+        let mut vars = c.vars();
+        vars.extend(transformed.vars());
+        let vars: Vec<String> = vars.into_iter().collect();
+        for (i, st) in states.iter().enumerate() {
+            let expected = ceval_fuel(st.clone(), c, fuel)?;
+            let found = ceval_fuel(st.clone(), &transformed, fuel)?;
+            for x in &vars {
+                let expected = expected.as_ref().map(|st| st(x));
+                let found = found.as_ref().map(|st| st(x));
+                if expected != found {
+                    return Ok(Err(Unsound {
+                        program: p,
+                        state: i,
+                        initial: st.show(&vars),
+                        var: x.clone(),
+                        expected,
+                        found,
+                    }));
+                }
+            }
+        }
+    }
+    Ok(Ok(()))
+}
+
+#[cfg(test)]
+mod test_imp_transform {
+    use super::*;
+    use crate::imp::{parse_aexp, parse_com};
+    use crate::state;
+
+    #[test]
+    fn test_optimize_0plus() {
+        // From the book: 2 + (0 + (0 + 1)) becomes 2 + 1.
+        let a = parse_aexp("2 + (0 + (0 + 1))").unwrap();
+        assert_eq!(
+            Optimize0Plus.transform_aexp(&a),
+            parse_aexp("2 + 1").unwrap()
+        );
+        let c = parse_com("while ~(X = 0 + 0) do X := 0 + X - 1 end").unwrap();
+        assert_eq!(
+            Optimize0Plus.transform_com(&c),
+            parse_com("while ~(X = 0) do X := X - 1 end").unwrap()
+        );
+    }
+
+    #[test]
+    fn test_optimize_mult_1() {
+        let a = parse_aexp("1 * X + Y * (1 * 1)").unwrap();
+        assert_eq!(
+            OptimizeMult1.transform_aexp(&a),
+            parse_aexp("X + Y").unwrap()
+        );
+    }
+
+    #[test]
+    fn test_pair_runs_both() {
+        let a = parse_aexp("0 + 1 * X").unwrap();
+        assert_eq!(
+            (OptimizeMult1, Optimize0Plus).transform_aexp(&a),
+            Aexp::var("X")
+        );
+        // Either order works: 1 * X is rewritten before its parent 0 + _.
+        assert_eq!(
+            (Optimize0Plus, OptimizeMult1).transform_aexp(&a),
+            Aexp::var("X")
+        );
+    }
+
+    fn samples() -> (Vec<Com>, Vec<State>) {
+        let programs = [
+            "Z := 0 + X; Y := 1 * 1; while ~(Z = 0) do Y := Y * (1 * Z); Z := Z - 1 end",
+            "if 0 + X <= 1 * 3 then Y := 0 + 0 else Y := X * 1 end",
+        ]
+        .iter()
+        .map(|s| parse_com(s).unwrap())
+        .collect();
+        let states = (0..6).map(|n| state! {"X" => n, "Y" => 7}).collect();
+        (programs, states)
+    }
+
+    #[test]
+    fn test_optimizations_are_sound() {
+        let (programs, states) = samples();
+        assert_eq!(
+            check_sound(&Optimize0Plus, &programs, &states, 100).unwrap(),
+            Ok(())
+        );
+        assert_eq!(
+            check_sound(&OptimizeMult1, &programs, &states, 100).unwrap(),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn test_unsound_transform_is_caught() {
+        // Dropping the left operand of every product is wrong.
+        struct DropFactor;
+        impl Transform for DropFactor {
+            fn rewrite_aexp(&self, a: Aexp) -> Aexp {
+                match a {
+                    Aexp::AMult(_, a2) => *a2,
+                    a => a,
+                }
+            }
+        }
+        let (programs, states) = samples();
+        let err = check_sound(&DropFactor, &programs, &states, 100)
+            .unwrap()
+            .unwrap_err();
+        assert_eq!((err.program, err.state), (0, 2));
+        assert_eq!(err.var, "Y");
+        assert_eq!((err.expected, err.found), (Some(2), Some(1)));
+        assert_eq!(
+            err.to_string(),
+            "program #0 from state #2 (X=2, Y=7, Z=0) ends with Y = 1 after the transform \
+             instead of 2"
+        );
+    }
+
+    #[test]
+    fn test_calls_are_unsupported() {
+        let (_, states) = samples();
+        let programs = [parse_com("Y := f(X)").unwrap()];
+        assert_eq!(
+            check_sound(&Optimize0Plus, &programs, &states, 100).err(),
+            Some(Unsupported("procedure calls"))
+        );
+    }
+}
@@ -0,0 +1,391 @@
+//! Weakest preconditions, from Hoare2: `wp c Q` is the assertion that holds
+//! of exactly the states from which `c`, if it finishes, finishes in one
+//! satisfying `Q`. Without loops it is computed backwards, one command at
+//! a time: an assignment `X := a` substitutes `a` for `X` in `Q`, and an
+//! `if` takes each branch's under the test that picks it. A loop's has no
+//! formula in general, so it takes an invariant from the user and leaves
+//! side conditions that make the invariant do.
+//!
+//! As in `ceval`, a `break` or `continue` outside any loop and an uncaught
+//! `throw` end the program, so what they need is `Q`; a `print` changes
+//! nothing `Q` can say.
+
+use std::ptr;
+use std::rc::Rc;
+
+use super::{ceval, Assertion, Com, Formula, Implication, Unsupported};
+
+/// The weakest precondition of `c` for `post`, for a `c` without loops.
+/// For a formula it is a formula, found syntactically; a predicate can
+/// only be carried back semantically, by running `c`.
+///
+/// Panics if `c` has a loop; see `wp_with_invariants`. Neither kind of
+/// assertion can be carried back over `havoc`, `par`, procedure calls or
+/// array writes, so those are an error.
+pub fn wp(c: &Com, post: &Assertion) -> Result<Assertion, Unsupported> {
+    assert!(
+        loop_free(c),
+        "wp needs an invariant for each loop of `{}`",
+        c
+    );
+    Ok(match post {
+        Assertion::Formula(q) => Assertion::Formula(wp_with_invariants(c, q, &[])?.0),
+        Assertion::Pred(name, q) => {
+            // Whatever `ceval` can't run, a formula can't be carried back
+            // over either, so this rules out what would fail below.
+            wp_with_invariants(c, &Formula::FTrue, &[])?;
+            let (c, q) = (c.clone(), q.clone());
+            let name = format!("wp({}, {})", c, name);
+            Assertion::Pred(
+                name,
+                Rc::new(move |st| q(&ceval(st.clone(), &c).expect("`c` has no calls"))),
+            )
+        }
+    })
+}
+
+/// The precondition of `c` for `post`, given an invariant for each loop
+/// in the order they start in `c`, and the side conditions that make it
+/// one: for each loop with invariant `I` and test `b`, that `I && b`
+/// implies what the body needs to leave `I` true, and `I && ~b` what is
+/// needed after the loop. A loop's precondition is its invariant, so the
+/// result is only as weak as the invariants are good, but if the side
+/// conditions are valid, `{{pre}} c {{post}}` holds.
+///
+/// Panics unless there is exactly one invariant per loop, and fails on
+/// what `wp` does.
+pub fn wp_with_invariants(
+    c: &Com,
+    post: &Formula,
+    invariants: &[Formula],
+) -> Result<(Formula, Vec<Implication>), Unsupported> {
+    let mut loops = Vec::new();
+    collect_loops(c, &mut loops);
+    assert_eq!(
+        loops.len(),
+        invariants.len(),
+        "`{}` has {} loops but {} invariants were given",
+        c,
+        loops.len(),
+        invariants.len()
+    );
+    let mut calc = Calc {
+        invariants: loops.into_iter().zip(invariants).collect(),
+        side: Vec::new(),
+        unsupported: None,
+    };
+    let exits = Exits {
+        brk: post.clone(),
+        cont: post.clone(),
+        throw: None,
+        end: post.clone(),
+    };
+    let pre = calc.wp(c, post.clone(), &exits);
+    match calc.unsupported {
+        Some(e) => Err(e),
+        None => Ok((pre, calc.side)),
+    }
+}
+
+/// Whether `c` has no loops.
+pub(super) fn loop_free(c: &Com) -> bool {
+    let mut loops = Vec::new();
+    collect_loops(c, &mut loops);
+    loops.is_empty()
+}
+
+/// The loops of `c`, each before those inside it and those after it.
+pub(super) fn collect_loops<'a>(c: &'a Com, loops: &mut Vec<&'a Com>) {
+    match c {
+        Com::CWhile(_, body) => {
+            loops.push(c);
+            collect_loops(body, loops);
+        }
+        Com::CFor(init, _, update, body) => {
+            loops.push(c);
+            collect_loops(init, loops);
+            collect_loops(body, loops);
+            collect_loops(update, loops);
+        }
+        Com::CSeq(c1, c2) | Com::CIf(_, c1, c2) | Com::CPar(c1, c2) | Com::CTry(c1, _, c2) => {
+            collect_loops(c1, loops);
+            collect_loops(c2, loops);
+        }
+        _ => {}
+    }
+}
+
+/// What must hold when a command leaves other than by finishing: by
+/// `break`, `continue` or `throw`, or by a `break` or `continue` outside
+/// any loop, which ends the program. A caught `throw` needs the handler's
+/// precondition with the code in the handler's variable.
+#[derive(Clone)]
+struct Exits {
+    brk: Formula,
+    cont: Formula,
+    throw: Option<(String, Formula)>,
+    end: Formula,
+}
+
+struct Calc<'a> {
+    invariants: Vec<(&'a Com, &'a Formula)>,
+    side: Vec<Implication>,
+    /// The first command found that nothing can be carried back over.
+    unsupported: Option<Unsupported>,
+}
+
+impl<'a> Calc<'a> {
+    fn wp(&mut self, c: &'a Com, q: Formula, exits: &Exits) -> Formula {
+        match c {
+            Com::CSkip | Com::CPrint(_) => q,
+            Com::CAsgn(x, a) => q.subst(x, a),
+            Com::CBreak => exits.brk.clone(),
+            Com::CContinue => exits.cont.clone(),
+            Com::CThrow(a) => match &exits.throw {
+                Some((x, handler)) => handler.subst(x, a),
+                None => exits.end.clone(),
+            },
+            Com::CSeq(c1, c2) => {
+                let q = self.wp(c2, q, exits);
+                self.wp(c1, q, exits)
+            }
+            Com::CIf(b, c1, c2) => {
+                let b = Formula::from(b);
+                let p1 = self.wp(c1, q.clone(), exits);
+                let p2 = self.wp(c2, q, exits);
+                Formula::and(
+                    Formula::implies(b.clone(), p1),
+                    Formula::implies(Formula::not(b), p2),
+                )
+            }
+            Com::CTry(body, x, handler) => {
+                let caught = self.wp(handler, q.clone(), exits);
+                let exits = Exits {
+                    throw: Some((x.clone(), caught)),
+                    ..exits.clone()
+                };
+                self.wp(body, q, &exits)
+            }
+            Com::CWhile(b, body) => self.run_loop(c, &Formula::from(b), body, None, q, exits),
+            Com::CFor(init, b, update, body) => {
+                let inv = self.run_loop(c, &Formula::from(b), body, Some(update), q, exits);
+                self.wp(init, inv, exits)
+            }
+            Com::CHavoc(_) => {
+                self.unsupported.get_or_insert(Unsupported("havoc"));
+                q
+            }
+            Com::CPar(..) => {
+                self.unsupported.get_or_insert(Unsupported("par"));
+                q
+            }
+            Com::CCall(..) => {
+                self.unsupported
+                    .get_or_insert(Unsupported("procedure calls"));
+                q
+            }
+            Com::CArrAsgn(..) => {
+                self.unsupported.get_or_insert(Unsupported("arrays"));
+                q
+            }
+        }
+    }
+
+    /// The invariant of the loop `c`, recording its side conditions.
+    fn run_loop(
+        &mut self,
+        c: &'a Com,
+        b: &Formula,
+        body: &'a Com,
+        update: Option<&'a Com>,
+        q: Formula,
+        exits: &Exits,
+    ) -> Formula {
+        let inv = self.invariant(c);
+        let inner = Exits {
+            brk: q.clone(),
+            cont: inv.clone(),
+            ..exits.clone()
+        };
+        let after_body = match update {
+            Some(update) => self.wp(update, inv.clone(), &inner),
+            None => inv.clone(),
+        };
+        let inner = Exits {
+            cont: after_body.clone(),
+            ..inner
+        };
+        let body_pre = self.wp(body, after_body, &inner);
+        self.side.push(Implication::new(
+            Formula::and(inv.clone(), b.clone()),
+            body_pre,
+        ));
+        self.side.push(Implication::new(
+            Formula::and(inv.clone(), Formula::not(b.clone())),
+            q,
+        ));
+        inv
+    }
+
+    fn invariant(&self, c: &Com) -> Formula {
+        let (_, inv) = self
+            .invariants
+            .iter()
+            .find(|(l, _)| ptr::eq(*l, c))
+            .expect("every loop was given an invariant");
+        (*inv).clone()
+    }
+}
+
+#[cfg(test)]
+mod test_imp_wp {
+    use super::*;
+    use crate::imp::{
+        gen_random_com, parse_bexp, parse_com, random_bexp, random_state, HoareTriple,
+    };
+    use crate::rng::Rng;
+    use crate::state;
+    use crate::state::{empty_state, lookup, State};
+
+    fn formula(src: &str) -> Formula {
+        Formula::from(parse_bexp(src).unwrap())
+    }
+
+    fn implies(p: &str, q: &str) -> Formula {
+        Formula::implies(formula(p), formula(q))
+    }
+
+    fn wp_of(c: &str, post: &str) -> String {
+        let pre = wp(&parse_com(c).unwrap(), &formula(post).into()).unwrap();
+        pre.to_string()
+    }
+
+    fn states(seed: u64, n: usize) -> Vec<State> {
+        let mut rng = Rng::new(seed);
+        (0..n)
+            .map(|_| random_state(&mut rng, &["X", "Y", "Z", "N"]))
+            .collect()
+    }
+
+    #[test]
+    fn test_hoare2_examples() {
+        assert_eq!(wp_of("X := X + 1", "X <= 5"), "X + 1 <= 5");
+        assert_eq!(wp_of("Y := X; X := Y * 2", "X = 4"), "X * 2 = 4");
+        assert_eq!(
+            wp_of("if X <= Y then Z := Y else Z := X end", "X <= Z"),
+            "(X <= Y -> X <= Y) && (~(X <= Y) -> X <= X)"
+        );
+        assert_eq!(wp_of("skip; print X", "Y = 1"), "Y = 1");
+    }
+
+    #[test]
+    fn test_exits() {
+        // A caught throw lands in the handler with its code in E.
+        assert_eq!(
+            wp_of("try throw X + 1; Y := 5 catch E do Y := E end", "Y = 3"),
+            "X + 1 = 3"
+        );
+        // A break outside any loop, and an uncaught throw, end the
+        // program where they are.
+        assert_eq!(wp_of("X := 1; break; X := 2", "X = 1"), "1 = 1");
+        assert_eq!(wp_of("Y := X; throw 0; Y := 2", "Y = 1"), "X = 1");
+    }
+
+    /// Without loops, `wp c Q` holds exactly where running `c` leads to
+    /// `Q`.
+    #[test]
+    fn test_wp_is_weakest() {
+        let mut rng = Rng::new(17);
+        let mut checked = 0;
+        for _ in 0..300 {
+            let c = gen_random_com(6, &["X", "Y"], &mut rng);
+            if !loop_free(&c) {
+                continue;
+            }
+            let q = Formula::from(random_bexp(&mut rng, 3, &["X", "Y"]));
+            let pre = wp(&c, &q.clone().into()).unwrap();
+            for _ in 0..5 {
+                let st = random_state(&mut rng, &["X", "Y"]);
+                let after = ceval(st.clone(), &c).unwrap();
+                assert_eq!(pre.holds(&st), q.holds(&after), "wp({}, {})", c, q);
+                checked += 1;
+            }
+        }
+        assert!(checked > 200, "only {} checked", checked);
+    }
+
+    #[test]
+    fn test_predicates() {
+        let c = parse_com("Z := X; X := Y; Y := Z").unwrap();
+        let post = Assertion::pred("X < Y", |st| lookup(st, "X") < lookup(st, "Y"));
+        let pre = wp(&c, &post).unwrap();
+        assert_eq!(pre.to_string(), "wp(Z := X; X := Y; Y := Z, X < Y)");
+        for st in states(5, 50) {
+            assert_eq!(pre.holds(&st), lookup(&st, "Y") < lookup(&st, "X"));
+        }
+    }
+
+    #[test]
+    fn test_loops_take_invariants() {
+        let c =
+            parse_com("Y := 0; while ~(X = 0) do X := X - 1; Y := Y + 1; if Y = 10 then break else skip end end")
+                .unwrap();
+        let post = Formula::or(formula("Y = N"), formula("Y = 10"));
+        let inv = formula("X + Y = N");
+        let (pre, side) = wp_with_invariants(&c, &post, &[inv]).unwrap();
+        assert_eq!(pre.to_string(), "X + 0 = N");
+        assert_eq!(
+            side.iter().map(|s| s.to_string()).collect::<Vec<_>>(),
+            [
+                "X + Y = N && ~(X = 0) ->> \
+                 (Y + 1 = 10 -> (Y + 1 = N || Y + 1 = 10)) && \
+                 (~(Y + 1 = 10) -> X - 1 + (Y + 1) = N)",
+                "X + Y = N && ~~(X = 0) ->> Y = N || Y = 10",
+            ]
+        );
+        for st in states(9, 200) {
+            assert!(side.iter().all(|s| s.holds(&st)));
+        }
+        // The side conditions are met, so the triple holds.
+        let triple = HoareTriple::new(pre, c, post);
+        assert!(triple.check_on(&states(9, 200), 50).unwrap().is_ok());
+
+        // A for loop's update runs after each iteration, and a continue
+        // goes to it.
+        let c = parse_com(
+            "C := 0; for I := 0; I <= 3; I := I + 1 do if I = 2 then continue else skip end; \
+             C := C + 1 end",
+        )
+        .unwrap();
+        let inv = Formula::and(
+            Formula::and(implies("3 <= I", "C = I - 1"), implies("I <= 2", "C = I")),
+            formula("I <= 4"),
+        );
+        let (pre, side) = wp_with_invariants(&c, &formula("C = 3"), &[inv]).unwrap();
+        assert!(pre.holds(&empty_state()));
+        let mut rng = Rng::new(4);
+        for _ in 0..500 {
+            let st = random_state(&mut rng, &["C", "I"]);
+            assert!(side.iter().all(|s| s.holds(&st)));
+        }
+        // Too weak an invariant leaves a side condition that fails.
+        let (_, side) = wp_with_invariants(&c, &formula("C = 3"), &[formula("I <= 4")]).unwrap();
+        assert!(!side[1].holds(&state! {"I" => 4}));
+    }
+
+    #[test]
+    fn test_havoc_and_par_are_unsupported() {
+        let post = Assertion::from(Formula::FTrue);
+        let c = parse_com("havoc X").unwrap();
+        assert_eq!(wp(&c, &post).err(), Some(Unsupported("havoc")));
+        let c = parse_com("par X := 1 with X := 2 end").unwrap();
+        assert_eq!(wp(&c, &post).err(), Some(Unsupported("par")));
+    }
+
+    #[test]
+    #[should_panic(expected = "has 1 loops but 0 invariants")]
+    fn test_missing_invariant() {
+        let c = parse_com("while true do skip end").unwrap();
+        wp_with_invariants(&c, &Formula::FTrue, &[]).unwrap();
+    }
+}
@@ -0,0 +1,255 @@
+//! The untyped lambda calculus, which the Stlc chapter puts types on:
+//! variables, abstractions `\x. t` and applications `t1 t2`, and nothing
+//! else. Church numerals and booleans (see `crate::church` for them as
+//! Rust closures) can all be written as terms.
+//!
+//! Terms use names, not indices, so substitution has to rename a binder
+//! that would capture a free variable of what is substituted in; `subst`
+//! does, with names from `fresh`.
+
+use std::collections::BTreeSet;
+
+mod debruijn;
+mod parser;
+mod pretty;
+mod reduce;
+
+pub use debruijn::{alpha_eq, beta, from_debruijn, shift, subst_index, to_debruijn, DbTerm};
+pub use parser::{parse_term, ParseError, Token};
+pub use reduce::{eval_cbv, is_value, normalize, step_cbv, step_normal_order};
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Term {
+    Var(String),
+    /// `\x. t`.
+    Abs(String, Box<Term>),
+    App(Box<Term>, Box<Term>),
+}
+
+impl Term {
+    pub fn var(x: &str) -> Term {
+        Term::Var(x.to_string())
+    }
+
+    pub fn abs(x: &str, t: Term) -> Term {
+        Term::Abs(x.to_string(), Box::new(t))
+    }
+
+    pub fn app(t1: Term, t2: Term) -> Term {
+        Term::App(Box::new(t1), Box::new(t2))
+    }
+
+    /// `\x1. \x2. ... t`.
+    pub fn abs_many(xs: &[&str], t: Term) -> Term {
+        xs.iter().rev().fold(t, |t, x| Term::abs(x, t))
+    }
+
+    /// `t t1 t2 ...`, applying to the arguments in turn.
+    pub fn app_many(t: Term, args: impl IntoIterator<Item = Term>) -> Term {
+        args.into_iter().fold(t, Term::app)
+    }
+
+    /// The variables occurring free in the term.
+    pub fn free_vars(&self) -> BTreeSet<String> {
+        let mut vars = BTreeSet::new();
+        self.collect_free(&mut Vec::new(), &mut vars);
+        vars
+    }
+
+    fn collect_free<'a>(&'a self, bound: &mut Vec<&'a str>, vars: &mut BTreeSet<String>) {
+        match self {
+            Term::Var(x) => {
+                if !bound.contains(&x.as_str()) {
+                    vars.insert(x.clone());
+                }
+            }
+            Term::Abs(x, t) => {
+                bound.push(x);
+                t.collect_free(bound, vars);
+                bound.pop();
+            }
+            Term::App(t1, t2) => {
+                t1.collect_free(bound, vars);
+                t2.collect_free(bound, vars);
+            }
+        }
+    }
+
+    /// Whether the term has no free variables.
+    pub fn is_closed(&self) -> bool {
+        self.free_vars().is_empty()
+    }
+}
+
+/// An endless supply of names made from `base` by numbering it: `x0`,
+/// `x1`, and so on for a base of `x`. Digits already ending the base are
+/// dropped first, so renaming `x0` again gives `x1`, not `x00`.
+#[derive(Debug, Clone)]
+pub struct Fresh {
+    base: String,
+    next: usize,
+}
+
+impl Fresh {
+    pub fn new(base: &str) -> Fresh {
+        Fresh {
+            base: base
+                .trim_end_matches(|c: char| c.is_ascii_digit())
+                .to_string(),
+            next: 0,
+        }
+    }
+}
+
+impl Iterator for Fresh {
+    type Item = String;
+
+    fn next(&mut self) -> Option<String> {
+        let name = format!("{}{}", self.base, self.next);
+        self.next += 1;
+        Some(name)
+    }
+}
+
+/// The first name from `Fresh::new(x)` that isn't in `avoid`.
+pub fn fresh(x: &str, avoid: &BTreeSet<String>) -> String {
+    Fresh::new(x)
+        .find(|y| !avoid.contains(y))
+        .expect("the supply is endless")
+}
+
+/// `[x:=s] t`: `t` with its free occurrences of `x` replaced by `s`. A
+/// binder of `t` around an occurrence of `x` that would capture a free
+/// variable of `s` is renamed first, to a name from `fresh`, so the
+/// result means what it should for every `s`, closed or not.
+pub fn subst(t: &Term, x: &str, s: &Term) -> Term {
+    subst_free(t, x, s, &s.free_vars())
+}
+
+/// `subst`, with the free variables of `s` worked out once.
+fn subst_free(t: &Term, x: &str, s: &Term, free_in_s: &BTreeSet<String>) -> Term {
+    match t {
+        Term::Var(y) if y == x => s.clone(),
+        Term::Var(_) => t.clone(),
+        Term::App(t1, t2) => Term::app(
+            subst_free(t1, x, s, free_in_s),
+            subst_free(t2, x, s, free_in_s),
+        ),
+        // `x` is shadowed, so none of its occurrences inside are free.
+        Term::Abs(y, _) if y == x => t.clone(),
+        Term::Abs(y, body) => {
+            let free_in_body = body.free_vars();
+            if !free_in_body.contains(x) {
+                t.clone()
+            } else if !free_in_s.contains(y) {
+                Term::abs(y, subst_free(body, x, s, free_in_s))
+            } else {
+                let mut avoid = free_in_body;
+                avoid.extend(free_in_s.iter().cloned());
+                avoid.insert(x.to_string());
+                let z = fresh(y, &avoid);
+                let body = subst(body, y, &Term::var(&z));
+                Term::abs(&z, subst_free(&body, x, s, free_in_s))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test_lambda {
+    use super::*;
+
+    fn v(x: &str) -> Term {
+        Term::var(x)
+    }
+
+    fn set(xs: &[&str]) -> BTreeSet<String> {
+        xs.iter().map(|x| x.to_string()).collect()
+    }
+
+    #[test]
+    fn test_free_vars() {
+        // \x. x y (\y. y z)
+        let t = Term::abs(
+            "x",
+            Term::app_many(v("x"), [v("y"), Term::abs("y", Term::app(v("y"), v("z")))]),
+        );
+        assert_eq!(t.free_vars(), set(&["y", "z"]));
+        assert!(!t.is_closed());
+        assert!(Term::abs_many(&["x", "y"], Term::app(v("y"), v("x"))).is_closed());
+    }
+
+    #[test]
+    fn test_fresh() {
+        let names: Vec<String> = Fresh::new("x").take(3).collect();
+        assert_eq!(names, ["x0", "x1", "x2"]);
+        assert_eq!(fresh("y", &set(&["y", "y0"])), "y1");
+        assert_eq!(fresh("y12", &set(&["y0"])), "y1");
+    }
+
+    #[test]
+    fn test_subst_simple() {
+        // [x:=z] (x (\y. x y)) = z (\y. z y)
+        let t = Term::app(v("x"), Term::abs("y", Term::app(v("x"), v("y"))));
+        assert_eq!(
+            subst(&t, "x", &v("z")),
+            Term::app(v("z"), Term::abs("y", Term::app(v("z"), v("y"))))
+        );
+        // Other variables are left alone.
+        assert_eq!(subst(&v("y"), "x", &v("z")), v("y"));
+    }
+
+    #[test]
+    fn test_subst_shadowed() {
+        // [x:=y] (\x. x) = \x. x: the `x` inside is bound, not free.
+        let id = Term::abs("x", v("x"));
+        assert_eq!(subst(&id, "x", &v("y")), id);
+        // [x:=y] (x (\x. x)) = y (\x. x)
+        let t = Term::app(v("x"), id.clone());
+        assert_eq!(subst(&t, "x", &v("y")), Term::app(v("y"), id));
+    }
+
+    #[test]
+    fn test_subst_avoids_capture() {
+        // [x:=y] (\y. x) is \y0. y, not the constant function \y. y.
+        let t = Term::abs("y", v("x"));
+        assert_eq!(subst(&t, "x", &v("y")), Term::abs("y0", v("y")));
+        // The new name avoids the free variables of both sides too:
+        // [x:=y y0] (\y. x y1 y) = \y2. (y y0) y1 y2.
+        let t = Term::abs("y", Term::app_many(v("x"), [v("y1"), v("y")]));
+        let s = Term::app(v("y"), v("y0"));
+        assert_eq!(
+            subst(&t, "x", &s),
+            Term::abs("y2", Term::app_many(s.clone(), [v("y1"), v("y2")]))
+        );
+        // A binder that doesn't enclose `x` needn't be renamed.
+        let t = Term::app(Term::abs("y", v("y")), v("x"));
+        assert_eq!(
+            subst(&t, "x", &v("y")),
+            Term::app(Term::abs("y", v("y")), v("y"))
+        );
+    }
+
+    #[test]
+    fn test_subst_nested_capture() {
+        // [x:=y] (\y. \y0. x y y0): renaming `y` to `y0` means renaming
+        // the inner `y0` out of the way as well, giving
+        // \y0. \y1. y y0 y1.
+        let t = Term::abs_many(&["y", "y0"], Term::app_many(v("x"), [v("y"), v("y0")]));
+        assert_eq!(
+            subst(&t, "x", &v("y")),
+            Term::abs_many(&["y0", "y1"], Term::app_many(v("y"), [v("y0"), v("y1")]))
+        );
+    }
+
+    #[test]
+    fn test_subst_closed_term() {
+        // Substituting a closed term never renames anything.
+        let id = Term::abs("y", v("y"));
+        let t = Term::abs_many(&["y", "z"], Term::app_many(v("x"), [v("y"), v("z")]));
+        assert_eq!(
+            subst(&t, "x", &id),
+            Term::abs_many(&["y", "z"], Term::app_many(id.clone(), [v("y"), v("z")]))
+        );
+    }
+}
@@ -0,0 +1,268 @@
+//! De Bruijn terms: a variable is the number of binders between it and
+//! its own, so `\x. \y. x` is `\. \. 1`, and terms that differ only in the
+//! names of their bound variables are the same term. The operations are
+//! those of TAPL's chapter 6: shifting the free indices of a term, and
+//! substituting for an index.
+//!
+//! A free variable is numbered past every binder around it, by its place
+//! in a list of names given for the free variables, the context.
+
+use std::collections::BTreeSet;
+
+use super::{fresh, Term};
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum DbTerm {
+    Var(usize),
+    Abs(Box<DbTerm>),
+    App(Box<DbTerm>, Box<DbTerm>),
+}
+
+impl DbTerm {
+    pub fn abs(t: DbTerm) -> DbTerm {
+        DbTerm::Abs(Box::new(t))
+    }
+
+    pub fn app(t1: DbTerm, t2: DbTerm) -> DbTerm {
+        DbTerm::App(Box::new(t1), Box::new(t2))
+    }
+}
+
+/// `t` with indices for names. The free variable `context[i]` becomes
+/// index `i` past the binders around it.
+///
+/// Panics if a free variable of `t` isn't in `context`.
+pub fn to_debruijn(t: &Term, context: &[String]) -> DbTerm {
+    let mut names: Vec<&str> = context.iter().rev().map(String::as_str).collect();
+    index(t, &mut names)
+}
+
+/// `names` has the innermost binder last, then the context reversed
+/// below the binders.
+fn index<'a>(t: &'a Term, names: &mut Vec<&'a str>) -> DbTerm {
+    match t {
+        Term::Var(x) => match names.iter().rev().position(|y| y == x) {
+            Some(i) => DbTerm::Var(i),
+            None => panic!("the free variable {} is not in the context", x),
+        },
+        Term::Abs(x, body) => {
+            names.push(x);
+            let body = index(body, names);
+            names.pop();
+            DbTerm::abs(body)
+        }
+        Term::App(t1, t2) => DbTerm::app(index(t1, names), index(t2, names)),
+    }
+}
+
+/// A named term for `t`, with the free indices named by `context` as in
+/// `to_debruijn`, and each binder named `x0`, `x1`, and so on, skipping any
+/// name a free variable has.
+///
+/// Panics if a free index is past the end of `context`.
+pub fn from_debruijn(t: &DbTerm, context: &[String]) -> Term {
+    let mut names: Vec<String> = context.iter().rev().cloned().collect();
+    let taken: BTreeSet<String> = context.iter().cloned().collect();
+    name(t, &mut names, &taken)
+}
+
+fn name(t: &DbTerm, names: &mut Vec<String>, taken: &BTreeSet<String>) -> Term {
+    match t {
+        DbTerm::Var(i) => match names.len().checked_sub(i + 1) {
+            Some(k) => Term::Var(names[k].clone()),
+            None => panic!("the free index {} is past the end of the context", i),
+        },
+        DbTerm::Abs(body) => {
+            let mut avoid = taken.clone();
+            avoid.extend(names.iter().cloned());
+            let x = fresh("x", &avoid);
+            names.push(x.clone());
+            let body = name(body, names, taken);
+            names.pop();
+            Term::Abs(x, Box::new(body))
+        }
+        DbTerm::App(t1, t2) => Term::app(name(t1, names, taken), name(t2, names, taken)),
+    }
+}
+
+/// `shift d c t`, TAPL's `↑d,c`: every index of `t` at least `c` past its
+/// binders, that is every free one when `c` is `0`, moved up by `d`.
+///
+/// Panics if that would make an index negative.
+pub fn shift(d: isize, cutoff: usize, t: &DbTerm) -> DbTerm {
+    match t {
+        DbTerm::Var(k) if *k < cutoff => DbTerm::Var(*k),
+        DbTerm::Var(k) => DbTerm::Var(
+            k.checked_add_signed(d)
+                .expect("shifting made an index negative"),
+        ),
+        DbTerm::Abs(body) => DbTerm::abs(shift(d, cutoff + 1, body)),
+        DbTerm::App(t1, t2) => DbTerm::app(shift(d, cutoff, t1), shift(d, cutoff, t2)),
+    }
+}
+
+/// `[j ↦ s] t`: `t` with the free index `j` replaced by `s`. Under each
+/// binder, `j` and the free indices of `s` are one further out.
+pub fn subst_index(j: usize, s: &DbTerm, t: &DbTerm) -> DbTerm {
+    match t {
+        DbTerm::Var(k) if *k == j => s.clone(),
+        DbTerm::Var(_) => t.clone(),
+        DbTerm::Abs(body) => DbTerm::abs(subst_index(j + 1, &shift(1, 0, s), body)),
+        DbTerm::App(t1, t2) => DbTerm::app(subst_index(j, s, t1), subst_index(j, s, t2)),
+    }
+}
+
+/// Contract the redex `(\. body) arg`: `arg` for the binder's index `0`,
+/// with the binder itself gone, `↑-1 ([0 ↦ ↑1 arg] body)`.
+pub fn beta(body: &DbTerm, arg: &DbTerm) -> DbTerm {
+    shift(-1, 0, &subst_index(0, &shift(1, 0, arg), body))
+}
+
+/// Whether `t1` and `t2` are the same up to the names of bound variables,
+/// decided by comparing their De Bruijn terms. Free variables have to
+/// have the same names.
+pub fn alpha_eq(t1: &Term, t2: &Term) -> bool {
+    let mut free = t1.free_vars();
+    free.extend(t2.free_vars());
+    let context: Vec<String> = free.into_iter().collect();
+    to_debruijn(t1, &context) == to_debruijn(t2, &context)
+}
+
+#[cfg(test)]
+mod test_lambda_debruijn {
+    use super::*;
+    use crate::lambda::subst;
+
+    fn v(x: &str) -> Term {
+        Term::var(x)
+    }
+
+    fn i(k: usize) -> DbTerm {
+        DbTerm::Var(k)
+    }
+
+    fn context(xs: &[&str]) -> Vec<String> {
+        xs.iter().map(|x| x.to_string()).collect()
+    }
+
+    #[test]
+    fn test_to_debruijn() {
+        // \x. \y. x and \x. \y. y
+        let k = Term::abs_many(&["x", "y"], v("x"));
+        assert_eq!(to_debruijn(&k, &[]), DbTerm::abs(DbTerm::abs(i(1))));
+        let t = Term::abs_many(&["x", "y"], v("y"));
+        assert_eq!(to_debruijn(&t, &[]), DbTerm::abs(DbTerm::abs(i(0))));
+        // Free variables are numbered past the binders: with context
+        // [a, b], \x. x b a is \. 0 2 1.
+        let t = Term::abs("x", Term::app_many(v("x"), [v("b"), v("a")]));
+        assert_eq!(
+            to_debruijn(&t, &context(&["a", "b"])),
+            DbTerm::abs(DbTerm::app(DbTerm::app(i(0), i(2)), i(1)))
+        );
+        // An inner binder shadows an outer one of the same name.
+        let t = Term::abs_many(&["x", "x"], v("x"));
+        assert_eq!(to_debruijn(&t, &[]), DbTerm::abs(DbTerm::abs(i(0))));
+    }
+
+    #[test]
+    #[should_panic(expected = "the free variable y is not in the context")]
+    fn test_unknown_free_variable() {
+        to_debruijn(&Term::abs("x", v("y")), &[]);
+    }
+
+    #[test]
+    fn test_from_debruijn() {
+        let t = DbTerm::abs(DbTerm::abs(DbTerm::app(i(1), i(2))));
+        assert_eq!(
+            from_debruijn(&t, &context(&["x1"])),
+            Term::abs_many(&["x0", "x2"], Term::app(v("x0"), v("x1")))
+        );
+        let terms = [
+            Term::abs_many(&["f", "x"], Term::app(v("f"), Term::app(v("f"), v("x")))),
+            Term::app(Term::abs("y", Term::app(v("y"), v("z"))), v("z")),
+        ];
+        let ctx = context(&["z"]);
+        for t in &terms {
+            let back = from_debruijn(&to_debruijn(t, &ctx), &ctx);
+            assert!(alpha_eq(&back, t));
+            assert_eq!(to_debruijn(&back, &ctx), to_debruijn(t, &ctx));
+        }
+    }
+
+    #[test]
+    fn test_shift() {
+        // TAPL exercise 6.2.2: ↑2 (\. \. 1 (0 2)) = \. \. 1 (0 4).
+        let t = DbTerm::abs(DbTerm::abs(DbTerm::app(i(1), DbTerm::app(i(0), i(2)))));
+        assert_eq!(
+            shift(2, 0, &t),
+            DbTerm::abs(DbTerm::abs(DbTerm::app(i(1), DbTerm::app(i(0), i(4)))))
+        );
+        // ↑2 (\. 0 1 (\. 0 1 2)) = \. 0 3 (\. 0 1 4).
+        let t = DbTerm::abs(DbTerm::app(
+            DbTerm::app(i(0), i(1)),
+            DbTerm::abs(DbTerm::app(DbTerm::app(i(0), i(1)), i(2))),
+        ));
+        assert_eq!(
+            shift(2, 0, &t),
+            DbTerm::abs(DbTerm::app(
+                DbTerm::app(i(0), i(3)),
+                DbTerm::abs(DbTerm::app(DbTerm::app(i(0), i(1)), i(4))),
+            ))
+        );
+        assert_eq!(shift(-1, 0, &shift(1, 0, &t)), t);
+    }
+
+    #[test]
+    fn test_subst_index() {
+        // [0 ↦ 1] (0 (\. \. 2)) = 1 (\. \. 3), from TAPL's exercise 6.2.5.
+        let t = DbTerm::app(i(0), DbTerm::abs(DbTerm::abs(i(2))));
+        assert_eq!(
+            subst_index(0, &i(1), &t),
+            DbTerm::app(i(1), DbTerm::abs(DbTerm::abs(i(3))))
+        );
+        // [0 ↦ 1 (\. 2)] (0 (\. 1)) = (1 (\. 2)) (\. (2 (\. 3))).
+        let s = DbTerm::app(i(1), DbTerm::abs(i(2)));
+        let t = DbTerm::app(i(0), DbTerm::abs(i(1)));
+        assert_eq!(
+            subst_index(0, &s, &t),
+            DbTerm::app(s.clone(), DbTerm::abs(DbTerm::app(i(2), DbTerm::abs(i(3)))))
+        );
+    }
+
+    #[test]
+    fn test_beta_agrees_with_named_substitution() {
+        // (\x. \y. x y) applied to `y`, a free variable the named version
+        // has to rename around.
+        let ctx = context(&["y", "z"]);
+        let body = Term::abs("y", Term::app(v("x"), v("y")));
+        for arg in [v("y"), v("z"), Term::abs("w", Term::app(v("w"), v("y")))] {
+            let named = subst(&body, "x", &arg);
+            // The binder's `x` is index 0 in the body, below the context.
+            let db_body = to_debruijn(&body, &context(&["x", "y", "z"]));
+            assert_eq!(
+                beta(&db_body, &to_debruijn(&arg, &ctx)),
+                to_debruijn(&named, &ctx)
+            );
+        }
+    }
+
+    #[test]
+    fn test_alpha_eq() {
+        assert!(alpha_eq(&Term::abs("x", v("x")), &Term::abs("y", v("y"))));
+        assert!(alpha_eq(
+            &Term::abs_many(&["x", "y"], Term::app(v("y"), v("x"))),
+            &Term::abs_many(&["a", "b"], Term::app(v("b"), v("a")))
+        ));
+        assert!(!alpha_eq(
+            &Term::abs_many(&["x", "y"], v("x")),
+            &Term::abs_many(&["x", "y"], v("y"))
+        ));
+        // Free variables are compared by name.
+        assert!(alpha_eq(&Term::abs("x", v("z")), &Term::abs("y", v("z"))));
+        assert!(!alpha_eq(&Term::abs("x", v("y")), &Term::abs("x", v("z"))));
+        assert!(!alpha_eq(&Term::abs("x", v("y")), &Term::abs("y", v("y"))));
+        // The capture-avoiding result of [x:=y] (\y. x) is \z. y for any z.
+        let t = subst(&Term::abs("y", v("x")), "x", &v("y"));
+        assert!(alpha_eq(&t, &Term::abs("z", v("y"))));
+    }
+}
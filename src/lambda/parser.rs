@@ -0,0 +1,257 @@
+//! A parser for the concrete syntax of lambda terms:
+//!
+//! ```text
+//! term ::= ("\" | "λ") ident+ "." term
+//!        | "let" ident "=" term "in" term
+//!        | atom+ (("\" | "λ") ident+ "." term | "let" ...)?
+//! atom ::= ident | "(" term ")"
+//! ```
+//!
+//! Application associates to the left and an abstraction extends as far
+//! to the right as it can, so `\x. x y z` is `\x. ((x y) z)`, and the last
+//! argument of an application may be an abstraction without parentheses:
+//! `f \x. x` is `f (\x. x)`. `\x y. t` is `\x. \y. t`, and `let x = t1 in
+//! t2` is sugar for `(\x. t2) t1`.
+//!
+//! Errors carry the byte offset they are at, and `ParseError::report`
+//! shows them at their line and column, as for Imp.
+
+use std::str::FromStr;
+
+use super::Term;
+use crate::syntax::{self, Cursor, Span};
+pub use crate::syntax::{ParseError, Token};
+
+const KEYWORDS: [&str; 2] = ["let", "in"];
+
+const SYMBOLS: [&str; 6] = ["\\", "λ", ".", "=", "(", ")"];
+
+/// Split `input` into tokens with their spans. `λ` is a symbol, not a
+/// letter.
+fn lex(input: &str) -> Result<Vec<(Token, Span)>, ParseError> {
+    syntax::lex(input, &KEYWORDS, &SYMBOLS)
+}
+
+struct Parser {
+    tokens: Vec<(Token, Span)>,
+    next: usize,
+}
+
+impl Cursor for Parser {
+    fn tokens(&self) -> &[(Token, Span)] {
+        &self.tokens
+    }
+
+    fn position(&self) -> usize {
+        self.next
+    }
+
+    fn advance(&mut self) {
+        self.next += 1;
+    }
+}
+
+impl Parser {
+    fn new(input: &str) -> Result<Self, ParseError> {
+        Ok(Parser {
+            tokens: lex(input)?,
+            next: 0,
+        })
+    }
+
+    fn term(&mut self) -> Result<Term, ParseError> {
+        if let Some(t) = self.binder()? {
+            return Ok(t);
+        }
+        let mut t = self.atom()?;
+        loop {
+            if let Some(arg) = self.binder()? {
+                return Ok(Term::app(t, arg));
+            }
+            match self.peek() {
+                Some(Token::Ident(_) | Token::Symbol("(")) => t = Term::app(t, self.atom()?),
+                _ => return Ok(t),
+            }
+        }
+    }
+
+    /// An abstraction or a `let`, if one starts here.
+    fn binder(&mut self) -> Result<Option<Term>, ParseError> {
+        if self.eat("\\") || self.eat("λ") {
+            let mut xs = vec![self.ident()?];
+            while let Some(Token::Ident(_)) = self.peek() {
+                xs.push(self.ident()?);
+            }
+            self.expect_or(".", "`.` or a variable")?;
+            let body = self.term()?;
+            let xs: Vec<&str> = xs.iter().map(String::as_str).collect();
+            Ok(Some(Term::abs_many(&xs, body)))
+        } else if self.eat("let") {
+            let x = self.ident()?;
+            self.expect("=")?;
+            let t1 = self.term()?;
+            self.expect("in")?;
+            let t2 = self.term()?;
+            Ok(Some(Term::app(Term::Abs(x, Box::new(t2)), t1)))
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn atom(&mut self) -> Result<Term, ParseError> {
+        if self.eat("(") {
+            let t = self.term()?;
+            self.expect(")")?;
+            Ok(t)
+        } else {
+            match self.peek() {
+                Some(Token::Ident(_)) => Ok(Term::Var(self.ident()?)),
+                _ => Err(self.error("a term")),
+            }
+        }
+    }
+}
+
+pub fn parse_term(input: &str) -> Result<Term, ParseError> {
+    let mut p = Parser::new(input)?;
+    let t = p.term()?;
+    p.finish(t)
+}
+
+impl FromStr for Term {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse_term(s)
+    }
+}
+
+#[cfg(test)]
+mod test_lambda_parser {
+    use super::*;
+
+    fn v(x: &str) -> Term {
+        Term::var(x)
+    }
+
+    #[test]
+    fn test_parse_terms() {
+        assert_eq!(parse_term("x"), Ok(v("x")));
+        assert_eq!(
+            parse_term("\\x. x y"),
+            Ok(Term::abs("x", Term::app(v("x"), v("y"))))
+        );
+        assert_eq!(parse_term("λx. x"), parse_term("\\x. x"));
+        assert_eq!(parse_term("λx.x"), parse_term("\\x. x"));
+        // Application is left-associative, and parentheses regroup it.
+        assert_eq!(
+            parse_term("f x y"),
+            Ok(Term::app(Term::app(v("f"), v("x")), v("y")))
+        );
+        assert_eq!(
+            parse_term("f (x y)"),
+            Ok(Term::app(v("f"), Term::app(v("x"), v("y"))))
+        );
+        assert_eq!(
+            parse_term("(\\x. x) y"),
+            Ok(Term::app(Term::abs("x", v("x")), v("y")))
+        );
+        // Several binders at once, and `λ` right next to a name.
+        assert_eq!(
+            parse_term("λf x. f (f x)"),
+            Ok(Term::abs_many(
+                &["f", "x"],
+                Term::app(v("f"), Term::app(v("f"), v("x")))
+            ))
+        );
+        // An abstraction ends the application it is the argument of.
+        assert_eq!(
+            parse_term("f \\x. x y"),
+            Ok(Term::app(v("f"), Term::abs("x", Term::app(v("x"), v("y")))))
+        );
+        assert!("x' y".parse::<Term>().is_err());
+        assert_eq!("x0 _y".parse::<Term>(), Ok(Term::app(v("x0"), v("_y"))));
+    }
+
+    #[test]
+    fn test_let() {
+        // let id = \x. x in id id  is  (\id. id id) (\x. x)
+        assert_eq!(
+            parse_term("let id = \\x. x in id id"),
+            Ok(Term::app(
+                Term::abs("id", Term::app(v("id"), v("id"))),
+                Term::abs("x", v("x"))
+            ))
+        );
+        // Nested, and as an argument.
+        assert_eq!(
+            parse_term("f let x = y in let z = x in z"),
+            Ok(Term::app(
+                v("f"),
+                Term::app(
+                    Term::abs("x", Term::app(Term::abs("z", v("z")), v("x"))),
+                    v("y")
+                )
+            ))
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(
+            parse_term("\\x y"),
+            Err(ParseError::UnexpectedEof {
+                expected: "`.` or a variable"
+            })
+        );
+        assert_eq!(
+            parse_term("\\. x"),
+            Err(ParseError::UnexpectedToken {
+                found: Token::Symbol("."),
+                pos: 1,
+                expected: "a variable"
+            })
+        );
+        assert_eq!(
+            parse_term("(x y"),
+            Err(ParseError::UnexpectedEof { expected: ")" })
+        );
+        assert_eq!(
+            parse_term("x)"),
+            Err(ParseError::UnexpectedToken {
+                found: Token::Symbol(")"),
+                pos: 1,
+                expected: "end of input"
+            })
+        );
+        assert_eq!(
+            parse_term("let x y"),
+            Err(ParseError::UnexpectedToken {
+                found: Token::Ident("y".to_string()),
+                pos: 6,
+                expected: "="
+            })
+        );
+        assert_eq!(
+            parse_term("x # y"),
+            Err(ParseError::UnexpectedChar { ch: '#', pos: 2 })
+        );
+        assert_eq!(
+            parse_term("").unwrap_err().to_string(),
+            "unexpected end of input, expected a term"
+        );
+    }
+
+    #[test]
+    fn test_report() {
+        let report = |input: &str| parse_term(input).unwrap_err().report(input);
+        assert_eq!(
+            report("λf.\n  f (λx x)"),
+            "expected `.` or a variable at line 2, col 10, found `)`\n2 |   f (λx x)\n  |          ^"
+        );
+        assert_eq!(
+            report("let x = y x"),
+            "expected `in` at line 1, col 12, found end of input\n1 | let x = y x\n  |            ^"
+        );
+    }
+}
@@ -0,0 +1,100 @@
+//! Printing terms back to the syntax `parse_term` reads, with only the
+//! parentheses the grammar needs: application is left-associative, and an
+//! abstraction extends as far right as it can, so it is parenthesized when
+//! something follows it, as in `(\x. x) y` or `(f \x. x) y`, and not as the
+//! last argument, as in `f \x. x`. So `parse_term(&t.to_string()) == Ok(t)`
+//! for every term whose names aren't keywords.
+
+use std::fmt;
+
+use super::Term;
+
+/// Print `t`. `followed` is whether more of an application comes after it,
+/// and `arg` whether it is an argument, where only a variable or an
+/// abstraction needs no parentheses.
+fn write_term(f: &mut fmt::Formatter<'_>, t: &Term, followed: bool, arg: bool) -> fmt::Result {
+    match t {
+        Term::Var(x) => write!(f, "{}", x),
+        Term::Abs(x, body) if followed => write!(f, "(\\{}. {})", x, body),
+        Term::Abs(x, body) => write!(f, "\\{}. {}", x, body),
+        Term::App(..) if arg => write!(f, "({})", t),
+        Term::App(t1, t2) => {
+            write_term(f, t1, true, false)?;
+            write!(f, " ")?;
+            write_term(f, t2, followed, true)
+        }
+    }
+}
+
+impl fmt::Display for Term {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write_term(f, self, false, false)
+    }
+}
+
+#[cfg(test)]
+mod test_lambda_pretty {
+    use super::*;
+    use crate::lambda::parse_term;
+    use crate::rng::Rng;
+
+    fn v(x: &str) -> Term {
+        Term::var(x)
+    }
+
+    #[test]
+    fn test_print() {
+        let id = Term::abs("x", v("x"));
+        assert_eq!(id.to_string(), "\\x. x");
+        assert_eq!(
+            Term::app_many(v("f"), [v("x"), v("y")]).to_string(),
+            "f x y"
+        );
+        assert_eq!(
+            Term::app(v("f"), Term::app(v("x"), v("y"))).to_string(),
+            "f (x y)"
+        );
+        assert_eq!(Term::app(id.clone(), v("y")).to_string(), "(\\x. x) y");
+        assert_eq!(Term::app(v("f"), id.clone()).to_string(), "f \\x. x");
+        // The abstraction is the last argument of `f \x. x` but not of the
+        // application around that.
+        assert_eq!(
+            Term::app_many(v("f"), [id.clone(), v("y")]).to_string(),
+            "f (\\x. x) y"
+        );
+        assert_eq!(
+            Term::abs_many(&["f", "x"], Term::app(v("f"), Term::app(v("f"), v("x")))).to_string(),
+            "\\f. \\x. f (f x)"
+        );
+        assert_eq!(
+            Term::app(v("g"), Term::app(v("f"), id)).to_string(),
+            "g (f \\x. x)"
+        );
+    }
+
+    /// A random term with `size` abstractions and applications over a few
+    /// variable names.
+    fn random_term(rng: &mut Rng, size: usize) -> Term {
+        let names = ["x", "y", "f", "x0"];
+        let x = *rng.choose(&names);
+        if size == 0 {
+            return v(x);
+        }
+        if rng.chance(1, 3) {
+            return Term::abs(x, random_term(rng, size - 1));
+        }
+        let left = rng.below(size as u64) as usize;
+        Term::app(random_term(rng, left), random_term(rng, size - 1 - left))
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let mut rng = Rng::new(408);
+        for _ in 0..500 {
+            let size = rng.below(12) as usize;
+            let t = random_term(&mut rng, size);
+            let printed = t.to_string();
+            assert_eq!(parse_term(&printed), Ok(t), "printed as {}", printed);
+        }
+    }
+}
@@ -0,0 +1,235 @@
+//! Reduction strategies as small-step relations, each with an evaluator
+//! that steps until nothing applies.
+//!
+//! Call-by-value is the strategy of the Stlc chapter: a function's
+//! argument is reduced to a value, an abstraction, before it is
+//! substituted, and nothing happens under a binder. An open term can get
+//! stuck, like `x (\y. y)`, which is no value but doesn't step either.
+//!
+//! Normal order always reduces the leftmost, outermost redex, under
+//! binders too. It reaches the beta-normal form of every term that has
+//! one, so terms like Church numerals can be computed with and compared.
+
+use super::{subst, Term};
+
+/// Whether `t` is a call-by-value value: an abstraction.
+pub fn is_value(t: &Term) -> bool {
+    matches!(t, Term::Abs(..))
+}
+
+/// One call-by-value step, `None` if `t` is a value or stuck:
+///
+/// ```text
+///    value v2                t1 --> t1'              value v1   t2 --> t2'
+/// -----------------------   ---------------------   ------------------------
+/// (\x. t1) v2 --> [x:=v2]t1   t1 t2 --> t1' t2          v1 t2 --> v1 t2'
+/// ```
+pub fn step_cbv(t: &Term) -> Option<Term> {
+    let Term::App(t1, t2) = t else {
+        return None;
+    };
+    if !is_value(t1) {
+        return Some(Term::app(step_cbv(t1)?, (**t2).clone()));
+    }
+    if !is_value(t2) {
+        return Some(Term::app((**t1).clone(), step_cbv(t2)?));
+    }
+    let Term::Abs(x, body) = &**t1 else {
+        unreachable!("a value is an abstraction");
+    };
+    Some(subst(body, x, t2))
+}
+
+/// Take call-by-value steps from `t` until none applies, and return the
+/// term reached: a value, or a stuck term. `None` if that takes more than
+/// `fuel` steps, as it does for `(\x. x x) (\x. x x)`, which steps to
+/// itself.
+pub fn eval_cbv(t: &Term, fuel: u64) -> Option<Term> {
+    multistep(t, fuel, step_cbv)
+}
+
+/// One normal-order step: the leftmost, outermost redex in `t`, wherever
+/// it is, is contracted. Arguments are substituted unevaluated. `None` if
+/// `t` is in normal form.
+pub fn step_normal_order(t: &Term) -> Option<Term> {
+    match t {
+        Term::Var(_) => None,
+        Term::Abs(x, body) => Some(Term::abs(x, step_normal_order(body)?)),
+        Term::App(t1, t2) => {
+            if let Term::Abs(x, body) = &**t1 {
+                return Some(subst(body, x, t2));
+            }
+            match step_normal_order(t1) {
+                Some(t1) => Some(Term::app(t1, (**t2).clone())),
+                None => Some(Term::app((**t1).clone(), step_normal_order(t2)?)),
+            }
+        }
+    }
+}
+
+/// The beta-normal form of `t`, found by normal-order steps. `None` if
+/// that takes more than `fuel` steps, which it does for every `fuel` if
+/// `t` has no normal form.
+pub fn normalize(t: &Term, fuel: u64) -> Option<Term> {
+    multistep(t, fuel, step_normal_order)
+}
+
+/// Step with `step` until it gives `None`, for at most `fuel` steps.
+fn multistep(t: &Term, fuel: u64, step: impl Fn(&Term) -> Option<Term>) -> Option<Term> {
+    let mut t = t.clone();
+    for _ in 0..fuel {
+        match step(&t) {
+            Some(next) => t = next,
+            None => return Some(t),
+        }
+    }
+    step(&t).is_none().then_some(t)
+}
+
+#[cfg(test)]
+mod test_lambda_reduce {
+    use super::*;
+
+    fn v(x: &str) -> Term {
+        Term::var(x)
+    }
+
+    fn id(x: &str) -> Term {
+        Term::abs(x, v(x))
+    }
+
+    /// `(\x. x x) (\x. x x)`, which steps to itself.
+    fn omega() -> Term {
+        let w = Term::abs("x", Term::app(v("x"), v("x")));
+        Term::app(w.clone(), w)
+    }
+
+    #[test]
+    fn test_values() {
+        assert!(is_value(&id("x")));
+        assert!(!is_value(&v("x")));
+        assert!(!is_value(&Term::app(id("x"), id("y"))));
+        assert_eq!(step_cbv(&id("x")), None);
+    }
+
+    #[test]
+    fn test_beta() {
+        // (\x. x) (\y. y) --> \y. y
+        assert_eq!(step_cbv(&Term::app(id("x"), id("y"))), Some(id("y")));
+        // (\x. \y. x) (\z. z) (\w. w) -->* \z. z, the function first.
+        let k = Term::abs_many(&["x", "y"], v("x"));
+        let t = Term::app_many(k.clone(), [id("z"), id("w")]);
+        assert_eq!(
+            step_cbv(&t),
+            Some(Term::app(Term::abs("y", id("z")), id("w")))
+        );
+        assert_eq!(eval_cbv(&t, 10), Some(id("z")));
+        // Then the argument: (\x. x) ((\y. y) (\z. z)) steps inside.
+        let t = Term::app(id("x"), Term::app(id("y"), id("z")));
+        assert_eq!(step_cbv(&t), Some(Term::app(id("x"), id("z"))));
+        // Nothing happens under a binder.
+        let t = Term::abs("f", Term::app(id("x"), v("f")));
+        assert_eq!(eval_cbv(&t, 10), Some(t));
+    }
+
+    #[test]
+    fn test_stuck() {
+        // A free variable in function position stops evaluation.
+        let t = Term::app(v("x"), Term::app(id("y"), id("z")));
+        assert_eq!(eval_cbv(&t, 10), Some(t));
+        // So do arguments that can't be evaluated further.
+        let t = Term::app(id("y"), v("x"));
+        assert_eq!(step_cbv(&t), None);
+    }
+
+    #[test]
+    fn test_omega_diverges() {
+        assert_eq!(step_cbv(&omega()), Some(omega()));
+        assert_eq!(eval_cbv(&omega(), 1_000), None);
+        // Call-by-value evaluates an argument even if it's dropped.
+        let t = Term::app(Term::abs("x", id("y")), omega());
+        assert_eq!(eval_cbv(&t, 1_000), None);
+    }
+
+    /// The Church numeral `\f. \x. f (... (f x))`.
+    fn church(n: usize) -> Term {
+        let body = (0..n).fold(v("x"), |t, _| Term::app(v("f"), t));
+        Term::abs_many(&["f", "x"], body)
+    }
+
+    /// The number `t` is the Church numeral of, whatever its variables are
+    /// called.
+    fn church_value(t: &Term) -> Option<usize> {
+        let Term::Abs(f, t) = t else { return None };
+        let Term::Abs(x, body) = &**t else {
+            return None;
+        };
+        let (mut t, mut n) = (&**body, 0);
+        loop {
+            match t {
+                Term::Var(y) if y == x && x != f => return Some(n),
+                Term::App(g, rest) if matches!(&**g, Term::Var(y) if y == f) => {
+                    n += 1;
+                    t = rest;
+                }
+                _ => return None,
+            }
+        }
+    }
+
+    #[test]
+    fn test_normal_order() {
+        // Under a binder: \x. (\y. y) x --> \x. x.
+        let t = Term::abs("x", Term::app(id("y"), v("x")));
+        assert_eq!(step_normal_order(&t), Some(id("x")));
+        // The outermost redex first, so a dropped argument is never
+        // evaluated: (\x. \y. y) omega --> \y. y, where call-by-value
+        // loops.
+        let t = Term::app(Term::abs("x", id("y")), omega());
+        assert_eq!(step_normal_order(&t), Some(id("y")));
+        assert_eq!(normalize(&t, 10), Some(id("y")));
+        // The head before the arguments: x ((\y. y) z) steps in the
+        // argument, since `x` is stuck.
+        let t = Term::app(v("x"), Term::app(id("y"), v("z")));
+        assert_eq!(normalize(&t, 10), Some(Term::app(v("x"), v("z"))));
+        assert_eq!(normalize(&omega(), 1_000), None);
+    }
+
+    #[test]
+    fn test_church_arithmetic() {
+        let succ = Term::abs_many(
+            &["n", "f", "x"],
+            Term::app(v("f"), Term::app_many(v("n"), [v("f"), v("x")])),
+        );
+        let plus = Term::abs_many(
+            &["m", "n", "f", "x"],
+            Term::app_many(v("m"), [v("f"), Term::app_many(v("n"), [v("f"), v("x")])]),
+        );
+        let mult = Term::abs_many(
+            &["m", "n", "f"],
+            Term::app(v("m"), Term::app(v("n"), v("f"))),
+        );
+        let exp = Term::abs_many(&["m", "n"], Term::app(v("n"), v("m")));
+        let value = |t: Term| church_value(&normalize(&t, 10_000).unwrap());
+        assert_eq!(value(Term::app(succ, church(2))), Some(3));
+        assert_eq!(value(Term::app_many(plus, [church(2), church(3)])), Some(5));
+        assert_eq!(
+            value(Term::app_many(mult.clone(), [church(3), church(0)])),
+            Some(0)
+        );
+        assert_eq!(value(Term::app_many(mult, [church(2), church(3)])), Some(6));
+        assert_eq!(value(Term::app_many(exp, [church(2), church(3)])), Some(8));
+        // Normal forms of the same number are the same up to naming.
+        assert_eq!(church_value(&church(4)), Some(4));
+        assert_eq!(church_value(&id("x")), None);
+    }
+
+    #[test]
+    fn test_fuel_counts_steps() {
+        // (\x. x) ((\x. x) (\y. y)) takes exactly two steps.
+        let t = Term::app(id("x"), Term::app(id("x"), id("y")));
+        assert_eq!(eval_cbv(&t, 1), None);
+        assert_eq!(eval_cbv(&t, 2), Some(id("y")));
+        assert_eq!(eval_cbv(&id("y"), 0), Some(id("y")));
+    }
+}
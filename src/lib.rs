@@ -1,4 +1,17 @@
+// Lets the paths that `imp!` and `hoare!` expand to resolve inside this
+// crate too.
+extern crate self as rust_coq;
+
+pub use rust_coq_derive::{hoare, imp, Recursor};
+
 pub mod peano;
 pub mod church;
 pub mod map;
-pub mod list;
\ No newline at end of file
+pub mod list;
+pub mod state;
+pub mod heap;
+pub mod syntax;
+pub mod imp;
+pub mod rng;
+pub mod lambda;
+pub mod stlc;
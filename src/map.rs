@@ -1,31 +1,203 @@
-use std::rc::Rc;
+use std::{borrow::Borrow, collections::HashMap, hash::Hash, rc::Rc};
+
+mod ext;
+mod fast;
+mod finite;
+pub mod laws;
+mod reified;
+#[cfg(feature = "serde")]
+mod serde_impls;
+pub mod sync;
+mod versioned;
+
+pub use ext::{MapExt, PartialMapExt};
+pub use fast::FastTotalMap;
+pub use finite::FiniteMap;
+pub use reified::{Overrides, ReifiedTotalMap};
+pub use versioned::VersionedMap;
 
 /// A total map is as function that returns a default value when
-/// looked up.
-pub type TotalMap<K, V> = Rc<dyn Fn(K) -> V>;
+/// looked up. Lookups borrow the key, so querying a map (or walking a
+/// chain of updates) never needs to clone or allocate one. `K` can be the
+/// borrowed form of the keys, like `str` for `String`s, so that a key
+/// that is only at hand borrowed can be looked up too.
+pub type TotalMap<K, V> = Rc<dyn Fn(&K) -> V>;
 
 /// Function tm_empty yields an empty total map given a default
 /// element. This map always returns the default element when applied
 /// to any key.
-pub fn tm_empty<K: 'static + PartialEq, V: 'static + Clone>(default_v: V) -> TotalMap<K, V> {
+pub fn tm_empty<K: 'static + PartialEq + ?Sized, V: 'static + Clone>(
+    default_v: V,
+) -> TotalMap<K, V> {
     Rc::new(move |_| default_v.clone())
 }
 
-pub fn tm_update<K: 'static + PartialEq, V: 'static + Clone>(
+/// Function tm_update binds `k` to `v`, shadowing what `m` had there. The
+/// key is stored owned, as any type that borrows as a `K`, like a `String`
+/// for a `TotalMap<str, V>`.
+pub fn tm_update<K: 'static + PartialEq + ?Sized, V: 'static + Clone>(
     m: TotalMap<K, V>,
-    k: K,
+    k: impl Borrow<K> + 'static,
     v: V,
 ) -> TotalMap<K, V> {
-    Rc::new(move |k_1| if k_1 == k { v.clone() } else { m(k_1) })
+    Rc::new(move |k_1: &K| if k_1 == k.borrow() { v.clone() } else { m(k_1) })
+}
+
+/// Function tm_empty_with yields a total map whose default is computed
+/// from the key on every miss, so the default needn't be `Clone`, can be
+/// expensive to build, or can depend on the key itself.
+pub fn tm_empty_with<K: 'static + ?Sized, V: 'static>(
+    f: impl Fn(&K) -> V + 'static,
+) -> TotalMap<K, V> {
+    Rc::new(f)
+}
+
+/// Function tm_apply looks `k` up. It is just `m(k)`, for places that
+/// want the lookup as a function of the map.
+pub fn tm_apply<K: ?Sized, V>(m: &TotalMap<K, V>, k: &K) -> V {
+    m(k)
+}
+
+/// Function tm_update_many applies `tm_update` for every pair in order, so a
+/// later pair for the same key shadows an earlier one.
+pub fn tm_update_many<K: 'static + PartialEq + ?Sized, V: 'static + Clone>(
+    m: TotalMap<K, V>,
+    pairs: impl IntoIterator<Item = (impl Borrow<K> + 'static, V)>,
+) -> TotalMap<K, V> {
+    pairs
+        .into_iter()
+        .fold(m, |map, (k, v)| tm_update(map, k, v))
+}
+
+/// Function tm_remove makes `k` map back to the default element. A
+/// closure map doesn't remember the default it was built from, so the
+/// caller has to hand it in again; removal is then just shadowing the
+/// old binding with the default.
+pub fn tm_remove<K: 'static + PartialEq + ?Sized, V: 'static + Clone>(
+    m: TotalMap<K, V>,
+    k: impl Borrow<K> + 'static,
+    default_v: V,
+) -> TotalMap<K, V> {
+    tm_update(m, k, default_v)
+}
+
+/// Function tm_map applies `f` to whatever the map returns, transforming
+/// every value (including the default) at once.
+pub fn tm_map<K: 'static + ?Sized, V: 'static, W: 'static>(
+    m: TotalMap<K, V>,
+    f: impl Fn(V) -> W + 'static,
+) -> TotalMap<K, W> {
+    Rc::new(move |k: &K| f(m(k)))
+}
+
+/// Function tm_compose precomposes the map with a function on keys:
+/// looking up `b` in the result looks up `f(b)` in `m`.
+///
+/// On program states this is variable renaming. If `rename` sends each
+/// new variable name to the old one it stands for, `tm_compose(st,
+/// rename)` is the state in which the program, rewritten with the new
+/// names, starts off exactly as the original did in `st`.
+pub fn tm_compose<B: 'static + ?Sized, K: 'static + ?Sized, Q: Borrow<K>, V: 'static>(
+    m: TotalMap<K, V>,
+    f: impl Fn(&B) -> Q + 'static,
+) -> TotalMap<B, V> {
+    Rc::new(move |b: &B| m(f(b).borrow()))
+}
+
+/// Function tm_agree_on checks that two total maps give the same value on
+/// every key in `keys`, e.g. that two program states agree on the
+/// variables a test cares about.
+pub fn tm_agree_on<K: ?Sized, V: PartialEq>(
+    m1: &TotalMap<K, V>,
+    m2: &TotalMap<K, V>,
+    keys: &[impl Borrow<K>],
+) -> bool {
+    keys.iter().all(|k| m1(k.borrow()) == m2(k.borrow()))
+}
+
+/// Function tm_equiv tests extensional equality on sampled keys. Two total
+/// maps are equal when they agree on *every* key, which can't be checked,
+/// but it can be refuted: `false` means some sample tells them apart.
+pub fn tm_equiv<K: ?Sized, V: PartialEq>(
+    m1: &TotalMap<K, V>,
+    m2: &TotalMap<K, V>,
+    samples: impl IntoIterator<Item = impl Borrow<K>>,
+) -> bool {
+    samples
+        .into_iter()
+        .all(|k| m1(k.borrow()) == m2(k.borrow()))
+}
+
+/// Function tm_equiv_prop is `tm_equiv` with the keys drawn from a proptest
+/// strategy. On failure proptest shrinks the distinguishing key, which is
+/// reported in the error.
+#[cfg(feature = "proptest")]
+pub fn tm_equiv_prop<K, V, S>(
+    m1: &TotalMap<K, V>,
+    m2: &TotalMap<K, V>,
+    keys: S,
+) -> Result<(), proptest::test_runner::TestError<K>>
+where
+    K: std::fmt::Debug,
+    V: PartialEq + std::fmt::Debug,
+    S: proptest::strategy::Strategy<Value = K>,
+{
+    use proptest::test_runner::{Config, TestCaseError, TestRunner};
+
+    let config = Config {
+        failure_persistence: None,
+        ..Config::default()
+    };
+    TestRunner::new(config).run(&keys, |k| {
+        let (v1, v2) = (m1(&k), m2(&k));
+        if v1 == v2 {
+            Ok(())
+        } else {
+            Err(TestCaseError::fail(format!("{:?} != {:?}", v1, v2)))
+        }
+    })
+}
+
+/// Function tm_compact flattens a long chain of updates. The values of
+/// `keys` are looked up once and stored in a table, and any other key maps
+/// to `default_v`, so lookups are a single hash lookup and the chain is
+/// dropped. As with `tm_remove`, the caller hands the default in again; the
+/// result is extensionally the same map when `keys` covers every key `m`
+/// was updated at.
+pub fn tm_compact<K: 'static + Eq + Hash + Clone, V: 'static + Clone>(
+    m: TotalMap<K, V>,
+    keys: &[K],
+    default_v: V,
+) -> TotalMap<K, V> {
+    let table: HashMap<K, V> = keys.iter().map(|k| (k.clone(), m(k))).collect();
+    Rc::new(move |k: &K| match table.get(k) {
+        Some(v) => v.clone(),
+        None => default_v.clone(),
+    })
 }
 
+/// `total_map!(_default, {k, v}, ...)` or, closer to the `!->` notation of
+/// Software Foundations, `total_map!(0, "x" => 3, "y" => 4 + 1,)`. In the
+/// `=>` form keys go through `Into`, so string literals can be used
+/// directly as `String` keys.
 #[macro_export]
 macro_rules! total_map {
-    ($default_v: expr, $({$k: expr, $v: expr}), *) => {
+    ($default_v: expr $(, {$k: expr, $v: expr})* $(,)?) => {
         {
-            let mut map = tm_empty ($default_v);
+            #[allow(unused_mut)]
+            let mut map = $crate::map::tm_empty($default_v);
             $(
-                map = tm_update(map, $k, $v);
+                map = $crate::map::tm_update(map, $k, $v);
+            )*
+            map
+        }
+    };
+    ($default_v: expr $(, $k: expr => $v: expr)* $(,)?) => {
+        {
+            #[allow(unused_mut)]
+            let mut map = $crate::map::tm_empty($default_v);
+            $(
+                map = $crate::map::MapExt::update(&map, $k, $v);
             )*
             map
         }
@@ -33,8 +205,14 @@ macro_rules! total_map {
 }
 
 #[cfg(test)]
+#[allow(clippy::bool_assert_comparison)]
 mod test_total_map {
-    use super::{tm_empty, tm_update, TotalMap};
+    use super::{
+        tm_agree_on, tm_compact, tm_compose, tm_empty, tm_empty_with, tm_equiv, tm_map, tm_remove,
+        tm_update, tm_update_many, TotalMap,
+    };
+    use crate::peano::Peano;
+    use std::{cell::Cell, rc::Rc};
 
     /// Fetch a map:
     /// "bar" !-> true;
@@ -53,19 +231,207 @@ mod test_total_map {
         total_map!(false, {"foo".to_string(), true}, {"bar".to_string(), true})
     }
 
+    #[test]
+    fn test_macro_arrow_syntax() {
+        let map: TotalMap<String, i32> = total_map!(0, "x" => 3, "y" => 1 + 3,);
+        assert_eq!(map(&"x".to_string()), 3);
+        assert_eq!(map(&"y".to_string()), 4);
+        assert_eq!(map(&"z".to_string()), 0);
+    }
+
+    #[test]
+    fn test_macro_matches_update_chain() {
+        let keys = ["foo".to_string(), "bar".to_string(), "box".to_string()];
+        let arrow: TotalMap<String, bool> = total_map!(false, "foo" => true, "bar" => true);
+        assert!(tm_agree_on(&macro_example(), &example_map(), &keys));
+        assert!(tm_agree_on(&arrow, &example_map(), &keys));
+    }
+
+    #[test]
+    fn test_macro_nested() {
+        let inner: TotalMap<String, i32> = total_map!(0, "x" => 1);
+        let map: TotalMap<String, i32> = total_map!(
+            inner(&"x".to_string()) * 10,
+            "y" => inner(&"x".to_string()) + 1,
+        );
+        assert_eq!(map(&"y".to_string()), 2);
+        assert_eq!(map(&"z".to_string()), 10);
+    }
+
     #[test]
     fn test_example_map_foo() {
-        assert_eq!(example_map()("foo".to_string()), true)
+        assert_eq!(example_map()(&"foo".to_string()), true)
     }
 
     #[test]
     fn test_example_map_bar() {
-        assert_eq!(example_map()("bar".to_string()), true)
+        assert_eq!(example_map()(&"bar".to_string()), true)
     }
 
     #[test]
     fn test_example_map_other() {
-        assert_eq!(example_map()("box".to_string()), false)
+        assert_eq!(example_map()(&"box".to_string()), false)
+    }
+
+    #[test]
+    fn test_empty_with_key_dependent_default() {
+        let map = tm_update(tm_empty_with(|k: &String| k.len()), "foo".to_string(), 0);
+        assert_eq!(map(&"foo".to_string()), 0);
+        assert_eq!(map(&"quux".to_string()), 4);
+    }
+
+    #[test]
+    fn test_empty_with_is_lazy() {
+        let calls = Rc::new(Cell::new(0));
+        let counter = Rc::clone(&calls);
+        let map = tm_empty_with(move |_: &i32| {
+            counter.set(counter.get() + 1);
+            vec![0u8; 1024]
+        });
+        let map = tm_update(map, 1, vec![]);
+        assert_eq!(calls.get(), 0);
+        assert!(map(&1).is_empty());
+        assert_eq!(calls.get(), 0);
+        assert_eq!(map(&2).len(), 1024);
+        assert_eq!(calls.get(), 1);
+    }
+
+    #[test]
+    fn test_lookup_borrows_key() {
+        let map = example_map();
+        let key = "foo".to_string();
+        assert!(map(&key));
+        assert!(tm_update(map, key.clone(), false)(&"bar".to_string()));
+        assert_eq!(key, "foo");
+    }
+
+    #[test]
+    fn test_update_many() {
+        let map = tm_update_many(
+            tm_empty(false),
+            [("foo".to_string(), true), ("bar".to_string(), true)],
+        );
+        let keys = ["foo".to_string(), "bar".to_string(), "box".to_string()];
+        assert!(tm_agree_on(&map, &example_map(), &keys));
+    }
+
+    #[test]
+    fn test_update_many_later_wins() {
+        let map = tm_update_many(tm_empty(0), [("x", 1), ("y", 2), ("x", 3)]);
+        assert_eq!(map(&"x"), 3);
+        assert_eq!(map(&"y"), 2);
+    }
+
+    #[test]
+    fn test_remove_restores_default() {
+        let map = tm_remove(example_map(), "foo".to_string(), false);
+        assert!(!map(&"foo".to_string()));
+        assert!(map(&"bar".to_string()));
+    }
+
+    #[test]
+    fn test_equiv() {
+        let permuted = tm_update(
+            tm_update(tm_empty(false), "bar".to_string(), true),
+            "foo".to_string(),
+            true,
+        );
+        let samples = ["foo", "bar", "box", ""].map(String::from);
+        assert!(tm_equiv(&example_map(), &permuted, samples.clone()));
+        let other = tm_update(permuted, "box".to_string(), true);
+        assert!(!tm_equiv(&example_map(), &other, samples));
+    }
+
+    #[cfg(feature = "proptest")]
+    #[test]
+    fn test_equiv_prop() {
+        use super::tm_equiv_prop;
+        use proptest::test_runner::TestError;
+
+        let m1: TotalMap<i32, i32> = tm_update(tm_update(tm_empty(0), 1, 10), 2, 20);
+        let m2: TotalMap<i32, i32> = tm_update(tm_update(tm_empty(0), 2, 20), 1, 10);
+        assert!(tm_equiv_prop(&m1, &m2, -100..100).is_ok());
+
+        // Differs from `m1` on every key from 10 on; shrinking finds 10.
+        let m3: TotalMap<i32, i32> = Rc::new(move |k: &i32| if *k >= 10 { 1 } else { m2(k) });
+        match tm_equiv_prop(&m1, &m3, 0..1000) {
+            Err(TestError::Fail(_, k)) => assert_eq!(k, 10),
+            other => panic!("expected a counterexample, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_compact_preserves_lookups() {
+        let mut map = tm_empty(-1);
+        for i in 0..5000 {
+            map = tm_update(map, i % 50, i);
+        }
+        let keys: Vec<i32> = (0..50).collect();
+        let compacted = tm_compact(map.clone(), &keys, -1);
+        let all: Vec<i32> = (0..100).collect();
+        assert!(tm_agree_on(&map, &compacted, &all));
+        assert_eq!(compacted(&7), 4957);
+        assert_eq!(compacted(&49), 4999);
+        assert_eq!(compacted(&60), -1);
+    }
+
+    #[test]
+    fn test_compact_drops_the_chain() {
+        let base: TotalMap<i32, i32> = tm_empty(0);
+        let map = tm_update(tm_update(base.clone(), 1, 10), 2, 20);
+        assert_eq!(Rc::strong_count(&base), 2);
+        let compacted = tm_compact(map, &[1, 2], 0);
+        assert_eq!(Rc::strong_count(&base), 1);
+        assert_eq!(compacted(&1), 10);
+        assert_eq!(compacted(&3), 0);
+    }
+
+    #[test]
+    fn test_map_values() {
+        let map = tm_map(example_map(), |b| if b { "yes" } else { "no" });
+        assert_eq!(map(&"foo".to_string()), "yes");
+        assert_eq!(map(&"box".to_string()), "no");
+    }
+
+    #[test]
+    fn test_map_into_peano_state() {
+        let state: TotalMap<String, i64> = tm_update(tm_empty(0), "X".to_string(), 3);
+        let peano_state = tm_map(state, |n: i64| Peano::from(n.max(0) as usize));
+        assert_eq!(peano_state(&"X".to_string()), Peano::from(3));
+        assert_eq!(peano_state(&"Y".to_string()), Peano::O);
+    }
+
+    #[test]
+    fn test_compose_renames_variables() {
+        // X and Y swapped, everything else unchanged.
+        let st = crate::state! {"X" => 1, "Y" => 2, "Z" => 3};
+        let swap = |x: &str| match x {
+            "X" => "Y".to_string(),
+            "Y" => "X".to_string(),
+            other => other.to_string(),
+        };
+        let renamed = tm_compose(st, swap);
+        assert_eq!(renamed("X"), 2);
+        assert_eq!(renamed("Y"), 1);
+        assert_eq!(renamed("Z"), 3);
+    }
+
+    #[test]
+    fn test_compose_with_different_key_type() {
+        // Registers r0, r1, ... renamed onto the variables they hold.
+        let st = crate::state! {"X" => 5, "Y" => 7};
+        let vars = ["X", "Y"];
+        let regs = tm_compose(st, move |r: &usize| vars[*r]);
+        assert_eq!(regs(&0), 5);
+        assert_eq!(regs(&1), 7);
+    }
+
+    #[test]
+    fn test_agree_on() {
+        let other = tm_update(example_map(), "box".to_string(), true);
+        let keys = ["foo".to_string(), "bar".to_string()];
+        assert!(tm_agree_on(&example_map(), &other, &keys));
+        assert!(!tm_agree_on(&example_map(), &other, &["box".to_string()]));
     }
 }
 
@@ -83,13 +449,148 @@ pub fn pm_update<K: 'static + PartialEq, V: 'static + Clone>(
     tm_update(m, k, Some(v))
 }
 
+/// Function pm_from_hashmap views a `HashMap` as a partial map. Lookups go
+/// straight to the table. (`PartialMap` is only an alias for an `Rc`, so
+/// this can't be a `From` impl; `FiniteMap` and `FastTotalMap` do get
+/// `From<HashMap>` and `FromIterator`.)
+pub fn pm_from_hashmap<K: 'static + Eq + Hash, V: 'static + Clone>(
+    h: HashMap<K, V>,
+) -> PartialMap<K, V> {
+    Rc::new(move |k: &K| h.get(k).cloned())
+}
+
+/// Function pm_update_with binds `k` to `combine(old, v)` if it was bound
+/// to `old`, and to `v` otherwise. The old value is looked up once, when
+/// the update is made, so the new binding answers directly instead of
+/// re-walking the chain on every lookup.
+pub fn pm_update_with<K: 'static + PartialEq, V: 'static + Clone>(
+    m: PartialMap<K, V>,
+    k: K,
+    v: V,
+    combine: impl Fn(V, V) -> V,
+) -> PartialMap<K, V> {
+    let v = match m(&k) {
+        Some(old) => combine(old, v),
+        None => v,
+    };
+    pm_update(m, k, v)
+}
+
+/// Function pm_update_many binds every pair in order.
+pub fn pm_update_many<K: 'static + PartialEq, V: 'static + Clone>(
+    m: PartialMap<K, V>,
+    pairs: impl IntoIterator<Item = (K, V)>,
+) -> PartialMap<K, V> {
+    tm_update_many(m, pairs.into_iter().map(|(k, v)| (k, Some(v))))
+}
+
+/// Function pm_remove unbinds `k`, so looking it up yields `None` again.
+pub fn pm_remove<K: 'static + PartialEq, V: 'static + Clone>(
+    m: PartialMap<K, V>,
+    k: K,
+) -> PartialMap<K, V> {
+    tm_update(m, k, None)
+}
+
+/// Function pm_map transforms the bound values and leaves unbound keys
+/// unbound.
+pub fn pm_map<K: 'static, V: 'static, W: 'static>(
+    m: PartialMap<K, V>,
+    f: impl Fn(V) -> W + 'static,
+) -> PartialMap<K, W> {
+    tm_map(m, move |v: Option<V>| v.map(&f))
+}
+
+/// Function pm_filter keeps the bindings satisfying `pred` and unbinds the
+/// rest.
+pub fn pm_filter<K: 'static, V: 'static>(
+    m: PartialMap<K, V>,
+    pred: impl Fn(&K, &V) -> bool + 'static,
+) -> PartialMap<K, V> {
+    Rc::new(move |k: &K| m(k).filter(|v| pred(k, v)))
+}
+
+/// Function pm_restrict keeps only the bindings of `keys`, e.g. the
+/// variables in scope or the footprint of a heap.
+pub fn pm_restrict<K: 'static + PartialEq + Clone, V: 'static>(
+    m: PartialMap<K, V>,
+    keys: &[K],
+) -> PartialMap<K, V> {
+    let keys = keys.to_vec();
+    pm_filter(m, move |k, _| keys.contains(k))
+}
+
+/// Function pm_union binds every key bound in either map. Keys bound in
+/// both are combined by `resolve`, which gets the value from `m1` first.
+pub fn pm_union<K: 'static, V: 'static>(
+    m1: PartialMap<K, V>,
+    m2: PartialMap<K, V>,
+    resolve: impl Fn(V, V) -> V + 'static,
+) -> PartialMap<K, V> {
+    Rc::new(move |k: &K| match (m1(k), m2(k)) {
+        (Some(v1), Some(v2)) => Some(resolve(v1, v2)),
+        (v1, None) => v1,
+        (None, v2) => v2,
+    })
+}
+
+/// The usual right-biased union: where both maps bind a key, `m2` wins.
+pub fn pm_union_right<K: 'static, V: 'static>(
+    m1: PartialMap<K, V>,
+    m2: PartialMap<K, V>,
+) -> PartialMap<K, V> {
+    pm_union(m1, m2, |_, v2| v2)
+}
+
+/// Function pm_included_in checks `m1 ⊆ m2` restricted to `keys`: every
+/// key in `keys` bound by `m1` is bound to the same value by `m2`.
+pub fn pm_included_in<K, V: PartialEq>(
+    m1: &PartialMap<K, V>,
+    m2: &PartialMap<K, V>,
+    keys: &[K],
+) -> bool {
+    keys.iter().all(|k| match m1(k) {
+        Some(v) => m2(k) == Some(v),
+        None => true,
+    })
+}
+
+/// Disjoint union `m1 ⊎ m2`, as used for heaps in separation logic. It is
+/// `None` if the two maps overlap. A closure map can only reveal its domain
+/// by being probed, so overlap is checked on the given `keys`.
+pub fn pm_disjoint_union<K: 'static, V: 'static>(
+    m1: PartialMap<K, V>,
+    m2: PartialMap<K, V>,
+    keys: &[K],
+) -> Option<PartialMap<K, V>> {
+    let overlap = keys.iter().any(|k| m1(k).is_some() && m2(k).is_some());
+    if overlap {
+        None
+    } else {
+        Some(pm_union_right(m1, m2))
+    }
+}
+
+/// `partial_map!({k, v}, ...)` or `partial_map!("Church" => true, ...)`,
+/// with the same key conversion as `total_map!`.
 #[macro_export]
 macro_rules! partial_map {
-    ($({$k: expr, $v: expr}), *) => {
+    ($({$k: expr, $v: expr}),* $(,)?) => {
+        {
+            #[allow(unused_mut)]
+            let mut map = $crate::map::pm_empty();
+            $(
+                map = $crate::map::pm_update(map, $k, $v);
+            )*
+            map
+        }
+    };
+    ($($k: expr => $v: expr),* $(,)?) => {
         {
-            let mut map = pm_empty();
+            #[allow(unused_mut)]
+            let mut map = $crate::map::pm_empty();
             $(
-                map = pm_update(map, $k, $v);
+                map = $crate::map::pm_update(map, ::std::convert::Into::into($k), $v);
             )*
             map
         }
@@ -98,7 +599,11 @@ macro_rules! partial_map {
 
 #[cfg(test)]
 mod test_partial_map {
-    use super::{pm_empty, pm_update, PartialMap};
+    use super::{
+        pm_disjoint_union, pm_empty, pm_filter, pm_from_hashmap, pm_included_in, pm_map, pm_remove,
+        pm_restrict, pm_union, pm_union_right, pm_update, pm_update_many, pm_update_with,
+        PartialMap,
+    };
 
     fn example_map() -> PartialMap<String, bool> {
         pm_update(
@@ -113,18 +618,248 @@ mod test_partial_map {
         partial_map!({"Church".to_string(), true}, {"Turing".to_string(), false})
     }
 
+    #[test]
+    fn test_macro_arrow_syntax() {
+        let keys = [
+            "Church".to_string(),
+            "Turing".to_string(),
+            "Other".to_string(),
+        ];
+        let map: PartialMap<String, bool> = partial_map!("Church" => true, "Turing" => false,);
+        assert!(pm_included_in(&map, &example_map(), &keys));
+        assert!(pm_included_in(&example_map(), &macro_example(), &keys));
+    }
+
+    #[test]
+    fn test_macro_empty() {
+        let map: PartialMap<String, bool> = partial_map!();
+        assert_eq!(map(&"Church".to_string()), None);
+    }
+
     #[test]
     fn test_example_map_church() {
-        assert_eq!(example_map()("Church".to_string()), Some(true));
+        assert_eq!(example_map()(&"Church".to_string()), Some(true));
     }
 
     #[test]
     fn test_example_map_turing() {
-        assert_eq!(example_map()("Turing".to_string()), Some(false));
+        assert_eq!(example_map()(&"Turing".to_string()), Some(false));
     }
 
     #[test]
     fn test_example_map_other() {
-        assert_eq!(example_map()("Other".to_string()), None)
+        assert_eq!(example_map()(&"Other".to_string()), None)
+    }
+
+    #[test]
+    fn test_update_many() {
+        let map = pm_update_many(pm_empty(), [("Church", true), ("Turing", false)]);
+        assert_eq!(map(&"Church"), Some(true));
+        assert_eq!(map(&"Turing"), Some(false));
+        assert_eq!(map(&"Other"), None);
+    }
+
+    #[test]
+    fn test_remove_unbinds() {
+        let map = pm_remove(example_map(), "Church".to_string());
+        assert_eq!(map(&"Church".to_string()), None);
+        assert_eq!(map(&"Turing".to_string()), Some(false));
+    }
+
+    #[test]
+    fn test_remove_then_update() {
+        let map = pm_update(
+            pm_remove(example_map(), "Turing".to_string()),
+            "Turing".to_string(),
+            true,
+        );
+        assert_eq!(map(&"Turing".to_string()), Some(true));
+    }
+
+    #[test]
+    fn test_map_values() {
+        let map = pm_map(example_map(), |b: bool| !b);
+        assert_eq!(map(&"Church".to_string()), Some(false));
+        assert_eq!(map(&"Turing".to_string()), Some(true));
+        assert_eq!(map(&"Other".to_string()), None);
+    }
+
+    #[test]
+    fn test_from_hashmap() {
+        let h = std::collections::HashMap::from([("Church", true), ("Turing", false)]);
+        let map = pm_from_hashmap(h);
+        assert_eq!(map(&"Church"), Some(true));
+        assert_eq!(map(&"Turing"), Some(false));
+        assert_eq!(map(&"Other"), None);
+    }
+
+    #[test]
+    fn test_update_with_counts() {
+        let words = ["a", "b", "a", "c", "a", "b"];
+        let counts = words.iter().fold(pm_empty(), |m, w| {
+            pm_update_with(m, *w, 1, |old, new| old + new)
+        });
+        assert_eq!(counts(&"a"), Some(3));
+        assert_eq!(counts(&"b"), Some(2));
+        assert_eq!(counts(&"c"), Some(1));
+        assert_eq!(counts(&"d"), None);
+    }
+
+    #[test]
+    fn test_update_with_argument_order() {
+        let m = pm_update(pm_empty(), "k", vec![1]);
+        let m = pm_update_with(m, "k", vec![2], |mut old, new| {
+            old.extend(new);
+            old
+        });
+        assert_eq!(m(&"k"), Some(vec![1, 2]));
+    }
+
+    #[test]
+    fn test_filter() {
+        let map = pm_filter(example_map(), |_, v| *v);
+        assert_eq!(map(&"Church".to_string()), Some(true));
+        assert_eq!(map(&"Turing".to_string()), None);
+    }
+
+    #[test]
+    fn test_filter_by_key() {
+        let map = pm_filter(example_map(), |k: &String, _| k.starts_with('T'));
+        assert_eq!(map(&"Church".to_string()), None);
+        assert_eq!(map(&"Turing".to_string()), Some(false));
+    }
+
+    #[test]
+    fn test_restrict() {
+        let map = pm_restrict(example_map(), &["Turing".to_string(), "Other".to_string()]);
+        assert_eq!(map(&"Church".to_string()), None);
+        assert_eq!(map(&"Turing".to_string()), Some(false));
+        assert_eq!(map(&"Other".to_string()), None);
+    }
+
+    fn counts() -> (PartialMap<&'static str, i32>, PartialMap<&'static str, i32>) {
+        (
+            pm_update(pm_update(pm_empty(), "a", 1), "b", 2),
+            pm_update(pm_update(pm_empty(), "b", 10), "c", 20),
+        )
+    }
+
+    #[test]
+    fn test_union_resolves_conflicts() {
+        let (m1, m2) = counts();
+        let map = pm_union(m1, m2, |v1, v2| v1 + v2);
+        assert_eq!(map(&"a"), Some(1));
+        assert_eq!(map(&"b"), Some(12));
+        assert_eq!(map(&"c"), Some(20));
+        assert_eq!(map(&"d"), None);
+    }
+
+    #[test]
+    fn test_union_right_biased() {
+        let (m1, m2) = counts();
+        assert_eq!(pm_union_right(m1, m2)(&"b"), Some(10));
+    }
+
+    #[test]
+    fn test_disjoint_union() {
+        let (m1, m2) = counts();
+        assert!(pm_disjoint_union(m1.clone(), m2.clone(), &["a", "b", "c"]).is_none());
+        let map = pm_disjoint_union(m1, pm_remove(m2, "b"), &["a", "b", "c"]).unwrap();
+        assert_eq!(map(&"a"), Some(1));
+        assert_eq!(map(&"b"), Some(2));
+        assert_eq!(map(&"c"), Some(20));
+    }
+
+    #[test]
+    fn test_included_in() {
+        let (m1, m2) = counts();
+        let keys = ["a", "b", "c", "d"];
+        let union = pm_union_right(m2.clone(), m1.clone());
+        assert!(pm_included_in(&m1, &union, &keys));
+        assert!(!pm_included_in(&m2, &union, &keys));
+        assert!(pm_included_in(&pm_empty(), &m1, &keys));
+    }
+}
+
+/// A two-key total map, curried: looking up `k1` yields a total map over
+/// the second key. Useful for relations and per-procedure environments.
+pub type TotalMap2<K1, K2, V> = TotalMap<K1, TotalMap<K2, V>>;
+
+pub fn tm2_empty<K1: 'static + PartialEq, K2: 'static + PartialEq, V: 'static + Clone>(
+    default_v: V,
+) -> TotalMap2<K1, K2, V> {
+    tm_empty(tm_empty(default_v))
+}
+
+/// Function tm2_update updates the inner map found at `k1`.
+pub fn tm2_update<K1: 'static + PartialEq, K2: 'static + PartialEq, V: 'static + Clone>(
+    m: TotalMap2<K1, K2, V>,
+    k1: K1,
+    k2: K2,
+    v: V,
+) -> TotalMap2<K1, K2, V> {
+    let inner = tm_update(m(&k1), k2, v);
+    tm_update(m, k1, inner)
+}
+
+pub fn tm2_apply<K1, K2, V>(m: &TotalMap2<K1, K2, V>, k1: &K1, k2: &K2) -> V {
+    m(k1)(k2)
+}
+
+/// Function tm2_flatten uncurries a two-key map into a map over pairs.
+pub fn tm2_flatten<K1: 'static, K2: 'static, V: 'static>(
+    m: TotalMap2<K1, K2, V>,
+) -> TotalMap<(K1, K2), V> {
+    Rc::new(move |(k1, k2): &(K1, K2)| m(k1)(k2))
+}
+
+/// Function tm2_curry is the inverse of `tm2_flatten`.
+pub fn tm2_curry<K1: 'static + Clone, K2: 'static + Clone, V: 'static>(
+    m: TotalMap<(K1, K2), V>,
+) -> TotalMap2<K1, K2, V> {
+    Rc::new(move |k1: &K1| {
+        let m = Rc::clone(&m);
+        let k1 = k1.clone();
+        Rc::new(move |k2: &K2| m(&(k1.clone(), k2.clone())))
+    })
+}
+
+#[cfg(test)]
+mod test_total_map2 {
+    use super::{tm2_apply, tm2_curry, tm2_empty, tm2_flatten, tm2_update, TotalMap2};
+
+    /// Per-procedure variable environments:
+    /// "main" -> {"x" !-> 1}, "f" -> {"x" !-> 2; "y" !-> 3}.
+    fn example_map() -> TotalMap2<&'static str, &'static str, i32> {
+        let m = tm2_update(tm2_empty(0), "main", "x", 1);
+        let m = tm2_update(m, "f", "x", 2);
+        tm2_update(m, "f", "y", 3)
+    }
+
+    #[test]
+    fn test_apply() {
+        let m = example_map();
+        assert_eq!(tm2_apply(&m, &"main", &"x"), 1);
+        assert_eq!(tm2_apply(&m, &"main", &"y"), 0);
+        assert_eq!(tm2_apply(&m, &"f", &"x"), 2);
+        assert_eq!(tm2_apply(&m, &"f", &"y"), 3);
+        assert_eq!(tm2_apply(&m, &"g", &"x"), 0);
+    }
+
+    #[test]
+    fn test_inner_maps_are_independent() {
+        let m = tm2_update(example_map(), "main", "x", 10);
+        assert_eq!(tm2_apply(&m, &"main", &"x"), 10);
+        assert_eq!(tm2_apply(&m, &"f", &"x"), 2);
+    }
+
+    #[test]
+    fn test_flatten_and_curry() {
+        let flat = tm2_flatten(example_map());
+        assert_eq!(flat(&("f", "y")), 3);
+        assert_eq!(flat(&("main", "y")), 0);
+        let curried = tm2_curry(flat);
+        assert_eq!(tm2_apply(&curried, &"main", &"x"), 1);
+        assert_eq!(tm2_apply(&curried, &"f", &"y"), 3);
     }
 }
@@ -0,0 +1,85 @@
+//! Method syntax for the closure maps, so a state can be written as
+//! `m.update("x", 3).update("y", 4).get("x")` instead of nested calls. The
+//! methods are thin wrappers over the free functions; keys go through
+//! `Into`, which lets string literals stand for `String` keys.
+
+use super::{pm_remove, pm_update, tm_update, PartialMap, TotalMap};
+
+pub trait MapExt<K, V> {
+    /// `tm_update` as a method: `k !-> v; self`.
+    fn update(&self, k: impl Into<K>, v: V) -> Self;
+
+    /// Apply the map to `k`.
+    fn get(&self, k: impl Into<K>) -> V;
+}
+
+impl<K: 'static + PartialEq, V: 'static + Clone> MapExt<K, V> for TotalMap<K, V> {
+    fn update(&self, k: impl Into<K>, v: V) -> Self {
+        tm_update(self.clone(), k.into(), v)
+    }
+
+    fn get(&self, k: impl Into<K>) -> V {
+        self(&k.into())
+    }
+}
+
+/// Extra methods for partial maps. Since a `PartialMap<K, V>` is a
+/// `TotalMap<K, Option<V>>`, `update` and `get` from `MapExt` work on
+/// `Option<V>`; these add the `|->` form and defaults for unbound keys.
+pub trait PartialMapExt<K, V> {
+    /// `pm_update` as a method: `k |-> v; self`.
+    fn bind(&self, k: impl Into<K>, v: V) -> Self;
+
+    /// `pm_remove` as a method.
+    fn unbind(&self, k: impl Into<K>) -> Self;
+
+    /// The value bound to `k`, or `default` if it is unbound.
+    fn get_or(&self, k: impl Into<K>, default: V) -> V;
+}
+
+impl<K: 'static + PartialEq, V: 'static + Clone> PartialMapExt<K, V> for PartialMap<K, V> {
+    fn bind(&self, k: impl Into<K>, v: V) -> Self {
+        pm_update(self.clone(), k.into(), v)
+    }
+
+    fn unbind(&self, k: impl Into<K>) -> Self {
+        pm_remove(self.clone(), k.into())
+    }
+
+    fn get_or(&self, k: impl Into<K>, default: V) -> V {
+        self(&k.into()).unwrap_or(default)
+    }
+}
+
+#[cfg(test)]
+mod test_map_ext {
+    use super::{MapExt, PartialMapExt};
+    use crate::map::{pm_empty, tm_empty, PartialMap, TotalMap};
+
+    #[test]
+    fn test_chained_updates() {
+        let m: TotalMap<String, i32> = tm_empty(0);
+        let m = m.update("x", 3).update("y", 4);
+        assert_eq!(m.get("x"), 3);
+        assert_eq!(m.get("y"), 4);
+        assert_eq!(m.get("z"), 0);
+    }
+
+    #[test]
+    fn test_update_does_not_change_original() {
+        let m: TotalMap<&str, bool> = tm_empty(false);
+        let updated = m.update("x", true);
+        assert!(updated.get("x"));
+        assert!(!m.get("x"));
+    }
+
+    #[test]
+    fn test_partial_map_methods() {
+        let m: PartialMap<String, i32> = pm_empty();
+        let m = m.bind("x", 3).bind("y", 4).unbind("y");
+        assert_eq!(m.get("x"), Some(3));
+        assert_eq!(m.get("y"), None);
+        assert_eq!(m.get_or("y", -1), -1);
+        assert_eq!(m.get_or("x", -1), 3);
+    }
+}
@@ -0,0 +1,144 @@
+use std::{collections::HashMap, hash::Hash, rc::Rc};
+
+use super::TotalMap;
+
+/// A total map backed by a `HashMap`, for when chains of `tm_update`
+/// closures get too long: every lookup on a closure map walks all the
+/// updates, while this one is a single hash lookup.
+///
+/// It is still persistent. `update` consumes the map and returns the new
+/// one; the table is only copied when another clone still holds on to it,
+/// so threading a map through an interpreter updates in place.
+#[derive(Debug, Clone)]
+pub struct FastTotalMap<K, V> {
+    default_v: V,
+    overrides: Rc<HashMap<K, V>>,
+}
+
+impl<K: Eq + Hash + Clone, V: Clone> FastTotalMap<K, V> {
+    pub fn empty(default_v: V) -> Self {
+        FastTotalMap {
+            default_v,
+            overrides: Rc::new(HashMap::new()),
+        }
+    }
+
+    pub fn update(mut self, k: K, v: V) -> Self {
+        Rc::make_mut(&mut self.overrides).insert(k, v);
+        self
+    }
+
+    pub fn apply(&self, k: &K) -> V {
+        self.overrides.get(k).unwrap_or(&self.default_v).clone()
+    }
+
+    pub fn default_value(&self) -> &V {
+        &self.default_v
+    }
+
+    /// The keys that have been updated, in no particular order.
+    pub fn iter_overrides(&self) -> impl Iterator<Item = (&K, &V)> {
+        self.overrides.iter()
+    }
+}
+
+impl<K: 'static + Eq + Hash + Clone, V: 'static + Clone> FastTotalMap<K, V> {
+    /// Wrap the table in the closure representation.
+    pub fn to_total_map(&self) -> TotalMap<K, V> {
+        let m = self.clone();
+        Rc::new(move |k: &K| m.apply(k))
+    }
+}
+
+/// Collecting pairs gives a map whose default is `V::default()`.
+impl<K: Eq + Hash + Clone, V: Clone + Default> FromIterator<(K, V)> for FastTotalMap<K, V> {
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(pairs: I) -> Self {
+        FastTotalMap {
+            default_v: V::default(),
+            overrides: Rc::new(pairs.into_iter().collect()),
+        }
+    }
+}
+
+impl<K: Eq + Hash + Clone, V: Clone + Default> From<HashMap<K, V>> for FastTotalMap<K, V> {
+    fn from(h: HashMap<K, V>) -> Self {
+        FastTotalMap {
+            default_v: V::default(),
+            overrides: Rc::new(h),
+        }
+    }
+}
+
+impl<K: Eq + Hash, V: PartialEq> PartialEq for FastTotalMap<K, V> {
+    fn eq(&self, other: &Self) -> bool {
+        let agree = |a: &Self, b: &Self| {
+            a.overrides
+                .iter()
+                .all(|(k, v)| b.overrides.get(k).unwrap_or(&b.default_v) == v)
+        };
+        self.default_v == other.default_v && agree(self, other) && agree(other, self)
+    }
+}
+
+#[cfg(test)]
+mod test_fast_total_map {
+    use super::FastTotalMap;
+
+    fn example_map() -> FastTotalMap<String, bool> {
+        FastTotalMap::empty(false)
+            .update("foo".to_string(), true)
+            .update("bar".to_string(), true)
+    }
+
+    #[test]
+    fn test_apply() {
+        let map = example_map();
+        assert!(map.apply(&"foo".to_string()));
+        assert!(map.apply(&"bar".to_string()));
+        assert!(!map.apply(&"box".to_string()));
+    }
+
+    #[test]
+    fn test_update_is_persistent() {
+        let old = example_map();
+        let new = old.clone().update("foo".to_string(), false);
+        assert!(old.apply(&"foo".to_string()));
+        assert!(!new.apply(&"foo".to_string()));
+    }
+
+    #[test]
+    fn test_many_updates() {
+        let mut map = FastTotalMap::empty(0);
+        for i in 0..100_000 {
+            map = map.update(i % 1000, i);
+        }
+        assert_eq!(map.apply(&7), 99_007);
+        assert_eq!(map.apply(&1000), 0);
+        assert_eq!(map.iter_overrides().count(), 1000);
+    }
+
+    #[test]
+    fn test_eq_ignores_updates_to_default() {
+        assert_eq!(
+            example_map(),
+            example_map().update("box".to_string(), false)
+        );
+        assert_ne!(example_map(), example_map().update("box".to_string(), true));
+    }
+
+    #[test]
+    fn test_from_iter_and_hashmap() {
+        let collected: FastTotalMap<&str, i32> = [("x", 1), ("y", 2)].into_iter().collect();
+        assert_eq!(collected.apply(&"y"), 2);
+        assert_eq!(collected.apply(&"z"), 0);
+        let h = std::collections::HashMap::from([("x", 1), ("y", 2)]);
+        assert_eq!(FastTotalMap::from(h), collected);
+    }
+
+    #[test]
+    fn test_to_total_map() {
+        let map = example_map().to_total_map();
+        assert!(map(&"bar".to_string()));
+        assert!(!map(&"box".to_string()));
+    }
+}
@@ -0,0 +1,204 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    hash::Hash,
+    rc::Rc,
+};
+
+use super::{tm_empty, tm_update, TotalMap};
+
+/// A total map that remembers which keys have ever been updated. The
+/// lookups still go through the closure representation; the recorded
+/// domain is what lets a final state be listed, counted and printed.
+#[derive(Clone)]
+pub struct FiniteMap<K, V> {
+    map: TotalMap<K, V>,
+    default_v: V,
+    /// The keys in the order they were first updated.
+    domain: Vec<K>,
+    /// The same keys, so that an update can tell a new key in constant
+    /// time.
+    seen: HashSet<K>,
+}
+
+impl<K: 'static + Eq + Hash + Clone, V: 'static + Clone> FiniteMap<K, V> {
+    pub fn empty(default_v: V) -> Self {
+        FiniteMap {
+            map: tm_empty(default_v.clone()),
+            default_v,
+            domain: Vec::new(),
+            seen: HashSet::new(),
+        }
+    }
+
+    pub fn update(mut self, k: K, v: V) -> Self {
+        if self.seen.insert(k.clone()) {
+            self.domain.push(k.clone());
+        }
+        self.map = tm_update(self.map, k, v);
+        self
+    }
+
+    pub fn apply(&self, k: &K) -> V {
+        (self.map)(k)
+    }
+
+    pub fn default_value(&self) -> &V {
+        &self.default_v
+    }
+
+    /// The updated keys, in the order they were first updated.
+    pub fn keys(&self) -> &[K] {
+        &self.domain
+    }
+
+    pub fn len(&self) -> usize {
+        self.domain.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.domain.is_empty()
+    }
+
+    /// The updated keys together with their current values.
+    pub fn iter(&self) -> impl Iterator<Item = (&K, V)> + '_ {
+        self.domain.iter().map(move |k| (k, self.apply(k)))
+    }
+
+    pub fn to_total_map(&self) -> TotalMap<K, V> {
+        Rc::clone(&self.map)
+    }
+
+    pub fn to_hashmap(&self) -> HashMap<K, V> {
+        self.iter().map(|(k, v)| (k.clone(), v)).collect()
+    }
+}
+
+/// Collecting pairs gives a finite map whose default is `V::default()`.
+impl<K: 'static + Eq + Hash + Clone, V: 'static + Clone + Default> FromIterator<(K, V)>
+    for FiniteMap<K, V>
+{
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(pairs: I) -> Self {
+        pairs
+            .into_iter()
+            .fold(FiniteMap::empty(V::default()), |m, (k, v)| m.update(k, v))
+    }
+}
+
+impl<K: 'static + Eq + Hash + Clone, V: 'static + Clone + Default> From<HashMap<K, V>>
+    for FiniteMap<K, V>
+{
+    fn from(h: HashMap<K, V>) -> Self {
+        h.into_iter().collect()
+    }
+}
+
+/// Printed as `{X !-> 5; Y !-> 120; _ !-> 0}`.
+impl<K, V> fmt::Display for FiniteMap<K, V>
+where
+    K: 'static + Eq + Hash + Clone + fmt::Display,
+    V: 'static + Clone + fmt::Display,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{{")?;
+        for (k, v) in self.iter() {
+            write!(f, "{} !-> {}; ", k, v)?;
+        }
+        write!(f, "_ !-> {}}}", self.default_v)
+    }
+}
+
+impl<K, V> fmt::Debug for FiniteMap<K, V>
+where
+    K: 'static + Eq + Hash + Clone + fmt::Debug,
+    V: 'static + Clone + fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+/// Two finite maps are equal when they agree on the keys either of them has
+/// recorded and have the same default.
+impl<K: 'static + Eq + Hash + Clone, V: 'static + Clone + PartialEq> PartialEq for FiniteMap<K, V> {
+    fn eq(&self, other: &Self) -> bool {
+        self.default_v == other.default_v
+            && self
+                .domain
+                .iter()
+                .chain(other.domain.iter())
+                .all(|k| self.apply(k) == other.apply(k))
+    }
+}
+
+#[cfg(test)]
+mod test_finite_map {
+    use std::collections::HashMap;
+
+    use super::FiniteMap;
+
+    fn example_state() -> FiniteMap<String, i64> {
+        FiniteMap::empty(0)
+            .update("X".to_string(), 5)
+            .update("Y".to_string(), 1)
+            .update("Y".to_string(), 120)
+    }
+
+    #[test]
+    fn test_keys_and_len() {
+        let state = example_state();
+        assert_eq!(state.keys(), ["X".to_string(), "Y".to_string()]);
+        assert_eq!(state.len(), 2);
+        assert!(FiniteMap::<String, i64>::empty(0).is_empty());
+    }
+
+    #[test]
+    fn test_keys_updated_again() {
+        let mut m = FiniteMap::empty(0);
+        for i in 0..5_000 {
+            m = m.update(i % 1_000, i);
+        }
+        assert_eq!(m.len(), 1_000);
+        assert_eq!(m.keys()[..3], [0, 1, 2]);
+        assert_eq!(m.apply(&7), 4_007);
+    }
+
+    #[test]
+    fn test_apply() {
+        let state = example_state();
+        assert_eq!(state.apply(&"Y".to_string()), 120);
+        assert_eq!(state.apply(&"Z".to_string()), 0);
+    }
+
+    #[test]
+    fn test_to_hashmap() {
+        let expected = HashMap::from([("X".to_string(), 5), ("Y".to_string(), 120)]);
+        assert_eq!(example_state().to_hashmap(), expected);
+    }
+
+    #[test]
+    fn test_from_iter_and_hashmap() {
+        let collected: FiniteMap<String, i64> = [("X".to_string(), 5), ("Y".to_string(), 120)]
+            .into_iter()
+            .collect();
+        assert_eq!(collected, example_state());
+        let from_hashmap = FiniteMap::from(example_state().to_hashmap());
+        assert_eq!(from_hashmap, example_state());
+        assert_eq!(from_hashmap.len(), 2);
+    }
+
+    #[test]
+    fn test_display() {
+        assert_eq!(example_state().to_string(), "{X !-> 5; Y !-> 120; _ !-> 0}");
+    }
+
+    #[test]
+    fn test_eq() {
+        let other = FiniteMap::empty(0)
+            .update("Y".to_string(), 120)
+            .update("X".to_string(), 5)
+            .update("Z".to_string(), 0);
+        assert_eq!(example_state(), other);
+        assert_ne!(example_state(), other.update("Z".to_string(), 1));
+    }
+}
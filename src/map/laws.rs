@@ -0,0 +1,244 @@
+//! The total-map laws of the Maps chapter, as executable checks. Every
+//! check takes the map to start from, the backend's `update` and `apply`
+//! functions, and the keys and values to sample, so any implementation can
+//! be compared against the reference closure maps, e.g.
+//! `check_all_laws(&m, ReifiedTotalMap::update, ReifiedTotalMap::apply, &keys, &values)`
+//! or, for the closure maps, `check_all_laws(&m, tm_update, tm_apply, ...)`.
+//!
+//! Extensional equality of two maps is approximated by agreement on the
+//! sampled keys.
+
+use std::fmt;
+
+/// A law that failed, with the key on which the two sides disagreed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LawViolation<K> {
+    pub law: &'static str,
+    pub key: K,
+}
+
+impl<K: fmt::Debug> fmt::Display for LawViolation<K> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} fails at key {:?}", self.law, self.key)
+    }
+}
+
+fn agree<M, K: Clone, V: PartialEq>(
+    law: &'static str,
+    m1: &M,
+    m2: &M,
+    apply: &impl Fn(&M, &K) -> V,
+    keys: &[K],
+) -> Result<(), LawViolation<K>> {
+    match keys.iter().find(|k| apply(m1, k) != apply(m2, k)) {
+        Some(k) => Err(LawViolation {
+            law,
+            key: k.clone(),
+        }),
+        None => Ok(()),
+    }
+}
+
+/// `(k !-> v; m) k = v`
+pub fn check_update_eq<M: Clone, K: Clone, V: Clone + PartialEq>(
+    m: &M,
+    update: impl Fn(M, K, V) -> M,
+    apply: impl Fn(&M, &K) -> V,
+    keys: &[K],
+    values: &[V],
+) -> Result<(), LawViolation<K>> {
+    for k in keys {
+        for v in values {
+            if apply(&update(m.clone(), k.clone(), v.clone()), k) != *v {
+                return Err(LawViolation {
+                    law: "t_update_eq",
+                    key: k.clone(),
+                });
+            }
+        }
+    }
+    Ok(())
+}
+
+/// `k1 <> k2 -> (k1 !-> v; m) k2 = m k2`
+pub fn check_update_neq<M: Clone, K: Clone + PartialEq, V: Clone + PartialEq>(
+    m: &M,
+    update: impl Fn(M, K, V) -> M,
+    apply: impl Fn(&M, &K) -> V,
+    keys: &[K],
+    values: &[V],
+) -> Result<(), LawViolation<K>> {
+    for k1 in keys {
+        for v in values {
+            let updated = update(m.clone(), k1.clone(), v.clone());
+            for k2 in keys.iter().filter(|k2| *k2 != k1) {
+                if apply(&updated, k2) != apply(m, k2) {
+                    return Err(LawViolation {
+                        law: "t_update_neq",
+                        key: k2.clone(),
+                    });
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// `(k !-> v2; k !-> v1; m) = (k !-> v2; m)`
+pub fn check_update_shadow<M: Clone, K: Clone, V: Clone + PartialEq>(
+    m: &M,
+    update: impl Fn(M, K, V) -> M,
+    apply: impl Fn(&M, &K) -> V,
+    keys: &[K],
+    values: &[V],
+) -> Result<(), LawViolation<K>> {
+    for k in keys {
+        for v1 in values {
+            for v2 in values {
+                let twice = update(
+                    update(m.clone(), k.clone(), v1.clone()),
+                    k.clone(),
+                    v2.clone(),
+                );
+                let once = update(m.clone(), k.clone(), v2.clone());
+                agree("t_update_shadow", &twice, &once, &apply, keys)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// `(k !-> m k; m) = m`
+pub fn check_update_same<M: Clone, K: Clone, V: Clone + PartialEq>(
+    m: &M,
+    update: impl Fn(M, K, V) -> M,
+    apply: impl Fn(&M, &K) -> V,
+    keys: &[K],
+) -> Result<(), LawViolation<K>> {
+    for k in keys {
+        let same = update(m.clone(), k.clone(), apply(m, k));
+        agree("t_update_same", &same, m, &apply, keys)?;
+    }
+    Ok(())
+}
+
+/// `k2 <> k1 -> (k1 !-> v1; k2 !-> v2; m) = (k2 !-> v2; k1 !-> v1; m)`
+pub fn check_update_permute<M: Clone, K: Clone + PartialEq, V: Clone + PartialEq>(
+    m: &M,
+    update: impl Fn(M, K, V) -> M,
+    apply: impl Fn(&M, &K) -> V,
+    keys: &[K],
+    values: &[V],
+) -> Result<(), LawViolation<K>> {
+    for k1 in keys {
+        for k2 in keys.iter().filter(|k2| *k2 != k1) {
+            for v1 in values {
+                for v2 in values {
+                    let m12 = update(
+                        update(m.clone(), k2.clone(), v2.clone()),
+                        k1.clone(),
+                        v1.clone(),
+                    );
+                    let m21 = update(
+                        update(m.clone(), k1.clone(), v1.clone()),
+                        k2.clone(),
+                        v2.clone(),
+                    );
+                    agree("t_update_permute", &m12, &m21, &apply, keys)?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Run every check above.
+pub fn check_all_laws<M: Clone, K: Clone + PartialEq, V: Clone + PartialEq>(
+    m: &M,
+    update: impl Fn(M, K, V) -> M,
+    apply: impl Fn(&M, &K) -> V,
+    keys: &[K],
+    values: &[V],
+) -> Result<(), LawViolation<K>> {
+    check_update_eq(m, &update, &apply, keys, values)?;
+    check_update_neq(m, &update, &apply, keys, values)?;
+    check_update_shadow(m, &update, &apply, keys, values)?;
+    check_update_same(m, &update, &apply, keys)?;
+    check_update_permute(m, &update, &apply, keys, values)
+}
+
+#[cfg(test)]
+mod test_map_laws {
+    use std::rc::Rc;
+
+    use super::*;
+    use crate::map::{
+        tm_apply, tm_empty, tm_update, FastTotalMap, FiniteMap, ReifiedTotalMap, TotalMap,
+    };
+
+    const KEYS: [&str; 4] = ["w", "x", "y", "z"];
+    const VALUES: [i32; 3] = [0, 1, 2];
+
+    #[test]
+    fn test_closure_map() {
+        let m: TotalMap<&str, i32> = tm_update(tm_empty(0), "x", 1);
+        assert_eq!(
+            check_all_laws(&m, tm_update, tm_apply, &KEYS, &VALUES),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn test_reified_map() {
+        let m = ReifiedTotalMap::empty(0).update("x", 1);
+        let result = check_all_laws(
+            &m,
+            ReifiedTotalMap::update,
+            ReifiedTotalMap::apply,
+            &KEYS,
+            &VALUES,
+        );
+        assert_eq!(result, Ok(()));
+    }
+
+    #[test]
+    fn test_fast_map() {
+        let m = FastTotalMap::empty(0).update("x", 1);
+        let result = check_all_laws(
+            &m,
+            FastTotalMap::update,
+            FastTotalMap::apply,
+            &KEYS,
+            &VALUES,
+        );
+        assert_eq!(result, Ok(()));
+    }
+
+    #[test]
+    fn test_finite_map() {
+        let m = FiniteMap::empty(0).update("x", 1);
+        let result = check_all_laws(&m, FiniteMap::update, FiniteMap::apply, &KEYS, &VALUES);
+        assert_eq!(result, Ok(()));
+    }
+
+    #[test]
+    fn test_broken_map_is_caught() {
+        // An "update" that forgets everything but the latest binding.
+        let forgetful = |_: TotalMap<&'static str, i32>, k: &'static str, v: i32| {
+            let m: TotalMap<&str, i32> = Rc::new(move |k_1: &&str| if *k_1 == k { v } else { 0 });
+            m
+        };
+        let m: TotalMap<&str, i32> = tm_update(tm_empty(0), "x", 1);
+        assert_eq!(
+            check_update_eq(&m, forgetful, tm_apply, &KEYS, &VALUES),
+            Ok(())
+        );
+        assert_eq!(
+            check_update_neq(&m, forgetful, tm_apply, &KEYS, &VALUES),
+            Err(LawViolation {
+                law: "t_update_neq",
+                key: "x"
+            })
+        );
+    }
+}
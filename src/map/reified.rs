@@ -0,0 +1,262 @@
+use std::{fmt, mem, rc::Rc};
+
+use super::TotalMap;
+
+/// A total map kept as the data structure a chain of `tm_update`s would
+/// build, rather than as an opaque closure. It answers lookups exactly like
+/// the closure form, but it can also be printed, compared and walked.
+#[derive(Clone)]
+pub enum ReifiedTotalMap<K, V> {
+    /// Every key maps to the default element.
+    Empty(V),
+    /// `Update(m, k, v)` is `k !-> v; m`.
+    Update(Rc<ReifiedTotalMap<K, V>>, K, V),
+}
+
+impl<K: PartialEq, V: Clone> ReifiedTotalMap<K, V> {
+    pub fn empty(default_v: V) -> Self {
+        ReifiedTotalMap::Empty(default_v)
+    }
+
+    pub fn update(self, k: K, v: V) -> Self {
+        ReifiedTotalMap::Update(Rc::new(self), k, v)
+    }
+
+    /// Look `k` up. The chain is walked with a loop, so long update
+    /// histories don't cost stack depth.
+    pub fn apply(&self, k: &K) -> V {
+        let mut current = self;
+        loop {
+            match current {
+                ReifiedTotalMap::Empty(default_v) => return default_v.clone(),
+                ReifiedTotalMap::Update(m, k_1, v) => {
+                    if k_1 == k {
+                        return v.clone();
+                    }
+                    current = m;
+                }
+            }
+        }
+    }
+
+    pub fn default_value(&self) -> &V {
+        let mut current = self;
+        loop {
+            match current {
+                ReifiedTotalMap::Empty(default_v) => return default_v,
+                ReifiedTotalMap::Update(m, _, _) => current = m,
+            }
+        }
+    }
+
+    /// Iterate over the bindings that are actually visible, most recent
+    /// first. Updates shadowed by a later update of the same key are
+    /// skipped.
+    pub fn iter_overrides(&self) -> Overrides<'_, K, V> {
+        Overrides {
+            current: self,
+            seen: Vec::new(),
+        }
+    }
+}
+
+impl<K: PartialEq + Clone, V: Clone> ReifiedTotalMap<K, V> {
+    /// Rebuild the chain from the visible overrides only, dropping every
+    /// update that has been shadowed. Lookups give the same answers but
+    /// walk at most one node per distinct key.
+    pub fn compact(&self) -> Self {
+        let overrides: Vec<(K, V)> = self
+            .iter_overrides()
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect();
+        overrides
+            .into_iter()
+            .rev()
+            .fold(Self::empty(self.default_value().clone()), |m, (k, v)| {
+                m.update(k, v)
+            })
+    }
+
+    /// The number of updates in the chain, shadowed ones included.
+    pub fn depth(&self) -> usize {
+        let mut depth = 0;
+        let mut current = self;
+        while let ReifiedTotalMap::Update(m, _, _) = current {
+            depth += 1;
+            current = m;
+        }
+        depth
+    }
+}
+
+impl<K: 'static + PartialEq + Clone, V: 'static + Clone> ReifiedTotalMap<K, V> {
+    /// Forget the structure and get back the closure representation.
+    pub fn to_total_map(&self) -> TotalMap<K, V> {
+        let m = self.clone();
+        Rc::new(move |k: &K| m.apply(k))
+    }
+}
+
+/// The derived drop would recurse once per update, so a long chain would
+/// overflow the stack. The nodes only this map holds are unlinked one at a
+/// time instead, down to the first one something else still holds.
+impl<K, V> Drop for ReifiedTotalMap<K, V> {
+    fn drop(&mut self) {
+        let ReifiedTotalMap::Update(link, _, _) = self else {
+            return;
+        };
+        let mut stop = &*link;
+        while Rc::strong_count(stop) == 1 {
+            match &**stop {
+                ReifiedTotalMap::Update(next, _, _) => stop = next,
+                ReifiedTotalMap::Empty(_) => break,
+            }
+        }
+        // Each node taken off the chain is left pointing at `stop`, so
+        // dropping it goes no further.
+        let stop = Rc::clone(stop);
+        let mut next = mem::replace(link, Rc::clone(&stop));
+        while let Ok(mut node) = Rc::try_unwrap(next) {
+            let ReifiedTotalMap::Update(link, _, _) = &mut node else {
+                break;
+            };
+            next = mem::replace(link, Rc::clone(&stop));
+        }
+    }
+}
+
+pub struct Overrides<'a, K, V> {
+    current: &'a ReifiedTotalMap<K, V>,
+    seen: Vec<&'a K>,
+}
+
+impl<'a, K: PartialEq, V> Iterator for Overrides<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match self.current {
+                ReifiedTotalMap::Empty(_) => return None,
+                ReifiedTotalMap::Update(m, k, v) => {
+                    self.current = m;
+                    if !self.seen.contains(&k) {
+                        self.seen.push(k);
+                        return Some((k, v));
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Printed in the `!->` notation of Software Foundations, e.g.
+/// `{"bar" !-> true; "foo" !-> true; _ !-> false}`.
+impl<K: PartialEq + fmt::Debug, V: Clone + fmt::Debug> fmt::Debug for ReifiedTotalMap<K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{{")?;
+        for (k, v) in self.iter_overrides() {
+            write!(f, "{:?} !-> {:?}; ", k, v)?;
+        }
+        write!(f, "_ !-> {:?}}}", self.default_value())
+    }
+}
+
+/// Two maps are equal when they agree on every key. Keys that neither map
+/// overrides go to the defaults, so it is enough to compare the defaults and
+/// the visible overrides of both sides.
+impl<K: PartialEq, V: Clone + PartialEq> PartialEq for ReifiedTotalMap<K, V> {
+    fn eq(&self, other: &Self) -> bool {
+        self.default_value() == other.default_value()
+            && self.iter_overrides().all(|(k, v)| other.apply(k) == *v)
+            && other.iter_overrides().all(|(k, v)| self.apply(k) == *v)
+    }
+}
+
+#[cfg(test)]
+mod test_reified_total_map {
+    use super::ReifiedTotalMap;
+
+    fn example_map() -> ReifiedTotalMap<String, bool> {
+        ReifiedTotalMap::empty(false)
+            .update("foo".to_string(), true)
+            .update("bar".to_string(), true)
+    }
+
+    #[test]
+    fn test_apply() {
+        let map = example_map();
+        assert!(map.apply(&"foo".to_string()));
+        assert!(map.apply(&"bar".to_string()));
+        assert!(!map.apply(&"box".to_string()));
+    }
+
+    #[test]
+    fn test_debug() {
+        let map = example_map().update("foo".to_string(), false);
+        assert_eq!(
+            format!("{:?}", map),
+            r#"{"foo" !-> false; "bar" !-> true; _ !-> false}"#
+        );
+    }
+
+    #[test]
+    fn test_iter_overrides_skips_shadowed() {
+        let map = example_map().update("foo".to_string(), false);
+        let overrides: Vec<_> = map.iter_overrides().collect();
+        assert_eq!(
+            overrides,
+            vec![(&"foo".to_string(), &false), (&"bar".to_string(), &true)]
+        );
+    }
+
+    #[test]
+    fn test_eq_is_extensional() {
+        let permuted = ReifiedTotalMap::empty(false)
+            .update("bar".to_string(), true)
+            .update("foo".to_string(), true);
+        assert_eq!(example_map(), permuted);
+        assert_ne!(example_map(), example_map().update("box".to_string(), true));
+    }
+
+    #[test]
+    fn test_compact() {
+        let mut map = ReifiedTotalMap::empty(0);
+        for i in 0..3000 {
+            map = map.update(i % 30, i);
+        }
+        let compacted = map.compact();
+        assert_eq!(map.depth(), 3000);
+        assert_eq!(compacted.depth(), 30);
+        assert_eq!(compacted, map);
+        for k in 0..40 {
+            assert_eq!(compacted.apply(&k), map.apply(&k));
+        }
+        assert_eq!(
+            compacted.iter_overrides().collect::<Vec<_>>(),
+            map.iter_overrides().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_drop_long_chain() {
+        let mut map = ReifiedTotalMap::empty(0);
+        for i in 0..1_000_000 {
+            map = map.update(i % 10, i);
+        }
+        let shorter = map.clone();
+        let map = map.update(3, -1);
+        assert_eq!(map.depth(), 1_000_001);
+        drop(map);
+        // What the dropped map shared is still there.
+        assert_eq!(shorter.depth(), 1_000_000);
+        assert_eq!(shorter.apply(&3), 999_993);
+        drop(shorter);
+    }
+
+    #[test]
+    fn test_to_total_map() {
+        let map = example_map().to_total_map();
+        assert!(map(&"foo".to_string()));
+        assert!(!map(&"box".to_string()));
+    }
+}
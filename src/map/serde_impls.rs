@@ -0,0 +1,204 @@
+//! Serde support for the map forms that know their overrides. Both are
+//! written as `{"default": d, "overrides": {k: v, ...}}`, so with JSON a
+//! state over string keys reads naturally. Deserializing rebuilds the map
+//! by updating an empty map with every override.
+
+use std::{fmt, hash::Hash, marker::PhantomData};
+
+use serde::{
+    de::{MapAccess, Visitor},
+    ser::SerializeStruct,
+    Deserialize, Deserializer, Serialize, Serializer,
+};
+
+use super::{FiniteMap, ReifiedTotalMap};
+
+const FIELDS: &[&str] = &["default", "overrides"];
+
+fn serialize_map<'a, S, K, V>(
+    serializer: S,
+    name: &'static str,
+    default_v: &V,
+    overrides: impl Iterator<Item = (&'a K, V)>,
+) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+    K: 'a + Serialize,
+    V: Serialize,
+{
+    struct Overrides<'a, K, V>(Vec<(&'a K, V)>);
+
+    impl<K: Serialize, V: Serialize> Serialize for Overrides<'_, K, V> {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            serializer.collect_map(self.0.iter().map(|(k, v)| (*k, v)))
+        }
+    }
+
+    let mut s = serializer.serialize_struct(name, 2)?;
+    s.serialize_field("default", default_v)?;
+    s.serialize_field("overrides", &Overrides(overrides.collect()))?;
+    s.end()
+}
+
+impl<K, V> Serialize for ReifiedTotalMap<K, V>
+where
+    K: PartialEq + Serialize,
+    V: Clone + Serialize,
+{
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serialize_map(
+            serializer,
+            "ReifiedTotalMap",
+            self.default_value(),
+            self.iter_overrides().map(|(k, v)| (k, v.clone())),
+        )
+    }
+}
+
+impl<K, V> Serialize for FiniteMap<K, V>
+where
+    K: 'static + Eq + Hash + Clone + Serialize,
+    V: 'static + Clone + Serialize,
+{
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serialize_map(serializer, "FiniteMap", self.default_value(), self.iter())
+    }
+}
+
+/// The on-disk form: a default and the overrides in the order they appear.
+struct Repr<K, V> {
+    default_v: V,
+    overrides: Vec<(K, V)>,
+}
+
+/// An ordered list of pairs read from a map, so keys needn't be `Hash`.
+struct Pairs<K, V>(Vec<(K, V)>);
+
+impl<'de, K: Deserialize<'de>, V: Deserialize<'de>> Deserialize<'de> for Pairs<K, V> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct PairsVisitor<K, V>(PhantomData<(K, V)>);
+
+        impl<'de, K: Deserialize<'de>, V: Deserialize<'de>> Visitor<'de> for PairsVisitor<K, V> {
+            type Value = Pairs<K, V>;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "a map of overrides")
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, mut access: A) -> Result<Self::Value, A::Error> {
+                let mut pairs = Vec::new();
+                while let Some(pair) = access.next_entry()? {
+                    pairs.push(pair);
+                }
+                Ok(Pairs(pairs))
+            }
+        }
+
+        deserializer.deserialize_map(PairsVisitor(PhantomData))
+    }
+}
+
+impl<'de, K: Deserialize<'de>, V: Deserialize<'de>> Deserialize<'de> for Repr<K, V> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct ReprVisitor<K, V>(PhantomData<(K, V)>);
+
+        impl<'de, K: Deserialize<'de>, V: Deserialize<'de>> Visitor<'de> for ReprVisitor<K, V> {
+            type Value = Repr<K, V>;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "a map with a default and overrides")
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, mut access: A) -> Result<Self::Value, A::Error> {
+                let mut default_v = None;
+                let mut overrides = None;
+                while let Some(field) = access.next_key::<String>()? {
+                    match field.as_str() {
+                        "default" => default_v = Some(access.next_value()?),
+                        "overrides" => overrides = Some(access.next_value::<Pairs<K, V>>()?.0),
+                        other => return Err(serde::de::Error::unknown_field(other, FIELDS)),
+                    }
+                }
+                Ok(Repr {
+                    default_v: default_v
+                        .ok_or_else(|| serde::de::Error::missing_field("default"))?,
+                    overrides: overrides.unwrap_or_default(),
+                })
+            }
+        }
+
+        deserializer.deserialize_struct("Map", FIELDS, ReprVisitor(PhantomData))
+    }
+}
+
+impl<'de, K, V> Deserialize<'de> for ReifiedTotalMap<K, V>
+where
+    K: PartialEq + Deserialize<'de>,
+    V: Clone + Deserialize<'de>,
+{
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let repr = Repr::deserialize(deserializer)?;
+        // Overrides are written most recent first; replay them oldest first.
+        Ok(repr
+            .overrides
+            .into_iter()
+            .rev()
+            .fold(ReifiedTotalMap::empty(repr.default_v), |m, (k, v)| {
+                m.update(k, v)
+            }))
+    }
+}
+
+impl<'de, K, V> Deserialize<'de> for FiniteMap<K, V>
+where
+    K: 'static + Eq + Hash + Clone + Deserialize<'de>,
+    V: 'static + Clone + Deserialize<'de>,
+{
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let repr = Repr::deserialize(deserializer)?;
+        Ok(repr
+            .overrides
+            .into_iter()
+            .fold(FiniteMap::empty(repr.default_v), |m, (k, v)| m.update(k, v)))
+    }
+}
+
+#[cfg(test)]
+mod test_serde_maps {
+    use super::{FiniteMap, ReifiedTotalMap};
+
+    #[test]
+    fn test_finite_map_json() {
+        let state = FiniteMap::empty(0)
+            .update("X".to_string(), 5)
+            .update("Y".to_string(), 120);
+        let json = serde_json::to_string(&state).unwrap();
+        assert_eq!(json, r#"{"default":0,"overrides":{"X":5,"Y":120}}"#);
+        let back: FiniteMap<String, i64> = serde_json::from_str(&json).unwrap();
+        assert_eq!(back, state);
+        assert_eq!(back.keys(), state.keys());
+    }
+
+    #[test]
+    fn test_reified_map_json() {
+        let map = ReifiedTotalMap::empty(false)
+            .update("foo".to_string(), true)
+            .update("bar".to_string(), true)
+            .update("foo".to_string(), false);
+        let json = serde_json::to_string(&map).unwrap();
+        assert_eq!(
+            json,
+            r#"{"default":false,"overrides":{"foo":false,"bar":true}}"#
+        );
+        let back: ReifiedTotalMap<String, bool> = serde_json::from_str(&json).unwrap();
+        assert_eq!(back, map);
+        assert_eq!(format!("{:?}", back), format!("{:?}", map));
+    }
+
+    #[test]
+    fn test_missing_default_is_an_error() {
+        let result: Result<FiniteMap<String, i64>, _> =
+            serde_json::from_str(r#"{"overrides":{"X":5}}"#);
+        assert!(result.is_err());
+    }
+}
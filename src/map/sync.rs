@@ -0,0 +1,168 @@
+//! Thread-safe counterparts of the closure maps. They are built exactly like
+//! `TotalMap` and `PartialMap`, only on `Arc` with `Send + Sync` closures,
+//! so a state can be shared between threads.
+//!
+//! The constructors keep their usual names, and `sync_total_map!` and
+//! `sync_partial_map!` build sync maps the way `total_map!` and
+//! `partial_map!` build the others.
+
+use std::sync::Arc;
+
+pub type SyncTotalMap<K, V> = Arc<dyn Fn(&K) -> V + Send + Sync>;
+
+pub fn tm_empty<K: 'static + PartialEq, V: 'static + Clone + Send + Sync>(
+    default_v: V,
+) -> SyncTotalMap<K, V> {
+    Arc::new(move |_: &K| default_v.clone())
+}
+
+pub fn tm_update<K, V>(m: SyncTotalMap<K, V>, k: K, v: V) -> SyncTotalMap<K, V>
+where
+    K: 'static + PartialEq + Send + Sync,
+    V: 'static + Clone + Send + Sync,
+{
+    Arc::new(move |k_1: &K| if *k_1 == k { v.clone() } else { m(k_1) })
+}
+
+pub fn tm_remove<K, V>(m: SyncTotalMap<K, V>, k: K, default_v: V) -> SyncTotalMap<K, V>
+where
+    K: 'static + PartialEq + Send + Sync,
+    V: 'static + Clone + Send + Sync,
+{
+    tm_update(m, k, default_v)
+}
+
+/// `sync_total_map!(0, {k, v}, ...)` or `sync_total_map!(0, "x" => 3, ...)`,
+/// `total_map!` for a `SyncTotalMap`.
+#[macro_export]
+macro_rules! sync_total_map {
+    ($default_v: expr $(, {$k: expr, $v: expr})* $(,)?) => {
+        {
+            #[allow(unused_mut)]
+            let mut map = $crate::map::sync::tm_empty($default_v);
+            $(
+                map = $crate::map::sync::tm_update(map, $k, $v);
+            )*
+            map
+        }
+    };
+    ($default_v: expr $(, $k: expr => $v: expr)* $(,)?) => {
+        {
+            #[allow(unused_mut)]
+            let mut map = $crate::map::sync::tm_empty($default_v);
+            $(
+                map = $crate::map::sync::tm_update(map, ::std::convert::Into::into($k), $v);
+            )*
+            map
+        }
+    };
+}
+
+pub type SyncPartialMap<K, V> = SyncTotalMap<K, Option<V>>;
+
+pub fn pm_empty<K: 'static + PartialEq, V: 'static + Clone + Send + Sync>() -> SyncPartialMap<K, V>
+{
+    tm_empty(None)
+}
+
+pub fn pm_update<K, V>(m: SyncPartialMap<K, V>, k: K, v: V) -> SyncPartialMap<K, V>
+where
+    K: 'static + PartialEq + Send + Sync,
+    V: 'static + Clone + Send + Sync,
+{
+    tm_update(m, k, Some(v))
+}
+
+pub fn pm_remove<K, V>(m: SyncPartialMap<K, V>, k: K) -> SyncPartialMap<K, V>
+where
+    K: 'static + PartialEq + Send + Sync,
+    V: 'static + Clone + Send + Sync,
+{
+    tm_update(m, k, None)
+}
+
+/// `sync_partial_map!({k, v}, ...)` or `sync_partial_map!("Church" => true,
+/// ...)`, `partial_map!` for a `SyncPartialMap`.
+#[macro_export]
+macro_rules! sync_partial_map {
+    ($({$k: expr, $v: expr}),* $(,)?) => {
+        {
+            #[allow(unused_mut)]
+            let mut map = $crate::map::sync::pm_empty();
+            $(
+                map = $crate::map::sync::pm_update(map, $k, $v);
+            )*
+            map
+        }
+    };
+    ($($k: expr => $v: expr),* $(,)?) => {
+        {
+            #[allow(unused_mut)]
+            let mut map = $crate::map::sync::pm_empty();
+            $(
+                map = $crate::map::sync::pm_update(map, ::std::convert::Into::into($k), $v);
+            )*
+            map
+        }
+    };
+}
+
+#[cfg(test)]
+mod test_sync_map {
+    use std::thread;
+
+    use super::{pm_empty, pm_remove, pm_update, tm_empty, tm_update, SyncTotalMap};
+
+    fn example_map() -> SyncTotalMap<String, bool> {
+        tm_update(
+            tm_update(tm_empty(false), "foo".to_string(), true),
+            "bar".to_string(),
+            true,
+        )
+    }
+
+    #[test]
+    fn test_shared_across_threads() {
+        let map = example_map();
+        let handles: Vec<_> = ["foo", "bar", "box"]
+            .into_iter()
+            .map(|k| {
+                let map = map.clone();
+                thread::spawn(move || map(&k.to_string()))
+            })
+            .collect();
+        let results: Vec<bool> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+        assert_eq!(results, vec![true, true, false]);
+    }
+
+    #[test]
+    fn test_partial_map() {
+        let map = pm_update(pm_update(pm_empty(), "Church", true), "Turing", false);
+        let map = pm_remove(map, "Church");
+        assert_eq!(
+            thread::spawn(move || map(&"Turing")).join().unwrap(),
+            Some(false)
+        );
+    }
+
+    #[test]
+    fn test_macro_builds_sync_map() {
+        let map = crate::sync_total_map!(0, {"x", 1}, {"y", 2});
+        assert_eq!(
+            thread::spawn(move || map(&"x") + map(&"y")).join().unwrap(),
+            3
+        );
+    }
+
+    #[test]
+    fn test_partial_macro_builds_sync_map() {
+        let map: super::SyncPartialMap<String, bool> =
+            crate::sync_partial_map!("Church" => true, "Turing" => false);
+        assert_eq!(
+            thread::spawn(move || map(&"Turing".to_string()))
+                .join()
+                .unwrap(),
+            Some(false)
+        );
+    }
+}
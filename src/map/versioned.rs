@@ -0,0 +1,159 @@
+use std::borrow::Borrow;
+
+use super::{tm_empty, tm_update, TotalMap};
+
+/// A total map that keeps its whole update history. Every update gets the
+/// next version number, starting from `0` for the empty map, and any
+/// earlier version can be looked at again or rolled back to. Since the
+/// closure maps are persistent, each version is just the map as it was,
+/// so keeping them around costs one `Rc` per update. As with the closure
+/// maps, `K` can be a borrowed form like `str`; the log holds the owned
+/// keys.
+pub struct VersionedMap<K: ?Sized + ToOwned, V> {
+    /// `versions[n]` is the map at version `n`.
+    versions: Vec<TotalMap<K, V>>,
+    /// `log[n]` is the update that produced version `n + 1`.
+    log: Vec<(K::Owned, V)>,
+}
+
+impl<K: ?Sized + ToOwned, V: Clone> Clone for VersionedMap<K, V> {
+    fn clone(&self) -> Self {
+        VersionedMap {
+            versions: self.versions.clone(),
+            log: self
+                .log
+                .iter()
+                .map(|(k, v)| (k.borrow().to_owned(), v.clone()))
+                .collect(),
+        }
+    }
+}
+
+impl<K, V> VersionedMap<K, V>
+where
+    K: 'static + PartialEq + ToOwned + ?Sized,
+    K::Owned: 'static,
+    V: 'static + Clone,
+{
+    pub fn empty(default_v: V) -> Self {
+        Self::from_map(tm_empty(default_v))
+    }
+
+    /// Start recording from an existing map, which becomes version `0`.
+    pub fn from_map(m: TotalMap<K, V>) -> Self {
+        VersionedMap {
+            versions: vec![m],
+            log: Vec::new(),
+        }
+    }
+
+    /// Apply an update and return the version it created.
+    pub fn update(&mut self, k: K::Owned, v: V) -> usize {
+        let m = tm_update(self.current(), k.borrow().to_owned(), v.clone());
+        self.versions.push(m);
+        self.log.push((k, v));
+        self.version()
+    }
+
+    pub fn apply(&self, k: &K) -> V {
+        self.versions[self.version()](k)
+    }
+
+    /// The current version number.
+    pub fn version(&self) -> usize {
+        self.log.len()
+    }
+
+    /// Name the current version so it can be rolled back to later. This is
+    /// the same number `version` returns; nothing is copied.
+    pub fn snapshot(&self) -> usize {
+        self.version()
+    }
+
+    /// The map as it is now.
+    pub fn current(&self) -> TotalMap<K, V> {
+        self.versions[self.version()].clone()
+    }
+
+    /// The map as it was at `version`, if that version exists.
+    pub fn at(&self, version: usize) -> Option<TotalMap<K, V>> {
+        self.versions.get(version).cloned()
+    }
+
+    /// Go back to `version`, discarding every later update. Returns `false`
+    /// (and changes nothing) if `version` is in the future.
+    pub fn rollback(&mut self, version: usize) -> bool {
+        if version > self.version() {
+            return false;
+        }
+        self.versions.truncate(version + 1);
+        self.log.truncate(version);
+        true
+    }
+
+    /// Undo the latest update, returning it.
+    pub fn undo(&mut self) -> Option<(K::Owned, V)> {
+        let last = self.log.pop()?;
+        self.versions.pop();
+        Some(last)
+    }
+
+    /// The updates made so far, oldest first; the update at index `n`
+    /// created version `n + 1`.
+    pub fn history(&self) -> &[(K::Owned, V)] {
+        &self.log
+    }
+}
+
+#[cfg(test)]
+mod test_versioned_map {
+    use super::VersionedMap;
+
+    #[test]
+    fn test_versions_count_updates() {
+        let mut m = VersionedMap::empty(0);
+        assert_eq!(m.version(), 0);
+        assert_eq!(m.update("x", 1), 1);
+        assert_eq!(m.update("y", 2), 2);
+        assert_eq!(m.apply(&"x"), 1);
+        assert_eq!(m.history(), [("x", 1), ("y", 2)]);
+    }
+
+    #[test]
+    fn test_snapshot_and_rollback() {
+        let mut m = VersionedMap::empty(0);
+        m.update("x", 1);
+        let snap = m.snapshot();
+        m.update("x", 2);
+        m.update("y", 3);
+        assert_eq!(m.apply(&"x"), 2);
+        assert!(m.rollback(snap));
+        assert_eq!(m.apply(&"x"), 1);
+        assert_eq!(m.apply(&"y"), 0);
+        assert_eq!(m.version(), snap);
+        assert!(!m.rollback(snap + 1));
+    }
+
+    #[test]
+    fn test_at_sees_old_versions() {
+        let mut m = VersionedMap::empty(false);
+        m.update("a", true);
+        m.update("a", false);
+        assert!(!m.at(0).unwrap()(&"a"));
+        assert!(m.at(1).unwrap()(&"a"));
+        assert!(!m.at(2).unwrap()(&"a"));
+        assert!(m.at(3).is_none());
+    }
+
+    #[test]
+    fn test_undo() {
+        let mut m = VersionedMap::empty(0);
+        m.update("x", 1);
+        m.update("x", 5);
+        assert_eq!(m.undo(), Some(("x", 5)));
+        assert_eq!(m.apply(&"x"), 1);
+        assert_eq!(m.undo(), Some(("x", 1)));
+        assert_eq!(m.undo(), None);
+        assert_eq!(m.apply(&"x"), 0);
+    }
+}
@@ -3,39 +3,88 @@ use std::{
     rc::Rc,
 };
 
-#[derive(Debug, Clone, PartialEq)]
-enum Peano {
+use crate::Recursor;
+
+#[derive(Debug, Clone, PartialEq, Recursor)]
+pub enum Peano {
     O,            // Zero is natural number.
     S(Rc<Peano>), // Successor of a natural number is a natural number.
 }
 
+/// `peano!(5)` builds a numeral from a `usize`, while `peano!(S S S O)`
+/// spells it out structurally like a Coq term. Parenthesised forms such as
+/// `peano!(S (S O))` are accepted as well.
+#[macro_export]
+macro_rules! peano {
+    (O) => {
+        $crate::peano::Peano::O
+    };
+    (S $($rest: tt)+) => {
+        $crate::peano::Peano::S(::std::rc::Rc::new($crate::peano!($($rest)+)))
+    };
+    (($($inner: tt)+)) => {
+        $crate::peano!($($inner)+)
+    };
+    ($x: expr) => {
+        $crate::peano::Peano::from($x)
+    };
+}
+
 impl Peano {
-    fn pred(self) -> Self {
-        match self {
+    pub fn pred(self) -> Self {
+        match &self {
             Peano::O => Peano::O,
             Peano::S(p) => p.as_ref().clone(),
         }
     }
 
-    fn succ(self) -> Self {
+    pub fn succ(self) -> Self {
         Peano::S(Rc::new(self))
     }
 }
 
+// Both conversions are written as loops rather than by structural recursion
+// so that they don't blow the stack on large numbers.
 impl From<usize> for Peano {
     fn from(value: usize) -> Self {
-        match value {
-            0 => Peano::O,
-            n => Peano::S(Rc::new((n - 1).into())),
+        let mut result = Peano::O;
+        for _ in 0..value {
+            result = result.succ();
         }
+        result
     }
 }
 
+#[allow(clippy::from_over_into)]
 impl Into<usize> for Peano {
     fn into(self) -> usize {
-        match self {
-            Peano::O => 0,
-            Peano::S(p) => 1 + Into::<usize>::into(p.as_ref().clone()),
+        let mut count = 0;
+        let mut current = &self;
+        while let Peano::S(p) = current {
+            count += 1;
+            current = p.as_ref();
+        }
+        count
+    }
+}
+
+// The derived drop glue would recurse once per `S`, so dropping a numeral
+// built from a large `usize` overflows the stack. Instead we unlink the spine
+// one node at a time: each node we uniquely own has its predecessor swapped
+// for a fresh `O` before it is dropped, so every individual drop is shallow.
+// As soon as we reach a node that is still shared, the rest of the chain is
+// kept alive by someone else and we can stop.
+impl Drop for Peano {
+    fn drop(&mut self) {
+        let mut next = match self {
+            Peano::O => return,
+            Peano::S(p) => std::mem::replace(p, Rc::new(Peano::O)),
+        };
+        while let Ok(mut node) = Rc::try_unwrap(next) {
+            next = match &mut node {
+                Peano::O => return,
+                Peano::S(p) => std::mem::replace(p, Rc::new(Peano::O)),
+            };
         }
     }
 }
@@ -55,13 +104,13 @@ impl Add for Peano {
 impl Sub for Peano {
     type Output = Peano;
     fn sub(self, rhs: Self) -> Self::Output {
-        match (self, rhs) {
+        match (&self, &rhs) {
             // This is the most tricky situation. Because there's no negative
             // number in Peano numerals (which is a kind of definition of
             // natural numbers) so we just return zero arbitrarily when there's
             // "0 - n"(n != 0).
             (Peano::O, _) => Peano::O,
-            (lhs, Peano::O) => lhs,
+            (lhs, Peano::O) => lhs.clone(),
             (Peano::S(l), Peano::S(r)) => l.as_ref().clone() - r.as_ref().clone(),
         }
     }
@@ -70,7 +119,7 @@ impl Sub for Peano {
 impl Mul for Peano {
     type Output = Peano;
     fn mul(self, rhs: Self) -> Self::Output {
-        match self {
+        match &self {
             Peano::O => Peano::O,
             Peano::S(n) => n.as_ref().clone() * rhs.clone() + rhs,
         }
@@ -103,4 +152,76 @@ mod test_peano {
         let peano_12: Peano = 12.into();
         assert_eq!(peano_3 * peano_4, peano_12);
     }
+
+    #[test]
+    pub fn check_deep_numeral_round_trip() {
+        let n: Peano = 1_000_000.into();
+        assert_eq!(Into::<usize>::into(n), 1_000_000);
+    }
+
+    #[test]
+    pub fn check_deep_numeral_shared_tail() {
+        let n: Peano = 1_000_000.into();
+        let m = n.clone().succ();
+        drop(n);
+        assert_eq!(Into::<usize>::into(m.pred()), 1_000_000);
+    }
+
+    #[test]
+    pub fn check_macro_literal() {
+        assert_eq!(peano!(0), Peano::O);
+        assert_eq!(peano!(3), Peano::from(3));
+    }
+
+    #[test]
+    pub fn check_macro_structural() {
+        assert_eq!(peano!(O), peano!(0));
+        assert_eq!(peano!(S S S O), peano!(3));
+        assert_eq!(peano!(S (S O)), peano!(2));
+    }
+
+    #[test]
+    pub fn check_rect_to_usize() {
+        let n = peano!(4);
+        assert_eq!(n.rect(&|| 0, &|_, acc: usize| acc + 1), 4);
+    }
+
+    #[test]
+    pub fn check_rect_double() {
+        // Fixpoint double (n : nat) := match n with O => O | S n' => S (S (double n')) end.
+        let double = |n: &Peano| n.rect(&|| Peano::O, &|_, acc: Peano| acc.succ().succ());
+        assert_eq!(double(&peano!(3)), peano!(6));
+    }
+
+    #[derive(Recursor)]
+    #[allow(dead_code)]
+    enum Tree<T> {
+        Leaf,
+        Node {
+            left: Box<Tree<T>>,
+            value: T,
+            right: Box<Tree<T>>,
+        },
+    }
+
+    #[test]
+    pub fn check_rect_on_user_enum() {
+        let leaf = || Box::new(Tree::Leaf);
+        let tree = Tree::Node {
+            left: Box::new(Tree::Node {
+                left: leaf(),
+                value: 1,
+                right: leaf(),
+            }),
+            value: 2,
+            right: leaf(),
+        };
+        let sum = tree.rect(&|| 0, &|_, l: i32, v: &i32, _, r: i32| l + v + r);
+        assert_eq!(sum, 3);
+    }
+
+    #[test]
+    pub fn check_macro_arith() {
+        assert_eq!(peano!(2) + peano!(S O), peano!(S S S O));
+    }
 }
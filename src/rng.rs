@@ -0,0 +1,100 @@
+//! A small deterministic random number generator for the test harnesses.
+//! It is SplitMix64: not cryptographic, but fast, seedable, and without a
+//! dependency, so a failing case can be replayed from its seed alone.
+
+/// The environment variable the harnesses read their seed from.
+pub const SEED_VAR: &str = "RUST_COQ_SEED";
+
+/// The seed in `RUST_COQ_SEED`, or `default` if it is unset or not a
+/// number.
+pub fn seed_from_env(default: u64) -> u64 {
+    std::env::var(SEED_VAR)
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(default)
+}
+
+#[derive(Debug, Clone)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Rng { state: seed }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// A number in `0..n`. Panics if `n` is `0`.
+    pub fn below(&mut self, n: u64) -> u64 {
+        assert!(n > 0, "Rng::below(0)");
+        self.next_u64() % n
+    }
+
+    /// A number in `lo..=hi`.
+    pub fn range(&mut self, lo: i64, hi: i64) -> i64 {
+        assert!(lo <= hi, "Rng::range with an empty range");
+        let width = hi.wrapping_sub(lo) as u64;
+        match width.checked_add(1) {
+            Some(n) => lo.wrapping_add(self.below(n) as i64),
+            None => self.next_u64() as i64,
+        }
+    }
+
+    /// `true` with probability `num / den`.
+    pub fn chance(&mut self, num: u64, den: u64) -> bool {
+        self.below(den) < num
+    }
+
+    /// A uniformly chosen element. Panics if `xs` is empty.
+    pub fn choose<'a, T>(&mut self, xs: &'a [T]) -> &'a T {
+        &xs[self.below(xs.len() as u64) as usize]
+    }
+}
+
+#[cfg(test)]
+mod test_rng {
+    use super::Rng;
+
+    #[test]
+    fn test_deterministic() {
+        let xs: Vec<u64> = (0..5)
+            .map({
+                let mut rng = Rng::new(7);
+                move |_| rng.next_u64()
+            })
+            .collect();
+        let mut rng = Rng::new(7);
+        assert!(xs.iter().all(|x| *x == rng.next_u64()));
+        assert_ne!(Rng::new(8).next_u64(), xs[0]);
+    }
+
+    #[test]
+    fn test_range_bounds() {
+        let mut rng = Rng::new(1);
+        for _ in 0..1000 {
+            let n = rng.range(-3, 3);
+            assert!((-3..=3).contains(&n));
+        }
+        assert_eq!(rng.range(5, 5), 5);
+        // The full range must not overflow.
+        rng.range(i64::MIN, i64::MAX);
+    }
+
+    #[test]
+    fn test_choose_hits_everything() {
+        let mut rng = Rng::new(3);
+        let mut seen = [false; 4];
+        for _ in 0..100 {
+            seen[*rng.choose(&[0, 1, 2, 3])] = true;
+        }
+        assert_eq!(seen, [true; 4]);
+    }
+}
@@ -0,0 +1,96 @@
+use crate::map::{tm_empty, TotalMap};
+
+/// Program states of Imp map variable names to integers. Variables that
+/// were never assigned read as `0`. They are keyed by `str`, so a name
+/// can be looked up as it is, without building a `String` for it.
+pub type State = TotalMap<str, i64>;
+
+/// The state every variable of which is `0`.
+pub fn empty_state() -> State {
+    tm_empty(0)
+}
+
+/// Look a variable up by name: `st(x)` as a function of the state.
+pub fn lookup(st: &State, x: &str) -> i64 {
+    st(x)
+}
+
+/// Render the given variables of a state, e.g. `X=5, Y=120, Z=0`. A state
+/// is a function, so it is up to the caller to say which variables matter.
+pub fn show_state(st: &State, vars: &[&str]) -> String {
+    st.show(vars)
+}
+
+/// Methods on states. `State` is an alias for a closure type, so they have
+/// to come from a trait.
+pub trait StateExt {
+    /// `show_state` as a method, taking the variable names as `&str`s or
+    /// `String`s: `st.show(&["X", "Y"])`.
+    fn show<S: AsRef<str>>(&self, vars: &[S]) -> String;
+}
+
+impl StateExt for State {
+    fn show<S: AsRef<str>>(&self, vars: &[S]) -> String {
+        vars.iter()
+            .map(|x| format!("{}={}", x.as_ref(), lookup(self, x.as_ref())))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+/// `state! {"X" => 3, "Y" => 4}` builds a state on top of `empty_state()`.
+#[macro_export]
+macro_rules! state {
+    ($($x: expr => $n: expr),* $(,)?) => {
+        {
+            #[allow(unused_mut)]
+            let mut st = $crate::state::empty_state();
+            $(
+                st = $crate::map::tm_update(st, ::std::string::String::from($x), $n);
+            )*
+            st
+        }
+    };
+}
+
+#[cfg(test)]
+mod test_state {
+    use super::*;
+
+    #[test]
+    fn test_empty_state() {
+        assert_eq!(lookup(&empty_state(), "X"), 0);
+    }
+
+    #[test]
+    fn test_state_macro() {
+        let st = state! {"X" => 3, "Y" => 1 + 3,};
+        assert_eq!(lookup(&st, "X"), 3);
+        assert_eq!(lookup(&st, "Y"), 4);
+        assert_eq!(lookup(&st, "Z"), 0);
+    }
+
+    #[test]
+    fn test_lookup_by_str() {
+        let st = state! {"X" => 3};
+        assert_eq!(st("X"), 3);
+        let x = "X".to_string();
+        assert_eq!(st(&x), 3);
+        let same = crate::map::tm_update(empty_state(), x, 3);
+        assert!(crate::map::tm_agree_on(&st, &same, &["X", "Y"]));
+    }
+
+    #[test]
+    fn test_show_state() {
+        let st = state! {"X" => 5, "Y" => 120};
+        assert_eq!(show_state(&st, &["X", "Y", "Z"]), "X=5, Y=120, Z=0");
+    }
+
+    #[test]
+    fn test_show_method() {
+        let st = state! {"X" => 5};
+        assert_eq!(st.show(&["X", "Y"]), "X=5, Y=0");
+        assert_eq!(st.show(&["X".to_string()]), "X=5");
+        assert_eq!(st.show::<&str>(&[]), "");
+    }
+}
@@ -0,0 +1,476 @@
+//! The simply typed lambda calculus of the Stlc chapter: the terms of
+//! `crate::lambda` with a type on every binder, `\x:T. t`, the types
+//! `Bool` and `T1 -> T2` to put there, and the booleans `true` and `false`
+//! with `if t1 then t2 else t3` to use them. The extensions of the MoreStlc
+//! chapter follow: natural numbers, with `succ`, `pred`, `mult` and
+//! `iszero` on literals `0`, `1`, and so on, `let x = t1 in t2`, and pairs
+//! `(t1, t2)` of type `T1 * T2`, taken apart by `t.fst` and `t.snd`, and
+//! sums `T1 + T2`, made with `inl` and `inr` and taken apart by `case`, and
+//! lists `List T`, made with `nil` and `cons` and taken apart by `case` too,
+//! and general recursion, `fix t` for `t` a function from a type to itself.
+//! `unit` is the one value of type `Unit`; it is what `t1; t2` throws away,
+//! sugar that `Tm::seq` spells out as an abstraction over `Unit`. Records
+//! `{x=t1, y=t2}` of type `{x:T1, y:T2}` are taken apart by `t.x`; as in
+//! the Records chapter, their fields are in order, which is part of the
+//! type, and a label given twice is looked up as its first occurrence.
+//!
+//! References are the chapter's too: `ref t` allocates a cell holding the
+//! value of `t` in the store and evaluates to its location `loc l`, of
+//! type `Ref T`; `!t` reads it and `t1 := t2` overwrites it.
+//!
+//! `subtype` relates types as the Sub chapter does, with a `Top` type
+//! above all the others, and `type_of` lets a term of a subtype be used
+//! where a supertype is expected: a record with more fields, or in
+//! another order, can be passed for one with fewer.
+//!
+//! Exceptions are the MoreStlc chapter's `error`, which aborts the whole
+//! evaluation, and `try t1 with t2`, which evaluates `t2` instead if `t1`
+//! aborts. `error` has type `Bot`, below all the other types, so it can
+//! stand wherever a term of any type is needed.
+//!
+//! A typing context is a `PartialMap` from variable names to types, as in
+//! the chapter, and `type_of` computes the one type a term has in a
+//! context, or says which part of it has none. `synth` and `check` do the
+//! same bidirectionally, the latter with a type to check against, which
+//! lets it point at a smaller part of a term that doesn't have it.
+
+use std::collections::BTreeSet;
+
+use crate::heap::Loc;
+use crate::lambda::fresh;
+use crate::map::{pm_empty, PartialMap};
+
+mod bidir;
+mod eval;
+mod parser;
+mod pretty;
+mod subtype;
+mod typing;
+
+pub use bidir::{check, synth};
+pub use eval::{eval, eval_in, is_value, step, step_in, Store};
+pub use parser::{parse_tm, parse_ty, ParseError, Token};
+pub use subtype::{join, meet, subtype};
+pub use typing::{desugar_let, type_of, type_of_in, TypeError};
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Ty {
+    Bool,
+    Nat,
+    Unit,
+    /// The supertype of every type.
+    Top,
+    /// The subtype of every type, which only `error` has.
+    Bot,
+    /// `T1 -> T2`.
+    Arrow(Box<Ty>, Box<Ty>),
+    /// `T1 * T2`, the type of pairs.
+    Prod(Box<Ty>, Box<Ty>),
+    /// `T1 + T2`, the type of values tagged as a `T1` or as a `T2`.
+    Sum(Box<Ty>, Box<Ty>),
+    /// `List T`.
+    List(Box<Ty>),
+    /// `{x1:T1, ..., xn:Tn}`.
+    Record(Vec<(String, Ty)>),
+    /// `Ref T`, the type of locations holding a `T`.
+    Ref(Box<Ty>),
+}
+
+impl Ty {
+    pub fn arrow(t1: Ty, t2: Ty) -> Ty {
+        Ty::Arrow(Box::new(t1), Box::new(t2))
+    }
+
+    pub fn prod(t1: Ty, t2: Ty) -> Ty {
+        Ty::Prod(Box::new(t1), Box::new(t2))
+    }
+
+    pub fn sum(t1: Ty, t2: Ty) -> Ty {
+        Ty::Sum(Box::new(t1), Box::new(t2))
+    }
+
+    pub fn list(t: Ty) -> Ty {
+        Ty::List(Box::new(t))
+    }
+
+    pub fn ref_(t: Ty) -> Ty {
+        Ty::Ref(Box::new(t))
+    }
+
+    pub fn record(fields: Vec<(&str, Ty)>) -> Ty {
+        Ty::Record(
+            fields
+                .into_iter()
+                .map(|(x, ty)| (x.to_string(), ty))
+                .collect(),
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Tm {
+    Var(String),
+    App(Box<Tm>, Box<Tm>),
+    /// `\x:T. t`.
+    Abs(String, Ty, Box<Tm>),
+    Tru,
+    Fls,
+    /// `unit`, the value of type `Unit`.
+    Unit,
+    /// `if t1 then t2 else t3`.
+    Test(Box<Tm>, Box<Tm>, Box<Tm>),
+    /// A natural number literal. Arithmetic on them wraps around at
+    /// `u64::MAX`; see `eval`.
+    Const(u64),
+    Scc(Box<Tm>),
+    Prd(Box<Tm>),
+    Mlt(Box<Tm>, Box<Tm>),
+    /// `iszero t`, a `Bool`.
+    IsZero(Box<Tm>),
+    /// `let x = t1 in t2`.
+    Let(String, Box<Tm>, Box<Tm>),
+    /// `(t1, t2)`.
+    Pair(Box<Tm>, Box<Tm>),
+    /// `t.fst`.
+    Fst(Box<Tm>),
+    /// `t.snd`.
+    Snd(Box<Tm>),
+    /// `inl T2 t`, `t` injected into `T1 + T2`. The type of the other side
+    /// is given, as the rest of the type is `t`'s.
+    Inl(Ty, Box<Tm>),
+    /// `inr T1 t`, `t` injected into `T1 + T2`.
+    Inr(Ty, Box<Tm>),
+    /// `case t0 of | inl x1 => t1 | inr x2 => t2`.
+    Case(Box<Tm>, String, Box<Tm>, String, Box<Tm>),
+    /// `nil T`, the empty `List T`.
+    Nil(Ty),
+    /// `cons t1 t2`.
+    Cons(Box<Tm>, Box<Tm>),
+    /// `case t1 of | nil => t2 | x1 :: x2 => t3`, the chapter's `lcase`.
+    Lcase(Box<Tm>, Box<Tm>, String, String, Box<Tm>),
+    /// `fix t`, the fixed point of `t : T -> T`.
+    Fix(Box<Tm>),
+    /// `{x1=t1, ..., xn=tn}`.
+    Record(Vec<(String, Tm)>),
+    /// `t.x`, the field `x` of a record.
+    Proj(Box<Tm>, String),
+    /// `ref t`, a new cell holding `t`.
+    Ref(Box<Tm>),
+    /// `!t`, what the cell `t` holds.
+    Deref(Box<Tm>),
+    /// `t1 := t2`.
+    Assign(Box<Tm>, Box<Tm>),
+    /// `loc l`, a location in the store; only evaluation makes these.
+    Loc(Loc),
+    /// `error`, which aborts evaluation.
+    Error,
+    /// `try t1 with t2`: `t1`, or `t2` if `t1` evaluates to `error`.
+    Try(Box<Tm>, Box<Tm>),
+}
+
+impl Tm {
+    pub fn var(x: &str) -> Tm {
+        Tm::Var(x.to_string())
+    }
+
+    pub fn app(t1: Tm, t2: Tm) -> Tm {
+        Tm::App(Box::new(t1), Box::new(t2))
+    }
+
+    pub fn abs(x: &str, ty: Ty, t: Tm) -> Tm {
+        Tm::Abs(x.to_string(), ty, Box::new(t))
+    }
+
+    pub fn test(t1: Tm, t2: Tm, t3: Tm) -> Tm {
+        Tm::Test(Box::new(t1), Box::new(t2), Box::new(t3))
+    }
+
+    pub fn scc(t: Tm) -> Tm {
+        Tm::Scc(Box::new(t))
+    }
+
+    pub fn prd(t: Tm) -> Tm {
+        Tm::Prd(Box::new(t))
+    }
+
+    pub fn mlt(t1: Tm, t2: Tm) -> Tm {
+        Tm::Mlt(Box::new(t1), Box::new(t2))
+    }
+
+    pub fn is_zero(t: Tm) -> Tm {
+        Tm::IsZero(Box::new(t))
+    }
+
+    pub fn let_(x: &str, t1: Tm, t2: Tm) -> Tm {
+        Tm::Let(x.to_string(), Box::new(t1), Box::new(t2))
+    }
+
+    pub fn pair(t1: Tm, t2: Tm) -> Tm {
+        Tm::Pair(Box::new(t1), Box::new(t2))
+    }
+
+    pub fn fst(t: Tm) -> Tm {
+        Tm::Fst(Box::new(t))
+    }
+
+    pub fn snd(t: Tm) -> Tm {
+        Tm::Snd(Box::new(t))
+    }
+
+    pub fn inl(ty: Ty, t: Tm) -> Tm {
+        Tm::Inl(ty, Box::new(t))
+    }
+
+    pub fn inr(ty: Ty, t: Tm) -> Tm {
+        Tm::Inr(ty, Box::new(t))
+    }
+
+    pub fn cons(t1: Tm, t2: Tm) -> Tm {
+        Tm::Cons(Box::new(t1), Box::new(t2))
+    }
+
+    pub fn lcase(t1: Tm, t2: Tm, x1: &str, x2: &str, t3: Tm) -> Tm {
+        Tm::Lcase(
+            Box::new(t1),
+            Box::new(t2),
+            x1.to_string(),
+            x2.to_string(),
+            Box::new(t3),
+        )
+    }
+
+    pub fn fix(t: Tm) -> Tm {
+        Tm::Fix(Box::new(t))
+    }
+
+    pub fn record(fields: Vec<(&str, Tm)>) -> Tm {
+        Tm::Record(
+            fields
+                .into_iter()
+                .map(|(x, t)| (x.to_string(), t))
+                .collect(),
+        )
+    }
+
+    pub fn proj(t: Tm, x: &str) -> Tm {
+        Tm::Proj(Box::new(t), x.to_string())
+    }
+
+    pub fn ref_(t: Tm) -> Tm {
+        Tm::Ref(Box::new(t))
+    }
+
+    pub fn deref(t: Tm) -> Tm {
+        Tm::Deref(Box::new(t))
+    }
+
+    pub fn assign(t1: Tm, t2: Tm) -> Tm {
+        Tm::Assign(Box::new(t1), Box::new(t2))
+    }
+
+    pub fn try_(t1: Tm, t2: Tm) -> Tm {
+        Tm::Try(Box::new(t1), Box::new(t2))
+    }
+
+    /// `t1; t2`: evaluate `t1`, a `Unit`, then `t2`. There is no term for
+    /// it; it is `(\_0:Unit. t2) t1`, with the bound name changed if `t2`
+    /// uses `_0`.
+    pub fn seq(t1: Tm, t2: Tm) -> Tm {
+        let x = fresh("_", &t2.free_vars());
+        Tm::app(Tm::abs(&x, Ty::Unit, t2), t1)
+    }
+
+    /// The variables occurring free in the term.
+    pub fn free_vars(&self) -> BTreeSet<String> {
+        let mut vars = BTreeSet::new();
+        self.collect_free(&mut Vec::new(), &mut vars);
+        vars
+    }
+
+    fn collect_free<'a>(&'a self, bound: &mut Vec<&'a str>, vars: &mut BTreeSet<String>) {
+        // Each subterm, with the variables its binder binds in it.
+        let subterms: Vec<(&Tm, Vec<&str>)> = match self {
+            Tm::Var(x) => {
+                if !bound.contains(&x.as_str()) {
+                    vars.insert(x.clone());
+                }
+                vec![]
+            }
+            Tm::Tru | Tm::Fls | Tm::Unit | Tm::Const(_) | Tm::Nil(_) | Tm::Loc(_) | Tm::Error => {
+                vec![]
+            }
+            Tm::Abs(x, _, t) => vec![(t, vec![x])],
+            Tm::Scc(t1)
+            | Tm::Prd(t1)
+            | Tm::IsZero(t1)
+            | Tm::Fix(t1)
+            | Tm::Fst(t1)
+            | Tm::Snd(t1)
+            | Tm::Inl(_, t1)
+            | Tm::Inr(_, t1)
+            | Tm::Proj(t1, _)
+            | Tm::Ref(t1)
+            | Tm::Deref(t1) => vec![(t1, vec![])],
+            Tm::Record(fields) => fields.iter().map(|(_, t)| (t, vec![])).collect(),
+            Tm::App(t1, t2)
+            | Tm::Mlt(t1, t2)
+            | Tm::Pair(t1, t2)
+            | Tm::Cons(t1, t2)
+            | Tm::Assign(t1, t2)
+            | Tm::Try(t1, t2) => {
+                vec![(t1, vec![]), (t2, vec![])]
+            }
+            Tm::Test(t1, t2, t3) => vec![(t1, vec![]), (t2, vec![]), (t3, vec![])],
+            Tm::Let(x, t1, t2) => vec![(t1, vec![]), (t2, vec![x])],
+            Tm::Case(t0, x1, t1, x2, t2) => vec![(t0, vec![]), (t1, vec![x1]), (t2, vec![x2])],
+            Tm::Lcase(t1, t2, x1, x2, t3) => {
+                vec![(t1, vec![]), (t2, vec![]), (t3, vec![x1, x2])]
+            }
+        };
+        for (t, xs) in subterms {
+            let n = bound.len();
+            bound.extend(xs);
+            t.collect_free(bound, vars);
+            bound.truncate(n);
+        }
+    }
+
+    pub fn case(t0: Tm, x1: &str, t1: Tm, x2: &str, t2: Tm) -> Tm {
+        Tm::Case(
+            Box::new(t0),
+            x1.to_string(),
+            Box::new(t1),
+            x2.to_string(),
+            Box::new(t2),
+        )
+    }
+}
+
+/// A typing context, `Gamma` in the chapter: the types of the variables in
+/// scope.
+pub type Context = PartialMap<String, Ty>;
+
+/// A store typing, `ST` in the References chapter: the types of the
+/// values the locations of a store hold.
+pub type StoreTy = crate::heap::Heap<Ty>;
+
+/// The context with nothing in scope, for typing closed terms.
+pub fn empty_context() -> Context {
+    pm_empty()
+}
+
+/// The first field labelled `x`, as the chapter's `Tlookup` and `tlookup`
+/// find it.
+fn lookup_field<'a, T>(fields: &'a [(String, T)], x: &str) -> Option<&'a T> {
+    fields.iter().find(|(y, _)| y == x).map(|(_, t)| t)
+}
+
+/// `[x:=s] t`. Unlike `lambda::subst` this renames nothing, so `s` should
+/// be closed, as it is when a closed term is evaluated: a free variable of
+/// `s` could otherwise be captured by a binder of `t`.
+pub fn subst(t: &Tm, x: &str, s: &Tm) -> Tm {
+    match t {
+        Tm::Var(y) if y == x => s.clone(),
+        Tm::Var(_) => t.clone(),
+        Tm::App(t1, t2) => Tm::app(subst(t1, x, s), subst(t2, x, s)),
+        Tm::Abs(y, _, _) if y == x => t.clone(),
+        Tm::Abs(y, ty, body) => Tm::abs(y, ty.clone(), subst(body, x, s)),
+        Tm::Tru | Tm::Fls | Tm::Unit | Tm::Const(_) | Tm::Loc(_) | Tm::Error => t.clone(),
+        Tm::Test(t1, t2, t3) => Tm::test(subst(t1, x, s), subst(t2, x, s), subst(t3, x, s)),
+        Tm::Scc(t1) => Tm::scc(subst(t1, x, s)),
+        Tm::Prd(t1) => Tm::prd(subst(t1, x, s)),
+        Tm::Mlt(t1, t2) => Tm::mlt(subst(t1, x, s), subst(t2, x, s)),
+        Tm::IsZero(t1) => Tm::is_zero(subst(t1, x, s)),
+        Tm::Fix(t1) => Tm::fix(subst(t1, x, s)),
+        Tm::Record(fields) => Tm::Record(
+            fields
+                .iter()
+                .map(|(y, t)| (y.clone(), subst(t, x, s)))
+                .collect(),
+        ),
+        Tm::Proj(t1, y) => Tm::proj(subst(t1, x, s), y),
+        Tm::Ref(t1) => Tm::ref_(subst(t1, x, s)),
+        Tm::Deref(t1) => Tm::deref(subst(t1, x, s)),
+        Tm::Assign(t1, t2) => Tm::assign(subst(t1, x, s), subst(t2, x, s)),
+        Tm::Try(t1, t2) => Tm::try_(subst(t1, x, s), subst(t2, x, s)),
+        // The bound variable scopes over the body only.
+        Tm::Let(y, t1, t2) if y == x => Tm::Let(y.clone(), Box::new(subst(t1, x, s)), t2.clone()),
+        Tm::Let(y, t1, t2) => Tm::let_(y, subst(t1, x, s), subst(t2, x, s)),
+        Tm::Pair(t1, t2) => Tm::pair(subst(t1, x, s), subst(t2, x, s)),
+        Tm::Fst(t1) => Tm::fst(subst(t1, x, s)),
+        Tm::Snd(t1) => Tm::snd(subst(t1, x, s)),
+        Tm::Inl(ty, t1) => Tm::inl(ty.clone(), subst(t1, x, s)),
+        Tm::Inr(ty, t1) => Tm::inr(ty.clone(), subst(t1, x, s)),
+        Tm::Case(t0, x1, t1, x2, t2) => {
+            let branch = |y: &String, t: &Tm| if y == x { t.clone() } else { subst(t, x, s) };
+            Tm::case(subst(t0, x, s), x1, branch(x1, t1), x2, branch(x2, t2))
+        }
+        Tm::Nil(_) => t.clone(),
+        Tm::Cons(t1, t2) => Tm::cons(subst(t1, x, s), subst(t2, x, s)),
+        Tm::Lcase(t1, t2, x1, x2, t3) => {
+            let t3 = if x1 == x || x2 == x {
+                (**t3).clone()
+            } else {
+                subst(t3, x, s)
+            };
+            Tm::lcase(subst(t1, x, s), subst(t2, x, s), x1, x2, t3)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test_stlc {
+    use super::*;
+
+    fn v(x: &str) -> Tm {
+        Tm::var(x)
+    }
+
+    #[test]
+    fn test_subst() {
+        // [x:=\z:Bool. z] (\y:Bool. x y) = \y:Bool. (\z:Bool. z) y
+        let id = Tm::abs("z", Ty::Bool, v("z"));
+        let t = Tm::abs("y", Ty::Bool, Tm::app(v("x"), v("y")));
+        assert_eq!(
+            subst(&t, "x", &id),
+            Tm::abs("y", Ty::Bool, Tm::app(id.clone(), v("y")))
+        );
+        // A binder of the same name shadows it.
+        let t = Tm::abs("x", Ty::Bool, v("x"));
+        assert_eq!(subst(&t, "x", &id), t);
+        assert_eq!(subst(&v("y"), "x", &id), v("y"));
+        // [x:=true] (if x then x else false) = if true then true else false
+        let t = Tm::test(v("x"), v("x"), Tm::Fls);
+        assert_eq!(
+            subst(&t, "x", &Tm::Tru),
+            Tm::test(Tm::Tru, Tm::Tru, Tm::Fls)
+        );
+        // [x:=0] (let x = x in x) = let x = 0 in x
+        let t = Tm::let_("x", v("x"), v("x"));
+        assert_eq!(
+            subst(&t, "x", &Tm::Const(0)),
+            Tm::let_("x", Tm::Const(0), v("x"))
+        );
+    }
+
+    #[test]
+    fn test_free_vars() {
+        let t = Tm::case(
+            v("s"),
+            "x",
+            Tm::app(v("x"), v("y")),
+            "y",
+            Tm::abs("z", Ty::Bool, Tm::pair(v("y"), v("z"))),
+        );
+        let names = |vars: BTreeSet<String>| vars.into_iter().collect::<Vec<_>>();
+        assert_eq!(names(t.free_vars()), ["s", "y"]);
+        let t = Tm::lcase(v("l"), v("x"), "x", "xs", Tm::cons(v("x"), v("xs")));
+        assert_eq!(names(t.free_vars()), ["l", "x"]);
+        // `seq` binds a name its second term doesn't use.
+        let t = Tm::seq(v("f"), Tm::app(v("_0"), v("_1")));
+        assert_eq!(
+            t,
+            Tm::app(Tm::abs("_2", Ty::Unit, Tm::app(v("_0"), v("_1"))), v("f"))
+        );
+    }
+}
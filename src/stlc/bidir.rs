@@ -0,0 +1,353 @@
+//! Bidirectional typechecking, another way to `type_of`'s answers for
+//! closed, location-free terms: `synth` finds a term's type from the term
+//! alone, and `check` checks a term against a type known beforehand,
+//! pushing it down into the term's parts instead of finding their types
+//! and comparing at the end. The types written on binders, `nil` and the
+//! injections are what synthesis needs; checking only compares them with
+//! the type pushed in.
+//!
+//! Pushing the expected type in is what makes for better errors: checking
+//! `\x:Nat. (x, true)` against `Nat -> Nat * Nat` blames `true`, where
+//! `type_of` can only say the whole abstraction has the wrong type.
+
+use super::typing::{list_element, referent, sum_components, upper};
+use super::{lookup_field, subtype, Context, Tm, Ty, TypeError};
+use crate::map::pm_update;
+
+/// Check that `t` has type `ty`, or a subtype of it, in `ctx`:
+///
+/// ```text
+/// T1 <: S1    x |-> S1; Gamma |- t1 <= T2
+/// --------------------------------------    Gamma |- t1 => S    S <: T
+///     Gamma |- \x:S1. t1 <= T1 -> T2        -------------------------
+///                                               Gamma |- t1 <= T
+///
+/// Gamma |- t1 <= Bool    Gamma |- t2 <= T    Gamma |- t3 <= T
+/// ----------------------------------------------------------
+///            Gamma |- if t1 then t2 else t3 <= T
+/// ```
+///
+/// `let` checks its body, and `try` and `case` their branches, against
+/// `T`; pairs, injections, `cons` and records are checked a part at a
+/// time against the parts of a product, sum, list or record type, fields
+/// `T` doesn't mention being synthesized. Anything else is synthesized and
+/// its type compared with `T`, the rule on the right. The error found is
+/// then about as small a part of `t` as can be blamed.
+///
+/// Branches are checked against `T` alone, so where `type_of` rejects
+/// branches whose only common supertype is `Top`, `check` accepts them if
+/// `Top` is what was asked for.
+pub fn check(ctx: &Context, t: &Tm, ty: &Ty) -> Result<(), TypeError> {
+    match (t, ty) {
+        (Tm::Abs(x, ty1, body), Ty::Arrow(ty11, ty12)) if subtype(ty11, ty1) => {
+            check(&pm_update(ctx.clone(), x.clone(), ty1.clone()), body, ty12)
+        }
+        (Tm::Test(t1, t2, t3), _) => {
+            check(ctx, t1, &Ty::Bool)?;
+            check(ctx, t2, ty)?;
+            check(ctx, t3, ty)
+        }
+        (Tm::Let(x, t1, t2), _) => {
+            let ty1 = synth(ctx, t1)?;
+            check(&pm_update(ctx.clone(), x.clone(), ty1), t2, ty)
+        }
+        (Tm::Try(t1, t2), _) => {
+            check(ctx, t1, ty)?;
+            check(ctx, t2, ty)
+        }
+        (Tm::Pair(t1, t2), Ty::Prod(ty1, ty2)) => {
+            check(ctx, t1, ty1)?;
+            check(ctx, t2, ty2)
+        }
+        (Tm::Inl(ty2, t1), Ty::Sum(ty1, ty2_)) if subtype(ty2, ty2_) => check(ctx, t1, ty1),
+        (Tm::Inr(ty1, t2), Ty::Sum(ty1_, ty2)) if subtype(ty1, ty1_) => check(ctx, t2, ty2),
+        (Tm::Case(t0, x1, t1, x2, t2), _) => {
+            let (ty1, ty2) = sum_components(t0, synth(ctx, t0)?)?;
+            check(&pm_update(ctx.clone(), x1.clone(), ty1), t1, ty)?;
+            check(&pm_update(ctx.clone(), x2.clone(), ty2), t2, ty)
+        }
+        (Tm::Cons(t1, t2), Ty::List(ty1)) => {
+            check(ctx, t1, ty1)?;
+            check(ctx, t2, ty)
+        }
+        (Tm::Lcase(t1, t2, x1, x2, t3), _) => {
+            let ty1 = list_element(t1, synth(ctx, t1)?)?;
+            check(ctx, t2, ty)?;
+            let ctx = pm_update(ctx.clone(), x1.clone(), ty1.clone());
+            check(&pm_update(ctx, x2.clone(), Ty::list(ty1)), t3, ty)
+        }
+        (Tm::Record(fields), Ty::Record(ty_fields))
+            if ty_fields
+                .iter()
+                .all(|(x, _)| lookup_field(fields, x).is_some()) =>
+        {
+            for (i, (x, t1)) in fields.iter().enumerate() {
+                // Only the first field labelled `x` has to be a `T1`.
+                let first = fields[..i].iter().all(|(y, _)| y != x);
+                match lookup_field(ty_fields, x) {
+                    Some(ty1) if first => check(ctx, t1, ty1)?,
+                    _ => {
+                        synth(ctx, t1)?;
+                    }
+                }
+            }
+            Ok(())
+        }
+        _ => {
+            let found = synth(ctx, t)?;
+            if subtype(&found, ty) {
+                Ok(())
+            } else {
+                Err(TypeError::Mismatch {
+                    term: Box::new(t.clone()),
+                    expected: ty.clone(),
+                    found,
+                })
+            }
+        }
+    }
+}
+
+/// The type of `t` in `ctx`, the least type `check` accepts it at. This
+/// is `type_of`, but for parts of `t` whose type is known, like the
+/// argument of an application or the operand of `succ`, which are checked
+/// against it instead. A location has no type, as the store is empty.
+pub fn synth(ctx: &Context, t: &Tm) -> Result<Ty, TypeError> {
+    match t {
+        Tm::Var(x) => ctx(x).ok_or_else(|| TypeError::Unbound(x.clone())),
+        Tm::Abs(x, ty, body) => {
+            let ctx = pm_update(ctx.clone(), x.clone(), ty.clone());
+            Ok(Ty::arrow(ty.clone(), synth(&ctx, body)?))
+        }
+        Tm::App(t1, t2) => match synth(ctx, t1)? {
+            Ty::Arrow(ty11, ty12) => {
+                check(ctx, t2, &ty11)?;
+                Ok(*ty12)
+            }
+            Ty::Bot => {
+                synth(ctx, t2)?;
+                Ok(Ty::Bot)
+            }
+            found => Err(TypeError::WrongShape {
+                term: t1.clone(),
+                expected: "a function type",
+                found,
+            }),
+        },
+        Tm::Tru | Tm::Fls => Ok(Ty::Bool),
+        Tm::Unit => Ok(Ty::Unit),
+        Tm::Const(_) => Ok(Ty::Nat),
+        Tm::Error => Ok(Ty::Bot),
+        Tm::Test(t1, t2, t3) => {
+            check(ctx, t1, &Ty::Bool)?;
+            let ty = synth(ctx, t2)?;
+            upper(t3, ty, synth(ctx, t3)?)
+        }
+        Tm::Scc(t1) | Tm::Prd(t1) => {
+            check(ctx, t1, &Ty::Nat)?;
+            Ok(Ty::Nat)
+        }
+        Tm::Mlt(t1, t2) => {
+            check(ctx, t1, &Ty::Nat)?;
+            check(ctx, t2, &Ty::Nat)?;
+            Ok(Ty::Nat)
+        }
+        Tm::IsZero(t1) => {
+            check(ctx, t1, &Ty::Nat)?;
+            Ok(Ty::Bool)
+        }
+        Tm::Let(x, t1, t2) => {
+            let ty1 = synth(ctx, t1)?;
+            synth(&pm_update(ctx.clone(), x.clone(), ty1), t2)
+        }
+        Tm::Try(t1, t2) => {
+            let ty = synth(ctx, t1)?;
+            upper(t2, ty, synth(ctx, t2)?)
+        }
+        Tm::Pair(t1, t2) => Ok(Ty::prod(synth(ctx, t1)?, synth(ctx, t2)?)),
+        Tm::Fst(t1) | Tm::Snd(t1) => match synth(ctx, t1)? {
+            Ty::Prod(ty1, ty2) => Ok(if matches!(t, Tm::Fst(_)) { *ty1 } else { *ty2 }),
+            Ty::Bot => Ok(Ty::Bot),
+            found => Err(TypeError::WrongShape {
+                term: t1.clone(),
+                expected: "a product type",
+                found,
+            }),
+        },
+        Tm::Inl(ty2, t1) => Ok(Ty::sum(synth(ctx, t1)?, ty2.clone())),
+        Tm::Inr(ty1, t2) => Ok(Ty::sum(ty1.clone(), synth(ctx, t2)?)),
+        Tm::Case(t0, x1, t1, x2, t2) => {
+            let (ty1, ty2) = sum_components(t0, synth(ctx, t0)?)?;
+            let ty = synth(&pm_update(ctx.clone(), x1.clone(), ty1), t1)?;
+            upper(t2, ty, synth(&pm_update(ctx.clone(), x2.clone(), ty2), t2)?)
+        }
+        Tm::Fix(t1) => match synth(ctx, t1)? {
+            Ty::Arrow(ty1, ty2) if subtype(&ty2, &ty1) => Ok(*ty2),
+            Ty::Bot => Ok(Ty::Bot),
+            Ty::Arrow(ty1, ty2) => Err(TypeError::Mismatch {
+                term: t1.clone(),
+                expected: Ty::arrow((*ty1).clone(), (*ty1).clone()),
+                found: Ty::Arrow(ty1, ty2),
+            }),
+            found => Err(TypeError::WrongShape {
+                term: t1.clone(),
+                expected: "a function type",
+                found,
+            }),
+        },
+        Tm::Record(fields) => Ok(Ty::Record(
+            fields
+                .iter()
+                .map(|(x, t)| Ok((x.clone(), synth(ctx, t)?)))
+                .collect::<Result<_, _>>()?,
+        )),
+        Tm::Proj(t1, x) => match synth(ctx, t1)? {
+            Ty::Record(fields) => match lookup_field(&fields, x) {
+                Some(ty) => Ok(ty.clone()),
+                None => Err(TypeError::NoField {
+                    term: t1.clone(),
+                    label: x.clone(),
+                    found: Ty::Record(fields),
+                }),
+            },
+            Ty::Bot => Ok(Ty::Bot),
+            found => Err(TypeError::WrongShape {
+                term: t1.clone(),
+                expected: "a record type",
+                found,
+            }),
+        },
+        Tm::Loc(l) => Err(TypeError::Dangling(*l)),
+        Tm::Ref(t1) => Ok(Ty::ref_(synth(ctx, t1)?)),
+        Tm::Deref(t1) => referent(t1, synth(ctx, t1)?),
+        Tm::Assign(t1, t2) => {
+            let ty = match referent(t1, synth(ctx, t1)?)? {
+                Ty::Bot => Ty::Top,
+                ty => ty,
+            };
+            check(ctx, t2, &ty)?;
+            Ok(Ty::Unit)
+        }
+        Tm::Nil(ty) => Ok(Ty::list(ty.clone())),
+        Tm::Cons(t1, t2) => {
+            let ty = Ty::list(synth(ctx, t1)?);
+            upper(t2, ty, synth(ctx, t2)?)
+        }
+        Tm::Lcase(t1, t2, x1, x2, t3) => {
+            let ty1 = list_element(t1, synth(ctx, t1)?)?;
+            let ty = synth(ctx, t2)?;
+            let ctx = pm_update(ctx.clone(), x1.clone(), ty1.clone());
+            upper(
+                t3,
+                ty,
+                synth(&pm_update(ctx, x2.clone(), Ty::list(ty1)), t3)?,
+            )
+        }
+    }
+}
+
+#[cfg(test)]
+mod test_stlc_bidir {
+    use super::*;
+    use crate::stlc::{empty_context, parse_tm, parse_ty, type_of};
+
+    fn ty(s: &str) -> Ty {
+        parse_ty(s).unwrap()
+    }
+
+    fn check_str(s: &str, t: &str) -> Result<(), String> {
+        check(&empty_context(), &parse_tm(s).unwrap(), &ty(t)).map_err(|e| e.to_string())
+    }
+
+    #[test]
+    fn test_agrees_with_type_of() {
+        let ctx = empty_context();
+        for s in [
+            "\\x:Bool -> Bool. \\y:Bool. x y",
+            "(\\r:{x:Nat}. r.x) {x=1, y=true}",
+            "if true then {x=1, y=2} else {x=3}",
+            "\\l:List Nat. case l of | nil => false | x :: xs => iszero x",
+            "cons {x=1, y=true} (nil {x:Nat})",
+            "fix (\\f:Nat -> Nat. \\n:Nat. if iszero n then 1 else mult n (f (pred n)))",
+            "let r = ref 0 in r := succ (!r); !r",
+            "try error (succ 0) with (inl Bool 1, unit)",
+            "case inr Nat true of | inl n => iszero n | inr b => b",
+            "\\b:Bool. if b then 1 else unit",
+            "(\\x:Nat. x) true",
+            "{x=1}.y",
+        ] {
+            let t = parse_tm(s).unwrap();
+            let found = type_of(&ctx, &t);
+            assert_eq!(synth(&ctx, &t), found, "synthesizing {}", s);
+            if let Ok(found) = found {
+                assert_eq!(check(&ctx, &t, &found), Ok(()), "checking {}", s);
+                assert_eq!(check(&ctx, &t, &Ty::Top), Ok(()), "checking {}", s);
+            }
+        }
+    }
+
+    #[test]
+    fn test_check() {
+        assert_eq!(check_str("\\x:Nat. x", "Nat -> Nat"), Ok(()));
+        // A binder may have a larger type than the one asked for.
+        assert_eq!(
+            check_str("\\r:{x:Nat}. r.x", "{x:Nat, y:Nat} -> Top"),
+            Ok(())
+        );
+        assert_eq!(check_str("(1, {x=1, y=2})", "Nat * {y:Nat}"), Ok(()));
+        assert_eq!(check_str("{y=0, x=1, x=true}", "{x:Nat, y:Top}"), Ok(()));
+        assert_eq!(check_str("inl Bool 3", "Nat + Top"), Ok(()));
+        assert_eq!(check_str("cons error (nil Nat)", "List Nat"), Ok(()));
+        // Branches only need to be what is asked for.
+        assert_eq!(check_str("if true then 1 else unit", "Top"), Ok(()));
+        assert_eq!(
+            type_of(
+                &empty_context(),
+                &parse_tm("if true then 1 else unit").unwrap()
+            )
+            .unwrap_err()
+            .to_string(),
+            "`unit` has type Unit, expected Nat"
+        );
+    }
+
+    #[test]
+    fn test_error_locations() {
+        // The part of the term that is wrong is blamed, not all of it.
+        let s = "\\x:Nat. (x, true)";
+        assert_eq!(
+            check_str(s, "Nat -> Nat * Nat"),
+            Err("`true` has type Bool, expected Nat".to_string())
+        );
+        assert_eq!(
+            type_of(&empty_context(), &parse_tm(s).unwrap())
+                .map(|found| subtype(&found, &ty("Nat -> Nat * Nat"))),
+            Ok(false)
+        );
+        assert_eq!(
+            check_str("cons 1 (cons true (nil Nat))", "List Nat"),
+            Err("`true` has type Bool, expected Nat".to_string())
+        );
+        assert_eq!(
+            check_str("\\b:Bool. if b then {x=1} else {x=unit}", "Bool -> {x:Nat}"),
+            Err("`unit` has type Unit, expected Nat".to_string())
+        );
+        assert_eq!(
+            check_str("let n = 1 in inr Nat (n, n)", "Nat + Nat * Bool"),
+            Err("`n` has type Nat, expected Bool".to_string())
+        );
+        // A part that can't be checked against its type is synthesized.
+        assert_eq!(
+            check_str("\\x:Nat. x", "Top -> Nat"),
+            Err("`\\x:Nat. x` has type Nat -> Nat, expected Top -> Nat".to_string())
+        );
+        assert_eq!(
+            check_str("{x=1}", "{x:Nat, y:Nat}"),
+            Err("`{x=1}` has type {x:Nat}, expected {x:Nat, y:Nat}".to_string())
+        );
+        assert_eq!(
+            synth(&empty_context(), &Tm::Loc(0)),
+            Err(TypeError::Dangling(0))
+        );
+    }
+}
@@ -0,0 +1,745 @@
+//! Call-by-value evaluation of STLC terms, the small-step relation of the
+//! Stlc chapter. It ignores types: a well-typed closed term never gets
+//! stuck, but an ill-typed one may evaluate fine anyway, or get stuck, as
+//! `if \x:Bool. x then true else false` does.
+//!
+//! Terms with references step with a store, the `t / st --> t' / st'` of
+//! the References chapter: a `Heap` of the values that locations hold.
+//!
+//! `error` is neither a value nor stuck: it takes the place of any term
+//! it is evaluated in, up to the nearest `try` around it, whose handler is
+//! evaluated instead, so a program may end in `error`.
+//!
+//! Numbers are `u64`s, so `succ` and `mult` wrap around modulo `2^64`,
+//! `succ 18446744073709551615` being `0`, while `pred 0` is `0` as in the
+//! chapter. That way a well-typed term doesn't get stuck at the edge.
+
+use super::{lookup_field, subst, Tm};
+use crate::heap::Heap;
+
+/// What the locations of a term hold, `st` in the chapter.
+pub type Store = Heap<Tm>;
+
+/// Whether `t` is a value: an abstraction, `true`, `false`, `unit`, a
+/// number, a location, a pair of values, a value injected into a sum, a
+/// list of values, or a record of them.
+pub fn is_value(t: &Tm) -> bool {
+    match t {
+        Tm::Abs(..) | Tm::Tru | Tm::Fls | Tm::Unit | Tm::Const(_) | Tm::Nil(_) | Tm::Loc(_) => true,
+        Tm::Pair(t1, t2) | Tm::Cons(t1, t2) => is_value(t1) && is_value(t2),
+        Tm::Inl(_, t1) | Tm::Inr(_, t1) => is_value(t1),
+        Tm::Record(fields) => fields.iter().all(|(_, t)| is_value(t)),
+        _ => false,
+    }
+}
+
+/// One call-by-value step of `t` in `store`, with the store after it;
+/// `None` if `t` is a value or stuck:
+///
+/// ```text
+///       value v2                  t1 --> t1'           value v1   t2 --> t2'
+/// ---------------------------   -----------------   ------------------------
+/// (\x:T. t1) v2 --> [x:=v2]t1   t1 t2 --> t1' t2       v1 t2 --> v1 t2'
+///
+/// if true then t2 else t3 --> t2      if false then t2 else t3 --> t3
+///
+///                         t1 --> t1'
+/// ------------------------------------------------------
+/// if t1 then t2 else t3 --> if t1' then t2 else t3
+///
+/// succ n --> n + 1    pred 0 --> 0    pred (n + 1) --> n
+/// mult m n --> m * n    iszero 0 --> true    iszero (n + 1) --> false
+///
+///            t1 --> t1'                             value v1
+/// -----------------------------------    --------------------------------
+/// let x = t1 in t2 --> let x = t1' in t2    let x = v1 in t2 --> [x:=v1]t2
+///
+/// (v1, v2).fst --> v1    (v1, v2).snd --> v2
+///
+/// case inl T2 v0 of | inl x1 => t1 | inr x2 => t2 --> [x1:=v0]t1
+/// case inr T1 v0 of | inl x1 => t1 | inr x2 => t2 --> [x2:=v0]t2
+///
+/// case nil T of | nil => t2 | x1 :: x2 => t3 --> t2
+/// case cons v1 vl of | nil => t2 | x1 :: x2 => t3 --> [x2:=vl][x1:=v1]t3
+///
+/// fix (\x:T. t1) --> [x:=fix (\x:T. t1)]t1
+///
+///                    ti --> ti'
+/// ------------------------------------------------------
+/// {x1=v1, ..., xi=ti, ...} --> {x1=v1, ..., xi=ti', ...}
+///
+/// {..., x=v, ...}.x --> v
+///
+///         l not in dom(st)
+/// -----------------------------    !(loc l) / st --> st(l) / st
+/// ref v / st --> loc l / st[l:=v]
+///
+/// loc l := v / st --> unit / st[l:=v]
+///
+/// E[error] --> error    try v1 with t2 --> v1    try error with t2 --> t2
+///
+///              t1 --> t1'
+/// ------------------------------------
+/// try t1 with t2 --> try t1' with t2
+/// ```
+///
+/// where `E` is any of the evaluation contexts the rules above step
+/// inside, like `error t2`, `v1 error` or `succ error`, but not `try`.
+///
+/// A pair is a value once both its components are; they are evaluated
+/// left to right, and so is the pair a projection is of. Record fields
+/// are evaluated left to right too, and projecting a field a record value
+/// doesn't have is stuck.
+///
+/// The arguments of `succ`, `pred`, `mult` and `iszero` are evaluated
+/// first, left to right, as is that of `fix`. Numbers are `u64`s, so
+/// `succ` or `mult` past `u64::MAX` is stuck rather than wrapping around.
+/// With `fix` a well-typed term may step forever, which `eval`'s fuel
+/// bounds. The operands of `ref`, `!` and `:=` are evaluated first, left
+/// to right, and a location the store doesn't have is stuck.
+pub fn step_in(t: &Tm, store: &Store) -> Option<(Tm, Store)> {
+    // The store is unchanged by all but the reference steps.
+    let done = |t: Tm| Some((t, store.clone()));
+    // A step of `t1`, a part of `t`, put back into `t` by `rebuild`.
+    // An `error` there aborts `t` instead.
+    let within = |t1: &Tm, rebuild: &dyn Fn(Tm) -> Tm| {
+        if *t1 == Tm::Error {
+            return done(Tm::Error);
+        }
+        let (t1, store) = step_in(t1, store)?;
+        Some((rebuild(t1), store))
+    };
+    // The same, for a part that mustn't be a value yet.
+    let within_arg = |t1: &Tm, rebuild: &dyn Fn(Tm) -> Tm| {
+        if is_value(t1) {
+            None
+        } else {
+            within(t1, rebuild)
+        }
+    };
+    match t {
+        Tm::Var(_)
+        | Tm::Abs(..)
+        | Tm::Tru
+        | Tm::Fls
+        | Tm::Unit
+        | Tm::Const(_)
+        | Tm::Nil(_)
+        | Tm::Loc(_)
+        | Tm::Error => None,
+        Tm::App(t1, t2) => {
+            if !is_value(t1) {
+                return within(t1, &|t1| Tm::app(t1, (**t2).clone()));
+            }
+            if !is_value(t2) {
+                return within(t2, &|t2| Tm::app((**t1).clone(), t2));
+            }
+            match &**t1 {
+                Tm::Abs(x, _, body) => done(subst(body, x, t2)),
+                // Applying a boolean is stuck.
+                _ => None,
+            }
+        }
+        Tm::Test(t1, t2, t3) => match &**t1 {
+            Tm::Tru => done((**t2).clone()),
+            Tm::Fls => done((**t3).clone()),
+            t1 => within_arg(t1, &|t1| Tm::test(t1, (**t2).clone(), (**t3).clone())),
+        },
+        Tm::Scc(t1) => match &**t1 {
+            Tm::Const(n) => done(Tm::Const(n.wrapping_add(1))),
+            t1 => within_arg(t1, &Tm::scc),
+        },
+        Tm::Prd(t1) => match &**t1 {
+            Tm::Const(n) => done(Tm::Const(n.saturating_sub(1))),
+            t1 => within_arg(t1, &Tm::prd),
+        },
+        Tm::Mlt(t1, t2) => match (&**t1, &**t2) {
+            (Tm::Const(m), Tm::Const(n)) => done(Tm::Const(m.wrapping_mul(*n))),
+            (t1, t2) if is_value(t1) => within_arg(t2, &|t2| Tm::mlt(t1.clone(), t2)),
+            (t1, t2) => within(t1, &|t1| Tm::mlt(t1, t2.clone())),
+        },
+        Tm::IsZero(t1) => match &**t1 {
+            Tm::Const(n) => done(if *n == 0 { Tm::Tru } else { Tm::Fls }),
+            t1 => within_arg(t1, &Tm::is_zero),
+        },
+        Tm::Fix(t1) => match &**t1 {
+            Tm::Abs(x, _, body) => done(subst(body, x, t)),
+            t1 => within_arg(t1, &Tm::fix),
+        },
+        Tm::Record(fields) => {
+            let i = fields.iter().position(|(_, t)| !is_value(t))?;
+            within(&fields[i].1, &|ti| {
+                let mut fields = fields.clone();
+                fields[i].1 = ti;
+                Tm::Record(fields)
+            })
+        }
+        Tm::Proj(t1, x) => match &**t1 {
+            Tm::Record(fields) if is_value(t1) => done(lookup_field(fields, x)?.clone()),
+            t1 => within_arg(t1, &|t1| Tm::proj(t1, x)),
+        },
+        Tm::Let(x, t1, t2) if is_value(t1) => done(subst(t2, x, t1)),
+        Tm::Let(x, t1, t2) => within(t1, &|t1| Tm::let_(x, t1, (**t2).clone())),
+        Tm::Pair(t1, t2) if is_value(t1) => within(t2, &|t2| Tm::pair((**t1).clone(), t2)),
+        Tm::Pair(t1, t2) => within(t1, &|t1| Tm::pair(t1, (**t2).clone())),
+        Tm::Fst(t1) | Tm::Snd(t1) => match &**t1 {
+            Tm::Pair(v1, v2) if is_value(t1) => done(if matches!(t, Tm::Fst(_)) {
+                (**v1).clone()
+            } else {
+                (**v2).clone()
+            }),
+            t1 if matches!(t, Tm::Fst(_)) => within_arg(t1, &Tm::fst),
+            t1 => within_arg(t1, &Tm::snd),
+        },
+        Tm::Inl(ty, t1) => within(t1, &|t1| Tm::inl(ty.clone(), t1)),
+        Tm::Inr(ty, t1) => within(t1, &|t1| Tm::inr(ty.clone(), t1)),
+        Tm::Case(t0, x1, t1, x2, t2) => match &**t0 {
+            Tm::Inl(_, v0) if is_value(v0) => done(subst(t1, x1, v0)),
+            Tm::Inr(_, v0) if is_value(v0) => done(subst(t2, x2, v0)),
+            t0 => within_arg(t0, &|t0| {
+                Tm::case(t0, x1, (**t1).clone(), x2, (**t2).clone())
+            }),
+        },
+        Tm::Cons(t1, t2) if is_value(t1) => within(t2, &|t2| Tm::cons((**t1).clone(), t2)),
+        Tm::Cons(t1, t2) => within(t1, &|t1| Tm::cons(t1, (**t2).clone())),
+        Tm::Lcase(t1, t2, x1, x2, t3) => match &**t1 {
+            Tm::Nil(_) => done((**t2).clone()),
+            Tm::Cons(v1, vl) if is_value(t1) => done(subst(&subst(t3, x1, v1), x2, vl)),
+            t1 => within_arg(t1, &|t1| {
+                Tm::lcase(t1, (**t2).clone(), x1, x2, (**t3).clone())
+            }),
+        },
+        Tm::Ref(t1) if is_value(t1) => {
+            let (store, l) = store.clone().alloc((**t1).clone());
+            Some((Tm::Loc(l), store))
+        }
+        Tm::Ref(t1) => within(t1, &Tm::ref_),
+        Tm::Deref(t1) => match &**t1 {
+            Tm::Loc(l) => done(store.lookup(*l)?),
+            t1 => within_arg(t1, &Tm::deref),
+        },
+        Tm::Assign(t1, t2) => match (&**t1, &**t2) {
+            (Tm::Loc(l), t2) if is_value(t2) => {
+                Some((Tm::Unit, store.clone().assign(*l, t2.clone())?))
+            }
+            (t1, t2) if is_value(t1) => within(t2, &|t2| Tm::assign(t1.clone(), t2)),
+            (t1, t2) => within(t1, &|t1| Tm::assign(t1, t2.clone())),
+        },
+        Tm::Try(t1, _) if is_value(t1) => done((**t1).clone()),
+        Tm::Try(t1, t2) if **t1 == Tm::Error => done((**t2).clone()),
+        Tm::Try(t1, t2) => within(t1, &|t1| Tm::try_(t1, (**t2).clone())),
+    }
+}
+
+/// `step_in` from the empty store, for terms that don't use references:
+/// the store a step leaves is dropped, so a location it allocated dangles.
+pub fn step(t: &Tm) -> Option<Tm> {
+    step_in(t, &Store::new()).map(|(t, _)| t)
+}
+
+/// Step from `t` and `store` until no step applies, and return the value
+/// or stuck term reached, with the store then; `None` if that takes more
+/// than `fuel` steps.
+pub fn eval_in(t: &Tm, store: &Store, fuel: u64) -> Option<(Tm, Store)> {
+    let (mut t, mut store) = (t.clone(), store.clone());
+    for _ in 0..=fuel {
+        match step_in(&t, &store) {
+            Some(next) => (t, store) = next,
+            None => return Some((t, store)),
+        }
+    }
+    None
+}
+
+/// `eval_in` from the empty store, keeping only the term reached.
+pub fn eval(t: &Tm, fuel: u64) -> Option<Tm> {
+    eval_in(t, &Store::new(), fuel).map(|(t, _)| t)
+}
+
+#[cfg(test)]
+mod test_stlc_eval {
+    use super::*;
+    use crate::stlc::{
+        desugar_let, empty_context, parse_tm, parse_ty, type_of, type_of_in, StoreTy, Ty, TypeError,
+    };
+
+    fn tm(s: &str) -> Tm {
+        parse_tm(s).unwrap()
+    }
+
+    const ID_B: &str = "\\x:Bool. x";
+    const ID_BB: &str = "\\x:Bool -> Bool. x";
+
+    #[test]
+    fn test_values() {
+        assert!(is_value(&tm(ID_B)));
+        assert!(!is_value(&tm("x")));
+        assert_eq!(step(&tm(ID_B)), None);
+        // Nothing happens under a binder.
+        let t = tm("\\y:Bool. (\\x:Bool. x) y");
+        assert_eq!(eval(&t, 10), Some(t));
+    }
+
+    #[test]
+    fn test_steps() {
+        // idBB idB --> idB, each well typed.
+        let t = Tm::app(tm(ID_BB), tm(ID_B));
+        assert_eq!(
+            type_of(&empty_context(), &t),
+            Ok(parse_ty("Bool -> Bool").unwrap())
+        );
+        assert_eq!(step(&t), Some(tm(ID_B)));
+        // idBB (idBB idB) -->* idB, the argument first.
+        let t = Tm::app(tm(ID_BB), Tm::app(tm(ID_BB), tm(ID_B)));
+        assert_eq!(step(&t), Some(Tm::app(tm(ID_BB), tm(ID_B))));
+        assert_eq!(eval(&t, 2), Some(tm(ID_B)));
+        assert_eq!(eval(&t, 1), None);
+        // (\f:(Bool -> Bool) -> Bool -> Bool. f idB) idBB -->* idB
+        let t = tm("(\\f:(Bool -> Bool) -> Bool -> Bool. f (\\x:Bool. x)) (\\x:Bool -> Bool. x)");
+        assert_eq!(eval(&t, 10), Some(tm(ID_B)));
+        let ty = type_of(&empty_context(), &t).unwrap();
+        assert_eq!(ty.to_string(), "Bool -> Bool");
+    }
+
+    #[test]
+    fn test_conditionals() {
+        // (\x:Bool. if x then false else true) true -->* false
+        let not = tm("\\x:Bool. if x then false else true");
+        let t = Tm::app(not.clone(), Tm::Tru);
+        assert_eq!(step(&t), Some(tm("if true then false else true")));
+        assert_eq!(eval(&t, 10), Some(Tm::Fls));
+        // The guard is evaluated first, and only the branch taken.
+        let t = tm("if (\\x:Bool. x) false then (\\x:Bool. x) true else false");
+        assert_eq!(
+            step(&t),
+            Some(tm("if false then (\\x:Bool. x) true else false"))
+        );
+        assert_eq!(eval(&t, 10), Some(Tm::Fls));
+        let t = Tm::test(Tm::app(not.clone(), Tm::Fls), Tm::Tru, Tm::Fls);
+        assert_eq!(type_of(&empty_context(), &t), Ok(Ty::Bool));
+        assert_eq!(eval(&t, 10), Some(Tm::Tru));
+    }
+
+    #[test]
+    fn test_arithmetic() {
+        assert!(is_value(&Tm::Const(3)));
+        // mult (succ 2) (pred 3) -->* 6, left to right.
+        let t = tm("mult (succ 2) (pred 3)");
+        assert_eq!(step(&t), Some(tm("mult 3 (pred 3)")));
+        assert_eq!(eval(&t, 10), Some(Tm::Const(6)));
+        assert_eq!(eval(&tm("pred 0"), 10), Some(Tm::Const(0)));
+        let t = tm("(\\n:Nat. if iszero n then 1 else mult n (pred n)) 4");
+        assert_eq!(type_of(&empty_context(), &t), Ok(Ty::Nat));
+        assert_eq!(eval(&t, 10), Some(Tm::Const(12)));
+        assert_eq!(eval(&tm("iszero (pred 1)"), 10), Some(Tm::Tru));
+        assert_eq!(eval(&tm("iszero 7"), 10), Some(Tm::Fls));
+        // Past `u64::MAX`, numbers wrap around.
+        let t = tm("succ 18446744073709551615");
+        assert_eq!(type_of(&empty_context(), &t), Ok(Ty::Nat));
+        assert_eq!(eval(&t, 10), Some(Tm::Const(0)));
+        assert_eq!(
+            eval(&tm("mult 4611686018427387904 4"), 10),
+            Some(Tm::Const(0))
+        );
+        assert_eq!(
+            eval(&tm("mult 4611686018427387905 4"), 10),
+            Some(Tm::Const(4))
+        );
+    }
+
+    #[test]
+    fn test_let() {
+        // The definition is evaluated first, then substituted.
+        let t = tm("let x = succ 1 in mult x x");
+        assert_eq!(step(&t), Some(tm("let x = 2 in mult x x")));
+        assert_eq!(step(&step(&t).unwrap()), Some(tm("mult 2 2")));
+        assert_eq!(eval(&t, 10), Some(Tm::Const(4)));
+        // The inner `x` shadows the outer in the body, but the inner
+        // definition still sees the outer one.
+        let t = tm("let x = 3 in let x = succ x in mult x x");
+        assert_eq!(eval(&t, 10), Some(Tm::Const(16)));
+        // A binder inside the body shadows the `let`.
+        let t = tm("(let x = true in \\x:Nat. succ x) 4");
+        assert_eq!(type_of(&empty_context(), &t), Ok(Ty::Nat));
+        assert_eq!(eval(&t, 10), Some(Tm::Const(5)));
+        // And the desugared term agrees.
+        let desugared = desugar_let(&empty_context(), &t).unwrap();
+        assert_eq!(eval(&desugared, 10), Some(Tm::Const(5)));
+    }
+
+    #[test]
+    fn test_pairs() {
+        // Components are evaluated left to right, and projected only once
+        // the whole pair is a value.
+        let t = tm("(succ 0, pred 2).snd");
+        assert_eq!(step(&t), Some(tm("(1, pred 2).snd")));
+        assert_eq!(step(&tm("(1, pred 2).snd")), Some(tm("(1, 1).snd")));
+        assert_eq!(eval(&t, 10), Some(Tm::Const(1)));
+        assert!(is_value(&tm("(1, \\x:Nat. x)")));
+        assert!(!is_value(&tm("(1, succ 1)")));
+        // Swapping, and a conditional on a component.
+        let swap = "\\p:Nat * Bool. (p.snd, p.fst)";
+        let t = tm(&format!("({}) (3, false)", swap));
+        assert_eq!(eval(&t, 10), Some(tm("(false, 3)")));
+        let t = tm(&format!(
+            "let p = ({}) (3, iszero 0) in if p.fst then mult p.snd 2 else 0",
+            swap
+        ));
+        assert_eq!(type_of(&empty_context(), &t), Ok(Ty::Nat));
+        assert_eq!(eval(&t, 20), Some(Tm::Const(6)));
+        // A pair of functions, and a function returning a pair.
+        let t = tm("((\\x:Nat. succ x, \\x:Nat. pred x).snd 5, (\\x:Nat. (x, x)) 2)");
+        assert_eq!(eval(&t, 20), Some(tm("(4, (2, 2))")));
+        // Projecting a non-pair is stuck.
+        let t = tm("(\\x:Nat. x).fst");
+        assert_eq!(step(&t), None);
+        assert!(type_of(&empty_context(), &t).is_err());
+    }
+
+    /// A `Nat + Bool` as an optional number: `inl Bool n` is some `n`, and
+    /// `inr Nat false` is none.
+    const SAFE_PRED: &str = "\\n:Nat. if iszero n then inr Nat false else inl Bool (pred n)";
+    const GET_OR: &str = "\\d:Nat. \\o:Nat + Bool. case o of | inl n => n | inr b => d";
+
+    #[test]
+    fn test_sums() {
+        // The payload is evaluated first, then the matching branch taken.
+        let t = tm("case inr Nat (iszero 0) of | inl n => 0 | inr b => if b then 1 else 2");
+        assert_eq!(
+            step(&t),
+            Some(tm(
+                "case inr Nat true of | inl n => 0 | inr b => if b then 1 else 2"
+            ))
+        );
+        assert_eq!(eval(&t, 10), Some(Tm::Const(1)));
+        assert!(is_value(&tm("inl Bool (1, 2)")));
+        assert!(!is_value(&tm("inl Bool (succ 1)")));
+        // An optional predecessor, defaulting to 100 at 0.
+        let t = |n: u64| {
+            tm(&format!(
+                "let safe_pred = {} in let get_or = {} in get_or 100 (safe_pred {})",
+                SAFE_PRED, GET_OR, n
+            ))
+        };
+        assert_eq!(type_of(&empty_context(), &t(0)), Ok(Ty::Nat));
+        assert_eq!(eval(&t(5), 50), Some(Tm::Const(4)));
+        assert_eq!(eval(&t(0), 50), Some(Tm::Const(100)));
+        // Mapping over an option.
+        let map = "\\f:Nat -> Nat. \\o:Nat + Bool. case o of | inl n => inl Bool (f n) | inr b => inr Nat b";
+        let t = tm(&format!(
+            "({}) (\\n:Nat. mult n n) (({}) 4)",
+            map, SAFE_PRED
+        ));
+        assert_eq!(
+            type_of(&empty_context(), &t),
+            Ok(parse_ty("Nat + Bool").unwrap())
+        );
+        assert_eq!(eval(&t, 50), Some(tm("inl Bool 9")));
+        // A case on something that isn't a sum is stuck.
+        let t = tm("case 0 of | inl x => x | inr y => y");
+        assert_eq!(step(&t), None);
+        assert!(type_of(&empty_context(), &t).is_err());
+    }
+
+    const FACT: &str = "fix (\\f:Nat -> Nat. \\n:Nat. if iszero n then 1 else mult n (f (pred n)))";
+
+    #[test]
+    fn test_fix() {
+        let t = tm(&format!("({}) 5", FACT));
+        assert_eq!(type_of(&empty_context(), &t), Ok(Ty::Nat));
+        assert_eq!(eval(&t, 200), Some(Tm::Const(120)));
+        // One unfolding, substituting the `fix` itself for `f`.
+        let fact = tm(FACT);
+        let Tm::Fix(f) = &fact else { unreachable!() };
+        let Tm::Abs(_, _, body) = &**f else {
+            unreachable!()
+        };
+        assert_eq!(step(&fact), Some(subst(body, "f", &fact)));
+        // Mutual recursion: a fixed point of a pair of functions, each
+        // calling the other through it.
+        let even_odd = "fix (\\eo:(Nat -> Bool) * (Nat -> Bool). \
+             (\\n:Nat. if iszero n then true else eo.snd (pred n), \
+              \\n:Nat. if iszero n then false else eo.fst (pred n)))";
+        let ty = type_of(&empty_context(), &tm(even_odd)).unwrap();
+        assert_eq!(ty.to_string(), "(Nat -> Bool) * (Nat -> Bool)");
+        for (n, even) in [(0, true), (3, false), (6, true)] {
+            for (proj, expected) in [("fst", even), ("snd", !even)] {
+                let t = tm(&format!("({}).{} {}", even_odd, proj, n));
+                let value = if expected { Tm::Tru } else { Tm::Fls };
+                assert_eq!(eval(&t, 500), Some(value));
+            }
+        }
+    }
+
+    #[test]
+    fn test_divergence() {
+        // fix (\x:Nat. x) is well typed and steps to itself forever.
+        let t = tm("fix (\\x:Nat. x)");
+        assert_eq!(type_of(&empty_context(), &t), Ok(Ty::Nat));
+        assert_eq!(step(&t), Some(t.clone()));
+        assert_eq!(eval(&t, 1000), None);
+        // The factorial of a number that never arrives runs out of fuel too.
+        let t = tm(&format!("({}) (fix (\\x:Nat. x))", FACT));
+        assert_eq!(eval(&t, 1000), None);
+    }
+
+    #[test]
+    fn test_list_recursion() {
+        let length = "fix (\\len:List Nat -> Nat. \\l:List Nat. \
+             case l of | nil => 0 | x :: xs => succ (len xs))";
+        let append = "fix (\\app:List Nat -> List Nat -> List Nat. \
+             \\l1:List Nat. \\l2:List Nat. \
+             case l1 of | nil => l2 | x :: xs => cons x (app xs l2))";
+        let ctx = empty_context();
+        assert_eq!(
+            type_of(&ctx, &tm(length)).unwrap().to_string(),
+            "List Nat -> Nat"
+        );
+        assert_eq!(
+            type_of(&ctx, &tm(append)).unwrap().to_string(),
+            "List Nat -> List Nat -> List Nat"
+        );
+        let l = "cons 1 (cons 2 (nil Nat))";
+        let t = tm(&format!("({}) ({})", length, l));
+        assert_eq!(eval(&t, 100), Some(Tm::Const(2)));
+        let t = tm(&format!("({}) (nil Nat)", length));
+        assert_eq!(eval(&t, 100), Some(Tm::Const(0)));
+        let t = tm(&format!("({}) ({}) (cons 3 (nil Nat))", append, l));
+        assert_eq!(
+            eval(&t, 100),
+            Some(tm("cons 1 (cons 2 (cons 3 (nil Nat)))"))
+        );
+        // length (append l l) = 4
+        let t = tm(&format!("({}) (({}) ({}) ({}))", length, append, l, l));
+        assert_eq!(eval(&t, 200), Some(Tm::Const(4)));
+    }
+
+    #[test]
+    fn test_sequencing() {
+        // Every term is evaluated, in order, and only the last one's value
+        // is kept; the others have to be `Unit`s.
+        let t = tm("(\\u:Unit. u) unit; (\\n:Nat. unit) 3; succ 1");
+        assert_eq!(type_of(&empty_context(), &t), Ok(Ty::Nat));
+        assert_eq!(eval(&t, 10), Some(Tm::Const(2)));
+        assert!(is_value(&Tm::Unit));
+        let t = tm("1; 2");
+        assert!(matches!(
+            type_of(&empty_context(), &t),
+            Err(TypeError::Mismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn test_exceptions() {
+        // An error aborts the application it is the function or the
+        // argument of, and everything evaluated around it.
+        assert_eq!(step(&tm("error (succ 0)")), Some(Tm::Error));
+        assert_eq!(step(&tm("(\\n:Nat. n) error")), Some(Tm::Error));
+        let t = tm("succ ((\\f:Nat -> Nat. f 0) (\\n:Nat. if iszero n then error else n))");
+        assert_eq!(type_of(&empty_context(), &t), Ok(Ty::Nat));
+        assert_eq!(eval(&t, 10), Some(Tm::Error));
+        assert_eq!(eval(&tm("(1, {x=error}).snd"), 10), Some(Tm::Error));
+        assert_eq!(
+            eval(&tm("case error of | nil => 0 | x :: xs => x"), 10),
+            Some(Tm::Error)
+        );
+        // Arguments after it are never evaluated, nor is the rest of a
+        // sequence.
+        let (t, store) = eval_in(
+            &tm("(\\r:Ref Nat. error; r := 1) (ref 0)"),
+            &Store::new(),
+            10,
+        )
+        .unwrap();
+        assert_eq!((t, store.lookup(0)), (Tm::Error, Some(Tm::Const(0))));
+        // A handler is evaluated instead of an error, and only then.
+        let t = tm("try succ ((\\n:Nat. error) 1) with 7");
+        assert_eq!(type_of(&empty_context(), &t), Ok(Ty::Nat));
+        assert_eq!(eval(&t, 10), Some(Tm::Const(7)));
+        assert_eq!(eval(&tm("try pred 3 with error"), 10), Some(Tm::Const(2)));
+        // The nearest `try` catches it, and a handler's own errors reach
+        // the next one out.
+        let t = tm("try (try error with error) with {n=5}");
+        assert_eq!(eval(&t, 10), Some(tm("{n=5}")));
+        let t = tm("try (\\f:Nat -> Nat. f 0) (\\n:Nat. try error with succ n) with 9");
+        assert_eq!(eval(&t, 10), Some(Tm::Const(1)));
+        // `error` isn't a value, but nothing steps from it.
+        assert!(!is_value(&Tm::Error));
+        assert_eq!(step(&Tm::Error), None);
+    }
+
+    #[test]
+    fn test_records() {
+        let t = tm("{x=succ 0, y=pred 3}.y");
+        assert_eq!(step(&t), Some(tm("{x=1, y=pred 3}.y")));
+        assert_eq!(step(&tm("{x=1, y=pred 3}.y")), Some(tm("{x=1, y=2}.y")));
+        assert_eq!(eval(&t, 10), Some(Tm::Const(2)));
+        assert!(is_value(&tm("{}")));
+        assert!(is_value(&tm("{x=1, f=\\n:Nat. succ n}")));
+        // A record as an argument, and a field of it applied.
+        let t = tm("(\\r:{f:Nat -> Nat, n:Nat}. r.f r.n) {f=\\n:Nat. mult n n, n=3}");
+        assert_eq!(type_of(&empty_context(), &t), Ok(Ty::Nat));
+        assert_eq!(eval(&t, 10), Some(Tm::Const(9)));
+        // Records nest, and so do projections.
+        let t = tm("{a={b=true}, c=0}.a.b");
+        assert_eq!(eval(&t, 10), Some(Tm::Tru));
+        // The first of two fields with the same label is the one found.
+        assert_eq!(eval(&tm("{x=1, x=2}.x"), 10), Some(Tm::Const(1)));
+        // A missing field is stuck.
+        assert_eq!(step(&tm("{x=1}.y")), None);
+    }
+
+    /// The store typing of `store`, each cell typed in that of those before.
+    fn store_ty(store: &Store) -> StoreTy {
+        store.domain().into_iter().fold(StoreTy::new(), |st, l| {
+            let ty = type_of_in(&st, &empty_context(), &store.lookup(l).unwrap()).unwrap();
+            st.alloc(ty).0
+        })
+    }
+
+    #[test]
+    fn test_references() {
+        let (t, store) = step_in(&tm("ref (succ 0)"), &Store::new()).unwrap();
+        assert_eq!(t, tm("ref 1"));
+        assert!(store.is_empty());
+        let (t, store) = step_in(&t, &store).unwrap();
+        assert_eq!(t, Tm::Loc(0));
+        assert_eq!(store.lookup(0), Some(Tm::Const(1)));
+        assert_eq!(
+            step_in(&tm("!(loc 0)"), &store),
+            Some((Tm::Const(1), store.clone()))
+        );
+        let (t, store) = step_in(&tm("loc 0 := 2"), &store).unwrap();
+        assert_eq!(t, Tm::Unit);
+        assert_eq!(store.lookup(0), Some(Tm::Const(2)));
+        // A location the store doesn't have is stuck.
+        assert_eq!(step_in(&tm("!(loc 1)"), &store), None);
+        assert_eq!(step_in(&tm("loc 1 := 0"), &store), None);
+        // Cells are shared, through any name for them.
+        let t = tm("let r = ref 5 in let s = r in s := succ (!r); !r");
+        assert_eq!(type_of(&empty_context(), &t), Ok(Ty::Nat));
+        assert_eq!(eval(&t, 20), Some(Tm::Const(6)));
+        // Each step keeps the type, in the store typing of its store.
+        let mut t = tm("(\\r:Ref Nat. r := mult (!r) (!r); (!r, ref r)) (ref 3)");
+        let mut store = Store::new();
+        let ty = type_of(&empty_context(), &t).unwrap();
+        assert_eq!(ty.to_string(), "Nat * Ref Ref Nat");
+        while let Some((next, next_store)) = step_in(&t, &store) {
+            (t, store) = (next, next_store);
+            assert_eq!(
+                type_of_in(&store_ty(&store), &empty_context(), &t),
+                Ok(ty.clone())
+            );
+        }
+        assert_eq!(t, tm("(9, loc 1)"));
+        assert_eq!(store.lookup(1), Some(Tm::Loc(0)));
+    }
+
+    #[test]
+    fn test_counter() {
+        // The References chapter's counter objects: a record of closures
+        // sharing a cell that nothing else can reach.
+        let new_counter = "\\_:Unit. let c = ref 0 in \
+             let inc = \\_:Unit. c := succ (!c); !c in \
+             let dec = \\_:Unit. c := pred (!c); !c in \
+             {i=inc, d=dec}";
+        let counter = parse_ty("{i:Unit -> Nat, d:Unit -> Nat}").unwrap();
+        assert_eq!(
+            type_of(&empty_context(), &tm(new_counter)),
+            Ok(Ty::arrow(Ty::Unit, counter))
+        );
+        let t = tm(&format!(
+            "let new = {} in let c1 = new unit in let c2 = new unit in \
+             let _ = c1.i unit in let _ = c1.i unit in let _ = c2.i unit in \
+             (c1.i unit, c2.d unit)",
+            new_counter
+        ));
+        assert_eq!(
+            type_of(&empty_context(), &t),
+            Ok(parse_ty("Nat * Nat").unwrap())
+        );
+        let (v, store) = eval_in(&t, &Store::new(), 500).unwrap();
+        assert_eq!(v, tm("(3, 0)"));
+        assert_eq!(store.domain(), [0, 1]);
+        assert_eq!(store.lookup(0), Some(Tm::Const(3)));
+        assert_eq!(store.lookup(1), Some(Tm::Const(0)));
+    }
+
+    #[test]
+    fn test_lists() {
+        // Elements are evaluated head first.
+        let t = tm("cons (succ 0) (cons (pred 3) (nil Nat))");
+        assert_eq!(step(&t), Some(tm("cons 1 (cons (pred 3) (nil Nat))")));
+        assert_eq!(eval(&t, 10), Some(tm("cons 1 (cons 2 (nil Nat))")));
+        assert!(is_value(&tm("cons 1 (nil Nat)")));
+        assert!(!is_value(&tm("cons 1 (cons (succ 1) (nil Nat))")));
+        // The head, or a default for the empty list.
+        let head_or = "\\d:Nat. \\l:List Nat. case l of | nil => d | x :: xs => x";
+        for (list, head) in [("nil Nat", 7), ("cons 3 (cons 4 (nil Nat))", 3)] {
+            let t = tm(&format!("({}) 7 ({})", head_or, list));
+            assert_eq!(type_of(&empty_context(), &t), Ok(Ty::Nat));
+            assert_eq!(eval(&t, 20), Some(Tm::Const(head)));
+        }
+        // The tail is bound too: the second element, doubled, consed on.
+        let t = tm("case cons 1 (cons 2 (nil Nat)) of | nil => nil Nat \
+             | x :: xs => case xs of | nil => xs | y :: ys => cons (mult y 2) xs");
+        assert_eq!(eval(&t, 20), Some(tm("cons 4 (cons 2 (nil Nat))")));
+        // A case on something that isn't a list is stuck.
+        let t = tm("case (0, 0) of | nil => 0 | x :: xs => x");
+        assert_eq!(step(&t), None);
+        assert!(type_of(&empty_context(), &t).is_err());
+    }
+
+    #[test]
+    fn test_stuck_arithmetic() {
+        // The successor of a boolean, or a number as a guard: stuck and
+        // ill typed.
+        for s in [
+            "succ true",
+            "iszero (\\x:Nat. x)",
+            "if 0 then true else false",
+            "mult 2 false",
+        ] {
+            let t = tm(s);
+            assert_eq!(eval(&t, 10), Some(t.clone()), "{}", s);
+            assert!(!is_value(&t));
+            assert!(type_of(&empty_context(), &t).is_err());
+        }
+    }
+
+    #[test]
+    fn test_stuck_conditionals() {
+        // A function as a guard: stuck, and ill typed.
+        let t = tm("if \\x:Bool. x then true else false");
+        assert!(!is_value(&t));
+        assert_eq!(step(&t), None);
+        assert!(type_of(&empty_context(), &t).is_err());
+        // So is applying a boolean.
+        let t = tm("true false");
+        assert_eq!(eval(&t, 10), Some(t.clone()));
+        assert!(type_of(&empty_context(), &t).is_err());
+        // A conditional whose branches disagree in type is ill typed, but
+        // doesn't get stuck.
+        let t = tm("if true then true else \\x:Bool. x");
+        assert!(type_of(&empty_context(), &t).is_err());
+        assert_eq!(eval(&t, 10), Some(Tm::Tru));
+    }
+
+    #[test]
+    fn test_ill_typed() {
+        // idB idB steps to idB though it has no type: idB wants a `Bool`.
+        let t = Tm::app(tm(ID_B), tm(ID_B));
+        assert!(matches!(
+            type_of(&empty_context(), &t),
+            Err(TypeError::Mismatch { .. })
+        ));
+        assert_eq!(eval(&t, 10), Some(tm(ID_B)));
+        // A free variable gets stuck, and has no type in the empty context.
+        let t = Tm::app(tm("b"), tm(ID_B));
+        assert_eq!(eval(&t, 10), Some(t.clone()));
+        assert_eq!(
+            type_of(&empty_context(), &t),
+            Err(TypeError::Unbound("b".to_string()))
+        );
+    }
+}
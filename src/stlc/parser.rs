@@ -0,0 +1,680 @@
+//! A parser for STLC types and terms, in the syntax of `lambda`'s parser
+//! with a type on each binder:
+//!
+//! ```text
+//! ty   ::= sum ("->" ty)?
+//! sum  ::= prod ("+" prod)*
+//! prod ::= aty ("*" aty)*
+//! aty  ::= "Bool" | "Nat" | "Unit" | "Top" | "Bot" | "List" aty | "Ref" aty | "(" ty ")"
+//!        | "{" (ident ":" ty ("," ident ":" ty)*)? "}"
+//! term ::= asgn (";" term)?
+//! asgn ::= app (":=" asgn)?
+//! app  ::= open | head atom* open?
+//! open ::= ("\" | "λ") ident ":" ty "." term
+//!        | "if" term "then" term "else" term
+//!        | "let" ident "=" term "in" term | "try" term "with" term
+//!        | "case" term "of" "|" "inl" ident "=>" term "|" "inr" ident "=>" term
+//!        | "case" term "of" "|" "nil" "=>" term "|" ident "::" ident "=>" term
+//! head ::= atom | ("succ" | "pred" | "iszero" | "fix") atom | "mult" atom atom
+//!        | ("inl" | "inr") aty atom | "nil" aty | "cons" atom atom
+//!        | "ref" atom | "!" atom | "loc" num
+//! atom ::= base ("." ("fst" | "snd" | ident))*
+//! base ::= ident | "true" | "false" | "unit" | "error" | num | "(" term ")"
+//!        | "(" term "," term ")" | "{" (ident "=" term ("," ident "=" term)*)? "}"
+//! ```
+//!
+//! `->` associates to the right, `+` and `*` (binding tighter still) and
+//! application to the left, and an abstraction, `if`, `let`, `try` or
+//! `case` extends as far to the right as it can, past a `;` too, as
+//! `t1; t2` binds loosest of all and is read as `Tm::seq` makes it.
+//! `t1 := t2` binds looser than application, and to the right. Errors are
+//! reported as for `lambda::parse_term`.
+
+use std::str::FromStr;
+
+use super::{Tm, Ty};
+use crate::syntax::{self, Cursor, Span};
+pub use crate::syntax::{ParseError, Token};
+
+const KEYWORDS: [&str; 33] = [
+    "Bool", "Nat", "Unit", "unit", "Top", "Bot", "Ref", "ref", "loc", "List", "true", "false",
+    "if", "then", "else", "succ", "pred", "mult", "iszero", "let", "in", "fst", "snd", "inl",
+    "inr", "case", "of", "nil", "cons", "fix", "error", "try", "with",
+];
+
+// Longest first, so `->` isn't read as something shorter.
+const SYMBOLS: [&str; 19] = [
+    "->", "=>", "::", ":=", "!", "\\", "λ", ":", ".", "=", ",", "*", "+", "|", ";", "(", ")", "{",
+    "}",
+];
+
+/// Split `input` into tokens with their spans, as `lambda`'s lexer does.
+fn lex(input: &str) -> Result<Vec<(Token, Span)>, ParseError> {
+    syntax::lex(input, &KEYWORDS, &SYMBOLS)
+}
+
+struct Parser {
+    tokens: Vec<(Token, Span)>,
+    next: usize,
+}
+
+impl Cursor for Parser {
+    fn tokens(&self) -> &[(Token, Span)] {
+        &self.tokens
+    }
+
+    fn position(&self) -> usize {
+        self.next
+    }
+
+    fn advance(&mut self) {
+        self.next += 1;
+    }
+}
+
+impl Parser {
+    fn new(input: &str) -> Result<Self, ParseError> {
+        Ok(Parser {
+            tokens: lex(input)?,
+            next: 0,
+        })
+    }
+
+    fn label(&mut self) -> Result<String, ParseError> {
+        match self.peek() {
+            Some(Token::Ident(_)) => self.ident(),
+            _ => Err(self.error("a label")),
+        }
+    }
+
+    fn ty(&mut self) -> Result<Ty, ParseError> {
+        let mut ty = self.prod_ty()?;
+        while self.eat("+") {
+            ty = Ty::sum(ty, self.prod_ty()?);
+        }
+        if self.eat("->") {
+            Ok(Ty::arrow(ty, self.ty()?))
+        } else {
+            Ok(ty)
+        }
+    }
+
+    fn prod_ty(&mut self) -> Result<Ty, ParseError> {
+        let mut ty = self.atomic_ty()?;
+        while self.eat("*") {
+            ty = Ty::prod(ty, self.atomic_ty()?);
+        }
+        Ok(ty)
+    }
+
+    fn atomic_ty(&mut self) -> Result<Ty, ParseError> {
+        if self.eat("Bool") {
+            Ok(Ty::Bool)
+        } else if self.eat("Nat") {
+            Ok(Ty::Nat)
+        } else if self.eat("Unit") {
+            Ok(Ty::Unit)
+        } else if self.eat("Top") {
+            Ok(Ty::Top)
+        } else if self.eat("Bot") {
+            Ok(Ty::Bot)
+        } else if self.eat("List") {
+            Ok(Ty::list(self.atomic_ty()?))
+        } else if self.eat("Ref") {
+            Ok(Ty::ref_(self.atomic_ty()?))
+        } else if self.eat("(") {
+            let ty = self.ty()?;
+            self.expect(")")?;
+            Ok(ty)
+        } else if self.eat("{") {
+            Ok(Ty::Record(self.fields(":", "`:`", Parser::ty)?))
+        } else {
+            Err(self.error("a type"))
+        }
+    }
+
+    fn term(&mut self) -> Result<Tm, ParseError> {
+        let t1 = self.assign()?;
+        if self.eat(";") {
+            Ok(Tm::seq(t1, self.term()?))
+        } else {
+            Ok(t1)
+        }
+    }
+
+    fn assign(&mut self) -> Result<Tm, ParseError> {
+        let t1 = self.app()?;
+        if self.eat(":=") {
+            Ok(Tm::assign(t1, self.assign()?))
+        } else {
+            Ok(t1)
+        }
+    }
+
+    fn app(&mut self) -> Result<Tm, ParseError> {
+        if let Some(t) = self.open()? {
+            return Ok(t);
+        }
+        let mut t = self.head()?;
+        loop {
+            if let Some(arg) = self.open()? {
+                return Ok(Tm::app(t, arg));
+            }
+            if !self.at_atom() {
+                return Ok(t);
+            }
+            t = Tm::app(t, self.atom()?);
+        }
+    }
+
+    /// A term that extends as far right as it can, if one starts here.
+    fn open(&mut self) -> Result<Option<Tm>, ParseError> {
+        if self.eat("\\") || self.eat("λ") {
+            let x = self.ident()?;
+            self.expect(":")?;
+            let ty = self.ty()?;
+            self.expect_or(".", "`.` or `->`")?;
+            let body = self.term()?;
+            Ok(Some(Tm::Abs(x, ty, Box::new(body))))
+        } else if self.eat("if") {
+            let t1 = self.term()?;
+            self.expect("then")?;
+            let t2 = self.term()?;
+            self.expect("else")?;
+            let t3 = self.term()?;
+            Ok(Some(Tm::test(t1, t2, t3)))
+        } else if self.eat("let") {
+            let x = self.ident()?;
+            self.expect("=")?;
+            let t1 = self.term()?;
+            self.expect("in")?;
+            let t2 = self.term()?;
+            Ok(Some(Tm::Let(x, Box::new(t1), Box::new(t2))))
+        } else if self.eat("try") {
+            let t1 = self.term()?;
+            self.expect("with")?;
+            let t2 = self.term()?;
+            Ok(Some(Tm::try_(t1, t2)))
+        } else if self.eat("case") {
+            let t0 = self.term()?;
+            self.expect("of")?;
+            self.expect("|")?;
+            if self.eat("nil") {
+                self.expect("=>")?;
+                let t2 = self.term()?;
+                self.expect("|")?;
+                let x1 = self.ident()?;
+                self.expect("::")?;
+                let x2 = self.ident()?;
+                self.expect("=>")?;
+                let t3 = self.term()?;
+                return Ok(Some(Tm::Lcase(
+                    Box::new(t0),
+                    Box::new(t2),
+                    x1,
+                    x2,
+                    Box::new(t3),
+                )));
+            }
+            self.expect_or("inl", "`inl` or `nil`")?;
+            let x1 = self.ident()?;
+            self.expect("=>")?;
+            let t1 = self.term()?;
+            self.expect("|")?;
+            self.expect("inr")?;
+            let x2 = self.ident()?;
+            self.expect("=>")?;
+            let t2 = self.term()?;
+            Ok(Some(Tm::Case(
+                Box::new(t0),
+                x1,
+                Box::new(t1),
+                x2,
+                Box::new(t2),
+            )))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// The first term of an application: an atom, or an arithmetic
+    /// operator with its arguments.
+    fn head(&mut self) -> Result<Tm, ParseError> {
+        if self.eat("succ") {
+            Ok(Tm::scc(self.atom()?))
+        } else if self.eat("pred") {
+            Ok(Tm::prd(self.atom()?))
+        } else if self.eat("iszero") {
+            Ok(Tm::is_zero(self.atom()?))
+        } else if self.eat("fix") {
+            Ok(Tm::fix(self.atom()?))
+        } else if self.eat("mult") {
+            let t1 = self.atom()?;
+            Ok(Tm::mlt(t1, self.atom()?))
+        } else if self.eat("ref") {
+            Ok(Tm::ref_(self.atom()?))
+        } else if self.eat("!") {
+            Ok(Tm::deref(self.atom()?))
+        } else if self.eat("loc") {
+            match self.peek() {
+                Some(&Token::Num(l)) => {
+                    self.next += 1;
+                    Ok(Tm::Loc(l as usize))
+                }
+                _ => Err(self.error("a location")),
+            }
+        } else if self.eat("nil") {
+            Ok(Tm::Nil(self.atomic_ty()?))
+        } else if self.eat("cons") {
+            let t1 = self.atom()?;
+            Ok(Tm::cons(t1, self.atom()?))
+        } else if self.eat("inl") {
+            let ty = self.atomic_ty()?;
+            Ok(Tm::inl(ty, self.atom()?))
+        } else if self.eat("inr") {
+            let ty = self.atomic_ty()?;
+            Ok(Tm::inr(ty, self.atom()?))
+        } else {
+            self.atom()
+        }
+    }
+
+    /// Whether the next token starts an atom.
+    fn at_atom(&self) -> bool {
+        matches!(
+            self.peek(),
+            Some(
+                Token::Ident(_)
+                    | Token::Num(_)
+                    | Token::Keyword("true" | "false" | "unit" | "error")
+                    | Token::Symbol("(" | "{")
+            )
+        )
+    }
+
+    /// The fields of a record or record type after its `{`, up to and
+    /// including the `}`: labels, each followed by `sep` and what `item`
+    /// parses.
+    fn fields<T>(
+        &mut self,
+        sep: &'static str,
+        expected: &'static str,
+        item: fn(&mut Self) -> Result<T, ParseError>,
+    ) -> Result<Vec<(String, T)>, ParseError> {
+        let mut fields = Vec::new();
+        if self.eat("}") {
+            return Ok(fields);
+        }
+        loop {
+            let x = self.label()?;
+            self.expect_or(sep, expected)?;
+            fields.push((x, item(self)?));
+            if self.eat("}") {
+                return Ok(fields);
+            }
+            self.expect_or(",", "`,` or `}`")?;
+        }
+    }
+
+    fn atom(&mut self) -> Result<Tm, ParseError> {
+        let mut t = self.base()?;
+        while self.eat(".") {
+            if self.eat("fst") {
+                t = Tm::fst(t);
+            } else if self.eat("snd") {
+                t = Tm::snd(t);
+            } else if let Some(Token::Ident(_)) = self.peek() {
+                t = Tm::Proj(Box::new(t), self.label()?);
+            } else {
+                return Err(self.error("`fst`, `snd` or a label"));
+            }
+        }
+        Ok(t)
+    }
+
+    fn base(&mut self) -> Result<Tm, ParseError> {
+        if self.eat("(") {
+            let t = self.term()?;
+            if self.eat(",") {
+                let t2 = self.term()?;
+                self.expect(")")?;
+                return Ok(Tm::pair(t, t2));
+            }
+            self.expect_or(")", "`,` or `)`")?;
+            Ok(t)
+        } else if self.eat("{") {
+            Ok(Tm::Record(self.fields("=", "`=`", Parser::term)?))
+        } else if self.eat("true") {
+            Ok(Tm::Tru)
+        } else if self.eat("unit") {
+            Ok(Tm::Unit)
+        } else if self.eat("error") {
+            Ok(Tm::Error)
+        } else if self.eat("false") {
+            Ok(Tm::Fls)
+        } else {
+            match self.peek() {
+                Some(Token::Ident(_)) => Ok(Tm::Var(self.ident()?)),
+                Some(&Token::Num(n)) => {
+                    self.next += 1;
+                    Ok(Tm::Const(n))
+                }
+                _ => Err(self.error("a term")),
+            }
+        }
+    }
+}
+
+pub fn parse_ty(input: &str) -> Result<Ty, ParseError> {
+    let mut p = Parser::new(input)?;
+    let ty = p.ty()?;
+    p.finish(ty)
+}
+
+pub fn parse_tm(input: &str) -> Result<Tm, ParseError> {
+    let mut p = Parser::new(input)?;
+    let t = p.term()?;
+    p.finish(t)
+}
+
+impl FromStr for Ty {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse_ty(s)
+    }
+}
+
+impl FromStr for Tm {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse_tm(s)
+    }
+}
+
+#[cfg(test)]
+mod test_stlc_parser {
+    use super::*;
+
+    fn v(x: &str) -> Tm {
+        Tm::var(x)
+    }
+
+    #[test]
+    fn test_parse_types() {
+        let b = || Ty::Bool;
+        assert_eq!(parse_ty("Bool"), Ok(b()));
+        assert_eq!(
+            parse_ty("Bool -> Bool -> Bool"),
+            Ok(Ty::arrow(b(), Ty::arrow(b(), b())))
+        );
+        assert_eq!(
+            parse_ty("(Bool->Bool) -> Bool"),
+            Ok(Ty::arrow(Ty::arrow(b(), b()), b()))
+        );
+        assert_eq!("((Bool))".parse::<Ty>(), Ok(b()));
+    }
+
+    #[test]
+    fn test_parse_terms() {
+        let bb = Ty::arrow(Ty::Bool, Ty::Bool);
+        assert_eq!(
+            parse_tm("\\f:Bool -> Bool. λx:Bool. f (f x)"),
+            Ok(Tm::abs(
+                "f",
+                bb,
+                Tm::abs("x", Ty::Bool, Tm::app(v("f"), Tm::app(v("f"), v("x"))))
+            ))
+        );
+        assert_eq!(
+            parse_tm("f x \\y:Bool. y"),
+            Ok(Tm::app(
+                Tm::app(v("f"), v("x")),
+                Tm::abs("y", Ty::Bool, v("y"))
+            ))
+        );
+        assert_eq!(
+            "(\\x:Bool. x) y".parse::<Tm>(),
+            Ok(Tm::app(Tm::abs("x", Ty::Bool, v("x")), v("y")))
+        );
+        // The `else` branch extends to the right; the guard and the `then`
+        // branch end at their keywords.
+        assert_eq!(
+            parse_tm("if f true then \\x:Bool. x else f false"),
+            Ok(Tm::test(
+                Tm::app(v("f"), Tm::Tru),
+                Tm::abs("x", Ty::Bool, v("x")),
+                Tm::app(v("f"), Tm::Fls)
+            ))
+        );
+        assert_eq!(
+            parse_tm("f if b then true else false"),
+            Ok(Tm::app(v("f"), Tm::test(v("b"), Tm::Tru, Tm::Fls)))
+        );
+        assert_eq!(
+            parse_tm("let x = 1 in f let y = x in y"),
+            Ok(Tm::let_(
+                "x",
+                Tm::Const(1),
+                Tm::app(v("f"), Tm::let_("y", v("x"), v("y")))
+            ))
+        );
+        // An operator takes atoms as its arguments.
+        assert_eq!(
+            parse_tm("mult (succ n) 2 x"),
+            Ok(Tm::app(Tm::mlt(Tm::scc(v("n")), Tm::Const(2)), v("x")))
+        );
+        assert_eq!(
+            parse_tm("iszero (pred 0)"),
+            Ok(Tm::is_zero(Tm::prd(Tm::Const(0))))
+        );
+        // References: `:=` takes in applications, but not a `;`.
+        assert_eq!(
+            parse_tm("r := succ (!r); !r"),
+            Ok(Tm::seq(
+                Tm::assign(v("r"), Tm::scc(Tm::deref(v("r")))),
+                Tm::deref(v("r"))
+            ))
+        );
+        assert_eq!(
+            parse_tm("\\r:Ref Nat. f r := ref 0 := !(loc 1)"),
+            Ok(Tm::abs(
+                "r",
+                Ty::ref_(Ty::Nat),
+                Tm::assign(
+                    Tm::app(v("f"), v("r")),
+                    Tm::assign(Tm::ref_(Tm::Const(0)), Tm::deref(Tm::Loc(1)))
+                )
+            ))
+        );
+        // Records, and projections, which are atoms as pairs' are.
+        assert_eq!(
+            parse_tm("\\r:{x:Nat, f:Nat -> {}}. r.f r.x.y {}"),
+            Ok(Tm::abs(
+                "r",
+                Ty::record(vec![
+                    ("x", Ty::Nat),
+                    ("f", Ty::arrow(Ty::Nat, Ty::record(vec![])))
+                ]),
+                Tm::app(
+                    Tm::app(Tm::proj(v("r"), "f"), Tm::proj(Tm::proj(v("r"), "x"), "y")),
+                    Tm::record(vec![])
+                )
+            ))
+        );
+        assert_eq!(
+            parse_tm("{a=f x, b={c=(1, 2)}}.b.c.fst"),
+            Ok(Tm::fst(Tm::proj(
+                Tm::proj(
+                    Tm::record(vec![
+                        ("a", Tm::app(v("f"), v("x"))),
+                        (
+                            "b",
+                            Tm::record(vec![("c", Tm::pair(Tm::Const(1), Tm::Const(2)))])
+                        )
+                    ]),
+                    "b"
+                ),
+                "c"
+            )))
+        );
+        assert_eq!(
+            parse_tm("fix f 3"),
+            Ok(Tm::app(Tm::fix(v("f")), Tm::Const(3)))
+        );
+        // `;` binds loosest and associates to the right, and the body of
+        // an abstraction takes it in.
+        assert_eq!(
+            parse_tm("f unit; g; \\x:Unit. x; h"),
+            Ok(Tm::seq(
+                Tm::app(v("f"), Tm::Unit),
+                Tm::seq(v("g"), Tm::abs("x", Ty::Unit, Tm::seq(v("x"), v("h"))))
+            ))
+        );
+        assert_eq!(parse_ty("Nat -> Bool"), Ok(Ty::arrow(Ty::Nat, Ty::Bool)));
+        // Pairs and projections, which bind tighter than application.
+        assert_eq!(
+            parse_ty("Nat * Bool * Nat -> Nat"),
+            Ok(Ty::arrow(
+                Ty::prod(Ty::prod(Ty::Nat, Ty::Bool), Ty::Nat),
+                Ty::Nat
+            ))
+        );
+        assert_eq!(
+            parse_tm("f p.fst (1, (x, y).snd).snd"),
+            Ok(Tm::app(
+                Tm::app(v("f"), Tm::fst(v("p"))),
+                Tm::snd(Tm::pair(Tm::Const(1), Tm::snd(Tm::pair(v("x"), v("y")))))
+            ))
+        );
+        assert_eq!(
+            parse_ty("Nat + Bool * Nat + Nat -> Nat"),
+            Ok(Ty::arrow(
+                Ty::sum(Ty::sum(Ty::Nat, Ty::prod(Ty::Bool, Ty::Nat)), Ty::Nat),
+                Ty::Nat
+            ))
+        );
+        assert_eq!(
+            parse_tm("case inl (Nat -> Nat) 0 of | inl x => f x | inr y => y 1"),
+            Ok(Tm::case(
+                Tm::inl(Ty::arrow(Ty::Nat, Ty::Nat), Tm::Const(0)),
+                "x",
+                Tm::app(v("f"), v("x")),
+                "y",
+                Tm::app(v("y"), Tm::Const(1))
+            ))
+        );
+        assert_eq!(
+            parse_tm("case cons x (nil List Nat) of | nil => 0 | y :: ys => f y"),
+            Ok(Tm::lcase(
+                Tm::cons(v("x"), Tm::Nil(Ty::list(Ty::Nat))),
+                Tm::Const(0),
+                "y",
+                "ys",
+                Tm::app(v("f"), v("y"))
+            ))
+        );
+        assert_eq!(
+            parse_tm("\\p:Nat * Nat. p.fst"),
+            Ok(Tm::abs("p", Ty::prod(Ty::Nat, Ty::Nat), Tm::fst(v("p"))))
+        );
+        // `try` extends to the right, and `error` is an atom.
+        assert_eq!(
+            parse_tm("try f error with try error with 0; x"),
+            Ok(Tm::try_(
+                Tm::app(v("f"), Tm::Error),
+                Tm::try_(Tm::Error, Tm::seq(Tm::Const(0), v("x")))
+            ))
+        );
+        assert_eq!(
+            parse_ty("Bot -> List Bot"),
+            Ok(Ty::arrow(Ty::Bot, Ty::list(Ty::Bot)))
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        // A binder needs a type.
+        assert_eq!(
+            parse_tm("\\x. x"),
+            Err(ParseError::UnexpectedToken {
+                found: Token::Symbol("."),
+                pos: 2,
+                expected: ":"
+            })
+        );
+        // `Bool` is a type, not a variable.
+        assert_eq!(
+            parse_tm("\\Bool:Bool. x"),
+            Err(ParseError::UnexpectedToken {
+                found: Token::Keyword("Bool"),
+                pos: 1,
+                expected: "a variable"
+            })
+        );
+        assert_eq!(
+            parse_tm("p.3"),
+            Err(ParseError::UnexpectedToken {
+                found: Token::Num(3),
+                pos: 2,
+                expected: "`fst`, `snd` or a label"
+            })
+        );
+        assert_eq!(
+            parse_tm("{x=1, 2}"),
+            Err(ParseError::UnexpectedToken {
+                found: Token::Num(2),
+                pos: 6,
+                expected: "a label"
+            })
+        );
+        assert_eq!(
+            parse_ty("{x:Nat y:Bool}"),
+            Err(ParseError::UnexpectedToken {
+                found: Token::Ident("y".to_string()),
+                pos: 7,
+                expected: "`,` or `}`"
+            })
+        );
+        assert_eq!(
+            parse_tm("case x of | inr y => y | inl z => z"),
+            Err(ParseError::UnexpectedToken {
+                found: Token::Keyword("inr"),
+                pos: 12,
+                expected: "`inl` or `nil`"
+            })
+        );
+        assert_eq!(
+            parse_tm("if b then true"),
+            Err(ParseError::UnexpectedEof { expected: "else" })
+        );
+        assert_eq!(
+            parse_ty("Bool ->"),
+            Err(ParseError::UnexpectedEof { expected: "a type" })
+        );
+        let input = "\\x:Bool Bool. x";
+        assert_eq!(
+            parse_tm(input).unwrap_err().report(input),
+            "expected `.` or `->` at line 1, col 9, found `Bool`\n1 | \\x:Bool Bool. x\n  |         ^^^^"
+        );
+        assert_eq!(
+            parse_tm("x - y"),
+            Err(ParseError::UnexpectedChar { ch: '-', pos: 2 })
+        );
+        // `succ` isn't a function, so it has to have an argument.
+        assert_eq!(
+            parse_tm("f succ"),
+            Err(ParseError::UnexpectedToken {
+                found: Token::Keyword("succ"),
+                pos: 2,
+                expected: "end of input"
+            })
+        );
+        let input = "succ 18446744073709551616";
+        assert_eq!(
+            parse_tm(input).unwrap_err().report(input),
+            "number literal too large at line 1, col 6\n1 | succ 18446744073709551616\n  |      ^^^^^^^^^^^^^^^^^^^^"
+        );
+    }
+}
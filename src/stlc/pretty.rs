@@ -0,0 +1,577 @@
+//! Printing types and terms in the syntax `parse_ty` and `parse_tm` read,
+//! with the fewest parentheses. `*` binds tighter than `+`, and both
+//! tighter than `->`; `->` is right-associative and the others
+//! left-associative.
+//!
+//! Terms follow the rules of `lambda::Term`'s printer, with the binder's
+//! type written after it. An `if`, a `let`, a `try` or a `case` extends
+//! to the right as far as an abstraction does, `succ t`, `pred t`,
+//! `iszero t`, `fix t`, `mult t1 t2`, `inl T t` and `cons t1 t2` are
+//! printed as applications are, and a projection `t.fst` needs `t` to be
+//! an atom, as `error` is.
+//! `List T` and `Ref T` apply to an atomic type, and `ref t`, `!t` and
+//! `loc l` are printed as applications are. `t1 := t2` binds more loosely
+//! than application, and to the right, so it is parenthesized inside one
+//! and on the left of another. Records and their types are atoms,
+//! and so is a projection `t.x`, as `t.fst` is.
+
+use std::fmt;
+
+use super::{Tm, Ty};
+
+// Binding strength of the type operators; atoms bind tightest.
+const PREC_ARROW: u8 = 0;
+const PREC_SUM: u8 = 1;
+const PREC_PROD: u8 = 2;
+const PREC_ATOM: u8 = 3;
+
+fn ty_prec(ty: &Ty) -> u8 {
+    match ty {
+        Ty::Arrow(..) => PREC_ARROW,
+        Ty::Sum(..) => PREC_SUM,
+        Ty::Prod(..) => PREC_PROD,
+        Ty::Bool
+        | Ty::Nat
+        | Ty::Unit
+        | Ty::Top
+        | Ty::Bot
+        | Ty::List(_)
+        | Ty::Ref(_)
+        | Ty::Record(_) => PREC_ATOM,
+    }
+}
+
+/// Print `ty`, parenthesized if it binds more loosely than `min_prec`.
+fn write_ty(f: &mut fmt::Formatter<'_>, ty: &Ty, min_prec: u8) -> fmt::Result {
+    let prec = ty_prec(ty);
+    if prec < min_prec {
+        write!(f, "(")?;
+    }
+    match ty {
+        Ty::Bool => write!(f, "Bool")?,
+        Ty::Nat => write!(f, "Nat")?,
+        Ty::Unit => write!(f, "Unit")?,
+        Ty::Top => write!(f, "Top")?,
+        Ty::Bot => write!(f, "Bot")?,
+        Ty::List(ty1) | Ty::Ref(ty1) => {
+            write!(
+                f,
+                "{} ",
+                if matches!(ty, Ty::List(_)) {
+                    "List"
+                } else {
+                    "Ref"
+                }
+            )?;
+            write_ty(f, ty1, PREC_ATOM)?;
+        }
+        Ty::Record(fields) => {
+            write!(f, "{{")?;
+            for (i, (x, ty)) in fields.iter().enumerate() {
+                if i > 0 {
+                    write!(f, ", ")?;
+                }
+                write!(f, "{}:{}", x, ty)?;
+            }
+            write!(f, "}}")?;
+        }
+        Ty::Arrow(t1, t2) => {
+            write_ty(f, t1, prec + 1)?;
+            write!(f, " -> ")?;
+            write_ty(f, t2, prec)?;
+        }
+        Ty::Prod(t1, t2) | Ty::Sum(t1, t2) => {
+            write_ty(f, t1, prec)?;
+            write!(f, " {} ", if prec == PREC_PROD { "*" } else { "+" })?;
+            write_ty(f, t2, prec + 1)?;
+        }
+    }
+    if prec < min_prec {
+        write!(f, ")")?;
+    }
+    Ok(())
+}
+
+impl fmt::Display for Ty {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write_ty(f, self, PREC_ARROW)
+    }
+}
+
+/// Print `t`. `followed` is whether more of an application comes after it,
+/// and `arg` whether it is an argument, where only an atom or a term that
+/// extends to the right, like an abstraction, needs no parentheses.
+fn write_tm(f: &mut fmt::Formatter<'_>, t: &Tm, followed: bool, arg: bool) -> fmt::Result {
+    match t {
+        Tm::Var(x) => write!(f, "{}", x),
+        Tm::Tru => write!(f, "true"),
+        Tm::Unit => write!(f, "unit"),
+        Tm::Error => write!(f, "error"),
+        Tm::Fls => write!(f, "false"),
+        Tm::Const(n) => write!(f, "{}", n),
+        Tm::Pair(t1, t2) => write!(f, "({}, {})", t1, t2),
+        Tm::Record(fields) => {
+            write!(f, "{{")?;
+            for (i, (x, t)) in fields.iter().enumerate() {
+                if i > 0 {
+                    write!(f, ", ")?;
+                }
+                write!(f, "{}={}", x, t)?;
+            }
+            write!(f, "}}")
+        }
+        Tm::Proj(t1, x) => {
+            write_tm(f, t1, true, true)?;
+            write!(f, ".{}", x)
+        }
+        Tm::Fst(t1) | Tm::Snd(t1) => {
+            write_tm(f, t1, true, true)?;
+            write!(
+                f,
+                ".{}",
+                if matches!(t, Tm::Fst(_)) {
+                    "fst"
+                } else {
+                    "snd"
+                }
+            )
+        }
+        Tm::Abs(x, ty, body) if followed => write!(f, "(\\{}:{}. {})", x, ty, body),
+        Tm::Abs(x, ty, body) => write!(f, "\\{}:{}. {}", x, ty, body),
+        Tm::Test(t1, t2, t3) if followed => write!(f, "(if {} then {} else {})", t1, t2, t3),
+        Tm::Test(t1, t2, t3) => write!(f, "if {} then {} else {}", t1, t2, t3),
+        Tm::Let(x, t1, t2) if followed => write!(f, "(let {} = {} in {})", x, t1, t2),
+        Tm::Let(x, t1, t2) => write!(f, "let {} = {} in {}", x, t1, t2),
+        Tm::Try(t1, t2) if followed => write!(f, "(try {} with {})", t1, t2),
+        Tm::Try(t1, t2) => write!(f, "try {} with {}", t1, t2),
+        Tm::Case(t0, x1, t1, x2, t2) => {
+            if followed {
+                write!(f, "(")?;
+            }
+            write!(
+                f,
+                "case {} of | inl {} => {} | inr {} => {}",
+                t0, x1, t1, x2, t2
+            )?;
+            if followed {
+                write!(f, ")")?;
+            }
+            Ok(())
+        }
+        Tm::App(..)
+        | Tm::Scc(_)
+        | Tm::Prd(_)
+        | Tm::Mlt(..)
+        | Tm::IsZero(_)
+        | Tm::Fix(_)
+        | Tm::Inl(..)
+        | Tm::Inr(..)
+        | Tm::Nil(_)
+        | Tm::Cons(..)
+        | Tm::Ref(_)
+        | Tm::Deref(_)
+        | Tm::Loc(_)
+            if arg =>
+        {
+            write!(f, "({})", t)
+        }
+        Tm::Assign(..) if followed || arg => write!(f, "({})", t),
+        Tm::Assign(t1, t2) => {
+            write_tm(f, t1, true, false)?;
+            write!(f, " := ")?;
+            write_tm(f, t2, false, false)
+        }
+        Tm::Ref(t1) => {
+            write!(f, "ref ")?;
+            write_tm(f, t1, true, true)
+        }
+        Tm::Deref(t1) => {
+            write!(f, "!")?;
+            write_tm(f, t1, true, true)
+        }
+        Tm::Loc(l) => write!(f, "loc {}", l),
+        Tm::Nil(ty) => {
+            write!(f, "nil ")?;
+            write_ty(f, ty, PREC_ATOM)
+        }
+        Tm::Cons(t1, t2) => {
+            write!(f, "cons ")?;
+            write_tm(f, t1, true, true)?;
+            write!(f, " ")?;
+            write_tm(f, t2, true, true)
+        }
+        Tm::Lcase(t1, t2, x1, x2, t3) => {
+            if followed {
+                write!(f, "(")?;
+            }
+            write!(
+                f,
+                "case {} of | nil => {} | {} :: {} => {}",
+                t1, t2, x1, x2, t3
+            )?;
+            if followed {
+                write!(f, ")")?;
+            }
+            Ok(())
+        }
+        Tm::Inl(ty, t1) | Tm::Inr(ty, t1) => {
+            write!(
+                f,
+                "{} ",
+                if matches!(t, Tm::Inl(..)) {
+                    "inl"
+                } else {
+                    "inr"
+                }
+            )?;
+            write_ty(f, ty, PREC_ATOM)?;
+            write!(f, " ")?;
+            write_tm(f, t1, true, true)
+        }
+        Tm::Scc(t1) | Tm::Prd(t1) | Tm::IsZero(t1) | Tm::Fix(t1) => {
+            let op = match t {
+                Tm::Scc(_) => "succ",
+                Tm::Prd(_) => "pred",
+                Tm::IsZero(_) => "iszero",
+                _ => "fix",
+            };
+            write!(f, "{} ", op)?;
+            write_tm(f, t1, true, true)
+        }
+        Tm::Mlt(t1, t2) => {
+            write!(f, "mult ")?;
+            write_tm(f, t1, true, true)?;
+            write!(f, " ")?;
+            write_tm(f, t2, true, true)
+        }
+        Tm::App(t1, t2) => {
+            write_tm(f, t1, true, false)?;
+            write!(f, " ")?;
+            write_tm(f, t2, followed, true)
+        }
+    }
+}
+
+impl fmt::Display for Tm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write_tm(f, self, false, false)
+    }
+}
+
+#[cfg(test)]
+mod test_stlc_pretty {
+    use super::*;
+    use crate::rng::Rng;
+    use crate::stlc::{parse_tm, parse_ty};
+
+    #[test]
+    fn test_print_types() {
+        let b = || Ty::Bool;
+        assert_eq!(
+            Ty::arrow(b(), Ty::arrow(b(), b())).to_string(),
+            "Bool -> Bool -> Bool"
+        );
+        assert_eq!(
+            Ty::arrow(Ty::arrow(b(), b()), b()).to_string(),
+            "(Bool -> Bool) -> Bool"
+        );
+        let n = || Ty::Nat;
+        assert_eq!(
+            Ty::arrow(Ty::prod(n(), b()), Ty::prod(b(), n())).to_string(),
+            "Nat * Bool -> Bool * Nat"
+        );
+        assert_eq!(
+            Ty::prod(Ty::prod(n(), n()), n()).to_string(),
+            "Nat * Nat * Nat"
+        );
+        assert_eq!(
+            Ty::sum(Ty::prod(n(), n()), Ty::sum(b(), n())).to_string(),
+            "Nat * Nat + (Bool + Nat)"
+        );
+        assert_eq!(
+            Ty::prod(Ty::sum(n(), b()), n()).to_string(),
+            "(Nat + Bool) * Nat"
+        );
+        assert_eq!(
+            Ty::arrow(Ty::list(Ty::list(n())), Ty::list(Ty::prod(n(), b()))).to_string(),
+            "List List Nat -> List (Nat * Bool)"
+        );
+        assert_eq!(
+            Ty::list(Ty::record(vec![
+                ("f", Ty::arrow(n(), b())),
+                ("r", Ty::record(vec![]))
+            ]))
+            .to_string(),
+            "List {f:Nat -> Bool, r:{}}"
+        );
+        assert_eq!(
+            Ty::prod(n(), Ty::prod(n(), Ty::arrow(n(), n()))).to_string(),
+            "Nat * (Nat * (Nat -> Nat))"
+        );
+    }
+
+    #[test]
+    fn test_print_terms() {
+        let f = Ty::arrow(Ty::Bool, Ty::Bool);
+        let t = Tm::abs("x", f.clone(), Tm::app(Tm::var("x"), Tm::var("y")));
+        assert_eq!(t.to_string(), "\\x:Bool -> Bool. x y");
+        assert_eq!(
+            Tm::app(t.clone(), Tm::var("z")).to_string(),
+            "(\\x:Bool -> Bool. x y) z"
+        );
+        assert_eq!(
+            Tm::app(Tm::var("g"), Tm::app(Tm::var("f"), t)).to_string(),
+            "g (f \\x:Bool -> Bool. x y)"
+        );
+        let t = Tm::mlt(Tm::scc(Tm::Const(2)), Tm::Const(3));
+        assert_eq!(t.to_string(), "mult (succ 2) 3");
+        assert_eq!(
+            Tm::is_zero(Tm::app(Tm::var("f"), t)).to_string(),
+            "iszero (f (mult (succ 2) 3))"
+        );
+        assert_eq!(
+            Tm::app(Tm::prd(Tm::var("f")), Tm::Const(1)).to_string(),
+            "pred f 1"
+        );
+        assert_eq!(
+            Tm::scc(Tm::abs("x", Ty::Nat, Tm::var("x"))).to_string(),
+            "succ (\\x:Nat. x)"
+        );
+        assert_eq!(
+            Tm::app(Tm::fix(Tm::var("f")), Tm::fix(Tm::var("g"))).to_string(),
+            "fix f (fix g)"
+        );
+        let p = Tm::pair(Tm::app(Tm::var("f"), Tm::Tru), Tm::Const(0));
+        assert_eq!(Tm::fst(p.clone()).to_string(), "(f true, 0).fst");
+        assert_eq!(
+            Tm::snd(Tm::app(Tm::var("f"), Tm::snd(Tm::var("p")))).to_string(),
+            "(f p.snd).snd"
+        );
+        let r = Tm::record(vec![
+            ("x", Tm::Const(1)),
+            ("f", Tm::abs("y", Ty::Nat, Tm::var("y"))),
+        ]);
+        assert_eq!(r.to_string(), "{x=1, f=\\y:Nat. y}");
+        assert_eq!(
+            Tm::app(Tm::proj(r, "f"), Tm::proj(Tm::var("r"), "x")).to_string(),
+            "{x=1, f=\\y:Nat. y}.f r.x"
+        );
+        assert_eq!(Tm::record(vec![]).to_string(), "{}");
+        let r = Tm::var("r");
+        let t = Tm::assign(r.clone(), Tm::scc(Tm::deref(r.clone())));
+        assert_eq!(t.to_string(), "r := succ (!r)");
+        assert_eq!(
+            Tm::app(Tm::abs("u", Ty::Unit, Tm::Unit), t.clone()).to_string(),
+            "(\\u:Unit. unit) (r := succ (!r))"
+        );
+        assert_eq!(
+            Tm::assign(t.clone(), Tm::assign(r, Tm::Loc(0))).to_string(),
+            "(r := succ (!r)) := r := loc 0"
+        );
+        assert_eq!(
+            Tm::deref(Tm::ref_(Tm::app(Tm::var("f"), Tm::Const(0)))).to_string(),
+            "!(ref (f 0))"
+        );
+        assert_eq!(
+            Ty::ref_(Ty::arrow(Ty::Nat, Ty::ref_(Ty::Nat))).to_string(),
+            "Ref (Nat -> Ref Nat)"
+        );
+        let t = Tm::case(
+            Tm::inl(Ty::arrow(Ty::Nat, Ty::Nat), Tm::Const(0)),
+            "x",
+            Tm::app(Tm::var("f"), Tm::var("x")),
+            "y",
+            Tm::inr(Ty::Nat, Tm::var("y")),
+        );
+        assert_eq!(
+            t.to_string(),
+            "case inl (Nat -> Nat) 0 of | inl x => f x | inr y => inr Nat y"
+        );
+        assert_eq!(
+            Tm::app(Tm::var("g"), t).to_string(),
+            "g case inl (Nat -> Nat) 0 of | inl x => f x | inr y => inr Nat y"
+        );
+        let l = Tm::cons(Tm::Const(1), Tm::Nil(Ty::list(Ty::Nat)));
+        assert_eq!(l.to_string(), "cons 1 (nil List Nat)");
+        let t = Tm::lcase(l, Tm::Const(0), "x", "xs", Tm::var("x"));
+        assert_eq!(
+            t.to_string(),
+            "case cons 1 (nil List Nat) of | nil => 0 | x :: xs => x"
+        );
+        let t = Tm::let_("x", Tm::app(Tm::var("f"), Tm::Tru), Tm::var("x"));
+        assert_eq!(t.to_string(), "let x = f true in x");
+        assert_eq!(
+            Tm::app(t.clone(), Tm::Tru).to_string(),
+            "(let x = f true in x) true"
+        );
+        let t = Tm::test(Tm::var("b"), Tm::Tru, Tm::app(Tm::var("f"), Tm::Fls));
+        assert_eq!(t.to_string(), "if b then true else f false");
+        assert_eq!(
+            Tm::app(t.clone(), Tm::Tru).to_string(),
+            "(if b then true else f false) true"
+        );
+        let e = Tm::try_(Tm::app(Tm::var("f"), Tm::Error), Tm::Const(0));
+        assert_eq!(e.to_string(), "try f error with 0");
+        assert_eq!(
+            Tm::try_(e.clone(), Tm::app(e, Tm::Error)).to_string(),
+            "try try f error with 0 with (try f error with 0) error"
+        );
+        assert_eq!(
+            Ty::arrow(Ty::Bot, Ty::list(Ty::Bot)).to_string(),
+            "Bot -> List Bot"
+        );
+        assert_eq!(
+            Tm::test(t.clone(), t.clone(), t).to_string(),
+            "if if b then true else f false then if b then true else f false else if b then true else f false"
+        );
+    }
+
+    fn random_ty(rng: &mut Rng, size: usize) -> Ty {
+        if size == 0 {
+            return rng
+                .choose(&[Ty::Bool, Ty::Nat, Ty::Unit, Ty::Top, Ty::Bot])
+                .clone();
+        }
+        let left = rng.below(size as u64) as usize;
+        let (ty1, ty2) = (random_ty(rng, left), random_ty(rng, size - 1 - left));
+        match rng.below(6) {
+            0 => Ty::arrow(ty1, ty2),
+            1 => Ty::prod(ty1, ty2),
+            2 => Ty::sum(ty1, ty2),
+            3 => Ty::record(vec![("a", ty1), ("b", ty2)]),
+            4 => Ty::ref_(ty1),
+            _ => Ty::list(ty1),
+        }
+    }
+
+    /// A random term, typed or not, with `size` abstractions and
+    /// applications.
+    fn random_tm(rng: &mut Rng, size: usize) -> Tm {
+        let x = *rng.choose(&["x", "y", "f"]);
+        if size == 0 {
+            return match rng.below(9) {
+                0 => Tm::Tru,
+                1 => Tm::Fls,
+                2 => Tm::Const(rng.below(20)),
+                3 => Tm::Nil(random_ty(rng, 1)),
+                4 => Tm::Unit,
+                5 => Tm::Loc(rng.below(4) as usize),
+                6 => Tm::Error,
+                _ => Tm::var(x),
+            };
+        }
+        if rng.chance(1, 4) {
+            let t1 = random_tm(rng, size - 1);
+            return match rng.below(6) {
+                0 => Tm::scc(t1),
+                1 => Tm::prd(t1),
+                2 => Tm::is_zero(t1),
+                3 => Tm::ref_(t1),
+                4 => Tm::deref(t1),
+                _ => Tm::fix(t1),
+            };
+        }
+        if rng.chance(1, 6) {
+            let left = rng.below(size as u64) as usize;
+            return Tm::assign(random_tm(rng, left), random_tm(rng, size - 1 - left));
+        }
+        if rng.chance(1, 5) {
+            let left = rng.below(size as u64) as usize;
+            return Tm::mlt(random_tm(rng, left), random_tm(rng, size - 1 - left));
+        }
+        if rng.chance(1, 5) {
+            let left = rng.below(size as u64) as usize;
+            return Tm::let_(x, random_tm(rng, left), random_tm(rng, size - 1 - left));
+        }
+        if rng.chance(1, 5) {
+            let left = rng.below(size as u64) as usize;
+            return Tm::pair(random_tm(rng, left), random_tm(rng, size - 1 - left));
+        }
+        if rng.chance(1, 6) {
+            let left = rng.below(size as u64) as usize;
+            return Tm::record(vec![
+                ("a", random_tm(rng, left)),
+                (x, random_tm(rng, size - 1 - left)),
+            ]);
+        }
+        if rng.chance(1, 6) {
+            return Tm::proj(random_tm(rng, size - 1), x);
+        }
+        if rng.chance(1, 6) {
+            let left = rng.below(size as u64) as usize;
+            return Tm::try_(random_tm(rng, left), random_tm(rng, size - 1 - left));
+        }
+        if rng.chance(1, 5) {
+            let t1 = random_tm(rng, size - 1);
+            return if rng.chance(1, 2) {
+                Tm::fst(t1)
+            } else {
+                Tm::snd(t1)
+            };
+        }
+        if rng.chance(1, 5) {
+            let left = rng.below(size as u64) as usize;
+            return Tm::cons(random_tm(rng, left), random_tm(rng, size - 1 - left));
+        }
+        if rng.chance(1, 5) {
+            let first = rng.below(size as u64) as usize;
+            let second = rng.below((size - first) as u64) as usize;
+            return Tm::lcase(
+                random_tm(rng, first),
+                random_tm(rng, second),
+                x,
+                "xs",
+                random_tm(rng, size - 1 - first - second),
+            );
+        }
+        if rng.chance(1, 5) {
+            let ty = random_ty(rng, 1);
+            let t1 = random_tm(rng, size - 1);
+            return if rng.chance(1, 2) {
+                Tm::inl(ty, t1)
+            } else {
+                Tm::inr(ty, t1)
+            };
+        }
+        if rng.chance(1, 5) {
+            let first = rng.below(size as u64) as usize;
+            let second = rng.below((size - first) as u64) as usize;
+            return Tm::case(
+                random_tm(rng, first),
+                x,
+                random_tm(rng, second),
+                "y",
+                random_tm(rng, size - 1 - first - second),
+            );
+        }
+        if rng.chance(1, 4) {
+            let first = rng.below(size as u64) as usize;
+            let second = rng.below((size - first) as u64) as usize;
+            return Tm::test(
+                random_tm(rng, first),
+                random_tm(rng, second),
+                random_tm(rng, size - 1 - first - second),
+            );
+        }
+        if rng.chance(1, 3) {
+            let ty_size = rng.below(3) as usize;
+            let ty = random_ty(rng, ty_size);
+            return Tm::abs(x, ty, random_tm(rng, size - 1));
+        }
+        let left = rng.below(size as u64) as usize;
+        Tm::app(random_tm(rng, left), random_tm(rng, size - 1 - left))
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let mut rng = Rng::new(409);
+        for _ in 0..500 {
+            let size = rng.below(8) as usize;
+            let ty = random_ty(&mut rng, size);
+            assert_eq!(parse_ty(&ty.to_string()), Ok(ty));
+            let t = random_tm(&mut rng, size);
+            let printed = t.to_string();
+            assert_eq!(parse_tm(&printed), Ok(t), "printed as {}", printed);
+        }
+    }
+}
@@ -0,0 +1,258 @@
+//! The subtype relation `S <: T` of the Sub chapter, on the types of this
+//! STLC: `Top` above everything and `Bot` below it, functions contravariant in their argument
+//! and covariant in their result, the other type constructors covariant,
+//! and records related by width, depth and permutation. `join` and `meet`
+//! are the least common supertype and greatest common subtype that the
+//! relation gives two types, as in the RecordSub chapter's algorithmic
+//! presentation.
+
+use super::{lookup_field, Ty};
+
+/// Whether `s <: t`. The relation is decided structurally, the way the
+/// chapter's algorithmic subtyping does it, with reflexivity and
+/// transitivity built in rather than applied as rules:
+///
+/// ```text
+///                                 T1 <: S1    S2 <: T2    S1 <: T1    S2 <: T2
+/// S <: S    S <: Top    Bot <: T   --------------------    --------------------
+///                                 S1 -> S2 <: T1 -> T2    S1 * S2 <: T1 * T2
+///
+///         for each xi:Ti in the right, the left has xi:Si, Si <: Ti
+/// ---------------------------------------------------------------------
+///         {x1:S1, ..., xm:Sm, ...} <: {y1:T1, ..., yn:Tn}
+/// ```
+///
+/// `S1 + S2 <: T1 + T2` and `List S <: List T` follow from their parts as
+/// products do, but `Ref S <: Ref T` needs `S` and `T` to be subtypes of
+/// each other, as a cell is both read and written. A record may so have
+/// more fields than its supertype (width), fields of smaller types
+/// (depth), and its fields in any order (permutation); a label it has
+/// twice is taken as its first occurrence.
+pub fn subtype(s: &Ty, t: &Ty) -> bool {
+    match (s, t) {
+        (_, Ty::Top) | (Ty::Bot, _) => true,
+        (Ty::Bool, Ty::Bool) | (Ty::Nat, Ty::Nat) | (Ty::Unit, Ty::Unit) => true,
+        (Ty::Arrow(s1, s2), Ty::Arrow(t1, t2)) => subtype(t1, s1) && subtype(s2, t2),
+        (Ty::Prod(s1, s2), Ty::Prod(t1, t2)) | (Ty::Sum(s1, s2), Ty::Sum(t1, t2)) => {
+            subtype(s1, t1) && subtype(s2, t2)
+        }
+        (Ty::List(s1), Ty::List(t1)) => subtype(s1, t1),
+        (Ty::Ref(s1), Ty::Ref(t1)) => subtype(s1, t1) && subtype(t1, s1),
+        (Ty::Record(s_fields), Ty::Record(t_fields)) => t_fields
+            .iter()
+            .all(|(x, t1)| lookup_field(s_fields, x).is_some_and(|s1| subtype(s1, t1))),
+        _ => false,
+    }
+}
+
+/// The least type both `s` and `t` are subtypes of. There always is one,
+/// as both are `Top`s, and the join of `Bot` and a type is that type.
+/// Records keep the fields they share, in `s`'s order, and functions need
+/// a common subtype of their arguments:
+///
+/// ```text
+/// {x:Nat, y:Bool} \/ {y:Bool, z:Unit} = {y:Bool}
+/// ({x:Nat} -> Nat) \/ ({y:Nat} -> Bool) = {x:Nat, y:Nat} -> Top
+/// ```
+pub fn join(s: &Ty, t: &Ty) -> Ty {
+    match (s, t) {
+        (Ty::Bot, _) => t.clone(),
+        (_, Ty::Bot) => s.clone(),
+        (Ty::Arrow(s1, s2), Ty::Arrow(t1, t2)) => match meet(s1, t1) {
+            Some(ty1) => Ty::arrow(ty1, join(s2, t2)),
+            None => Ty::Top,
+        },
+        (Ty::Prod(s1, s2), Ty::Prod(t1, t2)) => Ty::prod(join(s1, t1), join(s2, t2)),
+        (Ty::Sum(s1, s2), Ty::Sum(t1, t2)) => Ty::sum(join(s1, t1), join(s2, t2)),
+        (Ty::List(s1), Ty::List(t1)) => Ty::list(join(s1, t1)),
+        (Ty::Record(s_fields), Ty::Record(t_fields)) => Ty::Record(
+            first_fields(s_fields)
+                .filter_map(|(x, s1)| Some((x.clone(), join(s1, lookup_field(t_fields, x)?))))
+                .collect(),
+        ),
+        _ if subtype(s, t) && subtype(t, s) => s.clone(),
+        _ => Ty::Top,
+    }
+}
+
+/// The greatest type that is a subtype of both `s` and `t`, if there is
+/// one but `Bot`: records have the fields of both, those of `s` first,
+/// and `Nat` and `Bool` have no other common subtype. Only a meet with
+/// `Bot` itself is `Bot`.
+pub fn meet(s: &Ty, t: &Ty) -> Option<Ty> {
+    match (s, t) {
+        (Ty::Top, _) => Some(t.clone()),
+        (_, Ty::Top) => Some(s.clone()),
+        (Ty::Bot, _) | (_, Ty::Bot) => Some(Ty::Bot),
+        (Ty::Arrow(s1, s2), Ty::Arrow(t1, t2)) => Some(Ty::arrow(join(s1, t1), meet(s2, t2)?)),
+        (Ty::Prod(s1, s2), Ty::Prod(t1, t2)) => Some(Ty::prod(meet(s1, t1)?, meet(s2, t2)?)),
+        (Ty::Sum(s1, s2), Ty::Sum(t1, t2)) => Some(Ty::sum(meet(s1, t1)?, meet(s2, t2)?)),
+        (Ty::List(s1), Ty::List(t1)) => Some(Ty::list(meet(s1, t1)?)),
+        (Ty::Record(s_fields), Ty::Record(t_fields)) => {
+            let mut fields = Vec::new();
+            for (x, s1) in first_fields(s_fields) {
+                let ty = match lookup_field(t_fields, x) {
+                    Some(t1) => meet(s1, t1)?,
+                    None => s1.clone(),
+                };
+                fields.push((x.clone(), ty));
+            }
+            for (x, t1) in first_fields(t_fields) {
+                if lookup_field(s_fields, x).is_none() {
+                    fields.push((x.clone(), t1.clone()));
+                }
+            }
+            Some(Ty::Record(fields))
+        }
+        _ if subtype(s, t) && subtype(t, s) => Some(s.clone()),
+        _ => None,
+    }
+}
+
+/// The fields of a record type but for those whose label came earlier.
+fn first_fields(fields: &[(String, Ty)]) -> impl Iterator<Item = &(String, Ty)> {
+    fields
+        .iter()
+        .enumerate()
+        .filter(|(i, (x, _))| fields[..*i].iter().all(|(y, _)| y != x))
+        .map(|(_, field)| field)
+}
+
+#[cfg(test)]
+mod test_stlc_subtype {
+    use super::*;
+    use crate::stlc::parse_ty;
+
+    fn sub(s: &str, t: &str) -> bool {
+        subtype(&parse_ty(s).unwrap(), &parse_ty(t).unwrap())
+    }
+
+    #[test]
+    fn test_subtype() {
+        // Everything is a `Top`, and `Top` only is.
+        assert!(sub("Nat", "Top"));
+        assert!(sub("(Nat -> Bool) * {x:Unit}", "Top"));
+        assert!(sub("Top", "Top"));
+        assert!(!sub("Top", "Nat"));
+        assert!(!sub("Nat", "Bool"));
+        // `Bot` is everything, and nothing but `Bot` is a `Bot`.
+        assert!(sub("Bot", "Nat"));
+        assert!(sub("Bot", "{x:Nat -> Bool}"));
+        assert!(sub("Bot", "Bot"));
+        assert!(!sub("Top", "Bot"));
+        assert!(sub("Top -> Bot", "Nat -> Nat"));
+        assert!(sub("Ref Bot", "Ref Bot"));
+        assert!(!sub("Ref Bot", "Ref Nat"));
+        // Arrows: the argument contravariant, the result covariant.
+        assert!(sub("Top -> Nat", "Nat -> Top"));
+        assert!(sub("Top -> Nat", "Nat -> Nat"));
+        assert!(!sub("Nat -> Nat", "Top -> Nat"));
+        assert!(!sub("Nat -> Top", "Nat -> Nat"));
+        assert!(sub("(Nat -> Top) -> Nat", "(Top -> Nat) -> Top"));
+        assert!(!sub("(Top -> Nat) -> Nat", "(Nat -> Top) -> Nat"));
+        // Products, sums and lists are covariant.
+        assert!(sub("Nat * Nat", "Top * Nat"));
+        assert!(!sub("Top * Nat", "Nat * Nat"));
+        assert!(sub("Nat + {x:Nat, y:Nat}", "Top + {y:Nat}"));
+        assert!(sub("List {x:Nat, y:Nat}", "List {x:Nat}"));
+        assert!(!sub("List Nat", "Nat"));
+        // Records: width, depth and permutation, and all three together.
+        assert!(sub("{x:Nat, y:Bool}", "{x:Nat}"));
+        assert!(sub("{x:Nat}", "{}"));
+        assert!(sub("{x:Nat}", "{x:Top}"));
+        assert!(sub("{x:Nat, y:Bool}", "{y:Bool, x:Nat}"));
+        assert!(!sub(
+            "{z:Unit, y:Nat -> Nat, x:Nat}",
+            "{x:Top, y:Top -> Nat}"
+        ));
+        assert!(sub(
+            "{z:Unit, y:Top -> Nat, x:Nat}",
+            "{x:Top, y:Nat -> Top}"
+        ));
+        assert!(!sub("{x:Nat}", "{x:Nat, y:Bool}"));
+        assert!(!sub("{x:Top}", "{x:Nat}"));
+        assert!(!sub("{}", "Unit"));
+        // References are invariant.
+        assert!(sub("Ref {x:Nat, y:Nat}", "Ref {y:Nat, x:Nat}"));
+        assert!(!sub("Ref {x:Nat, y:Nat}", "Ref {x:Nat}"));
+        assert!(!sub("Ref {x:Nat}", "Ref {x:Nat, y:Nat}"));
+        assert!(sub("Ref Nat", "Top"));
+        // A record's other fields can't make up for a missing one.
+        assert!(!sub("{y:Nat}", "{x:Nat}"));
+    }
+
+    #[test]
+    fn test_record_subtyping() {
+        // Width: any fields can be forgotten, down to `{}`.
+        assert!(sub("{x:Nat, y:Bool, z:Unit}", "{x:Nat, y:Bool}"));
+        assert!(sub("{x:Nat, y:Bool, z:Unit}", "{x:Nat}"));
+        assert!(sub("{x:Nat, y:Bool, z:Unit}", "{}"));
+        assert!(!sub("{x:Nat, y:Bool}", "{x:Nat, y:Bool, z:Unit}"));
+        // Depth: each field may be a subtype, however deep.
+        assert!(sub("{a:{x:Nat, y:Nat}}", "{a:{x:Nat}}"));
+        assert!(sub("{f:Top -> {x:Nat, y:Nat}}", "{f:Nat -> {y:Top}}"));
+        assert!(!sub("{f:Nat -> Nat}", "{f:Top -> Nat}"));
+        assert!(!sub("{a:{x:Nat}}", "{a:{x:Nat, y:Nat}}"));
+        // Permutation: the order of the fields doesn't matter, so
+        // records in both orders are subtypes of each other.
+        assert!(sub("{x:Nat, y:Bool}", "{y:Bool, x:Nat}"));
+        assert!(sub("{y:Bool, x:Nat}", "{x:Nat, y:Bool}"));
+        // All three at once.
+        assert!(sub(
+            "{c:Unit, b:{y:Nat, x:Nat, w:Bool}, a:Nat}",
+            "{b:{x:Top, y:Nat}, a:Nat}"
+        ));
+        // Only the first of two fields with the same label counts.
+        assert!(sub("{x:Nat, x:Bool}", "{x:Nat}"));
+        assert!(!sub("{x:Nat, x:Bool}", "{x:Bool}"));
+    }
+
+    #[test]
+    fn test_join_meet() {
+        let ty = |s| parse_ty(s).unwrap();
+        let join = |s, t| join(&ty(s), &ty(t)).to_string();
+        let meet = |s, t| meet(&ty(s), &ty(t)).map(|ty| ty.to_string());
+        assert_eq!(join("{x:Nat, y:Bool}", "{y:Bool, z:Unit}"), "{y:Bool}");
+        assert_eq!(join("{x:Nat, y:Bool}", "{y:Nat}"), "{y:Top}");
+        assert_eq!(
+            join("{x:Nat} -> Nat", "{y:Nat} -> Bool"),
+            "{x:Nat, y:Nat} -> Top"
+        );
+        assert_eq!(join("{x:Nat} -> Nat", "{x:Bool} -> Nat"), "Top");
+        assert_eq!(join("Nat * {a:Nat}", "Bool * {}"), "Top * {}");
+        assert_eq!(join("Nat", "Nat"), "Nat");
+        assert_eq!(join("Nat", "Bool"), "Top");
+        assert_eq!(join("Ref Nat", "Ref Nat"), "Ref Nat");
+        assert_eq!(join("Ref {x:Nat, y:Nat}", "Ref {x:Nat}"), "Top");
+        assert_eq!(meet("Ref {x:Nat, y:Nat}", "Ref {x:Nat}"), None);
+        assert_eq!(
+            meet("{x:Nat, y:{a:Nat}}", "{z:Unit, y:{b:Nat}}"),
+            Some("{x:Nat, y:{a:Nat, b:Nat}, z:Unit}".to_string())
+        );
+        assert_eq!(meet("{x:Nat}", "{x:Bool}"), None);
+        assert_eq!(
+            meet("{x:Nat} -> {a:Nat}", "{y:Nat} -> Top"),
+            Some("{} -> {a:Nat}".to_string())
+        );
+        assert_eq!(meet("Top", "Nat -> Nat"), Some("Nat -> Nat".to_string()));
+        assert_eq!(join("Bot", "Nat -> Nat"), "Nat -> Nat");
+        assert_eq!(join("{x:Nat} * Bot", "{y:Nat} * Bool"), "{} * Bool");
+        assert_eq!(meet("Nat", "Bot"), Some("Bot".to_string()));
+        assert_eq!(
+            meet("Bot -> Nat", "Nat -> Nat"),
+            Some("Nat -> Nat".to_string())
+        );
+        // Each is the least, or greatest, of the bounds it could be.
+        for (s, t) in [
+            ("{x:Nat, y:Bool}", "{y:Bool, z:Unit}"),
+            ("{x:Nat} -> {a:Nat}", "{y:Nat} -> Top"),
+            ("{a:{b:Nat}, c:Nat}", "{a:{d:Unit}}"),
+        ] {
+            let (s, t) = (ty(s), ty(t));
+            let j = super::join(&s, &t);
+            assert!(subtype(&s, &j) && subtype(&t, &j));
+            let m = super::meet(&s, &t).unwrap();
+            assert!(subtype(&m, &s) && subtype(&m, &t));
+        }
+    }
+}
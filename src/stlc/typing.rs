@@ -0,0 +1,824 @@
+//! The typing relation `Gamma |- t \in T` of the Stlc chapter as a
+//! function: `type_of` follows the one rule that applies to each form of
+//! term, so it is both the typechecker and a proof that types are unique.
+//! With subtyping a term has many types; `type_of` finds the least of
+//! them, as the Sub chapter's algorithmic typing does, checking a subtype
+//! where the declarative rules would use subsumption.
+
+use std::fmt;
+
+use super::{join, lookup_field, subtype, Context, StoreTy, Tm, Ty};
+use crate::heap::Loc;
+use crate::map::pm_update;
+
+/// Why a term has no type. Each error names the smallest part of the term
+/// at fault.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TypeError {
+    /// A variable that isn't in the context.
+    Unbound(String),
+    /// `term` has type `found`, where `expected` was needed, like an
+    /// argument of the wrong type.
+    Mismatch {
+        term: Box<Tm>,
+        expected: Ty,
+        found: Ty,
+    },
+    /// `term` has type `found`, which isn't of the form `expected`
+    /// describes, like a function type for something applied.
+    WrongShape {
+        term: Box<Tm>,
+        expected: &'static str,
+        found: Ty,
+    },
+    /// A location the store typing has no type for.
+    Dangling(Loc),
+    /// `term` is projected on `label`, but has the record type `found`,
+    /// which has no field so labelled.
+    NoField {
+        term: Box<Tm>,
+        label: String,
+        found: Ty,
+    },
+}
+
+impl fmt::Display for TypeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TypeError::Unbound(x) => write!(f, "unbound variable {}", x),
+            TypeError::Dangling(l) => write!(f, "location {} has no type in the store", l),
+            TypeError::Mismatch {
+                term,
+                expected,
+                found,
+            } => write!(f, "`{}` has type {}, expected {}", term, found, expected),
+            TypeError::WrongShape {
+                term,
+                expected,
+                found,
+            } => write!(f, "`{}` has type {}, expected {}", term, found, expected),
+            TypeError::NoField { term, label, found } => {
+                write!(
+                    f,
+                    "`{}` has type {}, which has no field {}",
+                    term, found, label
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for TypeError {}
+
+/// The type of `t` in `ctx`, with the locations in `t` typed by `st`, the
+/// `Gamma; ST |- t \in T` of the References chapter:
+///
+/// ```text
+/// Gamma x = T1         x |-> T2; Gamma |- t1 \in T1
+/// ----------------     ------------------------------
+/// Gamma |- x \in T1    Gamma |- \x:T2. t1 \in T2 -> T1
+///
+/// Gamma |- t1 \in T2 -> T1    Gamma |- t2 \in T2'    T2' <: T2
+/// -----------------------------------------------------------
+///                   Gamma |- t1 t2 \in T1
+///
+/// ----------------------    -----------------------
+/// Gamma |- true \in Bool    Gamma |- false \in Bool
+///
+/// Gamma |- t1 \in Bool    Gamma |- t2 \in T1    Gamma |- t3 \in T1
+/// ------------------------------------------------------------
+///           Gamma |- if t1 then t2 else t3 \in T1
+///
+///                       Gamma |- t1 \in Nat        Gamma |- t1 \in Nat
+/// ------------------    -----------------------    -------------------------
+/// Gamma |- n \in Nat    Gamma |- succ t1 \in Nat    Gamma |- iszero t1 \in Bool
+///
+/// Gamma |- t1 \in Nat    Gamma |- t2 \in Nat
+/// ------------------------------------------    ------------------------
+///       Gamma |- mult t1 t2 \in Nat             Gamma |- unit \in Unit
+/// ```
+///
+/// `pred` is typed as `succ` is, and `let x = t1 in t2` has the type `t2`
+/// has with `x` given the type of `t1`. `(t1, t2)` has type `T1 * T2` for
+/// the types `T1` and `T2` of its components, which `t.fst` and `t.snd`
+/// have for `t` a pair of type `T1 * T2`. `inl T2 t` has type `T1 + T2` if
+/// `t` has type `T1`, and `case t0 of | inl x1 => t1 | inr x2 => t2` the
+/// type both branches have, given `x1:T1` and `x2:T2` for `t0` a `T1 + T2`.
+/// Lists are typed the same way: `nil T` is a `List T`, `cons t1 t2` one
+/// if `t1` is a `T` and `t2` a `List T`, and the `x1 :: x2` branch of a
+/// `case` on a `List T` has `x1:T` and `x2:List T`. `fix t1` has type `T1`
+/// if `t1` has type `T1 -> T1`. A record has the record type of the types
+/// of its fields, in the same order, and `t.x` the type of the field `x`
+/// of `t`'s record type. `loc l` has type `Ref T` for `T` the type `st`
+/// gives `l`, and `ref t` has type `Ref T` for `t` a `T`, which is the
+/// type of `!t1` for `t1` a `Ref T`. `t1 := t2` is a `Unit` if `t1` is a
+/// `Ref T` and `t2` a `T`.
+///
+/// Wherever a term of some type is needed, one of a subtype will do, as
+/// the argument `t2` of an application shows, and `fix t1` accepts `t1` of
+/// type `T1 -> T2` for `T2 <: T1`. The branches of an `if` or a `case`,
+/// and the head and tail of a `cons`, can have different types: the type
+/// of it all is their `join`, so an `if` choosing between records has the
+/// fields they share. A join of `Top` is the type of nearly any two types,
+/// though, and is taken for a mistake unless a branch already has type
+/// `Top`; the later branch, or the tail, is then blamed.
+///
+/// `error` has type `Bot`, and `try t1 with t2` the join of the types of
+/// `t1` and `t2`, as an `if` does. No value has type `Bot`, so a term of
+/// type `Bot` can be used as anything: applied to an argument of any type,
+/// projected, or taken apart by a `case`, which then also has type `Bot`,
+/// read from, or written to with any value.
+pub fn type_of_in(st: &StoreTy, ctx: &Context, t: &Tm) -> Result<Ty, TypeError> {
+    match t {
+        Tm::Var(x) => ctx(x).ok_or_else(|| TypeError::Unbound(x.clone())),
+        Tm::Abs(x, ty, body) => {
+            let ctx = pm_update(ctx.clone(), x.clone(), ty.clone());
+            Ok(Ty::arrow(ty.clone(), type_of_in(st, &ctx, body)?))
+        }
+        Tm::App(t1, t2) => {
+            let (ty11, ty12) = match type_of_in(st, ctx, t1)? {
+                Ty::Arrow(ty11, ty12) => (*ty11, *ty12),
+                Ty::Bot => (Ty::Top, Ty::Bot),
+                found => {
+                    return Err(TypeError::WrongShape {
+                        term: t1.clone(),
+                        expected: "a function type",
+                        found,
+                    })
+                }
+            };
+            expect(st, ctx, t2, &ty11)?;
+            Ok(ty12)
+        }
+        Tm::Tru | Tm::Fls => Ok(Ty::Bool),
+        Tm::Unit => Ok(Ty::Unit),
+        Tm::Test(t1, t2, t3) => {
+            expect(st, ctx, t1, &Ty::Bool)?;
+            let ty = type_of_in(st, ctx, t2)?;
+            upper(t3, ty, type_of_in(st, ctx, t3)?)
+        }
+        Tm::Const(_) => Ok(Ty::Nat),
+        Tm::Scc(t1) | Tm::Prd(t1) => {
+            expect(st, ctx, t1, &Ty::Nat)?;
+            Ok(Ty::Nat)
+        }
+        Tm::Mlt(t1, t2) => {
+            expect(st, ctx, t1, &Ty::Nat)?;
+            expect(st, ctx, t2, &Ty::Nat)?;
+            Ok(Ty::Nat)
+        }
+        Tm::IsZero(t1) => {
+            expect(st, ctx, t1, &Ty::Nat)?;
+            Ok(Ty::Bool)
+        }
+        Tm::Let(x, t1, t2) => {
+            let ty1 = type_of_in(st, ctx, t1)?;
+            type_of_in(st, &pm_update(ctx.clone(), x.clone(), ty1), t2)
+        }
+        Tm::Pair(t1, t2) => Ok(Ty::prod(type_of_in(st, ctx, t1)?, type_of_in(st, ctx, t2)?)),
+        Tm::Fst(t1) | Tm::Snd(t1) => match type_of_in(st, ctx, t1)? {
+            Ty::Prod(ty1, ty2) => Ok(if matches!(t, Tm::Fst(_)) { *ty1 } else { *ty2 }),
+            Ty::Bot => Ok(Ty::Bot),
+            found => Err(TypeError::WrongShape {
+                term: t1.clone(),
+                expected: "a product type",
+                found,
+            }),
+        },
+        Tm::Inl(ty2, t1) => Ok(Ty::sum(type_of_in(st, ctx, t1)?, ty2.clone())),
+        Tm::Inr(ty1, t2) => Ok(Ty::sum(ty1.clone(), type_of_in(st, ctx, t2)?)),
+        Tm::Case(t0, x1, t1, x2, t2) => {
+            let (ty1, ty2) = sum_components(t0, type_of_in(st, ctx, t0)?)?;
+            let ty = type_of_in(st, &pm_update(ctx.clone(), x1.clone(), ty1), t1)?;
+            upper(
+                t2,
+                ty,
+                type_of_in(st, &pm_update(ctx.clone(), x2.clone(), ty2), t2)?,
+            )
+        }
+        Tm::Fix(t1) => match type_of_in(st, ctx, t1)? {
+            Ty::Arrow(ty1, ty2) if subtype(&ty2, &ty1) => Ok(*ty2),
+            Ty::Bot => Ok(Ty::Bot),
+            Ty::Arrow(ty1, ty2) => Err(TypeError::Mismatch {
+                term: t1.clone(),
+                expected: Ty::arrow((*ty1).clone(), (*ty1).clone()),
+                found: Ty::Arrow(ty1, ty2),
+            }),
+            found => Err(TypeError::WrongShape {
+                term: t1.clone(),
+                expected: "a function type",
+                found,
+            }),
+        },
+        Tm::Record(fields) => Ok(Ty::Record(
+            fields
+                .iter()
+                .map(|(x, t)| Ok((x.clone(), type_of_in(st, ctx, t)?)))
+                .collect::<Result<_, _>>()?,
+        )),
+        Tm::Proj(t1, x) => match type_of_in(st, ctx, t1)? {
+            Ty::Record(fields) => match lookup_field(&fields, x) {
+                Some(ty) => Ok(ty.clone()),
+                None => Err(TypeError::NoField {
+                    term: t1.clone(),
+                    label: x.clone(),
+                    found: Ty::Record(fields),
+                }),
+            },
+            Ty::Bot => Ok(Ty::Bot),
+            found => Err(TypeError::WrongShape {
+                term: t1.clone(),
+                expected: "a record type",
+                found,
+            }),
+        },
+        Tm::Loc(l) => Ok(Ty::ref_(st.lookup(*l).ok_or(TypeError::Dangling(*l))?)),
+        Tm::Ref(t1) => Ok(Ty::ref_(type_of_in(st, ctx, t1)?)),
+        Tm::Deref(t1) => referent(t1, type_of_in(st, ctx, t1)?),
+        Tm::Assign(t1, t2) => {
+            // A cell of type `Bot` takes anything, as a `Ref T` for any `T`.
+            let ty = match referent(t1, type_of_in(st, ctx, t1)?)? {
+                Ty::Bot => Ty::Top,
+                ty => ty,
+            };
+            expect(st, ctx, t2, &ty)?;
+            Ok(Ty::Unit)
+        }
+        Tm::Error => Ok(Ty::Bot),
+        Tm::Try(t1, t2) => {
+            let ty = type_of_in(st, ctx, t1)?;
+            upper(t2, ty, type_of_in(st, ctx, t2)?)
+        }
+        Tm::Nil(ty) => Ok(Ty::list(ty.clone())),
+        Tm::Cons(t1, t2) => {
+            let ty = Ty::list(type_of_in(st, ctx, t1)?);
+            upper(t2, ty, type_of_in(st, ctx, t2)?)
+        }
+        Tm::Lcase(t1, t2, x1, x2, t3) => {
+            let ty1 = list_element(t1, type_of_in(st, ctx, t1)?)?;
+            let ty = type_of_in(st, ctx, t2)?;
+            let ctx = pm_update(ctx.clone(), x1.clone(), ty1.clone());
+            upper(
+                t3,
+                ty,
+                type_of_in(st, &pm_update(ctx, x2.clone(), Ty::list(ty1)), t3)?,
+            )
+        }
+    }
+}
+
+/// The type of a term with no locations in it, as a program is before it
+/// runs: `type_of_in` with nothing in the store.
+pub fn type_of(ctx: &Context, t: &Tm) -> Result<Ty, TypeError> {
+    type_of_in(&StoreTy::new(), ctx, t)
+}
+
+/// The join of `expected` and `found`, the type of `t`, which is blamed if
+/// that is only `Top`.
+pub(super) fn upper(t: &Tm, expected: Ty, found: Ty) -> Result<Ty, TypeError> {
+    match join(&expected, &found) {
+        Ty::Top if expected != Ty::Top && found != Ty::Top => Err(TypeError::Mismatch {
+            term: Box::new(t.clone()),
+            expected,
+            found,
+        }),
+        ty => Ok(ty),
+    }
+}
+
+/// The element type of `found`, the list type of `t`.
+pub(super) fn list_element(t: &Tm, found: Ty) -> Result<Ty, TypeError> {
+    match found {
+        Ty::List(ty) => Ok(*ty),
+        Ty::Bot => Ok(Ty::Bot),
+        found => Err(TypeError::WrongShape {
+            term: Box::new(t.clone()),
+            expected: "a list type",
+            found,
+        }),
+    }
+}
+
+/// The type of what `t` refers to, given `found`, its reference type.
+pub(super) fn referent(t: &Tm, found: Ty) -> Result<Ty, TypeError> {
+    match found {
+        Ty::Ref(ty) => Ok(*ty),
+        Ty::Bot => Ok(Ty::Bot),
+        found => Err(TypeError::WrongShape {
+            term: Box::new(t.clone()),
+            expected: "a reference type",
+            found,
+        }),
+    }
+}
+
+/// The two sides of `found`, the sum type of `t`.
+pub(super) fn sum_components(t: &Tm, found: Ty) -> Result<(Ty, Ty), TypeError> {
+    match found {
+        Ty::Sum(ty1, ty2) => Ok((*ty1, *ty2)),
+        Ty::Bot => Ok((Ty::Bot, Ty::Bot)),
+        found => Err(TypeError::WrongShape {
+            term: Box::new(t.clone()),
+            expected: "a sum type",
+            found,
+        }),
+    }
+}
+
+/// `t` with every `let x = t1 in t2` replaced by `(\x:T1. t2) t1`, `T1`
+/// being the type of `t1`, which is why this needs a context and can fail.
+/// The result evaluates as `t` does and has the same type.
+pub fn desugar_let(ctx: &Context, t: &Tm) -> Result<Tm, TypeError> {
+    let go = |t: &Tm| desugar_let(ctx, t);
+    Ok(match t {
+        Tm::Var(_) | Tm::Tru | Tm::Fls | Tm::Unit | Tm::Const(_) | Tm::Loc(_) | Tm::Error => {
+            t.clone()
+        }
+        Tm::Try(t1, t2) => Tm::try_(go(t1)?, go(t2)?),
+        Tm::Ref(t1) => Tm::ref_(go(t1)?),
+        Tm::Deref(t1) => Tm::deref(go(t1)?),
+        Tm::Assign(t1, t2) => Tm::assign(go(t1)?, go(t2)?),
+        Tm::App(t1, t2) => Tm::app(go(t1)?, go(t2)?),
+        Tm::Abs(x, ty, body) => {
+            let ctx = pm_update(ctx.clone(), x.clone(), ty.clone());
+            Tm::abs(x, ty.clone(), desugar_let(&ctx, body)?)
+        }
+        Tm::Test(t1, t2, t3) => Tm::test(go(t1)?, go(t2)?, go(t3)?),
+        Tm::Scc(t1) => Tm::scc(go(t1)?),
+        Tm::Prd(t1) => Tm::prd(go(t1)?),
+        Tm::Mlt(t1, t2) => Tm::mlt(go(t1)?, go(t2)?),
+        Tm::IsZero(t1) => Tm::is_zero(go(t1)?),
+        Tm::Fix(t1) => Tm::fix(go(t1)?),
+        Tm::Record(fields) => Tm::Record(
+            fields
+                .iter()
+                .map(|(x, t)| Ok((x.clone(), go(t)?)))
+                .collect::<Result<_, _>>()?,
+        ),
+        Tm::Proj(t1, x) => Tm::proj(go(t1)?, x),
+        Tm::Pair(t1, t2) => Tm::pair(go(t1)?, go(t2)?),
+        Tm::Fst(t1) => Tm::fst(go(t1)?),
+        Tm::Snd(t1) => Tm::snd(go(t1)?),
+        Tm::Inl(ty, t1) => Tm::inl(ty.clone(), go(t1)?),
+        Tm::Inr(ty, t1) => Tm::inr(ty.clone(), go(t1)?),
+        Tm::Case(t0, x1, t1, x2, t2) => {
+            let (ty1, ty2) = sum_components(t0, type_of(ctx, t0)?)?;
+            let t1 = desugar_let(&pm_update(ctx.clone(), x1.clone(), ty1), t1)?;
+            let t2 = desugar_let(&pm_update(ctx.clone(), x2.clone(), ty2), t2)?;
+            Tm::case(go(t0)?, x1, t1, x2, t2)
+        }
+        Tm::Nil(_) => t.clone(),
+        Tm::Cons(t1, t2) => Tm::cons(go(t1)?, go(t2)?),
+        Tm::Lcase(t1, t2, x1, x2, t3) => {
+            let ty1 = list_element(t1, type_of(ctx, t1)?)?;
+            let inner = pm_update(ctx.clone(), x1.clone(), ty1.clone());
+            let t3 = desugar_let(&pm_update(inner, x2.clone(), Ty::list(ty1)), t3)?;
+            Tm::lcase(go(t1)?, go(t2)?, x1, x2, t3)
+        }
+        Tm::Let(x, t1, t2) => {
+            let ty1 = type_of(ctx, t1)?;
+            let body = desugar_let(&pm_update(ctx.clone(), x.clone(), ty1.clone()), t2)?;
+            Tm::app(Tm::abs(x, ty1, body), go(t1)?)
+        }
+    })
+}
+
+/// Check that `t` has type `expected`, or a subtype of it.
+fn expect(st: &StoreTy, ctx: &Context, t: &Tm, expected: &Ty) -> Result<(), TypeError> {
+    let found = type_of_in(st, ctx, t)?;
+    if subtype(&found, expected) {
+        Ok(())
+    } else {
+        Err(TypeError::Mismatch {
+            term: Box::new(t.clone()),
+            expected: expected.clone(),
+            found,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test_stlc_typing {
+    use super::*;
+    use crate::map::pm_empty;
+    use crate::stlc::{empty_context, parse_tm, parse_ty};
+
+    fn ty(s: &str) -> Ty {
+        parse_ty(s).unwrap()
+    }
+
+    fn type_of_str(ctx: &Context, s: &str) -> Result<Ty, TypeError> {
+        type_of(ctx, &parse_tm(s).unwrap())
+    }
+
+    #[test]
+    fn test_well_typed() {
+        let ctx = empty_context();
+        assert_eq!(type_of_str(&ctx, "\\x:Bool. x"), Ok(ty("Bool -> Bool")));
+        // The examples of the chapter's typing section.
+        assert_eq!(
+            type_of_str(&ctx, "\\x:Bool -> Bool. \\y:Bool. x y"),
+            Ok(ty("(Bool -> Bool) -> Bool -> Bool"))
+        );
+        assert_eq!(
+            type_of_str(&ctx, "\\x:Bool. \\y:Bool -> Bool. y (y x)"),
+            Ok(ty("Bool -> (Bool -> Bool) -> Bool"))
+        );
+        assert_eq!(
+            type_of_str(
+                &ctx,
+                "(\\x:Bool. x) ((\\f:Bool -> Bool. f) (\\y:Bool. y) z)"
+            ),
+            Err(TypeError::Unbound("z".to_string()))
+        );
+        assert_eq!(
+            type_of_str(&ctx, "\\x:Bool. if x then false else true"),
+            Ok(ty("Bool -> Bool"))
+        );
+        assert_eq!(
+            type_of_str(&ctx, "if true then \\x:Bool. x else \\y:Bool. false"),
+            Ok(ty("Bool -> Bool"))
+        );
+        assert_eq!(
+            type_of_str(&ctx, "\\n:Nat. if iszero n then 1 else mult n (pred n)"),
+            Ok(ty("Nat -> Nat"))
+        );
+        // `let` shadows, so the inner `x` is a `Bool`.
+        assert_eq!(
+            type_of_str(&ctx, "let x = 1 in let x = iszero x in \\y:Nat. x"),
+            Ok(ty("Nat -> Bool"))
+        );
+        // The examples of MoreStlc's pairs section.
+        assert_eq!(
+            type_of_str(&ctx, "\\p:Nat * Bool. (p.snd, p.fst)"),
+            Ok(ty("Nat * Bool -> Bool * Nat"))
+        );
+        assert_eq!(
+            type_of_str(&ctx, "((\\x:Nat. succ x, 0), true).fst.fst"),
+            Ok(ty("Nat -> Nat"))
+        );
+        // The Records chapter's examples.
+        assert_eq!(
+            type_of_str(&ctx, "\\r:{i1:Nat -> Nat, i2:Bool}. r.i1"),
+            Ok(ty("{i1:Nat -> Nat, i2:Bool} -> Nat -> Nat"))
+        );
+        assert_eq!(
+            type_of_str(&ctx, "{i1=\\n:Nat. n, i2=true, i3={}}"),
+            Ok(ty("{i1:Nat -> Nat, i2:Bool, i3:{}}"))
+        );
+        // MoreStlc's processSum.
+        assert_eq!(
+            type_of_str(
+                &ctx,
+                "\\x:Nat + Nat. case x of | inl n => n | inr n => if iszero n then 1 else 0"
+            ),
+            Ok(ty("Nat + Nat -> Nat"))
+        );
+        assert_eq!(type_of_str(&ctx, "inr Nat true"), Ok(ty("Nat + Bool")));
+        assert_eq!(
+            type_of_str(&ctx, "inl (Nat -> Nat) (\\b:Bool. 0, 1)"),
+            Ok(ty("(Bool -> Nat) * Nat + (Nat -> Nat)"))
+        );
+        assert_eq!(
+            type_of_str(&ctx, "cons 1 (cons 2 (nil Nat))"),
+            Ok(ty("List Nat"))
+        );
+        assert_eq!(
+            type_of_str(
+                &ctx,
+                "\\l:List Nat. case l of | nil => false | x :: xs => iszero x"
+            ),
+            Ok(ty("List Nat -> Bool"))
+        );
+        // Free variables take their types from the context.
+        let ctx = pm_update(pm_empty(), "f".to_string(), ty("Bool -> Bool"));
+        let ctx = pm_update(ctx, "b".to_string(), Ty::Bool);
+        assert_eq!(type_of_str(&ctx, "f (f b)"), Ok(Ty::Bool));
+        // A binder shadows the context.
+        assert_eq!(
+            type_of_str(&ctx, "\\b:Bool -> Bool. b"),
+            Ok(ty("(Bool -> Bool) -> Bool -> Bool"))
+        );
+    }
+
+    #[test]
+    fn test_subsumption() {
+        let ctx = empty_context();
+        // A record with more fields passed for one with fewer.
+        assert_eq!(
+            type_of_str(&ctx, "(\\r:{x:Nat}. r.x) {x=1, y=true}"),
+            Ok(Ty::Nat)
+        );
+        // And a function of a larger argument type for one of a smaller.
+        assert_eq!(
+            type_of_str(
+                &ctx,
+                "(\\f:{x:Nat, y:Nat} -> Top. f {y=1, x=2}) (\\r:{x:Nat}. r.x)"
+            ),
+            Ok(Ty::Top)
+        );
+        // Branches and list elements have the larger of their types.
+        assert_eq!(
+            type_of_str(&ctx, "if true then {x=1, y=2} else {x=3}"),
+            Ok(ty("{x:Nat}"))
+        );
+        assert_eq!(
+            type_of_str(&ctx, "\\b:Bool. if b then 1 else unit"),
+            Err(TypeError::Mismatch {
+                term: Box::new(Tm::Unit),
+                expected: Ty::Nat,
+                found: Ty::Unit
+            })
+        );
+        assert_eq!(
+            type_of_str(&ctx, "cons {x=1, y=true} (nil {x:Nat})"),
+            Ok(ty("List {x:Nat}"))
+        );
+        // The elements of a list needn't have a type but `Top` in common,
+        // though nothing can be done with them then.
+        assert_eq!(
+            type_of_str(&ctx, "cons 1 (cons true (nil Bool))"),
+            Ok(ty("List Top"))
+        );
+        assert_eq!(
+            type_of_str(&ctx, "fix (\\r:{x:Nat}. {x=1, y=2})"),
+            Ok(ty("{x:Nat, y:Nat}"))
+        );
+        let err = type_of_str(&ctx, "(\\f:Top -> Nat. f 1) (\\n:Nat. n)").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "`\\n:Nat. n` has type Nat -> Nat, expected Top -> Nat"
+        );
+        // A `Top` can't be used as anything else.
+        let err = type_of_str(&ctx, "(\\x:Top. succ x) 1").unwrap_err();
+        assert_eq!(err.to_string(), "`x` has type Top, expected Nat");
+    }
+
+    #[test]
+    fn test_references() {
+        let ctx = empty_context();
+        assert_eq!(
+            type_of_str(&ctx, "\\r:Ref Nat. r := succ (!r)"),
+            Ok(ty("Ref Nat -> Unit"))
+        );
+        assert_eq!(type_of_str(&ctx, "ref (ref unit)"), Ok(ty("Ref Ref Unit")));
+        let err = type_of_str(&ctx, "!true").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "`true` has type Bool, expected a reference type"
+        );
+        let err = type_of_str(&ctx, "(ref 0) := true").unwrap_err();
+        assert_eq!(err.to_string(), "`true` has type Bool, expected Nat");
+        // A cell holds a subtype of its type, but is invariant itself.
+        assert_eq!(type_of_str(&ctx, "(ref {x=0}) := {x=1, y=2}"), Ok(Ty::Unit));
+        let err = type_of_str(&ctx, "(\\r:Ref {x:Nat}. !r) (ref {x=1, y=2})").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "`ref {x=1, y=2}` has type Ref {x:Nat, y:Nat}, expected Ref {x:Nat}"
+        );
+        // Locations are typed by the store typing.
+        let (st, l) = StoreTy::new().alloc(ty("Nat -> Nat"));
+        assert_eq!(
+            type_of_in(&st, &ctx, &Tm::app(Tm::deref(Tm::Loc(l)), Tm::Const(1))),
+            Ok(Ty::Nat)
+        );
+        assert_eq!(
+            type_of_in(&st, &ctx, &Tm::Loc(l + 1)),
+            Err(TypeError::Dangling(l + 1))
+        );
+        assert_eq!(
+            type_of(&ctx, &Tm::Loc(l)).unwrap_err().to_string(),
+            "location 0 has no type in the store"
+        );
+    }
+
+    #[test]
+    fn test_exceptions() {
+        let ctx = empty_context();
+        assert_eq!(type_of_str(&ctx, "error"), Ok(Ty::Bot));
+        // An error can stand for a term of any type.
+        assert_eq!(
+            type_of_str(&ctx, "\\n:Nat. if iszero n then error else pred n"),
+            Ok(ty("Nat -> Nat"))
+        );
+        assert_eq!(
+            type_of_str(&ctx, "(\\f:Nat -> Bool. f 0) error"),
+            Ok(Ty::Bool)
+        );
+        assert_eq!(type_of_str(&ctx, "error 1 true"), Ok(Ty::Bot));
+        assert_eq!(type_of_str(&ctx, "succ error.fst.x"), Ok(Ty::Nat));
+        assert_eq!(
+            type_of_str(&ctx, "case error of | inl x => x | inr y => succ y"),
+            Ok(Ty::Nat)
+        );
+        assert_eq!(type_of_str(&ctx, "error := 1; !error"), Ok(Ty::Bot));
+        // A `try` has the type both its terms have.
+        assert_eq!(
+            type_of_str(&ctx, "try {x=1, y=error} with {x=0}"),
+            Ok(ty("{x:Nat}"))
+        );
+        assert_eq!(type_of_str(&ctx, "try error with unit"), Ok(Ty::Unit));
+        let err = type_of_str(&ctx, "try 1 with false").unwrap_err();
+        assert_eq!(err.to_string(), "`false` has type Bool, expected Nat");
+        // What an error is applied to is still checked.
+        assert_eq!(
+            type_of_str(&ctx, "error (succ true)")
+                .unwrap_err()
+                .to_string(),
+            "`true` has type Bool, expected Nat"
+        );
+    }
+
+    #[test]
+    fn test_employee_person() {
+        const PERSON: &str = "{name:Nat, age:Nat}";
+        const EMPLOYEE: &str = "{name:Nat, age:Nat, salary:Nat}";
+        const STUDENT: &str = "{gpa:Nat, age:Nat, name:Nat}";
+        let ctx = [("p", PERSON), ("e", EMPLOYEE), ("s", STUDENT)]
+            .into_iter()
+            .fold(empty_context(), |ctx, (x, t)| {
+                pm_update(ctx, x.to_string(), ty(t))
+            });
+        let type_of = |s: String| {
+            type_of_str(&ctx, &s)
+                .map(|ty| ty.to_string())
+                .map_err(|err| err.to_string())
+        };
+        // An employee, and a student, with their fields in another order,
+        // can be used as a person.
+        let age = format!("\\p:{}. p.age", PERSON);
+        assert_eq!(
+            type_of(format!("(({}) e, ({}) s)", age, age)).as_deref(),
+            Ok("Nat * Nat")
+        );
+        assert_eq!(
+            type_of(format!("({}) {{salary=3, name=1, age=2}}", age)).as_deref(),
+            Ok("Nat")
+        );
+        // So a function on persons is a function on employees.
+        assert_eq!(
+            type_of(format!(
+                "(\\f:{} -> {{name:Nat}}. f e) (\\p:{}. p)",
+                EMPLOYEE, PERSON
+            ))
+            .as_deref(),
+            Ok("{name:Nat}")
+        );
+        // Either an employee or a student is a person.
+        let t = "\\b:Bool. if b then e else s".to_string();
+        assert_eq!(type_of(t).as_deref(), Ok("Bool -> {name:Nat, age:Nat}"));
+        assert_eq!(
+            type_of(format!("cons e (cons s (nil {}))", PERSON)).as_deref(),
+            Ok("List {name:Nat, age:Nat}")
+        );
+        // But a person isn't an employee, and a person's salary has no
+        // type, nor does a person's field after being forgotten.
+        assert_eq!(
+            type_of(format!("(\\x:{}. x.salary) p", EMPLOYEE)),
+            Err(format!("`p` has type {}, expected {}", PERSON, EMPLOYEE))
+        );
+        assert_eq!(
+            type_of("p.salary".to_string()),
+            Err(format!(
+                "`p` has type {}, which has no field salary",
+                PERSON
+            ))
+        );
+        assert_eq!(
+            type_of("(\\x:{name:Nat}. x.age) e".to_string()),
+            Err("`x` has type {name:Nat}, which has no field age".to_string())
+        );
+        let t = format!("(\\f:{} -> Nat. f p) (\\x:{}. x.salary)", PERSON, EMPLOYEE);
+        assert_eq!(
+            type_of(t),
+            Err(format!(
+                "`\\x:{}. x.salary` has type {} -> Nat, expected {} -> Nat",
+                EMPLOYEE, EMPLOYEE, PERSON
+            ))
+        );
+    }
+
+    #[test]
+    fn test_ill_typed() {
+        let ctx = pm_update(empty_context(), "b".to_string(), Ty::Bool);
+        // b b: `b` isn't a function.
+        let err = type_of_str(&ctx, "b b").unwrap_err();
+        assert_eq!(
+            err,
+            TypeError::WrongShape {
+                term: Box::new(Tm::var("b")),
+                expected: "a function type",
+                found: Ty::Bool
+            }
+        );
+        assert_eq!(
+            err.to_string(),
+            "`b` has type Bool, expected a function type"
+        );
+        // (\f:Bool -> Bool. f b) b: the argument should be a function.
+        let err = type_of_str(&ctx, "(\\f:Bool -> Bool. f b) b").unwrap_err();
+        assert_eq!(err.to_string(), "`b` has type Bool, expected Bool -> Bool");
+        // The error is about the innermost term at fault.
+        let err = type_of_str(&ctx, "\\x:Bool. (\\y:Bool. y) (\\z:Bool. z)").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "`\\z:Bool. z` has type Bool -> Bool, expected Bool"
+        );
+        // The guard of an `if` is a `Bool`, and the branches agree.
+        let err = type_of_str(&ctx, "if \\x:Bool. x then true else false").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "`\\x:Bool. x` has type Bool -> Bool, expected Bool"
+        );
+        let err = type_of_str(&ctx, "if b then true else \\x:Bool. x").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "`\\x:Bool. x` has type Bool -> Bool, expected Bool"
+        );
+        // Booleans aren't numbers, nor numbers booleans.
+        let err = type_of_str(&ctx, "mult 2 (succ b)").unwrap_err();
+        assert_eq!(err.to_string(), "`b` has type Bool, expected Nat");
+        let err = type_of_str(&ctx, "if iszero 0 then 0 else false").unwrap_err();
+        assert_eq!(err.to_string(), "`false` has type Bool, expected Nat");
+        let err = type_of_str(&ctx, "(\\x:Nat. x).snd").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "`\\x:Nat. x` has type Nat -> Nat, expected a product type"
+        );
+        let err = type_of_str(&ctx, "(\\p:Nat * Nat. p.fst) (0, true)").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "`(0, true)` has type Nat * Bool, expected Nat * Nat"
+        );
+        // The branches of a `case` agree, on a sum.
+        let err = type_of_str(&ctx, "case inl Bool 0 of | inl n => n | inr b => b").unwrap_err();
+        assert_eq!(err.to_string(), "`b` has type Bool, expected Nat");
+        let err = type_of_str(&ctx, "case (0, 1) of | inl n => n | inr n => n").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "`(0, 1)` has type Nat * Nat, expected a sum type"
+        );
+        // A list's tail is a list of something the head has in common.
+        let err = type_of_str(&ctx, "cons 1 true").unwrap_err();
+        assert_eq!(err.to_string(), "`true` has type Bool, expected List Nat");
+        let err = type_of_str(&ctx, "case 1 of | nil => 0 | x :: xs => x").unwrap_err();
+        assert_eq!(err.to_string(), "`1` has type Nat, expected a list type");
+        // The tail is a list.
+        let err = type_of_str(&ctx, "case nil Nat of | nil => 0 | x :: xs => xs").unwrap_err();
+        assert_eq!(err.to_string(), "`xs` has type List Nat, expected Nat");
+        // `fix` needs a function from a type to that same type.
+        let err = type_of_str(&ctx, "fix (\\n:Nat. iszero n)").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "`\\n:Nat. iszero n` has type Nat -> Bool, expected Nat -> Nat"
+        );
+        // An argument needs every field of the record type expected.
+        let err = type_of_str(&ctx, "(\\r:{x:Nat, y:Bool}. r.x) {y=true, z=1}").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "`{y=true, z=1}` has type {y:Bool, z:Nat}, expected {x:Nat, y:Bool}"
+        );
+        let err = type_of_str(&ctx, "{x=1}.y").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "`{x=1}` has type {x:Nat}, which has no field y"
+        );
+        let err = type_of_str(&ctx, "(1, 2).x").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "`(1, 2)` has type Nat * Nat, expected a record type"
+        );
+        let err = type_of_str(&ctx, "fix 0").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "`0` has type Nat, expected a function type"
+        );
+        // A `let` variable is out of scope in its own definition.
+        let err = type_of_str(&ctx, "let n = succ n in n").unwrap_err();
+        assert_eq!(err, TypeError::Unbound("n".to_string()));
+    }
+
+    #[test]
+    fn test_desugar_let() {
+        let ctx = empty_context();
+        let t = parse_tm("let f = \\x:Nat. succ x in f (f 0)").unwrap();
+        let desugared = desugar_let(&ctx, &t).unwrap();
+        assert_eq!(
+            desugared,
+            parse_tm("(\\f:Nat -> Nat. f (f 0)) (\\x:Nat. succ x)").unwrap()
+        );
+        assert_eq!(type_of(&ctx, &desugared), type_of(&ctx, &t));
+        // Nested and shadowing lets, under binders.
+        let t =
+            parse_tm("\\b:Bool. let x = 1 in let x = if b then succ x else x in mult x x").unwrap();
+        let desugared = desugar_let(&ctx, &t).unwrap();
+        assert_eq!(
+            desugared.to_string(),
+            "\\b:Bool. (\\x:Nat. (\\x:Nat. mult x x) if b then succ x else x) 1"
+        );
+        assert_eq!(type_of(&ctx, &desugared), Ok(ty("Bool -> Nat")));
+        // An ill-typed definition has no type to annotate the binder with.
+        let t = parse_tm("let x = succ true in x").unwrap();
+        assert!(desugar_let(&ctx, &t).is_err());
+    }
+}
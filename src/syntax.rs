@@ -0,0 +1,317 @@
+//! What the parsers of Imp, lambda terms and STLC share: tokens with
+//! their spans, a lexer parameterized by the keywords and symbols of the
+//! language, the errors, shown at their line and column with
+//! `ParseError::report`, and the `Cursor` the parsers read tokens with.
+//! Each parser keeps only its own token tables and grammar.
+
+use std::fmt;
+
+/// A range of byte offsets into the input, `start` included and `end` not.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    /// The line and column `start` is at, both counted from `1`, with
+    /// columns counted in characters.
+    pub fn line_col(&self, input: &str) -> (usize, usize) {
+        let before = &input[..self.start];
+        let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+        let line = before.matches('\n').count() + 1;
+        (line, before[line_start..].chars().count() + 1)
+    }
+
+    /// The text the span covers.
+    pub fn text<'a>(&self, input: &'a str) -> &'a str {
+        &input[self.start..self.end]
+    }
+
+    /// `message` with the line and column of the span, then that line of
+    /// `input` with a caret under the span, at least one wide.
+    ///
+    /// ```text
+    /// expected `:=` at line 2, col 3, found `1`
+    /// 2 | X 1
+    ///   |   ^
+    /// ```
+    ///
+    /// `found`, if not empty, goes after the position.
+    pub fn report(&self, input: &str, message: &str, found: &str) -> String {
+        let (line, col) = self.line_col(input);
+        let text = input.lines().nth(line - 1).unwrap_or("");
+        let gutter = " ".repeat(line.to_string().len());
+        let width = self.text(input).chars().count().max(1);
+        format!(
+            "{} at line {}, col {}{}\n{} | {}\n{} | {}{}",
+            message,
+            line,
+            col,
+            found,
+            line,
+            text,
+            gutter,
+            " ".repeat(col - 1),
+            "^".repeat(width)
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Token {
+    /// A literal's magnitude. Imp reads a `-` directly before it as part of
+    /// the literal, so whether that fits in an `i64` is for its parser to
+    /// say, not the lexer.
+    Num(u64),
+    Ident(String),
+    Keyword(&'static str),
+    Symbol(&'static str),
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Num(n) => write!(f, "`{}`", n),
+            Token::Ident(x) => write!(f, "`{}`", x),
+            Token::Keyword(s) | Token::Symbol(s) => write!(f, "`{}`", s),
+        }
+    }
+}
+
+/// Why a parse failed. Positions are byte offsets into the input.
+///
+/// What was expected is a keyword or symbol, like `:=`, or a phrase, like
+/// `a variable` or `` `.` or `->` ``.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseError {
+    /// A character that cannot start any token.
+    UnexpectedChar { ch: char, pos: usize },
+    /// A number literal too large for the language: an `i64` for Imp and
+    /// a `u64` for STLC.
+    NumberTooLarge { pos: usize },
+    /// A token the grammar doesn't allow here.
+    UnexpectedToken {
+        found: Token,
+        pos: usize,
+        expected: &'static str,
+    },
+    /// The input ended in the middle of a phrase.
+    UnexpectedEof { expected: &'static str },
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::UnexpectedChar { ch, pos } => {
+                write!(f, "unexpected character {:?} at position {}", ch, pos)
+            }
+            ParseError::NumberTooLarge { pos } => {
+                write!(f, "number literal at position {} is too large", pos)
+            }
+            ParseError::UnexpectedToken {
+                found,
+                pos,
+                expected,
+            } => write!(
+                f,
+                "unexpected token {} at position {}, expected {}",
+                found, pos, expected
+            ),
+            ParseError::UnexpectedEof { expected } => {
+                write!(f, "unexpected end of input, expected {}", expected)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+impl ParseError {
+    /// The byte offset the error is at, or `None` if it is the end of the
+    /// input.
+    pub fn pos(&self) -> Option<usize> {
+        match self {
+            ParseError::UnexpectedChar { pos, .. }
+            | ParseError::NumberTooLarge { pos }
+            | ParseError::UnexpectedToken { pos, .. } => Some(*pos),
+            ParseError::UnexpectedEof { .. } => None,
+        }
+    }
+
+    /// The error as it would be shown to whoever wrote `input`, the text
+    /// it came from, with `Span::report` under the offending token. A
+    /// keyword or symbol that was expected is quoted as code.
+    pub fn report(&self, input: &str) -> String {
+        let digits = |rest: &str| {
+            rest.find(|c: char| !c.is_ascii_digit())
+                .unwrap_or(rest.len())
+        };
+        let rest = &input[self.pos().unwrap_or(input.len())..];
+        let len = match self {
+            ParseError::UnexpectedToken { found, .. } => match found {
+                Token::Num(_) => digits(rest),
+                Token::Ident(x) => x.len(),
+                Token::Keyword(s) | Token::Symbol(s) => s.len(),
+            },
+            ParseError::NumberTooLarge { .. } => digits(rest),
+            ParseError::UnexpectedChar { ch, .. } => ch.len_utf8(),
+            ParseError::UnexpectedEof { .. } => 0,
+        };
+        // The end of the input is shown just after its last token.
+        let start = self.pos().unwrap_or(input.trim_end().len());
+        let span = Span {
+            start,
+            end: start + len,
+        };
+        let message = match self {
+            ParseError::UnexpectedChar { ch, .. } => format!("unexpected character {:?}", ch),
+            ParseError::NumberTooLarge { .. } => "number literal too large".to_string(),
+            ParseError::UnexpectedToken { expected, .. }
+            | ParseError::UnexpectedEof { expected } => {
+                if expected.contains([' ', '`']) {
+                    format!("expected {}", expected)
+                } else {
+                    format!("expected `{}`", expected)
+                }
+            }
+        };
+        let found = match self {
+            ParseError::UnexpectedToken { found, .. } => format!(", found {}", found),
+            ParseError::UnexpectedEof { .. } => ", found end of input".to_string(),
+            _ => String::new(),
+        };
+        span.report(input, &message, &found)
+    }
+}
+
+/// Split `input` into tokens with their spans. A number is a run of
+/// digits, and a name a letter or `_` followed by letters, digits and
+/// `_`s, unless it is one of `keywords`. Anything else must be one of
+/// `symbols`, which are tried in order, so a longer one has to come before
+/// its prefixes. A letter that starts a symbol, like `λ`, is that symbol.
+pub(crate) fn lex(
+    input: &str,
+    keywords: &[&'static str],
+    symbols: &[&'static str],
+) -> Result<Vec<(Token, Span)>, ParseError> {
+    let is_symbol = |c: char| symbols.iter().any(|sym| sym.starts_with(c));
+    let mut tokens = Vec::new();
+    let mut chars = input.char_indices().peekable();
+    while let Some(&(pos, ch)) = chars.peek() {
+        if ch.is_whitespace() {
+            chars.next();
+        } else if ch.is_ascii_digit() {
+            let mut end = pos;
+            while let Some(&(i, c)) = chars.peek() {
+                if !c.is_ascii_digit() {
+                    break;
+                }
+                end = i + c.len_utf8();
+                chars.next();
+            }
+            let n = input[pos..end]
+                .parse()
+                .map_err(|_| ParseError::NumberTooLarge { pos })?;
+            tokens.push((Token::Num(n), Span { start: pos, end }));
+        } else if (ch.is_alphabetic() || ch == '_') && !is_symbol(ch) {
+            let mut end = pos;
+            while let Some(&(i, c)) = chars.peek() {
+                if !(c.is_alphanumeric() || c == '_') || is_symbol(c) {
+                    break;
+                }
+                end = i + c.len_utf8();
+                chars.next();
+            }
+            let word = &input[pos..end];
+            let token = match keywords.iter().find(|kw| **kw == word) {
+                Some(kw) => Token::Keyword(kw),
+                None => Token::Ident(word.to_string()),
+            };
+            tokens.push((token, Span { start: pos, end }));
+        } else {
+            let sym = symbols
+                .iter()
+                .find(|sym| input[pos..].starts_with(**sym))
+                .ok_or(ParseError::UnexpectedChar { ch, pos })?;
+            let end = pos + sym.len();
+            while chars.peek().is_some_and(|&(i, _)| i < end) {
+                chars.next();
+            }
+            tokens.push((Token::Symbol(sym), Span { start: pos, end }));
+        }
+    }
+    Ok(tokens)
+}
+
+/// Reading a parser's tokens one at a time. A parser supplies its tokens
+/// and where it is in them, and gets the rest.
+pub(crate) trait Cursor {
+    fn tokens(&self) -> &[(Token, Span)];
+
+    /// The index of the next token to read.
+    fn position(&self) -> usize;
+
+    fn advance(&mut self);
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens().get(self.position()).map(|(t, _)| t)
+    }
+
+    /// The error of finding the next token, or the end of the input, where
+    /// `expected` should be.
+    fn error(&self, expected: &'static str) -> ParseError {
+        match self.tokens().get(self.position()) {
+            Some((found, span)) => ParseError::UnexpectedToken {
+                found: found.clone(),
+                pos: span.start,
+                expected,
+            },
+            None => ParseError::UnexpectedEof { expected },
+        }
+    }
+
+    /// Consume the next token if it is the keyword or symbol `s`.
+    fn eat(&mut self, s: &str) -> bool {
+        match self.peek() {
+            Some(Token::Keyword(t) | Token::Symbol(t)) if *t == s => {
+                self.advance();
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn expect(&mut self, s: &'static str) -> Result<(), ParseError> {
+        self.expect_or(s, s)
+    }
+
+    /// `expect`, saying that `expected` was expected if `s` isn't there,
+    /// for when something else could have been too.
+    fn expect_or(&mut self, s: &'static str, expected: &'static str) -> Result<(), ParseError> {
+        if self.eat(s) {
+            Ok(())
+        } else {
+            Err(self.error(expected))
+        }
+    }
+
+    fn finish<T>(&self, result: T) -> Result<T, ParseError> {
+        if self.position() == self.tokens().len() {
+            Ok(result)
+        } else {
+            Err(self.error("end of input"))
+        }
+    }
+
+    fn ident(&mut self) -> Result<String, ParseError> {
+        match self.peek() {
+            Some(Token::Ident(x)) => {
+                let x = x.clone();
+                self.advance();
+                Ok(x)
+            }
+            _ => Err(self.error("a variable")),
+        }
+    }
+}